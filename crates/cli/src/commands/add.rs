@@ -2,15 +2,16 @@ use super::AddArgs;
 use crate::project_config::ProjectConfig;
 use colored::Colorize;
 use doubleagent_core::{mise, Config, ServiceRegistry};
+use serde_json::json;
 
-pub async fn run(args: AddArgs) -> anyhow::Result<()> {
-    let config = Config::load()?;
+pub async fn run(config: Config, args: AddArgs) -> anyhow::Result<()> {
     let registry = ServiceRegistry::new(&config.services_dir, &config.repo_url, &config.branch)?;
 
     // Get services to add: from args or from project config
     let services: Vec<String> = if args.services.is_empty() {
         // Try to read from project config
-        if let Some(project_config) = ProjectConfig::try_load(config.project_config_path.as_deref())
+        if let Some(project_config) =
+            ProjectConfig::try_load(config.project_config_path.as_deref(), &config.cache_dir)
         {
             if project_config.services.is_empty() {
                 println!("{} No services specified in doubleagent.yaml", "ℹ".blue());
@@ -51,8 +52,8 @@ pub async fn run(args: AddArgs) -> anyhow::Result<()> {
     println!("{}", "Adding services from remote repository...".bold());
     println!();
 
-    let mut success_count = 0;
-    let mut error_count = 0;
+    let mut added = Vec::new();
+    let mut failed = Vec::new();
 
     for service_name in &services {
         print!("  {} Adding {}... ", "▶".blue(), service_name);
@@ -69,27 +70,28 @@ pub async fn run(args: AddArgs) -> anyhow::Result<()> {
                     "→".dimmed(),
                     path.display().to_string().dimmed()
                 );
-                success_count += 1;
+                added.push(json!({ "name": service_name, "path": path }));
             }
             Err(e) => {
                 println!("{}", "✗".red());
                 eprintln!("    {} {}", "Error:".red(), e);
-                error_count += 1;
+                failed.push(json!({ "name": service_name, "error": e.to_string() }));
             }
         }
     }
 
     println!();
-    if error_count == 0 {
-        println!("{} Added {} service(s)", "✓".green(), success_count);
+    if failed.is_empty() {
+        println!("{} Added {} service(s)", "✓".green(), added.len());
     } else {
         println!(
             "{} Added {} service(s), {} failed",
             "⚠".yellow(),
-            success_count,
-            error_count
+            added.len(),
+            failed.len()
         );
     }
+    crate::output::result(json!({ "added": added, "failed": failed }));
 
     println!();
     println!(
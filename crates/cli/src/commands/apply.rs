@@ -0,0 +1,298 @@
+use super::ApplyArgs;
+use colored::Colorize;
+use doubleagent_core::manifest::{self, Action, Manifest, SeedSource};
+use doubleagent_core::ports::PortAllocator;
+use doubleagent_core::reload::{self, DefinitionStatus};
+use doubleagent_core::snapshot::SnapshotStore;
+use doubleagent_core::workspace;
+use doubleagent_core::{
+    seed, Config, ControlClient, ProcessManager, SeedRecord, ServiceDefinition, ServiceRegistry,
+    StartOptions,
+};
+use std::collections::HashMap;
+use std::path::Path;
+
+pub async fn run(config: Config, args: ApplyArgs) -> anyhow::Result<()> {
+    let mut manager = ProcessManager::load(&config.state_file)?;
+
+    if let Some(file) = &args.file {
+        let path = Path::new(file);
+        let manifest = Manifest::load(path)?;
+        return apply_manifest(&config, &mut manager, &manifest, path, args.dry_run, true).await;
+    }
+
+    let services: Vec<String> = if args.services.is_empty() {
+        manager.running_services()
+    } else {
+        args.services
+    };
+
+    if services.is_empty() {
+        println!("No services running");
+        return Ok(());
+    }
+
+    let mut restarted = 0;
+
+    for service_name in &services {
+        let Some(info) = manager.get_info(service_name) else {
+            println!("{} {} is not running", "⚠".yellow(), service_name);
+            continue;
+        };
+
+        match reload::definition_status(&info) {
+            DefinitionStatus::Current => {
+                println!("  {} {} is up to date", "✓".green(), service_name);
+                continue;
+            }
+            DefinitionStatus::Unknown => {
+                println!(
+                    "  {} {} was started without a recorded definition, restart it manually",
+                    "⚠".yellow(),
+                    service_name
+                );
+                continue;
+            }
+            DefinitionStatus::Invalid(e) => {
+                println!(
+                    "  {} {} has an invalid service.yaml, keeping the running instance",
+                    "✗".red(),
+                    service_name
+                );
+                println!("    {}", e.dimmed());
+                continue;
+            }
+            DefinitionStatus::Changed => {}
+        }
+
+        let service = ServiceDefinition::from_dir(Path::new(&info.service_path))?;
+
+        print!("{} Restarting {}...", "↻".blue(), service_name);
+        manager.stop(service_name).await?;
+        let options = StartOptions {
+            env: info.env_overrides.clone(),
+            ..Default::default()
+        };
+        manager.start_with(&service, info.port, options).await?;
+
+        match manager
+            .wait_for_health(service_name, info.port, &config.health_for(service_name))
+            .await
+        {
+            Ok(_) => {
+                println!(" {}", "✓".green());
+                manager.set_tags(service_name, info.tags.clone());
+                manager.record_restart(service_name, &info);
+                restarted += 1;
+            }
+            Err(e) => {
+                println!(" {}", "✗".red());
+                manager.stop(service_name).await?;
+                manager.save(&config.state_file)?;
+                return Err(anyhow::anyhow!(
+                    "Health check failed for {}: {}",
+                    service_name,
+                    e
+                ));
+            }
+        }
+    }
+
+    manager.save(&config.state_file)?;
+
+    println!();
+    if restarted == 0 {
+        println!("{} Nothing to apply", "ℹ".blue());
+    } else {
+        println!(
+            "{} Applied changes to {} service(s)",
+            "✓".green(),
+            restarted
+        );
+    }
+
+    Ok(())
+}
+
+/// Converge running services on an environment manifest loaded from `path`.
+///
+/// With `prune`, running services that are not in the manifest are stopped.
+pub(crate) async fn apply_manifest(
+    config: &Config,
+    manager: &mut ProcessManager,
+    manifest: &Manifest,
+    path: &Path,
+    dry_run: bool,
+    prune: bool,
+) -> anyhow::Result<()> {
+    let registry = ServiceRegistry::new(&config.services_dir, &config.repo_url, &config.branch)?;
+    let store = SnapshotStore::new(&config.data_dir);
+
+    // Resolve every definition up front so version mismatches fail before
+    // any change; a dry run installs nothing
+    let (definitions, uninstalled) =
+        manifest::resolve_definitions(manifest, path, &registry, !dry_run)?;
+
+    let running: HashMap<_, _> = manager
+        .running_services()
+        .into_iter()
+        .filter_map(|name| manager.get_info(&name).map(|info| (name, info)))
+        .collect();
+    let mut ports = PortAllocator::new(
+        config.port_range,
+        config.base_port,
+        workspace::ports_in_use(&config.state_dir)?,
+    );
+    let mut actions = manifest::plan(manifest, &running, &mut ports)?;
+    if !prune {
+        actions.retain(|action| !matches!(action, Action::Stop { .. }));
+    }
+
+    println!("{} Plan for {}:", "▶".blue(), path.display());
+    for service in &uninstalled {
+        println!("  {} install {}", "+".green(), service.bold());
+    }
+    for action in &actions {
+        print_action(action);
+    }
+    println!();
+
+    if dry_run {
+        println!("{} Dry run, no changes made", "ℹ".blue());
+        return Ok(());
+    }
+
+    // Refuse snapshot seeds the compliance mode forbids before changing anything
+    for action in &actions {
+        if let Action::Seed { service, sources } = action {
+            for source in sources {
+                super::compliance::check_seed(config, &store, service, source)?;
+            }
+        }
+    }
+
+    for action in &actions {
+        match action {
+            Action::Start { service, port } | Action::Restart { service, port, .. } => {
+                let definition = &definitions[service];
+                let desired = &manifest.services[service];
+
+                let previous = manager.get_info(service);
+                if manager.is_running(service) {
+                    manager.stop(service).await?;
+                }
+
+                print!("{} Starting {} on port {}...", "▶".blue(), service, port);
+                let options = StartOptions {
+                    env: desired.env.clone(),
+                    ..Default::default()
+                };
+                manager.start_with(definition, *port, options).await?;
+                let health = config.health_for(service).with_overrides(&desired.health);
+                if let Err(e) = manager.wait_for_health(service, *port, &health).await {
+                    println!(" {}", "✗".red());
+                    manager.stop(service).await?;
+                    manager.save(&config.state_file)?;
+                    return Err(anyhow::anyhow!(
+                        "Health check failed for {}: {}",
+                        service,
+                        e
+                    ));
+                }
+                if let Some(previous) = &previous {
+                    manager.record_restart(service, previous);
+                }
+                println!(" {}", "✓".green());
+            }
+            Action::Seed { service, sources } => {
+                let payloads = sources
+                    .iter()
+                    .map(|source| seed::load_source(source, &definitions[service], &store))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let port = manager
+                    .get_info(service)
+                    .map(|info| info.port)
+                    .ok_or_else(|| anyhow::anyhow!("{} is not running", service))?;
+
+                let joined = SeedSource::join(sources);
+                print!("{} Seeding {} from {}...", "⬆".blue(), service, joined);
+                let client = ControlClient::new(port);
+                // The first seed is undone to the state from before the reset
+                let mut before_reset = Some(client.export().await);
+                client.reset().await?;
+                for (source, data) in sources.iter().zip(&payloads) {
+                    let prior = match before_reset.take() {
+                        Some(prior) => prior,
+                        None => client.export().await,
+                    };
+                    client.seed(data).await?;
+                    let counts = seed::record_counts(data);
+                    let record = SeedRecord::new(source.to_string(), counts, prior);
+                    manager.record_seed(service, record);
+                }
+                manager.set_seed(service, Some(joined));
+                println!(" {}", "✓".green());
+                for source in sources {
+                    super::audit::record_seed(config, &store, service, source, port)?;
+                }
+            }
+            Action::Stop { service } => {
+                print!("{} Stopping {}...", "■".red(), service);
+                manager.stop(service).await?;
+                println!(" {}", "✓".green());
+            }
+            Action::Unchanged { .. } => {}
+        }
+    }
+
+    // Services converged on the project config remember it, so `status`
+    // can tell when it (or a config it extends) changes
+    let project = config.project_config_path.as_deref() == Some(path);
+    for (name, desired) in &manifest.services {
+        manager.set_tags(name, desired.tags.clone());
+        if project {
+            let fingerprint = reload::project_fingerprint(path, &config.cache_dir, name).ok();
+            manager.set_project(name, path, fingerprint);
+        }
+    }
+    manager.save(&config.state_file)?;
+    super::stop::update_env_file(manager, &config.env_file_name());
+
+    println!();
+    println!("{} Environment matches {}", "✓".green(), path.display());
+    Ok(())
+}
+
+fn print_action(action: &Action) {
+    match action {
+        Action::Start { service, port } => {
+            println!(
+                "  {} start {} on port {}",
+                "+".green(),
+                service.bold(),
+                port
+            )
+        }
+        Action::Restart {
+            service,
+            port,
+            reason,
+        } => println!(
+            "  {} restart {} on port {} ({})",
+            "~".yellow(),
+            service.bold(),
+            port,
+            reason
+        ),
+        Action::Seed { service, sources } => println!(
+            "  {} seed {} from {}",
+            "↻".blue(),
+            service.bold(),
+            SeedSource::join(sources)
+        ),
+        Action::Stop { service } => println!("  {} stop {}", "-".red(), service.bold()),
+        Action::Unchanged { service } => {
+            println!("  {} {} unchanged", "=".dimmed(), service.dimmed())
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use super::{AuditArgs, AuditCommands, AuditExportArgs, AuditFormat, AuditShowArgs};
+use colored::Colorize;
+use doubleagent_core::audit::{AuditEntry, AuditLog, Operation};
+use doubleagent_core::manifest::SeedSource;
+use doubleagent_core::operations::{self, OperationEntry, OperationLog};
+use doubleagent_core::request_log;
+use doubleagent_core::snapshot::{SnapshotManifest, SnapshotStore};
+use doubleagent_core::{dates, Config};
+use std::fs;
+
+pub async fn run(config: Config, args: AuditArgs) -> anyhow::Result<()> {
+    match args.command {
+        AuditCommands::Export(args) => export(&config, args),
+        AuditCommands::Show(args) => show(&config, args),
+    }
+}
+
+fn show(config: &Config, args: AuditShowArgs) -> anyhow::Result<()> {
+    let since = args
+        .since
+        .as_deref()
+        .map(|since| request_log::parse_since(since, dates::now()))
+        .transpose()?
+        .map(|since| since.max(0) as u64);
+    let entries: Vec<OperationEntry> = config
+        .operation_log()
+        .entries()?
+        .into_iter()
+        .filter(|entry| args.command.as_ref().is_none_or(|c| &entry.command == c))
+        .filter(|entry| args.user.as_ref().is_none_or(|u| &entry.user == u))
+        .filter(|entry| since.is_none_or(|since| entry.timestamp >= since))
+        .filter(|entry| !args.failed || !entry.ok)
+        .collect();
+    let shown = &entries[entries.len().saturating_sub(args.limit)..];
+    crate::output::result(shown);
+
+    if shown.is_empty() {
+        println!("No matching commands recorded");
+        return Ok(());
+    }
+    if shown.len() < entries.len() {
+        println!(
+            "{}",
+            format!("… {} earlier (see -n)", entries.len() - shown.len()).dimmed()
+        );
+    }
+    for entry in shown {
+        let mark = if entry.ok { "✓".green() } else { "✗".red() };
+        let mut line = format!(
+            "{} {} {:<12} {}",
+            dates::format_time(entry.timestamp as i64).dimmed(),
+            mark,
+            entry.user,
+            entry.command_line()
+        );
+        if entry.workspace != doubleagent_core::workspace::DEFAULT_WORKSPACE {
+            line.push_str(&format!(
+                " {}",
+                format!("(workspace: {})", entry.workspace).dimmed()
+            ));
+        }
+        println!("{}", line);
+        if let Some(error) = &entry.error {
+            println!("    {}", error.dimmed());
+        }
+    }
+    Ok(())
+}
+
+/// Record a state-changing command in the operation log. Failing to record
+/// it is only warned about, so the log can't break the command.
+pub(crate) fn record_operation(
+    log: &OperationLog,
+    workspace: &str,
+    command: &str,
+    args: &[String],
+    result: &anyhow::Result<()>,
+) {
+    let error = result.as_ref().err().map(|e| format!("{:#}", e));
+    let entry = OperationEntry::new(command, operations::redact_args(args), workspace, error);
+    if let Err(e) = log.append(&entry) {
+        eprintln!(
+            "{} Could not record the command in {}: {}",
+            "⚠".yellow(),
+            log.path().display(),
+            e
+        );
+    }
+}
+
+fn export(config: &Config, args: AuditExportArgs) -> anyhow::Result<()> {
+    let log = AuditLog::new(config.audit_dir());
+    let entries: Vec<AuditEntry> = log
+        .entries()?
+        .into_iter()
+        .filter(|entry| args.service.as_ref().is_none_or(|s| &entry.service == s))
+        .collect();
+
+    let content = match args.format {
+        AuditFormat::Json => serde_json::to_string_pretty(&entries)? + "\n",
+        AuditFormat::Jsonl => entries
+            .iter()
+            .map(|entry| serde_json::to_string(entry).map(|line| line + "\n"))
+            .collect::<Result<String, _>>()?,
+        AuditFormat::Csv => to_csv(&entries),
+    };
+
+    match &args.output {
+        Some(path) => {
+            fs::write(path, content)?;
+            eprintln!(
+                "{} Exported {} audit entries to {}",
+                "✓".green(),
+                entries.len(),
+                path.bold()
+            );
+            if config.audit_log().is_none() {
+                eprintln!(
+                    "{} compliance_mode is off, so new operations aren't being recorded",
+                    "ℹ".blue()
+                );
+            }
+        }
+        None => print!("{}", content),
+    }
+    Ok(())
+}
+
+/// Record an operation on a profile, if compliance mode asks for an audit trail.
+pub(crate) fn record(
+    config: &Config,
+    operation: Operation,
+    manifest: &SnapshotManifest,
+    destination: &str,
+) -> anyhow::Result<()> {
+    if let Some(log) = config.audit_log() {
+        log.append(&AuditEntry::new(
+            operation,
+            manifest,
+            destination,
+            &config.compliance_mode.to_string(),
+        ))?;
+    }
+    Ok(())
+}
+
+/// Record seeding a running service, if it was seeded from a snapshot profile.
+pub(crate) fn record_seed(
+    config: &Config,
+    store: &SnapshotStore,
+    service: &str,
+    source: &SeedSource,
+    port: u16,
+) -> anyhow::Result<()> {
+    let SeedSource::Snapshot(profile) = source else {
+        return Ok(());
+    };
+    if config.audit_log().is_none() {
+        return Ok(());
+    }
+    let manifest = store.manifest(service, profile)?;
+    record(
+        config,
+        Operation::Seed,
+        &manifest,
+        &format!("http://localhost:{}", port),
+    )
+}
+
+fn to_csv(entries: &[AuditEntry]) -> String {
+    let mut csv = String::from(
+        "timestamp,user,operation,service,profile,connector,redactions,destination,compliance_mode\n",
+    );
+    for entry in entries {
+        let fields = [
+            entry.timestamp.to_string(),
+            entry.user.clone(),
+            entry.operation.to_string(),
+            entry.service.clone(),
+            entry.profile.clone(),
+            entry.connector.clone(),
+            entry.redactions.to_string(),
+            entry.destination.clone(),
+            entry.compliance_mode.clone(),
+        ];
+        let row: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Quote a CSV field if it contains a separator, quote or newline.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,108 @@
+use colored::Colorize;
+use doubleagent_core::compliance::{Compliance, Violation};
+use doubleagent_core::manifest::SeedSource;
+use doubleagent_core::snapshot::{SnapshotManifest, SnapshotStore};
+use doubleagent_core::Config;
+use serde_json::Value;
+
+/// Check that the compliance policy allows handling a service's data.
+pub(crate) fn check_service(config: &Config, service: &str, action: &str) -> anyhow::Result<()> {
+    let Some(compliance) = load(config)? else {
+        return Ok(());
+    };
+    Ok(settle(
+        &compliance,
+        action,
+        compliance.check_service(service),
+    )?)
+}
+
+/// Check that the compliance policy allows transferring through a registry.
+pub(crate) fn check_registry(config: &Config, url: &str, action: &str) -> anyhow::Result<()> {
+    let Some(compliance) = load(config)? else {
+        return Ok(());
+    };
+    Ok(settle(&compliance, action, compliance.check_registry(url))?)
+}
+
+/// Check a stored profile against the compliance mode and policy.
+pub(crate) fn check_profile(
+    config: &Config,
+    store: &SnapshotStore,
+    service: &str,
+    profile: &str,
+    action: &str,
+) -> anyhow::Result<()> {
+    let Some(compliance) = load(config)? else {
+        return Ok(());
+    };
+    let manifest = store.manifest(service, profile)?;
+    let seed = store.load_seed_payload(service, profile)?;
+    Ok(settle(
+        &compliance,
+        action,
+        compliance.check_profile(&manifest, &seed),
+    )?)
+}
+
+/// The check a profile being pulled, refreshed or fetched gets before it is
+/// stored, so one the compliance mode doesn't allow keeping is never written
+/// and the profile stored before stays as it was.
+pub(crate) fn check_staged(
+    config: &Config,
+    action: &str,
+) -> anyhow::Result<impl Fn(&SnapshotManifest, &Value) -> doubleagent_core::Result<()>> {
+    let compliance = load(config)?;
+    let action = action.to_string();
+    Ok(move |manifest: &SnapshotManifest, seed: &Value| {
+        let Some(compliance) = &compliance else {
+            return Ok(());
+        };
+        settle(
+            compliance,
+            &action,
+            compliance.check_profile(manifest, seed),
+        )
+    })
+}
+
+/// Check the seed a service is about to be seeded with, if it is a snapshot.
+pub(crate) fn check_seed(
+    config: &Config,
+    store: &SnapshotStore,
+    service: &str,
+    source: &SeedSource,
+) -> anyhow::Result<()> {
+    match source {
+        SeedSource::Snapshot(profile) => check_profile(
+            config,
+            store,
+            service,
+            profile,
+            &format!("seed {}/{}", service, profile),
+        ),
+        SeedSource::Fixture(_) | SeedSource::File(_) => Ok(()),
+    }
+}
+
+fn load(config: &Config) -> anyhow::Result<Option<Compliance>> {
+    let compliance = config.compliance()?;
+    Ok(compliance.enabled().then_some(compliance))
+}
+
+/// Fail on blocking violations and print the ones the mode only reports.
+fn settle(
+    compliance: &Compliance,
+    action: &str,
+    violations: Vec<Violation>,
+) -> doubleagent_core::Result<()> {
+    for violation in compliance.settle(action, violations)? {
+        eprintln!(
+            "{} {} {}",
+            "⚠".yellow(),
+            violation.message,
+            format!("({} compliance mode)", compliance.mode).dimmed()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use super::{ConfigArgs, ConfigCommands};
+use colored::Colorize;
+use doubleagent_core::operations::REDACTED;
+use doubleagent_core::settings::{self, SECRET_KEYS};
+use doubleagent_core::Config;
+use std::path::PathBuf;
+
+pub async fn run(config: Config, args: ConfigArgs) -> anyhow::Result<()> {
+    let settings = config.settings()?;
+
+    match args.command {
+        ConfigCommands::Get(args) => {
+            println!("{}", settings.get(&args.key)?);
+        }
+        ConfigCommands::Set(args) => {
+            let path = target_file(&config, args.project)?;
+            settings::set_value(&path, &args.key, &args.value)?;
+            let value = if SECRET_KEYS.contains(&args.key.as_str()) {
+                REDACTED
+            } else {
+                &args.value
+            };
+            println!(
+                "{} Set {} = {} in {}",
+                "✓".green(),
+                args.key.bold(),
+                value,
+                path.display()
+            );
+        }
+        ConfigCommands::Unset(args) => {
+            let path = target_file(&config, args.project)?;
+            if settings::unset_value(&path, &args.key)? {
+                println!(
+                    "{} Removed {} from {}",
+                    "✓".green(),
+                    args.key.bold(),
+                    path.display()
+                );
+            } else {
+                println!(
+                    "{} {} is not set in {}",
+                    "ℹ".blue(),
+                    args.key,
+                    path.display()
+                );
+            }
+        }
+        ConfigCommands::Show(args) => {
+            for (name, resolved) in settings.show()? {
+                println!("{} = {}", name.bold(), resolved.value.cyan());
+                if args.origin {
+                    println!("    {}", format!("from {}", resolved.origin).dimmed());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The config file a set/unset writes to.
+fn target_file(config: &Config, project: bool) -> anyhow::Result<PathBuf> {
+    if !project {
+        return Ok(config.config_file.clone());
+    }
+
+    config
+        .project_config_path
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("No doubleagent.yaml found in this directory or any parent"))
+}
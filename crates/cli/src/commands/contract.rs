@@ -1,98 +1,1916 @@
-use super::ContractArgs;
+use super::{
+    ContractArgs, ContractCommands, ContractHistoryArgs, ContractReportFormat, ContractTarget,
+};
 use anyhow::Context;
 use colored::Colorize;
-use doubleagent_core::{mise, Config, ProcessManager, ServiceRegistry};
+use doubleagent_core::artifacts;
+use doubleagent_core::conformance::{self, Check, Spec};
+use doubleagent_core::contract::{self, ContractReport, Outcome, TargetResults, TestCase};
+use doubleagent_core::coverage::{self, Coverage};
+use doubleagent_core::golden::{Comparison, DiffLine, Recorded, Scenarios};
+use doubleagent_core::history::{ContractHistory, Regressions, RunRecord};
+use doubleagent_core::manifest::SeedSource;
+use doubleagent_core::namespace;
+use doubleagent_core::ports::PortAllocator;
+use doubleagent_core::report;
+use doubleagent_core::snapshot::SnapshotStore;
+use doubleagent_core::{
+    dates, mise, openapi, workspace, Config, ContractsConfig, ControlClient, ProcessManager,
+    ServiceDefinition, ServiceInfo, ServiceRegistry,
+};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
-pub async fn run(args: ContractArgs) -> anyhow::Result<()> {
-    let config = Config::load()?;
+/// Tells contract tests which target they run against (`fake` or `real`)
+const TARGET_ENV: &str = "DOUBLEAGENT_CONTRACT_TARGET";
+
+/// Target name of the official SDK's smoke script, which runs against the fake
+const SDK_TARGET: &str = "sdk";
+
+/// Exit code when tests failed, or coverage fell below `--min-coverage`
+const EXIT_TESTS_FAILED: i32 = 1;
+
+/// Exit code when a fake failed to start or become healthy
+const EXIT_FAKE_FAILED: i32 = 2;
+
+/// Exit code for any other error, such as a bad service.yaml or a test
+/// runner that couldn't be run
+const EXIT_ERROR: i32 = 3;
+
+/// A fake failed to start or become healthy, as opposed to its tests failing
+#[derive(Debug)]
+pub(crate) struct FakeFailed(String);
+
+impl std::fmt::Display for FakeFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for FakeFailed {}
+
+/// How to run the contract command, the same for every target
+struct RunOptions<'a> {
+    /// A report or comparison needs the runner's per-test results (they are
+    /// collected for the history either way)
+    report: bool,
+    filter: Option<&'a str>,
+    /// Appended to the configured command
+    args: &'a [String],
+    /// Measure which of the fake's endpoints the tests request
+    coverage: bool,
+    /// Test the fakes already running instead of starting them
+    use_running: bool,
+    /// Rewrite the golden files from the fake's responses instead of comparing
+    update_golden: bool,
+    /// Snapshot profiles to seed the fake with, one run each
+    snapshots: &'a [String],
+    /// Stop the runner at its first failed test, and skip the runs left
+    fail_fast: bool,
+    /// Set once a run fails, shared by the services run in parallel
+    failed: AtomicBool,
+    /// Directory of this run's artifacts, collected from the fakes that fail
+    artifacts: PathBuf,
+}
+
+impl RunOptions<'_> {
+    /// Note how a run went, for `--fail-fast`
+    fn finished(&self, results: &TargetResults) {
+        if !results.success {
+            self.failed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Whether to skip the runs left, as one failed with `--fail-fast`
+    fn stopped(&self) -> bool {
+        self.fail_fast && self.failed.load(Ordering::Relaxed)
+    }
+}
+
+/// A service whose contracts are run, and what they run against
+struct Suite {
+    service: ServiceDefinition,
+    contracts: ContractsConfig,
+    contracts_dir: PathBuf,
+    fake: bool,
+    real: Option<Target>,
+    /// Description to check the fake's responses against
+    spec: Option<Spec>,
+    /// Scenarios to record, or to compare the fake's responses with
+    golden: Option<Scenarios>,
+    /// Expression selecting the tests to run by tag
+    tags: Option<String>,
+}
+
+/// Where one run of the contract tests sends its requests
+pub(super) struct Target {
+    /// `fake` or `real`
+    name: &'static str,
+    pub(super) url: String,
+    /// Port of the fake
+    port: Option<u16>,
+    /// Token for the real API, from its `auth_env` variable
+    pub(super) token: Option<String>,
+}
+
+impl Target {
+    /// The fake listening on `port`
+    fn fake(port: u16) -> Self {
+        Target {
+            name: "fake",
+            url: format!("http://localhost:{}", port),
+            port: Some(port),
+            token: None,
+        }
+    }
+}
+
+/// Run the contracts, exiting with a code that tells CI why they failed
+pub async fn run(config: Config, args: ContractArgs) -> anyhow::Result<()> {
+    let code = match run_contracts(config, args).await {
+        Ok(0) => 0,
+        Ok(code) => {
+            let (error, message) = match code {
+                EXIT_FAKE_FAILED => ("fake_failed", "A fake failed during the contract tests"),
+                _ => ("tests_failed", "Contract tests failed"),
+            };
+            crate::output::failed(error, &anyhow::anyhow!(message));
+            code
+        }
+        Err(e) => {
+            crate::print_error(&e);
+            if e.chain().any(|cause| cause.is::<FakeFailed>()) {
+                EXIT_FAKE_FAILED
+            } else {
+                EXIT_ERROR
+            }
+        }
+    };
+    if code != 0 {
+        std::process::exit(code);
+    }
+    Ok(())
+}
+
+/// Run the contracts, returning the exit code for the tests' outcome
+async fn run_contracts(config: Config, args: ContractArgs) -> anyhow::Result<i32> {
+    if let Some(ContractCommands::History(history_args)) = &args.command {
+        history(&config, history_args)?;
+        return Ok(0);
+    }
     let registry = ServiceRegistry::new(&config.services_dir, &config.repo_url, &config.branch)?;
 
-    // Auto-install if not present
-    let service = registry.get_or_install(&args.service, true)?;
+    let suites = if args.all {
+        // Services that can't run what was asked for are left out, not failed
+        let mut suites = Vec::new();
+        for service in registry.list()? {
+            let name = service.name.clone();
+            match prepare(service, &args) {
+                Ok(suite) => suites.push(suite),
+                Err(e) => println!("{} Skipping {}: {}", "⚠".yellow(), name, e),
+            }
+        }
+        if suites.is_empty() {
+            anyhow::bail!("No installed service has contracts to run");
+        }
+        suites
+    } else {
+        args.services
+            .iter()
+            // Auto-install if not present
+            .map(|name| {
+                let service = registry.get_or_install(name, true)?;
+                prepare(service, &args)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?
+    };
 
+    if args.record {
+        for suite in &suites {
+            record(suite).await?;
+        }
+        return Ok(0);
+    }
+    if args.list {
+        for suite in &suites {
+            print_tests(&config, suite, &args)?;
+        }
+        return Ok(0);
+    }
+
+    // Refuse profiles the compliance mode forbids before starting anything
+    let store = SnapshotStore::new(&config.data_dir);
+    for suite in suites.iter().filter(|suite| suite.fake) {
+        for profile in &args.snapshots {
+            if profile != contract::EMPTY_PROFILE {
+                let source = SeedSource::Snapshot(profile.clone());
+                super::compliance::check_seed(&config, &store, &suite.service.name, &source)?;
+            }
+        }
+    }
+
+    let options = RunOptions {
+        report: args.report.is_some() || args.compare_to_last,
+        filter: args.filter.as_deref(),
+        args: &args.args,
+        coverage: args.coverage || args.min_coverage.is_some(),
+        use_running: args.use_running,
+        update_golden: args.update_golden,
+        snapshots: &args.snapshots,
+        fail_fast: args.fail_fast,
+        failed: AtomicBool::new(false),
+        artifacts: artifacts::run_dir(Path::new(&args.artifacts_dir), dates::now()),
+    };
+    // A fake that fails to start on its own is an error; one of several is
+    // reported with the rest
+    let (reports, unhealthy) = match suites.as_slice() {
+        [suite] => (vec![run_suite(&config, suite, &options).await?], false),
+        suites => run_parallel(&config, suites, &options).await?,
+    };
+
+    crate::output::result(serde_json::json!({ "services": reports }));
+    if let Some(format) = args.report {
+        write_report(&reports, &suites, format, args.report_file.as_deref())?;
+    }
+    save_history(&config, &suites, &reports, args.compare_to_last)?;
+
+    let single = reports.len() == 1;
+    let failed: Vec<String> = reports
+        .iter()
+        .flat_map(|report| {
+            report.targets.iter().filter(|r| !r.success).map(move |r| {
+                match (single, r.target.as_str(), &r.profile) {
+                    (true, "fake", None) => "the fake".to_string(),
+                    (true, "fake", Some(profile)) => format!("the fake with {}", profile),
+                    (true, SDK_TARGET, _) => "the fake with the official SDK".to_string(),
+                    (true, _, _) => "the real API".to_string(),
+                    (_, _, _) => format!("{} ({})", report.service, r.label()),
+                }
+            })
+        })
+        .collect();
+    if !args.snapshots.is_empty() {
+        print_profiles(&reports);
+    }
+    println!();
+    if options.stopped() {
+        println!("{} Stopped at the first failure (--fail-fast)", "■".red());
+    }
+    if failed.is_empty() {
+        println!("{} All contract tests passed!", "✓".green());
+    } else {
+        println!(
+            "{} Contract tests failed against {}",
+            "✗".red(),
+            failed.join(", ")
+        );
+    }
+
+    // Services whose fake ran but whose tests hit too few of its endpoints
+    let below: Vec<String> = match args.min_coverage {
+        Some(min) => reports
+            .iter()
+            .filter(|report| report.targets.iter().any(|r| r.target == "fake"))
+            .filter_map(|report| match &report.coverage {
+                Some(coverage) if coverage.percent >= min => None,
+                Some(coverage) => Some(format!("{} ({:.1}%)", report.service, coverage.percent)),
+                None => Some(format!("{} (unknown)", report.service)),
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    if !below.is_empty() {
+        println!(
+            "{} Endpoint coverage is below {}% for {}",
+            "✗".red(),
+            args.min_coverage.unwrap_or_default(),
+            below.join(", ")
+        );
+    }
+    if unhealthy {
+        return Ok(EXIT_FAKE_FAILED);
+    }
+    if !failed.is_empty() || !below.is_empty() {
+        return Ok(EXIT_TESTS_FAILED);
+    }
+
+    Ok(0)
+}
+
+/// Check a service's contracts can run as asked before starting anything
+fn prepare(service: ServiceDefinition, args: &ContractArgs) -> anyhow::Result<Suite> {
+    let (mut contracts, mut contracts_dir) = contracts(&service)?;
+
+    // A suite narrows the run to its tags; --tag and --skip-tag narrow it further
+    let mut tags = contract::tag_expression(&args.tags, &args.skip_tags);
+    if let Some(name) = &args.suite {
+        let (config, suite) = contracts.suite(name)?;
+        tags = match (
+            contract::tag_expression(&suite.tags, &suite.skip_tags),
+            tags,
+        ) {
+            (Some(suite), Some(extra)) => Some(format!("({}) and ({})", suite, extra)),
+            (suite, extra) => suite.or(extra),
+        };
+        contracts_dir = checked_dir(&service, &config)?;
+        contracts = config;
+    }
+
+    // Check the real API is configured; recording always needs it
+    let target = if args.record {
+        ContractTarget::Real
+    } else {
+        args.target
+    };
+    let real = match target {
+        ContractTarget::Fake => None,
+        ContractTarget::Real | ContractTarget::Both => Some(real_target(&service, &contracts)?),
+    };
+
+    let fake = target != ContractTarget::Real;
+    let spec = (args.validate_spec && fake)
+        .then(|| Spec::load(&service))
+        .transpose()?;
+    let golden = (args.record || ((args.compare || args.update_golden) && fake))
+        .then(|| {
+            let config = contracts.golden.clone().unwrap_or_default();
+            Scenarios::load(&contracts_dir, &config)
+        })
+        .transpose()?;
+
+    Ok(Suite {
+        service,
+        contracts,
+        contracts_dir,
+        fake,
+        real,
+        spec,
+        golden,
+        tags,
+    })
+}
+
+/// A service's contracts configuration and the directory its tests are in,
+/// checked to be runnable
+pub(super) fn contracts(service: &ServiceDefinition) -> anyhow::Result<(ContractsConfig, PathBuf)> {
     // Get contracts config from service.yaml
-    let contracts_config = service.contracts.as_ref().ok_or_else(|| {
+    let contracts = service.contracts.clone().ok_or_else(|| {
         anyhow::anyhow!(
             "No contracts configuration found in service.yaml for '{}'.\n\
              Add a 'contracts' section with a 'command' to run tests.",
-            args.service
+            service.name
         )
     })?;
 
-    let contracts_dir = service.path.join(&contracts_config.directory);
+    let contracts_dir = checked_dir(service, &contracts)?;
+    Ok((contracts, contracts_dir))
+}
+
+/// The directory of a service's contract tests, checked to exist along with
+/// a command to run them
+fn checked_dir(
+    service: &ServiceDefinition,
+    contracts: &ContractsConfig,
+) -> anyhow::Result<PathBuf> {
+    let contracts_dir = service.path.join(&contracts.directory);
 
     if !contracts_dir.exists() {
         return Err(anyhow::anyhow!(
             "Contracts directory not found for {}. Expected: {}",
-            args.service,
+            service.name,
             contracts_dir.display()
         ));
     }
 
-    if contracts_config.command.is_empty() {
+    if contracts.command.is_empty() {
         return Err(anyhow::anyhow!(
             "No command specified in contracts configuration for '{}'",
-            args.service
+            service.name
         ));
     }
 
+    Ok(contracts_dir)
+}
+
+/// Run a service's smoke tests (`contracts.smoke`, or every test if it has
+/// none) against its fake on `port`, before `doubleagent run` hands it over
+///
+/// The tests' output is shown only if they fail.
+pub fn verify(service: &ServiceDefinition, port: u16) -> anyhow::Result<()> {
+    let (contracts, contracts_dir) = contracts(service)?;
+    install_tools(service)?;
+    let options = RunOptions {
+        report: false,
+        filter: contracts.smoke.as_deref(),
+        args: &[],
+        coverage: false,
+        use_running: true,
+        update_golden: false,
+        snapshots: &[],
+        fail_fast: false,
+        failed: AtomicBool::new(false),
+        artifacts: PathBuf::new(),
+    };
+    let suite = Suite {
+        service: service.clone(),
+        contracts: contracts.clone(),
+        contracts_dir,
+        fake: true,
+        real: None,
+        spec: None,
+        golden: None,
+        tags: None,
+    };
+    let fake = Target::fake(port);
+    let log = std::env::temp_dir().join(format!(
+        "doubleagent-verify-{}-{}.log",
+        std::process::id(),
+        service.name
+    ));
+    let _ = fs::remove_file(&log);
+    let results = run_tests(&suite, &fake, &options, Some(&log), None);
+    let output = fs::read_to_string(&log).unwrap_or_default();
+    let _ = fs::remove_file(&log);
+    if !results?.success {
+        anyhow::bail!(
+            "{}'s smoke contract tests failed:\n{}",
+            service.name,
+            output.trim_end()
+        );
+    }
+    Ok(())
+}
+
+/// Print the tests of a service's contracts, as collected by the runner
+fn print_tests(config: &Config, suite: &Suite, args: &ContractArgs) -> anyhow::Result<()> {
+    install_tools(&suite.service)?;
+    let tests = list_tests(config, suite, args)?;
     println!(
-        "{} Running contract tests for {}",
+        "{} {}: {} {}",
         "▶".blue(),
-        args.service.bold()
+        suite.service.name.bold(),
+        tests.len(),
+        if tests.len() == 1 { "test" } else { "tests" }
     );
-    println!();
+    for test in &tests {
+        if test.tags.is_empty() {
+            println!("  {}", test.id);
+        } else {
+            println!(
+                "  {}  {}",
+                test.id,
+                format!("[{}]", test.tags.join(", ")).dimmed()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Ask the runner to collect a service's tests without running any
+fn list_tests(
+    config: &Config,
+    suite: &Suite,
+    args: &ContractArgs,
+) -> anyhow::Result<Vec<contract::ListedTest>> {
+    let service = &suite.service;
+    let command: Vec<String> = suite
+        .contracts
+        .command
+        .iter()
+        .chain(&args.args)
+        .cloned()
+        .collect();
+    let mut cmd = mise::build_command(&service.path, &command)?;
+    cmd.current_dir(&suite.contracts_dir).stdin(Stdio::null());
+
+    // Nothing is requested, but the tests' setup may expect a URL
+    let target = suite.real.as_ref().filter(|_| !suite.fake);
+    let fake = Target::fake(config.contract_port);
+    set_target_env(&mut cmd, suite, target.unwrap_or(&fake), None)?;
+
+    let list_file = std::env::temp_dir().join(format!(
+        "doubleagent-contract-{}-{}.list",
+        std::process::id(),
+        service.name
+    ));
+    let _ = fs::remove_file(&list_file);
+    cmd.env(contract::LIST_ENV, &list_file);
+    if let Some(filter) = &args.filter {
+        cmd.env(contract::FILTER_ENV, filter);
+    }
+    if let Some(tags) = &suite.tags {
+        cmd.env(contract::TAGS_ENV, tags);
+    }
+    let addopts = std::env::var("PYTEST_ADDOPTS").unwrap_or_default();
+    cmd.env(
+        "PYTEST_ADDOPTS",
+        contract::pytest_list_addopts(&addopts, args.filter.as_deref(), suite.tags.as_deref()),
+    );
+
+    let output = cmd.output().with_context(|| {
+        format!(
+            "Failed to list the contract tests for '{}' with: {}",
+            service.name,
+            command.join(" ")
+        )
+    })?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // pytest exits with 5 when the filter selects no tests
+    if !output.status.success() && output.status.code() != Some(5) {
+        anyhow::bail!(
+            "Listing the contract tests for '{}' failed:\n{}{}",
+            service.name,
+            stdout,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+    match fs::read_to_string(&list_file) {
+        Ok(text) => {
+            let _ = fs::remove_file(&list_file);
+            Ok(contract::parse_list(&text)?)
+        }
+        Err(_) => Ok(contract::parse_collected(&stdout)),
+    }
+}
+
+/// Record the real API's responses to a service's scenarios as golden files
+async fn record(suite: &Suite) -> anyhow::Result<()> {
+    let (Some(real), Some(scenarios)) = (&suite.real, &suite.golden) else {
+        anyhow::bail!("Nothing to record for {}", suite.service.name);
+    };
+    println!(
+        "{} Recording {} responses of the real API at {}",
+        "▶".blue(),
+        scenarios.requests.len(),
+        real.url.cyan()
+    );
+    let recorded = scenarios
+        .record(&real.url, real.token.as_deref())
+        .await
+        .with_context(|| format!("Failed to record the real {} API", suite.service.name))?;
+    print_recorded(&recorded, &scenarios.directory, &mut std::io::stdout())?;
+    Ok(())
+}
+
+/// Print what each golden file recorded and how it changed, then a summary
+fn print_recorded(
+    recorded: &[Recorded],
+    directory: &Path,
+    out: &mut dyn Write,
+) -> std::io::Result<()> {
+    let (mut new, mut changed) = (0, 0);
+    for recorded in recorded {
+        let golden = &recorded.golden;
+        let change = match &recorded.changes {
+            None => {
+                new += 1;
+                "new".green()
+            }
+            Some(changes) if changes.is_empty() => "unchanged".dimmed(),
+            Some(changes) => {
+                changed += 1;
+                format!("{} changes", changes.len()).yellow()
+            }
+        };
+        writeln!(
+            out,
+            "  {} {} {} ({}) {}",
+            "✓".green(),
+            golden.method,
+            golden.path,
+            golden.status,
+            change
+        )?;
+        print_diff(&recorded.diff, out)?;
+    }
+    writeln!(
+        out,
+        "{} Wrote {} golden files to {} ({} new, {} changed, {} unchanged)",
+        "✓".green(),
+        recorded.len(),
+        directory.display(),
+        new,
+        changed,
+        recorded.len() - new - changed
+    )
+}
 
-    // Install mise tools if .mise.toml exists
+/// Lines of a diff that changed, with two lines of context around them
+fn print_diff(diff: &[DiffLine], out: &mut dyn Write) -> std::io::Result<()> {
+    const CONTEXT: usize = 2;
+    let changed: Vec<usize> = diff
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, DiffLine::Same(_)))
+        .map(|(i, _)| i)
+        .collect();
+    let near = |i: usize| changed.iter().any(|c| c.abs_diff(i) <= CONTEXT);
+    let mut skipped = false;
+    for (i, line) in diff.iter().enumerate() {
+        if !near(i) {
+            skipped = true;
+            continue;
+        }
+        if skipped {
+            writeln!(out, "      {}", "...".dimmed())?;
+            skipped = false;
+        }
+        match line {
+            DiffLine::Same(text) => writeln!(out, "        {}", text.dimmed())?,
+            DiffLine::Removed(text) => writeln!(out, "      {}", format!("- {}", text).red())?,
+            DiffLine::Added(text) => writeln!(out, "      {}", format!("+ {}", text).green())?,
+        }
+    }
+    Ok(())
+}
+
+/// Install the mise tools a service's contracts need, if it has a .mise.toml
+fn install_tools(service: &ServiceDefinition) -> anyhow::Result<()> {
     mise::install_tools(&service.path).with_context(|| {
         format!(
             "Failed to install mise tools for '{}' at {}",
-            args.service,
+            service.name,
             service.path.display()
         )
-    })?;
+    })
+}
+
+/// Run one service's contracts, with the tests' output shown as they run
+async fn run_suite(
+    config: &Config,
+    suite: &Suite,
+    options: &RunOptions<'_>,
+) -> anyhow::Result<ContractReport> {
+    println!(
+        "{} Running contract tests for {}",
+        "▶".blue(),
+        suite.service.name.bold()
+    );
+    if let Some(tags) = &suite.tags {
+        println!("  Tests tagged: {}", tags);
+    }
+    println!();
+
+    install_tools(&suite.service)?;
+    if runs_sdk(suite, options) {
+        install_sdk(suite)?;
+    }
+
+    let mut targets = Vec::new();
+    let mut coverage = None;
+    if suite.fake {
+        let (results, fake_coverage) = run_against_fake(config, suite, options).await?;
+        targets.extend(results);
+        coverage = fake_coverage;
+    }
+    if let Some(real) = suite.real.as_ref().filter(|_| !options.stopped()) {
+        if suite.fake {
+            println!();
+        }
+        println!(
+            "{} Running against the real API at {}",
+            "▶".blue(),
+            real.url.cyan()
+        );
+        println!();
+        targets.push(run_tests(suite, real, options, None, None)?);
+    }
 
-    // Start the service before running tests
+    Ok(ContractReport {
+        service: suite.service.name.clone(),
+        targets,
+        coverage,
+    })
+}
+
+/// Run several services' contracts at once, each fake on its own port
+///
+/// The tests' output is kept in a log per run and shown for the runs that
+/// fail, after a summary of every service. Also returns whether any fake
+/// failed its health check.
+async fn run_parallel(
+    config: &Config,
+    suites: &[Suite],
+    options: &RunOptions<'_>,
+) -> anyhow::Result<(Vec<ContractReport>, bool)> {
+    println!(
+        "{} Running contract tests for {} services in parallel",
+        "▶".blue(),
+        suites.len()
+    );
+    println!();
+
+    for suite in suites {
+        install_tools(&suite.service)?;
+        if runs_sdk(suite, options) {
+            install_sdk(suite)?;
+        }
+    }
+
+    // Start every fake before waiting on any, so they come up together
     let mut manager = ProcessManager::load(&config.state_file)?;
-    let port: u16 = 18080;
+    let mut ports = PortAllocator::new(
+        config.port_range,
+        config.contract_port,
+        workspace::ports_in_use(&config.state_dir)?,
+    );
+    let mut fakes = Vec::new();
+    for suite in suites.iter().filter(|suite| suite.fake) {
+        let port = if options.use_running {
+            running(&manager, &suite.service.name)?.port
+        } else {
+            let port = ports.allocate_free()?;
+            start(&mut manager, &suite.service, port).await?;
+            port
+        };
+        fakes.push((suite.service.name.as_str(), port));
+    }
+    manager.save(&config.state_file)?;
+
+    let mut started = Vec::new();
+    for suite in suites {
+        let fake = match fakes.iter().find(|(name, _)| *name == suite.service.name) {
+            Some((name, port)) => {
+                let health = config.health_for(name);
+                Some(match manager.wait_for_health(name, *port, &health).await {
+                    Ok(()) => Ok(Target::fake(*port)),
+                    Err(e) => {
+                        options.failed.store(true, Ordering::Relaxed);
+                        Err(format!("Health check failed: {}\n", e))
+                    }
+                })
+            }
+            None => None,
+        };
+        started.push(fake);
+    }
+
+    let log_dir = std::env::temp_dir();
+    let log_path = |suite: &Suite, target: &str| {
+        log_dir.join(format!(
+            "doubleagent-contract-{}-{}-{}.log",
+            std::process::id(),
+            suite.service.name,
+            target
+        ))
+    };
+
+    // Each service's runs go one after the other, in a thread of their own;
+    // seeding between profiles goes through the runtime
+    let unhealthy = started.iter().any(|fake| matches!(fake, Some(Err(_))));
+    let runtime = tokio::runtime::Handle::current();
+    let store = SnapshotStore::new(&config.data_dir);
+    let reports: Vec<anyhow::Result<ContractReport>> = std::thread::scope(|scope| {
+        let runs: Vec<_> = suites
+            .iter()
+            .zip(&started)
+            .map(|(suite, fake)| {
+                let log_path = &log_path;
+                let (runtime, store, fakes) = (&runtime, &store, &fakes);
+                scope.spawn(move || {
+                    let mut targets = Vec::new();
+                    match fake {
+                        Some(Ok(fake)) => {
+                            let port = fakes
+                                .iter()
+                                .find(|(name, _)| *name == suite.service.name)
+                                .map_or(0, |(_, port)| *port);
+                            let log = log_path(suite, fake.name);
+                            targets.extend(runtime.block_on(run_profiles(
+                                config,
+                                store,
+                                suite,
+                                fake,
+                                port,
+                                options,
+                                Some(&log),
+                            ))?);
+                            if runs_sdk(suite, options) && !options.stopped() {
+                                targets.push(run_sdk(suite, fake, options, Some(&log))?);
+                            }
+                        }
+                        Some(Err(e)) => {
+                            fs::write(log_path(suite, "fake"), e)?;
+                            targets.push(TargetResults {
+                                target: "fake".to_string(),
+                                profile: None,
+                                success: false,
+                                time: 0.0,
+                                tests: Vec::new(),
+                            });
+                        }
+                        None => {}
+                    }
+                    if let Some(real) = suite.real.as_ref().filter(|_| !options.stopped()) {
+                        targets.push(run_tests(
+                            suite,
+                            real,
+                            options,
+                            Some(&log_path(suite, real.name)),
+                            None,
+                        )?);
+                    }
+                    Ok(ContractReport {
+                        service: suite.service.name.clone(),
+                        targets,
+                        coverage: None,
+                    })
+                })
+            })
+            .collect();
+        runs.into_iter()
+            .map(|run| {
+                run.join()
+                    .unwrap_or_else(|_| Err(anyhow::anyhow!("Contract run panicked")))
+            })
+            .collect()
+    });
+
+    // Ask the fakes what the tests requested, then check their responses,
+    // while they still run
+    let mut reports = reports;
+    for ((suite, fake), report) in suites.iter().zip(&started).zip(&mut reports) {
+        let (Some(Ok(fake)), Ok(report)) = (fake, report) else {
+            continue;
+        };
+        if options.coverage {
+            if let Some((_, port)) = fakes.iter().find(|(name, _)| *name == suite.service.name) {
+                report.coverage = endpoint_coverage(suite, *port).await;
+            }
+        }
+        if suite.spec.is_none() && suite.golden.is_none() {
+            continue;
+        }
+        let mut log = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(log_path(suite, fake.name))?;
+        let Some(results) = report.targets.iter_mut().rfind(|r| r.target == fake.name) else {
+            continue;
+        };
+        if let Some(spec) = &suite.spec {
+            validate_spec(spec, fake, results, &mut log).await?;
+        }
+        if let Some(scenarios) = &suite.golden {
+            check_golden(scenarios, fake, results, options, &mut log).await?;
+        }
+    }
+
+    // Keep what shows why a fake failed, while it still runs
+    for ((suite, fake), report) in suites.iter().zip(&started).zip(&reports) {
+        let failed = match (fake, report) {
+            (None, _) => false,
+            (Some(Ok(_)), Ok(report)) => failed_fake(&report.targets),
+            _ => true,
+        };
+        if let Some((_, port)) = fakes.iter().find(|(name, _)| *name == suite.service.name) {
+            if failed {
+                collect_artifacts(&manager, suite, *port, options).await;
+            }
+        }
+    }
+
+    // Always stop the fakes started for the tests, regardless of outcome
+    if !options.use_running {
+        for (name, _) in &fakes {
+            manager.stop(name).await?;
+        }
+        manager.save(&config.state_file)?;
+    }
+    let reports = reports.into_iter().collect::<anyhow::Result<Vec<_>>>()?;
+
+    print_summary(&reports);
+    for (suite, report) in suites.iter().zip(&reports) {
+        for target in ["fake", "real"] {
+            // The SDK's smoke script writes to the fake's log
+            let mut results = report
+                .targets
+                .iter()
+                .filter(|r| r.target == target || (target == "fake" && r.target == SDK_TARGET));
+            let path = log_path(suite, target);
+            if results.any(|r| !r.success) {
+                println!();
+                println!(
+                    "{} {} ({}) output:",
+                    "✗".red(),
+                    report.service.bold(),
+                    target
+                );
+                print!("{}", fs::read_to_string(&path).unwrap_or_default());
+            }
+            let _ = fs::remove_file(&path);
+        }
+    }
+    Ok((reports, unhealthy))
+}
+
+/// A table of each service's result against each target
+fn print_summary(reports: &[ContractReport]) {
+    let width = reports
+        .iter()
+        .map(|r| r.service.len())
+        .chain(["SERVICE".len()])
+        .max()
+        .unwrap_or_default();
+    // A target run once per profile passes only if every run did
+    let cell = |report: &ContractReport, target: &str| {
+        let mut results = report.targets.iter().filter(|r| r.target == target);
+        match results.next() {
+            Some(first) if first.success && results.all(|r| r.success) => {
+                format!("{:<8}", "passed").green()
+            }
+            Some(_) => format!("{:<8}", "failed").red(),
+            None => format!("{:<8}", "-").dimmed(),
+        }
+    };
+
+    let coverage = reports.iter().any(|r| r.coverage.is_some());
+    let sdk = reports
+        .iter()
+        .any(|r| r.targets.iter().any(|t| t.target == SDK_TARGET));
+
+    print!(
+        "  {}  {}  {}  ",
+        format!("{:<width$}", "SERVICE", width = width).bold(),
+        format!("{:<8}", "FAKE").bold(),
+        format!("{:<8}", "REAL").bold(),
+    );
+    if sdk {
+        print!("{}  ", format!("{:<8}", "SDK").bold());
+    }
+    if coverage {
+        print!("{}  ", format!("{:<8}", "COVERAGE").bold());
+    }
+    println!("{}", "TIME".bold());
+    for report in reports {
+        let time: f64 = report.targets.iter().map(|r| r.time).sum();
+        print!(
+            "  {:<width$}  {}  {}  ",
+            report.service,
+            cell(report, "fake"),
+            cell(report, "real"),
+            width = width
+        );
+        if sdk {
+            print!("{}  ", cell(report, SDK_TARGET));
+        }
+        if coverage {
+            let percent = match &report.coverage {
+                Some(coverage) => format!("{:.0}%", coverage.percent),
+                None => "-".to_string(),
+            };
+            print!("{:<8}  ", percent);
+        }
+        println!("{:.1}s", time);
+    }
+}
+
+/// Append each target's run to its service's history, first comparing it
+/// with the last one if asked to
+fn save_history(
+    config: &Config,
+    suites: &[Suite],
+    reports: &[ContractReport],
+    compare: bool,
+) -> anyhow::Result<()> {
+    let history = ContractHistory::new(config.history_dir());
+    let now = dates::now();
+    let mut compared = false;
+    for (suite, report) in suites.iter().zip(reports) {
+        for results in &report.targets {
+            let record = RunRecord::new(
+                &report.service,
+                suite.service.version.as_deref(),
+                results,
+                now,
+            );
+            if compare {
+                if !compared {
+                    println!();
+                    println!("{} Compared with the last run:", "ℹ".blue());
+                    compared = true;
+                }
+                let label = format!("{} ({})", report.service, results.label());
+                match history.last(&record)? {
+                    Some(last) => print_regressions(&label, &last, &record),
+                    None => println!("  {} no earlier run", label),
+                }
+            }
+            history
+                .append(&record)
+                .context("Failed to save the contract history")?;
+        }
+    }
+    Ok(())
+}
+
+/// What newly fails, got fixed or got slower since `last`
+fn print_regressions(label: &str, last: &RunRecord, record: &RunRecord) {
+    let regressions = Regressions::between(last, record);
+    let when = dates::format_time(last.timestamp);
+    if !regressions.any() && regressions.fixed.is_empty() {
+        println!("  {} {} no regressions since {}", "✓".green(), label, when);
+        return;
+    }
+    println!("  {} since {}:", label, when);
+    for test in &regressions.newly_failing {
+        println!("    {} newly failing: {}", "✗".red(), test);
+    }
+    for slowdown in &regressions.slower {
+        println!(
+            "    {} slower: {} ({:.2}s → {:.2}s)",
+            "⚠".yellow(),
+            slowdown.test,
+            slowdown.before,
+            slowdown.after
+        );
+    }
+    for test in &regressions.fixed {
+        println!("    {} fixed: {}", "✓".green(), test);
+    }
+}
+
+/// Show a service's last runs from the history, oldest first
+fn history(config: &Config, args: &ContractHistoryArgs) -> anyhow::Result<()> {
+    let runs = ContractHistory::new(config.history_dir()).runs(&args.service)?;
+    if runs.is_empty() {
+        println!(
+            "{} No contract runs recorded for {}",
+            "ℹ".blue(),
+            args.service
+        );
+        return Ok(());
+    }
+    let shown = &runs[runs.len().saturating_sub(args.limit)..];
+    let target_width = shown
+        .iter()
+        .map(|run| target_label(run).len())
+        .chain(["TARGET".len()])
+        .max()
+        .unwrap_or_default();
 
-    println!("{} Starting {} service...", "▶".blue(), args.service);
-    let pid = manager.start(&service, port).await?;
+    println!(
+        "  {}  {}  {}  {}  {}  {}",
+        format!("{:<20}", "WHEN").bold(),
+        format!("{:<10}", "VERSION").bold(),
+        format!("{:<width$}", "TARGET", width = target_width).bold(),
+        format!("{:<8}", "RESULT").bold(),
+        format!("{:<18}", "TESTS").bold(),
+        "TIME".bold()
+    );
+    for run in shown {
+        let result = if run.success {
+            format!("{:<8}", "passed").green()
+        } else {
+            format!("{:<8}", "failed").red()
+        };
+        let tests = if run.tests.is_empty() {
+            "-".to_string()
+        } else {
+            format!(
+                "{} passed, {} failed",
+                run.count(Outcome::Passed),
+                run.count(Outcome::Failed) + run.count(Outcome::Error)
+            )
+        };
+        println!(
+            "  {:<20}  {:<10}  {:<width$}  {}  {:<18}  {:.1}s",
+            dates::format_time(run.timestamp),
+            run.version.as_deref().unwrap_or("-"),
+            target_label(run),
+            result,
+            tests,
+            run.time,
+            width = target_width
+        );
+    }
+    if runs.len() > shown.len() {
+        println!(
+            "  {}",
+            format!("… {} earlier", runs.len() - shown.len()).dimmed()
+        );
+    }
+    Ok(())
+}
+
+fn target_label(run: &RunRecord) -> String {
+    match &run.profile {
+        Some(profile) => format!("{}, {}", run.target, profile),
+        None => run.target.clone(),
+    }
+}
+
+/// The result of each service's run against the fake with each profile
+fn print_profiles(reports: &[ContractReport]) {
+    println!();
+    println!("{} Results per snapshot profile:", "ℹ".blue());
+    for report in reports {
+        for results in report.targets.iter().filter(|r| r.profile.is_some()) {
+            let outcome = if results.success {
+                "passed".green()
+            } else {
+                "failed".red()
+            };
+            println!(
+                "  {}  {:<20}  {}  {:.1}s",
+                report.service,
+                results.profile.as_deref().unwrap_or_default(),
+                outcome,
+                results.time
+            );
+        }
+    }
+}
+
+/// The real API to test, from `contracts.real` in service.yaml
+pub(super) fn real_target(
+    service: &ServiceDefinition,
+    contracts: &ContractsConfig,
+) -> anyhow::Result<Target> {
+    let real = contracts.real.as_ref().ok_or_else(|| {
+        anyhow::anyhow!(
+            "No real API configured for '{}'.\n\
+             Add 'real: {{base_url: ..., auth_env: ...}}' to the 'contracts' section of service.yaml.",
+            service.name
+        )
+    })?;
+    let token = match &real.auth_env {
+        Some(var) => Some(
+            std::env::var(var)
+                .ok()
+                .filter(|token| !token.is_empty())
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Set {} to a token for the real {} API to run contracts against it",
+                        var,
+                        service.name
+                    )
+                })?,
+        ),
+        None => None,
+    };
+    Ok(Target {
+        name: "real",
+        url: real.base_url.trim_end_matches('/').to_string(),
+        port: None,
+        token,
+    })
+}
+
+/// A fake already running in this workspace
+fn running(manager: &ProcessManager, name: &str) -> anyhow::Result<ServiceInfo> {
+    match manager.get_info(name) {
+        Some(info) => Ok(info),
+        None => anyhow::bail!(
+            "{} isn't running; start it with 'doubleagent start {}' or drop --use-running",
+            name,
+            name
+        ),
+    }
+}
+
+/// Whether any of the runs against the fake, rather than the real API, failed
+fn failed_fake(results: &[TargetResults]) -> bool {
+    results
+        .iter()
+        .any(|r| (r.target == "fake" || r.target == SDK_TARGET) && !r.success)
+}
+
+/// Collect a failed fake's log, state and request log, before it is stopped
+///
+/// Artifacts that can't be collected are warned about rather than failing the run.
+async fn collect_artifacts(
+    manager: &ProcessManager,
+    suite: &Suite,
+    port: u16,
+    options: &RunOptions<'_>,
+) {
+    let name = &suite.service.name;
+    let log = manager.log_file(name);
+    match artifacts::collect(&options.artifacts, name, port, &log).await {
+        Ok(collected) => {
+            println!(
+                "{} Collected {}'s failure artifacts in {}",
+                "■".red(),
+                name,
+                collected.dir.display().to_string().cyan()
+            );
+            for missing in &collected.missing {
+                println!("  {} Couldn't collect {}", "⚠".yellow(), missing);
+            }
+        }
+        Err(e) => println!(
+            "{} Couldn't collect {}'s failure artifacts: {}",
+            "⚠".yellow(),
+            name,
+            e
+        ),
+    }
+}
+
+/// Start a fake for the tests, its failure to start told apart from other errors
+async fn start(
+    manager: &mut ProcessManager,
+    service: &ServiceDefinition,
+    port: u16,
+) -> anyhow::Result<u32> {
+    manager
+        .start(service, port)
+        .await
+        .map_err(|e| FakeFailed(format!("Failed to start {}: {}", service.name, e)).into())
+}
+
+/// Start the fake (or find it running), run the tests against it and stop it again
+async fn run_against_fake(
+    config: &Config,
+    suite: &Suite,
+    options: &RunOptions<'_>,
+) -> anyhow::Result<(Vec<TargetResults>, Option<Coverage>)> {
+    let name = &suite.service.name;
+    let mut manager = ProcessManager::load(&config.state_file)?;
+    let (port, pid) = if options.use_running {
+        let info = running(&manager, name)?;
+        println!("{} Using the running {} service", "▶".blue(), name);
+        (info.port, info.pid)
+    } else {
+        let port = PortAllocator::new(
+            config.port_range,
+            config.contract_port,
+            workspace::ports_in_use(&config.state_dir)?,
+        )
+        .allocate_free()?;
+        println!("{} Starting {} service...", "▶".blue(), name);
+        (port, start(&mut manager, &suite.service, port).await?)
+    };
 
     print!("  Waiting for health check...");
-    if let Err(e) = manager.wait_for_health(&args.service, port, 30).await {
+    if let Err(e) = manager
+        .wait_for_health(name, port, &config.health_for(name))
+        .await
+    {
         println!(" {}", "✗".red());
-        manager.stop(&args.service).await?;
-        manager.save(&config.state_file)?;
-        return Err(anyhow::anyhow!("Health check failed: {}", e));
+        collect_artifacts(&manager, suite, port, options).await;
+        if !options.use_running {
+            manager.stop(name).await?;
+            manager.save(&config.state_file)?;
+        }
+        return Err(FakeFailed(format!("Health check failed: {}", e)).into());
     }
     println!(" {}", "✓".green());
 
-    let env_var_name = format!("DOUBLEAGENT_{}_URL", args.service.to_uppercase());
-    let service_url = format!("http://localhost:{}", port);
+    let target = Target::fake(port);
     println!(
         "{} {} running on {} (PID: {})",
         "✓".green(),
-        args.service.bold(),
-        service_url.cyan(),
+        name.bold(),
+        target.url.cyan(),
         pid
     );
     println!();
 
+    let store = SnapshotStore::new(&config.data_dir);
+    let mut results = run_profiles(config, &store, suite, &target, port, options, None).await;
+    if runs_sdk(suite, options) && !options.stopped() {
+        results = results.and_then(|mut results| {
+            println!();
+            results.push(run_sdk(suite, &target, options, None)?);
+            Ok(results)
+        });
+    }
+    let coverage = match &results {
+        Ok(_) if options.coverage => endpoint_coverage(suite, port).await,
+        _ => None,
+    };
+    if let Some(coverage) = &coverage {
+        print_coverage(coverage);
+    }
+    // Responses are checked once, with the data of the last run
+    let last = results
+        .as_mut()
+        .ok()
+        .and_then(|results| results.iter_mut().rfind(|r| r.target == target.name));
+    if let Some(results) = last {
+        if let Some(spec) = &suite.spec {
+            validate_spec(spec, &target, results, &mut std::io::stdout()).await?;
+        }
+        if let Some(scenarios) = &suite.golden {
+            check_golden(scenarios, &target, results, options, &mut std::io::stdout()).await?;
+        }
+    }
+    if results
+        .as_ref()
+        .map_or(true, |results| failed_fake(results))
+    {
+        println!();
+        collect_artifacts(&manager, suite, port, options).await;
+    }
+
+    // Always stop a service started for the tests, regardless of outcome
+    if !options.use_running {
+        println!();
+        println!("{} Stopping {} service...", "▶".blue(), name);
+        manager.stop(name).await?;
+        manager.save(&config.state_file)?;
+        println!("{} Service stopped", "✓".green());
+    }
+
+    Ok((results?, coverage))
+}
+
+/// Run the tests against the fake once per snapshot profile, seeding it
+/// before each run, or once as it is without profiles
+///
+/// Progress goes to `log` if given, like the tests' output, else to the terminal.
+async fn run_profiles(
+    config: &Config,
+    store: &SnapshotStore,
+    suite: &Suite,
+    fake: &Target,
+    port: u16,
+    options: &RunOptions<'_>,
+    log: Option<&Path>,
+) -> anyhow::Result<Vec<TargetResults>> {
+    if options.snapshots.is_empty() {
+        return Ok(vec![run_tests(suite, fake, options, log, None)?]);
+    }
+    let mut out = output(log)?;
+
+    let mut runs = Vec::new();
+    for (i, profile) in options.snapshots.iter().enumerate() {
+        if options.stopped() {
+            break;
+        }
+        if i > 0 {
+            writeln!(out)?;
+        }
+        writeln!(
+            out,
+            "{} Running with the {} snapshot profile",
+            "▶".blue(),
+            profile.bold()
+        )?;
+        writeln!(out)?;
+        out.flush()?;
+        seed_profile(config, store, &suite.service, port, profile)
+            .await
+            .with_context(|| format!("Failed to seed {} with {}", suite.service.name, profile))?;
+        runs.push(run_tests(suite, fake, options, log, Some(profile))?);
+    }
+    Ok(runs)
+}
+
+/// Reset the fake and seed it with a snapshot profile, or leave it empty
+/// for [`contract::EMPTY_PROFILE`]
+async fn seed_profile(
+    config: &Config,
+    store: &SnapshotStore,
+    service: &ServiceDefinition,
+    port: u16,
+    profile: &str,
+) -> anyhow::Result<()> {
+    let client = ControlClient::new(port);
+    client.reset().await?;
+    if profile == contract::EMPTY_PROFILE {
+        return Ok(());
+    }
+    let data = store.load_seed(service, profile)?;
+    client.seed(&data).await?;
+    let source = SeedSource::Snapshot(profile.to_string());
+    super::audit::record_seed(config, store, &service.name, &source, port)
+}
+
+/// Which of the fake's endpoints the tests requested, from its request log
+///
+/// Fakes that can't say are warned about rather than failing the run.
+async fn endpoint_coverage(suite: &Suite, port: u16) -> Option<Coverage> {
+    let name = &suite.service.name;
+    let measured = async {
+        let log = ControlClient::new(port).requests().await.with_context(|| {
+            format!(
+                "{} doesn't report the requests it served (GET /_doubleagent/{})",
+                name,
+                coverage::REQUESTS_ENDPOINT
+            )
+        })?;
+        let declared = if !log.routes.is_empty() {
+            log.routes
+        } else if openapi::find(&suite.service).is_some() {
+            Spec::load(&suite.service)?.endpoints()
+        } else {
+            anyhow::bail!(
+                "{} lists no routes and has no OpenAPI description to measure against",
+                name
+            );
+        };
+        Ok(Coverage::new(&declared, &log.requests))
+    };
+    match measured.await {
+        Ok(coverage) => Some(coverage),
+        Err(e) => {
+            println!("{} Can't measure endpoint coverage: {:#}", "⚠".yellow(), e);
+            None
+        }
+    }
+}
+
+/// The share of endpoints requested, and those that weren't
+fn print_coverage(coverage: &Coverage) {
+    println!();
+    println!(
+        "{} Endpoint coverage: {} of {} ({:.1}%)",
+        "ℹ".blue(),
+        coverage.hit,
+        coverage.declared,
+        coverage.percent
+    );
+    for missed in coverage.endpoints.iter().filter(|e| e.hits == 0) {
+        println!(
+            "  {} {} {}",
+            "✗".red(),
+            missed.endpoint.method,
+            missed.endpoint.path
+        );
+    }
+    for request in &coverage.undeclared {
+        println!("  {} {} (not a declared route)", "⚠".yellow(), request);
+    }
+}
+
+/// Check the fake's responses against the service's OpenAPI description,
+/// adding each request to `results` as a test
+async fn validate_spec(
+    spec: &Spec,
+    fake: &Target,
+    results: &mut TargetResults,
+    out: &mut dyn Write,
+) -> std::io::Result<()> {
+    writeln!(out)?;
+    writeln!(
+        out,
+        "{} Checking responses against the OpenAPI description",
+        "▶".blue()
+    )?;
+    match conformance::run(&fake.url, spec, &spec.requests()).await {
+        Ok(checks) => {
+            let failed = super::validate_spec::print_checks(&checks, out)?;
+            results.success &= failed == 0;
+            results.tests.extend(checks.iter().map(Check::to_test_case));
+        }
+        Err(e) => {
+            writeln!(out, "{} Could not check responses: {}", "✗".red(), e)?;
+            results.success = false;
+        }
+    }
+    Ok(())
+}
+
+/// Compare the fake's responses to the scenarios with their golden files,
+/// adding each scenario to `results` as a test, or rewrite the files from
+/// them with --update-golden
+async fn check_golden(
+    scenarios: &Scenarios,
+    fake: &Target,
+    results: &mut TargetResults,
+    options: &RunOptions<'_>,
+    out: &mut dyn Write,
+) -> std::io::Result<()> {
+    writeln!(out)?;
+    if options.update_golden {
+        writeln!(
+            out,
+            "{} Updating the golden files in {} from the fake's responses",
+            "▶".blue(),
+            scenarios.directory.display()
+        )?;
+        match scenarios.record(&fake.url, None).await {
+            Ok(recorded) => print_recorded(&recorded, &scenarios.directory, out)?,
+            Err(e) => {
+                writeln!(
+                    out,
+                    "{} Could not update the golden files: {}",
+                    "✗".red(),
+                    e
+                )?;
+                results.success = false;
+            }
+        }
+        return Ok(());
+    }
+    writeln!(
+        out,
+        "{} Comparing responses with the golden files in {}",
+        "▶".blue(),
+        scenarios.directory.display()
+    )?;
+    match scenarios.compare(&fake.url).await {
+        Ok(comparisons) => {
+            let failed = print_comparisons(&comparisons, out)?;
+            results.success &= failed == 0;
+            results
+                .tests
+                .extend(comparisons.iter().map(Comparison::to_test_case));
+        }
+        Err(e) => {
+            writeln!(out, "{} Could not compare responses: {}", "✗".red(), e)?;
+            results.success = false;
+        }
+    }
+    Ok(())
+}
+
+/// Print each comparison with its differences, returning how many failed
+fn print_comparisons(comparisons: &[Comparison], out: &mut dyn Write) -> std::io::Result<usize> {
+    let mut failed = 0;
+    for comparison in comparisons {
+        let mark = if comparison.differences.is_empty() {
+            "✓".green()
+        } else {
+            failed += 1;
+            "✗".red()
+        };
+        writeln!(
+            out,
+            "  {} {} ({})",
+            mark, comparison.request, comparison.status
+        )?;
+        // The diff shows what's different more readably, where there is one
+        if comparison.diff.is_empty() {
+            for difference in &comparison.differences {
+                writeln!(out, "      {}", difference)?;
+            }
+        } else {
+            print_diff(&comparison.diff, out)?;
+        }
+    }
+    Ok(failed)
+}
+
+/// Whether the official SDK's smoke script runs along with the contracts:
+/// when the service has one and the tests run against the fake unfiltered
+fn runs_sdk(suite: &Suite, options: &RunOptions<'_>) -> bool {
+    suite.fake && suite.contracts.sdk.is_some() && options.filter.is_none() && suite.tags.is_none()
+}
+
+/// Install the official SDK with `contracts.sdk.install`, if there is a command
+fn install_sdk(suite: &Suite) -> anyhow::Result<()> {
+    let Some(sdk) = &suite.contracts.sdk else {
+        return Ok(());
+    };
+    if sdk.install.is_empty() {
+        return Ok(());
+    }
+    let output = mise::build_command(&suite.service.path, &sdk.install)?
+        .current_dir(&suite.contracts_dir)
+        .output()
+        .with_context(|| {
+            format!(
+                "Failed to install the official SDK for '{}' with: {}",
+                suite.service.name,
+                sdk.install.join(" ")
+            )
+        })?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Installing the official SDK for '{}' failed ({}):\n{}",
+            suite.service.name,
+            sdk.install.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+    Ok(())
+}
+
+/// Run the official SDK's smoke script against the fake, as a target of its own
+/// with the script as its one test
+///
+/// The script's output goes to `log` if given, else to the terminal.
+fn run_sdk(
+    suite: &Suite,
+    fake: &Target,
+    options: &RunOptions<'_>,
+    log: Option<&Path>,
+) -> anyhow::Result<TargetResults> {
+    let Some(sdk) = &suite.contracts.sdk else {
+        anyhow::bail!("{} has no official SDK smoke script", suite.service.name);
+    };
+    let mut cmd = mise::build_command(&suite.service.path, &sdk.command)?;
+    cmd.current_dir(&suite.contracts_dir);
+    let mut out: Box<dyn Write> = match log {
+        Some(log) => {
+            let file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(log)
+                .with_context(|| format!("Failed to open {}", log.display()))?;
+            cmd.stdout(file.try_clone()?)
+                .stderr(file.try_clone()?)
+                .stdin(Stdio::null());
+            Box::new(file)
+        }
+        None => Box::new(std::io::stdout()),
+    };
+    writeln!(
+        out,
+        "{} Running the official SDK smoke script: {}",
+        "▶".blue(),
+        sdk.command.join(" ")
+    )?;
+    writeln!(out)?;
+    out.flush()?;
+    set_target_env(&mut cmd, suite, fake, None)?;
+
+    let started = Instant::now();
+    let status = cmd.status().with_context(|| {
+        format!(
+            "Failed to run the official SDK smoke script for '{}'.\n\
+             Command: {}\n\
+             Directory: {}",
+            suite.service.name,
+            sdk.command.join(" "),
+            suite.contracts_dir.display()
+        )
+    })?;
+    let time = started.elapsed().as_secs_f64();
+    let (outcome, message) = if status.success() {
+        (Outcome::Passed, None)
+    } else {
+        (
+            Outcome::Failed,
+            Some(format!("smoke script exited with {}", status)),
+        )
+    };
+
+    let results = TargetResults {
+        target: SDK_TARGET.to_string(),
+        profile: None,
+        success: status.success(),
+        time,
+        tests: vec![TestCase {
+            name: sdk.command.join(" "),
+            classname: SDK_TARGET.to_string(),
+            time,
+            outcome,
+            message,
+            details: None,
+            retries: 0,
+        }],
+    };
+    options.finished(&results);
+    Ok(results)
+}
+
+/// Point a test command at a target: its URL, which target it is, the real
+/// API's token, the profile the fake was seeded with, and `contracts.env`
+fn set_target_env(
+    cmd: &mut Command,
+    suite: &Suite,
+    target: &Target,
+    profile: Option<&str>,
+) -> anyhow::Result<()> {
+    let service = &suite.service;
+    let prefix = format!(
+        "DOUBLEAGENT_{}",
+        service.name.to_uppercase().replace('-', "_")
+    );
+    cmd.env(format!("{}_URL", prefix), &target.url);
+    cmd.env(TARGET_ENV, target.name);
+    if let Some(token) = &target.token {
+        cmd.env(format!("{}_TOKEN", prefix), token);
+    }
+    if let Some(profile) = profile {
+        cmd.env(contract::PROFILE_ENV, profile);
+    }
+
+    if suite.contracts.env.is_empty() {
+        return Ok(());
+    }
+    let mut vars = BTreeMap::from([
+        ("service".to_string(), service.name.clone()),
+        ("target".to_string(), target.name.to_string()),
+        ("url".to_string(), target.url.clone()),
+    ]);
+    if let Some(port) = target.port {
+        vars.insert("port".to_string(), port.to_string());
+    }
+    if let Some(profile) = profile {
+        vars.insert("profile".to_string(), profile.to_string());
+    }
+    if let Some(namespace) = namespace::from_env() {
+        vars.insert("namespace".to_string(), namespace);
+    }
+    let env = contract::render_env(&suite.contracts.env, &vars)
+        .with_context(|| format!("Failed to render contracts.env for '{}'", service.name))?;
+    cmd.envs(env);
+    Ok(())
+}
+
+/// Run the contract command with the target's URL (and token) in its environment,
+/// collecting the runner's results if a report was asked for
+///
+/// Failed tests are retried as `contracts.retries` allows. The command's output
+/// goes to `log` if given, else to the terminal.
+fn run_tests(
+    suite: &Suite,
+    target: &Target,
+    options: &RunOptions<'_>,
+    log: Option<&Path>,
+    profile: Option<&str>,
+) -> anyhow::Result<TargetResults> {
+    let service = &suite.service;
+    let started = Instant::now();
+    let (status, tests) = run_command(suite, target, options.filter, options, log, profile)?;
+    let mut tests = tests.unwrap_or_else(|| {
+        if options.report {
+            println!(
+                "{} {}'s tests wrote no results to ${}, so there are none per test for the {}",
+                "⚠".yellow(),
+                service.name,
+                contract::RESULTS_ENV,
+                target.name
+            );
+        }
+        Vec::new()
+    });
+
+    let mut success = status.success();
+    if let Some(timeout) = suite.contracts.timeout {
+        if contract::enforce_timeout(&mut tests, timeout) > 0 {
+            success = false;
+        }
+    }
+    // A failed run is put down to its failed tests, so it passes if they all do
+    if tests.iter().any(TestCase::failed) && suite.contracts.retries > 0 {
+        retry_failed(suite, target, options, log, profile, &mut tests)?;
+        success = !tests.iter().any(TestCase::failed);
+    }
+    let time = started.elapsed().as_secs_f64();
+
+    let results = TargetResults {
+        target: target.name.to_string(),
+        profile: profile.map(str::to_string),
+        success,
+        time,
+        tests,
+    };
+    options.finished(&results);
+    Ok(results)
+}
+
+/// Re-run the failed tests up to `contracts.retries` times, marking those
+/// that pass on a retry as flaky
+fn retry_failed(
+    suite: &Suite,
+    target: &Target,
+    options: &RunOptions<'_>,
+    log: Option<&Path>,
+    profile: Option<&str>,
+    tests: &mut [TestCase],
+) -> anyhow::Result<()> {
+    let retries = suite.contracts.retries;
+    for attempt in 1..=retries {
+        let failed = tests.iter().filter(|t| t.failed()).count();
+        if failed == 0 {
+            break;
+        }
+        let mut out = output(log)?;
+        writeln!(out)?;
+        writeln!(
+            out,
+            "{} Retrying {} failed {} ({} of {})",
+            "↻".blue(),
+            failed,
+            if failed == 1 { "test" } else { "tests" },
+            attempt,
+            retries
+        )?;
+        writeln!(out)?;
+        out.flush()?;
+
+        let filter = contract::retry_filter(tests, options.filter);
+        let (_, retried) = run_command(suite, target, Some(&filter), options, log, profile)?;
+        let Some(mut retried) = retried else {
+            break;
+        };
+        if let Some(timeout) = suite.contracts.timeout {
+            contract::enforce_timeout(&mut retried, timeout);
+        }
+        contract::merge_retry(tests, &retried, attempt);
+    }
+
+    let flaky: Vec<&TestCase> = tests.iter().filter(|t| t.flaky()).collect();
+    if !flaky.is_empty() {
+        let mut out = output(log)?;
+        writeln!(out)?;
+        for test in flaky {
+            writeln!(
+                out,
+                "{} Flaky: {}::{} passed on retry {}",
+                "⚠".yellow(),
+                test.classname,
+                test.name,
+                test.retries
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Where progress goes: appended to `log` if given, else the terminal
+fn output(log: Option<&Path>) -> anyhow::Result<Box<dyn Write>> {
+    Ok(match log {
+        Some(log) => Box::new(
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(log)
+                .with_context(|| format!("Failed to open {}", log.display()))?,
+        ),
+        None => Box::new(std::io::stdout()),
+    })
+}
+
+/// Run the contract command once, with only the tests matching `filter`,
+/// returning its exit status and the runner's results if it wrote any
+fn run_command(
+    suite: &Suite,
+    target: &Target,
+    filter: Option<&str>,
+    options: &RunOptions<'_>,
+    log: Option<&Path>,
+    profile: Option<&str>,
+) -> anyhow::Result<(ExitStatus, Option<Vec<TestCase>>)> {
+    let service = &suite.service;
+
     // Build command, wrapping with mise if .mise.toml exists
-    let mut cmd = mise::build_command(&service.path, &contracts_config.command)?;
-    cmd.current_dir(&contracts_dir);
+    let command: Vec<String> = suite
+        .contracts
+        .command
+        .iter()
+        .chain(options.args)
+        .cloned()
+        .collect();
+    let mut cmd = mise::build_command(&service.path, &command)?;
+    cmd.current_dir(&suite.contracts_dir);
+    if let Some(log) = log {
+        // Appended to, as a target can run once per snapshot profile
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(log)
+            .with_context(|| format!("Failed to create {}", log.display()))?;
+        cmd.stdout(file.try_clone()?)
+            .stderr(file)
+            .stdin(Stdio::null());
+    }
 
     // Pass service URL as environment variable
-    cmd.env(&env_var_name, &service_url);
+    set_target_env(&mut cmd, suite, target, profile)?;
+    if let Some(timeout) = suite.contracts.timeout {
+        cmd.env(contract::TIMEOUT_ENV, timeout.to_string());
+    }
 
-    let command_str = contracts_config.command.join(" ");
+    // The runner writes JUnit XML here; pytest is told to through PYTEST_ADDOPTS
+    let results_file = std::env::temp_dir().join(format!(
+        "doubleagent-contract-{}-{}-{}.xml",
+        std::process::id(),
+        service.name,
+        target.name
+    ));
+    let _ = fs::remove_file(&results_file);
+    cmd.env(contract::RESULTS_ENV, &results_file);
+    if let Some(filter) = filter {
+        cmd.env(contract::FILTER_ENV, filter);
+    }
+    if let Some(tags) = &suite.tags {
+        cmd.env(contract::TAGS_ENV, tags);
+    }
+    if options.fail_fast {
+        cmd.env(contract::FAIL_FAST_ENV, "1");
+    }
+    let addopts = std::env::var("PYTEST_ADDOPTS").unwrap_or_default();
+    cmd.env(
+        "PYTEST_ADDOPTS",
+        contract::pytest_addopts(
+            &addopts,
+            Some(&results_file),
+            filter,
+            suite.tags.as_deref(),
+            options.fail_fast,
+        ),
+    );
+
+    let command_str = command.join(" ");
     tracing::debug!(
-        "Running command '{}' in directory '{}' with {}={}",
+        "Running command '{}' in directory '{}' against {} (the {})",
         command_str,
-        contracts_dir.display(),
-        env_var_name,
-        service_url
+        suite.contracts_dir.display(),
+        target.url,
+        target.name
     );
 
     let status = cmd.status().with_context(|| {
@@ -101,31 +1919,111 @@ pub async fn run(args: ContractArgs) -> anyhow::Result<()> {
              Command: {}\n\
              Directory: {}\n\
              Service path: {}",
-            args.service,
+            service.name,
             command_str,
-            contracts_dir.display(),
+            suite.contracts_dir.display(),
             service.path.display()
         )
-    });
+    })?;
 
-    // Always stop the service after tests, regardless of outcome
+    let tests = match fs::read_to_string(&results_file) {
+        Ok(xml) => {
+            let _ = fs::remove_file(&results_file);
+            Some(contract::parse_junit(&xml)?)
+        }
+        Err(_) => None,
+    };
+    Ok((status, tests))
+}
+
+/// Annotate the failed tests for GitHub Actions, then add the job summary to
+/// `file` or `$GITHUB_STEP_SUMMARY`
+fn write_github(
+    reports: &[ContractReport],
+    suites: &[Suite],
+    file: Option<&str>,
+) -> anyhow::Result<()> {
+    // Annotations name files relative to the repository
+    let root = match std::env::var_os("GITHUB_WORKSPACE") {
+        Some(root) => PathBuf::from(root),
+        None => std::env::current_dir()?,
+    };
+    let root = fs::canonicalize(&root).unwrap_or(root);
+    let dirs = suites
+        .iter()
+        .filter_map(|suite| {
+            let dir = fs::canonicalize(&suite.contracts_dir).ok()?;
+            let dir = dir.strip_prefix(&root).ok()?;
+            Some((suite.service.name.clone(), dir.display().to_string()))
+        })
+        .collect();
     println!();
-    println!("{} Stopping {} service...", "▶".blue(), args.service);
-    manager.stop(&args.service).await?;
-    manager.save(&config.state_file)?;
-    println!("{} Service stopped", "✓".green());
+    print!("{}", report::github_annotations(reports, &dirs));
 
-    // Now handle the test result
-    let status = status?;
+    let path = file
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("GITHUB_STEP_SUMMARY").map(PathBuf::from));
+    let Some(path) = path else {
+        println!(
+            "{} No job summary written: $GITHUB_STEP_SUMMARY isn't set",
+            "ℹ".blue()
+        );
+        return Ok(());
+    };
+    // The summary file is shared by the job's steps
+    let mut summary = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    summary.write_all(report::github_summary(reports).as_bytes())?;
+    println!("{} Added the job summary to {}", "ℹ".blue(), path.display());
+    Ok(())
+}
 
-    if status.success() {
-        println!();
-        println!("{} All contract tests passed!", "✓".green());
-    } else {
-        println!();
-        println!("{} Contract tests failed", "✗".red());
-        std::process::exit(1);
+/// Write the report of a run in `format`
+fn write_report(
+    reports: &[ContractReport],
+    suites: &[Suite],
+    format: ContractReportFormat,
+    file: Option<&str>,
+) -> anyhow::Result<()> {
+    let (text, default_file) = match format {
+        ContractReportFormat::Github => return write_github(reports, suites, file),
+        ContractReportFormat::Junit => (contract::to_junit(reports), "contract-results.xml"),
+        ContractReportFormat::Json => (
+            serde_json::to_string_pretty(&serde_json::json!({ "services": reports }))? + "\n",
+            "contract-results.json",
+        ),
+        ContractReportFormat::Markdown => {
+            (report::to_markdown(reports), "doubleagent-report/report.md")
+        }
+        ContractReportFormat::Html => (report::to_html(reports), "doubleagent-report/index.html"),
+    };
+    let path = file.unwrap_or(default_file);
+    if let Some(dir) = Path::new(path)
+        .parent()
+        .filter(|d| !d.as_os_str().is_empty())
+    {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
     }
+    fs::write(path, text).with_context(|| format!("Failed to write {}", path))?;
 
+    let count = |outcome| -> usize {
+        reports
+            .iter()
+            .flat_map(|r| &r.targets)
+            .map(|t| t.count(outcome))
+            .sum()
+    };
+    println!();
+    println!(
+        "{} Wrote {} passed, {} failed, {} skipped to {}",
+        "ℹ".blue(),
+        count(Outcome::Passed),
+        count(Outcome::Failed) + count(Outcome::Error),
+        count(Outcome::Skipped),
+        path
+    );
     Ok(())
 }
@@ -0,0 +1,436 @@
+use super::DashboardArgs;
+use doubleagent_core::{
+    dates, hooks, tags, Config, ControlClient, ProcessManager, ServiceDefinition, StartOptions,
+};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph, Row as TableRow, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How often the dashboard refreshes when no key is pressed.
+const REFRESH: Duration = Duration::from_secs(1);
+
+/// How much of the end of a service's log the log pane reads.
+const LOG_TAIL: u64 = 64 * 1024;
+
+/// Lines the log pane scrolls per PgUp/PgDn.
+const SCROLL_PAGE: usize = 10;
+
+pub async fn run(config: Config, args: DashboardArgs) -> anyhow::Result<()> {
+    if !std::io::stdout().is_terminal() {
+        anyhow::bail!("the dashboard needs a terminal; use 'doubleagent status --watch' instead");
+    }
+
+    // Keys are read on their own thread, which ends on the keys that quit so
+    // nothing is left reading the terminal once it is restored
+    let (sender, mut keys) = tokio::sync::mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            let Event::Key(key) = event else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let quit = is_quit(&key);
+            if sender.send(key).is_err() || quit {
+                break;
+            }
+        }
+    });
+
+    // Raw mode on the alternate screen, restored on the way out (and on panic).
+    // ratatui styles what it draws, so the messages shared with the other
+    // commands are made without escape codes
+    colored::control::set_override(false);
+    let mut terminal = ratatui::init();
+    let result = dashboard(&config, &args, &mut terminal, &mut keys).await;
+    ratatui::restore();
+    colored::control::unset_override();
+    result
+}
+
+async fn dashboard(
+    config: &Config,
+    args: &DashboardArgs,
+    terminal: &mut DefaultTerminal,
+    keys: &mut tokio::sync::mpsc::UnboundedReceiver<KeyEvent>,
+) -> anyhow::Result<()> {
+    let mut dashboard = Dashboard::default();
+    loop {
+        dashboard.refresh(config, args).await;
+        terminal.draw(|frame| dashboard.draw(frame))?;
+        tokio::select! {
+            key = keys.recv() => match key {
+                Some(key) if !is_quit(&key) => dashboard.handle(key, config, terminal).await?,
+                _ => return Ok(()),
+            },
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+            _ = tokio::time::sleep(REFRESH) => {}
+        }
+    }
+}
+
+fn is_quit(key: &KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Char('q') | KeyCode::Esc => true,
+        KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+        _ => false,
+    }
+}
+
+/// A running service as last refreshed
+struct Row {
+    name: String,
+    port: u16,
+    pid: u32,
+    healthy: bool,
+    /// Whether its health keeps changing (see `doubleagent_core::health`)
+    flapping: bool,
+    /// Requests per second since the refresh before, for fakes that log them
+    rate: Option<f64>,
+}
+
+#[derive(Default)]
+struct Dashboard {
+    rows: Vec<Row>,
+    /// Name of the selected service, kept across refreshes
+    selected: Option<String>,
+    /// Log lines scrolled back from the end
+    scroll: usize,
+    /// Requests each service had logged, and when that was counted
+    counts: BTreeMap<String, (usize, Instant)>,
+    rates: BTreeMap<String, f64>,
+    log_file: Option<std::path::PathBuf>,
+    /// Outcome of the last action, shown at the bottom
+    message: Line<'static>,
+}
+
+/// A message line starting with a coloured symbol, as the other commands print
+fn message(symbol: &'static str, color: Color, text: impl Into<String>) -> Line<'static> {
+    Line::from(vec![
+        Span::styled(symbol, Style::new().fg(color)),
+        Span::raw(" "),
+        Span::raw(text.into()),
+    ])
+}
+
+/// A crash report, made without colours (see [`run`]) and shown red.
+fn report(line: &str) -> Line<'static> {
+    Line::from(line.to_string()).red()
+}
+
+impl Dashboard {
+    async fn refresh(&mut self, config: &Config, args: &DashboardArgs) {
+        match super::status::report_crashes(config).await {
+            Ok(crashes) => {
+                if let Some(crash) = crashes.last() {
+                    self.message = report(crash);
+                }
+            }
+            Err(e) => self.message = message("✗", Color::Red, e.to_string()),
+        }
+        let manager = match ProcessManager::load(&config.state_file) {
+            Ok(manager) => manager,
+            Err(e) => {
+                self.rows.clear();
+                self.message = message("✗", Color::Red, e.to_string());
+                return;
+            }
+        };
+
+        let mut rows = Vec::new();
+        for name in manager.running_services() {
+            let Some(info) = manager.get_info(&name) else {
+                continue;
+            };
+            if !tags::matches(&info.tags, &args.tags) {
+                continue;
+            }
+            let healthy = manager.check_health(&name, &config.health_for(&name)).await;
+            let rate = if healthy {
+                self.rate(&name, info.port).await
+            } else {
+                None
+            };
+            rows.push(Row {
+                name,
+                port: info.port,
+                pid: info.pid,
+                healthy,
+                flapping: info.is_flapping(dates::now()),
+                rate,
+            });
+        }
+        let results: Vec<(String, bool)> = rows
+            .iter()
+            .map(|row| (row.name.clone(), row.healthy))
+            .collect();
+        match super::status::record_health(config, &results).await {
+            Ok(true) => {
+                if let Ok(manager) = ProcessManager::load(&config.state_file) {
+                    for row in &mut rows {
+                        row.flapping = manager
+                            .get_info(&row.name)
+                            .is_some_and(|info| info.is_flapping(dates::now()));
+                    }
+                }
+            }
+            Ok(false) => {}
+            Err(e) => self.message = message("✗", Color::Red, e.to_string()),
+        }
+        self.rows = rows;
+
+        if !self
+            .rows
+            .iter()
+            .any(|row| Some(&row.name) == self.selected.as_ref())
+        {
+            self.selected = self.rows.first().map(|row| row.name.clone());
+            self.scroll = 0;
+        }
+        self.log_file = self.selected.as_ref().map(|name| manager.log_file(name));
+    }
+
+    /// Requests per second a service served since it was last counted.
+    ///
+    /// Counts less than a refresh apart (after a key press) keep the rate from
+    /// before rather than measure a short, noisy span.
+    async fn rate(&mut self, name: &str, port: u16) -> Option<f64> {
+        let count = ControlClient::new(port)
+            .requests()
+            .await
+            .ok()?
+            .requests
+            .len();
+        let now = Instant::now();
+        match self.counts.get(name) {
+            Some((_, at)) if now.duration_since(*at) < REFRESH / 2 => {}
+            Some((before, at)) => {
+                // A reset empties the log; count from zero again
+                let served = count.checked_sub(*before).unwrap_or(count);
+                let rate = served as f64 / now.duration_since(*at).as_secs_f64();
+                self.rates.insert(name.to_string(), rate);
+                self.counts.insert(name.to_string(), (count, now));
+            }
+            None => {
+                self.counts.insert(name.to_string(), (count, now));
+            }
+        }
+        Some(self.rates.get(name).copied().unwrap_or(0.0))
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [header, table, log, footer] = Layout::vertical([
+            Constraint::Length(2),
+            Constraint::Length(self.rows.len().max(1) as u16 + 1),
+            Constraint::Fill(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(
+            Line::from(vec![
+                "doubleagent dashboard".bold(),
+                "  ↑/↓ select  r restart  x reset  s stop  PgUp/PgDn scroll  q quit".dim(),
+            ]),
+            header,
+        );
+
+        let rows = self.rows.iter().map(|row| {
+            let health = if row.flapping {
+                "flapping".yellow()
+            } else if row.healthy {
+                "healthy".green()
+            } else {
+                "unhealthy".red()
+            };
+            let rate = match row.rate {
+                Some(rate) => format!("{:>7.1}", rate),
+                None => format!("{:>7}", "-"),
+            };
+            TableRow::new(vec![
+                Line::from(row.name.clone()),
+                Line::from(row.port.to_string()).right_aligned(),
+                Line::from(row.pid.to_string()).right_aligned(),
+                Line::from(health),
+                Line::from(rate).right_aligned(),
+            ])
+        });
+        let widths = [
+            Constraint::Length(20),
+            Constraint::Length(6),
+            Constraint::Length(8),
+            Constraint::Length(10),
+            Constraint::Length(7),
+        ];
+        let mut state = TableState::default().with_selected(
+            self.rows
+                .iter()
+                .position(|row| Some(&row.name) == self.selected.as_ref()),
+        );
+        if self.rows.is_empty() {
+            frame.render_widget(Paragraph::new("\n  No services running".dim()), table);
+        } else {
+            let widget = Table::new(rows, widths)
+                .header(TableRow::new(["SERVICE", "PORT", "PID", "HEALTH", "REQ/S"]).dim())
+                .row_highlight_style(Style::new().add_modifier(Modifier::BOLD))
+                .highlight_symbol("› ");
+            frame.render_stateful_widget(widget, table, &mut state);
+        }
+
+        // The log pane takes what's left, above the message line
+        if let (Some(name), Some(path)) = (&self.selected, &self.log_file) {
+            let block = Block::new()
+                .borders(Borders::TOP)
+                .title(Line::from(vec!["Log: ".bold(), name.clone().into()]));
+            let rows = block.inner(log).height as usize;
+            let lines = tail(path);
+            let end = lines
+                .len()
+                .saturating_sub(self.scroll.min(lines.len().saturating_sub(rows)));
+            let start = end.saturating_sub(rows);
+            let text: Vec<Line> = if lines.is_empty() {
+                vec!["  (no output yet)".dim().into()]
+            } else {
+                lines[start..end]
+                    .iter()
+                    .map(|line| Line::from(line.as_str()))
+                    .collect()
+            };
+            frame.render_widget(Paragraph::new(text).block(block), log);
+        }
+
+        frame.render_widget(self.message.clone(), footer);
+    }
+
+    async fn handle(
+        &mut self,
+        key: KeyEvent,
+        config: &Config,
+        terminal: &mut DefaultTerminal,
+    ) -> anyhow::Result<()> {
+        let index = self
+            .rows
+            .iter()
+            .position(|row| Some(&row.name) == self.selected.as_ref());
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => self.select(index.map(|i| i.saturating_sub(1))),
+            KeyCode::Down | KeyCode::Char('j') => self.select(index.map(|i| i + 1)),
+            KeyCode::PageUp => self.scroll += SCROLL_PAGE,
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(SCROLL_PAGE),
+            KeyCode::Char(action @ ('r' | 'x' | 's')) => {
+                let Some(name) = self.selected.clone() else {
+                    return Ok(());
+                };
+                let doing = match action {
+                    'r' => "Restarting",
+                    'x' => "Resetting",
+                    _ => "Stopping",
+                };
+                self.message = message("↻", Color::Blue, format!("{} {}...", doing, name));
+                terminal.draw(|frame| self.draw(frame))?;
+                let (result, verb) = match action {
+                    'r' => (restart(config, &name).await, "restart"),
+                    'x' => (reset(config, &name).await, "reset"),
+                    _ => (stop(config, &name).await, "stop"),
+                };
+                super::audit::record_operation(
+                    &config.operation_log(),
+                    &config.workspace,
+                    "dashboard",
+                    &["dashboard".to_string(), verb.to_string(), name.clone()],
+                    &result,
+                );
+                self.message = match result {
+                    Ok(()) => message("✓", Color::Green, format!("{} {}", doing, name)),
+                    Err(e) => message("✗", Color::Red, format!("{} {}: {}", doing, name, e)),
+                };
+                if action == 'r' {
+                    // The restarted fake logs from the start again
+                    self.counts.remove(&name);
+                    self.scroll = 0;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn select(&mut self, index: Option<usize>) {
+        if let Some(row) = index.and_then(|i| self.rows.get(i)) {
+            if Some(&row.name) != self.selected.as_ref() {
+                self.selected = Some(row.name.clone());
+                self.scroll = 0;
+            }
+        }
+    }
+}
+
+/// Restart a service on its port, with the env and tags it had.
+async fn restart(config: &Config, name: &str) -> anyhow::Result<()> {
+    let mut manager = ProcessManager::load(&config.state_file)?;
+    let info = manager
+        .get_info(name)
+        .ok_or_else(|| anyhow::anyhow!("{} is not running", name))?;
+    let service = ServiceDefinition::from_dir(Path::new(&info.service_path))?;
+    manager.stop(name).await?;
+    let options = StartOptions {
+        env: info.env_overrides.clone(),
+        ..Default::default()
+    };
+    manager.start_with(&service, info.port, options).await?;
+    let health = manager
+        .wait_for_health(name, info.port, &config.health_for(name))
+        .await;
+    if health.is_ok() {
+        manager.set_tags(name, info.tags.clone());
+        manager.record_restart(name, &info);
+    }
+    manager.save(&config.state_file)?;
+    health.map_err(|e| anyhow::anyhow!("health check failed: {}", e))
+}
+
+async fn reset(config: &Config, name: &str) -> anyhow::Result<()> {
+    let manager = ProcessManager::load(&config.state_file)?;
+    let info = manager
+        .get_info(name)
+        .ok_or_else(|| anyhow::anyhow!("{} is not running", name))?;
+    ControlClient::new(info.port).reset().await?;
+    // Nothing seeded before the reset is left to undo
+    ProcessManager::update(&config.state_file, |manager| {
+        manager.clear_seed_history(name)
+    })?;
+    Ok(())
+}
+
+async fn stop(config: &Config, name: &str) -> anyhow::Result<()> {
+    let mut manager = ProcessManager::load(&config.state_file)?;
+    manager.stop(name).await?;
+    manager.save(&config.state_file)?;
+    let env_file = config.env_file_name();
+    if Path::new(&env_file).exists() {
+        super::stop::update_env_file(&manager, &env_file);
+    }
+    Ok(())
+}
+
+/// The last lines of a log file, without control characters that would
+/// upset the drawing.
+fn tail(path: &Path) -> Vec<String> {
+    hooks::tail_lines(path, LOG_TAIL)
+        .into_iter()
+        .map(|line| {
+            line.replace('\t', "    ")
+                .chars()
+                .filter(|c| !c.is_control())
+                .collect()
+        })
+        .collect()
+}
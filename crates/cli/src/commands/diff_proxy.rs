@@ -0,0 +1,171 @@
+use super::DiffProxyArgs;
+use colored::Colorize;
+use doubleagent_core::differential::{self, DivergenceReport, Upstreams};
+use doubleagent_core::ports::PortAllocator;
+use doubleagent_core::{workspace, Config, ProcessManager};
+use std::io::Write;
+
+pub async fn run(config: Config, args: DiffProxyArgs) -> anyhow::Result<()> {
+    let service = super::snapshot::service_for(&config, &args.service)?;
+    let name = &service.name;
+    let (contracts, _) = super::contract::contracts(&service)?;
+    let real = super::contract::real_target(&service, &contracts)?;
+    let mut ignore = contracts
+        .golden
+        .map(|golden| golden.ignore)
+        .unwrap_or_default();
+    ignore.extend(args.ignore);
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", args.port))
+        .await
+        .map_err(|e| anyhow::anyhow!("Could not listen on port {}: {}", args.port, e))?;
+
+    // Compare with the running fake, or one started for the session
+    let mut manager = ProcessManager::load(&config.state_file)?;
+    let (port, started) = match manager.get_info(name) {
+        Some(info) => (info.port, false),
+        None => {
+            let port = PortAllocator::new(
+                config.port_range,
+                config.contract_port,
+                workspace::ports_in_use(&config.state_dir)?,
+            )
+            .allocate_free()?;
+            println!("{} Starting {} service...", "▶".blue(), name);
+            manager.start(&service, port).await?;
+            manager.save(&config.state_file)?;
+            if let Err(e) = manager
+                .wait_for_health(name, port, &config.health_for(name))
+                .await
+            {
+                manager.stop(name).await?;
+                manager.save(&config.state_file)?;
+                return Err(anyhow::anyhow!("Health check failed: {}", e));
+            }
+            (port, true)
+        }
+    };
+
+    println!(
+        "{} Comparing {} with the real API at {}",
+        "▶".blue(),
+        name.bold(),
+        real.url.cyan()
+    );
+    println!(
+        "  {} Point your app at {}",
+        "→".dimmed(),
+        format!("http://127.0.0.1:{}", args.port).cyan()
+    );
+    println!(
+        "  {} Only GET, HEAD and OPTIONS requests reach the real API",
+        "→".dimmed()
+    );
+    println!("  {} Press Ctrl+C to stop and report", "→".dimmed());
+
+    let upstreams = Upstreams {
+        fake: format!("http://localhost:{}", port),
+        real: real.url,
+        token: real.token,
+        ignore,
+    };
+    let report = differential::run(listener, upstreams, async {
+        let _ = tokio::signal::ctrl_c().await;
+    })
+    .await;
+
+    if started {
+        manager.stop(name).await?;
+        manager.save(&config.state_file)?;
+    }
+    let report = report?;
+
+    println!();
+    print_report(&report, &mut std::io::stdout())?;
+    if let Some(path) = &args.report {
+        std::fs::write(path, serde_json::to_string_pretty(&report)? + "\n")?;
+        println!("  Report written to {}", path);
+    }
+    crate::output::result(&report);
+    if !report.divergences.is_empty() {
+        crate::output::failed(
+            "divergences",
+            &anyhow::anyhow!(
+                "{} response(s) of the fake diverge from the real API",
+                report.divergences.len()
+            ),
+        );
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Print each endpoint's comparisons, then every divergence and a summary
+fn print_report(report: &DivergenceReport, out: &mut dyn Write) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "{} Proxied {} requests, compared {} with the real API",
+        "■".red(),
+        report.requests,
+        report.compared
+    )?;
+    if report.unreachable > 0 {
+        writeln!(
+            out,
+            "{} The real API couldn't be reached for {} requests",
+            "⚠".yellow(),
+            report.unreachable
+        )?;
+    }
+    if report.endpoints.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(out)?;
+    let width = report.endpoints.keys().map(String::len).max().unwrap_or(0);
+    for (endpoint, summary) in &report.endpoints {
+        let symbol = if summary.divergent == 0 {
+            "✓".green()
+        } else {
+            "✗".red()
+        };
+        writeln!(
+            out,
+            "  {} {:<width$}  {}/{} match",
+            symbol,
+            endpoint,
+            summary.compared - summary.divergent,
+            summary.compared,
+            width = width
+        )?;
+    }
+
+    if report.divergences.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "{} The fake matched every response", "✓".green())?;
+        return Ok(());
+    }
+    writeln!(out)?;
+    for divergence in &report.divergences {
+        writeln!(
+            out,
+            "  {} {} {} (real {}, fake {})",
+            "✗".red(),
+            divergence.method,
+            divergence.path,
+            divergence.real_status,
+            divergence.fake_status
+        )?;
+        for difference in &divergence.differences {
+            writeln!(out, "      {}", difference)?;
+        }
+    }
+    writeln!(out)?;
+    writeln!(
+        out,
+        "{} {} of {} responses diverged from the real API",
+        "✗".red(),
+        report.divergences.len(),
+        report.compared
+    )?;
+    Ok(())
+}
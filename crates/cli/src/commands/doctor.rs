@@ -0,0 +1,103 @@
+use super::DoctorArgs;
+use colored::Colorize;
+use doubleagent_core::doctor::{self, Check, Status};
+use doubleagent_core::Config;
+use serde_json::json;
+
+/// Diagnose the environment with the configuration as `loaded`; one that
+/// doesn't load is reported, and the other checks use the built-in settings.
+pub async fn run(loaded: doubleagent_core::Result<Config>, args: DoctorArgs) -> anyhow::Result<()> {
+    let (config, error) = match loaded {
+        Ok(config) => (config, None),
+        Err(e) => (Config::load_defaults()?, Some(e)),
+    };
+    let configuration = vec![doctor::configuration(error.as_ref())];
+    let toolchain = doctor::toolchain();
+    let mut network = Vec::new();
+    if !args.offline {
+        network.push(doctor::services_repo(&config).await);
+        network.extend(doctor::registry(&config).await);
+    }
+    let ports = doctor::ports(&config)?;
+
+    let (mut stale, corrupt) = doctor::stale_state(&config.state_dir)?;
+    let mut orphans = doctor::orphans(&config.state_dir)?;
+    let mut fixed = Vec::new();
+    if args.fix {
+        let forgotten = doctor::forget_stale(&config.state_dir)?;
+        if forgotten > 0 {
+            fixed.push(format!("Forgot {} stale state entries", forgotten));
+        }
+        stale.clear();
+        for pid in doctor::stop_orphans(&orphans)? {
+            fixed.push(format!("Stopped untracked fake (PID {})", pid));
+        }
+        orphans.clear();
+    }
+    let mut state = doctor::state_checks(&stale, &corrupt);
+    state.extend(doctor::orphan_checks(&orphans));
+    let disk = doctor::disk_usage(&config)?;
+
+    for line in &fixed {
+        println!("{} {}", "✓".green(), line);
+    }
+    let sections = [
+        ("Config", &configuration),
+        ("Toolchain", &toolchain),
+        ("Network", &network),
+        ("Ports", &ports),
+        ("State", &state),
+        ("Disk", &disk),
+    ];
+    for (title, checks) in sections {
+        if checks.is_empty() {
+            continue;
+        }
+        println!("{}", title.bold());
+        for check in checks {
+            print_check(check);
+        }
+    }
+
+    let count = |status| {
+        sections
+            .iter()
+            .flat_map(|(_, checks)| checks.iter())
+            .filter(|check| check.status == status)
+            .count()
+    };
+    let (problems, warnings) = (count(Status::Problem), count(Status::Warning));
+    crate::output::result(json!({
+        "config": configuration,
+        "toolchain": toolchain,
+        "network": network,
+        "ports": ports,
+        "state": state,
+        "disk": disk,
+        "fixed": fixed,
+        "problems": problems,
+        "warnings": warnings,
+    }));
+
+    if problems > 0 {
+        anyhow::bail!("{} problem(s) found, see the fixes above", problems);
+    }
+    if warnings > 0 {
+        println!("\n{} No problems, {} warning(s)", "⚠".yellow(), warnings);
+    } else {
+        println!("\n{} Everything looks good", "✓".green());
+    }
+    Ok(())
+}
+
+fn print_check(check: &Check) {
+    let symbol = match check.status {
+        Status::Ok => "✓".green(),
+        Status::Warning => "⚠".yellow(),
+        Status::Problem => "✗".red(),
+    };
+    println!("  {} {}: {}", symbol, check.name, check.detail);
+    if let Some(fix) = &check.fix {
+        println!("    {} {}", "→".dimmed(), fix);
+    }
+}
@@ -0,0 +1,43 @@
+use super::DownArgs;
+use colored::Colorize;
+use doubleagent_core::manifest::Manifest;
+use doubleagent_core::{Config, ProcessManager};
+use std::path::PathBuf;
+
+pub async fn run(config: Config, _args: DownArgs) -> anyhow::Result<()> {
+    let path = project_config_path(&config)?;
+    let manifest = Manifest::from_project_config(&path, &config.cache_dir)?;
+    let mut manager = ProcessManager::load(&config.state_file)?;
+
+    let running: Vec<&String> = manifest
+        .services
+        .keys()
+        .filter(|name| manager.is_running(name))
+        .collect();
+
+    if running.is_empty() {
+        println!(
+            "No services from {} are running",
+            path.display().to_string().dimmed()
+        );
+        return Ok(());
+    }
+
+    for name in running {
+        print!("{} Stopping {}...", "■".red(), name);
+        manager.stop(name).await?;
+        println!(" {}", "✓".green());
+    }
+
+    manager.save(&config.state_file)?;
+    super::stop::update_env_file(&manager, &config.env_file_name());
+
+    Ok(())
+}
+
+/// The project's doubleagent.yaml, which up and down require.
+pub(crate) fn project_config_path(config: &Config) -> anyhow::Result<PathBuf> {
+    config.project_config_path.clone().ok_or_else(|| {
+        anyhow::anyhow!("No doubleagent.yaml found. Run 'doubleagent init' to create one.")
+    })
+}
@@ -0,0 +1,61 @@
+use super::DumpArgs;
+use colored::Colorize;
+use doubleagent_core::snapshot::{self, SnapshotStore};
+use doubleagent_core::{Config, ControlClient, Error, ProcessManager};
+use std::path::Path;
+
+pub async fn run(config: Config, args: DumpArgs) -> anyhow::Result<()> {
+    let manager = ProcessManager::load(&config.state_file)?;
+    let name = &args.service;
+    let info = manager
+        .get_info(name)
+        .ok_or_else(|| anyhow::anyhow!("{} is not running", name))?;
+
+    let state = ControlClient::new(info.port)
+        .export()
+        .await
+        .map_err(|e| match e {
+            Error::ExportUnsupported => anyhow::anyhow!("service {} does not support export", name),
+            e => anyhow::anyhow!("Failed to export state from {}: {}", name, e),
+        })?;
+
+    crate::output::result(&state);
+    // Without a destination the state goes to stdout, so it can be piped
+    if args.output.is_none() && args.profile.is_none() {
+        println!("{}", serde_json::to_string_pretty(&state)?);
+        return Ok(());
+    }
+
+    if let Some(output) = &args.output {
+        let path = Path::new(output);
+        let yaml = matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("yaml" | "yml")
+        );
+        let text = if yaml {
+            serde_yaml::to_string(&state)?
+        } else {
+            serde_json::to_string_pretty(&state)? + "\n"
+        };
+        std::fs::write(path, text)
+            .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", output, e))?;
+        println!("{} Dumped {} state to {}", "✓".green(), name.bold(), output);
+    }
+
+    if let Some(profile) = &args.profile {
+        let store = SnapshotStore::new(&config.data_dir);
+        let service = super::snapshot::service_for(&config, name)?;
+        let redactor = super::snapshot::redactor_for(&config, &service)?;
+        let manifest = snapshot::save_dump(&store, &service, profile, state, &redactor)?;
+
+        println!(
+            "{} Dumped {} state into {}/{}",
+            "✓".green(),
+            name.bold(),
+            name,
+            profile
+        );
+        super::snapshot::print_stored(&store, &manifest);
+    }
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use super::FuzzArgs;
+use colored::Colorize;
+use doubleagent_core::conformance::Spec;
+use doubleagent_core::fuzz::{self, Finding, FuzzOptions};
+use doubleagent_core::ports::PortAllocator;
+use doubleagent_core::{workspace, Config, ProcessManager};
+use serde_json::json;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub async fn run(config: Config, args: FuzzArgs) -> anyhow::Result<()> {
+    let service = super::snapshot::service_for(&config, &args.service)?;
+    let name = &service.name;
+    let spec = Spec::load(&service)?;
+    if args.timeout.is_nan() || args.timeout <= 0.0 {
+        anyhow::bail!("--timeout must be a positive number of seconds");
+    }
+    let seed = args.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default()
+    });
+    let options = FuzzOptions {
+        requests: args.requests,
+        seed,
+        timeout: Duration::from_secs_f64(args.timeout),
+    };
+
+    // Fuzzing leaves junk state behind, so it always gets its own instance
+    let mut manager = ProcessManager::load(&config.state_file)?;
+    let port = PortAllocator::new(
+        config.port_range,
+        config.contract_port,
+        workspace::ports_in_use(&config.state_dir)?,
+    )
+    .allocate_free()?;
+    println!("{} Starting {} service...", "▶".blue(), name);
+    manager.start(&service, port).await?;
+    manager.save(&config.state_file)?;
+    if let Err(e) = manager
+        .wait_for_health(name, port, &config.health_for(name))
+        .await
+    {
+        manager.stop(name).await?;
+        manager.save(&config.state_file)?;
+        return Err(anyhow::anyhow!("Health check failed: {}", e));
+    }
+
+    println!(
+        "{} Fuzzing {} with {} requests (seed {})",
+        "▶".blue(),
+        name.bold(),
+        options.requests,
+        seed
+    );
+    let report = fuzz::run(&format!("http://localhost:{}", port), &spec, &options).await;
+
+    manager.stop(name).await?;
+    manager.save(&config.state_file)?;
+    let report = report?;
+
+    println!(
+        "  Sent {} requests across {} operations",
+        report.sent, report.operations
+    );
+    println!();
+    if report.findings.is_empty() {
+        crate::output::result(json!({
+            "seed": seed,
+            "sent": report.sent,
+            "operations": report.operations,
+            "findings": [],
+        }));
+        println!("{} No crashes, hangs or invalid responses", "✓".green());
+        return Ok(());
+    }
+
+    let out = Path::new(&args.out);
+    let mut findings = Vec::new();
+    for (i, finding) in report.findings.iter().enumerate() {
+        let path = fuzz::write_repro(out, name, i, finding, seed)?;
+        print_finding(finding);
+        println!("      Reproduction: {}", path.display());
+        findings.push(json!({
+            "kind": finding.kind.slug(),
+            "operation": finding.operation,
+            "request": finding.request.to_string(),
+            "status": finding.status,
+            "detail": finding.detail,
+            "reproduction": path,
+        }));
+    }
+    crate::output::result(json!({
+        "seed": seed,
+        "sent": report.sent,
+        "operations": report.operations,
+        "findings": findings,
+    }));
+    println!();
+    println!(
+        "{} {} finding(s); replay one with `doubleagent validate-spec {} --requests <file>`",
+        "✗".red(),
+        report.findings.len(),
+        name
+    );
+    crate::output::failed(
+        "fuzz_findings",
+        &anyhow::anyhow!("{} finding(s) in {}", report.findings.len(), name),
+    );
+    std::process::exit(1);
+}
+
+fn print_finding(finding: &Finding) {
+    let status = finding
+        .status
+        .map_or_else(|| "no response".to_string(), |s| s.to_string());
+    println!(
+        "  {} {} in {} ({})",
+        "✗".red(),
+        finding.kind.to_string().bold(),
+        finding.operation,
+        status
+    );
+    println!("      {}", finding.request);
+    if !finding.detail.is_empty() {
+        println!("      {}", finding.detail.dimmed());
+    }
+}
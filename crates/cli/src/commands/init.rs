@@ -0,0 +1,535 @@
+use super::{CiProvider, InitArgs};
+use colored::Colorize;
+use dialoguer::{Confirm, Input, MultiSelect};
+use doubleagent_core::config::PROJECT_CONFIG_NAMES;
+use doubleagent_core::format::Format;
+use doubleagent_core::migrate::CURRENT_VERSION;
+use doubleagent_core::{Config, ServiceRegistry};
+use serde_yaml::{Mapping, Value};
+use std::collections::HashSet;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+const INSTALL_URL: &str = "https://raw.githubusercontent.com/islo-labs/doubleagent/main/install.sh";
+
+/// A project language, detected from marker files in the project root.
+struct Language {
+    name: &'static str,
+    /// Files whose presence identifies the language; also scanned for SDK dependencies
+    markers: &'static [&'static str],
+    /// Command used to run the project's tests in the CI snippet
+    test_command: &'static str,
+}
+
+const LANGUAGES: &[Language] = &[
+    Language {
+        name: "Python",
+        markers: &[
+            "pyproject.toml",
+            "requirements.txt",
+            "requirements-dev.txt",
+            "setup.py",
+            "Pipfile",
+        ],
+        test_command: "pytest",
+    },
+    Language {
+        name: "JavaScript/TypeScript",
+        markers: &["package.json"],
+        test_command: "npm test",
+    },
+    Language {
+        name: "Go",
+        markers: &["go.mod"],
+        test_command: "go test ./...",
+    },
+    Language {
+        name: "Rust",
+        markers: &["Cargo.toml"],
+        test_command: "cargo test",
+    },
+    Language {
+        name: "Ruby",
+        markers: &["Gemfile"],
+        test_command: "bundle exec rake test",
+    },
+];
+
+/// SDK packages that suggest a project talks to a service, by their exact
+/// names in each ecosystem (Python names normalized, Go modules without
+/// their major version suffix).
+const SDK_HINTS: &[(&str, &[&str])] = &[
+    (
+        "auth0",
+        &[
+            "auth0",
+            "auth0-python",
+            "@auth0/auth0-react",
+            "@auth0/nextjs-auth0",
+            "github.com/auth0/go-auth0",
+        ],
+    ),
+    (
+        "descope",
+        &[
+            "descope",
+            "@descope/node-sdk",
+            "@descope/react-sdk",
+            "@descope/web-js-sdk",
+            "github.com/descope/go-sdk",
+        ],
+    ),
+    (
+        "github",
+        &[
+            "pygithub",
+            "octokit",
+            "@octokit/rest",
+            "@octokit/core",
+            "github.com/google/go-github",
+            "octocrab",
+        ],
+    ),
+    (
+        "posthog",
+        &[
+            "posthog",
+            "posthog-js",
+            "posthog-node",
+            "posthog-ruby",
+            "github.com/posthog/posthog-go",
+        ],
+    ),
+    (
+        "resend",
+        &["resend", "resend-rs", "github.com/resend/resend-go"],
+    ),
+    (
+        "slack",
+        &[
+            "slack-sdk",
+            "slack-bolt",
+            "@slack/web-api",
+            "@slack/bolt",
+            "github.com/slack-go/slack",
+            "slack-ruby-client",
+        ],
+    ),
+    (
+        "stripe",
+        &["stripe", "async-stripe", "github.com/stripe/stripe-go"],
+    ),
+    (
+        "todoist",
+        &["todoist-api-python", "@doist/todoist-api-typescript"],
+    ),
+];
+
+pub async fn run(config: Config, args: InitArgs) -> anyhow::Result<()> {
+    let project_dir = std::env::current_dir()?;
+
+    let existing = PROJECT_CONFIG_NAMES
+        .iter()
+        .find(|name| project_dir.join(name).exists());
+    if let (Some(existing), false) = (existing, args.force) {
+        return Err(anyhow::anyhow!(
+            "{} already exists in {}. Use --force to overwrite it.",
+            existing,
+            project_dir.display()
+        ));
+    }
+    // --force overwrites the config the project has, in its format
+    let config_path = project_dir.join(existing.copied().unwrap_or("doubleagent.yaml"));
+
+    let interactive = !args.yes && std::io::stdin().is_terminal();
+
+    let language = detect_language(&project_dir);
+    let suggested = match language {
+        Some(language) => {
+            println!("{} Detected {} project", "ℹ".blue(), language.name);
+            detect_services(&project_dir, language)
+        }
+        None => Vec::new(),
+    };
+    if !suggested.is_empty() {
+        println!(
+            "{} Found SDKs for: {}",
+            "ℹ".blue(),
+            suggested.join(", ").cyan()
+        );
+    }
+
+    let services = if !args.services.is_empty() {
+        args.services
+    } else if interactive {
+        let registry =
+            ServiceRegistry::new(&config.services_dir, &config.repo_url, &config.branch)?;
+        prompt_services(&registry, &suggested)?
+    } else {
+        suggested
+    };
+
+    fs::write(
+        &config_path,
+        render_project_config(&config_path, &services)?,
+    )?;
+    println!("{} Wrote {}", "✓".green(), config_path.display());
+
+    let ci = match args.ci {
+        Some(ci) => Some(ci),
+        None if interactive => Confirm::new()
+            .with_prompt("Add a GitHub Actions workflow that runs your tests with these services?")
+            .default(false)
+            .interact()?
+            .then_some(CiProvider::Github),
+        None => None,
+    };
+
+    if let Some(ci) = ci {
+        let test_command = language.map_or("make test", |l| l.test_command);
+        let (path, snippet) = match ci {
+            CiProvider::Github => (
+                project_dir.join(".github/workflows/doubleagent.yml"),
+                github_workflow(&services, test_command),
+            ),
+            CiProvider::Gitlab => (
+                project_dir.join("doubleagent.gitlab-ci.yml"),
+                gitlab_job(&services, test_command),
+            ),
+        };
+        write_ci_snippet(&path, &snippet, args.force)?;
+        if ci == CiProvider::Gitlab {
+            println!(
+                "  {} Add {} to the include list in .gitlab-ci.yml",
+                "→".dimmed(),
+                "local: doubleagent.gitlab-ci.yml".cyan()
+            );
+        }
+    }
+
+    println!();
+    if services.is_empty() {
+        println!(
+            "Add services to {} and run {} to install them",
+            config_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .cyan(),
+            "doubleagent add".cyan()
+        );
+    } else {
+        println!("Use {} to install the services", "doubleagent add".cyan());
+        println!(
+            "Use {} to start them",
+            format!("doubleagent start {}", services.join(" ")).cyan()
+        );
+    }
+
+    Ok(())
+}
+
+/// Detect the project language from marker files.
+fn detect_language(dir: &Path) -> Option<&'static Language> {
+    LANGUAGES
+        .iter()
+        .find(|l| l.markers.iter().any(|m| dir.join(m).exists()))
+}
+
+/// Suggest services whose SDKs appear in the project's dependency files.
+fn detect_services(dir: &Path, language: &Language) -> Vec<String> {
+    let dependencies: HashSet<String> = language
+        .markers
+        .iter()
+        .filter_map(|m| Some((m, fs::read_to_string(dir.join(m)).ok()?)))
+        .flat_map(|(marker, content)| dependency_names(marker, &content))
+        .collect();
+
+    SDK_HINTS
+        .iter()
+        .filter(|(_, packages)| packages.iter().any(|p| dependencies.contains(*p)))
+        .map(|(service, _)| service.to_string())
+        .collect()
+}
+
+/// The names of the packages a dependency file declares, lowercase.
+fn dependency_names(file: &str, content: &str) -> Vec<String> {
+    let toml = || {
+        Format::Toml
+            .parse(content, Path::new(file))
+            .unwrap_or(Value::Null)
+    };
+    let keys = |table: Option<&Value>| -> Vec<String> {
+        table
+            .and_then(Value::as_mapping)
+            .into_iter()
+            .flat_map(|table| table.keys().filter_map(Value::as_str).map(str::to_string))
+            .collect()
+    };
+    let names: Vec<String> = match file {
+        "package.json" => {
+            let manifest: serde_json::Value = serde_json::from_str(content).unwrap_or_default();
+            [
+                "dependencies",
+                "devDependencies",
+                "peerDependencies",
+                "optionalDependencies",
+            ]
+            .iter()
+            .filter_map(|key| manifest.get(key)?.as_object())
+            .flat_map(|deps| deps.keys().cloned())
+            .collect()
+        }
+        "Cargo.toml" => {
+            let manifest = toml();
+            ["dependencies", "dev-dependencies", "build-dependencies"]
+                .iter()
+                .flat_map(|key| keys(manifest.get(key)))
+                .chain(keys(
+                    manifest
+                        .get("workspace")
+                        .and_then(|w| w.get("dependencies")),
+                ))
+                .collect()
+        }
+        "go.mod" => content
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim();
+                let line = line.strip_prefix("require").unwrap_or(line).trim();
+                let module = line.split_whitespace().next()?;
+                module.contains('/').then(|| without_major_version(module))
+            })
+            .collect(),
+        "Gemfile" => content
+            .lines()
+            .filter_map(|line| {
+                let name = line.trim().strip_prefix("gem ")?.trim();
+                let name = name.split(',').next()?.trim();
+                Some(name.trim_matches(|c| c == '"' || c == '\'').to_string())
+            })
+            .collect(),
+        "Pipfile" => {
+            let manifest = toml();
+            ["packages", "dev-packages"]
+                .iter()
+                .flat_map(|key| keys(manifest.get(key)))
+                .map(|name| python_name(&name))
+                .collect()
+        }
+        "pyproject.toml" => {
+            let manifest = toml();
+            let project = manifest.get("project");
+            let poetry = manifest.get("tool").and_then(|t| t.get("poetry"));
+            let groups = |groups: Option<&Value>| -> Vec<Value> {
+                groups
+                    .and_then(Value::as_mapping)
+                    .map(|groups| groups.values().cloned().collect())
+                    .unwrap_or_default()
+            };
+            let mut requirements: Vec<Value> = project
+                .and_then(|p| p.get("dependencies"))
+                .cloned()
+                .into_iter()
+                .chain(groups(project.and_then(|p| p.get("optional-dependencies"))))
+                .chain(groups(manifest.get("dependency-groups")))
+                .collect();
+            let mut names: Vec<String> = requirements
+                .drain(..)
+                .filter_map(|list| list.as_sequence().cloned())
+                .flatten()
+                .filter_map(|spec| spec.as_str().map(requirement_name))
+                .collect();
+            names.extend(keys(poetry.and_then(|p| p.get("dependencies"))));
+            names.extend(keys(poetry.and_then(|p| p.get("dev-dependencies"))));
+            for group in groups(poetry.and_then(|p| p.get("group"))) {
+                names.extend(keys(group.get("dependencies")));
+            }
+            names.iter().map(|name| python_name(name)).collect()
+        }
+        // setup.py lists requirements as string literals
+        "setup.py" => content
+            .split(['"', '\''])
+            .skip(1)
+            .step_by(2)
+            .map(requirement_name)
+            .collect(),
+        // requirements files: one requirement per line, with options and comments
+        _ => content
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('-'))
+            .map(requirement_name)
+            .collect(),
+    };
+    names
+        .into_iter()
+        .map(|name: String| name.to_lowercase())
+        .collect()
+}
+
+/// The normalized package name a Python requirement (`slack_sdk>=3`) is for.
+fn requirement_name(spec: &str) -> String {
+    let end = spec
+        .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        .unwrap_or(spec.len());
+    python_name(&spec[..end])
+}
+
+/// A Python package name as PyPI compares them: lowercase, with runs of
+/// `-`, `_` and `.` as a single `-`.
+fn python_name(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    for c in name.trim().chars() {
+        if matches!(c, '-' | '_' | '.') {
+            if !normalized.ends_with('-') {
+                normalized.push('-');
+            }
+        } else {
+            normalized.push(c.to_ascii_lowercase());
+        }
+    }
+    normalized
+}
+
+/// A Go module path without its major version suffix (`/v2` and up).
+fn without_major_version(module: &str) -> String {
+    match module.rsplit_once('/') {
+        Some((path, version))
+            if version.len() > 1
+                && version.starts_with('v')
+                && version[1..].bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            path.to_string()
+        }
+        _ => module.to_string(),
+    }
+}
+
+/// Ask which services the project needs, offering the services in the remote repository.
+fn prompt_services(
+    registry: &ServiceRegistry,
+    suggested: &[String],
+) -> anyhow::Result<Vec<String>> {
+    println!("{}", "Fetching available services...".dimmed());
+    let available = match registry.list_remote() {
+        Ok(available) if !available.is_empty() => available,
+        Ok(_) | Err(_) => {
+            println!(
+                "{} Could not list remote services, enter names manually",
+                "⚠".yellow()
+            );
+            let input: String = Input::new()
+                .with_prompt("Services (comma-separated)")
+                .with_initial_text(suggested.join(","))
+                .allow_empty(true)
+                .interact_text()?;
+            return Ok(input
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect());
+        }
+    };
+
+    let defaults: Vec<bool> = available.iter().map(|s| suggested.contains(s)).collect();
+    let selected = MultiSelect::new()
+        .with_prompt("Which services does this project use? (space to select, enter to confirm)")
+        .items(&available)
+        .defaults(&defaults)
+        .interact()?;
+
+    Ok(selected.into_iter().map(|i| available[i].clone()).collect())
+}
+
+/// The project config listing `services`, in the format of `path`.
+fn render_project_config(path: &Path, services: &[String]) -> anyhow::Result<String> {
+    let mut config = Mapping::new();
+    config.insert("version".into(), CURRENT_VERSION.into());
+    config.insert(
+        "services".into(),
+        Value::Mapping(
+            services
+                .iter()
+                .map(|service| (service.as_str().into(), Value::Mapping(Mapping::new())))
+                .collect(),
+        ),
+    );
+    let format = Format::from_path(path);
+    let mut content = match format {
+        // JSON has no comments
+        Format::Json => String::new(),
+        Format::Yaml | Format::Toml => String::from(
+            "# DoubleAgent project configuration\n\
+             # Run 'doubleagent add' to install the services listed here.\n",
+        ),
+    };
+    content.push_str(&format.to_string(&Value::Mapping(config))?);
+    Ok(content)
+}
+
+/// The command that runs tests with services started, or the bare test command.
+fn run_command(services: &[String], test_command: &str) -> String {
+    if services.is_empty() {
+        test_command.to_string()
+    } else {
+        format!(
+            "doubleagent run --services {} -- {}",
+            services.join(" "),
+            test_command
+        )
+    }
+}
+
+fn github_workflow(services: &[String], test_command: &str) -> String {
+    format!(
+        "name: Tests with DoubleAgent\n\
+         \n\
+         on: [push, pull_request]\n\
+         \n\
+         jobs:\n  \
+           test:\n    \
+             runs-on: ubuntu-latest\n    \
+             steps:\n      \
+               - uses: actions/checkout@v4\n      \
+               - name: Install doubleagent\n        \
+                 run: curl -sSL {} | bash\n      \
+               - name: Install services\n        \
+                 run: doubleagent add\n      \
+               - name: Run tests\n        \
+                 run: {}\n",
+        INSTALL_URL,
+        run_command(services, test_command)
+    )
+}
+
+fn gitlab_job(services: &[String], test_command: &str) -> String {
+    format!(
+        "doubleagent-tests:\n  \
+           script:\n    \
+             - curl -sSL {} | bash\n    \
+             - doubleagent add\n    \
+             - {}\n",
+        INSTALL_URL,
+        run_command(services, test_command)
+    )
+}
+
+fn write_ci_snippet(path: &PathBuf, snippet: &str, force: bool) -> anyhow::Result<()> {
+    if path.exists() && !force {
+        println!(
+            "{} {} already exists, skipping (use --force to overwrite)",
+            "⚠".yellow(),
+            path.display()
+        );
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, snippet)?;
+    println!("{} Wrote {}", "✓".green(), path.display());
+    Ok(())
+}
@@ -1,9 +1,9 @@
 use super::ListArgs;
 use colored::Colorize;
 use doubleagent_core::{Config, ServiceRegistry};
+use serde_json::json;
 
-pub async fn run(args: ListArgs) -> anyhow::Result<()> {
-    let config = Config::load()?;
+pub async fn run(config: Config, args: ListArgs) -> anyhow::Result<()> {
     let registry = ServiceRegistry::new(&config.services_dir, &config.repo_url, &config.branch)?;
 
     if args.remote {
@@ -12,6 +12,14 @@ pub async fn run(args: ListArgs) -> anyhow::Result<()> {
         println!();
 
         let remote_services = registry.list_remote()?;
+        let installed: std::collections::HashSet<String> =
+            registry.list()?.into_iter().map(|s| s.name).collect();
+        crate::output::result(json!({
+            "services": remote_services
+                .iter()
+                .map(|name| json!({ "name": name, "installed": installed.contains(name) }))
+                .collect::<Vec<_>>(),
+        }));
 
         if remote_services.is_empty() {
             println!("No services found in remote repository");
@@ -21,10 +29,6 @@ pub async fn run(args: ListArgs) -> anyhow::Result<()> {
         println!("{}", "Available services (remote):".bold());
         println!();
 
-        // Check which are installed locally
-        let installed: std::collections::HashSet<String> =
-            registry.list()?.into_iter().map(|s| s.name).collect();
-
         for name in remote_services {
             let status = if installed.contains(&name) {
                 format!("{}", "installed".green())
@@ -43,6 +47,19 @@ pub async fn run(args: ListArgs) -> anyhow::Result<()> {
     } else {
         // List installed services
         let services = registry.list()?;
+        crate::output::result(json!({
+            "services": services
+                .iter()
+                .map(|service| {
+                    json!({
+                        "name": service.name,
+                        "description": service.description,
+                        "docs": service.docs,
+                        "path": service.path,
+                    })
+                })
+                .collect::<Vec<_>>(),
+        }));
 
         if services.is_empty() {
             println!("No services installed");
@@ -58,7 +75,14 @@ pub async fn run(args: ListArgs) -> anyhow::Result<()> {
             return Ok(());
         }
 
-        println!("{}", "Installed services:".bold());
+        match &config.project_services_dir {
+            Some(dir) => println!(
+                "{} {}",
+                "Installed services:".bold(),
+                format!("(project: {})", dir.display()).dimmed()
+            ),
+            None => println!("{}", "Installed services:".bold()),
+        }
         println!();
 
         for service in services {
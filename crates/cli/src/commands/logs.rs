@@ -0,0 +1,229 @@
+use super::LogsArgs;
+use colored::{Color, Colorize};
+use doubleagent_core::{tags, Config, ProcessManager};
+use serde_json::json;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How often `logs --follow` checks the log files for new output.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Colors services' prefixes cycle through, in the order services are listed.
+const COLORS: [Color; 6] = [
+    Color::Cyan,
+    Color::Yellow,
+    Color::Green,
+    Color::Magenta,
+    Color::Blue,
+    Color::Red,
+];
+
+pub async fn run(config: Config, args: LogsArgs) -> anyhow::Result<()> {
+    if args.follow {
+        crate::output::text_only("--follow")?;
+    }
+    let manager = ProcessManager::load(&config.state_file)?;
+    let names = services(&manager, &args);
+    if names.is_empty() {
+        crate::output::result(json!({}));
+        if args.tags.is_empty() {
+            println!("No services running");
+        } else {
+            println!("No running services match the given tags");
+        }
+        if !args.follow {
+            return Ok(());
+        }
+    }
+
+    let mut logs = Logs::new(&args);
+    for name in &names {
+        logs.add(&manager, name, false);
+    }
+
+    let mut shown = BTreeMap::new();
+    for log in &logs.logs {
+        let lines = last_lines(&log.path, args.tail);
+        for line in &lines {
+            logs.print(log, line);
+        }
+        shown.insert(log.name.clone(), lines);
+    }
+    crate::output::result(&shown);
+    if !args.follow {
+        return Ok(());
+    }
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+            _ = tokio::time::sleep(FOLLOW_INTERVAL) => {}
+        }
+        let manager = ProcessManager::load(&config.state_file)?;
+        // With --all, services started since join in, from their first line
+        if args.all {
+            for name in services(&manager, &args) {
+                if !logs.logs.iter().any(|log| log.name == name) {
+                    logs.add(&manager, &name, true);
+                }
+            }
+        }
+        for i in 0..logs.logs.len() {
+            logs.logs[i].restarted(&manager);
+            let lines = logs.logs[i].read_new();
+            for line in lines {
+                logs.print(&logs.logs[i], &line);
+            }
+        }
+    }
+}
+
+/// The services named, or with `--all` the running ones, with the given tags.
+fn services(manager: &ProcessManager, args: &LogsArgs) -> Vec<String> {
+    let names = if args.all {
+        let mut names = manager.running_services();
+        names.sort();
+        names
+    } else {
+        args.services.clone()
+    };
+    names
+        .into_iter()
+        .filter(|name| {
+            args.tags.is_empty()
+                || manager
+                    .get_info(name)
+                    .is_some_and(|info| tags::matches(&info.tags, &args.tags))
+        })
+        .collect()
+}
+
+/// The logs being shown, with their prefixes lined up.
+struct Logs {
+    logs: Vec<Log>,
+    prefix: bool,
+    width: usize,
+}
+
+impl Logs {
+    fn new(args: &LogsArgs) -> Self {
+        Self {
+            logs: Vec::new(),
+            prefix: !args.no_prefix,
+            width: 0,
+        }
+    }
+
+    /// Start showing a service's log, from its start or what's written next.
+    fn add(&mut self, manager: &ProcessManager, name: &str, from_start: bool) {
+        let path = manager.log_file(name);
+        if !path.exists() {
+            eprintln!("{} {} has no log yet", "⚠".yellow(), name);
+        }
+        let offset = if from_start {
+            0
+        } else {
+            std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0)
+        };
+        self.width = self.width.max(name.len());
+        self.logs.push(Log {
+            name: name.to_string(),
+            color: COLORS[self.logs.len() % COLORS.len()],
+            path,
+            pid: manager.get_info(name).map(|info| info.pid),
+            offset,
+            partial: Vec::new(),
+        });
+    }
+
+    /// Print a line of `log`, after its service's prefix.
+    fn print(&self, log: &Log, line: &str) {
+        if self.prefix {
+            println!(
+                "{} {}",
+                format!("{:<width$} |", log.name, width = self.width).color(log.color),
+                line
+            );
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+/// A service's log file, read as it grows.
+struct Log {
+    name: String,
+    color: Color,
+    path: PathBuf,
+    /// Process the service ran as when last checked
+    pid: Option<u32>,
+    /// How far the file has been read
+    offset: u64,
+    /// The start of a line still being written
+    partial: Vec<u8>,
+}
+
+impl Log {
+    /// Read from the top again if the service was restarted, which starts its
+    /// log afresh.
+    fn restarted(&mut self, manager: &ProcessManager) {
+        let pid = manager.get_info(&self.name).map(|info| info.pid);
+        if pid.is_some() && pid != self.pid {
+            self.offset = 0;
+            self.partial.clear();
+        }
+        self.pid = pid;
+    }
+
+    /// Complete lines written since the last read.
+    fn read_new(&mut self) -> Vec<String> {
+        let Ok(mut file) = File::open(&self.path) else {
+            return Vec::new();
+        };
+        let length = file.metadata().map(|m| m.len()).unwrap_or(0);
+        // A shorter file was started afresh, so all of it is new
+        if length < self.offset {
+            self.offset = 0;
+            self.partial.clear();
+        }
+        if length == self.offset || file.seek(SeekFrom::Start(self.offset)).is_err() {
+            return Vec::new();
+        }
+        let mut bytes = Vec::new();
+        let Ok(read) = file.read_to_end(&mut bytes) else {
+            return Vec::new();
+        };
+        self.offset += read as u64;
+        self.partial.extend_from_slice(&bytes);
+
+        let Some(end) = self.partial.iter().rposition(|&b| b == b'\n') else {
+            return Vec::new();
+        };
+        let complete: Vec<u8> = self.partial.drain(..=end).collect();
+        String::from_utf8_lossy(&complete)
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+/// The last `count` lines of a log file.
+fn last_lines(path: &Path, count: usize) -> Vec<String> {
+    let Ok(file) = File::open(path) else {
+        return Vec::new();
+    };
+    let mut lines = VecDeque::with_capacity(count);
+    for line in BufReader::new(file).split(b'\n').map_while(Result::ok) {
+        if count == 0 {
+            break;
+        }
+        if lines.len() == count {
+            lines.pop_front();
+        }
+        lines.push_back(String::from_utf8_lossy(&line).into_owned());
+    }
+    lines.into()
+}
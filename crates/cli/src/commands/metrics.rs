@@ -0,0 +1,27 @@
+use super::MetricsArgs;
+use colored::Colorize;
+use doubleagent_core::metrics::{self, Exporter};
+use doubleagent_core::Config;
+
+pub async fn run(config: Config, args: MetricsArgs) -> anyhow::Result<()> {
+    let listener = tokio::net::TcpListener::bind((args.host.as_str(), args.port))
+        .await
+        .map_err(|e| anyhow::anyhow!("Could not listen on {}:{}: {}", args.host, args.port, e))?;
+
+    println!(
+        "{} Serving metrics at {}",
+        "▶".blue(),
+        format!("http://{}:{}/metrics", args.host, args.port).cyan()
+    );
+    println!(
+        "  {} Each scrape checks the running services' health and request logs",
+        "→".dimmed()
+    );
+    println!("  {} Press Ctrl+C to stop", "→".dimmed());
+
+    metrics::serve(listener, config, Exporter::new(args.tags), async {
+        let _ = tokio::signal::ctrl_c().await;
+    })
+    .await?;
+    Ok(())
+}
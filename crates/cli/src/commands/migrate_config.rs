@@ -0,0 +1,66 @@
+use super::MigrateConfigArgs;
+use colored::Colorize;
+use doubleagent_core::golden::{self, DiffLine};
+use doubleagent_core::migrate::{self, CURRENT_VERSION};
+use doubleagent_core::Config;
+use std::fs;
+
+pub async fn run(config: Config, args: MigrateConfigArgs) -> anyhow::Result<()> {
+    let path = super::down::project_config_path(&config)?;
+
+    let (from, content) = migrate::migrate_file(&path)?;
+    let Some(content) = content else {
+        println!(
+            "{} {} is already at config version {}",
+            "ℹ".blue(),
+            path.display(),
+            CURRENT_VERSION
+        );
+        return Ok(());
+    };
+
+    let old: Vec<String> = fs::read_to_string(&path)?
+        .lines()
+        .map(str::to_string)
+        .collect();
+    let new: Vec<String> = content.lines().map(str::to_string).collect();
+    let diff = golden::line_diff(&old, &new);
+    for line in &diff {
+        match line {
+            DiffLine::Same(text) => println!("  {}", text.dimmed()),
+            DiffLine::Removed(text) => println!("{}", format!("- {}", text).red()),
+            DiffLine::Added(text) => println!("{}", format!("+ {}", text).green()),
+        }
+    }
+    println!();
+
+    if !args.write {
+        println!(
+            "{} {} not changed; run with {} to write it (comments are not kept)",
+            "ℹ".blue(),
+            path.display(),
+            "--write".cyan()
+        );
+        return Ok(());
+    }
+
+    let mut backup = path.clone().into_os_string();
+    backup.push(".bak");
+    fs::copy(&path, &backup)?;
+    fs::write(&path, content)?;
+
+    println!(
+        "{} Migrated {} from config version {} to {}",
+        "✓".green(),
+        path.display(),
+        from,
+        CURRENT_VERSION
+    );
+    println!(
+        "  {} Comments are not preserved; the original was saved to {}",
+        "→".dimmed(),
+        backup.to_string_lossy()
+    );
+
+    Ok(())
+}
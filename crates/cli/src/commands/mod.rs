@@ -1,29 +1,109 @@
 pub mod add;
+pub mod apply;
+pub mod audit;
+pub mod compliance;
+pub mod config;
 pub mod contract;
+pub mod dashboard;
+pub mod diff_proxy;
+pub mod doctor;
+pub mod down;
+pub mod dump;
+pub mod fuzz;
+pub mod init;
 pub mod list;
+pub mod logs;
+pub mod metrics;
+pub mod migrate_config;
+pub mod namespace;
+pub mod requests;
 pub mod reset;
 pub mod run;
 pub mod seed;
+pub mod snapshot;
 pub mod start;
 pub mod status;
 pub mod stop;
+pub mod up;
 pub mod update;
+pub mod upgrade;
+pub mod validate;
+pub mod validate_spec;
 
-use clap::{Parser, Subcommand};
+use crate::output::OutputFormat;
+use clap::{Parser, Subcommand, ValueEnum};
+use doubleagent_core::settings::Overrides;
+use doubleagent_core::{RetryPolicy, SeedMode};
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(name = "doubleagent")]
 #[command(author, version, about = "Fake services. Real agents.", long_about = None)]
 pub struct Cli {
+    /// Workspace to operate on, for running independent environments side by side
+    #[arg(short, long, global = true, value_name = "NAME")]
+    pub workspace: Option<String>,
+
+    /// Print results as text, or as one JSON document on stdout (the text
+    /// then goes to stderr)
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        env = "DOUBLEAGENT_OUTPUT_FORMAT",
+        default_value = "text"
+    )]
+    pub output_format: OutputFormat,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
+impl Cli {
+    /// Settings given as flags, which take precedence over every other layer.
+    pub fn overrides(&self) -> doubleagent_core::Result<Overrides> {
+        let mut overrides = Overrides::new();
+        if let Some(workspace) = &self.workspace {
+            overrides.set("workspace", workspace)?;
+        }
+        match &self.command {
+            Commands::Start(StartArgs {
+                port: Some(port), ..
+            })
+            | Commands::Run(RunArgs {
+                port: Some(port), ..
+            }) => overrides.set("base_port", port.to_string())?,
+            Commands::Snapshot(SnapshotArgs {
+                command:
+                    SnapshotCommands::Fetch(SnapshotTransferArgs {
+                        registry: Some(registry),
+                        ..
+                    })
+                    | SnapshotCommands::Push(SnapshotTransferArgs {
+                        registry: Some(registry),
+                        ..
+                    }),
+            }) => overrides.set("registry", registry)?,
+            _ => {}
+        }
+        Ok(overrides)
+    }
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
+    /// Set up doubleagent.yaml for the current project
+    Init(InitArgs),
+
     /// Add (install) a service from the remote repository
     Add(AddArgs),
 
+    /// Start, seed and configure everything declared in doubleagent.yaml
+    Up(UpArgs),
+
+    /// Stop the services declared in doubleagent.yaml
+    Down(DownArgs),
+
     /// Start one or more services
     Start(StartArgs),
 
@@ -31,7 +111,16 @@ pub enum Commands {
     Stop(StopArgs),
 
     /// Show status of running services
-    Status,
+    Status(StatusArgs),
+
+    /// Watch running services in a full-screen dashboard, with their logs
+    Dashboard(DashboardArgs),
+
+    /// Show the output of services, interleaved as it comes with --follow
+    Logs(LogsArgs),
+
+    /// Serve Prometheus metrics of running services
+    Metrics(MetricsArgs),
 
     /// Reset service state
     Reset(ResetArgs),
@@ -39,6 +128,21 @@ pub enum Commands {
     /// Seed service with data
     Seed(SeedArgs),
 
+    /// Export the current state of a running service as a seed file or profile
+    Dump(DumpArgs),
+
+    /// Show the requests a running service served
+    Requests(RequestsArgs),
+
+    /// Create and remove isolated namespaces of a running service's state
+    Namespace(NamespaceArgs),
+
+    /// Pull and manage snapshots of real service data
+    Snapshot(SnapshotArgs),
+
+    /// Export the audit log of snapshot operations
+    Audit(AuditArgs),
+
     /// List available services
     List(ListArgs),
 
@@ -50,6 +154,127 @@ pub enum Commands {
 
     /// Run a command with services started and env vars set
     Run(RunArgs),
+
+    /// Restart changed services, or converge on an environment manifest with -f
+    Apply(ApplyArgs),
+
+    /// Upgrade a running service to a new version without losing its state
+    Upgrade(UpgradeArgs),
+
+    /// Read and write configuration settings
+    Config(ConfigArgs),
+
+    /// Diagnose the toolchain, network access, ports, leftover state and disk space
+    Doctor(DoctorArgs),
+
+    /// Check doubleagent.yaml and service.yaml files for mistakes
+    Validate(ValidateArgs),
+
+    /// Check a fake's responses against the service's OpenAPI description
+    ValidateSpec(ValidateSpecArgs),
+
+    /// Send a fake generated requests from its OpenAPI description to find crashes
+    Fuzz(FuzzArgs),
+
+    /// Proxy to a fake, comparing its answers to read-only requests with the real API's
+    DiffProxy(DiffProxyArgs),
+
+    /// Rewrite doubleagent.yaml in the latest config version
+    MigrateConfig(MigrateConfigArgs),
+}
+
+impl Commands {
+    /// Whether the command changes what runs, or the settings, snapshots and
+    /// services it runs with, and so goes in the operation log.
+    pub fn changes_state(&self) -> bool {
+        match self {
+            Commands::Init(_)
+            | Commands::Add(_)
+            | Commands::Up(_)
+            | Commands::Down(_)
+            | Commands::Start(_)
+            | Commands::Stop(_)
+            | Commands::Reset(_)
+            | Commands::Seed(_)
+            | Commands::Update(_)
+            | Commands::Run(_)
+            | Commands::Apply(_)
+            | Commands::Upgrade(_)
+            | Commands::MigrateConfig(_) => true,
+            Commands::Namespace(args) => !matches!(args.command, NamespaceCommands::List(_)),
+            Commands::Snapshot(args) => !matches!(
+                args.command,
+                SnapshotCommands::List(_)
+                    | SnapshotCommands::Inspect(_)
+                    | SnapshotCommands::Diff(_)
+                    | SnapshotCommands::Scan(_)
+                    | SnapshotCommands::Validate(_)
+            ),
+            Commands::Config(args) => matches!(
+                args.command,
+                ConfigCommands::Set(_) | ConfigCommands::Unset(_)
+            ),
+            Commands::Doctor(args) => args.fix,
+            // Dashboard actions are recorded one by one
+            Commands::Status(_)
+            | Commands::Dashboard(_)
+            | Commands::Logs(_)
+            | Commands::Metrics(_)
+            | Commands::Dump(_)
+            | Commands::Requests(_)
+            | Commands::Audit(_)
+            | Commands::List(_)
+            | Commands::Contract(_)
+            | Commands::Validate(_)
+            | Commands::ValidateSpec(_)
+            | Commands::Fuzz(_)
+            | Commands::DiffProxy(_) => false,
+        }
+    }
+}
+
+/// Parse a `key=value` tag argument
+fn parse_tag(s: &str) -> Result<(String, String), String> {
+    doubleagent_core::tags::parse_tag(s).map_err(|e| e.to_string())
+}
+
+fn parse_percent(s: &str) -> Result<f64, String> {
+    doubleagent_core::coverage::parse_percent(s).map_err(|e| e.to_string())
+}
+
+fn parse_var(s: &str) -> Result<(String, String), String> {
+    doubleagent_core::template::parse_var(s).map_err(|e| e.to_string())
+}
+
+fn parse_column(s: &str) -> Result<(String, String), String> {
+    doubleagent_core::rows::parse_column(s).map_err(|e| e.to_string())
+}
+
+#[derive(Parser)]
+pub struct InitArgs {
+    /// Services the project uses (skips the interactive prompt)
+    pub services: Vec<String>,
+
+    /// Also write a CI snippet that runs tests with the services
+    #[arg(long, value_enum)]
+    pub ci: Option<CiProvider>,
+
+    /// Don't prompt; use detected services unless services are given
+    #[arg(short, long)]
+    pub yes: bool,
+
+    /// Overwrite existing files
+    #[arg(short, long)]
+    pub force: bool,
+}
+
+/// CI systems `init` can write a snippet for
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CiProvider {
+    /// .github/workflows/doubleagent.yml
+    Github,
+    /// doubleagent.gitlab-ci.yml, to include from .gitlab-ci.yml
+    Gitlab,
 }
 
 #[derive(Parser)]
@@ -58,12 +283,26 @@ pub struct AddArgs {
     pub services: Vec<String>,
 }
 
+#[derive(Parser)]
+pub struct UpArgs {
+    /// Show what would change without starting or seeding anything
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Keep running and re-apply doubleagent.yaml whenever it changes
+    #[arg(long, conflicts_with = "dry_run")]
+    pub watch: bool,
+}
+
+#[derive(Parser)]
+pub struct DownArgs {}
+
 #[derive(Parser)]
 pub struct StartArgs {
     /// Services to start (ignored when --local is used)
     pub services: Vec<String>,
 
-    /// Port for the first service (subsequent services increment)
+    /// Port for the first service (overrides base_port)
     #[arg(short, long)]
     pub port: Option<u16>,
 
@@ -74,6 +313,19 @@ pub struct StartArgs {
     /// Start a service from a local directory (for development/testing)
     #[arg(short, long)]
     pub local: Option<String>,
+
+    /// Tag started services (repeatable, e.g. --tag team=payments)
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+    pub tags: Vec<(String, String)>,
+
+    /// Seed started services with a stored snapshot profile as their baseline
+    #[arg(long, value_name = "PROFILE")]
+    pub snapshot: Option<String>,
+
+    /// Seed started services with their profiles in a scenario (starts every
+    /// service of the scenario when none are given)
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["snapshot", "local"])]
+    pub scenario: Option<String>,
 }
 
 #[derive(Parser)]
@@ -93,36 +345,799 @@ pub struct UpdateArgs {
 pub struct StopArgs {
     /// Services to stop (empty = all)
     pub services: Vec<String>,
+
+    /// Only stop services with this tag (repeatable, all must match)
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+    pub tags: Vec<(String, String)>,
+}
+
+#[derive(Parser)]
+pub struct StatusArgs {
+    /// Only show services with this tag (repeatable, all must match)
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+    pub tags: Vec<(String, String)>,
+
+    /// Keep refreshing every SECONDS (default: 2), showing services that
+    /// start, stop, restart or change health
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        num_args = 0..=1,
+        default_missing_value = "2"
+    )]
+    pub watch: Option<u64>,
+}
+
+#[derive(Parser)]
+pub struct DashboardArgs {
+    /// Only show services with this tag (repeatable, all must match)
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+    pub tags: Vec<(String, String)>,
+}
+
+#[derive(Parser)]
+pub struct LogsArgs {
+    /// Services whose output to show
+    #[arg(required_unless_present = "all")]
+    pub services: Vec<String>,
+
+    /// Show every running service's output
+    #[arg(long, conflicts_with = "services")]
+    pub all: bool,
+
+    /// Keep printing output as services write it; with --all, services
+    /// started meanwhile join in
+    #[arg(short, long)]
+    pub follow: bool,
+
+    /// Lines to show from the end of each log first
+    #[arg(short = 'n', long, value_name = "LINES", default_value_t = 20)]
+    pub tail: usize,
+
+    /// Only show services with this tag (repeatable, all must match)
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+    pub tags: Vec<(String, String)>,
+
+    /// Print lines without the service name in front
+    #[arg(long)]
+    pub no_prefix: bool,
+}
+
+#[derive(Parser)]
+pub struct DoctorArgs {
+    /// Forget stale state entries and stop fakes no workspace tracks
+    #[arg(long)]
+    pub fix: bool,
+
+    /// Skip the checks that need the network
+    #[arg(long)]
+    pub offline: bool,
+}
+
+#[derive(Parser)]
+pub struct MetricsArgs {
+    /// Port to serve /metrics on
+    #[arg(long, default_value_t = doubleagent_core::metrics::DEFAULT_PORT)]
+    pub port: u16,
+
+    /// Address to listen on (e.g. 0.0.0.0 for a Prometheus in a container)
+    #[arg(long, default_value = "127.0.0.1")]
+    pub host: String,
+
+    /// Only report services with this tag (repeatable, all must match)
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+    pub tags: Vec<(String, String)>,
 }
 
 #[derive(Parser)]
 pub struct ResetArgs {
     /// Services to reset (empty = all running)
     pub services: Vec<String>,
+
+    /// Reset to the seed each service was started with (or last replaced
+    /// with), instead of to empty
+    #[arg(long)]
+    pub keep_seed: bool,
+
+    /// Reset only this namespace of each service (default: $DOUBLEAGENT_NAMESPACE)
+    #[arg(long, value_name = "NAME", conflicts_with = "keep_seed")]
+    pub namespace: Option<String>,
+}
+
+impl ResetArgs {
+    /// Namespace to reset, from the flag or the environment.
+    pub fn namespace(&self) -> Option<String> {
+        self.namespace
+            .clone()
+            .or_else(doubleagent_core::namespace::from_env)
+    }
 }
 
 #[derive(Parser)]
 pub struct SeedArgs {
     /// Service to seed
+    #[arg(required_unless_present = "combined")]
+    pub service: Option<String>,
+
+    /// Path to seed data file (YAML, JSON, CSV or NDJSON), a directory of
+    /// them to merge, or - to read YAML or JSON from stdin
+    #[arg(required_unless_present_any = ["snapshot", "fixture", "generate", "openapi", "combined", "undo"])]
+    pub file: Option<String>,
+
+    /// Seed several running services from one file mapping service names to
+    /// their payloads
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["service", "file", "snapshot", "generate"]
+    )]
+    pub combined: Option<String>,
+
+    /// Seed from a stored snapshot profile instead of a file
+    #[arg(long, value_name = "PROFILE", conflicts_with = "file")]
+    pub snapshot: Option<String>,
+
+    /// Seed from fixtures shipped with the service, layered in order when
+    /// joined by '+' (e.g. base+billing+edge-cases)
+    #[arg(
+        long,
+        value_name = "NAMES",
+        conflicts_with_all = ["file", "snapshot", "combined"]
+    )]
+    pub fixture: Option<String>,
+
+    /// Seed with synthetic data from the service's generators.yaml, with
+    /// optional record counts (e.g. issues=2000,users=500)
+    #[arg(
+        long,
+        value_name = "COUNTS",
+        num_args = 0..=1,
+        default_missing_value = "",
+        conflicts_with_all = ["file", "snapshot", "fixture"]
+    )]
+    pub generate: Option<String>,
+
+    /// Seed with records made from the examples and schemas of the service's
+    /// openapi.yaml
+    #[arg(long, conflicts_with_all = ["file", "snapshot", "fixture", "generate", "combined"])]
+    pub openapi: bool,
+
+    /// Seed of the random generator, to vary the generated data
+    #[arg(long, value_name = "N", default_value_t = 0, requires = "generate")]
+    pub random_seed: u64,
+
+    /// Render the seed file as a template with this variable (repeatable)
+    #[arg(
+        long = "var",
+        value_name = "NAME=VALUE",
+        value_parser = parse_var,
+        conflicts_with_all = ["snapshot", "fixture", "generate"]
+    )]
+    pub vars: Vec<(String, String)>,
+
+    /// Render the seed file as a template even without --var
+    #[arg(long, conflicts_with_all = ["snapshot", "fixture", "generate"])]
+    pub template: bool,
+
+    /// Resource the rows of a .csv or .ndjson file are records of
+    /// (default: the file's name)
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["combined", "snapshot", "fixture", "generate"])]
+    pub resource: Option<String>,
+
+    /// Take a field of each record from a differently named CSV column
+    /// (repeatable)
+    #[arg(
+        long = "column",
+        value_name = "FIELD=COLUMN",
+        value_parser = parse_column,
+        conflicts_with_all = ["combined", "snapshot", "fixture", "generate"]
+    )]
+    pub columns: Vec<(String, String)>,
+
+    /// Update records that already exist and add the rest (needs support
+    /// from the service)
+    #[arg(long, conflicts_with = "replace")]
+    pub merge: bool,
+
+    /// Discard the current state first; the seed becomes the baseline that
+    /// `reset --keep-seed` returns to
+    #[arg(long)]
+    pub replace: bool,
+
+    /// Check the payload against the service's seed schema and show what it
+    /// would create, without sending it
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Retry a seed call this many times when the service can't be connected
+    /// to, backing off between tries
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    pub retries: u32,
+
+    /// Give up on seeding after this many seconds, retries included
+    #[arg(long, value_name = "SECS")]
+    pub timeout: Option<u64>,
+
+    /// Write a JSON report of the resources applied, failed and not sent,
+    /// to recover from a partial seed
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["combined", "dry_run"])]
+    pub report: Option<String>,
+
+    /// Seed only this namespace of the service (default: $DOUBLEAGENT_NAMESPACE)
+    #[arg(long, value_name = "NAME")]
+    pub namespace: Option<String>,
+
+    /// Undo the service's last seed, restoring the state it was seeded over
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "file", "combined", "snapshot", "fixture", "generate", "openapi",
+            "merge", "replace", "dry_run", "report"
+        ]
+    )]
+    pub undo: bool,
+}
+
+impl SeedArgs {
+    /// Namespace to seed, from the flag or the environment.
+    pub fn namespace(&self) -> Option<String> {
+        self.namespace
+            .clone()
+            .or_else(doubleagent_core::namespace::from_env)
+    }
+
+    /// How transient failures while seeding are retried.
+    pub fn retry(&self) -> RetryPolicy {
+        RetryPolicy {
+            retries: self.retries,
+            timeout: self.timeout.map(Duration::from_secs),
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// How the payload combines with the current state.
+    pub fn mode(&self) -> SeedMode {
+        if self.merge {
+            SeedMode::Merge
+        } else if self.replace {
+            SeedMode::Replace
+        } else {
+            SeedMode::Default
+        }
+    }
+}
+
+#[derive(Parser)]
+pub struct NamespaceArgs {
+    #[command(subcommand)]
+    pub command: NamespaceCommands,
+}
+
+#[derive(Subcommand)]
+pub enum NamespaceCommands {
+    /// Mint a namespace for a test session and print how to use it
+    Create(NamespaceCreateArgs),
+
+    /// List the namespaces created for running services
+    List(NamespaceListArgs),
+
+    /// Reset a namespace and forget it
+    Delete(NamespaceDeleteArgs),
+}
+
+#[derive(Parser)]
+pub struct NamespaceCreateArgs {
+    /// Service to create the namespace in
+    pub service: String,
+
+    /// Prefix of the namespace, e.g. a pytest-xdist worker id
+    #[arg(long, value_name = "LABEL")]
+    pub label: Option<String>,
+
+    /// Print only the namespace, for scripts
+    #[arg(short, long)]
+    pub quiet: bool,
+}
+
+#[derive(Parser)]
+pub struct NamespaceListArgs {
+    /// Only list the namespaces of this service
+    pub service: Option<String>,
+}
+
+#[derive(Parser)]
+pub struct NamespaceDeleteArgs {
+    /// Service the namespace is in
+    pub service: String,
+
+    /// Namespace to delete
+    pub namespace: String,
+}
+
+#[derive(Parser)]
+pub struct SnapshotArgs {
+    #[command(subcommand)]
+    pub command: SnapshotCommands,
+}
+
+#[derive(Subcommand)]
+pub enum SnapshotCommands {
+    /// Pull a snapshot from the real service with its connector
+    Pull(SnapshotPullArgs),
+
+    /// Bring pulled profiles up to date, fetching only new data where possible
+    Refresh(SnapshotRefreshArgs),
+
+    /// List stored snapshot profiles
+    List(SnapshotListArgs),
+
+    /// Show a stored profile's manifest, or browse its records with --data
+    Inspect(SnapshotInspectArgs),
+
+    /// Show what changed between two profiles of a service
+    Diff(SnapshotDiffArgs),
+
+    /// Download a profile from the shared registry
+    Fetch(SnapshotTransferArgs),
+
+    /// Upload a stored profile to the shared registry
+    Push(SnapshotTransferArgs),
+
+    /// Store a seed file as a profile, redacting it like a pull
+    Import(SnapshotImportArgs),
+
+    /// Record a profile from live traffic through a proxy to the real API
+    Record(SnapshotRecordArgs),
+
+    /// Apply the current redaction rules to a stored profile
+    Redact(SnapshotRedactArgs),
+
+    /// Upgrade a stored profile to the service's current seed schema version
+    Migrate(SnapshotMigrateArgs),
+
+    /// Combine stored profiles into a new one
+    Merge(SnapshotMergeArgs),
+
+    /// Store a small subsample of a profile, e.g. as a CI baseline
+    Sample(SnapshotSampleArgs),
+
+    /// Audit a stored profile for personal data and credentials left unredacted
+    Scan(SnapshotScanArgs),
+
+    /// Check a stored profile against the service's seed schema
+    Validate(SnapshotValidateArgs),
+}
+
+#[derive(Parser)]
+pub struct SnapshotPullArgs {
+    /// Services to snapshot
+    #[arg(required = true)]
+    pub services: Vec<String>,
+
+    /// Profile to store the snapshots as, replaced if it exists
+    /// (default: the scenario's name, or "default")
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    /// Link the pulled profiles into a scenario, to seed them together
+    #[arg(long, value_name = "NAME")]
+    pub scenario: Option<String>,
+
+    /// Pull at most this many records per resource
+    #[arg(long)]
+    pub limit: Option<usize>,
+
+    /// Start over instead of resuming an interrupted pull
+    #[arg(long)]
+    pub restart: bool,
+
+    /// Make at most this many API requests per second (overrides the connector's rate_limit)
+    #[arg(long, value_name = "PER_SECOND")]
+    pub rate_limit: Option<f64>,
+
+    /// Stop after this many API requests (overrides the connector's max_requests)
+    #[arg(long, value_name = "N")]
+    pub max_requests: Option<u64>,
+}
+
+#[derive(Parser)]
+pub struct SnapshotRefreshArgs {
+    /// Service whose profiles to refresh
+    pub service: String,
+
+    /// Only refresh this profile (default: every pulled profile)
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    /// Make at most this many API requests per second (overrides the connector's rate_limit)
+    #[arg(long, value_name = "PER_SECOND")]
+    pub rate_limit: Option<f64>,
+
+    /// Stop after this many API requests (overrides the connector's max_requests)
+    #[arg(long, value_name = "N")]
+    pub max_requests: Option<u64>,
+}
+
+#[derive(Parser)]
+pub struct SnapshotListArgs {
+    /// Only list profiles of this service
+    pub service: Option<String>,
+}
+
+#[derive(Parser)]
+pub struct SnapshotInspectArgs {
+    /// Service the profile belongs to
     pub service: String,
 
-    /// Path to seed data file (YAML or JSON)
+    /// Profile to inspect
+    #[arg(long, default_value = doubleagent_core::snapshot::DEFAULT_PROFILE)]
+    pub profile: String,
+
+    /// Show the profile's resources and record counts instead of its manifest
+    #[arg(long)]
+    pub data: bool,
+
+    /// Page through the records of this resource (implies --data)
+    #[arg(long)]
+    pub resource: Option<String>,
+
+    /// Only show records matching a jq-style filter, e.g. '.state == "open" and .comments > 3'
+    #[arg(long, requires = "resource")]
+    pub filter: Option<String>,
+
+    /// Page of records to show, from 1
+    #[arg(long, default_value_t = 1)]
+    pub page: usize,
+
+    /// Records per page
+    #[arg(long, default_value_t = 20)]
+    pub page_size: usize,
+}
+
+#[derive(Parser)]
+pub struct SnapshotTransferArgs {
+    /// Service the profile belongs to
+    pub service: String,
+
+    /// Profile to transfer (replaces the copy at the destination)
+    #[arg(long, default_value = doubleagent_core::snapshot::DEFAULT_PROFILE)]
+    pub profile: String,
+
+    /// Registry to use (overrides the registry setting)
+    #[arg(long, value_name = "URL")]
+    pub registry: Option<String>,
+}
+
+#[derive(Parser)]
+pub struct SnapshotImportArgs {
+    /// Service the data belongs to
+    pub service: String,
+
+    /// Seed payload file (YAML or JSON)
     pub file: String,
+
+    /// Profile to store the data as (replaced if it exists)
+    #[arg(long, default_value = doubleagent_core::snapshot::DEFAULT_PROFILE)]
+    pub profile: String,
+}
+
+#[derive(Parser)]
+pub struct SnapshotRecordArgs {
+    /// Service the traffic belongs to
+    pub service: String,
+
+    /// Base URL of the real API to forward requests to
+    #[arg(long)]
+    pub target: String,
+
+    /// Local port for the proxy
+    #[arg(long, default_value = "8899")]
+    pub port: u16,
+
+    /// Profile to store the recording as (replaced if it exists)
+    #[arg(long, default_value = doubleagent_core::snapshot::DEFAULT_PROFILE)]
+    pub profile: String,
+}
+
+#[derive(Parser)]
+pub struct SnapshotMigrateArgs {
+    /// Service the profile belongs to
+    pub service: String,
+
+    /// Profile to migrate
+    #[arg(long, default_value = doubleagent_core::snapshot::DEFAULT_PROFILE)]
+    pub profile: String,
+}
+
+#[derive(Parser)]
+pub struct SnapshotMergeArgs {
+    /// Service the profiles belong to
+    pub service: String,
+
+    /// Profiles to merge, in order
+    #[arg(required = true, num_args = 2..)]
+    pub profiles: Vec<String>,
+
+    /// Profile to store the result as (replaced if it exists)
+    #[arg(long)]
+    pub into: String,
+
+    /// Which record to keep when profiles disagree
+    #[arg(long, value_enum, default_value_t = OnConflict::Last)]
+    pub on_conflict: OnConflict,
+
+    /// Field records are matched by
+    #[arg(long, default_value = doubleagent_core::snapshot::diff::DEFAULT_KEY)]
+    pub key: String,
+}
+
+#[derive(Parser)]
+pub struct SnapshotSampleArgs {
+    /// Service the profile belongs to
+    pub service: String,
+
+    /// Profile to sample
+    #[arg(long, default_value = doubleagent_core::snapshot::DEFAULT_PROFILE)]
+    pub profile: String,
+
+    /// Profile to store the sample as (replaced if it exists)
+    #[arg(long)]
+    pub out: String,
+
+    /// Share of each resource's records to keep, e.g. 0.05
+    #[arg(long)]
+    pub fraction: f64,
+
+    /// Keep records referring to sampled ones and everything they refer to
+    #[arg(long)]
+    pub keep_referenced: bool,
+
+    /// Field records are identified by
+    #[arg(long, default_value = doubleagent_core::snapshot::diff::DEFAULT_KEY)]
+    pub key: String,
+
+    /// A reference besides `<resource>_id` fields, e.g. issues.repo=repos.full_name
+    #[arg(long = "reference", value_name = "FROM=TO")]
+    pub references: Vec<String>,
+}
+
+/// How `snapshot merge` settles records that differ between profiles
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnConflict {
+    /// Keep the record from the profile listed first
+    First,
+    /// Keep the record from the profile listed last
+    Last,
+    /// Stop without writing anything
+    Fail,
 }
 
 #[derive(Parser)]
+pub struct SnapshotScanArgs {
+    /// Service the profile belongs to
+    pub service: String,
+
+    /// Profile to scan
+    #[arg(long, default_value = doubleagent_core::snapshot::DEFAULT_PROFILE)]
+    pub profile: String,
+}
+
+#[derive(Parser)]
+pub struct SnapshotValidateArgs {
+    /// Service the profile belongs to
+    pub service: String,
+
+    /// Profile to validate
+    #[arg(long, default_value = doubleagent_core::snapshot::DEFAULT_PROFILE)]
+    pub profile: String,
+}
+
+#[derive(Parser)]
+pub struct SnapshotRedactArgs {
+    /// Service the profile belongs to
+    pub service: String,
+
+    /// Profile to redact
+    #[arg(long, default_value = doubleagent_core::snapshot::DEFAULT_PROFILE)]
+    pub profile: String,
+}
+
+#[derive(Parser)]
+pub struct SnapshotDiffArgs {
+    /// Service the profiles belong to
+    pub service: String,
+
+    /// Older profile
+    #[arg(long)]
+    pub from: String,
+
+    /// Newer profile
+    #[arg(long)]
+    pub to: String,
+
+    /// Field records are matched by
+    #[arg(long, default_value = doubleagent_core::snapshot::diff::DEFAULT_KEY)]
+    pub key: String,
+
+    /// Print the differences as a JSON Patch instead of a summary
+    #[arg(long)]
+    pub patch: bool,
+}
+
+#[derive(Parser)]
+#[command(
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    subcommand_precedence_over_arg = true
+)]
 pub struct ContractArgs {
-    /// Service to test
+    #[command(subcommand)]
+    pub command: Option<ContractCommands>,
+
+    /// Services to test, in parallel if there are several
+    #[arg(required_unless_present = "all", conflicts_with = "all")]
+    pub services: Vec<String>,
+
+    /// Test every installed service that has contracts
+    #[arg(long)]
+    pub all: bool,
+
+    /// What to run the contracts against
+    #[arg(long, value_enum, default_value_t = ContractTarget::Fake)]
+    pub target: ContractTarget,
+
+    /// Write the results, with per-test timing, for CI
+    #[arg(long, value_enum)]
+    pub report: Option<ContractReportFormat>,
+
+    /// Where to write the report (default: contract-results.xml or .json,
+    /// doubleagent-report/report.md or index.html, or $GITHUB_STEP_SUMMARY
+    /// for github)
+    #[arg(long, value_name = "FILE", requires = "report")]
+    pub report_file: Option<String>,
+
+    /// Run only the tests matching this expression (pytest's -k)
+    #[arg(short = 'k', long, value_name = "EXPR")]
+    pub filter: Option<String>,
+
+    /// Run only the tests with this tag, e.g. smoke (repeatable, any may
+    /// match; pytest markers)
+    #[arg(long = "tag", value_name = "TAG")]
+    pub tags: Vec<String>,
+
+    /// Leave out the tests with this tag, e.g. destructive (repeatable)
+    #[arg(long = "skip-tag", value_name = "TAG")]
+    pub skip_tags: Vec<String>,
+
+    /// Run this suite of contracts.suites in service.yaml, with its own
+    /// command, directory and tags
+    #[arg(long, value_name = "NAME")]
+    pub suite: Option<String>,
+
+    /// List the tests, with their tags, without running them (with --filter,
+    /// only those it selects)
+    #[arg(long, conflicts_with_all = [
+        "report", "validate_spec", "coverage", "min_coverage", "compare", "update_golden",
+        "use_running", "compare_to_last", "snapshots", "record", "fail_fast"
+    ])]
+    pub list: bool,
+
+    /// Also check the fake's responses against the service's OpenAPI description
+    #[arg(long)]
+    pub validate_spec: bool,
+
+    /// Report which of the fake's endpoints the tests requested
+    #[arg(long)]
+    pub coverage: bool,
+
+    /// Fail if the tests request less than this share of the fake's endpoints,
+    /// e.g. 80 (implies --coverage)
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percent)]
+    pub min_coverage: Option<f64>,
+
+    /// Test the services already running in this workspace, with their
+    /// current state, instead of starting fresh ones (and leave them running)
+    #[arg(long, conflicts_with = "snapshots")]
+    pub use_running: bool,
+
+    /// Compare each run with the last one recorded in the history, showing
+    /// tests that newly fail and tests that got slower
+    #[arg(long)]
+    pub compare_to_last: bool,
+
+    /// Run the tests once per snapshot profile, seeding the fake with it
+    /// first (repeatable; "empty" runs with no data)
+    #[arg(long = "snapshot", value_name = "PROFILE")]
+    pub snapshots: Vec<String>,
+
+    /// Stop at the first failed test, skipping the remaining targets and
+    /// snapshot profiles
+    #[arg(long)]
+    pub fail_fast: bool,
+
+    /// Where to collect the fake's log, state and request log when tests
+    /// fail against it, in a directory per run
+    #[arg(long, value_name = "DIR", default_value = doubleagent_core::artifacts::DEFAULT_DIR)]
+    pub artifacts_dir: String,
+
+    /// Record the real API's responses to the contract scenarios as golden
+    /// files, instead of running the tests
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "target", "report", "filter", "tags", "skip_tags", "validate_spec", "coverage",
+            "min_coverage", "compare", "use_running", "snapshots", "compare_to_last",
+            "fail_fast"
+        ]
+    )]
+    pub record: bool,
+
+    /// Also compare the fake's responses to the contract scenarios with the
+    /// recorded golden files
+    #[arg(long)]
+    pub compare: bool,
+
+    /// Rewrite the golden files from the fake's responses to the contract
+    /// scenarios, after an intentional change to the fake
+    #[arg(long, conflicts_with_all = ["record", "compare"])]
+    pub update_golden: bool,
+
+    /// Extra arguments for the test command, after --
+    #[arg(last = true)]
+    pub args: Vec<String>,
+}
+
+/// Formats `contract --report` writes
+#[derive(Subcommand)]
+pub enum ContractCommands {
+    /// Show a service's recent contract runs
+    History(ContractHistoryArgs),
+}
+
+#[derive(Parser)]
+pub struct ContractHistoryArgs {
+    /// Service to show the runs of
     pub service: String,
+
+    /// Number of runs to show, most recent last
+    #[arg(short = 'n', long, default_value_t = 10)]
+    pub limit: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ContractReportFormat {
+    /// JUnit XML, one test suite per target
+    Junit,
+    /// A JSON object with the tests of each target
+    Json,
+    /// A Markdown summary, with failures, slowest tests and coverage per
+    /// service, e.g. for a pull request comment
+    Markdown,
+    /// The same as a self-contained HTML page, e.g. for a CI artifact
+    Html,
+    /// GitHub Actions annotations on each failed test's file and line, and
+    /// a job summary table
+    Github,
+}
+
+/// What `contract` runs the tests against
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ContractTarget {
+    /// The fake, started for the run
+    Fake,
+    /// The real API, as configured under contracts.real in service.yaml
+    Real,
+    /// The fake, then the real API
+    Both,
 }
 
 #[derive(Parser)]
 pub struct RunArgs {
     /// Services to start before running the command
-    #[arg(short, long, required = true, num_args = 1..)]
+    #[arg(short, long, required_unless_present = "scenario", num_args = 1..)]
     pub services: Vec<String>,
 
-    /// Base port for services (subsequent services increment)
+    /// Port for the first service (overrides base_port)
     #[arg(short, long)]
     pub port: Option<u16>,
 
@@ -130,7 +1145,317 @@ pub struct RunArgs {
     #[arg(short, long)]
     pub keep: bool,
 
+    /// Tag started services (repeatable, e.g. --tag team=payments)
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+    pub tags: Vec<(String, String)>,
+
+    /// Seed started services with a stored snapshot profile as their baseline
+    #[arg(long, value_name = "PROFILE")]
+    pub snapshot: Option<String>,
+
+    /// Seed started services with their profiles in a scenario (starts every
+    /// service of the scenario when none are given)
+    #[arg(long, value_name = "NAME", conflicts_with = "snapshot")]
+    pub scenario: Option<String>,
+
+    /// Run each started service's smoke contract tests before the command,
+    /// and stop if any fail
+    #[arg(long)]
+    pub verify_contracts: bool,
+
     /// Command to run (everything after --)
     #[arg(last = true, required = true)]
     pub command: Vec<String>,
 }
+
+#[derive(Parser)]
+pub struct ApplyArgs {
+    /// Services to apply changes to (empty = all running)
+    #[arg(conflicts_with = "file")]
+    pub services: Vec<String>,
+
+    /// Environment manifest describing the desired services
+    #[arg(short, long)]
+    pub file: Option<String>,
+
+    /// Print the plan without making changes
+    #[arg(long, requires = "file")]
+    pub dry_run: bool,
+}
+
+#[derive(Parser)]
+pub struct DumpArgs {
+    /// Service to export state from
+    pub service: String,
+
+    /// Write the state to a file: YAML for .yaml/.yml, JSON otherwise (default: stdout)
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<String>,
+
+    /// Store the state as a snapshot profile, redacted like any other
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+}
+
+#[derive(Parser)]
+#[command(
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    subcommand_precedence_over_arg = true
+)]
+pub struct RequestsArgs {
+    #[command(subcommand)]
+    pub command: Option<RequestsCommands>,
+
+    /// Service whose requests to show
+    #[arg(required = true)]
+    pub service: Option<String>,
+
+    /// Only requests with this method, e.g. POST
+    #[arg(long)]
+    pub method: Option<String>,
+
+    /// Only requests whose path matches, with * for any characters, e.g.
+    /// /repos/*/issues
+    #[arg(long, value_name = "PATTERN")]
+    pub path: Option<String>,
+
+    /// Only requests served in the last span of time, e.g. 30s, 5m or 1h, or
+    /// since a time (YYYY-MM-DDTHH:MM:SSZ)
+    #[arg(long, value_name = "WHEN")]
+    pub since: Option<String>,
+
+    /// Keep printing requests as the service serves them
+    #[arg(short, long)]
+    pub follow: bool,
+
+    /// Print the requests as logged, as a JSON array (one object per line
+    /// with --follow)
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Subcommand)]
+pub enum RequestsCommands {
+    /// Check the requests a service served, failing unless all expectations hold
+    Assert(RequestsAssertArgs),
+}
+
+#[derive(Parser)]
+pub struct RequestsAssertArgs {
+    /// Service whose requests to check
+    pub service: String,
+
+    /// Expectations as [METHOD] PATH [STATUS] [OP COUNT], e.g.
+    /// 'POST /repos/*/issues == 1' or 'GET /user 2xx >= 1' (at least one
+    /// without a comparison)
+    #[arg(required = true, value_name = "EXPECTATION")]
+    pub expectations: Vec<String>,
+
+    /// Only count requests served in the last span of time, e.g. 5m, or
+    /// since a time (YYYY-MM-DDTHH:MM:SSZ)
+    #[arg(long, value_name = "WHEN")]
+    pub since: Option<String>,
+}
+
+#[derive(Parser)]
+pub struct UpgradeArgs {
+    /// Service to upgrade
+    pub service: String,
+
+    /// Port to try the new version on before it takes over the service's
+    /// port (default: a free port of port_range)
+    #[arg(short, long)]
+    pub port: Option<u16>,
+
+    /// Start the new version empty instead of migrating the current state
+    #[arg(long)]
+    pub skip_state: bool,
+}
+
+#[derive(Parser)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    pub command: ConfigCommands,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Print the effective value of a key
+    Get(ConfigGetArgs),
+
+    /// Set a key in the global config (or doubleagent.yaml with --project)
+    Set(ConfigSetArgs),
+
+    /// Remove a key from the global config (or doubleagent.yaml with --project)
+    Unset(ConfigUnsetArgs),
+
+    /// Show all effective settings
+    Show(ConfigShowArgs),
+}
+
+#[derive(Parser)]
+pub struct ConfigGetArgs {
+    /// Key to read (e.g. repo_url, branch, base_port, compliance_mode)
+    pub key: String,
+}
+
+#[derive(Parser)]
+pub struct ConfigSetArgs {
+    /// Key to set
+    pub key: String,
+
+    /// Value to set
+    pub value: String,
+
+    /// Write to the project's doubleagent.yaml instead of the global config
+    #[arg(long)]
+    pub project: bool,
+}
+
+#[derive(Parser)]
+pub struct ConfigUnsetArgs {
+    /// Key to remove
+    pub key: String,
+
+    /// Remove from the project's doubleagent.yaml instead of the global config
+    #[arg(long)]
+    pub project: bool,
+}
+
+#[derive(Parser)]
+pub struct ConfigShowArgs {
+    /// Show which layer each effective value came from
+    #[arg(long)]
+    pub origin: bool,
+}
+
+#[derive(Parser)]
+pub struct AuditArgs {
+    #[command(subcommand)]
+    pub command: AuditCommands,
+}
+
+#[derive(Subcommand)]
+pub enum AuditCommands {
+    /// Write the recorded pulls, pushes and seeds of snapshot data
+    Export(AuditExportArgs),
+
+    /// Show who ran the commands that started, stopped or seeded services, and when
+    Show(AuditShowArgs),
+}
+
+#[derive(Parser)]
+pub struct AuditShowArgs {
+    /// Only this command, e.g. start or snapshot
+    #[arg(long)]
+    pub command: Option<String>,
+
+    /// Only commands run by this user
+    #[arg(long)]
+    pub user: Option<String>,
+
+    /// Only commands run in the last span of time, e.g. 30m or 2d, or since a
+    /// time (YYYY-MM-DDTHH:MM:SSZ)
+    #[arg(long, value_name = "WHEN")]
+    pub since: Option<String>,
+
+    /// Only commands that failed
+    #[arg(long)]
+    pub failed: bool,
+
+    /// Number of commands to show, most recent last
+    #[arg(short = 'n', long, default_value_t = 50)]
+    pub limit: usize,
+}
+
+#[derive(Parser)]
+pub struct AuditExportArgs {
+    /// File to write (default: stdout)
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value = "json")]
+    pub format: AuditFormat,
+
+    /// Only entries about this service
+    #[arg(long)]
+    pub service: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AuditFormat {
+    /// A JSON array of entries
+    Json,
+    /// One JSON entry per line, as stored
+    Jsonl,
+    /// Comma-separated values with a header row
+    Csv,
+}
+
+#[derive(Parser)]
+pub struct MigrateConfigArgs {
+    /// Write the migrated config (by default the changes are only shown)
+    #[arg(long)]
+    pub write: bool,
+}
+
+#[derive(Parser)]
+pub struct ValidateArgs {
+    /// Service directories, service.yaml or doubleagent.yaml files
+    /// (default: the project's doubleagent.yaml and the services it lists)
+    pub paths: Vec<String>,
+}
+
+#[derive(Parser)]
+pub struct ValidateSpecArgs {
+    /// Service to check (started for the check if it isn't running)
+    pub service: String,
+
+    /// Replay the requests in this file (METHOD /path [JSON body] per line)
+    /// instead of one generated for each GET operation
+    #[arg(long, value_name = "FILE")]
+    pub requests: Option<String>,
+}
+
+#[derive(Parser)]
+pub struct FuzzArgs {
+    /// Service to fuzz (a fresh instance is started for the run)
+    pub service: String,
+
+    /// Number of requests to generate
+    #[arg(short = 'n', long, default_value_t = 200)]
+    pub requests: usize,
+
+    /// Seed of the generator, to repeat an earlier run (default: random)
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Seconds a response may take before it counts as a hang
+    #[arg(long, default_value_t = 5.0, value_name = "SECS")]
+    pub timeout: f64,
+
+    /// Directory to write a minimized reproduction of each finding to
+    #[arg(long, default_value = "fuzz-findings", value_name = "DIR")]
+    pub out: String,
+}
+
+#[derive(Parser)]
+pub struct DiffProxyArgs {
+    /// Service to compare (started for the session if it isn't running)
+    pub service: String,
+
+    /// Local port for the proxy
+    #[arg(long, default_value = "8898")]
+    pub port: u16,
+
+    /// Body fields to leave out of the comparison, as globs like `**.id`
+    /// (added to `contracts.golden.ignore`)
+    #[arg(long, value_name = "GLOB")]
+    pub ignore: Vec<String>,
+
+    /// Write the divergence report as JSON to this file
+    #[arg(long, value_name = "FILE")]
+    pub report: Option<String>,
+}
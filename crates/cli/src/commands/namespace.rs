@@ -0,0 +1,81 @@
+use super::{NamespaceArgs, NamespaceCommands};
+use colored::Colorize;
+use doubleagent_core::{namespace, Config, ProcessManager};
+
+pub async fn run(config: Config, args: NamespaceArgs) -> anyhow::Result<()> {
+    let mut manager = ProcessManager::load(&config.state_file)?;
+
+    match args.command {
+        NamespaceCommands::Create(args) => {
+            let name = &args.service;
+            let info = manager
+                .get_info(name)
+                .ok_or_else(|| anyhow::anyhow!("{} is not running", name))?;
+            let minted = namespace::mint(args.label.as_deref())?;
+            // Fails early for fakes that would ignore the header
+            namespace::client(info.port, &minted).await?;
+            manager.add_namespace(name, &minted);
+            manager.save(&config.state_file)?;
+
+            if args.quiet {
+                println!("{}", minted);
+                return Ok(());
+            }
+            println!(
+                "{} Created namespace {} in {}",
+                "✓".green(),
+                minted.bold(),
+                name
+            );
+            println!("  Export: {}={}", namespace::ENV_VAR, minted);
+            println!("  Send header: {}: {}", namespace::HEADER, minted);
+        }
+        NamespaceCommands::List(args) => {
+            let services = match &args.service {
+                Some(service) => vec![service.clone()],
+                None => manager.running_services(),
+            };
+            let mut any = false;
+            for service in services {
+                let Some(info) = manager.get_info(&service) else {
+                    println!("{} {} is not running", "⚠".yellow(), service);
+                    continue;
+                };
+                for ns in &info.namespaces {
+                    any = true;
+                    println!("{} {}", service.bold(), ns);
+                }
+            }
+            if !any {
+                println!("No namespaces");
+            }
+        }
+        NamespaceCommands::Delete(args) => {
+            let name = &args.service;
+            let info = manager
+                .get_info(name)
+                .ok_or_else(|| anyhow::anyhow!("{} is not running", name))?;
+            print!(
+                "{} Resetting namespace {} of {}...",
+                "↻".blue(),
+                args.namespace,
+                name
+            );
+            let reset = async {
+                namespace::client(info.port, &args.namespace)
+                    .await?
+                    .reset()
+                    .await
+            };
+            if let Err(e) = reset.await {
+                println!(" {}", "✗".red());
+                return Err(e.into());
+            }
+            println!(" {}", "✓".green());
+            if manager.remove_namespace(name, &args.namespace) {
+                manager.save(&config.state_file)?;
+            }
+        }
+    }
+    Ok(())
+}
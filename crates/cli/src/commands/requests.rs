@@ -0,0 +1,212 @@
+use super::{RequestsArgs, RequestsAssertArgs, RequestsCommands};
+use colored::Colorize;
+use doubleagent_core::coverage::{self, LoggedRequest};
+use doubleagent_core::request_log::{self, Expectation, RequestFilter};
+use doubleagent_core::{dates, Config, ControlClient, ProcessManager};
+use serde_json::Value;
+use std::time::Duration;
+
+/// How often `requests --follow` asks the service for new requests.
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Requests shown under a failed expectation.
+const MATCHES_SHOWN: usize = 10;
+
+pub async fn run(config: Config, args: RequestsArgs) -> anyhow::Result<()> {
+    if let Some(RequestsCommands::Assert(assert_args)) = args.command {
+        return assert(&config, assert_args).await;
+    }
+    let name = args.service.as_deref().unwrap_or_default();
+    let port = port(&config, name)?;
+    let since = parse_since(args.since.as_deref())?;
+    let filter = RequestFilter {
+        method: args.method.clone(),
+        path: args.path.clone(),
+        since,
+    };
+
+    if args.follow {
+        crate::output::text_only("--follow")?;
+    }
+    let client = ControlClient::new(port);
+    let entries = fetch(&client, name).await?;
+    if since.is_some() && entries.iter().any(|(_, request)| request.time.is_none()) {
+        eprintln!(
+            "{} {} doesn't log when it served each request, so --since leaves those out",
+            "⚠".yellow(),
+            name
+        );
+    }
+    let shown: Vec<&(Value, LoggedRequest)> = entries
+        .iter()
+        .filter(|(_, request)| filter.matches(request))
+        .collect();
+
+    if !args.follow {
+        crate::output::result(shown.iter().map(|(entry, _)| entry).collect::<Vec<_>>());
+        if args.json {
+            let logged: Vec<&Value> = shown.iter().map(|(entry, _)| entry).collect();
+            println!("{}", serde_json::to_string_pretty(&logged)?);
+        } else if shown.is_empty() {
+            println!("No matching requests ({} logged)", entries.len());
+        } else {
+            for (_, request) in shown {
+                print_request(request);
+            }
+        }
+        return Ok(());
+    }
+
+    for (entry, request) in shown {
+        print_entry(entry, request, args.json)?;
+    }
+    let mut seen = entries.len();
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+            _ = tokio::time::sleep(FOLLOW_INTERVAL) => {}
+        }
+        let entries = fetch(&client, name).await?;
+        // A shorter log was cleared, so all of it is new
+        if entries.len() < seen {
+            seen = 0;
+        }
+        for (entry, request) in &entries[seen..] {
+            if filter.matches(request) {
+                print_entry(entry, request, args.json)?;
+            }
+        }
+        seen = entries.len();
+    }
+}
+
+/// Check expectations against the requests a service served, failing when
+/// any doesn't hold.
+async fn assert(config: &Config, args: RequestsAssertArgs) -> anyhow::Result<()> {
+    let name = &args.service;
+    let since = parse_since(args.since.as_deref())?;
+    let expectations = args
+        .expectations
+        .iter()
+        .map(|text| {
+            let mut expectation = Expectation::parse(text)?;
+            expectation.filter.since = since;
+            Ok(expectation)
+        })
+        .collect::<doubleagent_core::Result<Vec<_>>>()?;
+
+    let client = ControlClient::new(port(config, name)?);
+    let requests: Vec<LoggedRequest> = fetch(&client, name)
+        .await?
+        .into_iter()
+        .map(|(_, request)| request)
+        .collect();
+
+    let outcomes: Vec<_> = expectations
+        .iter()
+        .map(|expectation| expectation.check(&requests))
+        .collect();
+    crate::output::result(&outcomes);
+    for (expectation, outcome) in expectations.iter().zip(&outcomes) {
+        if outcome.passed {
+            println!(
+                "{} {} {}",
+                "✓".green(),
+                outcome.expectation,
+                format!("({})", outcome.actual).dimmed()
+            );
+            continue;
+        }
+        println!(
+            "{} {} {}",
+            "✗".red(),
+            outcome.expectation,
+            format!("(got {})", outcome.actual).red()
+        );
+        for request in requests
+            .iter()
+            .filter(|r| expectation.matches(r))
+            .take(MATCHES_SHOWN)
+        {
+            print!("    ");
+            print_request(request);
+        }
+        if outcome.actual > MATCHES_SHOWN {
+            println!(
+                "    {}",
+                format!("… {} more", outcome.actual - MATCHES_SHOWN).dimmed()
+            );
+        }
+    }
+
+    let failed = outcomes.iter().filter(|o| !o.passed).count();
+    if failed > 0 {
+        anyhow::bail!(
+            "{} of {} expectations failed against the {} requests {} logged",
+            failed,
+            outcomes.len(),
+            requests.len(),
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Port of a running service
+fn port(config: &Config, name: &str) -> anyhow::Result<u16> {
+    let manager = ProcessManager::load(&config.state_file)?;
+    let info = manager
+        .get_info(name)
+        .ok_or_else(|| anyhow::anyhow!("{} is not running", name))?;
+    Ok(info.port)
+}
+
+fn parse_since(since: Option<&str>) -> anyhow::Result<Option<i64>> {
+    Ok(since
+        .map(|since| request_log::parse_since(since, dates::now()))
+        .transpose()?)
+}
+
+/// The requests a service logged
+async fn fetch(client: &ControlClient, name: &str) -> anyhow::Result<Vec<(Value, LoggedRequest)>> {
+    let log = client.requests_json().await.map_err(|e| {
+        anyhow::anyhow!(
+            "{} doesn't report the requests it served (GET /_doubleagent/{}): {}",
+            name,
+            coverage::REQUESTS_ENDPOINT,
+            e
+        )
+    })?;
+    Ok(request_log::entries(&log)?)
+}
+
+/// A request followed as it comes in, as a line of JSON or of text
+fn print_entry(entry: &Value, request: &LoggedRequest, json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string(entry)?);
+    } else {
+        print_request(request);
+    }
+    Ok(())
+}
+
+/// `HH:MM:SS  METHOD  /path  status`, the time in UTC
+fn print_request(request: &LoggedRequest) {
+    let time = match request.time {
+        Some(time) => dates::format_time(time as i64)[11..19].to_string(),
+        None => "--:--:--".to_string(),
+    };
+    let status = match request.status {
+        Some(status @ 200..=399) => status.to_string().green(),
+        Some(status @ 400..=499) => status.to_string().yellow(),
+        Some(status) => status.to_string().red(),
+        None => "-".dimmed(),
+    };
+    println!(
+        "{}  {:<7} {}  {}",
+        time.dimmed(),
+        request.method,
+        request.path,
+        status
+    );
+}
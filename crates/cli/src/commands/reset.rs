@@ -1,10 +1,19 @@
 use super::ResetArgs;
 use colored::Colorize;
-use doubleagent_core::{Config, ProcessManager};
+use doubleagent_core::manifest::SeedSource;
+use doubleagent_core::snapshot::SnapshotStore;
+use doubleagent_core::{seed, Config, ControlClient, ProcessManager, ServiceDefinition};
+use std::path::Path;
 
-pub async fn run(args: ResetArgs) -> anyhow::Result<()> {
-    let config = Config::load()?;
-    let manager = ProcessManager::load(&config.state_file)?;
+pub async fn run(config: Config, args: ResetArgs) -> anyhow::Result<()> {
+    let mut manager = ProcessManager::load(&config.state_file)?;
+    let namespace = args.namespace();
+    if namespace.is_some() && args.keep_seed {
+        anyhow::bail!(
+            "--keep-seed resets the whole service; unset {} to use it",
+            doubleagent_core::namespace::ENV_VAR
+        );
+    }
 
     let services: Vec<String> = if args.services.is_empty() {
         manager.running_services()
@@ -17,19 +26,47 @@ pub async fn run(args: ResetArgs) -> anyhow::Result<()> {
         return Ok(());
     }
 
+    let store = SnapshotStore::new(&config.data_dir);
     for service_name in &services {
         if let Some(info) = manager.get_info(service_name) {
+            if let Some(namespace) = &namespace {
+                print!(
+                    "{} Resetting {} (namespace {})...",
+                    "↻".blue(),
+                    service_name,
+                    namespace
+                );
+                match reset_namespace(info.port, namespace).await {
+                    Ok(()) => println!(" {}", "✓".green()),
+                    Err(e) => println!(" {} ({})", "✗".red(), e),
+                }
+                continue;
+            }
             print!("{} Resetting {}...", "↻".blue(), service_name);
 
-            let url = format!("http://localhost:{}/_doubleagent/reset", info.port);
-            let client = reqwest::Client::new();
+            let result = if args.keep_seed {
+                match ServiceDefinition::from_dir(Path::new(&info.service_path)) {
+                    Ok(service) => seed::reset_to_seed(&manager, &store, &service).await,
+                    Err(e) => Err(e),
+                }
+            } else {
+                ControlClient::new(info.port).reset().await.map(|_| None)
+            };
 
-            match client.post(&url).send().await {
-                Ok(resp) if resp.status().is_success() => {
-                    println!(" {}", "✓".green());
+            if result.is_ok() {
+                // Nothing seeded before the reset is left to undo
+                manager.clear_seed_history(service_name);
+            }
+            match result {
+                Ok(Some(sources)) => {
+                    println!(
+                        " {} (reseeded from {})",
+                        "✓".green(),
+                        SeedSource::join(&sources)
+                    );
                 }
-                Ok(resp) => {
-                    println!(" {} (status: {})", "✗".red(), resp.status());
+                Ok(None) => {
+                    println!(" {}", "✓".green());
                 }
                 Err(e) => {
                     println!(" {} ({})", "✗".red(), e);
@@ -39,6 +76,14 @@ pub async fn run(args: ResetArgs) -> anyhow::Result<()> {
             println!("{} {} is not running", "⚠".yellow(), service_name);
         }
     }
+    manager.save(&config.state_file)?;
 
     Ok(())
 }
+
+async fn reset_namespace(port: u16, namespace: &str) -> doubleagent_core::Result<()> {
+    doubleagent_core::namespace::client(port, namespace)
+        .await?
+        .reset()
+        .await
+}
@@ -1,29 +1,71 @@
 use super::RunArgs;
 use colored::Colorize;
+use doubleagent_core::manifest::SeedSource;
+use doubleagent_core::ports::PortAllocator;
+use doubleagent_core::snapshot::SnapshotStore;
+use doubleagent_core::tags::Tags;
+use doubleagent_core::{seed, workspace};
 use doubleagent_core::{Config, ProcessManager, ServiceRegistry};
 use std::collections::HashMap;
 use std::process::Command;
 
+/// The wrapped command exited unsuccessfully; `doubleagent` exits with its code
+#[derive(Debug)]
+pub(crate) struct CommandFailed {
+    pub(crate) command: String,
+    pub(crate) code: i32,
+}
+
+impl std::fmt::Display for CommandFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "'{}' exited with code {}", self.command, self.code)
+    }
+}
+
+impl std::error::Error for CommandFailed {}
+
 /// Collected service info for env var generation
 struct StartedService {
     name: String,
     url: String,
 }
 
-pub async fn run(args: RunArgs) -> anyhow::Result<()> {
-    let config = Config::load()?;
+pub async fn run(config: Config, args: RunArgs) -> anyhow::Result<()> {
     let mut manager = ProcessManager::load(&config.state_file)?;
     let registry = ServiceRegistry::new(&config.services_dir, &config.repo_url, &config.branch)?;
 
-    let base_port = args.port.unwrap_or(8080);
+    // An explicit --port is taken as is for the first service started
+    let mut explicit_port = args.port;
+    if let Some(port) = explicit_port {
+        config.port_range.check(port)?;
+    }
+    // Skip ports taken by services in any workspace
+    let mut ports = PortAllocator::new(
+        config.port_range,
+        config.base_port,
+        workspace::ports_in_use(&config.state_dir)?,
+    );
+    let tags: Tags = args.tags.into_iter().collect();
+    let store = SnapshotStore::new(&config.data_dir);
+    let defaults = super::start::project_services(&config)?;
+    let (scenario, services) =
+        super::start::scenario_services(&store, &args.scenario, args.services)?;
     let mut started_services: Vec<StartedService> = Vec::new();
 
+    // Refuse snapshot seeds the compliance mode forbids before starting anything
+    for service_name in &services {
+        for source in
+            super::start::seed_sources(&args.snapshot, scenario.as_ref(), &defaults, service_name)
+        {
+            super::compliance::check_seed(&config, &store, service_name, &source)?;
+        }
+    }
+
     // Start all requested services
     println!("{} Starting services...", "▶".blue());
 
-    for (i, service_name) in args.services.iter().enumerate() {
+    for service_name in &services {
         let service = registry.get_or_install(service_name, true)?;
-        let port = base_port + i as u16;
 
         if manager.is_running(service_name) {
             // Already running, get existing port
@@ -38,16 +80,81 @@ pub async fn run(args: RunArgs) -> anyhow::Result<()> {
                     service_name,
                     info.port
                 );
+                if args.verify_contracts {
+                    // The tests would reset the state it is running with
+                    println!(
+                        "  {} {} not verified, as it was already running",
+                        "⚠".yellow(),
+                        service_name
+                    );
+                }
             }
             continue;
         }
 
-        let pid = manager.start(&service, port).await?;
+        let port = match explicit_port.take() {
+            Some(port) => ports.claim(port)?,
+            None => ports.allocate_free()?,
+        };
+        let pid = manager
+            .start_with(
+                &service,
+                port,
+                super::start::start_options(&config, service_name),
+            )
+            .await?;
 
         print!("  {} waiting for health check...", service_name);
-        match manager.wait_for_health(service_name, port, 30).await {
+        match manager
+            .wait_for_health(service_name, port, &config.health_for(service_name))
+            .await
+        {
             Ok(_) => {
                 println!(" {}", "✓".green());
+                super::start::trace(&config, service_name, port).await;
+                // Before seeding, as the tests may reset the fake
+                if args.verify_contracts && service.contracts.is_none() {
+                    println!(
+                        "  {} {} has no contract tests to verify",
+                        "⚠".yellow(),
+                        service_name
+                    );
+                } else if args.verify_contracts {
+                    print!("  {} running smoke contract tests...", service_name);
+                    if let Err(e) = super::contract::verify(&service, port) {
+                        println!(" {}", "✗".red());
+                        let _ = manager.stop(service_name).await;
+                        cleanup_services(&mut manager, &started_services, &config).await;
+                        return Err(e);
+                    }
+                    println!(" {}", "✓".green());
+                }
+                let sources = super::start::seed_sources(
+                    &args.snapshot,
+                    scenario.as_ref(),
+                    &defaults,
+                    service_name,
+                );
+                if !sources.is_empty() {
+                    print!(
+                        "  {} seeding from {}...",
+                        service_name,
+                        SeedSource::join(&sources)
+                    );
+                    if let Err(e) =
+                        seed::seed_started(&mut manager, &store, &service, &sources).await
+                    {
+                        println!(" {}", "✗".red());
+                        // The failed service was already stopped
+                        cleanup_services(&mut manager, &started_services, &config).await;
+                        return Err(e.into());
+                    }
+                    println!(" {}", "✓".green());
+                    for source in &sources {
+                        super::audit::record_seed(&config, &store, service_name, source, port)?;
+                    }
+                }
+                manager.set_tags(service_name, tags.clone());
                 started_services.push(StartedService {
                     name: service_name.clone(),
                     url: format!("http://localhost:{}", port),
@@ -111,15 +218,15 @@ pub async fn run(args: RunArgs) -> anyhow::Result<()> {
         );
     }
 
-    // Handle command result
+    // Handle command result; main exits with the command's code once the
+    // run is recorded
     match status {
-        Ok(exit_status) => {
-            if exit_status.success() {
-                Ok(())
-            } else {
-                std::process::exit(exit_status.code().unwrap_or(1));
-            }
+        Ok(exit_status) if exit_status.success() => Ok(()),
+        Ok(exit_status) => Err(CommandFailed {
+            command: args.command.join(" "),
+            code: exit_status.code().unwrap_or(1),
         }
+        .into()),
         Err(e) => Err(anyhow::anyhow!("Failed to execute command: {}", e)),
     }
 }
@@ -1,50 +1,429 @@
 use super::SeedArgs;
 use colored::Colorize;
-use doubleagent_core::{Config, ProcessManager};
-use std::fs;
+use doubleagent_core::generate::{self, Generators};
+use doubleagent_core::manifest::SeedSource;
+use doubleagent_core::snapshot::{schema, SnapshotStore};
+use doubleagent_core::{dates, namespace, openapi, references, seed, validate};
+use doubleagent_core::{Config, ControlClient, Error, ProcessManager, SeedRecord};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
 
-pub async fn run(args: SeedArgs) -> anyhow::Result<()> {
-    let config = Config::load()?;
-    let manager = ProcessManager::load(&config.state_file)?;
+pub async fn run(config: Config, args: SeedArgs) -> anyhow::Result<()> {
+    let mut manager = ProcessManager::load(&config.state_file)?;
+    let namespace = args.namespace();
+    if let Some(file) = &args.combined {
+        if namespace.is_some() {
+            anyhow::bail!(
+                "--combined seeds each service's default namespace; unset {} to use it",
+                namespace::ENV_VAR
+            );
+        }
+        return seed_combined(&config, &mut manager, Path::new(file), &args).await;
+    }
+    let service_name = args.service.as_deref().unwrap_or_default();
+    if args.undo {
+        if namespace.is_some() {
+            anyhow::bail!(
+                "Seeds into a namespace aren't recorded, so they can't be undone; \
+                 reset the namespace instead"
+            );
+        }
+        return undo(&config, &mut manager, service_name).await;
+    }
 
-    let info = manager
-        .get_info(&args.service)
-        .ok_or_else(|| anyhow::anyhow!("{} is not running", args.service))?;
+    let port = match manager.get_info(service_name) {
+        Some(info) => info.port,
+        None if args.dry_run => 0,
+        None => anyhow::bail!("{} is not running", service_name),
+    };
 
-    // Read and parse seed file
-    let content = fs::read_to_string(&args.file)?;
-    let data: serde_json::Value = if args.file.ends_with(".yaml") || args.file.ends_with(".yml") {
-        serde_yaml::from_str(&content)?
-    } else {
-        serde_json::from_str(&content)?
+    // Read and parse seed file, or the stored snapshot
+    let store = SnapshotStore::new(&config.data_dir);
+    let data = match (&args.file, &args.snapshot) {
+        _ if args.fixture.is_some() => {
+            let service = super::snapshot::service_for(&config, service_name)?;
+            seed::load_fixtures(&service, args.fixture.as_deref().unwrap_or_default())?
+        }
+        _ if args.openapi => {
+            let service = super::snapshot::service_for(&config, service_name)?;
+            openapi::seed(&service)?
+        }
+        _ if args.generate.is_some() => {
+            let service = super::snapshot::service_for(&config, service_name)?;
+            let counts = generate::parse_counts(args.generate.as_deref().unwrap_or_default())?;
+            Generators::load(&service)?.generate(&counts, args.random_seed)?
+        }
+        (_, Some(profile)) => {
+            let service = super::snapshot::service_for(&config, service_name)?;
+            let source = SeedSource::Snapshot(profile.clone());
+            super::compliance::check_seed(&config, &store, service_name, &source)?;
+            store.load_seed(&service, profile)?
+        }
+        (Some(file), None) => load_file(Path::new(file), &args)?,
+        (None, None) => unreachable!("clap requires a file or another source"),
     };
 
-    print!("{} Seeding {}...", "⬆".blue(), args.service);
+    if args.dry_run {
+        let file = match args.file.as_deref() {
+            Some(seed::STDIN) => "<stdin>",
+            Some(file) => file,
+            None => "<payload>",
+        };
+        return dry_run(&config, service_name, Path::new(file), &data);
+    }
 
-    let url = format!("http://localhost:{}/_doubleagent/seed", info.port);
-    let client = reqwest::Client::new();
+    let client = match &namespace {
+        Some(namespace) => {
+            print!(
+                "{} Seeding {} (namespace {})...",
+                "⬆".blue(),
+                service_name,
+                namespace
+            );
+            namespace::client(port, namespace).await
+        }
+        None => {
+            print!("{} Seeding {}...", "⬆".blue(), service_name);
+            Ok(ControlClient::new(port))
+        }
+    };
+    let client = match client {
+        Ok(client) => client.with_retry(args.retry()),
+        Err(e) => {
+            println!(" {}", "✗".red());
+            return Err(e.into());
+        }
+    };
+    // Seeds into a namespace leave the service's own history alone
+    let prior = match namespace {
+        Some(_) => None,
+        None => Some(client.export().await),
+    };
+    let report = match client.seed_report(&data, args.mode()).await {
+        Ok(report) => report,
+        Err(e) => {
+            println!(" {}", "✗".red());
+            return Err(e.into());
+        }
+    };
+    crate::output::result(serde_json::json!({
+        "service": service_name,
+        "namespace": namespace,
+        "report": report,
+    }));
+    if let Some(path) = &args.report {
+        std::fs::write(path, serde_json::to_string_pretty(&report)? + "\n")
+            .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", path, e))?;
+    }
 
-    match client.post(&url).json(&data).send().await {
-        Ok(resp) if resp.status().is_success() => {
-            let result: serde_json::Value = resp.json().await?;
-            println!(" {}", "✓".green());
+    let applied = report.failed.is_none() || !report.applied.is_empty();
+    if let Some(prior) = prior.filter(|_| applied) {
+        let mut counts = seed::record_counts(&data);
+        if report.failed.is_some() {
+            counts.retain(|resource, _| report.applied.contains(resource));
+        }
+        manager.record_seed(
+            service_name,
+            SeedRecord::new(describe(&args)?, counts, prior),
+        );
+        manager.save(&config.state_file)?;
+    }
+
+    if let Some(failure) = &report.failed {
+        println!(" {}", "✗".red());
+        println!("  {}", failure.error);
+        if !report.applied.is_empty() {
+            println!("  {} Applied: {}", "→".dimmed(), report.applied.join(", "));
+        }
+        println!(
+            "  {} Failed: {}",
+            "→".dimmed(),
+            failure.resources.join(", ")
+        );
+        if !report.pending.is_empty() {
+            println!("  {} Not sent: {}", "→".dimmed(), report.pending.join(", "));
+        }
+        if !report.applied.is_empty() && namespace.is_none() {
+            println!(
+                "  {} {} is partly seeded; seed --undo reverts it, --replace starts over",
+                "ℹ".blue(),
+                service_name
+            );
+        }
+        anyhow::bail!("Seeding {} failed", service_name);
+    }
 
-            if let Some(seeded) = result.get("seeded") {
-                println!("  Seeded: {}", serde_json::to_string(seeded)?);
+    println!(" {}", "✓".green());
+    if let Some(seeded) = report.response.get("seeded") {
+        println!("  Seeded: {}", serde_json::to_string(seeded)?);
+    }
+    if let Some(profile) = &args.snapshot {
+        let source = SeedSource::Snapshot(profile.clone());
+        super::audit::record_seed(&config, &store, service_name, &source, port)?;
+    }
+    if args.replace && namespace.is_none() {
+        let baseline = baseline(&args)?;
+        if baseline.is_none() {
+            println!(
+                "  {} This seed can't be reapplied, so reset --keep-seed will reset {} to empty",
+                "ℹ".blue(),
+                service_name
+            );
+        }
+        manager.set_seed(service_name, baseline.map(|source| source.to_string()));
+        manager.save(&config.state_file)?;
+    }
+    Ok(())
+}
+
+/// Check a payload against the service's seed schema and show what seeding
+/// it would create, without sending it.
+fn dry_run(config: &Config, service_name: &str, file: &Path, data: &Value) -> anyhow::Result<()> {
+    let service = super::snapshot::service_for(config, service_name)?;
+    // Checked as seeded, with relative times resolved
+    let mut data = data.clone();
+    dates::resolve_relative(&mut data, dates::now())?;
+    let data = &data;
+    println!(
+        "{} Dry run for {} (nothing is sent)",
+        "ℹ".blue(),
+        service_name.bold()
+    );
+
+    let Value::Object(resources) = data else {
+        anyhow::bail!(
+            "{}: a seed payload must be a mapping of resources",
+            file.display()
+        );
+    };
+    let mut total = 0;
+    for (resource, records) in resources {
+        match records {
+            Value::Array(records) => {
+                total += records.len();
+                println!("  {} {} {}", "→".dimmed(), resource, records.len());
             }
+            _ => println!(
+                "  {} {} {}",
+                "→".dimmed(),
+                resource,
+                "(not a list)".dimmed()
+            ),
         }
-        Ok(resp) => {
-            let status = resp.status();
-            let body = resp.text().await.unwrap_or_default();
-            println!(" {} (status: {})", "✗".red(), status);
-            if !body.is_empty() {
-                println!("  {}", body);
+    }
+    if let Some(stages) = references::seed_order(data)? {
+        let order: Vec<String> = stages.iter().map(|stage| stage.join(", ")).collect();
+        println!("  {} Seeded in order: {}", "→".dimmed(), order.join(" → "));
+    }
+
+    // References only hold the right type once the fake has seeded them
+    let refs = references::pointers(data);
+    let checked = schema::check(&service, file, data)?.map(|mut diagnostics| {
+        diagnostics.retain(|d| {
+            !refs
+                .iter()
+                .any(|r| d.pointer == *r || d.pointer.starts_with(&format!("{}/", r)))
+        });
+        diagnostics
+    });
+    match checked {
+        None => println!(
+            "{} {} ships no {}, so the payload can't be checked",
+            "⚠".yellow(),
+            service_name.bold(),
+            schema::SEED_SCHEMA_FILE
+        ),
+        Some(diagnostics) if diagnostics.is_empty() => {
+            println!("{} Matches the service's seed schema", "✓".green())
+        }
+        Some(diagnostics) => {
+            println!(
+                "{} Does not match the service's seed schema:",
+                if validate::has_errors(&diagnostics) {
+                    "✗".red()
+                } else {
+                    "⚠".yellow()
+                }
+            );
+            super::snapshot::print_diagnostics(&diagnostics);
+            if validate::has_errors(&diagnostics) {
+                println!();
+                anyhow::bail!(
+                    "{} failed validation with {} error(s)",
+                    file.display(),
+                    diagnostics.len()
+                );
             }
         }
+    }
+    println!(
+        "{} Would seed {} records into {}",
+        "✓".green(),
+        total,
+        service_name
+    );
+    Ok(())
+}
+
+/// Restore the state a running service had before its last seed.
+async fn undo(
+    config: &Config,
+    manager: &mut ProcessManager,
+    service_name: &str,
+) -> anyhow::Result<()> {
+    print!("{} Undoing last seed of {}...", "↻".blue(), service_name);
+    match seed::undo_seed(manager, service_name).await {
+        Ok(record) => {
+            println!(" {}", "✓".green());
+            println!("  {} Undid {}", "→".dimmed(), record.source);
+            manager.save(&config.state_file)?;
+            Ok(())
+        }
         Err(e) => {
-            println!(" {} ({})", "✗".red(), e);
+            println!(" {}", "✗".red());
+            Err(e.into())
+        }
+    }
+}
+
+/// How a seed is described in the service's history.
+fn describe(args: &SeedArgs) -> anyhow::Result<String> {
+    if let Some(source) = baseline(args)? {
+        return Ok(source.to_string());
+    }
+    Ok(match (&args.generate, &args.file) {
+        (Some(counts), _) if counts.is_empty() => "generate".to_string(),
+        (Some(counts), _) => format!("generate:{}", counts),
+        _ if args.openapi => "openapi".to_string(),
+        (None, Some(file)) if file == seed::STDIN => "stdin".to_string(),
+        (None, Some(file)) => format!("file:{}", file),
+        (None, None) => "payload".to_string(),
+    })
+}
+
+/// The source `reset --keep-seed` can load the seed from again, if any:
+/// generated data, rendered templates, remapped rows and stdin aren't recorded.
+fn baseline(args: &SeedArgs) -> anyhow::Result<Option<SeedSource>> {
+    if let Some(profile) = &args.snapshot {
+        return Ok(Some(SeedSource::Snapshot(profile.clone())));
+    }
+    if let Some(names) = &args.fixture {
+        return Ok(Some(SeedSource::Fixture(names.clone())));
+    }
+    match &args.file {
+        Some(file)
+            if file != seed::STDIN
+                && args.generate.is_none()
+                && !args.template
+                && args.vars.is_empty()
+                && args.resource.is_none()
+                && args.columns.is_empty() =>
+        {
+            let path = std::fs::canonicalize(file)
+                .map_err(|e| anyhow::anyhow!("Failed to resolve {}: {}", file, e))?;
+            Ok(Some(SeedSource::File(path)))
         }
+        _ => Ok(None),
     }
+}
+
+/// Read a seed file, taking its rows as records of --resource and rendering
+/// it as a template if asked to.
+fn load_file(path: &Path, args: &SeedArgs) -> anyhow::Result<Value> {
+    if args.resource.is_some() || !args.columns.is_empty() {
+        let columns = args.columns.iter().cloned().collect();
+        let mut seed = seed::load_rows(path, args.resource.as_deref(), &columns)?;
+        if args.template || !args.vars.is_empty() {
+            let vars = args.vars.iter().cloned().collect();
+            doubleagent_core::template::render(&mut seed, &vars, path)?;
+        }
+        return Ok(seed);
+    }
+    if args.template || !args.vars.is_empty() {
+        Ok(seed::load_template(
+            path,
+            &args.vars.iter().cloned().collect(),
+        )?)
+    } else {
+        Ok(seed::load_file(path)?)
+    }
+}
 
+/// Seed every service named in a combined file, reporting each.
+async fn seed_combined(
+    config: &Config,
+    manager: &mut ProcessManager,
+    path: &Path,
+    args: &SeedArgs,
+) -> anyhow::Result<()> {
+    let payloads = seed::split_combined(load_file(path, args)?, path)?;
+    if args.dry_run {
+        let mut failed = 0;
+        for (service, payload) in &payloads {
+            if let Err(e) = dry_run(config, service, path, payload) {
+                failed += 1;
+                println!("{} {}: {}", "✗".red(), service.bold(), e);
+            }
+        }
+        if failed > 0 {
+            anyhow::bail!(
+                "{} of {} services failed validation",
+                failed,
+                payloads.len()
+            );
+        }
+        return Ok(());
+    }
+    println!(
+        "{} Seeding {} services from {}",
+        "⬆".blue(),
+        payloads.len(),
+        path.display()
+    );
+
+    let mut priors = BTreeMap::new();
+    for service in payloads.keys() {
+        if let Some(info) = manager.get_info(service) {
+            priors.insert(service, ControlClient::new(info.port).export().await);
+        }
+    }
+    let results = seed::seed_combined(manager, &payloads, args.mode(), args.retry()).await?;
+    let mut failed = 0;
+    for (service, result) in &results {
+        match result {
+            Ok(response) => {
+                let record = SeedRecord::new(
+                    format!("combined:{}", path.display()),
+                    seed::record_counts(&payloads[service]),
+                    priors.remove(service).unwrap_or_else(|| {
+                        Err(Error::Other(format!("{} wasn't running", service)))
+                    }),
+                );
+                manager.record_seed(service, record);
+                if args.replace {
+                    // A part of a combined file can't be reapplied on its own
+                    manager.set_seed(service, None);
+                }
+                let seeded = response
+                    .get("seeded")
+                    .map(|seeded| serde_json::to_string(seeded).unwrap_or_default())
+                    .unwrap_or_default();
+                println!("  {} {} {}", "✓".green(), service.bold(), seeded.dimmed());
+            }
+            Err(e) => {
+                failed += 1;
+                println!("  {} {}: {}", "✗".red(), service.bold(), e);
+            }
+        }
+    }
+    manager.save(&config.state_file)?;
+    if failed > 0 {
+        anyhow::bail!(
+            "Seeding failed for {} of {} services",
+            failed,
+            results.len()
+        );
+    }
     Ok(())
 }
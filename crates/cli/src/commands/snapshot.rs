@@ -0,0 +1,1022 @@
+use super::{
+    compliance, OnConflict, SnapshotArgs, SnapshotCommands, SnapshotDiffArgs, SnapshotImportArgs,
+    SnapshotInspectArgs, SnapshotListArgs, SnapshotMergeArgs, SnapshotMigrateArgs,
+    SnapshotPullArgs, SnapshotRecordArgs, SnapshotRedactArgs, SnapshotRefreshArgs,
+    SnapshotSampleArgs, SnapshotScanArgs, SnapshotTransferArgs, SnapshotValidateArgs,
+};
+use colored::Colorize;
+use doubleagent_core::audit::Operation;
+use doubleagent_core::compliance::ComplianceMode;
+use doubleagent_core::doctor::human_size;
+use doubleagent_core::manifest::Manifest;
+use doubleagent_core::redact::{self, Redactor};
+use doubleagent_core::secrets::SecretResolver;
+use doubleagent_core::snapshot::{
+    self, inspect, schema, ConflictPolicy, ProgressEvent, Reference, Registry, RequestLimits,
+    SampleOptions, SnapshotManifest, SnapshotStore,
+};
+use doubleagent_core::validate::{self, Diagnostic, Severity};
+use doubleagent_core::{seed, Config, ServiceDefinition, ServiceRegistry};
+use serde_json::{json, Value};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub async fn run(config: Config, args: SnapshotArgs) -> anyhow::Result<()> {
+    let store = SnapshotStore::new(&config.data_dir);
+
+    match args.command {
+        SnapshotCommands::Pull(args) => pull(&config, &store, args).await,
+        SnapshotCommands::Refresh(args) => refresh(&config, &store, args).await,
+        SnapshotCommands::List(args) => list(&store, args),
+        SnapshotCommands::Inspect(args) => inspect(&store, args),
+        SnapshotCommands::Diff(args) => diff(&store, args),
+        SnapshotCommands::Fetch(args) => fetch(&config, &store, args).await,
+        SnapshotCommands::Push(args) => push(&config, &store, args).await,
+        SnapshotCommands::Import(args) => import(&config, &store, args),
+        SnapshotCommands::Record(args) => record(&config, &store, args).await,
+        SnapshotCommands::Redact(args) => redact(&config, &store, args),
+        SnapshotCommands::Migrate(args) => migrate(&config, &store, args),
+        SnapshotCommands::Merge(args) => merge(&store, args),
+        SnapshotCommands::Sample(args) => sample(&store, args),
+        SnapshotCommands::Scan(args) => scan(&config, &store, args),
+        SnapshotCommands::Validate(args) => validate(&config, &store, args),
+    }
+}
+
+async fn pull(
+    config: &Config,
+    store: &SnapshotStore,
+    args: SnapshotPullArgs,
+) -> anyhow::Result<()> {
+    let profile = args
+        .profile
+        .or_else(|| args.scenario.clone())
+        .unwrap_or_else(|| snapshot::DEFAULT_PROFILE.to_string());
+    let limits = RequestLimits {
+        rate_limit: args.rate_limit,
+        max_requests: args.max_requests,
+    };
+    let mut scenario = match &args.scenario {
+        Some(name) => Some(store.scenario_or_new(name)?),
+        None => None,
+    };
+
+    let mut pulled = Vec::new();
+    for service in &args.services {
+        let manifest = pull_one(
+            config,
+            store,
+            service,
+            &profile,
+            args.limit,
+            limits,
+            args.restart,
+        )
+        .await?;
+        if let Some(scenario) = &mut scenario {
+            // Link as we go, so a failed pull keeps the profiles pulled before it
+            scenario.link(&manifest.service, &manifest.profile);
+            store.save_scenario(scenario)?;
+        }
+        pulled.push(manifest);
+    }
+    crate::output::result(json!({ "profiles": pulled, "scenario": scenario }));
+
+    if let Some(scenario) = &scenario {
+        println!(
+            "{} Scenario {} links {}",
+            "✓".green(),
+            scenario.name.bold(),
+            scenario
+                .profiles
+                .iter()
+                .map(|(service, profile)| format!("{}/{}", service, profile))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(())
+}
+
+async fn pull_one(
+    config: &Config,
+    store: &SnapshotStore,
+    name: &str,
+    profile: &str,
+    limit: Option<usize>,
+    limits: RequestLimits,
+    restart: bool,
+) -> anyhow::Result<SnapshotManifest> {
+    let service = service_for(config, name)?;
+    let action = format!("pull {}/{}", name, profile);
+    compliance::check_service(config, name, &action)?;
+
+    println!(
+        "{} Pulling {} snapshot {}",
+        "▶".blue(),
+        name.bold(),
+        format!("(profile: {})", profile).dimmed()
+    );
+
+    if restart {
+        store.discard_partial(name, profile)?;
+    }
+    let secrets = SecretResolver::new(config.secrets_dir());
+    let redactor = redactor_for(config, &service)?;
+    let progress = PullProgress::new();
+    let manifest = snapshot::pull(
+        &service,
+        profile,
+        store,
+        &secrets,
+        &redactor,
+        limit,
+        limits,
+        &|event| progress.show(event),
+        &compliance::check_staged(config, &action)?,
+    )
+    .await?;
+
+    print_stored(store, &manifest);
+    super::audit::record(
+        config,
+        Operation::Pull,
+        &manifest,
+        &store
+            .profile_dir(&manifest.service, &manifest.profile)
+            .display()
+            .to_string(),
+    )?;
+    Ok(manifest)
+}
+
+async fn refresh(
+    config: &Config,
+    store: &SnapshotStore,
+    args: SnapshotRefreshArgs,
+) -> anyhow::Result<()> {
+    let service = service_for(config, &args.service)?;
+    let kind = service.connector.as_ref().map(|c| c.kind());
+    let profiles: Vec<String> = match args.profile {
+        Some(profile) => vec![profile],
+        None => store
+            .list(Some(&args.service))?
+            .into_iter()
+            .filter(|m| Some(m.connector.as_str()) == kind)
+            .map(|m| m.profile)
+            .collect(),
+    };
+    if profiles.is_empty() {
+        println!("No pulled snapshots of {} to refresh", args.service.bold());
+        println!();
+        println!(
+            "Use {} to pull one",
+            format!(
+                "doubleagent snapshot pull {} --profile <name>",
+                args.service
+            )
+            .cyan()
+        );
+        return Ok(());
+    }
+
+    compliance::check_service(config, &args.service, &format!("refresh {}", args.service))?;
+    let secrets = SecretResolver::new(config.secrets_dir());
+    let redactor = redactor_for(config, &service)?;
+    let limits = RequestLimits {
+        rate_limit: args.rate_limit,
+        max_requests: args.max_requests,
+    };
+    for profile in profiles {
+        println!(
+            "{} Refreshing {}/{}",
+            "↻".blue(),
+            args.service.bold(),
+            profile
+        );
+        let resumed = store.manifest(&args.service, &profile)?.cursors.len();
+        let progress = PullProgress::new();
+        let action = format!("refresh {}/{}", args.service, profile);
+        let (manifest, fetched) = snapshot::refresh(
+            &service,
+            &profile,
+            store,
+            &secrets,
+            &redactor,
+            limits,
+            &|event| progress.show(event),
+            &compliance::check_staged(config, &action)?,
+        )
+        .await?;
+        println!(
+            "  {} fetched {} records{}",
+            "→".dimmed(),
+            fetched,
+            if resumed > 0 {
+                format!(" ({} streams resumed from their cursors)", resumed)
+            } else {
+                String::new()
+            }
+        );
+        print_stored(store, &manifest);
+        super::audit::record(
+            config,
+            Operation::Refresh,
+            &manifest,
+            &store
+                .profile_dir(&manifest.service, &manifest.profile)
+                .display()
+                .to_string(),
+        )?;
+    }
+    Ok(())
+}
+
+fn import(config: &Config, store: &SnapshotStore, args: SnapshotImportArgs) -> anyhow::Result<()> {
+    let data = seed::load_file(Path::new(&args.file))?;
+    let service = service_for(config, &args.service)?;
+    let redactor = redactor_for(config, &service)?;
+    let manifest = snapshot::import(store, &service, &args.profile, data, &redactor)?;
+
+    println!(
+        "{} Imported {} into {}/{}",
+        "✓".green(),
+        args.file,
+        args.service.bold(),
+        args.profile
+    );
+    print_stored(store, &manifest);
+    Ok(())
+}
+
+async fn record(
+    config: &Config,
+    store: &SnapshotStore,
+    args: SnapshotRecordArgs,
+) -> anyhow::Result<()> {
+    // Load the rules up front so a bad rule fails before any traffic is seen
+    let service = service_for(config, &args.service)?;
+    let redactor = redactor_for(config, &service)?;
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", args.port))
+        .await
+        .map_err(|e| anyhow::anyhow!("Could not listen on port {}: {}", args.port, e))?;
+
+    println!(
+        "{} Recording {} traffic to {}",
+        "▶".blue(),
+        args.service.bold(),
+        args.target.cyan()
+    );
+    println!(
+        "  {} Point your app at {}",
+        "→".dimmed(),
+        format!("http://127.0.0.1:{}", args.port).cyan()
+    );
+    println!(
+        "  {} Press Ctrl+C to stop and store the profile",
+        "→".dimmed()
+    );
+
+    let recording = snapshot::record::run(listener, &args.target, async {
+        let _ = tokio::signal::ctrl_c().await;
+    })
+    .await?;
+
+    println!();
+    println!(
+        "{} Proxied {} requests, recorded {} responses",
+        "■".red(),
+        recording.requests,
+        recording.recorded
+    );
+    let manifest = snapshot::save_recording(store, &service, &args.profile, recording, &redactor)?;
+    print_stored(store, &manifest);
+    Ok(())
+}
+
+fn redact(config: &Config, store: &SnapshotStore, args: SnapshotRedactArgs) -> anyhow::Result<()> {
+    let redactor = redactor_for(config, &service_for(config, &args.service)?)?;
+    let before = store.manifest(&args.service, &args.profile)?;
+    let manifest = snapshot::redact_profile(store, &args.service, &args.profile, &redactor)?;
+
+    let newly: usize =
+        manifest.redactions.values().sum::<usize>() - before.redactions.values().sum::<usize>();
+    if newly == 0 {
+        println!(
+            "{} Nothing left to redact in {}/{}",
+            "✓".green(),
+            args.service.bold(),
+            args.profile
+        );
+    } else {
+        println!(
+            "{} Redacted {} more values in {}/{}",
+            "✓".green(),
+            newly,
+            args.service.bold(),
+            args.profile
+        );
+    }
+    Ok(())
+}
+
+fn migrate(
+    config: &Config,
+    store: &SnapshotStore,
+    args: SnapshotMigrateArgs,
+) -> anyhow::Result<()> {
+    let service = service_for(config, &args.service)?;
+    let before = store.manifest(&args.service, &args.profile)?;
+    if before.seed_schema_version == service.seed_schema_version {
+        println!(
+            "{} {}/{} is already at seed schema version {}",
+            "✓".green(),
+            args.service.bold(),
+            args.profile,
+            service.seed_schema_version
+        );
+        return Ok(());
+    }
+
+    println!(
+        "{} Migrating {}/{} {}",
+        "↻".blue(),
+        args.service.bold(),
+        args.profile,
+        format!(
+            "(seed schema {} → {})",
+            before.seed_schema_version, service.seed_schema_version
+        )
+        .dimmed()
+    );
+    let redactor = redactor_for(config, &service)?;
+    let manifest = snapshot::migrate(store, &service, &args.profile, &redactor)?;
+    print_stored(store, &manifest);
+    Ok(())
+}
+
+fn merge(store: &SnapshotStore, args: SnapshotMergeArgs) -> anyhow::Result<()> {
+    let policy = match args.on_conflict {
+        OnConflict::First => ConflictPolicy::First,
+        OnConflict::Last => ConflictPolicy::Last,
+        OnConflict::Fail => ConflictPolicy::Fail,
+    };
+    let (manifest, conflicts) = snapshot::merge(
+        store,
+        &args.service,
+        &args.profiles,
+        &args.into,
+        &args.key,
+        policy,
+    )?;
+
+    println!(
+        "{} Merged {} into {}/{}",
+        "✓".green(),
+        args.profiles.join(", "),
+        args.service.bold(),
+        args.into
+    );
+    if conflicts > 0 {
+        println!(
+            "  {} {} conflicting records, kept the {} profile's",
+            "⚠".yellow(),
+            conflicts,
+            if policy == ConflictPolicy::First {
+                "first"
+            } else {
+                "last"
+            }
+        );
+    }
+    print_stored(store, &manifest);
+    Ok(())
+}
+
+fn sample(store: &SnapshotStore, args: SnapshotSampleArgs) -> anyhow::Result<()> {
+    let options = SampleOptions {
+        fraction: args.fraction,
+        keep_referenced: args.keep_referenced,
+        key: args.key,
+        references: args
+            .references
+            .iter()
+            .map(|spec| Reference::parse(spec))
+            .collect::<Result<_, _>>()?,
+    };
+    let source = store.manifest(&args.service, &args.profile)?;
+    let manifest = snapshot::sample(store, &args.service, &args.profile, &args.out, &options)?;
+
+    println!(
+        "{} Sampled {} of {} records from {}/{} into {}",
+        "✓".green(),
+        manifest.total_records(),
+        source.total_records(),
+        args.service.bold(),
+        args.profile,
+        args.out
+    );
+    print_stored(store, &manifest);
+    Ok(())
+}
+
+fn inspect(store: &SnapshotStore, args: SnapshotInspectArgs) -> anyhow::Result<()> {
+    if !args.data && args.resource.is_none() {
+        let manifest = store.manifest(&args.service, &args.profile)?;
+        crate::output::result(&manifest);
+        println!("{}", serde_json::to_string_pretty(&manifest)?);
+        return Ok(());
+    }
+    if args.page == 0 || args.page_size == 0 {
+        anyhow::bail!("--page and --page-size start at 1");
+    }
+
+    let seed = store.load_seed_payload(&args.service, &args.profile)?;
+    let resources = seed.as_object().cloned().unwrap_or_default();
+    let Some(name) = &args.resource else {
+        let counts: BTreeMap<&String, usize> = resources
+            .iter()
+            .map(|(name, records)| (name, records.as_array().map_or(0, Vec::len)))
+            .collect();
+        crate::output::result(json!({ "resources": counts }));
+        println!(
+            "{} {}/{}:",
+            "Resources of".bold(),
+            args.service.bold(),
+            args.profile
+        );
+        println!();
+        for (name, records) in &resources {
+            println!(
+                "  {} {} {}",
+                "●".cyan(),
+                name.bold(),
+                match records.as_array().map_or(0, Vec::len) {
+                    1 => "(1 record)".to_string(),
+                    count => format!("({} records)", count),
+                }
+                .dimmed()
+            );
+        }
+        println!();
+        println!(
+            "Use {} to page through one",
+            format!(
+                "doubleagent snapshot inspect {} --profile {} --resource <name>",
+                args.service, args.profile
+            )
+            .cyan()
+        );
+        return Ok(());
+    };
+
+    let records = match resources.get(name) {
+        Some(Value::Array(records)) => records,
+        _ => {
+            let names: Vec<&str> = resources.keys().map(String::as_str).collect();
+            anyhow::bail!(
+                "{}/{} has no resource '{}' (resources: {})",
+                args.service,
+                args.profile,
+                name,
+                names.join(", ")
+            );
+        }
+    };
+    let filter = args
+        .filter
+        .as_deref()
+        .map(inspect::Filter::parse)
+        .transpose()?;
+    let page = inspect::page(records, filter.as_ref(), args.page, args.page_size);
+
+    crate::output::result(json!({
+        "records": page.records,
+        "offset": page.offset,
+        "matched": page.matched,
+    }));
+    // Records go to stdout as JSON, so they can be piped on
+    println!("{}", serde_json::to_string_pretty(&page.records)?);
+    let of = match &args.filter {
+        Some(_) => format!("{} (filtered from {})", page.matched, records.len()),
+        None => records.len().to_string(),
+    };
+    if page.records.is_empty() {
+        eprintln!(
+            "{}",
+            format!("No records on page {} ({} {})", args.page, of, name).dimmed()
+        );
+    } else {
+        eprintln!(
+            "{}",
+            format!(
+                "{} {}-{} of {}",
+                name,
+                page.offset + 1,
+                page.offset + page.records.len(),
+                of
+            )
+            .dimmed()
+        );
+    }
+    if page.offset + page.records.len() < page.matched {
+        eprintln!(
+            "{}",
+            format!("Next page: --page {}", args.page + 1).dimmed()
+        );
+    }
+    Ok(())
+}
+
+fn scan(config: &Config, store: &SnapshotStore, args: SnapshotScanArgs) -> anyhow::Result<()> {
+    let manifest = store.manifest(&args.service, &args.profile)?;
+    let seed = store.load_seed_payload(&args.service, &args.profile)?;
+    let findings = redact::scan(&seed, &manifest.pseudonyms);
+
+    if findings.is_empty() {
+        println!(
+            "{} No personal data or credentials found in {}/{}",
+            "✓".green(),
+            args.service.bold(),
+            args.profile
+        );
+        return Ok(());
+    }
+
+    println!(
+        "{} {}/{} has values that look sensitive:",
+        "⚠".yellow(),
+        args.service.bold(),
+        args.profile
+    );
+    println!();
+    for finding in &findings {
+        println!(
+            "  {} {} {}",
+            "●".cyan(),
+            finding.path,
+            format!(
+                "({}, {} {})",
+                finding.detector,
+                finding.count,
+                if finding.count == 1 {
+                    "value"
+                } else {
+                    "values"
+                }
+            )
+            .dimmed()
+        );
+    }
+    println!();
+    println!(
+        "Add redaction rules for these fields, then use {} to apply them",
+        format!(
+            "doubleagent snapshot redact {} --profile {}",
+            args.service, args.profile
+        )
+        .cyan()
+    );
+
+    if config.compliance_mode == ComplianceMode::Strict {
+        anyhow::bail!(
+            "{}/{} failed the scan in strict compliance mode",
+            args.service,
+            args.profile
+        );
+    }
+    Ok(())
+}
+
+fn validate(
+    config: &Config,
+    store: &SnapshotStore,
+    args: SnapshotValidateArgs,
+) -> anyhow::Result<()> {
+    let service = service_for(config, &args.service)?;
+    let manifest = store.manifest(&args.service, &args.profile)?;
+    if manifest.seed_schema_version != service.seed_schema_version {
+        anyhow::bail!(
+            "{}/{} has seed schema version {}, but the service expects {}. \
+             Run 'doubleagent snapshot migrate {} --profile {}' first.",
+            args.service,
+            args.profile,
+            manifest.seed_schema_version,
+            service.seed_schema_version,
+            args.service,
+            args.profile
+        );
+    }
+
+    let seed = store.load_seed_payload(&args.service, &args.profile)?;
+    let Some(diagnostics) = schema::check(&service, &store.seed_path(&manifest), &seed)? else {
+        println!(
+            "{} {} ships no {}, so {}/{} can't be checked",
+            "⚠".yellow(),
+            args.service.bold(),
+            schema::SEED_SCHEMA_FILE,
+            args.service,
+            args.profile
+        );
+        return Ok(());
+    };
+
+    if diagnostics.is_empty() {
+        println!(
+            "{} {}/{} matches the service's seed schema",
+            "✓".green(),
+            args.service.bold(),
+            args.profile
+        );
+        return Ok(());
+    }
+
+    println!(
+        "{} {}/{} does not match the service's seed schema:",
+        "✗".red(),
+        args.service.bold(),
+        args.profile
+    );
+    print_diagnostics(&diagnostics);
+    if validate::has_errors(&diagnostics) {
+        println!();
+        anyhow::bail!(
+            "{}/{} failed validation with {} error(s)",
+            args.service,
+            args.profile,
+            diagnostics.len()
+        );
+    }
+    Ok(())
+}
+
+/// Print seed schema diagnostics with their hints, indented under a heading.
+pub(super) fn print_diagnostics(diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        match diagnostic.severity {
+            Severity::Error => println!("    {} {}", "error:".red(), diagnostic),
+            Severity::Warning => println!("    {} {}", "warning:".yellow(), diagnostic),
+        }
+        if let Some(hint) = &diagnostic.hint {
+            println!("      {} {}", "hint:".dimmed(), hint);
+        }
+    }
+}
+
+/// The installed definition of a service, installing it if needed.
+pub(super) fn service_for(config: &Config, name: &str) -> anyhow::Result<ServiceDefinition> {
+    let registry = ServiceRegistry::new(&config.services_dir, &config.repo_url, &config.branch)?;
+    Ok(registry.get_or_install(name, true)?)
+}
+
+/// Redaction rules for a service: its service.yaml rules, then the project's.
+pub(super) fn redactor_for(
+    config: &Config,
+    service: &ServiceDefinition,
+) -> anyhow::Result<Redactor> {
+    let mut rules = service.redaction.clone();
+    if let Some(path) = &config.project_config_path {
+        let project = Manifest::from_project_config(path, &config.cache_dir)?;
+        if let Some(entry) = project.services.get(&service.name) {
+            rules.extend(entry.redaction.iter().cloned());
+        }
+    }
+    Ok(Redactor::new(&rules)?.seeded(&config.pseudonym_seed))
+}
+
+/// Renders connector progress on stderr: a live line for the stream being
+/// pulled when stderr is a terminal, and a line per finished stream.
+struct PullProgress {
+    started: Instant,
+    stream_started: Cell<Instant>,
+    live: bool,
+}
+
+impl PullProgress {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            stream_started: Cell::new(Instant::now()),
+            live: std::io::stderr().is_terminal(),
+        }
+    }
+
+    fn show(&self, event: &ProgressEvent) {
+        let mut stderr = std::io::stderr();
+        match event {
+            ProgressEvent::StreamStarted { stream } => {
+                self.stream_started.set(Instant::now());
+                self.live_line(stream, 0);
+            }
+            ProgressEvent::Resumed { stream, count } => {
+                if self.live {
+                    let _ = write!(stderr, "\r\x1b[2K");
+                }
+                let _ = writeln!(
+                    stderr,
+                    "  {} {} resuming after {} records",
+                    "↻".blue(),
+                    stream,
+                    count
+                );
+                self.live_line(stream, *count);
+            }
+            ProgressEvent::Records { stream, count } => self.live_line(stream, *count),
+            ProgressEvent::StreamDone { stream, count } => {
+                if self.live {
+                    let _ = write!(stderr, "\r\x1b[2K");
+                }
+                let _ = writeln!(
+                    stderr,
+                    "  {} {} {} records {}",
+                    "✓".green(),
+                    stream,
+                    count,
+                    format!("({})", elapsed(self.stream_started.get().elapsed())).dimmed()
+                );
+            }
+        }
+    }
+
+    fn live_line(&self, stream: &str, count: usize) {
+        if !self.live {
+            return;
+        }
+        let mut stderr = std::io::stderr();
+        let _ = write!(
+            stderr,
+            "\r\x1b[2K  {} {} {} records {}",
+            "↻".blue(),
+            stream.bold(),
+            count,
+            format!(
+                "({} stream, {} total)",
+                elapsed(self.stream_started.get().elapsed()),
+                elapsed(self.started.elapsed())
+            )
+            .dimmed()
+        );
+        let _ = stderr.flush();
+    }
+}
+
+fn elapsed(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs < 60 {
+        format!("{:.1}s", duration.as_secs_f64())
+    } else {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    }
+}
+
+pub(super) fn print_stored(store: &SnapshotStore, manifest: &SnapshotManifest) {
+    for (resource, count) in &manifest.resources {
+        println!("  {} {} {}", "→".dimmed(), resource, count);
+    }
+    let redacted: usize = manifest.redactions.values().sum();
+    if redacted > 0 {
+        println!("  {} {} values redacted", "■".red(), redacted);
+    }
+    if let Some(requests) = manifest.requests {
+        let budget = match manifest.max_requests {
+            Some(max) => format!(" of a budget of {}", max),
+            None => String::new(),
+        };
+        println!("  {} {} API requests{}", "→".dimmed(), requests, budget);
+    }
+    println!(
+        "{} Stored {} records in {}",
+        "✓".green(),
+        manifest.total_records(),
+        store
+            .profile_dir(&manifest.service, &manifest.profile)
+            .display()
+    );
+}
+
+fn list(store: &SnapshotStore, args: SnapshotListArgs) -> anyhow::Result<()> {
+    let manifests = store.list(args.service.as_deref())?;
+    let scenarios: Vec<_> = store
+        .scenarios()?
+        .into_iter()
+        .filter(|s| {
+            args.service
+                .as_ref()
+                .is_none_or(|service| s.profiles.contains_key(service))
+        })
+        .collect();
+    crate::output::result(json!({ "profiles": manifests, "scenarios": scenarios }));
+
+    if manifests.is_empty() {
+        println!("No snapshots stored");
+        println!();
+        println!(
+            "Use {} to pull one",
+            "doubleagent snapshot pull <service> --profile <name>".cyan()
+        );
+        return Ok(());
+    }
+
+    println!("{}", "Snapshots:".bold());
+    println!();
+    for manifest in manifests {
+        let size = match (manifest.size, manifest.compressed_size) {
+            (Some(size), Some(stored)) => {
+                format!(", {} ({} stored)", human_size(size), human_size(stored))
+            }
+            (Some(size), None) => format!(", {}", human_size(size)),
+            _ => String::new(),
+        };
+        println!(
+            "  {} {}/{} {} records{} {}",
+            "●".cyan(),
+            manifest.service.bold(),
+            manifest.profile,
+            manifest.total_records(),
+            size,
+            format!("({}, {})", manifest.connector, ago(manifest.created_at)).dimmed()
+        );
+    }
+
+    if !scenarios.is_empty() {
+        println!();
+        println!("{}", "Scenarios:".bold());
+        println!();
+        for scenario in scenarios {
+            let profiles: Vec<String> = scenario
+                .profiles
+                .iter()
+                .map(|(service, profile)| format!("{}/{}", service, profile))
+                .collect();
+            println!(
+                "  {} {} {}",
+                "●".cyan(),
+                scenario.name.bold(),
+                profiles.join(", ").dimmed()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn diff(store: &SnapshotStore, args: SnapshotDiffArgs) -> anyhow::Result<()> {
+    let from = store.load_seed_payload(&args.service, &args.from)?;
+    let to = store.load_seed_payload(&args.service, &args.to)?;
+    let diff = snapshot::diff(&from, &to, &args.key);
+    crate::output::result(json!({ "patch": diff.to_json_patch() }));
+
+    if args.patch {
+        println!("{}", serde_json::to_string_pretty(&diff.to_json_patch())?);
+        return Ok(());
+    }
+
+    println!(
+        "{} {} {} {} {}",
+        "▶".blue(),
+        args.service.bold(),
+        args.from,
+        "→".dimmed(),
+        args.to
+    );
+    if diff.is_empty() {
+        println!("{} No differences", "✓".green());
+        return Ok(());
+    }
+
+    for resource in &diff.resources {
+        println!();
+        println!(
+            "  {} {} {} {}",
+            resource.name.bold(),
+            format!("+{}", resource.added.len()).green(),
+            format!("-{}", resource.removed.len()).red(),
+            format!("~{}", resource.changed.len()).yellow()
+        );
+        for record in &resource.changed {
+            println!(
+                "    {} {}={} {}",
+                "~".yellow(),
+                args.key,
+                record.key,
+                record.fields.join(", ").dimmed()
+            );
+        }
+    }
+    println!();
+    println!(
+        "Use {} for the full changes",
+        format!(
+            "doubleagent snapshot diff {} --from {} --to {} --patch",
+            args.service, args.from, args.to
+        )
+        .cyan()
+    );
+    Ok(())
+}
+
+fn registry_for(config: &Config) -> anyhow::Result<Registry> {
+    let registry = config.registry.as_deref().ok_or_else(|| {
+        anyhow::anyhow!(
+            "No snapshot registry configured.\n\
+             Set one with 'doubleagent config set registry s3://bucket/prefix', \
+             add 'registry:' to doubleagent.yaml, or pass --registry."
+        )
+    })?;
+    Ok(Registry::parse(registry)?)
+}
+
+async fn push(
+    config: &Config,
+    store: &SnapshotStore,
+    args: SnapshotTransferArgs,
+) -> anyhow::Result<()> {
+    let registry = registry_for(config)?;
+    // Refuse profiles the service can't be seeded with before sharing them
+    store.load_seed(&service_for(config, &args.service)?, &args.profile)?;
+    let action = format!("push {}/{}", args.service, args.profile);
+    compliance::check_registry(config, registry.url(), &action)?;
+    compliance::check_profile(config, store, &args.service, &args.profile, &action)?;
+
+    println!(
+        "{} Pushing {}/{} to {}",
+        "⬆".blue(),
+        args.service.bold(),
+        args.profile,
+        registry.url().cyan()
+    );
+
+    let secrets = SecretResolver::new(config.secrets_dir());
+    let manifest = registry
+        .push(store, &args.service, &args.profile, &secrets)
+        .await?;
+    let destination = registry.profile_url(&args.service, &args.profile);
+    crate::output::result(json!({ "profile": manifest, "destination": destination }));
+    println!(
+        "{} Pushed {} records to {}",
+        "✓".green(),
+        manifest.total_records(),
+        destination
+    );
+    super::audit::record(config, Operation::Push, &manifest, &destination)?;
+    Ok(())
+}
+
+async fn fetch(
+    config: &Config,
+    store: &SnapshotStore,
+    args: SnapshotTransferArgs,
+) -> anyhow::Result<()> {
+    let registry = registry_for(config)?;
+    let action = format!("fetch {}/{}", args.service, args.profile);
+    compliance::check_service(config, &args.service, &action)?;
+    compliance::check_registry(config, registry.url(), &action)?;
+
+    println!(
+        "{} Fetching {}/{} from {}",
+        "⬇".blue(),
+        args.service.bold(),
+        args.profile,
+        registry.url().cyan()
+    );
+
+    let secrets = SecretResolver::new(config.secrets_dir());
+    let manifest = registry
+        .fetch(
+            store,
+            &args.service,
+            &args.profile,
+            &secrets,
+            &compliance::check_staged(config, &action)?,
+        )
+        .await?;
+    let destination = store
+        .profile_dir(&manifest.service, &manifest.profile)
+        .display()
+        .to_string();
+    crate::output::result(json!({ "profile": manifest, "destination": destination }));
+    println!(
+        "{} Verified and stored {} records in {}",
+        "✓".green(),
+        manifest.total_records(),
+        destination
+    );
+    super::audit::record(config, Operation::Fetch, &manifest, &destination)?;
+    Ok(())
+}
+
+/// How long ago a Unix timestamp was, e.g. "3h ago".
+fn ago(timestamp: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let secs = now.saturating_sub(timestamp);
+    match secs {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
@@ -1,29 +1,48 @@
 use super::StartArgs;
 use colored::Colorize;
-use doubleagent_core::{Config, ProcessManager, ServiceDefinition, ServiceRegistry};
+use doubleagent_core::manifest::{Manifest, SeedSource};
+use doubleagent_core::ports::PortAllocator;
+use doubleagent_core::snapshot::{Scenario, SnapshotStore};
+use doubleagent_core::tags::Tags;
+use doubleagent_core::telemetry::{self, TelemetryConfig};
+use doubleagent_core::{seed, workspace};
+use doubleagent_core::{
+    Config, ControlClient, ProcessManager, ServiceDefinition, ServiceRegistry, StartOptions,
+};
+use serde_json::json;
 use std::fs;
 use std::path::PathBuf;
 
-/// Env file name for service URLs
-const ENV_FILE: &str = ".doubleagent.env";
-
 /// Collects started service info for env file generation
 struct StartedService {
     name: String,
     url: String,
 }
 
-pub async fn run(args: StartArgs) -> anyhow::Result<()> {
-    let config = Config::load()?;
+pub async fn run(config: Config, args: StartArgs) -> anyhow::Result<()> {
     let mut manager = ProcessManager::load(&config.state_file)?;
 
-    let base_port = args.port.unwrap_or(8080);
+    // An explicit --port is taken as is for the first service started
+    let mut explicit_port = args.port;
+    if let Some(port) = explicit_port {
+        config.port_range.check(port)?;
+    }
+    // Skip ports taken by services in any workspace
+    let mut ports = PortAllocator::new(
+        config.port_range,
+        config.base_port,
+        workspace::ports_in_use(&config.state_dir)?,
+    );
+    let env_file = config.env_file_name();
+    let tags: Tags = args.tags.into_iter().collect();
+    let store = SnapshotStore::new(&config.data_dir);
+    let defaults = project_services(&config)?;
+    let (scenario, services) = scenario_services(&store, &args.scenario, args.services)?;
     let mut started_services: Vec<StartedService> = Vec::new();
 
     // Handle --local flag for development/testing
     if let Some(local_path) = &args.local {
         let service = load_local_service(local_path)?;
-        let port = base_port;
 
         // Check if already running
         if manager.is_running(&service.name) {
@@ -42,14 +61,31 @@ pub async fn run(args: StartArgs) -> anyhow::Result<()> {
                 local_path
             );
 
+            let sources = seed_sources(&args.snapshot, scenario.as_ref(), &defaults, &service.name);
+            for source in &sources {
+                super::compliance::check_seed(&config, &store, &service.name, source)?;
+            }
+
             // Start the service
-            let pid = manager.start(&service, port).await?;
+            let port = match explicit_port.take() {
+                Some(port) => ports.claim(port)?,
+                None => ports.allocate_free()?,
+            };
+            let pid = manager
+                .start_with(&service, port, start_options(&config, &service.name))
+                .await?;
 
             // Wait for health check
             print!("  Waiting for health check...");
-            match manager.wait_for_health(&service.name, port, 30).await {
+            match manager
+                .wait_for_health(&service.name, port, &config.health_for(&service.name))
+                .await
+            {
                 Ok(_) => {
                     println!(" {}", "✓".green());
+                    trace(&config, &service.name, port).await;
+                    seed_started(&config, &mut manager, &store, &service, &sources).await?;
+                    manager.set_tags(&service.name, tags.clone());
                     let env_var_name = format!("DOUBLEAGENT_{}_URL", service.name.to_uppercase());
                     let url = format!("http://localhost:{}", port);
                     println!(
@@ -68,18 +104,19 @@ pub async fn run(args: StartArgs) -> anyhow::Result<()> {
                 Err(e) => {
                     println!(" {}", "✗".red());
                     manager.stop(&service.name).await?;
-                    return Err(anyhow::anyhow!("Health check failed: {}", e));
+                    return Err(anyhow::Error::new(e).context("Health check failed"));
                 }
             }
         }
 
         manager.save(&config.state_file)?;
-        write_env_file(&started_services)?;
+        started(&started_services, &env_file);
+        write_env_file(&started_services, &env_file)?;
         return Ok(());
     }
 
     // Normal mode: fetch from registry
-    if args.services.is_empty() {
+    if services.is_empty() {
         return Err(anyhow::anyhow!(
             "No services specified. Use 'doubleagent start <service>' or 'doubleagent start --local <path>'"
         ));
@@ -87,10 +124,16 @@ pub async fn run(args: StartArgs) -> anyhow::Result<()> {
 
     let registry = ServiceRegistry::new(&config.services_dir, &config.repo_url, &config.branch)?;
 
-    for (i, service_name) in args.services.iter().enumerate() {
+    // Refuse snapshot seeds the compliance mode forbids before starting anything
+    for service_name in &services {
+        for source in seed_sources(&args.snapshot, scenario.as_ref(), &defaults, service_name) {
+            super::compliance::check_seed(&config, &store, service_name, &source)?;
+        }
+    }
+
+    for service_name in &services {
         // Auto-install if not present (fetches from remote)
         let service = registry.get_or_install(service_name, true)?;
-        let port = base_port + i as u16;
 
         // Check if already running
         if manager.is_running(service_name) {
@@ -107,13 +150,27 @@ pub async fn run(args: StartArgs) -> anyhow::Result<()> {
         println!("{} Starting {}...", "▶".blue(), service_name);
 
         // Start the service
-        let pid = manager.start(&service, port).await?;
+        let port = match explicit_port.take() {
+            Some(port) => ports.claim(port)?,
+            None => ports.allocate_free()?,
+        };
+        let pid = manager
+            .start_with(&service, port, start_options(&config, service_name))
+            .await?;
 
         // Wait for health check
         print!("  Waiting for health check...");
-        match manager.wait_for_health(service_name, port, 30).await {
+        match manager
+            .wait_for_health(service_name, port, &config.health_for(service_name))
+            .await
+        {
             Ok(_) => {
                 println!(" {}", "✓".green());
+                trace(&config, service_name, port).await;
+                let sources =
+                    seed_sources(&args.snapshot, scenario.as_ref(), &defaults, service_name);
+                seed_started(&config, &mut manager, &store, &service, &sources).await?;
+                manager.set_tags(service_name, tags.clone());
                 let env_var_name = format!("DOUBLEAGENT_{}_URL", service_name.to_uppercase());
                 let url = format!("http://localhost:{}", port);
                 println!(
@@ -132,24 +189,143 @@ pub async fn run(args: StartArgs) -> anyhow::Result<()> {
             Err(e) => {
                 println!(" {}", "✗".red());
                 manager.stop(service_name).await?;
-                return Err(anyhow::anyhow!("Health check failed: {}", e));
+                return Err(anyhow::Error::new(e).context("Health check failed"));
             }
         }
     }
 
     manager.save(&config.state_file)?;
-    write_env_file(&started_services)?;
+    started(&started_services, &env_file);
+    write_env_file(&started_services, &env_file)?;
     Ok(())
 }
 
-/// Write service URLs to .doubleagent.env file
-fn write_env_file(services: &[StartedService]) -> anyhow::Result<()> {
+/// Options a service is started with: with an `otel_endpoint`, the `OTEL_*`
+/// variables that point its traces at the collector
+pub(crate) fn start_options(config: &Config, service: &str) -> StartOptions {
+    StartOptions {
+        env: config
+            .otel_endpoint
+            .as_deref()
+            .map(|endpoint| telemetry::env(endpoint, service))
+            .unwrap_or_default(),
+        ..Default::default()
+    }
+}
+
+/// Tell a healthy fake where to send trace spans, if an `otel_endpoint` is set.
+///
+/// Fakes that can't are still started with the `OTEL_*` variables, which is
+/// all fakes built on an OpenTelemetry SDK need.
+pub(crate) async fn trace(config: &Config, service: &str, port: u16) {
+    let Some(endpoint) = &config.otel_endpoint else {
+        return;
+    };
+    let client = ControlClient::new(port);
+    if !client.supports_telemetry().await {
+        return;
+    }
+    match client
+        .configure_telemetry(&TelemetryConfig::new(endpoint, service))
+        .await
+    {
+        Ok(()) => println!("  {} Tracing to {}", "→".dimmed(), endpoint),
+        Err(e) => println!("  {} Could not enable tracing: {}", "⚠".yellow(), e),
+    }
+}
+
+/// Services declared in doubleagent.yaml, whose seed settings apply at start
+pub(crate) fn project_services(config: &Config) -> anyhow::Result<Manifest> {
+    match &config.project_config_path {
+        Some(path) => Ok(Manifest::from_project_config(path, &config.cache_dir)?),
+        None => Ok(Manifest::default()),
+    }
+}
+
+/// Load `--scenario`, if given, with the services to start: those requested,
+/// else every service the scenario links
+pub(crate) fn scenario_services(
+    store: &SnapshotStore,
+    scenario: &Option<String>,
+    services: Vec<String>,
+) -> anyhow::Result<(Option<Scenario>, Vec<String>)> {
+    let Some(name) = scenario else {
+        return Ok((None, services));
+    };
+    let scenario = store.scenario(name)?;
+    let services = if services.is_empty() {
+        scenario.profiles.keys().cloned().collect()
+    } else {
+        services
+    };
+    Ok((Some(scenario), services))
+}
+
+/// What to seed a started service with, in order: `--snapshot`, else its
+/// profile in `--scenario`, else its project defaults
+pub(crate) fn seed_sources(
+    snapshot: &Option<String>,
+    scenario: Option<&Scenario>,
+    defaults: &Manifest,
+    service: &str,
+) -> Vec<SeedSource> {
+    if let Some(profile) = snapshot {
+        return vec![SeedSource::Snapshot(profile.clone())];
+    }
+    match scenario.and_then(|s| s.profile(service)) {
+        Some(profile) => vec![SeedSource::Snapshot(profile.to_string())],
+        None => defaults.seed_sources(service),
+    }
+}
+
+/// Seed a healthy service; the service is stopped if that fails
+async fn seed_started(
+    config: &Config,
+    manager: &mut ProcessManager,
+    store: &SnapshotStore,
+    service: &ServiceDefinition,
+    sources: &[SeedSource],
+) -> anyhow::Result<()> {
+    if sources.is_empty() {
+        return Ok(());
+    }
+    print!("  Seeding from {}...", SeedSource::join(sources));
+    match seed::seed_started(manager, store, service, sources).await {
+        Ok(()) => {
+            println!(" {}", "✓".green());
+            let port = manager.get_info(&service.name).map_or(0, |info| info.port);
+            for source in sources {
+                super::audit::record_seed(config, store, &service.name, source, port)?;
+            }
+            Ok(())
+        }
+        Err(e) => {
+            println!(" {}", "✗".red());
+            Err(e.into())
+        }
+    }
+}
+
+/// The services started, or already running, as the command's result
+fn started(services: &[StartedService], env_file: &str) {
+    let services: Vec<_> = services
+        .iter()
+        .map(|service| json!({ "name": service.name, "url": service.url }))
+        .collect();
+    crate::output::result(json!({ "services": services, "env_file": env_file }));
+}
+
+/// Write service URLs to the workspace's env file (.doubleagent.env)
+fn write_env_file(services: &[StartedService], env_file: &str) -> anyhow::Result<()> {
     if services.is_empty() {
         return Ok(());
     }
 
     let mut content = String::from("# Generated by doubleagent - do not edit\n");
-    content.push_str("# Load with: source .doubleagent.env (bash) or use dotenv library\n\n");
+    content.push_str(&format!(
+        "# Load with: source {} (bash) or use dotenv library\n\n",
+        env_file
+    ));
 
     for service in services {
         let env_name = format!(
@@ -159,13 +335,13 @@ fn write_env_file(services: &[StartedService]) -> anyhow::Result<()> {
         content.push_str(&format!("{}={}\n", env_name, service.url));
     }
 
-    fs::write(ENV_FILE, &content)?;
+    fs::write(env_file, &content)?;
     println!();
     println!(
         "{} Wrote {} (load with 'source {}' or dotenv)",
         "✓".green(),
-        ENV_FILE.bold(),
-        ENV_FILE
+        env_file.bold(),
+        env_file
     );
 
     Ok(())
@@ -185,9 +361,5 @@ fn load_local_service(path: &str) -> anyhow::Result<ServiceDefinition> {
         ));
     }
 
-    let content = std::fs::read_to_string(&service_yaml)?;
-    let mut service: ServiceDefinition = serde_yaml::from_str(&content)?;
-    service.path = service_path;
-
-    Ok(service)
+    Ok(ServiceDefinition::from_dir(&service_path)?)
 }
@@ -1,11 +1,413 @@
+use super::StatusArgs;
 use colored::Colorize;
-use doubleagent_core::{Config, ProcessManager};
+use doubleagent_core::health::{self, FlapAlert};
+use doubleagent_core::hooks::{self, Alert, Event};
+use doubleagent_core::reload::{self, DefinitionStatus};
+use doubleagent_core::{
+    dates, tags, workspace, Config, ControlClient, ProcessManager, SeedRecord, ServiceInfo,
+};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::IsTerminal;
+use std::time::Duration;
 
-pub async fn run() -> anyhow::Result<()> {
-    let config = Config::load()?;
-    let manager = ProcessManager::load(&config.state_file)?;
+/// Changes `status --watch` keeps showing, most recent last.
+const EVENTS_SHOWN: usize = 10;
 
-    let services = manager.running_services();
+pub async fn run(config: Config, args: StatusArgs) -> anyhow::Result<()> {
+    if let Some(interval) = args.watch {
+        crate::output::text_only("--watch")?;
+        return watch(&config, &args, Duration::from_secs(interval.max(1))).await;
+    }
+    for report in report_crashes(&config).await? {
+        eprintln!("{}", report);
+    }
+    let mut manager = ProcessManager::load(&config.state_file)?;
+    let checks = check(&config, &mut manager, &args).await?;
+    crate::output::result(status_json(&config, &manager, &checks));
+    print_status(&config, &manager, &args, &checks);
+    Ok(())
+}
+
+/// The running services with their health and config state, as the result
+/// of `--output-format json`
+fn status_json(
+    config: &Config,
+    manager: &ProcessManager,
+    checks: &BTreeMap<String, Check>,
+) -> Value {
+    let services: Vec<Value> = checks
+        .iter()
+        .filter_map(|(name, check)| {
+            let info = manager.get_info(name)?;
+            let project = reload::project_status(&info, &config.cache_dir, name);
+            let definition = match reload::definition_status(&info) {
+                DefinitionStatus::Changed => "changed",
+                _ if project == DefinitionStatus::Changed => "changed",
+                DefinitionStatus::Invalid(_) => "invalid",
+                _ if matches!(project, DefinitionStatus::Invalid(_)) => "invalid",
+                DefinitionStatus::Current => "current",
+                DefinitionStatus::Unknown => "unknown",
+            };
+            let mut service = json!({
+                "name": name,
+                "url": format!("http://localhost:{}", info.port),
+                "healthy": check.healthy,
+                "config": definition,
+                "flapping": info.is_flapping(dates::now()),
+                "uptime_seconds": uptime(&info),
+                "last_request_at": check.last_request.map(dates::format_time),
+            });
+            if let (Some(service), Value::Object(info)) = (service.as_object_mut(), json!(info)) {
+                service.extend(info);
+            }
+            Some(service)
+        })
+        .collect();
+    json!({ "workspace": config.workspace, "services": services })
+}
+
+/// What checking on a running service found
+struct Check {
+    healthy: bool,
+    /// When the fake last served a request, if it logs them with times
+    last_request: Option<i64>,
+}
+
+/// Check the running services matching the tags asked for, recording the
+/// changes of health seen in the state file and reloading `manager` with them
+async fn check(
+    config: &Config,
+    manager: &mut ProcessManager,
+    args: &StatusArgs,
+) -> anyhow::Result<BTreeMap<String, Check>> {
+    let mut checks = BTreeMap::new();
+    for name in manager.running_services() {
+        let Some(info) = manager.get_info(&name) else {
+            continue;
+        };
+        if !tags::matches(&info.tags, &args.tags) {
+            continue;
+        }
+        let healthy = manager.check_health(&name, &config.health_for(&name)).await;
+        let last_request = if healthy {
+            last_request(info.port).await
+        } else {
+            None
+        };
+        checks.insert(
+            name,
+            Check {
+                healthy,
+                last_request,
+            },
+        );
+    }
+    let results: Vec<(String, bool)> = checks
+        .iter()
+        .map(|(name, check)| (name.clone(), check.healthy))
+        .collect();
+    if record_health(config, &results).await? {
+        *manager = ProcessManager::load(&config.state_file)?;
+    }
+    Ok(checks)
+}
+
+/// A change of health recorded, for alerting once the state is saved
+struct Recorded {
+    name: String,
+    healthy: bool,
+    was_flapping: bool,
+    info: ServiceInfo,
+    log_file: std::path::PathBuf,
+}
+
+/// Record health checks of running services, posting a [`FlapAlert`] to the
+/// `flap_webhook` for each that starts flapping, and alerting the crash hooks
+/// for each that has failed [`hooks::FAILURE_STREAK`] checks in a row.
+///
+/// The checks are recorded under the state file's lock, on the state as it is
+/// then, and the alerts sent after. Returns whether any recorded health
+/// changed.
+pub(crate) async fn record_health(
+    config: &Config,
+    results: &[(String, bool)],
+) -> anyhow::Result<bool> {
+    // Only a check that changes something takes the lock and writes, so
+    // watching services whose health holds steady only reads the state
+    let mut current = ProcessManager::load(&config.state_file)?;
+    if !results
+        .iter()
+        .any(|(name, healthy)| current.record_health(name, *healthy))
+    {
+        return Ok(false);
+    }
+    let now = dates::now();
+    let recorded = ProcessManager::update(&config.state_file, |manager| {
+        let mut recorded = Vec::new();
+        for (name, healthy) in results {
+            let was_flapping = manager
+                .get_info(name)
+                .is_some_and(|info| info.is_flapping(now));
+            if !manager.record_health(name, *healthy) {
+                continue;
+            }
+            if let Some(info) = manager.get_info(name) {
+                recorded.push(Recorded {
+                    name: name.clone(),
+                    healthy: *healthy,
+                    was_flapping,
+                    info,
+                    log_file: manager.log_file(name),
+                });
+            }
+        }
+        recorded
+    })?;
+
+    for Recorded {
+        name,
+        healthy,
+        was_flapping,
+        info,
+        log_file,
+    } in &recorded
+    {
+        if let (false, true, Some(url)) =
+            (was_flapping, info.is_flapping(now), &config.flap_webhook)
+        {
+            let changes = health::recent_changes(&info.health_history, now);
+            let alert = FlapAlert::new(name, &config.workspace, *healthy, changes);
+            if let Err(e) = alert.send(url).await {
+                eprintln!(
+                    "{} Could not send the flapping alert for {}: {}",
+                    "⚠".yellow(),
+                    name,
+                    e
+                );
+            }
+        }
+        if info.failed_checks == hooks::FAILURE_STREAK && hooks::configured(config) {
+            let alert = Alert {
+                event: Event::Unhealthy,
+                service: name.to_string(),
+                workspace: config.workspace.clone(),
+                pid: info.pid,
+                port: info.port,
+                message: format!(
+                    "{} failed {} health checks in a row",
+                    name, info.failed_checks
+                ),
+                log_tail: hooks::log_tail(log_file, hooks::LOG_LINES),
+            };
+            if let Err(e) = hooks::notify(config, &alert).await {
+                eprintln!("{} Could not alert the crash hooks: {}", "⚠".yellow(), e);
+            }
+        }
+    }
+    Ok(!recorded.is_empty())
+}
+
+/// Alert the crash hooks about the services found dead since crashes were
+/// last checked, returning what happened for each, for the caller to show.
+///
+/// They are taken out of the state file under its lock before the hooks run,
+/// so each crash is reported once however many commands notice it.
+pub(crate) async fn report_crashes(config: &Config) -> anyhow::Result<Vec<String>> {
+    if ProcessManager::load(&config.state_file)?
+        .crashed()
+        .is_empty()
+    {
+        return Ok(Vec::new());
+    }
+    let crashed = ProcessManager::update(&config.state_file, |manager| {
+        let logs: Vec<_> = manager
+            .crashed()
+            .iter()
+            .map(|(name, _)| manager.log_file(name))
+            .collect();
+        manager
+            .forget_crashed()
+            .into_iter()
+            .zip(logs)
+            .collect::<Vec<_>>()
+    })?;
+    if !hooks::configured(config) {
+        return Ok(Vec::new());
+    }
+    let mut reports = Vec::new();
+    for ((name, info), log_file) in crashed {
+        let alert = Alert {
+            event: Event::Crashed,
+            service: name.clone(),
+            workspace: config.workspace.clone(),
+            pid: info.pid,
+            port: info.port,
+            message: format!("{} (PID {}) exited without being stopped", name, info.pid),
+            log_tail: hooks::log_tail(&log_file, hooks::LOG_LINES),
+        };
+        reports.push(match hooks::notify(config, &alert).await {
+            Ok(()) => format!("{} {}, alerted the crash hooks", "✗".red(), alert.message),
+            Err(e) => format!(
+                "{} {}, and the crash hooks failed: {}",
+                "✗".red(),
+                alert.message,
+                e
+            ),
+        });
+    }
+    Ok(reports)
+}
+
+/// When the fake on `port` last served a request, from its request log
+async fn last_request(port: u16) -> Option<i64> {
+    let log = ControlClient::new(port).requests().await.ok()?;
+    log.requests
+        .iter()
+        .filter_map(|request| request.time)
+        .max_by(f64::total_cmp)
+        .map(|time| time as i64)
+}
+
+/// Seconds a service has been up
+fn uptime(info: &ServiceInfo) -> Option<i64> {
+    let started: i64 = info.started_at.parse().ok()?;
+    Some(dates::now() - started)
+}
+
+/// Redraw the status every `interval`, with the services that started,
+/// stopped, restarted or changed health since the watch began.
+///
+/// Without a terminal, only the changes are printed. Runs until interrupted.
+async fn watch(config: &Config, args: &StatusArgs, interval: Duration) -> anyhow::Result<()> {
+    let terminal = std::io::stdout().is_terminal();
+    let mut seen: Option<BTreeMap<String, Observed>> = None;
+    let mut events: VecDeque<String> = VecDeque::new();
+
+    loop {
+        let crashes = report_crashes(config).await?;
+        let mut manager = ProcessManager::load(&config.state_file)?;
+        let checks = check(config, &mut manager, args).await?;
+        let observed: BTreeMap<String, Observed> = checks
+            .iter()
+            .filter_map(|(name, check)| {
+                let info = manager.get_info(name)?;
+                let observed = Observed {
+                    pid: info.pid,
+                    port: info.port,
+                    healthy: check.healthy,
+                };
+                Some((name.clone(), observed))
+            })
+            .collect();
+
+        let at = dates::format_time(dates::now())[11..19].to_string();
+        let changed = match &seen {
+            Some(seen) => changes(seen, &observed),
+            None if !terminal => {
+                println!(
+                    "{} Watching {} running services (Ctrl+C to stop)",
+                    "ℹ".blue(),
+                    observed.len()
+                );
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+        for change in crashes.into_iter().chain(changed) {
+            let event = format!("{} {}", at.dimmed(), change);
+            if !terminal {
+                println!("{}", event);
+            }
+            events.push_back(event);
+            if events.len() > EVENTS_SHOWN {
+                events.pop_front();
+            }
+        }
+        seen = Some(observed);
+
+        if terminal {
+            // Clear the screen and draw from the top
+            print!("\x1b[2J\x1b[H");
+            println!(
+                "{} every {}s, last at {} UTC (Ctrl+C to stop)",
+                "Watching".bold(),
+                interval.as_secs(),
+                at
+            );
+            println!();
+            print_status(config, &manager, args, &checks);
+            if !events.is_empty() {
+                println!();
+                println!("{}", "Changes:".bold());
+                for event in &events {
+                    println!("  {}", event);
+                }
+            }
+        }
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+            _ = tokio::time::sleep(interval) => {}
+        }
+    }
+}
+
+/// What a watch refresh compares with the one before
+#[derive(Clone, Copy, PartialEq)]
+struct Observed {
+    pid: u32,
+    port: u16,
+    healthy: bool,
+}
+
+/// Describe how the running services changed between two refreshes
+fn changes(before: &BTreeMap<String, Observed>, after: &BTreeMap<String, Observed>) -> Vec<String> {
+    let mut changes = Vec::new();
+    for (name, now) in after {
+        let url = format!("http://localhost:{}", now.port);
+        match before.get(name) {
+            None => changes.push(format!(
+                "{} {} started on {}",
+                "●".green(),
+                name.bold(),
+                url.cyan()
+            )),
+            Some(was) if was.pid != now.pid => changes.push(format!(
+                "{} {} restarted on {} (PID: {})",
+                "↻".blue(),
+                name.bold(),
+                url.cyan(),
+                now.pid
+            )),
+            Some(was) if was.healthy && !now.healthy => {
+                changes.push(format!("{} {} became unhealthy", "✗".red(), name.bold()))
+            }
+            Some(was) if !was.healthy && now.healthy => {
+                changes.push(format!("{} {} became healthy", "✓".green(), name.bold()))
+            }
+            Some(_) => {}
+        }
+    }
+    for name in before.keys().filter(|name| !after.contains_key(*name)) {
+        changes.push(format!("{} {} stopped", "■".red(), name.bold()));
+    }
+    changes
+}
+
+/// Print each service with its health, seeds and config state
+fn print_status(
+    config: &Config,
+    manager: &ProcessManager,
+    args: &StatusArgs,
+    checks: &BTreeMap<String, Check>,
+) {
+    let services: Vec<&String> = checks.keys().collect();
+
+    if services.is_empty() && !args.tags.is_empty() {
+        println!("No running services match the given tags");
+        return;
+    }
 
     if services.is_empty() {
         println!("No services running");
@@ -13,15 +415,24 @@ pub async fn run() -> anyhow::Result<()> {
             "\nUse {} to start services",
             "doubleagent start <service>".cyan()
         );
-        return Ok(());
+        return;
     }
 
-    println!("{}", "Running services:".bold());
+    if config.workspace == workspace::DEFAULT_WORKSPACE {
+        println!("{}", "Running services:".bold());
+    } else {
+        println!(
+            "{} {}",
+            "Running services:".bold(),
+            format!("(workspace: {})", config.workspace).dimmed()
+        );
+    }
     println!();
 
-    for service_name in &services {
+    for service_name in services {
         if let Some(info) = manager.get_info(service_name) {
-            let health = if manager.check_health(service_name).await {
+            let check = &checks[service_name];
+            let health = if check.healthy {
                 "healthy".green()
             } else {
                 "unhealthy".red()
@@ -36,9 +447,128 @@ pub async fn run() -> anyhow::Result<()> {
                 url.cyan(),
                 status
             );
-            println!("    PID: {}  Started: {}", info.pid, info.started_at);
+            let mut line = format!("    PID: {}", info.pid);
+            if let Some(uptime) = uptime(&info) {
+                line.push_str(&format!("  Up: {}", dates::format_span(uptime)));
+            }
+            if info.restarts > 0 {
+                line.push_str(&format!("  Restarts: {}", info.restarts));
+            }
+            println!("{}", line);
+            if let Some(change) = info.health() {
+                if let Ok(at) = change.at.parse::<i64>() {
+                    println!(
+                        "    {} since {} ({} ago)",
+                        if change.healthy {
+                            "Healthy"
+                        } else {
+                            "Unhealthy"
+                        },
+                        dates::format_time(at).dimmed(),
+                        dates::format_span(dates::now() - at)
+                    );
+                }
+            }
+            let now = dates::now();
+            if info.is_flapping(now) {
+                println!(
+                    "    {} flapping: {} health changes in the last {}",
+                    "⚠".yellow(),
+                    health::recent_changes(&info.health_history, now),
+                    dates::format_span(health::FLAP_WINDOW_SECS)
+                );
+            }
+            if let Some(at) = check.last_request {
+                println!(
+                    "    Last request: {} ({} ago)",
+                    dates::format_time(at).dimmed(),
+                    dates::format_span(dates::now() - at)
+                );
+            }
+            if !info.tags.is_empty() {
+                println!("    Tags: {}", tags::format(&info.tags).dimmed());
+            }
+            if let Some(seed) = &info.seed {
+                println!("    Seed: {}", seed.dimmed());
+            }
+            if !info.namespaces.is_empty() {
+                println!("    Namespaces: {}", info.namespaces.join(", ").dimmed());
+            }
+            print_seed_history(service_name, &info.seed_history);
+
+            match reload::definition_status(&info) {
+                DefinitionStatus::Changed => {
+                    println!(
+                        "    {} config changed, restart to apply ({})",
+                        "⚠".yellow(),
+                        "doubleagent apply".cyan()
+                    );
+                }
+                DefinitionStatus::Invalid(e) => {
+                    println!("    {} config changed but is invalid: {}", "✗".red(), e);
+                }
+                DefinitionStatus::Current | DefinitionStatus::Unknown => {}
+            }
+            let project = info.project_config.as_deref().unwrap_or_default();
+            match reload::project_status(&info, &config.cache_dir, service_name) {
+                DefinitionStatus::Changed => {
+                    println!(
+                        "    {} {} changed, apply it with {}",
+                        "⚠".yellow(),
+                        project,
+                        "doubleagent up".cyan()
+                    );
+                }
+                DefinitionStatus::Invalid(e) => {
+                    println!(
+                        "    {} {} changed but is invalid: {}",
+                        "✗".red(),
+                        project,
+                        e
+                    );
+                }
+                DefinitionStatus::Current | DefinitionStatus::Unknown => {}
+            }
         }
     }
+}
 
-    Ok(())
+/// Seeds shown per service, most recent first.
+const HISTORY_SHOWN: usize = 5;
+
+fn print_seed_history(service_name: &str, history: &[SeedRecord]) {
+    if history.is_empty() {
+        return;
+    }
+    println!("    Seeds:");
+    for record in history.iter().rev().take(HISTORY_SHOWN) {
+        let at = record
+            .at
+            .parse()
+            .map(dates::format_time)
+            .unwrap_or_else(|_| record.at.clone());
+        let counts: Vec<String> = record
+            .counts
+            .iter()
+            .map(|(resource, count)| format!("{}={}", resource, count))
+            .collect();
+        println!(
+            "      {} {} {}",
+            at.dimmed(),
+            record.source,
+            counts.join(", ").dimmed()
+        );
+    }
+    if history.len() > HISTORY_SHOWN {
+        println!(
+            "      {}",
+            format!("… {} earlier", history.len() - HISTORY_SHOWN).dimmed()
+        );
+    }
+    if history.last().is_some_and(|record| record.prior.is_some()) {
+        println!(
+            "    Undo the last seed with {}",
+            format!("doubleagent seed {} --undo", service_name).cyan()
+        );
+    }
 }
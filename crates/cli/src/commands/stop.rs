@@ -1,14 +1,11 @@
 use super::StopArgs;
 use colored::Colorize;
-use doubleagent_core::{Config, ProcessManager};
+use doubleagent_core::{tags, Config, ProcessManager};
+use serde_json::json;
 use std::fs;
 use std::path::Path;
 
-/// Env file name for service URLs
-const ENV_FILE: &str = ".doubleagent.env";
-
-pub async fn run(args: StopArgs) -> anyhow::Result<()> {
-    let config = Config::load()?;
+pub async fn run(config: Config, args: StopArgs) -> anyhow::Result<()> {
     let mut manager = ProcessManager::load(&config.state_file)?;
 
     let services: Vec<String> = if args.services.is_empty() {
@@ -17,57 +14,82 @@ pub async fn run(args: StopArgs) -> anyhow::Result<()> {
         args.services
     };
 
+    // Narrow down to services carrying every requested tag
+    let services: Vec<String> = services
+        .into_iter()
+        .filter(|name| {
+            args.tags.is_empty()
+                || manager
+                    .get_info(name)
+                    .is_some_and(|info| tags::matches(&info.tags, &args.tags))
+        })
+        .collect();
+
     if services.is_empty() {
-        println!("No services running");
+        crate::output::result(json!({ "stopped": [], "not_running": [] }));
+        if args.tags.is_empty() {
+            println!("No services running");
+        } else {
+            println!("No running services match the given tags");
+        }
         return Ok(());
     }
 
+    let mut stopped = Vec::new();
+    let mut not_running = Vec::new();
     for service_name in &services {
         if !manager.is_running(service_name) {
             println!("{} {} is not running", "⚠".yellow(), service_name);
+            not_running.push(service_name);
             continue;
         }
 
         print!("{} Stopping {}...", "■".red(), service_name);
         manager.stop(service_name).await?;
         println!(" {}", "✓".green());
+        stopped.push(service_name);
     }
+    crate::output::result(json!({ "stopped": stopped, "not_running": not_running }));
 
     manager.save(&config.state_file)?;
 
-    // Clean up .doubleagent.env if no services are running
+    // Clean up the env file if no services are running
+    let env_file = config.env_file_name();
     if manager.running_services().is_empty() {
-        cleanup_env_file();
+        cleanup_env_file(&env_file);
     } else {
-        // Update .doubleagent.env with remaining services
-        update_env_file(&manager);
+        // Update the env file with remaining services
+        update_env_file(&manager, &env_file);
     }
 
     Ok(())
 }
 
-/// Remove the .doubleagent.env file
-fn cleanup_env_file() {
-    let env_path = Path::new(ENV_FILE);
+/// Remove the workspace's env file (.doubleagent.env)
+fn cleanup_env_file(env_file: &str) {
+    let env_path = Path::new(env_file);
     if env_path.exists() {
         if let Err(e) = fs::remove_file(env_path) {
-            eprintln!("{} Failed to remove {}: {}", "⚠".yellow(), ENV_FILE, e);
+            eprintln!("{} Failed to remove {}: {}", "⚠".yellow(), env_file, e);
         } else {
-            println!("{} Removed {}", "✓".green(), ENV_FILE);
+            println!("{} Removed {}", "✓".green(), env_file);
         }
     }
 }
 
-/// Update .doubleagent.env with remaining running services
-fn update_env_file(manager: &ProcessManager) {
+/// Update the workspace's env file with remaining running services
+pub(crate) fn update_env_file(manager: &ProcessManager, env_file: &str) {
     let services = manager.running_services();
     if services.is_empty() {
-        cleanup_env_file();
+        cleanup_env_file(env_file);
         return;
     }
 
     let mut content = String::from("# Generated by doubleagent - do not edit\n");
-    content.push_str("# Load with: source .doubleagent.env (bash) or use dotenv library\n\n");
+    content.push_str(&format!(
+        "# Load with: source {} (bash) or use dotenv library\n\n",
+        env_file
+    ));
 
     for name in &services {
         if let Some(info) = manager.get_info(name) {
@@ -76,9 +98,9 @@ fn update_env_file(manager: &ProcessManager) {
         }
     }
 
-    if let Err(e) = fs::write(ENV_FILE, &content) {
-        eprintln!("{} Failed to update {}: {}", "⚠".yellow(), ENV_FILE, e);
+    if let Err(e) = fs::write(env_file, &content) {
+        eprintln!("{} Failed to update {}: {}", "⚠".yellow(), env_file, e);
     } else {
-        println!("{} Updated {}", "✓".green(), ENV_FILE);
+        println!("{} Updated {}", "✓".green(), env_file);
     }
 }
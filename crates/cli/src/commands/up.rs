@@ -0,0 +1,115 @@
+use super::UpArgs;
+use colored::Colorize;
+use doubleagent_core::extends;
+use doubleagent_core::manifest::Manifest;
+use doubleagent_core::{Config, ProcessManager};
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::time::Duration;
+
+/// How often `up --watch` checks the project config for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+pub async fn run(config: Config, args: UpArgs) -> anyhow::Result<()> {
+    let path = super::down::project_config_path(&config)?;
+    let manifest = Manifest::from_project_config(&path, &config.cache_dir)?;
+
+    if manifest.services.is_empty() && !args.watch {
+        println!("{} No services declared in {}", "ℹ".blue(), path.display());
+        return Ok(());
+    }
+
+    for report in super::status::report_crashes(&config).await? {
+        eprintln!("{}", report);
+    }
+    let mut manager = ProcessManager::load(&config.state_file)?;
+
+    // Services started outside doubleagent.yaml are left alone
+    super::apply::apply_manifest(&config, &mut manager, &manifest, &path, args.dry_run, false)
+        .await?;
+
+    if args.watch {
+        watch(&config, &path, manifest).await?;
+    }
+    Ok(())
+}
+
+/// Re-apply the project config whenever it (or a config it extends) changes.
+///
+/// Services added to the config are started, removed ones are stopped, and
+/// changed ones are restarted. Runs until interrupted; services keep running.
+async fn watch(config: &Config, path: &Path, mut current: Manifest) -> anyhow::Result<()> {
+    println!();
+    println!(
+        "{} Watching {} for changes (Ctrl+C to stop watching)",
+        "ℹ".blue(),
+        path.display()
+    );
+    let mut seen = fingerprint(config, path);
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {
+                println!();
+                println!("{} Stopped watching; services are still running", "ℹ".blue());
+                return Ok(());
+            }
+            _ = tokio::time::sleep(WATCH_INTERVAL) => {}
+        }
+
+        let latest = fingerprint(config, path);
+        if latest == seen {
+            continue;
+        }
+        seen = latest;
+
+        println!();
+        println!("{} {} changed", "↻".blue(), path.display());
+        let manifest = match Manifest::from_project_config(path, &config.cache_dir) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                println!("{} Not applied: {}", "✗".red(), e);
+                continue;
+            }
+        };
+
+        // Only a config that was applied becomes the one to diff against, so
+        // services it dropped are still stopped when a later edit applies
+        match reconcile(config, path, &current, &manifest).await {
+            Ok(()) => current = manifest,
+            Err(e) => println!("{} {:#}", "✗".red(), e),
+        }
+    }
+}
+
+/// Stop services dropped from the config, then converge on the new one.
+async fn reconcile(
+    config: &Config,
+    path: &Path,
+    previous: &Manifest,
+    manifest: &Manifest,
+) -> anyhow::Result<()> {
+    let mut manager = ProcessManager::load(&config.state_file)?;
+
+    let removed: BTreeSet<&String> = previous
+        .services
+        .keys()
+        .filter(|name| !manifest.services.contains_key(*name) && manager.is_running(name))
+        .collect();
+    for name in removed {
+        print!("{} Stopping {}...", "■".red(), name);
+        manager.stop(name).await?;
+        println!(" {}", "✓".green());
+    }
+    manager.save(&config.state_file)?;
+
+    super::apply::apply_manifest(config, &mut manager, manifest, path, false, false).await
+}
+
+/// The merged project config, or the error loading it, for change detection.
+fn fingerprint(config: &Config, path: &Path) -> String {
+    match extends::load(path, &config.cache_dir) {
+        Ok(value) => serde_yaml::to_string(&value).unwrap_or_default(),
+        Err(e) => e.to_string(),
+    }
+}
@@ -2,8 +2,7 @@ use super::UpdateArgs;
 use colored::Colorize;
 use doubleagent_core::{Config, ServiceRegistry};
 
-pub async fn run(args: UpdateArgs) -> anyhow::Result<()> {
-    let config = Config::load()?;
+pub async fn run(config: Config, args: UpdateArgs) -> anyhow::Result<()> {
     let registry = ServiceRegistry::new(&config.services_dir, &config.repo_url, &config.branch)?;
 
     if args.services.is_empty() {
@@ -0,0 +1,263 @@
+use super::UpgradeArgs;
+use anyhow::Context;
+use colored::Colorize;
+use doubleagent_core::git::ServiceBackup;
+use doubleagent_core::ports::{self, PortAllocator};
+use doubleagent_core::{
+    workspace, Config, ControlClient, Error, ProcessManager, ServiceDefinition, ServiceInfo,
+    ServiceRegistry, StartOptions,
+};
+use std::fs;
+use std::path::Path;
+
+pub async fn run(config: Config, args: UpgradeArgs) -> anyhow::Result<()> {
+    let mut manager = ProcessManager::load(&config.state_file)?;
+    let registry = ServiceRegistry::new(&config.services_dir, &config.repo_url, &config.branch)?;
+
+    let name = &args.service;
+    let old = manager
+        .get_info(name)
+        .ok_or_else(|| anyhow::anyhow!("{} is not running", name))?;
+
+    // Capture state from the running instance before touching anything
+    let state = if args.skip_state {
+        None
+    } else {
+        print!("{} Exporting state from port {}...", "⬆".blue(), old.port);
+        match ControlClient::new(old.port).export().await {
+            Ok(state) => {
+                println!(" {}", "✓".green());
+                Some(state)
+            }
+            Err(Error::ExportUnsupported) => {
+                println!(" {}", "⚠".yellow());
+                println!(
+                    "  {} {} does not support export, so the new version starts empty",
+                    "⚠".yellow(),
+                    name
+                );
+                None
+            }
+            Err(e) => {
+                println!(" {}", "✗".red());
+                return Err(anyhow::anyhow!(
+                    "Failed to export state from {}: {}\n\
+                     Use --skip-state to upgrade without migrating state.",
+                    name,
+                    e
+                ));
+            }
+        }
+    };
+
+    // Try the new version alongside the old one, on a port of the port range
+    // and with its own log, before anything is switched over
+    let mut ports = PortAllocator::new(
+        config.port_range,
+        config.base_port,
+        workspace::ports_in_use(&config.state_dir)?,
+    );
+    let port = match args.port {
+        Some(port) => ports.claim(port)?,
+        None => ports
+            .allocate_free()
+            .context("Failed to allocate a port for the new instance")?,
+    };
+
+    // Fetch the new version (services started from a local directory are
+    // reloaded in place), keeping the installed files to roll back to
+    let (service, backup) = if Path::new(&old.service_path).starts_with(&config.services_dir) {
+        print!("{} Fetching latest {}...", "⬇".blue(), name);
+        let backup = registry.backup(name)?;
+        let fetched = registry.update(name).and_then(|_| registry.get(name));
+        match fetched {
+            Ok(service) => {
+                println!(" {}", "✓".green());
+                (service, Some(backup))
+            }
+            Err(e) => {
+                println!(" {}", "✗".red());
+                backup.restore()?;
+                return Err(e.into());
+            }
+        }
+    } else {
+        (
+            ServiceDefinition::from_dir(Path::new(&old.service_path))?,
+            None,
+        )
+    };
+
+    let health = config.health_for(name);
+    let trial_log = manager.trial_log_file(name);
+    println!("{} Trying new {} on port {}...", "▶".blue(), name, port);
+    let options = StartOptions {
+        env: old.env_overrides.clone(),
+        log_file: Some(trial_log.clone()),
+    };
+    if let Err(e) = manager.start_with(&service, port, options).await {
+        // Nothing new is running, and the old instance is still tracked
+        if let Some(backup) = backup {
+            backup.restore()?;
+        }
+        return Err(e.into());
+    }
+
+    print!("  Waiting for health check...");
+    if let Err(e) = manager.wait_for_health(name, port, &health).await {
+        println!(" {}", "✗".red());
+        rollback(&mut manager, &config, name, old, backup).await?;
+        return Err(anyhow::anyhow!(
+            "Health check failed: {} (see {})",
+            e,
+            trial_log.display()
+        ));
+    }
+    println!(" {}", "✓".green());
+
+    if let Some(state) = &state {
+        print!("  Migrating state...");
+        if let Err(e) = ControlClient::new(port).seed(state).await {
+            println!(" {}", "✗".red());
+            rollback(&mut manager, &config, name, old, backup).await?;
+            return Err(anyhow::anyhow!(
+                "Failed to migrate state: {} (see {})",
+                e,
+                trial_log.display()
+            ));
+        }
+        println!(" {}", "✓".green());
+    }
+
+    // Export again, so changes made to the old instance meanwhile move over too
+    let state = match state {
+        Some(_) => match ControlClient::new(old.port).export().await {
+            Ok(state) => Some(state),
+            Err(e) => {
+                rollback(&mut manager, &config, name, old, backup).await?;
+                return Err(anyhow::anyhow!(
+                    "Failed to export state from {}: {}",
+                    name,
+                    e
+                ));
+            }
+        },
+        None => None,
+    };
+    manager.stop(name).await?;
+
+    // Switch over on the original port, so clients keep the URL they have
+    print!(
+        "{} Switching {} over on port {} (old PID: {})...",
+        "↻".blue(),
+        name,
+        old.port,
+        old.pid
+    );
+    let options = StartOptions {
+        env: old.env_overrides.clone(),
+        ..Default::default()
+    };
+    let switched = match manager
+        .replace_in_place(&old, &service, options, &health)
+        .await
+    {
+        Ok(pid) => match &state {
+            Some(state) => ControlClient::new(old.port).seed(state).await.map(|_| pid),
+            None => Ok(pid),
+        },
+        Err(e) => Err(e),
+    };
+    let pid = match switched {
+        Ok(pid) => pid,
+        Err(e) => {
+            println!(" {}", "✗".red());
+            restore_in_place(&mut manager, &config, name, &old, backup, state.as_ref()).await?;
+            return Err(anyhow::anyhow!("Failed to switch over: {}", e));
+        }
+    };
+    println!(" {}", "✓".green());
+    carry_over(&mut manager, name, &old);
+    manager.save(&config.state_file)?;
+    let _ = fs::remove_file(&trial_log);
+    if let Some(backup) = backup {
+        backup.discard()?;
+    }
+
+    println!();
+    println!(
+        "{} {} upgraded, still on {} (PID: {})",
+        "✓".green(),
+        name.bold(),
+        format!("http://localhost:{}", old.port).cyan(),
+        pid
+    );
+
+    Ok(())
+}
+
+/// Keep what the state records about the instance a new one replaced.
+fn carry_over(manager: &mut ProcessManager, name: &str, old: &ServiceInfo) {
+    manager.set_tags(name, old.tags.clone());
+    manager.record_restart(name, old);
+    manager.set_seed(name, old.seed.clone());
+    for record in &old.seed_history {
+        manager.record_seed(name, record.clone());
+    }
+}
+
+/// Stop the new instance, put the previous version's files back and point
+/// the state back at the old instance.
+async fn rollback(
+    manager: &mut ProcessManager,
+    config: &Config,
+    name: &str,
+    old: ServiceInfo,
+    backup: Option<ServiceBackup>,
+) -> anyhow::Result<()> {
+    manager.stop(name).await?;
+    if let Some(backup) = backup {
+        backup.restore()?;
+    }
+    manager.track(name, old);
+    manager.save(&config.state_file)?;
+    println!("{} Kept the previous {} instance running", "ℹ".blue(), name);
+    Ok(())
+}
+
+/// Start the previous version again on its port, with the state it had,
+/// after the old instance was already stopped for a switch-over that failed.
+async fn restore_in_place(
+    manager: &mut ProcessManager,
+    config: &Config,
+    name: &str,
+    old: &ServiceInfo,
+    backup: Option<ServiceBackup>,
+    state: Option<&serde_json::Value>,
+) -> anyhow::Result<()> {
+    manager.stop(name).await?;
+    if let Some(backup) = backup {
+        backup.restore()?;
+    }
+    let service = ServiceDefinition::from_dir(Path::new(&old.service_path))?;
+    let options = StartOptions {
+        env: old.env_overrides.clone(),
+        ..Default::default()
+    };
+    let health = config.health_for(name);
+    ports::wait_until_free(old.port, health.startup_timeout).await?;
+    manager.start_with(&service, old.port, options).await?;
+    carry_over(manager, name, old);
+    manager.save(&config.state_file)?;
+    manager.wait_for_health(name, old.port, &health).await?;
+    if let Some(state) = state {
+        ControlClient::new(old.port).seed(state).await?;
+    }
+    println!(
+        "{} Put the previous {} back on port {}",
+        "ℹ".blue(),
+        name,
+        old.port
+    );
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use super::ValidateArgs;
+use colored::Colorize;
+use doubleagent_core::validate::{self, Diagnostic, Severity};
+use doubleagent_core::{Config, ServiceRegistry};
+use std::path::{Path, PathBuf};
+
+pub async fn run(config: Config, args: ValidateArgs) -> anyhow::Result<()> {
+    let registry = ServiceRegistry::new(&config.services_dir, &config.repo_url, &config.branch)?;
+
+    let mut results: Vec<(PathBuf, Vec<Diagnostic>)> = Vec::new();
+
+    if args.paths.is_empty() {
+        // Validate the project config and every service it references
+        let project_path = config.project_config_path.clone().ok_or_else(|| {
+            anyhow::anyhow!(
+                "No doubleagent.yaml found. Pass a service directory or config file to validate."
+            )
+        })?;
+
+        results.push((
+            project_path.clone(),
+            validate::validate_project_config(&project_path, &registry, &config.cache_dir),
+        ));
+
+        if let Some(project) =
+            crate::project_config::ProjectConfig::try_load(Some(&project_path), &config.cache_dir)
+        {
+            for name in &project.services {
+                if let Some(dir) = registry.service_dir(name) {
+                    results.push((
+                        dir.join("service.yaml"),
+                        validate::validate_service_dir(&dir),
+                    ));
+                }
+            }
+        }
+    } else {
+        for path in &args.paths {
+            let path = Path::new(path);
+            if path.is_dir() || path.file_name().is_some_and(|n| n == "service.yaml") {
+                let dir = if path.is_dir() {
+                    path.to_path_buf()
+                } else {
+                    path.parent().map(Path::to_path_buf).unwrap_or_default()
+                };
+                results.push((
+                    dir.join("service.yaml"),
+                    validate::validate_service_dir(&dir),
+                ));
+            } else {
+                results.push((
+                    path.to_path_buf(),
+                    validate::validate_project_config(path, &registry, &config.cache_dir),
+                ));
+            }
+        }
+    }
+
+    let mut errors = 0;
+    let mut warnings = 0;
+
+    for (file, diagnostics) in &results {
+        if validate::has_errors(diagnostics) {
+            println!("{} {}", "✗".red(), file.display());
+        } else if diagnostics.is_empty() {
+            println!("{} {}", "✓".green(), file.display());
+        } else {
+            println!("{} {}", "⚠".yellow(), file.display());
+        }
+
+        for diagnostic in diagnostics {
+            match diagnostic.severity {
+                Severity::Error => {
+                    errors += 1;
+                    println!("    {} {}", "error:".red(), diagnostic);
+                }
+                Severity::Warning => {
+                    warnings += 1;
+                    println!("    {} {}", "warning:".yellow(), diagnostic);
+                }
+            }
+            if let Some(hint) = &diagnostic.hint {
+                println!("      {} {}", "hint:".dimmed(), hint);
+            }
+        }
+    }
+
+    println!();
+    if errors > 0 {
+        return Err(anyhow::anyhow!(
+            "{} error(s), {} warning(s) found",
+            errors,
+            warnings
+        ));
+    }
+
+    println!(
+        "{} Configuration is valid ({} warning(s))",
+        "✓".green(),
+        warnings
+    );
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use super::ValidateSpecArgs;
+use colored::Colorize;
+use doubleagent_core::conformance::{self, Check, Request, Spec};
+use doubleagent_core::ports::PortAllocator;
+use doubleagent_core::{workspace, Config, ProcessManager};
+use serde_json::json;
+use std::io::Write;
+
+pub async fn run(config: Config, args: ValidateSpecArgs) -> anyhow::Result<()> {
+    let service = super::snapshot::service_for(&config, &args.service)?;
+    let name = &service.name;
+    let spec = Spec::load(&service)?;
+    let requests = requests(&spec, args.requests.as_deref())?;
+
+    // Check the running fake, or one started just for the check
+    let mut manager = ProcessManager::load(&config.state_file)?;
+    let (port, started) = match manager.get_info(name) {
+        Some(info) => (info.port, false),
+        None => {
+            let port = PortAllocator::new(
+                config.port_range,
+                config.contract_port,
+                workspace::ports_in_use(&config.state_dir)?,
+            )
+            .allocate()?;
+            println!("{} Starting {} service...", "▶".blue(), name);
+            manager.start(&service, port).await?;
+            manager.save(&config.state_file)?;
+            if let Err(e) = manager
+                .wait_for_health(name, port, &config.health_for(name))
+                .await
+            {
+                manager.stop(name).await?;
+                manager.save(&config.state_file)?;
+                return Err(anyhow::anyhow!("Health check failed: {}", e));
+            }
+            (port, true)
+        }
+    };
+
+    println!(
+        "{} Checking {} responses of {} against its OpenAPI description",
+        "▶".blue(),
+        requests.len(),
+        name.bold()
+    );
+    println!();
+    let checks = conformance::run(&format!("http://localhost:{}", port), &spec, &requests).await;
+
+    if started {
+        manager.stop(name).await?;
+        manager.save(&config.state_file)?;
+    }
+
+    let checks = checks?;
+    let failed = print_checks(&checks, &mut std::io::stdout())?;
+    crate::output::result(json!({
+        "checked": checks.len(),
+        "failed": failed,
+        "checks": checks
+            .iter()
+            .map(|check| json!({
+                "request": check.request.to_string(),
+                "status": check.status,
+                "violations": check.violations,
+            }))
+            .collect::<Vec<_>>(),
+    }));
+    println!();
+    if failed > 0 {
+        let message = format!(
+            "{} of {} responses don't match the description",
+            failed,
+            requests.len()
+        );
+        println!("{} {}", "✗".red(), message);
+        crate::output::failed("spec_violations", &anyhow::anyhow!(message));
+        std::process::exit(1);
+    }
+    println!("{} All responses match the description", "✓".green());
+    Ok(())
+}
+
+/// The requests in `file`, or those generated from the description
+fn requests(spec: &Spec, file: Option<&str>) -> anyhow::Result<Vec<Request>> {
+    let requests = match file {
+        Some(file) => conformance::parse_requests(&std::fs::read_to_string(file)?)?,
+        None => spec.requests(),
+    };
+    if requests.is_empty() {
+        anyhow::bail!("No requests to check; pass a --requests file");
+    }
+    Ok(requests)
+}
+
+/// Print each check with its violations, returning how many failed
+pub(crate) fn print_checks(checks: &[Check], out: &mut dyn Write) -> std::io::Result<usize> {
+    let mut failed = 0;
+    for check in checks {
+        if check.violations.is_empty() {
+            writeln!(
+                out,
+                "  {} {} ({})",
+                "✓".green(),
+                check.request,
+                check.status
+            )?;
+            continue;
+        }
+        failed += 1;
+        writeln!(out, "  {} {} ({})", "✗".red(), check.request, check.status)?;
+        for violation in &check.violations {
+            writeln!(out, "      {}", violation)?;
+        }
+    }
+    Ok(failed)
+}
@@ -1,4 +1,5 @@
 mod commands;
+mod output;
 mod project_config;
 
 use anyhow::Context;
@@ -15,40 +16,144 @@ async fn main() {
         )
         .init();
 
-    if let Err(err) = run().await {
+    let cli = commands::Cli::parse();
+    if let Err(err) = output::init(cli.output_format) {
         print_error(&err);
         std::process::exit(1);
     }
+    if let Err(err) = run(cli).await {
+        print_error(&err);
+        std::process::exit(exit_code(&err));
+    }
+    output::succeeded();
 }
 
 /// Execute a command with automatic error context
 macro_rules! run_command {
-    ($name:expr, $cmd:expr) => {
+    ($name:expr, $cmd:expr) => {{
+        output::command($name);
         $cmd.await
             .with_context(|| format!("Command '{}' failed", $name))
-    };
+    }};
 }
 
-async fn run() -> anyhow::Result<()> {
-    let cli = commands::Cli::parse();
+async fn run(cli: commands::Cli) -> anyhow::Result<()> {
+    // Resolve settings once for every command: flag > env > project > global > default
+    let loaded = doubleagent_core::Config::load_with(cli.overrides()?);
+    let changes_state = cli.command.changes_state();
+
+    // Doctor reports a configuration that doesn't load rather than failing on it
+    if let commands::Commands::Doctor(args) = cli.command {
+        let operations = loaded
+            .as_ref()
+            .ok()
+            .filter(|_| changes_state)
+            .map(|config| (config.operation_log(), config.workspace.clone()));
+        let result = run_command!("doctor", commands::doctor::run(loaded, args));
+        record_operation(operations, &result);
+        return result;
+    }
+
+    let config = loaded.context("Failed to load configuration")?;
+    let operations = changes_state.then(|| (config.operation_log(), config.workspace.clone()));
 
-    match cli.command {
-        commands::Commands::Add(args) => run_command!("add", commands::add::run(args)),
-        commands::Commands::Start(args) => run_command!("start", commands::start::run(args)),
-        commands::Commands::Stop(args) => run_command!("stop", commands::stop::run(args)),
-        commands::Commands::Status => run_command!("status", commands::status::run()),
-        commands::Commands::Reset(args) => run_command!("reset", commands::reset::run(args)),
-        commands::Commands::Seed(args) => run_command!("seed", commands::seed::run(args)),
-        commands::Commands::List(args) => run_command!("list", commands::list::run(args)),
+    let result = match cli.command {
+        commands::Commands::Init(args) => run_command!("init", commands::init::run(config, args)),
+        commands::Commands::Add(args) => run_command!("add", commands::add::run(config, args)),
+        commands::Commands::Up(args) => run_command!("up", commands::up::run(config, args)),
+        commands::Commands::Down(args) => run_command!("down", commands::down::run(config, args)),
+        commands::Commands::Start(args) => {
+            run_command!("start", commands::start::run(config, args))
+        }
+        commands::Commands::Stop(args) => run_command!("stop", commands::stop::run(config, args)),
+        commands::Commands::Status(args) => {
+            run_command!("status", commands::status::run(config, args))
+        }
+        commands::Commands::Dashboard(args) => {
+            run_command!("dashboard", commands::dashboard::run(config, args))
+        }
+        commands::Commands::Logs(args) => run_command!("logs", commands::logs::run(config, args)),
+        commands::Commands::Metrics(args) => {
+            run_command!("metrics", commands::metrics::run(config, args))
+        }
+        commands::Commands::Reset(args) => {
+            run_command!("reset", commands::reset::run(config, args))
+        }
+        commands::Commands::Seed(args) => run_command!("seed", commands::seed::run(config, args)),
+        commands::Commands::Dump(args) => run_command!("dump", commands::dump::run(config, args)),
+        commands::Commands::Requests(args) => {
+            run_command!("requests", commands::requests::run(config, args))
+        }
+        commands::Commands::Namespace(args) => {
+            run_command!("namespace", commands::namespace::run(config, args))
+        }
+        commands::Commands::Snapshot(args) => {
+            run_command!("snapshot", commands::snapshot::run(config, args))
+        }
+        commands::Commands::Audit(args) => {
+            run_command!("audit", commands::audit::run(config, args))
+        }
+        commands::Commands::List(args) => run_command!("list", commands::list::run(config, args)),
         commands::Commands::Contract(args) => {
-            run_command!("contract", commands::contract::run(args))
+            run_command!("contract", commands::contract::run(config, args))
+        }
+        commands::Commands::Update(args) => {
+            run_command!("update", commands::update::run(config, args))
+        }
+        commands::Commands::Run(args) => run_command!("run", commands::run::run(config, args)),
+        commands::Commands::Apply(args) => {
+            run_command!("apply", commands::apply::run(config, args))
+        }
+        commands::Commands::Upgrade(args) => {
+            run_command!("upgrade", commands::upgrade::run(config, args))
+        }
+        commands::Commands::Config(args) => {
+            run_command!("config", commands::config::run(config, args))
+        }
+        commands::Commands::Doctor(_) => unreachable!("doctor runs before the config is required"),
+        commands::Commands::Validate(args) => {
+            run_command!("validate", commands::validate::run(config, args))
+        }
+        commands::Commands::ValidateSpec(args) => {
+            run_command!("validate-spec", commands::validate_spec::run(config, args))
+        }
+        commands::Commands::Fuzz(args) => run_command!("fuzz", commands::fuzz::run(config, args)),
+        commands::Commands::DiffProxy(args) => {
+            run_command!("diff-proxy", commands::diff_proxy::run(config, args))
         }
-        commands::Commands::Update(args) => run_command!("update", commands::update::run(args)),
-        commands::Commands::Run(args) => run_command!("run", commands::run::run(args)),
+        commands::Commands::MigrateConfig(args) => {
+            run_command!(
+                "migrate-config",
+                commands::migrate_config::run(config, args)
+            )
+        }
+    };
+
+    record_operation(operations, &result);
+    result
+}
+
+/// Append the command to the operation log of the workspace it changed, if any.
+fn record_operation(
+    operations: Option<(doubleagent_core::operations::OperationLog, String)>,
+    result: &anyhow::Result<()>,
+) {
+    if let Some((log, workspace)) = operations {
+        let command = output::command_name().unwrap_or_default();
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        commands::audit::record_operation(&log, &workspace, &command, &args, result);
     }
 }
 
+/// The code to exit with after `err`: that of the command `run` wrapped, or 1.
+fn exit_code(err: &anyhow::Error) -> i32 {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<commands::run::CommandFailed>())
+        .map_or(1, |failed| failed.code)
+}
+
 fn print_error(err: &anyhow::Error) {
+    output::failed(output::error_code(err), err);
     eprintln!("{} {}", "Error:".red().bold(), err);
 
     // Print the error chain
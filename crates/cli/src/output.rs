@@ -0,0 +1,155 @@
+//! Machine-readable results, for `--output-format json`.
+//!
+//! In JSON mode what commands print for people goes to stderr, without
+//! colors, and stdout carries one JSON document, written when the command
+//! ends:
+//!
+//! ```json
+//! {"ok": true, "command": "start", "result": {"services": [...]}}
+//! {"ok": false, "command": "start", "error": {"code": "health_check_failed", "message": "...", "causes": [...]}}
+//! ```
+//!
+//! Commands hand over what they did with [`result`]; the result of those that
+//! don't is `null`. A failed command keeps the result it set, e.g. the seed
+//! report of a seed that failed partway.
+
+use crate::commands::{contract, run};
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::FromRawFd;
+use std::sync::{Mutex, OnceLock};
+
+/// How commands report what they did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Colored text for people
+    #[default]
+    Text,
+    /// One JSON document on stdout, with the text on stderr
+    Json,
+}
+
+/// The process's stdout, once JSON mode has moved the text to stderr.
+static STDOUT: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// The running command's name and result.
+static COMMAND: Mutex<Option<String>> = Mutex::new(None);
+static RESULT: Mutex<Option<Value>> = Mutex::new(None);
+
+/// Switch to `format`, before anything is printed.
+pub fn init(format: OutputFormat) -> anyhow::Result<()> {
+    if format == OutputFormat::Text {
+        return Ok(());
+    }
+    colored::control::set_override(false);
+    // Keep stdout for the document, and send everything else printed to stderr
+    let stdout = unsafe { libc::dup(libc::STDOUT_FILENO) };
+    if stdout < 0 || unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+        anyhow::bail!(
+            "Could not set up JSON output: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    let _ = STDOUT.set(Mutex::new(unsafe { File::from_raw_fd(stdout) }));
+    Ok(())
+}
+
+/// Whether commands report in JSON.
+pub fn is_json() -> bool {
+    STDOUT.get().is_some()
+}
+
+/// Refuse an option that only makes sense for people, such as following
+/// output as it comes, in JSON mode.
+pub fn text_only(option: &str) -> anyhow::Result<()> {
+    if is_json() {
+        anyhow::bail!("{} can't be used with --output-format json", option);
+    }
+    Ok(())
+}
+
+/// Name the running command, as the document will.
+pub fn command(name: &str) {
+    *COMMAND.lock().unwrap() = Some(name.to_string());
+}
+
+/// Name of the running command.
+pub fn command_name() -> Option<String> {
+    COMMAND.lock().unwrap().clone()
+}
+
+/// Set the running command's result.
+pub fn result(value: impl Serialize) {
+    if is_json() {
+        let value = serde_json::to_value(value).unwrap_or(Value::Null);
+        *RESULT.lock().unwrap() = Some(value);
+    }
+}
+
+/// Write the document of a command that succeeded.
+pub fn succeeded() {
+    let result = RESULT.lock().unwrap().take();
+    write(json!({
+        "ok": true,
+        "command": COMMAND.lock().unwrap().clone(),
+        "result": result,
+    }));
+}
+
+/// Write the document of a command that failed with `err`, which `code`
+/// tells apart from other failures.
+pub fn failed(code: &str, err: &anyhow::Error) {
+    let result = RESULT.lock().unwrap().take();
+    let causes: Vec<String> = err.chain().map(ToString::to_string).collect();
+    write(json!({
+        "ok": false,
+        "command": COMMAND.lock().unwrap().clone(),
+        "error": {
+            "code": code,
+            // The whole chain, e.g. "Command 'dump' failed: github is not running"
+            "message": format!("{:#}", err),
+            "causes": causes,
+        },
+        "result": result,
+    }));
+}
+
+/// Code of an error, from the first core error in its chain.
+pub fn error_code(err: &anyhow::Error) -> &'static str {
+    use doubleagent_core::Error;
+    if err.chain().any(|cause| cause.is::<contract::FakeFailed>()) {
+        return "fake_failed";
+    }
+    if err.chain().any(|cause| cause.is::<run::CommandFailed>()) {
+        return "command_failed";
+    }
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<Error>())
+        .map_or("error", |error| match error {
+            Error::ServiceNotFound(_) => "service_not_found",
+            Error::ServiceAlreadyRunning(_) => "service_already_running",
+            Error::HealthCheckFailed(_) => "health_check_failed",
+            Error::HealthCheckTimeout(_) => "health_check_timeout",
+            Error::ServiceProcessDied => "service_process_died",
+            Error::ExportUnsupported => "export_unsupported",
+            Error::GitError(_) => "git_error",
+            Error::IoError(_) => "io_error",
+            Error::YamlError(_) => "yaml_error",
+            Error::JsonError(_) => "json_error",
+            Error::HttpError(_) => "http_error",
+            Error::Other(_) => "error",
+        })
+}
+
+fn write(document: Value) {
+    if let Some(stdout) = STDOUT.get() {
+        // Text printed without a newline is still buffered for stderr
+        let _ = std::io::stdout().flush();
+        let mut stdout = stdout.lock().unwrap();
+        let _ = writeln!(stdout, "{}", document);
+        let _ = stdout.flush();
+    }
+}
@@ -1,25 +1,25 @@
-use serde::{Deserialize, Serialize};
-use std::fs;
+use doubleagent_core::manifest::Manifest;
 use std::path::Path;
 
 /// Project configuration from doubleagent.yaml
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct ProjectConfig {
     /// List of services required by this project
-    #[serde(default)]
     pub services: Vec<String>,
 }
 
 impl ProjectConfig {
-    /// Load project config from a file path
-    pub fn load(path: &Path) -> anyhow::Result<Self> {
-        let content = fs::read_to_string(path)?;
-        let config: ProjectConfig = serde_yaml::from_str(&content)?;
-        Ok(config)
+    /// Load project config from a file path, interpolating environment variables
+    /// and merging in the configs it extends
+    pub fn load(path: &Path, cache_dir: &Path) -> anyhow::Result<Self> {
+        let manifest = Manifest::from_project_config(path, cache_dir)?;
+        Ok(Self {
+            services: manifest.services.into_keys().collect(),
+        })
     }
 
     /// Try to load project config, returning None if it doesn't exist
-    pub fn try_load(path: Option<&Path>) -> Option<Self> {
-        path.and_then(|p| Self::load(p).ok())
+    pub fn try_load(path: Option<&Path>, cache_dir: &Path) -> Option<Self> {
+        path.and_then(|p| Self::load(p, cache_dir).ok())
     }
 }
@@ -0,0 +1,175 @@
+//! Artifacts of failed contract runs, for CI to keep.
+//!
+//! When the tests fail against a fake, `doubleagent contract` collects what
+//! it takes to see why before the fake is stopped, into
+//! `<dir>/<YYYYMMDDTHHMMSSZ>/<service>/`:
+//!
+//! - `service.log`: the fake's output (see [`crate::process`])
+//! - `state.json`: its state, from `/_doubleagent/export`
+//! - `requests.json`: the requests it served, from `/_doubleagent/requests`
+//! - `manifest.json`: which of those were collected, and why the others
+//!   weren't
+//!
+//! Those a fake can't give, e.g. because it never became healthy or has no
+//! export endpoint, are left out, noted in the manifest, and the rest
+//! collected.
+
+use crate::control::ControlClient;
+use crate::dates;
+use crate::Result;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory artifacts are collected in unless told otherwise.
+pub const DEFAULT_DIR: &str = "doubleagent-artifacts";
+
+/// Directory for the artifacts of a run started at `now` (Unix seconds),
+/// named by its UTC time so that runs sort in order.
+pub fn run_dir(base: &Path, now: i64) -> PathBuf {
+    let stamp: String = dates::format_time(now)
+        .chars()
+        .filter(|c| !matches!(c, '-' | ':'))
+        .collect();
+    base.join(stamp)
+}
+
+/// File in each service's directory listing what was collected.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// The artifacts collected for one service.
+#[derive(Debug)]
+pub struct Collected {
+    pub dir: PathBuf,
+    /// Names of the files written
+    pub files: Vec<String>,
+    /// Artifacts that couldn't be collected, each with the reason
+    pub missing: Vec<String>,
+}
+
+/// Collect a service's artifacts in `run_dir`, from its log file and the
+/// fake listening on `port`.
+pub async fn collect(run_dir: &Path, service: &str, port: u16, log: &Path) -> Result<Collected> {
+    let dir = run_dir.join(service);
+    fs::create_dir_all(&dir)?;
+    let mut files = Vec::new();
+    // Artifact -> why it's missing
+    let mut reasons = BTreeMap::new();
+
+    match fs::copy(log, dir.join("service.log")) {
+        Ok(_) => files.push("service.log".to_string()),
+        Err(e) => {
+            reasons.insert("service.log", e.to_string());
+        }
+    }
+    let client = ControlClient::new(port);
+    let dumps = [
+        ("state.json", client.export().await),
+        ("requests.json", client.requests_json().await),
+    ];
+    for (name, dump) in dumps {
+        match dump {
+            Ok(value) => {
+                fs::write(dir.join(name), serde_json::to_string_pretty(&value)? + "\n")?;
+                files.push(name.to_string());
+            }
+            Err(e) => {
+                reasons.insert(name, e.to_string());
+            }
+        }
+    }
+
+    let manifest = json!({
+        "service": service,
+        "port": port,
+        "collected_at": dates::format_time(dates::now()),
+        "files": files,
+        "missing": reasons,
+    });
+    fs::write(
+        dir.join(MANIFEST_FILE),
+        serde_json::to_string_pretty(&manifest)? + "\n",
+    )?;
+    let missing = reasons
+        .iter()
+        .map(|(name, reason)| format!("{} ({})", name, reason))
+        .collect();
+
+    Ok(Collected {
+        dir,
+        files,
+        missing,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::rest::tests::serve;
+
+    #[test]
+    fn test_run_dir() {
+        assert_eq!(
+            run_dir(Path::new("artifacts"), 1_700_000_000),
+            Path::new("artifacts/20231114T221320Z")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_collect() {
+        let (url, server) = serve(vec![
+            json!({"repos": [{"id": 1, "name": "api"}]}),
+            json!({"requests": [{"method": "GET", "path": "/repos/api", "status": 404}]}),
+        ]);
+        let port = url.rsplit(':').next().unwrap().parse().unwrap();
+        let temp = tempfile::tempdir().unwrap();
+        let log = temp.path().join("github.log");
+        fs::write(&log, "listening on 8080\n").unwrap();
+
+        let collected = collect(temp.path(), "github", port, &log).await.unwrap();
+        assert_eq!(
+            collected.files,
+            ["service.log", "state.json", "requests.json"]
+        );
+        assert!(collected.missing.is_empty());
+        let manifest: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(temp.path().join("github").join(MANIFEST_FILE)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(manifest["missing"], json!({}));
+        let dir = temp.path().join("github");
+        assert_eq!(
+            fs::read_to_string(dir.join("service.log")).unwrap(),
+            "listening on 8080\n"
+        );
+        let state: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dir.join("state.json")).unwrap()).unwrap();
+        assert_eq!(state["repos"][0]["name"], "api");
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("GET /_doubleagent/export"));
+    }
+
+    #[tokio::test]
+    async fn test_collect_without_a_fake() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let temp = tempfile::tempdir().unwrap();
+        let log = temp.path().join("github.log");
+        fs::write(&log, "Traceback: address in use\n").unwrap();
+
+        let collected = collect(temp.path(), "github", port, &log).await.unwrap();
+        assert_eq!(collected.files, ["service.log"]);
+        assert_eq!(collected.missing.len(), 2);
+        assert!(collected.missing[1].starts_with("state.json"));
+        let manifest: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(temp.path().join("github").join(MANIFEST_FILE)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(manifest["files"], json!(["service.log"]));
+        assert!(manifest["missing"]["state.json"].is_string());
+    }
+}
@@ -0,0 +1,210 @@
+//! Audit log of operations on production-derived snapshot data.
+//!
+//! When the compliance mode isn't `off`, every pull, refresh, fetch, push and
+//! seed of a snapshot profile appends one JSON line to
+//! `<state_dir>/audit/audit.jsonl`: who did it and when, which service and
+//! profile, how much of it was redacted and where the data went. Entries are
+//! only ever appended; `doubleagent audit export` reads them back.
+
+use crate::snapshot::SnapshotManifest;
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// File in the audit directory entries are appended to.
+pub const AUDIT_FILE: &str = "audit.jsonl";
+
+/// What was done with a profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    /// Pulled from the real service
+    Pull,
+    /// Brought up to date from the real service
+    Refresh,
+    /// Downloaded from the shared registry
+    Fetch,
+    /// Uploaded to the shared registry
+    Push,
+    /// Loaded into a running fake
+    Seed,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Operation::Pull => "pull",
+            Operation::Refresh => "refresh",
+            Operation::Fetch => "fetch",
+            Operation::Push => "push",
+            Operation::Seed => "seed",
+        };
+        f.write_str(name)
+    }
+}
+
+/// One recorded operation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// When it happened (seconds since the Unix epoch)
+    pub timestamp: u64,
+    /// Local user who ran it
+    pub user: String,
+    /// What was done
+    pub operation: Operation,
+    /// Service the profile belongs to
+    pub service: String,
+    /// Profile name
+    pub profile: String,
+    /// Connector that produced the profile
+    pub connector: String,
+    /// Number of values redacted in the profile (0 if none were)
+    pub redactions: usize,
+    /// Where the data went: a local directory, registry URL or service URL
+    pub destination: String,
+    /// Compliance mode in effect
+    pub compliance_mode: String,
+}
+
+impl AuditEntry {
+    /// Describe an operation on a profile done now by the current user.
+    pub fn new(
+        operation: Operation,
+        manifest: &SnapshotManifest,
+        destination: impl Into<String>,
+        compliance_mode: &str,
+    ) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            user: current_user(),
+            operation,
+            service: manifest.service.clone(),
+            profile: manifest.profile.clone(),
+            connector: manifest.connector.clone(),
+            redactions: manifest.redactions.values().sum(),
+            destination: destination.into(),
+            compliance_mode: compliance_mode.to_string(),
+        }
+    }
+}
+
+/// The append-only audit log in a directory.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    dir: PathBuf,
+}
+
+impl AuditLog {
+    /// Log kept in `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Path of the log file.
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(AUDIT_FILE)
+    }
+
+    /// Append an entry, creating the log readable only by its owner.
+    pub fn append(&self, entry: &AuditEntry) -> Result<()> {
+        append_line(&self.dir, &self.path(), &serde_json::to_string(entry)?)
+    }
+
+    /// Every entry, oldest first. A log that doesn't exist yet is empty.
+    pub fn entries(&self) -> Result<Vec<AuditEntry>> {
+        let path = self.path();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        read_entries(&path)
+    }
+}
+
+/// Append a line to a log file in `dir`, creating it readable only by its owner.
+pub(crate) fn append_line(dir: &Path, path: &Path, line: &str) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    // One write per entry, so concurrent commands never interleave lines
+    file.write_all(format!("{}\n", line).as_bytes())?;
+    file.sync_data()?;
+    Ok(())
+}
+
+fn read_entries(path: &Path) -> Result<Vec<AuditEntry>> {
+    let mut entries = Vec::new();
+    for (i, line) in BufReader::new(fs::File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line)
+            .map_err(|e| Error::Other(format!("{} line {}: {}", path.display(), i + 1, e)))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+pub(crate) fn current_user() -> String {
+    ["USER", "USERNAME"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_entries_are_appended_in_order() {
+        let dir = tempdir().unwrap();
+        let log = AuditLog::new(dir.path().join("audit"));
+        assert!(log.entries().unwrap().is_empty());
+
+        let seed = json!({"users": [{"email": "a@example.com"}]});
+        let mut manifest = SnapshotManifest::new("github", "prod", "rest", &seed);
+        manifest.redactions.insert("users[].email".to_string(), 1);
+
+        let pull = AuditEntry::new(Operation::Pull, &manifest, "/data/github/prod", "strict");
+        let push = AuditEntry::new(
+            Operation::Push,
+            &manifest,
+            "s3://team/github/prod",
+            "strict",
+        );
+        log.append(&pull).unwrap();
+        log.append(&push).unwrap();
+
+        let entries = log.entries().unwrap();
+        assert_eq!(entries, vec![pull, push]);
+        assert_eq!(entries[0].redactions, 1);
+        assert_eq!(
+            fs::read_to_string(log.path()).unwrap().lines().count(),
+            2,
+            "one line per entry"
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(log.path()).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
@@ -0,0 +1,357 @@
+//! Compliance modes and the `compliance.yaml` policy for production-derived data.
+//!
+//! The `compliance_mode` setting grades how snapshot pulls, fetches, pushes and
+//! seeds are checked:
+//!
+//! - `off`: nothing is checked or audited
+//! - `warn`: problems are reported, but nothing is stopped
+//! - `allow-redacted`: policy violations stop the operation; profiles that went
+//!   through redaction are trusted, so values `snapshot scan` still flags are
+//!   only reported
+//! - `strict`: policy violations and flagged values both stop the operation
+//!
+//! An optional policy, `compliance.yaml` next to doubleagent.yaml or in the
+//! global config directory, narrows what is allowed:
+//!
+//! ```yaml
+//! allowed_services: [github, slack]  # default: every service
+//! required_redactions:               # paths whose values must be redacted
+//!   github: [users.email, users.name]
+//! banned_registries:                 # `*` matches anything; entries match as prefixes
+//!   - "https://*"
+//!   - s3://public-*
+//! ```
+
+use crate::redact;
+use crate::snapshot::SnapshotManifest;
+use crate::{Error, Result};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// File name of the compliance policy.
+pub const POLICY_FILE: &str = "compliance.yaml";
+
+/// How strictly production-derived data is checked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ComplianceMode {
+    /// No checks
+    #[default]
+    Off,
+    /// Report problems without stopping
+    Warn,
+    /// Stop on policy violations, trusting redacted profiles
+    AllowRedacted,
+    /// Stop on policy violations and unredacted values
+    Strict,
+}
+
+impl ComplianceMode {
+    /// Every mode, mildest first.
+    pub const ALL: &'static [ComplianceMode] = &[
+        ComplianceMode::Off,
+        ComplianceMode::Warn,
+        ComplianceMode::AllowRedacted,
+        ComplianceMode::Strict,
+    ];
+
+    /// Whether a violation stops the operation in this mode.
+    pub fn blocks(self, violation: &Violation) -> bool {
+        match self {
+            ComplianceMode::Off | ComplianceMode::Warn => false,
+            ComplianceMode::AllowRedacted => !violation.flagged,
+            ComplianceMode::Strict => true,
+        }
+    }
+}
+
+impl FromStr for ComplianceMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|mode| mode.to_string() == s)
+            .ok_or_else(|| {
+                let names: Vec<String> = Self::ALL.iter().map(|m| format!("'{}'", m)).collect();
+                format!("expected one of {}, got '{}'", names.join(", "), s)
+            })
+    }
+}
+
+impl fmt::Display for ComplianceMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ComplianceMode::Off => "off",
+            ComplianceMode::Warn => "warn",
+            ComplianceMode::AllowRedacted => "allow-redacted",
+            ComplianceMode::Strict => "strict",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Rules from `compliance.yaml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Services whose data may be handled (default: every service)
+    #[serde(default)]
+    pub allowed_services: Option<Vec<String>>,
+    /// Paths, per service, whose values must have been redacted
+    #[serde(default)]
+    pub required_redactions: BTreeMap<String, Vec<String>>,
+    /// Registry URLs profiles may not be pushed to or fetched from
+    #[serde(default)]
+    pub banned_registries: Vec<String>,
+}
+
+impl Policy {
+    /// Load a policy file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        serde_yaml::from_str::<Option<Policy>>(&content)
+            .map(Option::unwrap_or_default)
+            .map_err(|e| Error::Other(format!("Invalid {}: {}", path.display(), e)))
+    }
+}
+
+/// Something an operation would do that the policy doesn't allow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// What is wrong
+    pub message: String,
+    /// Whether it was found by scanning for unredacted values, rather than
+    /// breaking a rule of the policy
+    pub flagged: bool,
+}
+
+impl Violation {
+    fn rule(message: String) -> Self {
+        Self {
+            message,
+            flagged: false,
+        }
+    }
+}
+
+/// The compliance mode with the policy it enforces.
+#[derive(Debug, Clone, Default)]
+pub struct Compliance {
+    /// Mode in effect
+    pub mode: ComplianceMode,
+    /// Policy rules (empty without a policy file)
+    pub policy: Policy,
+    /// Where the policy was loaded from
+    pub policy_path: Option<PathBuf>,
+}
+
+impl Compliance {
+    /// Load the policy from the first of `candidates` that exists.
+    pub fn load(mode: ComplianceMode, candidates: &[PathBuf]) -> Result<Self> {
+        let mut compliance = Self {
+            mode,
+            ..Self::default()
+        };
+        if let Some(path) = candidates.iter().find(|p| p.is_file()) {
+            compliance.policy = Policy::load(path)?;
+            compliance.policy_path = Some(path.clone());
+        }
+        Ok(compliance)
+    }
+
+    /// Whether anything is checked at all.
+    pub fn enabled(&self) -> bool {
+        self.mode != ComplianceMode::Off
+    }
+
+    /// Problems with handling data of `service`.
+    pub fn check_service(&self, service: &str) -> Vec<Violation> {
+        match &self.policy.allowed_services {
+            Some(allowed) if !allowed.iter().any(|s| s == service) => {
+                vec![Violation::rule(format!(
+                    "{} is not in the policy's allowed_services",
+                    service
+                ))]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Problems with transferring profiles through the registry at `url`.
+    pub fn check_registry(&self, url: &str) -> Vec<Violation> {
+        self.policy
+            .banned_registries
+            .iter()
+            .filter(|banned| prefix_match(banned, url))
+            .map(|banned| {
+                Violation::rule(format!(
+                    "registry {} is banned by the policy ({})",
+                    url, banned
+                ))
+            })
+            .collect()
+    }
+
+    /// Problems with a stored profile and its seed payload.
+    pub fn check_profile(&self, manifest: &SnapshotManifest, seed: &Value) -> Vec<Violation> {
+        let mut violations = self.check_service(&manifest.service);
+        let required = self
+            .policy
+            .required_redactions
+            .get(&manifest.service)
+            .into_iter()
+            .flatten();
+        for path in required {
+            let present = count_values(seed, &path.split('.').collect::<Vec<_>>());
+            if present > 0 && manifest.redactions.get(path).copied().unwrap_or(0) == 0 {
+                violations.push(Violation::rule(format!(
+                    "{}/{} holds {} values at {} that the policy requires to be redacted",
+                    manifest.service, manifest.profile, present, path
+                )));
+            }
+        }
+        for finding in redact::scan(seed, &manifest.pseudonyms) {
+            violations.push(Violation {
+                message: format!(
+                    "{}/{} has {} unredacted {} values at {}",
+                    manifest.service,
+                    manifest.profile,
+                    finding.count,
+                    finding.detector,
+                    finding.path
+                ),
+                flagged: true,
+            });
+        }
+        violations
+    }
+
+    /// Fail with every violation the mode blocks on, or return those it only
+    /// reports. `action` describes what was checked, e.g. "push github/prod".
+    pub fn settle(&self, action: &str, violations: Vec<Violation>) -> Result<Vec<Violation>> {
+        if !self.enabled() {
+            return Ok(Vec::new());
+        }
+        let (blocking, reported): (Vec<_>, Vec<_>) =
+            violations.into_iter().partition(|v| self.mode.blocks(v));
+        if blocking.is_empty() {
+            return Ok(reported);
+        }
+        let mut message = format!("Can't {} in {} compliance mode:", action, self.mode);
+        for violation in &blocking {
+            message.push_str(&format!("\n  {}", violation.message));
+        }
+        if let Some(path) = &self.policy_path {
+            message.push_str(&format!("\n(policy: {})", path.display()));
+        }
+        Err(Error::Other(message))
+    }
+}
+
+/// Whether `url` starts with something matching `pattern`, where `*` matches
+/// any characters.
+fn prefix_match(pattern: &str, url: &str) -> bool {
+    let regex = format!("^{}", regex::escape(pattern).replace(r"\*", ".*"));
+    Regex::new(&regex).is_ok_and(|re| re.is_match(url))
+}
+
+/// Number of non-null values at a dotted path, looking through lists.
+fn count_values(value: &Value, path: &[&str]) -> usize {
+    match value {
+        Value::Array(items) => items.iter().map(|item| count_values(item, path)).sum(),
+        Value::Null => 0,
+        _ => match path.split_first() {
+            None => 1,
+            Some((field, rest)) => value.get(field).map_or(0, |v| count_values(v, rest)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn compliance(mode: ComplianceMode, policy: &str) -> Compliance {
+        Compliance {
+            mode,
+            policy: serde_yaml::from_str(policy).unwrap(),
+            policy_path: None,
+        }
+    }
+
+    #[test]
+    fn test_modes_parse_and_grade() {
+        for mode in ComplianceMode::ALL {
+            assert_eq!(mode.to_string().parse::<ComplianceMode>(), Ok(*mode));
+        }
+        assert!("lenient".parse::<ComplianceMode>().is_err());
+
+        let rule = Violation::rule("not allowed".to_string());
+        let flagged = Violation {
+            message: "email".to_string(),
+            flagged: true,
+        };
+        let blocked = |mode: ComplianceMode| (mode.blocks(&rule), mode.blocks(&flagged));
+        assert_eq!(blocked(ComplianceMode::Warn), (false, false));
+        assert_eq!(blocked(ComplianceMode::AllowRedacted), (true, false));
+        assert_eq!(blocked(ComplianceMode::Strict), (true, true));
+    }
+
+    #[test]
+    fn test_policy_rules() {
+        let policy = "allowed_services: [github]\n\
+                      required_redactions:\n  github: [users.email]\n\
+                      banned_registries: [\"https://*\", s3://public-]\n";
+        let strict = compliance(ComplianceMode::Strict, policy);
+
+        assert!(strict.check_service("github").is_empty());
+        assert_eq!(strict.check_service("slack").len(), 1);
+        assert_eq!(strict.check_registry("s3://public-snapshots/da").len(), 1);
+        assert_eq!(
+            strict.check_registry("https://example.com/snapshots").len(),
+            1
+        );
+        assert!(strict.check_registry("s3://team/da").is_empty());
+
+        let seed = json!({"users": [{"email": "x-1f2e"}, {"email": null}]});
+        let mut manifest = SnapshotManifest::new("github", "prod", "rest", &seed);
+        let violations = strict.check_profile(&manifest, &seed);
+        assert_eq!(violations.len(), 1, "{:?}", violations);
+        assert!(violations[0].message.contains("1 values at users.email"));
+
+        manifest.redactions.insert("users.email".to_string(), 1);
+        assert!(strict.check_profile(&manifest, &seed).is_empty());
+    }
+
+    #[test]
+    fn test_settle_by_mode() {
+        let seed = json!({"users": [{"email": "ada@corp.com"}]});
+        let manifest = SnapshotManifest::new("slack", "prod", "rest", &seed);
+        let policy = "allowed_services: [github]\n";
+        let check = |mode| {
+            let compliance = compliance(mode, policy);
+            compliance.settle(
+                "seed slack/prod",
+                compliance.check_profile(&manifest, &seed),
+            )
+        };
+
+        assert!(check(ComplianceMode::Off).unwrap().is_empty());
+        assert_eq!(check(ComplianceMode::Warn).unwrap().len(), 2);
+        let msg = check(ComplianceMode::AllowRedacted)
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("allowed_services"), "{}", msg);
+        assert!(!msg.contains("unredacted"), "{}", msg);
+        let msg = check(ComplianceMode::Strict).unwrap_err().to_string();
+        assert!(msg.contains("unredacted email"), "{}", msg);
+    }
+}
@@ -1,85 +1,823 @@
 //! Configuration management for DoubleAgent.
 
-use crate::git::DEFAULT_REPO_URL;
-use crate::Result;
-use std::path::PathBuf;
+use crate::audit::AuditLog;
+use crate::compliance::{self, Compliance, ComplianceMode};
+use crate::health::{HealthOverrides, HealthSettings};
+use crate::operations::OperationLog;
+use crate::ports::PortRange;
+use crate::settings::{key_spec, Overrides, Settings};
+use crate::workspace::{self, DEFAULT_WORKSPACE};
+use crate::{Error, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-/// Environment variable to override the services repository URL
-const REPO_URL_ENV: &str = "DOUBLEAGENT_SERVICES_REPO";
-/// Environment variable to override the branch to fetch services from
-const BRANCH_ENV: &str = "DOUBLEAGENT_BRANCH";
+/// Environment variable to override the config directory (config.yaml)
+const CONFIG_DIR_ENV: &str = "DOUBLEAGENT_CONFIG_DIR";
+/// Environment variable to override the cache directory (services, repo clone)
+const CACHE_DIR_ENV: &str = "DOUBLEAGENT_CACHE_DIR";
+/// Environment variable to override the state directory (state.json)
+const STATE_DIR_ENV: &str = "DOUBLEAGENT_STATE_DIR";
+/// Environment variable to override the data directory (snapshots)
+const DATA_DIR_ENV: &str = "DOUBLEAGENT_DATA_DIR";
+
+/// Project config file names, in order of preference
+pub const PROJECT_CONFIG_NAMES: &[&str] = &[
+    "doubleagent.yaml",
+    "doubleagent.yml",
+    "doubleagent.toml",
+    "doubleagent.json",
+];
+
+/// Project-local services directory, relative to the directory that contains it
+const PROJECT_SERVICES_DIR: &str = ".doubleagent/services";
 
 /// Configuration for DoubleAgent operations.
 pub struct Config {
-    /// Directory where services are cached (from remote repo)
+    /// Global config file ($XDG_CONFIG_HOME/doubleagent/config.yaml)
+    pub config_file: PathBuf,
+    /// Directory for re-creatable files ($XDG_CACHE_HOME/doubleagent)
+    pub cache_dir: PathBuf,
+    /// Directory for runtime state ($XDG_STATE_HOME/doubleagent)
+    pub state_dir: PathBuf,
+    /// Directory for user data such as snapshots ($XDG_DATA_HOME/doubleagent)
+    pub data_dir: PathBuf,
+    /// Directory where services are installed (from remote repo)
     pub services_dir: PathBuf,
-    /// State file for tracking running processes
+    /// Project-local services directory (.doubleagent/services) if one was found.
+    /// When set, it is also `services_dir`.
+    pub project_services_dir: Option<PathBuf>,
+    /// Workspace whose services commands operate on (defaults to "default")
+    pub workspace: String,
+    /// State file for tracking running processes in the workspace
     pub state_file: PathBuf,
     /// URL of the services monorepo
     pub repo_url: String,
     /// Branch to fetch services from (defaults to "main")
     pub branch: String,
+    /// Port for the first started service (defaults to 8080)
+    pub base_port: u16,
+    /// Ports services may be started on (defaults to 1024-65535)
+    pub port_range: PortRange,
+    /// Preferred port for services started by contract tests (defaults to 18080)
+    pub contract_port: u16,
+    /// Health check and startup timing (defaults to 500ms polls, 2s requests, 30s startup)
+    pub health: HealthSettings,
+    /// Per-service overrides of `health`, keyed by service name
+    pub service_health: BTreeMap<String, HealthOverrides>,
+    /// Compliance mode for production-derived data (defaults to off)
+    pub compliance_mode: ComplianceMode,
+    /// Shared registry snapshots are fetched from, if configured
+    pub registry: Option<String>,
+    /// Secret pseudonymized snapshot values are derived from (defaults to "")
+    pub pseudonym_seed: String,
+    /// OTLP collector started fakes send trace spans to, if configured
+    pub otel_endpoint: Option<String>,
+    /// URL alerts of services starting to flap are posted to, if configured
+    pub flap_webhook: Option<String>,
+    /// Shell command run when a service crashes, if configured
+    pub crash_hook: Option<String>,
+    /// URL alerts of crashed services are posted to, if configured
+    pub crash_webhook: Option<String>,
     /// Path to project config file (doubleagent.yaml) if it exists
     pub project_config_path: Option<PathBuf>,
+    /// Command-line overrides the settings were resolved with
+    pub overrides: Overrides,
 }
 
 impl Config {
     /// Load configuration from default locations.
     ///
-    /// Creates necessary directories if they don't exist.
+    /// Directories follow the XDG base directory spec, each overridable with a
+    /// DOUBLEAGENT_*_DIR environment variable. A legacy ~/.doubleagent layout is
+    /// migrated on first use. Creates necessary directories if they don't exist.
     pub fn load() -> Result<Self> {
-        // Data directory: ~/.doubleagent
-        let data_dir = dirs::home_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join(".doubleagent");
+        Self::load_with(Overrides::default())
+    }
 
-        std::fs::create_dir_all(&data_dir)?;
+    /// Load configuration like [`Config::load`], with command-line overrides
+    /// taking precedence over every other layer.
+    pub fn load_with(overrides: Overrides) -> Result<Self> {
+        let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
 
-        // Services are cached in the data directory
-        let services_dir = data_dir.join("services");
-        std::fs::create_dir_all(&services_dir)?;
+        let config_dir = resolve_dir(CONFIG_DIR_ENV, "XDG_CONFIG_HOME", &home, ".config");
+        let cache_dir = resolve_dir(CACHE_DIR_ENV, "XDG_CACHE_HOME", &home, ".cache");
+        let state_dir = resolve_dir(STATE_DIR_ENV, "XDG_STATE_HOME", &home, ".local/state");
+        let data_dir = resolve_dir(DATA_DIR_ENV, "XDG_DATA_HOME", &home, ".local/share");
 
-        // Get repo URL from environment or use default
-        let repo_url = std::env::var(REPO_URL_ENV).unwrap_or_else(|_| DEFAULT_REPO_URL.to_string());
+        let global_services_dir = cache_dir.join("services");
 
-        // Get branch from environment or use default
-        let branch = std::env::var(BRANCH_ENV).unwrap_or_else(|_| "main".to_string());
+        migrate_legacy_layout(
+            &home.join(".doubleagent"),
+            &[
+                ("services", global_services_dir.as_path()),
+                ("state.json", state_dir.join("state.json").as_path()),
+                ("snapshots", data_dir.join("snapshots").as_path()),
+            ],
+        );
 
         // Look for project config file
         let project_config_path = Self::find_project_config();
 
-        Ok(Self {
-            services_dir,
-            state_file: data_dir.join("state.json"),
-            repo_url,
-            branch,
-            project_config_path,
-        })
+        // A project opts into hermetic installs by creating .doubleagent/services
+        let project_services_dir = std::env::current_dir()
+            .ok()
+            .and_then(|cwd| find_project_services_dir(&cwd, &home));
+
+        // Resolve settings: flag > env > project config > global config > default
+        let config_file = config_dir.join("config.yaml");
+        let settings = Settings::load(&config_file, project_config_path.as_deref(), &cache_dir)?
+            .with_overrides(overrides.clone());
+
+        let mut builder = Self::builder()
+            .config_file(config_file)
+            .cache_dir(cache_dir)
+            .state_dir(state_dir)
+            .data_dir(data_dir)
+            .services_dir(global_services_dir)
+            .workspace(settings.get("workspace")?)
+            .repo_url(settings.get("repo_url")?)
+            .branch(settings.get("branch")?)
+            .base_port(settings.get_parsed("base_port")?)
+            .port_range(settings.get_parsed("port_range")?)
+            .contract_port(settings.get_parsed("contract_port")?)
+            .health(HealthSettings {
+                poll_interval: Duration::from_millis(
+                    settings.get_parsed("health_poll_interval_ms")?,
+                ),
+                request_timeout: Duration::from_millis(
+                    settings.get_parsed("health_request_timeout_ms")?,
+                ),
+                startup_timeout: Duration::from_secs(settings.get_parsed("startup_timeout_secs")?),
+            })
+            .compliance_mode(settings.get_parsed("compliance_mode")?)
+            .pseudonym_seed(settings.get("pseudonym_seed")?)
+            .overrides(overrides);
+        let registry = settings.get("registry")?;
+        if !registry.is_empty() {
+            builder = builder.registry(registry);
+        }
+        let otel_endpoint = settings.get("otel_endpoint")?;
+        if !otel_endpoint.is_empty() {
+            builder = builder.otel_endpoint(otel_endpoint);
+        }
+        let flap_webhook = settings.get("flap_webhook")?;
+        if !flap_webhook.is_empty() {
+            builder = builder.flap_webhook(flap_webhook);
+        }
+        let crash_hook = settings.get("crash_hook")?;
+        if !crash_hook.is_empty() {
+            builder = builder.crash_hook(crash_hook);
+        }
+        let crash_webhook = settings.get("crash_webhook")?;
+        if !crash_webhook.is_empty() {
+            builder = builder.crash_webhook(crash_webhook);
+        }
+        for (service, overrides) in settings.service_health()? {
+            builder = builder.service_health(service, overrides);
+        }
+        if let Some(dir) = project_services_dir {
+            builder = builder.project_services_dir(dir);
+        }
+        if let Some(path) = project_config_path {
+            builder = builder.project_config_path(path);
+        }
+        builder.build()
+    }
+
+    /// The built-in settings in the usual directories, ignoring every config
+    /// file, for `doctor` to carry on with when the configuration won't load.
+    pub fn load_defaults() -> Result<Self> {
+        let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
+        let config_dir = resolve_dir(CONFIG_DIR_ENV, "XDG_CONFIG_HOME", &home, ".config");
+        let cache_dir = resolve_dir(CACHE_DIR_ENV, "XDG_CACHE_HOME", &home, ".cache");
+        Self::builder()
+            .config_file(config_dir.join("config.yaml"))
+            .services_dir(cache_dir.join("services"))
+            .cache_dir(cache_dir)
+            .state_dir(resolve_dir(
+                STATE_DIR_ENV,
+                "XDG_STATE_HOME",
+                &home,
+                ".local/state",
+            ))
+            .data_dir(resolve_dir(
+                DATA_DIR_ENV,
+                "XDG_DATA_HOME",
+                &home,
+                ".local/share",
+            ))
+            .build()
+    }
+
+    /// The settings layers this configuration was resolved from.
+    pub fn settings(&self) -> Result<Settings> {
+        Ok(Settings::load(
+            &self.config_file,
+            self.project_config_path.as_deref(),
+            &self.cache_dir,
+        )?
+        .with_overrides(self.overrides.clone()))
+    }
+
+    /// Name of the env file written for the workspace in the current directory.
+    pub fn env_file_name(&self) -> String {
+        workspace::env_file_name(&self.workspace)
+    }
+
+    /// Health check and startup timing for a service, including its overrides.
+    pub fn health_for(&self, service: &str) -> HealthSettings {
+        match self.service_health.get(service) {
+            Some(overrides) => self.health.with_overrides(overrides),
+            None => self.health,
+        }
+    }
+
+    /// Audit log of snapshot operations, unless compliance mode is off.
+    pub fn audit_log(&self) -> Option<AuditLog> {
+        (self.compliance_mode != ComplianceMode::Off).then(|| AuditLog::new(self.audit_dir()))
+    }
+
+    /// The compliance mode with its policy, read from `compliance.yaml` next to
+    /// doubleagent.yaml or else next to the global config file.
+    pub fn compliance(&self) -> Result<Compliance> {
+        let candidates: Vec<PathBuf> = [
+            self.project_config_path.as_deref(),
+            Some(self.config_file.as_path()),
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|file| file.parent())
+        .map(|dir| dir.join(compliance::POLICY_FILE))
+        .collect();
+        Compliance::load(self.compliance_mode, &candidates)
+    }
+
+    /// Log of state-changing commands, kept in the audit directory.
+    pub fn operation_log(&self) -> OperationLog {
+        OperationLog::new(self.audit_dir())
+    }
+
+    /// Directory holding the audit log (`audit/` in the state directory).
+    pub fn audit_dir(&self) -> PathBuf {
+        self.state_dir.join("audit")
+    }
+
+    /// Directory holding the history of contract runs (`contract-history/` in
+    /// the data directory).
+    pub fn history_dir(&self) -> PathBuf {
+        self.data_dir.join("contract-history")
+    }
+
+    /// Directory holding file-based secrets (next to the global config file).
+    pub fn secrets_dir(&self) -> PathBuf {
+        self.config_file
+            .parent()
+            .map(|dir| dir.join("secrets"))
+            .unwrap_or_else(|| PathBuf::from("secrets"))
+    }
+
+    /// Start building a configuration with explicit paths and settings.
+    ///
+    /// Unlike [`Config::load`], the builder never reads environment variables,
+    /// config files or the current directory, which makes it suitable for
+    /// embedding and tests.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
     }
 
-    /// Find project config file (doubleagent.yaml) by traversing up from cwd
+    /// Find project config file (doubleagent.yaml, .yml, .toml or .json) by
+    /// traversing up from cwd
     fn find_project_config() -> Option<PathBuf> {
         let cwd = std::env::current_dir().ok()?;
+        find_project_config_from(&cwd)
+    }
+}
 
-        let mut dir = cwd.as_path();
-        loop {
-            let config_path = dir.join("doubleagent.yaml");
-            if config_path.exists() {
-                return Some(config_path);
-            }
+/// Builder for [`Config`], created with [`Config::builder`].
+///
+/// Directories that are not set are derived from `root` (`root/config`,
+/// `root/cache`, `root/state`, `root/data`); `services_dir` and `state_file`
+/// default to locations inside the cache and state directories. Settings
+/// default to their built-in values.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder {
+    root: Option<PathBuf>,
+    config_file: Option<PathBuf>,
+    cache_dir: Option<PathBuf>,
+    state_dir: Option<PathBuf>,
+    data_dir: Option<PathBuf>,
+    services_dir: Option<PathBuf>,
+    project_services_dir: Option<PathBuf>,
+    workspace: Option<String>,
+    state_file: Option<PathBuf>,
+    repo_url: Option<String>,
+    branch: Option<String>,
+    base_port: Option<u16>,
+    port_range: Option<PortRange>,
+    contract_port: Option<u16>,
+    health: Option<HealthSettings>,
+    service_health: BTreeMap<String, HealthOverrides>,
+    compliance_mode: Option<ComplianceMode>,
+    registry: Option<String>,
+    pseudonym_seed: Option<String>,
+    otel_endpoint: Option<String>,
+    flap_webhook: Option<String>,
+    crash_hook: Option<String>,
+    crash_webhook: Option<String>,
+    project_config_path: Option<PathBuf>,
+    overrides: Overrides,
+}
 
-            // Also check for .yml extension
-            let config_path = dir.join("doubleagent.yml");
-            if config_path.exists() {
-                return Some(config_path);
-            }
+impl ConfigBuilder {
+    /// Directory under which unset directories are placed.
+    pub fn root(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.root = Some(dir.into());
+        self
+    }
+
+    /// Global config file.
+    pub fn config_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_file = Some(path.into());
+        self
+    }
+
+    /// Directory for re-creatable files.
+    pub fn cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+
+    /// Directory for runtime state.
+    pub fn state_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.state_dir = Some(dir.into());
+        self
+    }
+
+    /// Directory for user data such as snapshots.
+    pub fn data_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = Some(dir.into());
+        self
+    }
+
+    /// Directory where services are installed.
+    pub fn services_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.services_dir = Some(dir.into());
+        self
+    }
+
+    /// Project-local services directory, used instead of `services_dir`.
+    pub fn project_services_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.project_services_dir = Some(dir.into());
+        self
+    }
+
+    /// Workspace whose services commands operate on.
+    pub fn workspace(mut self, name: impl Into<String>) -> Self {
+        self.workspace = Some(name.into());
+        self
+    }
+
+    /// State file for tracking running processes (default: the workspace's state file).
+    pub fn state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state_file = Some(path.into());
+        self
+    }
+
+    /// URL of the services monorepo.
+    pub fn repo_url(mut self, url: impl Into<String>) -> Self {
+        self.repo_url = Some(url.into());
+        self
+    }
+
+    /// Branch to fetch services from.
+    pub fn branch(mut self, branch: impl Into<String>) -> Self {
+        self.branch = Some(branch.into());
+        self
+    }
+
+    /// Port for the first started service.
+    pub fn base_port(mut self, port: u16) -> Self {
+        self.base_port = Some(port);
+        self
+    }
+
+    /// Ports services may be started on.
+    pub fn port_range(mut self, range: PortRange) -> Self {
+        self.port_range = Some(range);
+        self
+    }
+
+    /// Preferred port for services started by contract tests.
+    pub fn contract_port(mut self, port: u16) -> Self {
+        self.contract_port = Some(port);
+        self
+    }
+
+    /// Health check and startup timing.
+    pub fn health(mut self, health: HealthSettings) -> Self {
+        self.health = Some(health);
+        self
+    }
+
+    /// Override health check and startup timing for one service.
+    pub fn service_health(
+        mut self,
+        service: impl Into<String>,
+        overrides: HealthOverrides,
+    ) -> Self {
+        self.service_health.insert(service.into(), overrides);
+        self
+    }
+
+    /// Compliance mode for production-derived data.
+    pub fn compliance_mode(mut self, mode: ComplianceMode) -> Self {
+        self.compliance_mode = Some(mode);
+        self
+    }
 
-            match dir.parent() {
-                Some(parent) => dir = parent,
-                None => break,
+    /// Shared registry snapshots are fetched from.
+    pub fn registry(mut self, url: impl Into<String>) -> Self {
+        self.registry = Some(url.into());
+        self
+    }
+
+    /// Secret pseudonymized snapshot values are derived from.
+    pub fn pseudonym_seed(mut self, seed: impl Into<String>) -> Self {
+        self.pseudonym_seed = Some(seed.into());
+        self
+    }
+
+    /// OTLP collector started fakes send trace spans to.
+    pub fn otel_endpoint(mut self, url: impl Into<String>) -> Self {
+        self.otel_endpoint = Some(url.into());
+        self
+    }
+
+    /// URL alerts of services starting to flap are posted to.
+    pub fn flap_webhook(mut self, url: impl Into<String>) -> Self {
+        self.flap_webhook = Some(url.into());
+        self
+    }
+
+    /// Shell command run when a service crashes.
+    pub fn crash_hook(mut self, command: impl Into<String>) -> Self {
+        self.crash_hook = Some(command.into());
+        self
+    }
+
+    /// URL alerts of crashed services are posted to.
+    pub fn crash_webhook(mut self, url: impl Into<String>) -> Self {
+        self.crash_webhook = Some(url.into());
+        self
+    }
+
+    /// Project config file (doubleagent.yaml).
+    pub fn project_config_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.project_config_path = Some(path.into());
+        self
+    }
+
+    /// Command-line overrides, reported by [`Config::settings`].
+    pub fn overrides(mut self, overrides: Overrides) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// Build the configuration, creating its directories if they don't exist.
+    pub fn build(self) -> Result<Config> {
+        let root = self.root;
+        let dir = |explicit: Option<PathBuf>, name: &str| -> Result<PathBuf> {
+            explicit
+                .or_else(|| root.as_ref().map(|r| r.join(name)))
+                .ok_or_else(|| {
+                    Error::Other(format!("Config::builder() requires {}_dir or root", name))
+                })
+        };
+
+        let config_file = match self.config_file {
+            Some(path) => path,
+            None => dir(None, "config")?.join("config.yaml"),
+        };
+        let cache_dir = dir(self.cache_dir, "cache")?;
+        let state_dir = dir(self.state_dir, "state")?;
+        let data_dir = dir(self.data_dir, "data")?;
+        let global_services_dir = self
+            .services_dir
+            .unwrap_or_else(|| cache_dir.join("services"));
+        let workspace = self
+            .workspace
+            .unwrap_or_else(|| DEFAULT_WORKSPACE.to_string());
+        workspace::check_name(&workspace)?;
+        let state_file = self
+            .state_file
+            .unwrap_or_else(|| workspace::state_file(&state_dir, &workspace));
+
+        fs::create_dir_all(&global_services_dir)?;
+        fs::create_dir_all(&state_dir)?;
+        fs::create_dir_all(&data_dir)?;
+
+        Ok(Config {
+            config_file,
+            cache_dir,
+            state_dir,
+            data_dir,
+            services_dir: self
+                .project_services_dir
+                .clone()
+                .unwrap_or(global_services_dir),
+            project_services_dir: self.project_services_dir,
+            workspace,
+            state_file,
+            repo_url: self.repo_url.unwrap_or_else(|| default_setting("repo_url")),
+            branch: self.branch.unwrap_or_else(|| default_setting("branch")),
+            base_port: match self.base_port {
+                Some(port) => port,
+                None => parse_default("base_port")?,
+            },
+            port_range: match self.port_range {
+                Some(range) => range,
+                None => parse_default("port_range")?,
+            },
+            contract_port: match self.contract_port {
+                Some(port) => port,
+                None => parse_default("contract_port")?,
+            },
+            health: match self.health {
+                Some(health) => health,
+                None => HealthSettings {
+                    poll_interval: Duration::from_millis(parse_default("health_poll_interval_ms")?),
+                    request_timeout: Duration::from_millis(parse_default(
+                        "health_request_timeout_ms",
+                    )?),
+                    startup_timeout: Duration::from_secs(parse_default("startup_timeout_secs")?),
+                },
+            },
+            service_health: self.service_health,
+            compliance_mode: match self.compliance_mode {
+                Some(mode) => mode,
+                None => parse_default("compliance_mode")?,
+            },
+            registry: self.registry,
+            pseudonym_seed: self.pseudonym_seed.unwrap_or_default(),
+            otel_endpoint: self.otel_endpoint,
+            flap_webhook: self.flap_webhook,
+            crash_hook: self.crash_hook,
+            crash_webhook: self.crash_webhook,
+            project_config_path: self.project_config_path,
+            overrides: self.overrides,
+        })
+    }
+}
+
+/// Built-in default of a settings key.
+fn default_setting(name: &str) -> String {
+    key_spec(name)
+        .map(|spec| spec.default.to_string())
+        .unwrap_or_default()
+}
+
+/// Built-in default of a settings key, parsed.
+fn parse_default<T: std::str::FromStr>(name: &str) -> Result<T> {
+    default_setting(name)
+        .parse()
+        .map_err(|_| Error::Other(format!("invalid default for {}", name)))
+}
+
+/// Find the nearest project config file in `start` or one of its parents.
+fn find_project_config_from(start: &Path) -> Option<PathBuf> {
+    start.ancestors().find_map(|dir| {
+        PROJECT_CONFIG_NAMES
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.exists())
+    })
+}
+
+/// Find a project-local services directory by traversing up from `start`.
+///
+/// The home directory is skipped so a legacy ~/.doubleagent/services is never
+/// mistaken for a project install.
+fn find_project_services_dir(start: &Path, home: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .filter(|dir| *dir != home)
+        .map(|dir| dir.join(PROJECT_SERVICES_DIR))
+        .find(|candidate| candidate.is_dir())
+}
+
+/// Resolve a doubleagent directory from an explicit override, an XDG variable,
+/// or the XDG default relative to the home directory.
+fn resolve_dir(override_env: &str, xdg_env: &str, home: &Path, default: &str) -> PathBuf {
+    if let Some(dir) = env_path(override_env) {
+        return dir;
+    }
+
+    env_path(xdg_env)
+        .unwrap_or_else(|| home.join(default))
+        .join("doubleagent")
+}
+
+/// Read an absolute path from an environment variable (the XDG spec says
+/// relative paths must be ignored).
+fn env_path(name: &str) -> Option<PathBuf> {
+    std::env::var_os(name)
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+}
+
+/// Move entries from the legacy ~/.doubleagent directory to their XDG locations.
+///
+/// Entries whose destination already exists are left in place. The legacy
+/// directory is removed once it is empty.
+fn migrate_legacy_layout(legacy_dir: &Path, moves: &[(&str, &Path)]) {
+    if !legacy_dir.is_dir() {
+        return;
+    }
+
+    for (name, dest) in moves {
+        let src = legacy_dir.join(name);
+        if !src.exists() || dest.exists() {
+            continue;
+        }
+
+        if let Some(parent) = dest.parent() {
+            if let Err(e) = fs::create_dir_all(parent) {
+                tracing::warn!("Failed to create {}: {}", parent.display(), e);
+                continue;
             }
         }
 
-        None
+        match fs::rename(&src, dest) {
+            Ok(_) => tracing::info!("Migrated {} to {}", src.display(), dest.display()),
+            Err(e) => tracing::warn!(
+                "Failed to migrate {} to {}: {}",
+                src.display(),
+                dest.display(),
+                e
+            ),
+        }
+    }
+
+    // Only succeeds when nothing is left behind
+    let _ = fs::remove_dir(legacy_dir);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_migrate_legacy_layout_moves_entries() {
+        let home = tempdir().unwrap();
+        let legacy = home.path().join(".doubleagent");
+        fs::create_dir_all(legacy.join("services/github")).unwrap();
+        fs::write(legacy.join("state.json"), "{}").unwrap();
+
+        let services_dir = home.path().join("cache/doubleagent/services");
+        let state_file = home.path().join("state/doubleagent/state.json");
+
+        migrate_legacy_layout(
+            &legacy,
+            &[
+                ("services", services_dir.as_path()),
+                ("state.json", state_file.as_path()),
+            ],
+        );
+
+        assert!(services_dir.join("github").is_dir());
+        assert_eq!(fs::read_to_string(&state_file).unwrap(), "{}");
+        assert!(!legacy.exists());
+    }
+
+    #[test]
+    fn test_migrate_legacy_layout_keeps_existing_destination() {
+        let home = tempdir().unwrap();
+        let legacy = home.path().join(".doubleagent");
+        fs::create_dir_all(&legacy).unwrap();
+        fs::write(legacy.join("state.json"), "old").unwrap();
+
+        let state_file = home.path().join("state.json");
+        fs::write(&state_file, "new").unwrap();
+
+        migrate_legacy_layout(&legacy, &[("state.json", state_file.as_path())]);
+
+        assert_eq!(fs::read_to_string(&state_file).unwrap(), "new");
+        assert!(legacy.join("state.json").exists());
+    }
+
+    #[test]
+    fn test_find_project_config_prefers_yaml() {
+        let root = tempdir().unwrap();
+        let nested = root.path().join("src");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(root.path().join("doubleagent.toml"), "").unwrap();
+
+        assert_eq!(
+            find_project_config_from(&nested),
+            Some(root.path().join("doubleagent.toml"))
+        );
+
+        fs::write(root.path().join("doubleagent.yaml"), "").unwrap();
+        assert_eq!(
+            find_project_config_from(&nested),
+            Some(root.path().join("doubleagent.yaml"))
+        );
+    }
+
+    #[test]
+    fn test_find_project_services_dir_searches_upward() {
+        let root = tempdir().unwrap();
+        let project = root.path().join("project");
+        let nested = project.join("src/app");
+        fs::create_dir_all(project.join(PROJECT_SERVICES_DIR)).unwrap();
+        fs::create_dir_all(&nested).unwrap();
+
+        assert_eq!(
+            find_project_services_dir(&nested, Path::new("/nonexistent")),
+            Some(project.join(PROJECT_SERVICES_DIR))
+        );
+    }
+
+    #[test]
+    fn test_find_project_services_dir_skips_home() {
+        let home = tempdir().unwrap();
+        fs::create_dir_all(home.path().join(PROJECT_SERVICES_DIR)).unwrap();
+        let nested = home.path().join("code");
+        fs::create_dir_all(&nested).unwrap();
+
+        assert_eq!(find_project_services_dir(&nested, home.path()), None);
+    }
+
+    #[test]
+    fn test_builder_derives_paths_from_root() {
+        let root = tempdir().unwrap();
+        let config = Config::builder()
+            .root(root.path())
+            .base_port(9000)
+            .build()
+            .unwrap();
+
+        assert_eq!(config.config_file, root.path().join("config/config.yaml"));
+        assert_eq!(config.services_dir, root.path().join("cache/services"));
+        assert_eq!(config.state_file, root.path().join("state/state.json"));
+        assert_eq!(config.workspace, "default");
+        assert!(config.data_dir.is_dir());
+        assert_eq!(config.base_port, 9000);
+        assert_eq!(config.port_range, PortRange::default());
+        assert_eq!(config.contract_port, 18080);
+        assert_eq!(config.health.startup_timeout, Duration::from_secs(30));
+        assert_eq!(config.branch, "main");
+        assert_eq!(config.compliance_mode, ComplianceMode::Off);
+        assert!(config.project_config_path.is_none());
+    }
+
+    #[test]
+    fn test_health_for_applies_service_overrides() {
+        let root = tempdir().unwrap();
+        let config = Config::builder()
+            .root(root.path())
+            .service_health(
+                "stripe",
+                HealthOverrides {
+                    startup_timeout_secs: Some(90),
+                    ..Default::default()
+                },
+            )
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            config.health_for("stripe").startup_timeout,
+            Duration::from_secs(90)
+        );
+        assert_eq!(config.health_for("github"), config.health);
+    }
+
+    #[test]
+    fn test_builder_requires_directories_without_root() {
+        let dir = tempdir().unwrap();
+        assert!(Config::builder().build().is_err());
+        assert!(Config::builder()
+            .config_file(dir.path().join("config.yaml"))
+            .cache_dir(dir.path().join("cache"))
+            .state_dir(dir.path().join("state"))
+            .data_dir(dir.path().join("data"))
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_resolve_dir_defaults_under_home() {
+        let home = Path::new("/home/agent");
+        let dir = resolve_dir(
+            "DOUBLEAGENT_TEST_UNSET_OVERRIDE",
+            "DOUBLEAGENT_TEST_UNSET_XDG",
+            home,
+            ".cache",
+        );
+        assert_eq!(dir, PathBuf::from("/home/agent/.cache/doubleagent"));
     }
 }
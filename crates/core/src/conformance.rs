@@ -0,0 +1,631 @@
+//! Checking a fake's responses against the service's OpenAPI description.
+//!
+//! Requests are either generated from the description, one per `GET`
+//! operation with its path (and required query) parameters filled from
+//! examples, or replayed from a requests file. Each response must have a
+//! status the operation documents (exactly, as `2XX`, or as `default`), and
+//! a JSON body that matches the schema documented for that status. OpenAPI
+//! 3.0's `nullable` is understood, so 3.0 and 3.1 descriptions both work.
+//!
+//! A requests file has one request per line, `#` starting a comment:
+//!
+//! ```text
+//! GET /repos/acme/api/issues?state=open
+//! POST /repos/acme/api/issues {"title": "Flaky test"}
+//! ```
+//!
+//! Generated requests only read, so checking doesn't change the fake's state;
+//! requests that write belong in a requests file.
+
+use crate::contract::{Outcome, TestCase};
+use crate::coverage::Endpoint;
+use crate::openapi;
+use crate::service::ServiceDefinition;
+use crate::{Error, Result};
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// HTTP methods an OpenAPI path item can describe.
+pub(crate) const METHODS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// How many schema errors are listed for one response before the rest are counted.
+const MAX_BODY_ERRORS: usize = 5;
+
+/// One request to check the response of.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    /// Upper case, e.g. `GET`
+    pub method: String,
+    /// Path and query, e.g. `/repos/acme/api/issues?state=open`
+    pub path: String,
+    pub body: Option<Value>,
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.method, self.path)
+    }
+}
+
+/// Parse a requests file: `METHOD /path [JSON body]` per line.
+pub fn parse_requests(content: &str) -> Result<Vec<Request>> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            let invalid =
+                |reason: &str| Error::Other(format!("Requests file line {}: {}", number, reason));
+            let (method, rest) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| invalid("expected METHOD /path"))?;
+            let rest = rest.trim_start();
+            let (path, body) = match rest.split_once(char::is_whitespace) {
+                Some((path, body)) => (path, Some(body.trim())),
+                None => (rest, None),
+            };
+            if !path.starts_with('/') {
+                return Err(invalid("the path must start with /"));
+            }
+            if !METHODS.contains(&method.to_lowercase().as_str()) {
+                return Err(invalid(&format!("unknown method '{}'", method)));
+            }
+            let body = body
+                .map(serde_json::from_str)
+                .transpose()
+                .map_err(|e| invalid(&format!("invalid JSON body: {}", e)))?;
+            Ok(Request {
+                method: method.to_uppercase(),
+                path: path.to_string(),
+                body,
+            })
+        })
+        .collect()
+}
+
+/// The response to one request, and how it strays from the description.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub request: Request,
+    pub status: u16,
+    /// Empty if the response conforms
+    pub violations: Vec<String>,
+}
+
+impl Check {
+    /// The check as a test case of a contract report.
+    pub fn to_test_case(&self) -> TestCase {
+        let outcome = if self.violations.is_empty() {
+            Outcome::Passed
+        } else {
+            Outcome::Failed
+        };
+        TestCase {
+            name: self.request.to_string(),
+            classname: "openapi".to_string(),
+            time: 0.0,
+            outcome,
+            message: (!self.violations.is_empty()).then(|| self.violations.join("; ")),
+            details: None,
+            retries: 0,
+        }
+    }
+}
+
+/// An OpenAPI description to check responses against.
+#[derive(Debug, Clone)]
+pub struct Spec {
+    document: Value,
+}
+
+impl Spec {
+    pub fn new(mut document: Value) -> Self {
+        normalize(&mut document);
+        Self { document }
+    }
+
+    /// The description, with `nullable` rewritten as JSON Schema.
+    pub fn document(&self) -> &Value {
+        &self.document
+    }
+
+    /// The OpenAPI description the service ships.
+    pub fn load(service: &ServiceDefinition) -> Result<Self> {
+        let (_, document) = openapi::load(service, "checked against it")?;
+        Ok(Self::new(document))
+    }
+
+    /// A request for every `GET` operation whose parameters can be filled in.
+    pub fn requests(&self) -> Vec<Request> {
+        let spec = &self.document;
+        let paths = spec.get("paths").and_then(Value::as_object);
+        let mut requests = Vec::new();
+        for (template, item) in paths.into_iter().flatten() {
+            let Some(operation) = item.get("get") else {
+                continue;
+            };
+            let parameters = parameters(spec, item, operation);
+            let mut path = String::new();
+            let mut complete = true;
+            for segment in template.split('/').filter(|s| !s.is_empty()) {
+                let value = match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                    Some(name) => parameters
+                        .iter()
+                        .find(|p| is_parameter(p, "path", name))
+                        .and_then(|p| parameter_value(spec, p)),
+                    None => Some(segment.to_string()),
+                };
+                match value {
+                    Some(value) => {
+                        path.push('/');
+                        path.push_str(&encode(&value));
+                    }
+                    None => complete = false,
+                }
+            }
+            if !complete {
+                continue;
+            }
+            if path.is_empty() {
+                path.push('/');
+            }
+
+            let query: Vec<String> = parameters
+                .iter()
+                .filter(|p| p.get("in").and_then(Value::as_str) == Some("query"))
+                .filter(|p| p.get("required").and_then(Value::as_bool) == Some(true))
+                .filter_map(|p| {
+                    let name = p.get("name").and_then(Value::as_str)?;
+                    Some(format!(
+                        "{}={}",
+                        encode(name),
+                        encode(&parameter_value(spec, p)?)
+                    ))
+                })
+                .collect();
+            if !query.is_empty() {
+                path = format!("{}?{}", path, query.join("&"));
+            }
+            requests.push(Request {
+                method: "GET".to_string(),
+                path,
+                body: None,
+            });
+        }
+        requests
+    }
+
+    /// How a response to `request` strays from the description, if it does.
+    pub fn check(&self, request: &Request, status: u16, body: &[u8]) -> Vec<String> {
+        let spec = &self.document;
+        let path = request.path.split(['?', '#']).next().unwrap_or_default();
+        let Some((template, item)) = self.path_item(path) else {
+            return vec![format!("no path in the description matches {}", path)];
+        };
+        let Some(operation) = item.get(request.method.to_lowercase()) else {
+            return vec![format!(
+                "{} has no {} operation in the description",
+                template, request.method
+            )];
+        };
+
+        let responses = operation
+            .get("responses")
+            .and_then(Value::as_object)
+            .cloned()
+            .unwrap_or_default();
+        let code = status.to_string();
+        let range = format!("{}XX", status / 100);
+        let response = responses.get(&code).or_else(|| {
+            responses
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(&range))
+                .map(|(_, response)| response)
+                .or_else(|| responses.get("default"))
+        });
+        let Some(response) = response else {
+            let documented: Vec<&str> = responses.keys().map(String::as_str).collect();
+            return vec![format!(
+                "status {} isn't documented for {} {} (documented: {})",
+                status,
+                request.method,
+                template,
+                documented.join(", ")
+            )];
+        };
+
+        let response = openapi::resolve(spec, response);
+        let Some(schema) = response
+            .get("content")
+            .and_then(Value::as_object)
+            .and_then(|content| {
+                content
+                    .iter()
+                    .find(|(media, _)| media.contains("json"))
+                    .map(|(_, media)| media)
+            })
+            .and_then(|media| media.get("schema"))
+        else {
+            return Vec::new();
+        };
+        if body.iter().all(u8::is_ascii_whitespace) {
+            return vec![format!(
+                "status {} should have a JSON body, but the response is empty",
+                status
+            )];
+        }
+        let body: Value = match serde_json::from_slice(body) {
+            Ok(body) => body,
+            Err(e) => return vec![format!("the response isn't JSON: {}", e)],
+        };
+        self.check_body(schema, &body)
+    }
+
+    /// Errors of `body` against a schema of the description.
+    fn check_body(&self, schema: &Value, body: &Value) -> Vec<String> {
+        // Local $refs resolve against the schema's root, so give it the components
+        let mut schema = schema.clone();
+        if let (Value::Object(root), Some(components)) =
+            (&mut schema, self.document.get("components"))
+        {
+            root.insert("components".to_string(), components.clone());
+        }
+        let validator = match jsonschema::validator_for(&schema) {
+            Ok(validator) => validator,
+            Err(e) => return vec![format!("the description's schema is invalid: {}", e)],
+        };
+        let errors: Vec<String> = validator
+            .iter_errors(body)
+            .map(|error| {
+                let pointer = error.instance_path().to_string();
+                format!("body{}: {}", pointer, error)
+            })
+            .collect();
+        if errors.len() <= MAX_BODY_ERRORS {
+            return errors;
+        }
+        let more = errors.len() - MAX_BODY_ERRORS;
+        let mut errors: Vec<String> = errors.into_iter().take(MAX_BODY_ERRORS).collect();
+        errors.push(format!("... and {} more", more));
+        errors
+    }
+
+    /// The path item a concrete path belongs to.
+    fn path_item(&self, path: &str) -> Option<(&str, &Value)> {
+        let paths = self.document.get("paths").and_then(Value::as_object)?;
+        let template = match_template(paths.keys().map(String::as_str), path)?;
+        Some((template, &paths[template]))
+    }
+
+    /// Every operation the description declares.
+    pub fn endpoints(&self) -> Vec<Endpoint> {
+        let paths = self.document.get("paths").and_then(Value::as_object);
+        paths
+            .into_iter()
+            .flatten()
+            .flat_map(|(template, item)| {
+                METHODS
+                    .iter()
+                    .filter(|method| item.get(**method).is_some())
+                    .map(|method| Endpoint {
+                        method: method.to_uppercase(),
+                        path: template.clone(),
+                    })
+            })
+            .collect()
+    }
+}
+
+/// The path template a concrete path belongs to, preferring literal segments
+/// over parameters (`/users/me` over `/users/{id}`).
+pub fn match_template<'a>(
+    templates: impl IntoIterator<Item = &'a str>,
+    path: &str,
+) -> Option<&'a str> {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    templates
+        .into_iter()
+        .filter_map(|template| {
+            let parts: Vec<&str> = template.split('/').filter(|s| !s.is_empty()).collect();
+            if parts.len() != segments.len() {
+                return None;
+            }
+            let mut literals = 0;
+            for (part, segment) in parts.iter().zip(&segments) {
+                if part.starts_with('{') && part.ends_with('}') {
+                    continue;
+                }
+                if part != segment {
+                    return None;
+                }
+                literals += 1;
+            }
+            Some((literals, template))
+        })
+        .max_by_key(|(literals, _)| *literals)
+        .map(|(_, template)| template)
+}
+
+/// Send each request to the fake at `base_url` and check its response.
+pub async fn run(base_url: &str, spec: &Spec, requests: &[Request]) -> Result<Vec<Check>> {
+    let client = reqwest::Client::new();
+    let base_url = base_url.trim_end_matches('/');
+    let mut checks = Vec::new();
+    for request in requests {
+        let method = reqwest::Method::from_bytes(request.method.as_bytes())
+            .map_err(|e| Error::Other(format!("Invalid method '{}': {}", request.method, e)))?;
+        let mut builder = client
+            .request(method, format!("{}{}", base_url, request.path))
+            .header(reqwest::header::ACCEPT, "application/json");
+        if let Some(body) = &request.body {
+            builder = builder.json(body);
+        }
+        let response = builder.send().await?;
+        let status = response.status().as_u16();
+        let body = response.bytes().await?;
+        checks.push(Check {
+            request: request.clone(),
+            status,
+            violations: spec.check(request, status, &body),
+        });
+    }
+    Ok(checks)
+}
+
+/// The parameters of an operation, its own replacing the path item's.
+pub(crate) fn parameters<'a>(
+    spec: &'a Value,
+    item: &'a Value,
+    operation: &'a Value,
+) -> Vec<&'a Value> {
+    let list = |value: &'a Value| -> Vec<&'a Value> {
+        value
+            .get("parameters")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .map(|p| openapi::resolve(spec, p))
+            .collect()
+    };
+    let own = list(operation);
+    let mut all: Vec<&Value> = list(item)
+        .into_iter()
+        .filter(|p| {
+            !own.iter()
+                .any(|o| o.get("name") == p.get("name") && o.get("in") == p.get("in"))
+        })
+        .collect();
+    all.extend(own);
+    all
+}
+
+fn is_parameter(parameter: &Value, location: &str, name: &str) -> bool {
+    parameter.get("in").and_then(Value::as_str) == Some(location)
+        && parameter.get("name").and_then(Value::as_str) == Some(name)
+}
+
+/// A value for a parameter: its example, or one made from its schema.
+fn parameter_value(spec: &Value, parameter: &Value) -> Option<String> {
+    let value = match parameter.get("example") {
+        Some(example) => example.clone(),
+        None => match parameter
+            .get("examples")
+            .and_then(Value::as_object)
+            .and_then(|examples| examples.values().next())
+            .and_then(|example| openapi::resolve(spec, example).get("value"))
+        {
+            Some(example) => example.clone(),
+            None => openapi::example(spec, parameter.get("schema")?, 0),
+        },
+    };
+    match value {
+        Value::String(s) => Some(s),
+        Value::Null | Value::Array(_) | Value::Object(_) => None,
+        value => Some(value.to_string()),
+    }
+}
+
+/// Percent-encode what can't appear in a path segment or query value.
+pub(crate) fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~:@".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Rewrite OpenAPI 3.0's `nullable: true` as JSON Schema.
+fn normalize(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for item in map.values_mut() {
+                normalize(item);
+            }
+            if map.get("nullable") == Some(&Value::Bool(true)) {
+                map.remove("nullable");
+                match map.get_mut("type") {
+                    Some(Value::String(t)) => {
+                        let t = std::mem::take(t);
+                        map.insert("type".to_string(), serde_json::json!([t, "null"]));
+                    }
+                    Some(Value::Array(types)) => types.push(Value::String("null".to_string())),
+                    _ => {
+                        let schema = std::mem::take(map);
+                        let mut nullable = Map::new();
+                        nullable.insert(
+                            "anyOf".to_string(),
+                            serde_json::json!([Value::Object(schema), {"type": "null"}]),
+                        );
+                        *map = nullable;
+                    }
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(normalize),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SPEC: &str = r##"
+openapi: 3.0.3
+paths:
+  /repos/{owner}/{repo}/issues:
+    parameters:
+      - {name: owner, in: path, required: true, schema: {type: string}, example: acme}
+      - {name: repo, in: path, required: true, schema: {type: string}}
+    get:
+      parameters:
+        - {name: state, in: query, required: true, schema: {type: string, enum: [open, closed]}}
+        - {name: page, in: query, schema: {type: integer}}
+      responses:
+        "200":
+          content:
+            application/json:
+              schema:
+                type: array
+                items: {$ref: "#/components/schemas/Issue"}
+        4XX:
+          description: Client error
+    post:
+      responses:
+        "201":
+          content:
+            application/json:
+              schema: {$ref: "#/components/schemas/Issue"}
+  /repos/{owner}/{repo}/issues/{number}:
+    get:
+      parameters:
+        - {name: number, in: path, required: true, schema: {type: integer}}
+      responses:
+        "200":
+          content:
+            application/json:
+              schema: {$ref: "#/components/schemas/Issue"}
+  /users/me:
+    get:
+      responses:
+        default:
+          content:
+            application/json:
+              schema: {type: object, required: [login]}
+components:
+  schemas:
+    Issue:
+      type: object
+      required: [id, title]
+      properties:
+        id: {type: integer}
+        title: {type: string}
+        closed_at: {type: string, nullable: true}
+"##;
+
+    fn spec() -> Spec {
+        Spec::new(serde_yaml::from_str(SPEC).unwrap())
+    }
+
+    fn get(path: &str) -> Request {
+        Request {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            body: None,
+        }
+    }
+
+    #[test]
+    fn test_requests_are_generated_for_get_operations() {
+        let paths: Vec<String> = spec().requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(
+            paths,
+            [
+                // The single issue's owner and repo aren't declared, so it's left out
+                "/repos/acme/string/issues?state=open",
+                "/users/me",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_endpoints_are_every_operation() {
+        let endpoints: Vec<String> = spec()
+            .endpoints()
+            .into_iter()
+            .map(|e| format!("{} {}", e.method, e.path))
+            .collect();
+        assert_eq!(
+            endpoints,
+            [
+                "GET /repos/{owner}/{repo}/issues",
+                "POST /repos/{owner}/{repo}/issues",
+                "GET /repos/{owner}/{repo}/issues/{number}",
+                "GET /users/me",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_responses_are_checked_against_the_description() {
+        let spec = spec();
+        let issues = get("/repos/acme/api/issues?state=open");
+        let ok = br#"[{"id": 1, "title": "Flaky", "closed_at": null}]"#;
+        assert!(spec.check(&issues, 200, ok).is_empty());
+        // 4XX covers 404; nothing covers 500
+        assert!(spec.check(&issues, 404, b"").is_empty());
+        let violations = spec.check(&issues, 500, b"");
+        assert!(
+            violations[0].contains("status 500 isn't documented"),
+            "{:?}",
+            violations
+        );
+
+        let violations = spec.check(&issues, 200, br#"[{"id": "1", "closed_at": 3}]"#);
+        assert_eq!(violations.len(), 3, "{:?}", violations);
+        assert!(violations.iter().any(|v| v.starts_with("body/0/id:")));
+        assert!(violations
+            .iter()
+            .any(|v| v.starts_with("body/0/closed_at:")));
+        assert!(spec.check(&issues, 200, b"<html>")[0].contains("isn't JSON"));
+
+        assert!(spec
+            .check(&get("/users/me"), 200, br#"{"login": "ada"}"#)
+            .is_empty());
+        assert!(spec.check(&get("/users/me"), 503, br#"{}"#)[0].contains("login"));
+        assert!(spec.check(&get("/orgs"), 200, b"")[0].contains("no path"));
+        let delete = Request {
+            method: "DELETE".to_string(),
+            ..get("/repos/acme/api/issues/1")
+        };
+        assert!(spec.check(&delete, 204, b"")[0].contains("has no DELETE operation"));
+    }
+
+    #[test]
+    fn test_parse_requests() {
+        let requests = parse_requests(
+            "# recorded\nGET /users/me\n\npost /repos/acme/api/issues {\"title\": \"x\"}\n",
+        )
+        .unwrap();
+        assert_eq!(requests[0], get("/users/me"));
+        assert_eq!(requests[1].method, "POST");
+        assert_eq!(requests[1].body, Some(json!({"title": "x"})));
+
+        let msg = parse_requests("GET users").unwrap_err().to_string();
+        assert!(
+            msg.contains("line 1: the path must start with /"),
+            "{}",
+            msg
+        );
+        assert!(parse_requests("FETCH /users").is_err());
+        assert!(parse_requests("POST /users {").is_err());
+    }
+}
@@ -0,0 +1,679 @@
+//! Structured results of contract test runs, for `doubleagent contract --report`.
+//!
+//! The test runner writes JUnit XML to the path in `DOUBLEAGENT_CONTRACT_RESULTS`
+//! (pytest does so without changes, through `PYTEST_ADDOPTS`). The results of
+//! each target the contracts ran against are collected into a
+//! [`ContractReport`], written out as JUnit XML or JSON with per-test timing.
+//!
+//! `contract --filter` reaches the runner the same way: as
+//! `DOUBLEAGENT_CONTRACT_FILTER`, and as `-k` for pytest. Tests are tagged
+//! with the runner's own means (pytest markers such as `smoke`, `slow` or
+//! `destructive`); `contract --tag` and `--skip-tag`, and the tags of a
+//! `contracts.suites` entry, reach it as an expression in
+//! `DOUBLEAGENT_CONTRACT_TAGS`, and as `-m` for pytest. `contract --fail-fast`
+//! sets `DOUBLEAGENT_CONTRACT_FAIL_FAST`, and gives pytest `-x`, so the runner
+//! stops at its first failed test.
+//!
+//! `contract --list` asks the runner to collect the tests without running
+//! them: pytest gets `--collect-only -q`, and any runner may write one JSON
+//! object per test (`{"id": ..., "tags": [...]}`) to the path in
+//! `DOUBLEAGENT_CONTRACT_LIST`. Without that file, the test ids are read from
+//! pytest's output.
+//!
+//! Failed tests are re-run up to `contracts.retries` times, each time with a
+//! filter selecting only them; those that pass on a retry are reported as
+//! flaky instead of failed. A test that takes longer than `contracts.timeout`
+//! seconds fails, and the limit reaches the runner as
+//! `DOUBLEAGENT_CONTRACT_TIMEOUT`.
+//!
+//! `contracts.env` in service.yaml adds variables of its own, rendered as
+//! `{{ }}` templates with what the run knows: `service`, `target`, `url`,
+//! `port` (of the fake), `profile` and `namespace` if there is one, and
+//! `env.<NAME>`.
+//!
+//! With `contract --snapshot`, the fake is seeded with each profile in turn
+//! and the contracts run once per profile, named in
+//! `DOUBLEAGENT_CONTRACT_PROFILE`; each run's results carry its profile.
+
+use crate::coverage::Coverage;
+use crate::template;
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Environment variable holding the path the runner writes JUnit XML results to.
+pub const RESULTS_ENV: &str = "DOUBLEAGENT_CONTRACT_RESULTS";
+
+/// Environment variable holding the expression selecting the tests to run.
+pub const FILTER_ENV: &str = "DOUBLEAGENT_CONTRACT_FILTER";
+
+/// Environment variable holding the expression selecting tests by tag.
+pub const TAGS_ENV: &str = "DOUBLEAGENT_CONTRACT_TAGS";
+
+/// Environment variable set when the runner should stop at the first failed test.
+pub const FAIL_FAST_ENV: &str = "DOUBLEAGENT_CONTRACT_FAIL_FAST";
+
+/// Environment variable holding the snapshot profile the fake was seeded with.
+pub const PROFILE_ENV: &str = "DOUBLEAGENT_CONTRACT_PROFILE";
+
+/// Environment variable holding the seconds a single test may take.
+pub const TIMEOUT_ENV: &str = "DOUBLEAGENT_CONTRACT_TIMEOUT";
+
+/// Environment variable holding the path the runner lists the collected tests in.
+pub const LIST_ENV: &str = "DOUBLEAGENT_CONTRACT_LIST";
+
+/// Profile name for a run against the fake with no data seeded.
+pub const EMPTY_PROFILE: &str = "empty";
+
+/// The expression selecting tests with any of `tags` and none of `skip`, in
+/// pytest's `-m` syntax, e.g. `(smoke or fast) and not destructive`; `None`
+/// selects every test.
+pub fn tag_expression(tags: &[String], skip: &[String]) -> Option<String> {
+    let mut terms = Vec::new();
+    match tags {
+        [] => {}
+        [tag] => terms.push(tag.clone()),
+        tags => terms.push(format!("({})", tags.join(" or "))),
+    }
+    terms.extend(skip.iter().map(|tag| format!("not {}", tag)));
+    (!terms.is_empty()).then(|| terms.join(" and "))
+}
+
+/// `PYTEST_ADDOPTS` with the options that make pytest write its results to
+/// `results` and run only the tests matching `filter` and the `tags`
+/// expression, stopping at the first failure if `fail_fast`, after those
+/// already in `existing`.
+pub fn pytest_addopts(
+    existing: &str,
+    results: Option<&Path>,
+    filter: Option<&str>,
+    tags: Option<&str>,
+    fail_fast: bool,
+) -> String {
+    let mut options = vec![existing.trim().to_string()];
+    if let Some(path) = results {
+        options.push(quote(&format!("--junitxml={}", path.display())));
+    }
+    if let Some(filter) = filter {
+        options.push(format!("-k {}", quote(filter)));
+    }
+    if let Some(tags) = tags {
+        options.push(format!("-m {}", quote(tags)));
+    }
+    if fail_fast {
+        options.push("-x".to_string());
+    }
+    options.retain(|option| !option.is_empty());
+    options.join(" ")
+}
+
+/// `PYTEST_ADDOPTS` making pytest only collect the tests matching `filter`
+/// and `tags` and print their ids, after the options already in `existing`.
+pub fn pytest_list_addopts(existing: &str, filter: Option<&str>, tags: Option<&str>) -> String {
+    let options = pytest_addopts(existing, None, filter, tags, false);
+    format!("{} --collect-only -q", options).trim().to_string()
+}
+
+/// Render the templates of `contracts.env` with the variables of a run.
+///
+/// Using a variable this run doesn't have, such as `port` against the real
+/// API, without a default is an error.
+pub fn render_env(
+    templates: &BTreeMap<String, String>,
+    vars: &BTreeMap<String, String>,
+) -> Result<BTreeMap<String, String>> {
+    let lookup = |name: &str| std::env::var(name).ok();
+    let mut missing = Vec::new();
+    let mut env = BTreeMap::new();
+    for (name, value) in templates {
+        let rendered = template::render_str(value, vars, &lookup, &mut missing)
+            .map_err(|e| Error::Other(format!("contracts.env.{}: {}", name, e)))?;
+        env.insert(name.clone(), rendered);
+    }
+    if !missing.is_empty() {
+        missing.sort();
+        missing.dedup();
+        return Err(Error::Other(format!(
+            "contracts.env uses variables this run doesn't have: {}\n\
+             Give them a default with | default(\"...\").",
+            missing.join(", ")
+        )));
+    }
+    Ok(env)
+}
+
+/// Quote an argument for the shell-like splitting pytest does on `PYTEST_ADDOPTS`.
+fn quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_=./:".contains(c))
+    {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', "'\"'\"'"))
+}
+
+/// How a single test ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Passed,
+    Failed,
+    Error,
+    Skipped,
+}
+
+/// One test of a contract run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TestCase {
+    pub name: String,
+    /// Class or module the test belongs to
+    pub classname: String,
+    /// Seconds the test took
+    pub time: f64,
+    pub outcome: Outcome,
+    /// Failure, error or skip message, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// The full output behind the message, e.g. a traceback or diff
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+    /// Retries the test needed to pass; a test that needed any is flaky
+    #[serde(skip_serializing_if = "is_zero")]
+    pub retries: u32,
+}
+
+impl TestCase {
+    /// Whether the test failed or errored.
+    pub fn failed(&self) -> bool {
+        matches!(self.outcome, Outcome::Failed | Outcome::Error)
+    }
+
+    /// Whether the test passed only on a retry.
+    pub fn flaky(&self) -> bool {
+        self.retries > 0
+    }
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+/// Fail the tests that took longer than `timeout` seconds, returning how many did.
+pub fn enforce_timeout(tests: &mut [TestCase], timeout: f64) -> usize {
+    let mut over = 0;
+    for test in tests.iter_mut().filter(|t| t.time > timeout && !t.failed()) {
+        test.outcome = Outcome::Failed;
+        test.message = Some(format!(
+            "took {:.1}s, over the {}s timeout",
+            test.time, timeout
+        ));
+        over += 1;
+    }
+    over
+}
+
+/// Expression selecting the failed tests (and any others sharing their
+/// names) to re-run them, within `filter` if there is one.
+///
+/// Parametrized tests are selected with all of their parameters, as pytest's
+/// `-k` doesn't match the `[...]` suffix.
+pub fn retry_filter(tests: &[TestCase], filter: Option<&str>) -> String {
+    let mut names: Vec<&str> = tests
+        .iter()
+        .filter(|t| t.failed())
+        .map(|t| t.name.split('[').next().unwrap_or(&t.name))
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    let failed = names.join(" or ");
+    match filter {
+        Some(filter) => format!("({}) and ({})", filter, failed),
+        None => failed,
+    }
+}
+
+/// Fold the results of a retry into the tests of the first run: failed tests
+/// that passed this time are marked flaky, those that failed again keep the
+/// latest message.
+pub fn merge_retry(tests: &mut [TestCase], retried: &[TestCase], attempt: u32) {
+    for test in tests.iter_mut().filter(|t| t.failed()) {
+        let Some(again) = retried
+            .iter()
+            .find(|r| r.classname == test.classname && r.name == test.name)
+        else {
+            continue;
+        };
+        if again.outcome == Outcome::Passed {
+            test.outcome = Outcome::Passed;
+            test.message = None;
+            test.time = again.time;
+            test.retries = attempt;
+        } else if again.failed() {
+            test.message = again.message.clone();
+        }
+    }
+}
+
+/// A test found by `contract --list`, with the runner's tags (pytest markers).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ListedTest {
+    pub id: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Parse the tests a runner listed in the file at [`LIST_ENV`], one JSON
+/// object per line.
+pub fn parse_list(text: &str) -> Result<Vec<ListedTest>> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| Error::Other(format!("Invalid test list line {}: {}", i + 1, e)))
+        })
+        .collect()
+}
+
+/// The test ids `pytest --collect-only -q` printed, without tags.
+pub fn parse_collected(output: &str) -> Vec<ListedTest> {
+    let mut seen = std::collections::HashSet::new();
+    output
+        .lines()
+        // Indented lines are details, such as warnings about a test, which
+        // may name it again
+        .filter(|line| !line.starts_with(char::is_whitespace) && line.contains("::"))
+        .filter(|line| seen.insert(line.trim_end()))
+        .map(|id| ListedTest {
+            id: id.trim_end().to_string(),
+            tags: Vec::new(),
+        })
+        .collect()
+}
+
+/// The results of running the contracts against one target.
+#[derive(Debug, Clone, Serialize)]
+pub struct TargetResults {
+    /// `fake` or `real`
+    pub target: String,
+    /// Snapshot profile the fake was seeded with for this run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Whether the test command succeeded
+    pub success: bool,
+    /// Seconds the whole run took
+    pub time: f64,
+    pub tests: Vec<TestCase>,
+}
+
+impl TargetResults {
+    /// Number of tests that ended with `outcome`.
+    pub fn count(&self, outcome: Outcome) -> usize {
+        self.tests.iter().filter(|t| t.outcome == outcome).count()
+    }
+
+    /// Number of tests that passed only on a retry.
+    pub fn flaky(&self) -> usize {
+        self.tests.iter().filter(|t| t.flaky()).count()
+    }
+
+    /// The target, with the profile if there is one, e.g. `fake, prod-small`.
+    pub fn label(&self) -> String {
+        match &self.profile {
+            Some(profile) => format!("{}, {}", self.target, profile),
+            None => self.target.clone(),
+        }
+    }
+}
+
+/// The results of a `contract` run, per target.
+#[derive(Debug, Clone, Serialize)]
+pub struct ContractReport {
+    pub service: String,
+    pub targets: Vec<TargetResults>,
+    /// Endpoints of the fake the run requested, if asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coverage: Option<Coverage>,
+}
+
+/// Reports as JUnit XML, with one test suite per service and target.
+pub fn to_junit(reports: &[ContractReport]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+    for report in reports {
+        for target in &report.targets {
+            let _ = writeln!(
+                xml,
+                "  <testsuite name=\"{} ({})\" tests=\"{}\" failures=\"{}\" errors=\"{}\" \
+                 skipped=\"{}\" time=\"{:.3}\">",
+                escape(&report.service),
+                escape(&target.label()),
+                target.tests.len(),
+                target.count(Outcome::Failed),
+                target.count(Outcome::Error),
+                target.count(Outcome::Skipped),
+                target.time
+            );
+            for test in &target.tests {
+                let _ = write!(
+                    xml,
+                    "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+                    escape(&test.classname),
+                    escape(&test.name),
+                    test.time
+                );
+                let element = match test.outcome {
+                    Outcome::Passed if test.flaky() => {
+                        let _ = writeln!(
+                            xml,
+                            ">\n      <properties>\n        \
+                             <property name=\"flaky\" value=\"true\"/>\n        \
+                             <property name=\"retries\" value=\"{}\"/>\n      \
+                             </properties>\n    </testcase>",
+                            test.retries
+                        );
+                        continue;
+                    }
+                    Outcome::Passed => {
+                        xml.push_str("/>\n");
+                        continue;
+                    }
+                    Outcome::Failed => "failure",
+                    Outcome::Error => "error",
+                    Outcome::Skipped => "skipped",
+                };
+                let message = test.message.as_deref().unwrap_or_default();
+                match &test.details {
+                    Some(details) => writeln!(
+                        xml,
+                        ">\n      <{} message=\"{}\">{}</{}>\n    </testcase>",
+                        element,
+                        escape(message),
+                        escape(details),
+                        element
+                    ),
+                    None => writeln!(
+                        xml,
+                        ">\n      <{} message=\"{}\"/>\n    </testcase>",
+                        element,
+                        escape(message)
+                    ),
+                }
+                .ok();
+            }
+            xml.push_str("  </testsuite>\n");
+        }
+    }
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+/// Parse the test cases of a JUnit XML results file.
+pub fn parse_junit(xml: &str) -> Result<Vec<TestCase>> {
+    let document = roxmltree::Document::parse(xml)
+        .map_err(|e| Error::Other(format!("Invalid JUnit XML: {}", e)))?;
+    let tests = document
+        .descendants()
+        .filter(|node| node.has_tag_name("testcase"))
+        .map(|node| {
+            let result = node.children().find(|child| {
+                ["failure", "error", "skipped"]
+                    .iter()
+                    .any(|tag| child.has_tag_name(*tag))
+            });
+            let outcome = match result.map(|r| r.tag_name().name()) {
+                Some("failure") => Outcome::Failed,
+                Some("error") => Outcome::Error,
+                Some(_) => Outcome::Skipped,
+                None => Outcome::Passed,
+            };
+            let attribute = result.and_then(|r| r.attribute("message"));
+            let text = result
+                .and_then(|r| r.text())
+                .map(str::trim)
+                .filter(|t| !t.is_empty());
+            let message = attribute
+                .or(text)
+                .map(|m| m.trim().to_string())
+                .filter(|m| !m.is_empty());
+            // The element's text holds the traceback when the message is an attribute
+            let details = attribute
+                .and(text)
+                .filter(|t| Some(*t) != message.as_deref())
+                .map(str::to_string);
+            TestCase {
+                name: node.attribute("name").unwrap_or_default().to_string(),
+                classname: node.attribute("classname").unwrap_or_default().to_string(),
+                time: node
+                    .attribute("time")
+                    .and_then(|t| t.parse().ok())
+                    .unwrap_or(0.0),
+                outcome,
+                message,
+                details,
+                retries: node
+                    .descendants()
+                    .find(|p| p.has_tag_name("property") && p.attribute("name") == Some("retries"))
+                    .and_then(|p| p.attribute("value")?.parse().ok())
+                    .unwrap_or(0),
+            }
+        })
+        .collect();
+    Ok(tests)
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\n' => escaped.push_str("&#10;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PYTEST: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<testsuites><testsuite name="pytest" errors="0" failures="1" skipped="1" tests="3" time="1.2">
+<testcase classname="test_repos.TestRepositories" name="test_create_repo" time="0.512" />
+<testcase classname="test_repos.TestRepositories" name="test_get_repo" time="0.3">
+<failure message="assert 404 == 200">traceback</failure></testcase>
+<testcase classname="test_issues.TestIssues" name="test_lock" time="0.001">
+<skipped type="pytest.skip" message="not supported">skipped</skipped></testcase>
+</testsuite></testsuites>"#;
+
+    #[test]
+    fn test_junit_round_trip() {
+        let tests = parse_junit(PYTEST).unwrap();
+        assert_eq!(tests.len(), 3);
+        assert_eq!(tests[0].outcome, Outcome::Passed);
+        assert_eq!(tests[0].time, 0.512);
+        assert_eq!(tests[1].outcome, Outcome::Failed);
+        assert_eq!(tests[1].message.as_deref(), Some("assert 404 == 200"));
+        assert_eq!(tests[1].details.as_deref(), Some("traceback"));
+        assert_eq!(tests[2].outcome, Outcome::Skipped);
+
+        let mut report = ContractReport {
+            service: "github".to_string(),
+            targets: vec![TargetResults {
+                target: "fake".to_string(),
+                profile: None,
+                success: false,
+                time: 1.5,
+                tests: tests.clone(),
+            }],
+            coverage: None,
+        };
+        let xml = to_junit(std::slice::from_ref(&report));
+        assert!(
+            xml.contains("name=\"github (fake)\" tests=\"3\" failures=\"1\""),
+            "{}",
+            xml
+        );
+        assert_eq!(parse_junit(&xml).unwrap(), tests);
+
+        // Flaky tests keep their retries through the report
+        report.targets[0].tests[1].outcome = Outcome::Passed;
+        report.targets[0].tests[1].message = None;
+        report.targets[0].tests[1].details = None;
+        report.targets[0].tests[1].retries = 2;
+        let xml = to_junit(std::slice::from_ref(&report));
+        let parsed = parse_junit(&xml).unwrap();
+        assert!(parsed[1].flaky() && parsed[1].retries == 2);
+        assert_eq!(parsed, report.targets[0].tests);
+
+        report.targets[0].profile = Some("prod-small".to_string());
+        let xml = to_junit(&[report]);
+        assert!(
+            xml.contains("name=\"github (fake, prod-small)\""),
+            "{}",
+            xml
+        );
+
+        assert!(parse_junit("<testsuite>").is_err());
+    }
+
+    #[test]
+    fn test_retrying_failed_tests() {
+        let mut tests = parse_junit(PYTEST).unwrap();
+        tests.push(TestCase {
+            name: "test_list_repos[page-2]".to_string(),
+            classname: "test_repos.TestRepositories".to_string(),
+            time: 12.0,
+            outcome: Outcome::Passed,
+            message: None,
+            details: None,
+            retries: 0,
+        });
+        assert_eq!(enforce_timeout(&mut tests, 10.0), 1);
+        assert_eq!(
+            tests[3].message.as_deref(),
+            Some("took 12.0s, over the 10s timeout")
+        );
+        assert_eq!(
+            retry_filter(&tests, Some("repos")),
+            "(repos) and (test_get_repo or test_list_repos)"
+        );
+
+        let mut retried = tests.clone();
+        retried[1].outcome = Outcome::Passed;
+        retried[1].time = 0.2;
+        retried[3].message = Some("assert 2 == 3".to_string());
+        merge_retry(&mut tests, &retried[1..], 1);
+        assert_eq!(tests[1].outcome, Outcome::Passed);
+        assert!(tests[1].flaky());
+        assert_eq!(tests[1].time, 0.2);
+        assert!(tests[3].failed());
+        assert_eq!(tests[3].message.as_deref(), Some("assert 2 == 3"));
+        assert!(!tests[0].flaky());
+    }
+
+    #[test]
+    fn test_render_env() {
+        let templates: BTreeMap<String, String> = [
+            ("GITHUB_API", "http://localhost:{{ port }}/api/v3"),
+            ("GITHUB_TOKEN", "fake-{{ service }}-token"),
+            ("TENANT", "{{ namespace | default(\"default\") }}"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let mut vars: BTreeMap<String, String> = [("service", "github"), ("port", "8080")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        let env = render_env(&templates, &vars).unwrap();
+        assert_eq!(env["GITHUB_API"], "http://localhost:8080/api/v3");
+        assert_eq!(env["GITHUB_TOKEN"], "fake-github-token");
+        assert_eq!(env["TENANT"], "default");
+
+        vars.remove("port");
+        let err = render_env(&templates, &vars).unwrap_err().to_string();
+        assert!(err.contains("doesn't have: port"), "{}", err);
+    }
+
+    #[test]
+    fn test_listed_tests() {
+        let listed = parse_list(
+            "{\"id\": \"test_repos.py::TestRepositories::test_get_repo\", \"tags\": [\"smoke\"]}\n\n\
+             {\"id\": \"test_issues.py::TestIssues::test_lock\"}\n",
+        )
+        .unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].tags, vec!["smoke"]);
+        assert!(listed[1].tags.is_empty());
+        assert!(parse_list("not json").is_err());
+
+        let output = "test_repos.py::TestRepositories::test_get_repo\n\
+                      test_issues.py::TestIssues::test_lock[open]\n\
+                      \n\
+                      2/9 tests collected (7 deselected) in 0.02s\n";
+        let ids: Vec<String> = parse_collected(output).into_iter().map(|t| t.id).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "test_repos.py::TestRepositories::test_get_repo",
+                "test_issues.py::TestIssues::test_lock[open]"
+            ]
+        );
+
+        assert_eq!(
+            pytest_list_addopts("", Some("repos"), Some("smoke")),
+            "-k repos -m smoke --collect-only -q"
+        );
+    }
+
+    #[test]
+    fn test_pytest_addopts() {
+        assert_eq!(pytest_addopts("", None, None, None, false), "");
+        assert_eq!(
+            pytest_addopts(
+                " -q ",
+                Some(Path::new("/tmp/r.xml")),
+                Some("issues"),
+                None,
+                false
+            ),
+            "-q --junitxml=/tmp/r.xml -k issues"
+        );
+        assert_eq!(
+            pytest_addopts("", None, Some("issues and not it's"), None, false),
+            r#"-k 'issues and not it'"'"'s'"#
+        );
+        assert_eq!(
+            pytest_addopts("", None, None, Some("smoke and not slow"), false),
+            "-m 'smoke and not slow'"
+        );
+        assert_eq!(pytest_addopts("-q", None, None, None, true), "-q -x");
+    }
+
+    #[test]
+    fn test_tag_expression() {
+        let tags = |tags: &[&str]| tags.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        assert_eq!(tag_expression(&[], &[]), None);
+        assert_eq!(
+            tag_expression(&tags(&["smoke"]), &[]),
+            Some("smoke".to_string())
+        );
+        assert_eq!(
+            tag_expression(&tags(&["smoke", "full"]), &tags(&["slow", "destructive"])),
+            Some("(smoke or full) and not slow and not destructive".to_string())
+        );
+        assert_eq!(
+            tag_expression(&[], &tags(&["destructive"])),
+            Some("not destructive".to_string())
+        );
+    }
+}
@@ -0,0 +1,609 @@
+//! Client for the `/_doubleagent/*` control API implemented by every service.
+
+use crate::coverage::{self, RequestLog};
+use crate::telemetry::{self, TelemetryConfig};
+use crate::{dates, namespace, references};
+use crate::{Error, Result};
+use reqwest::Method;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How a seed payload combines with a service's current state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SeedMode {
+    /// Whatever the service does by default, usually adding the records
+    #[default]
+    Default,
+    /// Update records that already exist and add the others
+    Merge,
+    /// Discard the current state first
+    Replace,
+}
+
+impl fmt::Display for SeedMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SeedMode::Default => "default",
+            SeedMode::Merge => "merge",
+            SeedMode::Replace => "replace",
+        };
+        f.write_str(name)
+    }
+}
+
+/// How seeding copes with a service that fails transiently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts after the first, on connection errors only: a seed that
+    /// reached the service may have been applied, and isn't safe to send again
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each one after it
+    pub backoff: Duration,
+    /// Limit on the whole seed, retries included
+    pub timeout: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            backoff: Duration::from_millis(500),
+            timeout: None,
+        }
+    }
+}
+
+/// What a seed applied, so a caller can recover from a partial failure.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SeedReport {
+    /// Resources the service accepted, in the order they were sent
+    pub applied: Vec<String>,
+    /// The resources of the call that failed, and why
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed: Option<SeedFailure>,
+    /// Resources not sent because an earlier call failed
+    pub pending: Vec<String>,
+    /// Calls made to the seed endpoint, retries included
+    pub attempts: u32,
+    /// The service's responses, combined
+    pub response: Value,
+}
+
+/// A seed call that failed after its retries.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SeedFailure {
+    /// Resources the call carried
+    pub resources: Vec<String>,
+    /// Last error seen
+    pub error: String,
+}
+
+impl SeedReport {
+    /// The combined response, or an error naming what was and wasn't applied.
+    pub fn into_result(self) -> Result<Value> {
+        let Some(failure) = self.failed else {
+            return Ok(self.response);
+        };
+        if self.applied.is_empty() {
+            return Err(Error::Other(failure.error));
+        }
+        Err(Error::Other(format!(
+            "Seeding {} failed after {} were seeded: {}",
+            failure.resources.join(", "),
+            self.applied.join(", "),
+            failure.error
+        )))
+    }
+}
+
+/// Client for a running service's control endpoints.
+pub struct ControlClient {
+    base_url: String,
+    client: reqwest::Client,
+    retry: RetryPolicy,
+    namespace: Option<String>,
+}
+
+impl ControlClient {
+    /// Create a client for a service listening on localhost at the given port.
+    pub fn new(port: u16) -> Self {
+        Self {
+            base_url: format!("http://localhost:{}", port),
+            client: reqwest::Client::new(),
+            retry: RetryPolicy::default(),
+            namespace: None,
+        }
+    }
+
+    /// Retry seeds that fail transiently, within an overall timeout.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Send every request in a namespace, so it only sees and changes that
+    /// namespace's state.
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Base URL of the service.
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Export the service's current state as a seed-compatible payload.
+    ///
+    /// The endpoint is optional; services without it give
+    /// [`Error::ExportUnsupported`].
+    pub async fn export(&self) -> Result<Value> {
+        let url = self.url("export");
+        let resp = self.request(Method::GET, &url).send().await?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(Error::ExportUnsupported);
+        }
+        Self::json_response("export", resp).await
+    }
+
+    /// Describe the service (its optional `/_doubleagent/info` endpoint).
+    pub async fn info(&self) -> Result<Value> {
+        let url = self.url("info");
+        let resp = self.request(Method::GET, &url).send().await?;
+        Self::json_response("info", resp).await
+    }
+
+    /// Requests the service served and the routes it declares (its optional
+    /// `/_doubleagent/requests` endpoint, see [`crate::coverage`]).
+    pub async fn requests(&self) -> Result<RequestLog> {
+        Ok(serde_json::from_value(self.requests_json().await?)?)
+    }
+
+    /// The `/_doubleagent/requests` response as the service gives it, with
+    /// any detail it logs beyond [`RequestLog`].
+    pub async fn requests_json(&self) -> Result<Value> {
+        let url = self.url(coverage::REQUESTS_ENDPOINT);
+        let resp = self.request(Method::GET, &url).send().await?;
+        Self::json_response(coverage::REQUESTS_ENDPOINT, resp).await
+    }
+
+    /// Seed modes the service supports besides its default, as listed under
+    /// `seed_modes` by `/_doubleagent/info`.
+    pub async fn seed_modes(&self) -> Vec<SeedMode> {
+        let Ok(info) = self.info().await else {
+            return Vec::new();
+        };
+        let listed = info.get("seed_modes").and_then(Value::as_array);
+        [SeedMode::Merge, SeedMode::Replace]
+            .into_iter()
+            .filter(|mode| {
+                listed.is_some_and(|modes| {
+                    modes.iter().any(|m| m.as_str() == Some(&mode.to_string()))
+                })
+            })
+            .collect()
+    }
+
+    /// Whether the service keeps separate state per namespace, as
+    /// `namespaces: true` in `/_doubleagent/info` says.
+    pub async fn supports_namespaces(&self) -> bool {
+        self.info()
+            .await
+            .is_ok_and(|info| info.get("namespaces") == Some(&Value::Bool(true)))
+    }
+
+    /// Whether the service can send trace spans, as `telemetry: true` in
+    /// `/_doubleagent/info` says.
+    pub async fn supports_telemetry(&self) -> bool {
+        self.info()
+            .await
+            .is_ok_and(|info| info.get("telemetry") == Some(&Value::Bool(true)))
+    }
+
+    /// Tell the service where to send trace spans (see [`crate::telemetry`]).
+    pub async fn configure_telemetry(&self, config: &TelemetryConfig) -> Result<()> {
+        let url = self.url(telemetry::ENDPOINT);
+        let resp = self.request(Method::POST, &url).json(config).send().await?;
+        Self::json_response(telemetry::ENDPOINT, resp)
+            .await
+            .map(|_| ())
+    }
+
+    /// Seed the service with the given payload, returning the service's response.
+    ///
+    /// Payloads with `$ref` references are seeded in dependency order, one
+    /// call per stage, and the responses are combined. Relative times such
+    /// as `$now - 2d` are resolved first (see [`crate::dates`]).
+    pub async fn seed(&self, data: &Value) -> Result<Value> {
+        self.seed_with(data, SeedMode::Default).await
+    }
+
+    /// Seed the service in a given mode, after checking the service supports it.
+    ///
+    /// Services that don't support `replace` are reset first instead; `merge`
+    /// needs support from the service, as only it knows how records match.
+    pub async fn seed_with(&self, data: &Value, mode: SeedMode) -> Result<Value> {
+        self.seed_in_parts(data, mode, false).await?.into_result()
+    }
+
+    /// Seed the service one resource at a time, reporting which resources
+    /// were applied.
+    ///
+    /// Errors are only returned for problems found before anything is sent;
+    /// a resource the service fails on ends up in the report's `failed`, and
+    /// the resources after it in `pending`.
+    pub async fn seed_report(&self, data: &Value, mode: SeedMode) -> Result<SeedReport> {
+        self.seed_in_parts(data, mode, true).await
+    }
+
+    async fn seed_in_parts(
+        &self,
+        data: &Value,
+        mode: SeedMode,
+        per_resource: bool,
+    ) -> Result<SeedReport> {
+        let deadline = self.retry.timeout.map(|timeout| Instant::now() + timeout);
+        let mut data = data.clone();
+        dates::resolve_relative(&mut data, dates::now())?;
+        let data = &data;
+        let mode = match mode {
+            SeedMode::Default => mode,
+            mode => {
+                let supported = self.seed_modes().await.contains(&mode);
+                match mode {
+                    SeedMode::Merge if !supported => {
+                        return Err(Error::Other(format!(
+                            "The service at {} doesn't support merge seeding \
+                             (seed_modes in /_doubleagent/info doesn't list it)",
+                            self.base_url
+                        )))
+                    }
+                    SeedMode::Replace if !supported => {
+                        self.reset().await?;
+                        SeedMode::Default
+                    }
+                    mode => mode,
+                }
+            }
+        };
+
+        let mut report = SeedReport::default();
+        let Value::Object(resources) = data else {
+            // Not a payload of resources: pass it on for the service to judge
+            match self
+                .post_seed(data, mode, deadline, &mut report.attempts)
+                .await
+            {
+                Ok(response) => report.response = response,
+                Err(e) => {
+                    report.failed = Some(SeedFailure {
+                        resources: Vec::new(),
+                        error: e.to_string(),
+                    })
+                }
+            }
+            return Ok(report);
+        };
+        let stages = match references::seed_order(data)? {
+            Some(stages) => stages,
+            None => vec![resources.keys().cloned().collect()],
+        };
+        let stages: Vec<Vec<String>> = if per_resource {
+            stages.into_iter().flatten().map(|r| vec![r]).collect()
+        } else {
+            stages
+        };
+
+        let mut payload = resources.clone();
+        let mut created = BTreeMap::new();
+        let mut combined = Map::new();
+        let mut stages = stages.into_iter();
+        for stage in stages.by_ref() {
+            let mut part = Map::new();
+            let resolved = stage.iter().try_for_each(|resource| {
+                let mut records = payload.get(resource).cloned().unwrap_or(Value::Null);
+                references::resolve(&mut records, &payload, &created)?;
+                payload.insert(resource.clone(), records.clone());
+                part.insert(resource.clone(), records);
+                Ok::<_, Error>(())
+            });
+            if let Err(e) = resolved {
+                // Earlier stages are already in, so this is a partial seed too
+                report.failed = Some(SeedFailure {
+                    resources: stage,
+                    error: e.to_string(),
+                });
+                break;
+            }
+
+            // Only the first stage may replace the state the others add to
+            let stage_mode = match mode {
+                SeedMode::Replace if !report.applied.is_empty() => SeedMode::Default,
+                mode => mode,
+            };
+            let part = Value::Object(part);
+            let response = match self
+                .post_seed(&part, stage_mode, deadline, &mut report.attempts)
+                .await
+            {
+                Ok(response) => response,
+                Err(e) => {
+                    report.failed = Some(SeedFailure {
+                        resources: stage,
+                        error: e.to_string(),
+                    });
+                    break;
+                }
+            };
+            if let Some(Value::Object(records)) = response.get("records") {
+                for (resource, records) in records {
+                    if let Value::Array(records) = records {
+                        created.insert(resource.clone(), records.clone());
+                    }
+                }
+            }
+            if let Value::Object(response) = response {
+                for (key, value) in response {
+                    match (combined.get_mut(&key), value) {
+                        (Some(Value::Object(into)), Value::Object(from)) => into.extend(from),
+                        (_, value) => {
+                            combined.insert(key, value);
+                        }
+                    }
+                }
+            }
+            report.applied.extend(stage);
+        }
+        report.pending = stages.flatten().collect();
+        report.response = Value::Object(combined);
+        Ok(report)
+    }
+
+    /// POST a payload to the seed endpoint, retrying under the client's
+    /// [`RetryPolicy`] while the service can't be connected to.
+    async fn post_seed(
+        &self,
+        data: &Value,
+        mode: SeedMode,
+        deadline: Option<Instant>,
+        attempts: &mut u32,
+    ) -> Result<Value> {
+        let url = match mode {
+            SeedMode::Default => self.url("seed"),
+            mode => format!("{}?mode={}", self.url("seed"), mode),
+        };
+        let timed_out = || {
+            Error::Other(format!(
+                "Seeding timed out after {}s",
+                self.retry.timeout.unwrap_or_default().as_secs_f64()
+            ))
+        };
+
+        let mut backoff = self.retry.backoff;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            *attempts += 1;
+            let send = self.request(Method::POST, &url).json(data).send();
+            let sent = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, send)
+                    .await
+                    .map_err(|_| timed_out())?,
+                None => send.await,
+            };
+            let error = match sent {
+                Ok(resp) => return Self::json_response("seed", resp).await,
+                Err(e) if e.is_connect() => Error::HttpError(e),
+                Err(e) => return Err(Error::HttpError(e)),
+            };
+            if attempt > self.retry.retries {
+                return Err(error);
+            }
+            tracing::debug!("Seeding failed ({}), retrying in {:?}", error, backoff);
+            if deadline.is_some_and(|deadline| Instant::now() + backoff >= deadline) {
+                return Err(timed_out());
+            }
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+
+    /// Clear all state in the service.
+    pub async fn reset(&self) -> Result<()> {
+        let url = self.url("reset");
+        let resp = self.request(Method::POST, &url).send().await?;
+        Self::json_response("reset", resp).await.map(|_| ())
+    }
+
+    fn request(&self, method: Method, url: &str) -> reqwest::RequestBuilder {
+        let request = self.client.request(method, url);
+        match &self.namespace {
+            Some(namespace) => request.header(namespace::HEADER, namespace),
+            None => request,
+        }
+    }
+
+    fn url(&self, endpoint: &str) -> String {
+        format!("{}/_doubleagent/{}", self.base_url, endpoint)
+    }
+
+    async fn json_response(endpoint: &str, resp: reqwest::Response) -> Result<Value> {
+        let status = resp.status();
+        if !status.is_success() {
+            let body = resp.text().await.unwrap_or_default();
+            return Err(Error::Other(format!(
+                "/_doubleagent/{} returned {}: {}",
+                endpoint, status, body
+            )));
+        }
+
+        let body = resp.text().await?;
+        if body.trim().is_empty() {
+            return Ok(Value::Null);
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::rest::tests::serve;
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    /// Answer one request per connection with each status and body in turn.
+    fn serve_statuses(responses: Vec<(u16, Value)>) -> (u16, std::thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        (port, serve_on(listener, responses))
+    }
+
+    fn serve_on(
+        listener: TcpListener,
+        responses: Vec<(u16, Value)>,
+    ) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || {
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                reader.read_exact(&mut vec![0; length]).unwrap();
+                let body = body.to_string();
+                write!(
+                    stream,
+                    "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_seed_resolves_references_in_stages() {
+        let (url, server) = serve(vec![
+            json!({"status": "ok", "seeded": {"repos": 1}, "records": {"repos": [{"id": 7}]}}),
+            json!({"status": "ok", "seeded": {"issues": 1}}),
+        ]);
+        let port = url.rsplit(':').next().unwrap().parse().unwrap();
+        let payload = json!({
+            "issues": [{"title": "Flaky", "repo_id": {"$ref": "repos[0].id"}}],
+            "repos": [{"name": "api"}],
+        });
+
+        let response = ControlClient::new(port).seed(&payload).await.unwrap();
+        assert_eq!(response["seeded"], json!({"repos": 1, "issues": 1}));
+        let requests = server.join().unwrap();
+        let seeds = requests
+            .iter()
+            .filter(|line| line.starts_with("POST /_doubleagent/seed"))
+            .count();
+        assert_eq!(seeds, 2);
+    }
+
+    #[tokio::test]
+    async fn test_seed_report_names_what_was_applied() {
+        let ok = |resource: &str| json!({"status": "ok", "seeded": {resource: 1}});
+        let (port, server) = serve_statuses(vec![
+            (200, ok("repos")),
+            (200, ok("users")),
+            (503, json!({"error": "busy"})),
+        ]);
+        let payload = json!({
+            "comments": [{"issue": {"$ref": "issues[0].title"}}],
+            "issues": [{"title": "Flaky", "repo": {"$ref": "repos[0].name"}}],
+            "repos": [{"name": "api"}],
+            "users": [{"login": "octo"}],
+        });
+        let client = ControlClient::new(port).with_retry(RetryPolicy {
+            retries: 1,
+            backoff: Duration::from_millis(1),
+            timeout: Some(Duration::from_secs(30)),
+        });
+
+        let report = client
+            .seed_report(&payload, SeedMode::Default)
+            .await
+            .unwrap();
+        server.join().unwrap();
+        assert_eq!(report.applied, ["repos", "users"]);
+        assert_eq!(report.pending, ["comments"]);
+        // The service answered, so the seed may have been applied and isn't sent again
+        assert_eq!(report.attempts, 3);
+        assert_eq!(report.response["seeded"], json!({"repos": 1, "users": 1}));
+        let failed = report.failed.clone().unwrap();
+        assert_eq!(failed.resources, ["issues"]);
+        assert!(failed.error.contains("503"), "{}", failed.error);
+
+        let msg = report.into_result().unwrap_err().to_string();
+        assert!(
+            msg.contains("Seeding issues failed after repos, users were seeded"),
+            "{}",
+            msg
+        );
+    }
+
+    #[tokio::test]
+    async fn test_seed_retries_until_the_service_accepts_connections() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let server = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+            serve_on(listener, vec![(200, json!({"status": "ok"}))])
+                .join()
+                .unwrap();
+        });
+        let client = ControlClient::new(port).with_retry(RetryPolicy {
+            retries: 10,
+            backoff: Duration::from_millis(50),
+            timeout: Some(Duration::from_secs(30)),
+        });
+
+        let report = client
+            .seed_report(&json!({"repos": [{"name": "api"}]}), SeedMode::Default)
+            .await
+            .unwrap();
+        server.join().unwrap();
+        assert_eq!(report.applied, ["repos"]);
+        assert!(report.attempts > 1, "{}", report.attempts);
+    }
+
+    #[tokio::test]
+    async fn test_export_without_the_endpoint_is_unsupported() {
+        let (port, server) = serve_statuses(vec![
+            (404, json!({"detail": "Not Found"})),
+            (200, json!({"repos": []})),
+        ]);
+        let client = ControlClient::new(port);
+        assert!(matches!(
+            client.export().await,
+            Err(Error::ExportUnsupported)
+        ));
+        assert_eq!(client.export().await.unwrap(), json!({"repos": []}));
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,208 @@
+//! Endpoint coverage of contract runs, for `doubleagent contract --coverage`.
+//!
+//! Fakes that log the requests they serve return them from the optional
+//! `GET /_doubleagent/requests` control endpoint, together with the routes
+//! they declare:
+//!
+//! ```json
+//! {
+//!   "routes": [{"method": "GET", "path": "/repos/{owner}/{repo}/issues"}],
+//!   "requests": [{"method": "GET", "path": "/repos/acme/api/issues", "status": 200,
+//!                 "time": 1760000000.25}]
+//! }
+//! ```
+//!
+//! `time` (Unix seconds) is optional; `doubleagent requests --since` needs it.
+//!
+//! Each request counts as a hit of the declared route whose template its
+//! path matches. Fakes that don't list their routes are measured against
+//! the operations of their openapi.yaml instead. Control endpoints
+//! (`/_doubleagent/*`) are never counted.
+
+use crate::conformance::match_template;
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// Control endpoint returning the requests a fake served.
+pub const REQUESTS_ENDPOINT: &str = "requests";
+
+/// An operation a fake declares, e.g. `GET /repos/{owner}/{repo}/issues`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Endpoint {
+    pub method: String,
+    /// Path template, with `{name}` parameters
+    pub path: String,
+}
+
+/// One request a fake served.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct LoggedRequest {
+    pub method: String,
+    pub path: String,
+    #[serde(default)]
+    pub status: Option<u16>,
+    /// When it was served, in seconds since the Unix epoch
+    #[serde(default)]
+    pub time: Option<f64>,
+}
+
+/// A fake's `/_doubleagent/requests` response.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RequestLog {
+    /// Routes the fake declares, if it lists them
+    #[serde(default)]
+    pub routes: Vec<Endpoint>,
+    #[serde(default)]
+    pub requests: Vec<LoggedRequest>,
+}
+
+/// How often one declared endpoint was requested.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EndpointHits {
+    #[serde(flatten)]
+    pub endpoint: Endpoint,
+    pub hits: usize,
+}
+
+/// Which of a fake's declared endpoints a run requested.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Coverage {
+    /// Number of declared endpoints requested at least once
+    pub hit: usize,
+    pub declared: usize,
+    /// Share of declared endpoints hit, from 0 to 100
+    pub percent: f64,
+    pub endpoints: Vec<EndpointHits>,
+    /// Requests that matched no declared endpoint, e.g. `GET /nope`
+    pub undeclared: Vec<String>,
+}
+
+impl Coverage {
+    /// Count the requests that hit each of the `declared` endpoints.
+    pub fn new(declared: &[Endpoint], requests: &[LoggedRequest]) -> Self {
+        let declared: BTreeSet<&Endpoint> = declared.iter().collect();
+        let mut endpoints: Vec<EndpointHits> = declared
+            .iter()
+            .map(|endpoint| EndpointHits {
+                endpoint: (*endpoint).clone(),
+                hits: 0,
+            })
+            .collect();
+        let mut undeclared = BTreeSet::new();
+
+        for request in requests {
+            let path = request.path.split(['?', '#']).next().unwrap_or_default();
+            if path.starts_with("/_doubleagent") {
+                continue;
+            }
+            let templates = declared
+                .iter()
+                .filter(|e| e.method.eq_ignore_ascii_case(&request.method))
+                .map(|e| e.path.as_str());
+            let hit = match_template(templates, path).and_then(|template| {
+                endpoints.iter_mut().find(|e| {
+                    e.endpoint.path == template
+                        && e.endpoint.method.eq_ignore_ascii_case(&request.method)
+                })
+            });
+            match hit {
+                Some(endpoint) => endpoint.hits += 1,
+                None => {
+                    undeclared.insert(format!("{} {}", request.method.to_uppercase(), path));
+                }
+            }
+        }
+
+        let hit = endpoints.iter().filter(|e| e.hits > 0).count();
+        let percent = match endpoints.len() {
+            0 => 100.0,
+            declared => hit as f64 * 100.0 / declared as f64,
+        };
+        Self {
+            hit,
+            declared: endpoints.len(),
+            percent,
+            endpoints,
+            undeclared: undeclared.into_iter().collect(),
+        }
+    }
+}
+
+/// Parse a percentage such as `80` or `80%`.
+pub fn parse_percent(s: &str) -> Result<f64> {
+    let percent: f64 = s
+        .trim()
+        .trim_end_matches('%')
+        .parse()
+        .map_err(|_| Error::Other(format!("Invalid percentage '{}'", s)))?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(Error::Other(format!(
+            "Percentage {} isn't between 0 and 100",
+            s
+        )));
+    }
+    Ok(percent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(method: &str, path: &str) -> Endpoint {
+        Endpoint {
+            method: method.to_string(),
+            path: path.to_string(),
+        }
+    }
+
+    fn request(method: &str, path: &str) -> LoggedRequest {
+        LoggedRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            status: Some(200),
+            time: None,
+        }
+    }
+
+    #[test]
+    fn test_requests_hit_declared_endpoints() {
+        let declared = [
+            endpoint("GET", "/repos/{owner}/{repo}/issues"),
+            endpoint("POST", "/repos/{owner}/{repo}/issues"),
+            endpoint("GET", "/user"),
+            endpoint("GET", "/users/{login}"),
+        ];
+        let coverage = Coverage::new(
+            &declared,
+            &[
+                request("GET", "/repos/acme/api/issues?state=open"),
+                request("get", "/repos/acme/web/issues"),
+                request("GET", "/users/ada"),
+                request("DELETE", "/user"),
+                request("GET", "/_doubleagent/health"),
+            ],
+        );
+        assert_eq!(coverage.hit, 2);
+        assert_eq!(coverage.declared, 4);
+        assert_eq!(coverage.percent, 50.0);
+        let hits: Vec<usize> = coverage.endpoints.iter().map(|e| e.hits).collect();
+        // Sorted by method, then path
+        assert_eq!(
+            coverage.endpoints[0].endpoint.path,
+            "/repos/{owner}/{repo}/issues"
+        );
+        assert_eq!(hits, [2, 0, 1, 0]);
+        assert_eq!(coverage.undeclared, ["DELETE /user"]);
+
+        assert_eq!(Coverage::new(&[], &[]).percent, 100.0);
+    }
+
+    #[test]
+    fn test_parse_percent() {
+        assert_eq!(parse_percent("80").unwrap(), 80.0);
+        assert_eq!(parse_percent("62.5%").unwrap(), 62.5);
+        assert!(parse_percent("120").is_err());
+        assert!(parse_percent("most").is_err());
+    }
+}
@@ -0,0 +1,270 @@
+//! Calendar dates without a date library: days and seconds since the Unix
+//! epoch, converted from and to `YYYY-MM-DD` and RFC 3339 UTC times.
+//!
+//! Seed payloads may also give times relative to when they are seeded, so
+//! fixtures don't go stale: a string that is exactly `$now`, optionally
+//! followed by offsets such as `$now - 2d` or `$now - 1d + 6h`, is replaced by
+//! that RFC 3339 time when the payload is seeded. Units are `s`, `m`, `h`, `d`
+//! and `w`; a leading `$$` keeps a literal `$`.
+
+use crate::{Error, Result};
+use serde_json::Value;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Start of a relative time expression.
+pub const NOW: &str = "$now";
+
+/// Seconds in a day.
+pub const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Days since 1970-01-01 of a `YYYY-MM-DD` date.
+pub fn parse_date(date: &str) -> Result<i64> {
+    let invalid = || Error::Other(format!("invalid date '{}' (expected YYYY-MM-DD)", date));
+    let parts: Vec<&str> = date.split('-').collect();
+    let [year, month, day] = parts.as_slice() else {
+        return Err(invalid());
+    };
+    let year: i64 = year.parse().map_err(|_| invalid())?;
+    let month: i64 = month.parse().map_err(|_| invalid())?;
+    let day: i64 = day.parse().map_err(|_| invalid())?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+    // Days from civil, counting years from March so leap days come last
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    Ok(era * 146_097 + doe - 719_468)
+}
+
+/// RFC 3339 UTC time of seconds since the Unix epoch.
+pub fn format_time(seconds: i64) -> String {
+    let days = seconds.div_euclid(SECONDS_PER_DAY);
+    let secs = seconds.rem_euclid(SECONDS_PER_DAY);
+    // Civil from days, the inverse of `parse_date`
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// `YYYY-MM-DD` of a day since 1970-01-01.
+pub fn format_date(days: i64) -> String {
+    format_time(days * SECONDS_PER_DAY)[..10].to_string()
+}
+
+/// Seconds since the Unix epoch of an RFC 3339 UTC time
+/// (`YYYY-MM-DDTHH:MM:SSZ`) or of midnight of a `YYYY-MM-DD` date.
+pub fn parse_time(time: &str) -> Result<i64> {
+    let invalid = || {
+        Error::Other(format!(
+            "invalid time '{}' (expected YYYY-MM-DD or YYYY-MM-DDTHH:MM:SSZ)",
+            time
+        ))
+    };
+    let (date, clock) = match time.split_once('T') {
+        Some((date, clock)) => (date, Some(clock.strip_suffix('Z').ok_or_else(invalid)?)),
+        None => (time, None),
+    };
+    let days = parse_date(date).map_err(|_| invalid())?;
+    let seconds = match clock {
+        None => 0,
+        Some(clock) => {
+            let parts: Vec<i64> = clock
+                .split(':')
+                .map(|part| part.parse().map_err(|_| invalid()))
+                .collect::<Result<_>>()?;
+            match parts.as_slice() {
+                [h, m, s] if *h < 24 && *m < 60 && *s < 60 => h * 3600 + m * 60 + s,
+                _ => return Err(invalid()),
+            }
+        }
+    };
+    Ok(days * SECONDS_PER_DAY + seconds)
+}
+
+/// Seconds since the Unix epoch now.
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+/// A span of seconds in its two largest units, e.g. `45s`, `3m 20s`,
+/// `2h 5m` or `4d 1h`; a smaller unit of zero is left out, as in `10m`.
+pub fn format_span(seconds: i64) -> String {
+    let seconds = seconds.max(0);
+    let (days, hours) = (seconds / SECONDS_PER_DAY, seconds / 3600 % 24);
+    let (minutes, secs) = (seconds / 60 % 60, seconds % 60);
+    let (large, small) = match seconds {
+        0..=59 => return format!("{}s", secs),
+        60..=3599 => ((minutes, "m"), (secs, "s")),
+        3600..=86_399 => ((hours, "h"), (minutes, "m")),
+        _ => ((days, "d"), (hours, "h")),
+    };
+    if small.0 == 0 {
+        format!("{}{}", large.0, large.1)
+    } else {
+        format!("{}{} {}{}", large.0, large.1, small.0, small.1)
+    }
+}
+
+/// Seconds since the Unix epoch of a relative time expression, or `None` if
+/// `text` isn't one.
+pub fn parse_relative(text: &str, now: i64) -> Option<Result<i64>> {
+    let rest = text.strip_prefix(NOW)?;
+    // `$nowhere` is just a string
+    if rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+    let invalid = || {
+        Error::Other(format!(
+            "invalid relative time '{}' (expected e.g. $now, $now - 2d or $now + 1h)",
+            text
+        ))
+    };
+
+    let mut time = now;
+    let mut rest = rest.trim_start();
+    while !rest.is_empty() {
+        let sign = match rest.as_bytes()[0] {
+            b'+' => 1,
+            b'-' => -1,
+            _ => return Some(Err(invalid())),
+        };
+        rest = rest[1..].trim_start();
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let Ok(amount) = rest[..digits].parse::<i64>() else {
+            return Some(Err(invalid()));
+        };
+        let unit = match rest[digits..].chars().next() {
+            Some('s') => 1,
+            Some('m') => 60,
+            Some('h') => 3600,
+            Some('d') => SECONDS_PER_DAY,
+            Some('w') => 7 * SECONDS_PER_DAY,
+            _ => return Some(Err(invalid())),
+        };
+        let Some(next) = amount
+            .checked_mul(sign * unit)
+            .and_then(|offset| time.checked_add(offset))
+        else {
+            return Some(Err(invalid()));
+        };
+        time = next;
+        rest = rest[digits + 1..].trim_start();
+    }
+    Some(Ok(time))
+}
+
+/// Replace every string of a payload that is a relative time expression with
+/// the RFC 3339 time it stands for, measured from `now`.
+pub fn resolve_relative(value: &mut Value, now: i64) -> Result<()> {
+    match value {
+        Value::String(s) => {
+            if let Some(literal) = s.strip_prefix('$').filter(|l| l.starts_with(NOW)) {
+                *s = literal.to_string();
+            } else if let Some(time) = parse_relative(s, now) {
+                *s = format_time(time?);
+            }
+            Ok(())
+        }
+        Value::Array(items) => items
+            .iter_mut()
+            .try_for_each(|item| resolve_relative(item, now)),
+        Value::Object(map) => map
+            .values_mut()
+            .try_for_each(|item| resolve_relative(item, now)),
+        Value::Null | Value::Bool(_) | Value::Number(_) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_span() {
+        assert_eq!(format_span(-3), "0s");
+        assert_eq!(format_span(45), "45s");
+        assert_eq!(format_span(200), "3m 20s");
+        assert_eq!(format_span(7_500), "2h 5m");
+        assert_eq!(format_span(349_200), "4d 1h");
+        assert_eq!(format_span(600), "10m");
+    }
+
+    #[test]
+    fn test_dates_round_trip() {
+        assert_eq!(parse_date("1970-01-01").unwrap(), 0);
+        assert_eq!(format_time(0), "1970-01-01T00:00:00Z");
+        let leap = parse_date("2024-02-29").unwrap();
+        assert_eq!(
+            format_time(leap * SECONDS_PER_DAY + 3661),
+            "2024-02-29T01:01:01Z"
+        );
+        assert_eq!(
+            format_time((leap + 1) * SECONDS_PER_DAY),
+            "2024-03-01T00:00:00Z"
+        );
+        assert!(parse_date("2024-13-01").is_err());
+    }
+
+    #[test]
+    fn test_times_parse() {
+        assert_eq!(parse_time("1970-01-02").unwrap(), SECONDS_PER_DAY);
+        assert_eq!(parse_time("1970-01-01T01:02:03Z").unwrap(), 3723);
+        assert_eq!(format_date(parse_date("2024-02-29").unwrap()), "2024-02-29");
+        assert!(parse_time("1970-01-01T25:00:00Z").is_err());
+        assert!(parse_time("1970-01-01T01:02:03").is_err());
+    }
+
+    #[test]
+    fn test_relative_times_resolve_from_now() {
+        let now = parse_time("2024-03-01T12:00:00Z").unwrap();
+        let mut payload = serde_json::json!({"issues": [{
+            "created_at": "$now - 2d",
+            "updated_at": "$now-1d+6h",
+            "closed_at": "$now",
+            "due": "$now + 1w",
+            "title": "$nowhere",
+            "note": "$$now - 2d",
+            "count": 3,
+        }]});
+        resolve_relative(&mut payload, now).unwrap();
+        assert_eq!(
+            payload,
+            serde_json::json!({"issues": [{
+                "created_at": "2024-02-28T12:00:00Z",
+                "updated_at": "2024-02-29T18:00:00Z",
+                "closed_at": "2024-03-01T12:00:00Z",
+                "due": "2024-03-08T12:00:00Z",
+                "title": "$nowhere",
+                "note": "$now - 2d",
+                "count": 3,
+            }]})
+        );
+
+        for invalid in ["$now - 2", "$now 2d", "$now - 2y", "$now - d"] {
+            let mut value = Value::String(invalid.to_string());
+            assert!(resolve_relative(&mut value, now).is_err(), "{}", invalid);
+        }
+    }
+}
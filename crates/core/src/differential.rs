@@ -0,0 +1,471 @@
+//! Differential testing: the same requests sent to a fake and the real API,
+//! for `doubleagent diff-proxy`.
+//!
+//! [`run`] starts a plain HTTP proxy in front of the fake. Every request is
+//! forwarded to the fake and answered with the fake's response, so the app
+//! behind the proxy works as usual. Read-only requests (`GET`, `HEAD` and
+//! `OPTIONS`) are also sent to the real API, authenticated with its token,
+//! and the two responses are compared: the status, then the body field by
+//! field. Requests that change state are never sent to the real API.
+//!
+//! Before comparing, both bodies are normalized: strings starting with the
+//! base URL of the side that sent them have it replaced by `{base_url}`, so
+//! links compare equal, and fields matching the `ignore` globs (the same
+//! globs as `contracts.golden.ignore`) are left out.
+
+use crate::golden;
+use crate::snapshot::record::{endpoint, is_hop_by_hop};
+use crate::{Error, Result};
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{HeaderMap, Method, Response};
+use hyper_util::rt::TokioIo;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+
+/// Placeholder for the base URL of either side in normalized bodies.
+const BASE_URL: &str = "{base_url}";
+
+/// How many differences are kept for one response before the rest are counted.
+const MAX_DIFFERENCES: usize = 10;
+
+/// Where the proxy sends requests.
+#[derive(Debug, Clone)]
+pub struct Upstreams {
+    /// Base URL of the fake, which answers every request
+    pub fake: String,
+    /// Base URL of the real API, which read-only requests are also sent to
+    pub real: String,
+    /// Token for the real API, sent as a bearer token in place of the
+    /// client's `Authorization` header
+    pub token: Option<String>,
+    /// Globs of body fields left out of the comparison
+    pub ignore: Vec<String>,
+}
+
+/// A read-only request the fake and the real API answered differently.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Divergence {
+    pub method: String,
+    /// Path and query of the request
+    pub path: String,
+    /// The path with IDs replaced by `{id}`
+    pub endpoint: String,
+    pub fake_status: u16,
+    pub real_status: u16,
+    /// How the fake's response differs from the real one
+    pub differences: Vec<String>,
+}
+
+/// Requests compared per endpoint.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct EndpointSummary {
+    pub compared: usize,
+    pub divergent: usize,
+}
+
+/// Everything the proxy saw.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DivergenceReport {
+    /// Requests proxied to the fake
+    pub requests: usize,
+    /// Read-only requests also sent to the real API
+    pub compared: usize,
+    /// Read-only requests the real API couldn't be reached for
+    pub unreachable: usize,
+    /// Compared requests by endpoint
+    pub endpoints: BTreeMap<String, EndpointSummary>,
+    pub divergences: Vec<Divergence>,
+}
+
+impl DivergenceReport {
+    /// Add the comparison of one request.
+    fn add(&mut self, divergence: Divergence) {
+        self.compared += 1;
+        let summary = self
+            .endpoints
+            .entry(divergence.endpoint.clone())
+            .or_default();
+        summary.compared += 1;
+        if !divergence.differences.is_empty() {
+            summary.divergent += 1;
+            self.divergences.push(divergence);
+        }
+    }
+}
+
+/// Whether a request is sent to the real API as well.
+pub fn is_read_only(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// `body` with the `base_url` at the start of any string replaced by a
+/// placeholder both sides share.
+pub fn normalize(body: &Value, base_url: &str) -> Value {
+    let base_url = base_url.trim_end_matches('/');
+    match body {
+        Value::String(s) => match s.strip_prefix(base_url) {
+            Some(rest) if !base_url.is_empty() => Value::String(format!("{}{}", BASE_URL, rest)),
+            _ => body.clone(),
+        },
+        Value::Array(items) => Value::Array(items.iter().map(|v| normalize(v, base_url)).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(k, v)| (k.clone(), normalize(v, base_url)))
+                .collect(),
+        ),
+        _ => body.clone(),
+    }
+}
+
+/// How the fake's response differs from the real one, both normalized.
+pub fn compare(upstreams: &Upstreams, real: (u16, &Value), fake: (u16, &Value)) -> Vec<String> {
+    let mut differences = Vec::new();
+    if real.0 != fake.0 {
+        differences.push(format!("status: real {}, fake {}", real.0, fake.0));
+    }
+    let ignore: Vec<Vec<String>> = upstreams
+        .ignore
+        .iter()
+        .map(|glob| golden::segments(glob))
+        .collect();
+    golden::diff(
+        &normalize(real.1, &upstreams.real),
+        &normalize(fake.1, &upstreams.fake),
+        &mut Vec::new(),
+        &ignore,
+        "the real response",
+        &mut differences,
+    );
+    if differences.len() > MAX_DIFFERENCES {
+        let more = differences.len() - MAX_DIFFERENCES;
+        differences.truncate(MAX_DIFFERENCES);
+        differences.push(format!("... and {} more", more));
+    }
+    differences
+}
+
+/// Proxy connections on `listener` to the fake, comparing read-only requests
+/// with the real API, until `shutdown` completes.
+pub async fn run(
+    listener: TcpListener,
+    upstreams: Upstreams,
+    shutdown: impl Future<Output = ()>,
+) -> Result<DivergenceReport> {
+    for url in [&upstreams.fake, &upstreams.real] {
+        if !(url.starts_with("http://") || url.starts_with("https://")) {
+            return Err(Error::Other(format!(
+                "'{}' must be an http:// or https:// URL",
+                url
+            )));
+        }
+    }
+    let upstreams = Arc::new(Upstreams {
+        fake: upstreams.fake.trim_end_matches('/').to_string(),
+        real: upstreams.real.trim_end_matches('/').to_string(),
+        ..upstreams
+    });
+
+    let report = Arc::new(Mutex::new(DivergenceReport::default()));
+    let client = reqwest::Client::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        let (stream, _) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = &mut shutdown => break,
+        };
+
+        let report = report.clone();
+        let client = client.clone();
+        let upstreams = upstreams.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |request| {
+                forward(client.clone(), upstreams.clone(), report.clone(), request)
+            });
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("Diff proxy connection error: {}", e);
+            }
+        });
+    }
+
+    let report = std::mem::take(&mut *report.lock().unwrap());
+    Ok(report)
+}
+
+/// A response as read from an upstream.
+struct Upstream {
+    status: u16,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+/// Send one request to an upstream, with the client's headers.
+async fn send(
+    client: &reqwest::Client,
+    url: String,
+    method: Method,
+    headers: &HeaderMap,
+    token: Option<&str>,
+    body: Bytes,
+) -> std::result::Result<Upstream, reqwest::Error> {
+    let mut request = client.request(method, url);
+    for (name, value) in headers {
+        // Ask for an identity encoding so JSON bodies can be read
+        if is_hop_by_hop(name.as_str()) || name == "host" || name == "accept-encoding" {
+            continue;
+        }
+        if token.is_some() && name == "authorization" {
+            continue;
+        }
+        request = request.header(name, value);
+    }
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = request.body(body).send().await?;
+    Ok(Upstream {
+        status: response.status().as_u16(),
+        headers: response.headers().clone(),
+        body: response.bytes().await?,
+    })
+}
+
+/// Forward one request to the fake, and compare read-only ones with the real API.
+async fn forward(
+    client: reqwest::Client,
+    upstreams: Arc<Upstreams>,
+    report: Arc<Mutex<DivergenceReport>>,
+    request: hyper::Request<Incoming>,
+) -> std::result::Result<Response<Full<Bytes>>, std::convert::Infallible> {
+    let (parts, body) = request.into_parts();
+    let path_and_query = parts
+        .uri
+        .path_and_query()
+        .map_or("/", |p| p.as_str())
+        .to_string();
+    let body = match body.collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(e) => return Ok(bad_gateway(&e.to_string())),
+    };
+
+    let to_fake = send(
+        &client,
+        format!("{}{}", upstreams.fake, path_and_query),
+        parts.method.clone(),
+        &parts.headers,
+        None,
+        body.clone(),
+    );
+    let (fake, real) = if is_read_only(&parts.method) {
+        let to_real = send(
+            &client,
+            format!("{}{}", upstreams.real, path_and_query),
+            parts.method.clone(),
+            &parts.headers,
+            upstreams.token.as_deref(),
+            body,
+        );
+        let (fake, real) = tokio::join!(to_fake, to_real);
+        (fake, Some(real))
+    } else {
+        (to_fake.await, None)
+    };
+
+    let fake = match fake {
+        Ok(fake) => fake,
+        Err(e) => {
+            tracing::warn!("Diff proxy could not reach the fake: {}", e);
+            report.lock().unwrap().requests += 1;
+            return Ok(bad_gateway(&e.to_string()));
+        }
+    };
+
+    {
+        let mut report = report.lock().unwrap();
+        report.requests += 1;
+        match real {
+            Some(Ok(real)) => {
+                let differences = compare(
+                    &upstreams,
+                    (real.status, &golden::parse_body(&real.body)),
+                    (fake.status, &golden::parse_body(&fake.body)),
+                );
+                report.add(Divergence {
+                    method: parts.method.to_string(),
+                    path: path_and_query.clone(),
+                    endpoint: endpoint(parts.uri.path()),
+                    fake_status: fake.status,
+                    real_status: real.status,
+                    differences,
+                });
+            }
+            Some(Err(e)) => {
+                tracing::warn!("Diff proxy could not reach {}: {}", upstreams.real, e);
+                report.unreachable += 1;
+            }
+            None => {}
+        }
+    }
+
+    let mut builder = Response::builder().status(fake.status);
+    for (name, value) in &fake.headers {
+        if !is_hop_by_hop(name.as_str()) && name != "content-length" {
+            builder = builder.header(name, value);
+        }
+    }
+    Ok(builder.body(Full::new(fake.body)).unwrap())
+}
+
+fn bad_gateway(message: &str) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(502)
+        .body(Full::new(Bytes::from(format!(
+            "doubleagent diff-proxy: {}",
+            message
+        ))))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    fn upstreams(ignore: &[&str]) -> Upstreams {
+        Upstreams {
+            fake: "http://localhost:8080".to_string(),
+            real: "https://api.github.com".to_string(),
+            token: None,
+            ignore: ignore.iter().map(|glob| glob.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_compare_normalizes_urls_and_ignores_fields() {
+        let real = json!({
+            "id": 1296269,
+            "name": "api",
+            "url": "https://api.github.com/repos/acme/api",
+            "private": false,
+        });
+        let fake = json!({
+            "id": 1,
+            "name": "api",
+            "url": "http://localhost:8080/repos/acme/api",
+            "private": true,
+            "extra": null,
+        });
+        assert_eq!(
+            compare(&upstreams(&["id"]), (200, &real), (200, &fake)),
+            vec![
+                "body.extra: not in the real response",
+                "body.private: expected false, got true",
+            ]
+        );
+        assert_eq!(
+            compare(&upstreams(&[]), (200, &json!(null)), (404, &json!(null))),
+            vec!["status: real 200, fake 404"]
+        );
+    }
+
+    /// An upstream answering one request with `body`, returning its request line
+    fn upstream(body: &'static str) -> (String, std::thread::JoinHandle<String>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let mut authorization = String::new();
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if header.to_lowercase().starts_with("authorization:") {
+                    authorization = header.trim().to_string();
+                }
+            }
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+            let _ = reader.read(&mut [0; 1]);
+            format!("{} {}", line.trim(), authorization)
+        });
+        (url, server)
+    }
+
+    #[tokio::test]
+    async fn test_proxy_answers_from_the_fake_and_reports_divergences() {
+        let (fake, fake_server) = upstream(r#"[{"id": 1, "title": "Flaky"}]"#);
+        let (real, real_server) = upstream(r#"[{"id": 7, "title": "Slow"}]"#);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = format!("http://{}", listener.local_addr().unwrap());
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let upstreams = Upstreams {
+            fake,
+            real,
+            token: Some("real-token".to_string()),
+            ignore: vec!["*.id".to_string()],
+        };
+        let differ = tokio::spawn(async move {
+            run(listener, upstreams, async {
+                let _ = stopped.await;
+            })
+            .await
+        });
+
+        let body: Value = reqwest::Client::new()
+            .get(format!("{}/repos/acme/api/issues?state=open", proxy))
+            .bearer_auth("fake-token")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body, json!([{"id": 1, "title": "Flaky"}]));
+
+        stop.send(()).unwrap();
+        let report = differ.await.unwrap().unwrap();
+        assert_eq!((report.requests, report.compared), (1, 1));
+        assert_eq!(
+            report.divergences,
+            vec![Divergence {
+                method: "GET".to_string(),
+                path: "/repos/acme/api/issues?state=open".to_string(),
+                endpoint: "/repos/acme/api/issues".to_string(),
+                fake_status: 200,
+                real_status: 200,
+                differences: vec!["body.0.title: expected \"Slow\", got \"Flaky\"".to_string()],
+            }]
+        );
+        assert_eq!(
+            fake_server.join().unwrap(),
+            "GET /repos/acme/api/issues?state=open HTTP/1.1 authorization: Bearer fake-token"
+        );
+        assert_eq!(
+            real_server.join().unwrap(),
+            "GET /repos/acme/api/issues?state=open HTTP/1.1 authorization: Bearer real-token"
+        );
+    }
+}
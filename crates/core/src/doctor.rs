@@ -0,0 +1,816 @@
+//! Environment diagnostics for `doubleagent doctor`.
+//!
+//! New setups mostly fail the same few ways: a missing toolchain (git, mise,
+//! uv, Python), no access to the services repository or snapshot registry, a
+//! port taken by something else, state left behind by processes that died or
+//! fakes that outlived their state, and a full disk. Each check here returns
+//! [`Check`]s saying what was found and, for problems, how to fix them.
+
+use crate::config::Config;
+use crate::process::ProcessManager;
+use crate::snapshot::registry::Registry;
+use crate::{workspace, Error, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// How long the services repository and registry get to answer.
+pub const NETWORK_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Size of the services' logs worth pointing out.
+pub const LOGS_WARNING_BYTES: u64 = 512 * 1000 * 1000;
+
+/// Free disk space below which snapshots and logs may not fit.
+pub const FREE_SPACE_WARNING_BYTES: u64 = 1000 * 1000 * 1000;
+
+/// How a check came out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// Nothing to do
+    Ok,
+    /// Works, but may cause trouble
+    Warning,
+    /// Something won't work until it's fixed
+    Problem,
+}
+
+/// The outcome of one check.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Check {
+    /// What was checked, e.g. "git" or "port 8080"
+    pub name: String,
+    pub status: Status,
+    /// What was found
+    pub detail: String,
+    /// How to fix what was found
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<String>,
+}
+
+impl Check {
+    pub fn ok(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: Status::Ok,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    pub fn warning(
+        name: impl Into<String>,
+        detail: impl Into<String>,
+        fix: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            status: Status::Warning,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    pub fn problem(
+        name: impl Into<String>,
+        detail: impl Into<String>,
+        fix: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            status: Status::Problem,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// Whether the tools services are fetched and run with are on the PATH.
+///
+/// Services run under mise, which provides their uv and Python, so those
+/// only matter when mise is missing.
+pub fn toolchain() -> Vec<Check> {
+    let mise = version("mise");
+    let mut checks = vec![
+        match version("git") {
+            Some(version) => Check::ok("git", version),
+            None => Check::problem(
+                "git",
+                "not found; services can't be added or updated",
+                "Install git: https://git-scm.com/downloads",
+            ),
+        },
+        match &mise {
+            Some(version) => Check::ok("mise", version.clone()),
+            None => Check::problem(
+                "mise",
+                "not found; services can't install their toolchains",
+                "Install mise: curl https://mise.run | sh",
+            ),
+        },
+    ];
+    let tools = [
+        (
+            "uv",
+            "Install uv: curl -LsSf https://astral.sh/uv/install.sh | sh",
+        ),
+        (
+            "python3",
+            "Install Python 3.11 or later: mise use -g python@3.11",
+        ),
+    ];
+    for (tool, fix) in tools {
+        checks.push(match (version(tool), &mise) {
+            (Some(version), _) => Check::ok(tool, version),
+            (None, Some(_)) => Check::ok(tool, "not on the PATH; services get it from mise"),
+            (None, None) => {
+                Check::warning(tool, "not found, and mise isn't there to provide it", fix)
+            }
+        });
+    }
+    checks
+}
+
+/// First line of `<tool> --version`, if the tool is on the PATH.
+fn version(tool: &str) -> Option<String> {
+    let path = which::which(tool).ok()?;
+    let output = Command::new(&path)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let line = text.lines().next().unwrap_or_default().trim();
+    Some(if line.is_empty() {
+        path.display().to_string()
+    } else {
+        line.to_string()
+    })
+}
+
+/// Whether the services repository's branch can be listed, without cloning it.
+pub async fn services_repo(config: &Config) -> Check {
+    const NAME: &str = "services repository";
+    let fix = format!(
+        "Check network and proxy access to {}, or point repo_url at a mirror: \
+         doubleagent config set repo_url <url>",
+        config.repo_url
+    );
+    if which::which("git").is_err() {
+        return Check::warning(NAME, "not checked without git", "Install git first");
+    }
+    let output = tokio::process::Command::new("git")
+        .args(["ls-remote", "--heads", &config.repo_url, &config.branch])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    match tokio::time::timeout(NETWORK_TIMEOUT, output).await {
+        Err(_) => Check::problem(
+            NAME,
+            format!(
+                "{} didn't answer within {}s",
+                config.repo_url,
+                NETWORK_TIMEOUT.as_secs()
+            ),
+            fix,
+        ),
+        Ok(Err(e)) => Check::problem(NAME, format!("git failed to run: {}", e), fix),
+        Ok(Ok(output)) if !output.status.success() => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reason = stderr.lines().last().unwrap_or("git ls-remote failed");
+            Check::problem(NAME, format!("{}: {}", config.repo_url, reason.trim()), fix)
+        }
+        Ok(Ok(output)) if output.stdout.is_empty() => Check::problem(
+            NAME,
+            format!("{} has no branch '{}'", config.repo_url, config.branch),
+            "Choose an existing branch: doubleagent config set branch <branch>",
+        ),
+        Ok(Ok(_)) => Check::ok(NAME, format!("{} ({})", config.repo_url, config.branch)),
+    }
+}
+
+/// Whether the snapshot registry, if one is configured, can be reached.
+///
+/// HTTP and OCI registries are asked over HTTPS, where any answer counts;
+/// bucket registries need their provider's CLI, unless they're transferred
+/// natively.
+pub async fn registry(config: &Config) -> Option<Check> {
+    const NAME: &str = "snapshot registry";
+    let url = config.registry.as_deref()?;
+    let registry = match Registry::parse(url) {
+        Ok(registry) => registry,
+        Err(e) => {
+            return Some(Check::problem(
+                NAME,
+                e.to_string(),
+                "Fix it: doubleagent config set registry <url>",
+            ))
+        }
+    };
+    let cli = |tools: &[&str], install: &str| {
+        if cfg!(feature = "native-storage") {
+            return Check::ok(NAME, format!("{} (native transfers)", url));
+        }
+        match tools.iter().find(|tool| which::which(tool).is_ok()) {
+            Some(tool) => Check::ok(NAME, format!("{} (through {})", url, tool)),
+            None => Check::problem(
+                NAME,
+                format!("{} needs the {} CLI, which isn't installed", url, tools[0]),
+                install.to_string(),
+            ),
+        }
+    };
+    Some(match &registry {
+        Registry::S3(_) => cli(&["aws"], "Install the AWS CLI: https://aws.amazon.com/cli/"),
+        Registry::Gcs(_) => cli(
+            &["gcloud", "gsutil"],
+            "Install the Google Cloud CLI: https://cloud.google.com/sdk/docs/install",
+        ),
+        Registry::Azure(_) => cli(
+            &["az"],
+            "Install the Azure CLI: https://learn.microsoft.com/cli/azure/install-azure-cli",
+        ),
+        Registry::Http(url) => reachable(NAME, url).await,
+        Registry::Oci(url) => {
+            let host = url
+                .trim_start_matches("oci://")
+                .split('/')
+                .next()
+                .unwrap_or_default();
+            reachable(NAME, &format!("https://{}/v2/", host)).await
+        }
+    })
+}
+
+/// Whether `url` answers at all; a 404 or 401 still means it's reachable.
+async fn reachable(name: &str, url: &str) -> Check {
+    let response = reqwest::Client::new()
+        .get(url)
+        .timeout(NETWORK_TIMEOUT)
+        .send()
+        .await;
+    match response {
+        Ok(response) => Check::ok(name, format!("{} answered {}", url, response.status())),
+        Err(e) => Check::problem(
+            name,
+            format!("{} is unreachable: {}", url, e),
+            "Check network and proxy access, or change it: doubleagent config set registry <url>",
+        ),
+    }
+}
+
+/// Whether the configuration loads, given the error loading it gave if not.
+pub fn configuration(error: Option<&Error>) -> Check {
+    match error {
+        None => Check::ok("config", "the global and project configs load"),
+        Some(e) => Check::problem(
+            "config",
+            e.to_string(),
+            "Fix the setting it names in doubleagent.yaml or the global config; until \
+             then other commands fail, and the checks below use the built-in settings",
+        ),
+    }
+}
+
+/// Whether the ports services and contract tests start from are free, or
+/// held by doubleagent's own services.
+pub fn ports(config: &Config) -> Result<Vec<Check>> {
+    // A corrupt state file is reported under State
+    let in_use = workspace::ports_in_use(&config.state_dir).unwrap_or_default();
+    let ports = [
+        ("base_port", config.base_port, "services"),
+        ("contract_port", config.contract_port, "contract tests"),
+    ];
+    Ok(ports
+        .into_iter()
+        .map(|(key, port, user)| {
+            let name = format!("port {}", port);
+            if in_use.contains(&port) {
+                Check::ok(
+                    name,
+                    format!("{} ({}), used by a running service", key, user),
+                )
+            } else if TcpListener::bind(("127.0.0.1", port)).is_ok() {
+                Check::ok(name, format!("{} ({}), free", key, user))
+            } else {
+                Check::warning(
+                    name,
+                    format!("{} ({}) is taken by another process", key, user),
+                    format!(
+                        "Stop whatever listens on it (lsof -i :{}), or choose another: \
+                         doubleagent config set {} <port>",
+                        port, key
+                    ),
+                )
+            }
+        })
+        .collect())
+}
+
+/// A service a state file still lists although its process is gone.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StaleEntry {
+    pub workspace: String,
+    pub service: String,
+    pub pid: u32,
+}
+
+/// Services listed in any workspace's state whose process died, and state
+/// files that can't be read (which commands in that workspace refuse).
+pub fn stale_state(state_dir: &Path) -> Result<(Vec<StaleEntry>, Vec<PathBuf>)> {
+    let mut stale = Vec::new();
+    let mut corrupt = Vec::new();
+    for name in workspace::list(state_dir)? {
+        let path = workspace::state_file(state_dir, &name);
+        let Ok(manager) = ProcessManager::load(&path) else {
+            corrupt.push(path);
+            continue;
+        };
+        stale.extend(manager.crashed().iter().map(|(service, info)| StaleEntry {
+            workspace: name.clone(),
+            service: service.clone(),
+            pid: info.pid,
+        }));
+    }
+    Ok((stale, corrupt))
+}
+
+/// Checks of the state left behind, see [`stale_state`].
+pub fn state_checks(stale: &[StaleEntry], corrupt: &[PathBuf]) -> Vec<Check> {
+    let mut checks = Vec::new();
+    for path in corrupt {
+        checks.push(Check::problem(
+            "state file",
+            format!(
+                "{} can't be read, so commands in its workspace fail",
+                path.display()
+            ),
+            format!(
+                "Move it aside (mv {0} {0}.bak) and stop any services it listed",
+                path.display()
+            ),
+        ));
+    }
+    if !stale.is_empty() {
+        let entries: Vec<String> = stale
+            .iter()
+            .map(|entry| format!("{}/{} (PID {})", entry.workspace, entry.service, entry.pid))
+            .collect();
+        checks.push(Check::warning(
+            "stale state",
+            format!("processes gone: {}", entries.join(", ")),
+            "Forget them: doubleagent doctor --fix",
+        ));
+    }
+    if checks.is_empty() {
+        checks.push(Check::ok("state", "every tracked service is running"));
+    }
+    checks
+}
+
+/// Forget the stale entries of every workspace whose state file is readable.
+/// Returns how many were forgotten.
+pub fn forget_stale(state_dir: &Path) -> Result<usize> {
+    let (stale, _) = stale_state(state_dir)?;
+    let workspaces: BTreeSet<&str> = stale.iter().map(|entry| entry.workspace.as_str()).collect();
+    for name in workspaces {
+        let path = workspace::state_file(state_dir, name);
+        ProcessManager::update(&path, |manager| manager.forget_crashed())?;
+    }
+    Ok(stale.len())
+}
+
+/// A fake's process that no workspace tracks, e.g. one left running after
+/// its state file was removed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Orphan {
+    pub pid: u32,
+    /// Its command line
+    pub command: String,
+    /// The service's server directory it runs in
+    pub dir: PathBuf,
+    /// The port it was started on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+}
+
+/// Processes running in a service's `server` directory with a `PORT`, as
+/// fakes are started, that aren't a tracked service, its children, or started
+/// by a doubleagent that is still running. Only Linux is searched.
+pub fn orphans(state_dir: &Path) -> Result<Vec<Orphan>> {
+    let mut tracked = BTreeSet::new();
+    for name in workspace::list(state_dir)? {
+        // Without every workspace's state, any fake might be a tracked one
+        let Ok(manager) = ProcessManager::load(&workspace::state_file(state_dir, &name)) else {
+            return Ok(Vec::new());
+        };
+        for service in manager.running_services() {
+            if let Some(info) = manager.get_info(&service) {
+                tracked.insert(info.pid);
+            }
+        }
+    }
+
+    let processes = processes();
+    let mut orphans = Vec::new();
+    for (&pid, process) in &processes {
+        if pid == std::process::id() || owned(pid, &processes, &tracked) {
+            continue;
+        }
+        let proc_dir = Path::new("/proc").join(pid.to_string());
+        let Ok(dir) = fs::read_link(proc_dir.join("cwd")) else {
+            continue;
+        };
+        let is_server_dir = dir.file_name().is_some_and(|name| name == "server")
+            && dir
+                .parent()
+                .is_some_and(|service| service.join("service.yaml").exists());
+        if !is_server_dir {
+            continue;
+        }
+        let Ok(environ) = fs::read(proc_dir.join("environ")) else {
+            continue;
+        };
+        let Some(port) = environ
+            .split(|&b| b == 0)
+            .find_map(|var| var.strip_prefix(b"PORT="))
+        else {
+            continue;
+        };
+        let command = fs::read(proc_dir.join("cmdline"))
+            .map(|cmdline| {
+                cmdline
+                    .split(|&b| b == 0)
+                    .filter(|arg| !arg.is_empty())
+                    .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .unwrap_or_else(|_| process.name.clone());
+        orphans.push(Orphan {
+            pid,
+            command,
+            dir,
+            port: String::from_utf8_lossy(port).parse().ok(),
+        });
+    }
+    Ok(orphans)
+}
+
+/// Checks of fakes' processes nothing tracks, see [`orphans`].
+pub fn orphan_checks(orphans: &[Orphan]) -> Vec<Check> {
+    if orphans.is_empty() {
+        return vec![Check::ok("processes", "no untracked fakes running")];
+    }
+    orphans
+        .iter()
+        .map(|orphan| {
+            let port = orphan
+                .port
+                .map(|port| format!(" on port {}", port))
+                .unwrap_or_default();
+            Check::warning(
+                format!("PID {}", orphan.pid),
+                format!(
+                    "untracked fake{} in {}: {}",
+                    port,
+                    orphan.dir.display(),
+                    orphan.command
+                ),
+                format!("Stop it: kill {} (or doubleagent doctor --fix)", orphan.pid),
+            )
+        })
+        .collect()
+}
+
+/// A running process's name and parent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Process {
+    name: String,
+    parent: u32,
+}
+
+/// Every process in `/proc`, by PID.
+fn processes() -> BTreeMap<u32, Process> {
+    let mut processes = BTreeMap::new();
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return processes;
+    };
+    for entry in entries.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|s| s.parse().ok()) else {
+            continue;
+        };
+        if let Some(process) = fs::read_to_string(entry.path().join("stat"))
+            .ok()
+            .and_then(|stat| parse_stat(&stat))
+        {
+            processes.insert(pid, process);
+        }
+    }
+    processes
+}
+
+/// The name and parent PID from a `/proc/<pid>/stat` line, whose name is in
+/// parentheses and may itself contain spaces and parentheses.
+fn parse_stat(stat: &str) -> Option<Process> {
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let name = stat.get(open + 1..close)?.to_string();
+    // After the name come the state and the parent PID
+    let parent = stat[close + 1..].split_whitespace().nth(1)?.parse().ok()?;
+    Some(Process { name, parent })
+}
+
+/// Whether `pid` is a tracked service, or descends from one or from a running
+/// doubleagent (which may be starting it, or running it in the foreground).
+fn owned(pid: u32, processes: &BTreeMap<u32, Process>, tracked: &BTreeSet<u32>) -> bool {
+    let mut current = pid;
+    // Bounded, in case the table changed while it was read
+    for _ in 0..64 {
+        if tracked.contains(&current) {
+            return true;
+        }
+        let Some(process) = processes.get(&current) else {
+            return false;
+        };
+        if current != pid && process.name == "doubleagent" {
+            return true;
+        }
+        if process.parent == 0 || process.parent == current {
+            return false;
+        }
+        current = process.parent;
+    }
+    false
+}
+
+/// Stop orphaned fakes. Returns the PIDs signalled.
+pub fn stop_orphans(orphans: &[Orphan]) -> Result<Vec<u32>> {
+    let mut stopped = Vec::new();
+    for orphan in orphans {
+        ProcessManager::terminate(orphan.pid)?;
+        stopped.push(orphan.pid);
+    }
+    Ok(stopped)
+}
+
+/// Space taken by services, snapshots and logs, and free space left.
+pub fn disk_usage(config: &Config) -> Result<Vec<Check>> {
+    let mut checks = vec![
+        Check::ok(
+            "cache",
+            format!(
+                "{} in {}",
+                human_size(dir_size(&config.cache_dir)),
+                config.cache_dir.display()
+            ),
+        ),
+        Check::ok(
+            "data",
+            format!(
+                "{} in {}",
+                human_size(dir_size(&config.data_dir)),
+                config.data_dir.display()
+            ),
+        ),
+    ];
+
+    let mut logs_dirs = Vec::new();
+    for name in workspace::list(&config.state_dir)? {
+        let state_file = workspace::state_file(&config.state_dir, &name);
+        if let Some(dir) = state_file.parent() {
+            logs_dirs.push(dir.join("logs"));
+        }
+    }
+    let logs: u64 = logs_dirs.iter().map(|dir| dir_size(dir)).sum();
+    checks.push(if logs >= LOGS_WARNING_BYTES {
+        let dirs: Vec<String> = logs_dirs.iter().map(|d| d.display().to_string()).collect();
+        Check::warning(
+            "logs",
+            format!("{} of service output", human_size(logs)),
+            format!(
+                "Delete the logs of services you're done with in {}; each \
+                 start begins a fresh log",
+                dirs.join(", ")
+            ),
+        )
+    } else {
+        Check::ok("logs", human_size(logs))
+    });
+
+    let mut seen = BTreeSet::new();
+    for dir in [&config.cache_dir, &config.data_dir, &config.state_dir] {
+        let Some(existing) = dir.ancestors().find(|d| d.exists()) else {
+            continue;
+        };
+        let Ok(metadata) = fs::metadata(existing) else {
+            continue;
+        };
+        // One check per filesystem
+        if !seen.insert(std::os::unix::fs::MetadataExt::dev(&metadata)) {
+            continue;
+        }
+        let Some(free) = free_space(existing) else {
+            continue;
+        };
+        let name = format!("free space ({})", existing.display());
+        checks.push(if free < FREE_SPACE_WARNING_BYTES {
+            Check::warning(
+                name,
+                format!("only {} left", human_size(free)),
+                "Free up space, or move doubleagent's directories elsewhere with \
+                 DOUBLEAGENT_CACHE_DIR, DOUBLEAGENT_DATA_DIR and DOUBLEAGENT_STATE_DIR",
+            )
+        } else {
+            Check::ok(name, format!("{} free", human_size(free)))
+        });
+    }
+    Ok(checks)
+}
+
+/// Total size of the files under `dir`, without following symlinks.
+pub fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(kind) if kind.is_dir() => dir_size(&entry.path()),
+            Ok(kind) if kind.is_file() => entry.metadata().map_or(0, |m| m.len()),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`.
+fn free_space(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// A byte count for humans, e.g. "1.2 MB".
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KB", "MB", "GB"];
+    if bytes < 1000 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    /// A PID no process has.
+    const DEAD_PID: u32 = 999_999_999;
+
+    fn write_state(path: &Path, services: serde_json::Value) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(
+            path,
+            serde_json::json!({ "services": services }).to_string(),
+        )
+        .unwrap();
+    }
+
+    fn service(pid: u32) -> serde_json::Value {
+        serde_json::json!({
+            "pid": pid,
+            "port": 8080,
+            "started_at": "0",
+            "service_path": "/tmp",
+        })
+    }
+
+    #[test]
+    fn test_stale_state_is_found_and_forgotten() {
+        let dir = tempdir().unwrap();
+        let state_dir = dir.path();
+        write_state(
+            &workspace::state_file(state_dir, "default"),
+            serde_json::json!({ "github": service(DEAD_PID), "slack": service(std::process::id()) }),
+        );
+        let ci = workspace::state_file(state_dir, "ci");
+        fs::create_dir_all(ci.parent().unwrap()).unwrap();
+        fs::write(&ci, "{ not json").unwrap();
+
+        let (stale, corrupt) = stale_state(state_dir).unwrap();
+        assert_eq!(
+            stale,
+            vec![StaleEntry {
+                workspace: "default".to_string(),
+                service: "github".to_string(),
+                pid: DEAD_PID,
+            }]
+        );
+        assert_eq!(corrupt, vec![ci.clone()]);
+        let checks = state_checks(&stale, &corrupt);
+        assert_eq!(checks[0].status, Status::Problem);
+        assert_eq!(checks[1].status, Status::Warning);
+
+        assert_eq!(forget_stale(state_dir).unwrap(), 1);
+        let (stale, _) = stale_state(state_dir).unwrap();
+        assert!(stale.is_empty());
+        // The unreadable file is left for people to look at
+        assert_eq!(fs::read_to_string(&ci).unwrap(), "{ not json");
+    }
+
+    #[test]
+    fn test_config_that_does_not_load_is_a_problem() {
+        assert_eq!(configuration(None).status, Status::Ok);
+        let error = Error::Other("base_port: invalid digit".to_string());
+        let check = configuration(Some(&error));
+        assert_eq!(check.status, Status::Problem);
+        assert_eq!(check.detail, "base_port: invalid digit");
+    }
+
+    #[test]
+    fn test_taken_port_is_a_warning() {
+        let dir = tempdir().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let taken = listener.local_addr().unwrap().port();
+        let free = crate::process::free_port().unwrap();
+        let config = Config::builder()
+            .root(dir.path())
+            .base_port(taken)
+            .contract_port(free)
+            .build()
+            .unwrap();
+
+        let checks = ports(&config).unwrap();
+        assert_eq!(checks[0].status, Status::Warning);
+        assert!(checks[0].fix.as_ref().unwrap().contains("base_port"));
+        assert_eq!(checks[1].status, Status::Ok);
+    }
+
+    #[test]
+    fn test_parse_stat() {
+        let process = parse_stat("4242 (uv run (x)) S 17 4242 4242 0 -1").unwrap();
+        assert_eq!(process.name, "uv run (x)");
+        assert_eq!(process.parent, 17);
+        assert!(parse_stat("garbage").is_none());
+    }
+
+    #[test]
+    fn test_owned() {
+        let process = |name: &str, parent| Process {
+            name: name.to_string(),
+            parent,
+        };
+        let processes = BTreeMap::from([
+            (1, process("init", 0)),
+            (10, process("uv", 1)),
+            (11, process("python", 10)),
+            (20, process("doubleagent", 1)),
+            (21, process("uv", 20)),
+            (30, process("python", 1)),
+        ]);
+        let tracked = BTreeSet::from([10]);
+        assert!(owned(11, &processes, &tracked));
+        assert!(owned(21, &processes, &tracked));
+        assert!(!owned(30, &processes, &tracked));
+    }
+
+    #[test]
+    fn test_disk_usage() {
+        let dir = tempdir().unwrap();
+        let config = Config::builder().root(dir.path()).build().unwrap();
+        let logs = config.state_dir.join("logs");
+        fs::create_dir_all(&logs).unwrap();
+        fs::write(logs.join("github.log"), "x".repeat(1500)).unwrap();
+        write_state(&config.state_file, serde_json::json!({}));
+
+        assert!(dir_size(&config.state_dir) > 1500);
+        let checks = disk_usage(&config).unwrap();
+        let logs = checks.iter().find(|check| check.name == "logs").unwrap();
+        assert_eq!(logs.detail, "1.5 KB");
+        assert!(checks
+            .iter()
+            .any(|check| check.name.starts_with("free space")));
+    }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(999), "999 B");
+        assert_eq!(human_size(1_500), "1.5 KB");
+        assert_eq!(human_size(2_300_000_000), "2.3 GB");
+    }
+}
@@ -25,6 +25,10 @@ pub enum Error {
     #[error("Service process died")]
     ServiceProcessDied,
 
+    /// The service has no `/_doubleagent/export` endpoint.
+    #[error("The service does not support export (it has no /_doubleagent/export endpoint)")]
+    ExportUnsupported,
+
     /// Git operation failed.
     #[error("Git operation failed: {0}")]
     GitError(#[from] git2::Error),
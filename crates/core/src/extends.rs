@@ -0,0 +1,365 @@
+//! Project config inheritance through `extends`.
+//!
+//! A project config can build on shared baselines:
+//!
+//! ```yaml
+//! extends:
+//!   - https://example.com/platform/base-doubleagent.yaml
+//!   - ../shared/doubleagent.yaml
+//! services:
+//!   stripe:
+//!     port: 9100
+//! ```
+//!
+//! Each file is first upgraded to the latest config version (see
+//! [`crate::migrate`]). Bases are then merged in order, with the file itself on
+//! top: mappings, including `services`, merge key by key, and any other value
+//! in a later file replaces the earlier one. Relative paths are resolved
+//! against the file (or URL) that names them. Remote files are cached under
+//! `<cache_dir>/extends` and reused for [`CACHE_TTL`]; a stale copy is used if
+//! the remote can't be reached.
+
+use crate::interpolate;
+use crate::migrate;
+use crate::reload::fnv1a_hex;
+use crate::{Error, Result};
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Key naming the configs a file builds on.
+pub const KEY: &str = "extends";
+
+/// How long a fetched remote config is reused before fetching it again.
+pub const CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Load a project config file with its `extends` chain merged in.
+///
+/// The returned value no longer contains the `extends` key.
+pub fn load(path: &Path, cache_dir: &Path) -> Result<Value> {
+    let (value, _) = load_deferred(path, cache_dir)?;
+    interpolate::ensure_set(&value)?;
+    Ok(value)
+}
+
+/// Load a project config file like [`load`], leaving markers in place of the
+/// values that reference unset environment variables (see
+/// [`interpolate::parse_config_deferred`]) rather than failing.
+///
+/// Also returns the file (path or URL) each top-level key was last set in.
+pub fn load_deferred(path: &Path, cache_dir: &Path) -> Result<(Value, BTreeMap<String, String>)> {
+    let loader = Loader {
+        cache_dir: cache_dir.join("extends"),
+    };
+    loader.load(&Source::Local(path.to_path_buf()), &mut Vec::new())
+}
+
+/// Where a config file comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Source {
+    Local(PathBuf),
+    Remote(String),
+}
+
+impl Source {
+    /// Resolve a reference found in this file.
+    fn join(&self, reference: &str) -> Source {
+        if is_url(reference) {
+            return Source::Remote(reference.to_string());
+        }
+        match self {
+            Source::Local(path) => Source::Local(
+                path.parent()
+                    .map(|dir| dir.join(reference))
+                    .unwrap_or_else(|| PathBuf::from(reference)),
+            ),
+            Source::Remote(url) => {
+                let base = url.rsplit_once('/').map_or(url.as_str(), |(base, _)| base);
+                let mut url = base.to_string();
+                for part in reference.split('/') {
+                    match part {
+                        "" | "." => {}
+                        ".." => {
+                            if let Some((parent, _)) = url.rsplit_once('/') {
+                                if !parent.ends_with('/') {
+                                    url.truncate(parent.len());
+                                }
+                            }
+                        }
+                        part => {
+                            url.push('/');
+                            url.push_str(part);
+                        }
+                    }
+                }
+                Source::Remote(url)
+            }
+        }
+    }
+
+    /// Identity used for cycle detection.
+    fn key(&self) -> String {
+        match self {
+            Source::Local(path) => fs::canonicalize(path)
+                .unwrap_or_else(|_| path.clone())
+                .display()
+                .to_string(),
+            Source::Remote(url) => url.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Local(path) => write!(f, "{}", path.display()),
+            Source::Remote(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+struct Loader {
+    cache_dir: PathBuf,
+}
+
+impl Loader {
+    fn load(
+        &self,
+        source: &Source,
+        stack: &mut Vec<String>,
+    ) -> Result<(Value, BTreeMap<String, String>)> {
+        let key = source.key();
+        if let Some(start) = stack.iter().position(|k| *k == key) {
+            let mut cycle: Vec<&str> = stack[start..].iter().map(String::as_str).collect();
+            cycle.push(&key);
+            return Err(Error::Other(format!(
+                "Cycle in '{}': {}",
+                KEY,
+                cycle.join(" -> ")
+            )));
+        }
+
+        let (content, name) = match source {
+            Source::Local(path) => (
+                fs::read_to_string(path).map_err(|e| {
+                    Error::Other(format!("Failed to read {}: {}", path.display(), e))
+                })?,
+                path.clone(),
+            ),
+            Source::Remote(url) => (self.fetch(url)?, PathBuf::from(url.as_str())),
+        };
+        let mut document = interpolate::parse_config_deferred(&content, &name)?;
+        migrate::upgrade(&mut document, source)?;
+
+        let bases = match &mut document {
+            Value::Mapping(mapping) => mapping.remove(KEY),
+            _ => None,
+        };
+        if let Some(bases) = &bases {
+            interpolate::ensure_set(bases)?;
+        }
+        let bases = match bases {
+            None | Some(Value::Null) => Vec::new(),
+            Some(Value::String(base)) => vec![base],
+            Some(Value::Sequence(items)) => items
+                .into_iter()
+                .map(|item| match item {
+                    Value::String(base) => Ok(base),
+                    _ => Err(Error::Other(format!(
+                        "{}: '{}' entries must be paths or URLs",
+                        source, KEY
+                    ))),
+                })
+                .collect::<Result<_>>()?,
+            Some(_) => {
+                return Err(Error::Other(format!(
+                    "{}: '{}' must be a path, a URL or a list of them",
+                    source, KEY
+                )))
+            }
+        };
+
+        stack.push(key);
+        let mut merged = Value::Null;
+        let mut sources = BTreeMap::new();
+        for base in &bases {
+            let (base, base_sources) = self.load(&source.join(base), stack)?;
+            merge(&mut merged, base);
+            sources.extend(base_sources);
+        }
+        stack.pop();
+
+        if let Value::Mapping(mapping) = &document {
+            for key in mapping.keys().filter_map(Value::as_str) {
+                sources.insert(key.to_string(), source.to_string());
+            }
+        }
+        merge(&mut merged, document);
+        Ok((merged, sources))
+    }
+
+    /// Fetch a remote config, going through the cache.
+    fn fetch(&self, url: &str) -> Result<String> {
+        let extension = Path::new(url)
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+        let cached = self
+            .cache_dir
+            .join(format!("{}{}", fnv1a_hex(url.as_bytes()), extension));
+
+        let age = fs::metadata(&cached)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+        if matches!(age, Some(age) if age < CACHE_TTL) {
+            return Ok(fs::read_to_string(&cached)?);
+        }
+
+        match fetch_url(url) {
+            Ok(content) => {
+                fs::create_dir_all(&self.cache_dir)?;
+                fs::write(&cached, &content)?;
+                Ok(content)
+            }
+            Err(e) if cached.exists() => {
+                tracing::warn!("Could not fetch {} ({}), using cached copy", url, e);
+                Ok(fs::read_to_string(&cached)?)
+            }
+            Err(e) => Err(Error::Other(format!("Failed to fetch {}: {}", url, e))),
+        }
+    }
+}
+
+fn is_url(reference: &str) -> bool {
+    reference.starts_with("https://") || reference.starts_with("http://")
+}
+
+/// Download a URL on a separate thread, so it works with or without a runtime.
+fn fetch_url(url: &str) -> Result<String> {
+    let url = url.to_string();
+    std::thread::spawn(move || -> Result<String> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        runtime.block_on(async {
+            let response = reqwest::Client::new()
+                .get(&url)
+                .timeout(Duration::from_secs(10))
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(response.text().await?)
+        })
+    })
+    .join()
+    .map_err(|_| Error::Other("fetch thread panicked".to_string()))?
+}
+
+/// Merge `overlay` into `base`.
+///
+/// Both are already upgraded to the latest config version, so `services` is
+/// a mapping and merges like any other.
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (_, Value::Null) => {}
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_extends_merges_bases_in_order() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("shared")).unwrap();
+        fs::write(
+            dir.path().join("shared/base.yaml"),
+            "base_port: 9000\nbranch: stable\nservices:\n  - github\n  - slack\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("doubleagent.yaml"),
+            "extends: shared/base.yaml\nbranch: dev\nservices:\n  stripe:\n  slack:\n    port: 9100\n",
+        )
+        .unwrap();
+
+        let value = load(&dir.path().join("doubleagent.yaml"), dir.path()).unwrap();
+        assert_eq!(value["base_port"], Value::from(9000));
+        assert_eq!(value["branch"], Value::from("dev"));
+        assert!(value.get(KEY).is_none());
+
+        let services = value["services"].as_mapping().unwrap();
+        let names: Vec<&str> = services.keys().filter_map(Value::as_str).collect();
+        assert_eq!(names, vec!["github", "slack", "stripe"]);
+        assert_eq!(services["slack"]["port"], Value::from(9100));
+    }
+
+    #[test]
+    fn test_extends_detects_cycles() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.yaml"), "extends: b.yaml\n").unwrap();
+        fs::write(dir.path().join("b.yaml"), "extends: [a.yaml]\n").unwrap();
+
+        let msg = load(&dir.path().join("a.yaml"), dir.path())
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("Cycle"), "{}", msg);
+        assert!(
+            msg.contains("a.yaml -> ") && msg.contains("b.yaml"),
+            "{}",
+            msg
+        );
+    }
+
+    #[test]
+    fn test_remote_uses_fresh_cache() {
+        let dir = tempdir().unwrap();
+        let url = "https://example.invalid/team/base.yaml";
+        let cache = dir.path().join("extends");
+        fs::create_dir_all(&cache).unwrap();
+        fs::write(
+            cache.join(format!("{}.yaml", fnv1a_hex(url.as_bytes()))),
+            "services:\n  - github\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("doubleagent.yaml"),
+            format!("extends: {}\nservices:\n  - stripe\n", url),
+        )
+        .unwrap();
+
+        let value = load(&dir.path().join("doubleagent.yaml"), dir.path()).unwrap();
+        let services = value["services"].as_mapping().unwrap();
+        let names: Vec<&str> = services.keys().filter_map(Value::as_str).collect();
+        assert_eq!(names, vec!["github", "stripe"]);
+    }
+
+    #[test]
+    fn test_relative_references_in_remote_files() {
+        let source = Source::Remote("https://example.com/team/configs/base.yaml".to_string());
+        assert_eq!(
+            source.join("../common.yaml"),
+            Source::Remote("https://example.com/team/common.yaml".to_string())
+        );
+        assert_eq!(
+            source.join("extra.yaml"),
+            Source::Remote("https://example.com/team/configs/extra.yaml".to_string())
+        );
+    }
+}
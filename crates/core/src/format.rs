@@ -0,0 +1,231 @@
+//! Config file formats, chosen by file extension.
+//!
+//! Project config may be written as YAML, TOML or JSON. All three are read
+//! into a YAML value so the rest of the crate (interpolation, settings,
+//! validation) works the same regardless of format.
+
+use crate::{Error, Result};
+use serde_yaml::Value;
+use std::path::Path;
+
+/// A config file format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl Format {
+    /// Detect the format from a file extension, defaulting to YAML.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Format::Toml,
+            Some("json") => Format::Json,
+            _ => Format::Yaml,
+        }
+    }
+
+    /// Parse file content into a YAML value.
+    pub fn parse(self, content: &str, source: &Path) -> Result<Value> {
+        let error = |e: &dyn std::fmt::Display| {
+            Error::Other(format!("Failed to parse {}: {}", source.display(), e))
+        };
+        match self {
+            Format::Yaml => Ok(serde_yaml::from_str(content)?),
+            Format::Toml => {
+                let value: toml::Value = toml::from_str(content).map_err(|e| error(&e))?;
+                Ok(serde_yaml::to_value(value)?)
+            }
+            Format::Json => {
+                let value: serde_json::Value =
+                    serde_json::from_str(content).map_err(|e| error(&e))?;
+                Ok(serde_yaml::to_value(value)?)
+            }
+        }
+    }
+
+    /// Serialize a YAML value in this format.
+    pub fn to_string(self, value: &Value) -> Result<String> {
+        match self {
+            Format::Yaml => Ok(serde_yaml::to_string(value)?),
+            Format::Toml => toml::to_string_pretty(value)
+                .map_err(|e| Error::Other(format!("Failed to write TOML: {}", e))),
+            Format::Json => Ok(serde_json::to_string_pretty(value)? + "\n"),
+        }
+    }
+
+    /// The line setting a top-level key to a scalar value in this format.
+    pub fn key_line(self, name: &str, value: &Value) -> Result<String> {
+        Ok(match (self, value) {
+            (Format::Yaml, _) => format!("{}: {}", name, serde_yaml::to_string(value)?.trim_end()),
+            (Format::Toml, Value::String(s)) => {
+                format!("{} = {}", name, toml::Value::String(s.clone()))
+            }
+            (Format::Toml, _) => format!("{} = {}", name, serde_json::to_string(value)?),
+            (Format::Json, _) => format!("\"{}\": {}", name, serde_json::to_string(value)?),
+        })
+    }
+
+    /// Set a top-level key in file content by rewriting or adding only the
+    /// line that holds it, so comments and the order of other keys are kept.
+    pub fn set_key(self, content: &str, name: &str, value: &Value) -> Result<String> {
+        let line = self.key_line(name, value)?;
+        let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+        match self.find_key(&lines, name) {
+            Some(index) => {
+                let old = &lines[index];
+                lines[index] = match self {
+                    Format::Json => {
+                        let indent = &old[..old.len() - old.trim_start().len()];
+                        let comma = if old.trim_end().ends_with(',') {
+                            ","
+                        } else {
+                            ""
+                        };
+                        format!("{}{}{}", indent, line, comma)
+                    }
+                    Format::Yaml | Format::Toml => format!("{}{}", line, trailing_comment(old)),
+                };
+            }
+            None => match self {
+                Format::Json if content.trim().is_empty() => {
+                    lines = vec!["{".to_string(), format!("  {}", line), "}".to_string()];
+                }
+                Format::Json => {
+                    let close = content
+                        .rfind('}')
+                        .ok_or_else(|| Error::Other("JSON config must be an object".to_string()))?;
+                    let before = content[..close].trim_end();
+                    let comma = if before.ends_with('{') { "" } else { "," };
+                    let edited = format!("{}{}\n  {}\n{}", before, comma, line, &content[close..]);
+                    lines = edited.lines().map(str::to_string).collect();
+                }
+                Format::Toml => {
+                    // Top-level keys must come before the first table
+                    let table = lines.iter().position(|l| l.trim_start().starts_with('['));
+                    let mut at = table.unwrap_or(lines.len());
+                    while at > 0 && lines[at - 1].trim().is_empty() {
+                        at -= 1;
+                    }
+                    lines.insert(at, line);
+                }
+                Format::Yaml => lines.push(line),
+            },
+        }
+        Ok(lines.join("\n") + "\n")
+    }
+
+    /// Remove the line holding a top-level key from file content, keeping
+    /// everything else as it is. Returns None if no line holds the key.
+    pub fn remove_key(self, content: &str, name: &str) -> Option<String> {
+        let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+        let index = self.find_key(&lines, name)?;
+        let removed = lines.remove(index);
+        if self == Format::Json && !removed.trim_end().ends_with(',') {
+            // The last entry went, so the one before it is now the last
+            if let Some(previous) = lines[..index].iter_mut().rfind(|l| !l.trim().is_empty()) {
+                if let Some(stripped) = previous.trim_end().strip_suffix(',') {
+                    *previous = stripped.to_string();
+                }
+            }
+        }
+        Some(lines.join("\n") + "\n")
+    }
+
+    /// The index of the line holding a top-level key.
+    fn find_key(self, lines: &[String], name: &str) -> Option<usize> {
+        let holds = |line: &str, separator: char| {
+            line.strip_prefix(name)
+                .is_some_and(|rest| rest.trim_start().starts_with(separator))
+        };
+        match self {
+            Format::Yaml => lines.iter().position(|l| holds(l, ':')),
+            Format::Toml => lines
+                .iter()
+                .take_while(|l| !l.trim_start().starts_with('['))
+                .position(|l| holds(l, '=')),
+            Format::Json => {
+                let quoted = format!("\"{}\"", name);
+                lines.iter().position(|l| {
+                    l.trim_start()
+                        .strip_prefix(&quoted)
+                        .is_some_and(|rest| rest.trim_start().starts_with(':'))
+                })
+            }
+        }
+    }
+}
+
+/// The comment ending a YAML or TOML line, with the space before it.
+fn trailing_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') if previous.is_whitespace() => {
+                return &line[line[..index].trim_end().len()..];
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+    ""
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats_parse_to_the_same_value() {
+        let yaml = Format::Yaml
+            .parse(
+                "services:\n  - github\nbase_port: 9000\n",
+                Path::new("a.yaml"),
+            )
+            .unwrap();
+        let toml = Format::Toml
+            .parse(
+                "services = [\"github\"]\nbase_port = 9000\n",
+                Path::new("a.toml"),
+            )
+            .unwrap();
+        let json = Format::Json
+            .parse(
+                r#"{"services": ["github"], "base_port": 9000}"#,
+                Path::new("a.json"),
+            )
+            .unwrap();
+
+        assert_eq!(yaml, toml);
+        assert_eq!(yaml, json);
+    }
+
+    #[test]
+    fn test_round_trip_toml() {
+        let value = Format::Toml
+            .parse("branch = \"dev\"\n", Path::new("a.toml"))
+            .unwrap();
+        let written = Format::Toml.to_string(&value).unwrap();
+        assert_eq!(written.trim(), "branch = \"dev\"");
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            Format::from_path(Path::new("doubleagent.toml")),
+            Format::Toml
+        );
+        assert_eq!(
+            Format::from_path(Path::new("doubleagent.json")),
+            Format::Json
+        );
+        assert_eq!(
+            Format::from_path(Path::new("doubleagent.yml")),
+            Format::Yaml
+        );
+    }
+}
@@ -0,0 +1,839 @@
+//! Property-based fuzzing of a fake from its OpenAPI description, for
+//! `doubleagent fuzz`.
+//!
+//! Each request picks one of the description's operations and fills in its
+//! path and query parameters and JSON body from their schemas: mostly valid
+//! values, with boundary and hostile ones (empty and very long strings,
+//! control characters, huge numbers, wrong types, missing required fields)
+//! mixed in. A response is a finding if it is a server error (5xx), doesn't
+//! arrive within the timeout, or is a success whose body doesn't match the
+//! schema documented for its status. Client errors are expected for hostile
+//! input and not checked.
+//!
+//! Generation is driven by a seed, so a run can be repeated exactly. Each
+//! finding is minimized by dropping and shrinking parts of the request for as
+//! long as the same kind of failure reproduces, and written out as a requests
+//! file that `doubleagent validate-spec --requests` replays.
+
+use crate::conformance::{self, Request, Spec};
+use crate::openapi;
+use crate::{Error, Result};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Deepest a generated body nests.
+const MAX_DEPTH: usize = 5;
+
+/// Requests tried while minimizing one finding.
+const MAX_SHRINK_STEPS: usize = 200;
+
+/// Requests tried while minimizing a hang, each of which may take the whole timeout.
+const MAX_HANG_SHRINK_STEPS: usize = 5;
+
+/// Strings that tend to break handlers that trust their input.
+const HOSTILE_STRINGS: [&str; 10] = [
+    "",
+    " ",
+    "0",
+    "-1",
+    "null",
+    "../../etc/passwd",
+    "'\"<>;--",
+    "%00",
+    "\u{0}\u{1b}[0m",
+    "😀ü\u{202e}",
+];
+
+/// How a fuzzed request went wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FindingKind {
+    /// A 5xx response
+    ServerError,
+    /// No response within the timeout
+    Hang,
+    /// A success whose body doesn't match the description
+    InvalidResponse,
+    /// The fake stopped accepting connections
+    Crash,
+}
+
+impl FindingKind {
+    /// Short name, used in repro file names.
+    pub fn slug(self) -> &'static str {
+        match self {
+            FindingKind::ServerError => "server-error",
+            FindingKind::Hang => "hang",
+            FindingKind::InvalidResponse => "invalid-response",
+            FindingKind::Crash => "crash",
+        }
+    }
+}
+
+impl fmt::Display for FindingKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FindingKind::ServerError => "server error",
+            FindingKind::Hang => "hang",
+            FindingKind::InvalidResponse => "invalid response",
+            FindingKind::Crash => "crash",
+        })
+    }
+}
+
+/// A request the fake handled badly, minimized.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub kind: FindingKind,
+    /// Operation the request was generated for, e.g. `GET /repos/{owner}/{repo}`
+    pub operation: String,
+    pub request: Request,
+    /// Status of the response, if there was one
+    pub status: Option<u16>,
+    /// What was wrong, e.g. the schema errors of the body
+    pub detail: String,
+}
+
+/// How to fuzz.
+#[derive(Debug, Clone)]
+pub struct FuzzOptions {
+    /// Requests to generate
+    pub requests: usize,
+    /// Seed of the generator; the same seed sends the same requests
+    pub seed: u64,
+    /// How long a response may take before it counts as a hang
+    pub timeout: Duration,
+}
+
+/// The outcome of a fuzzing run.
+#[derive(Debug, Clone, Default)]
+pub struct FuzzReport {
+    /// Requests sent, not counting those sent while minimizing
+    pub sent: usize,
+    /// Operations requests were generated for
+    pub operations: usize,
+    /// One finding per kind and operation, minimized
+    pub findings: Vec<Finding>,
+}
+
+/// One operation of the description, with what's needed to build requests.
+#[derive(Debug, Clone)]
+pub struct Operation {
+    /// Upper case, e.g. `POST`
+    pub method: String,
+    /// Path template, e.g. `/repos/{owner}/{repo}/issues`
+    pub template: String,
+    parameters: Vec<Value>,
+    body: Option<Value>,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.method, self.template)
+    }
+}
+
+/// Every operation of the description, in a stable order.
+pub fn operations(spec: &Spec) -> Vec<Operation> {
+    let document = spec.document();
+    let paths = document.get("paths").and_then(Value::as_object);
+    let mut operations = Vec::new();
+    for (template, item) in paths.into_iter().flatten() {
+        for method in conformance::METHODS {
+            // HEAD, OPTIONS and TRACE are served by the framework, not the fake
+            if matches!(method, "head" | "options" | "trace") {
+                continue;
+            }
+            let Some(operation) = item.get(method) else {
+                continue;
+            };
+            let parameters = conformance::parameters(document, item, operation)
+                .into_iter()
+                .cloned()
+                .collect();
+            let body = operation
+                .get("requestBody")
+                .map(|body| openapi::resolve(document, body))
+                .and_then(|body| body.get("content"))
+                .and_then(Value::as_object)
+                .and_then(|content| {
+                    content
+                        .iter()
+                        .find(|(media, _)| media.contains("json"))
+                        .map(|(_, media)| media)
+                })
+                .and_then(|media| media.get("schema"))
+                .cloned();
+            operations.push(Operation {
+                method: method.to_uppercase(),
+                template: template.clone(),
+                parameters,
+                body,
+            });
+        }
+    }
+    operations
+}
+
+/// A small, fast, seedable generator (xorshift64*), so runs repeat exactly.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Zero is a fixed point of xorshift
+        Self(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// A number below `n` (which must not be zero).
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// True once in `n` times.
+    pub fn one_in(&mut self, n: usize) -> bool {
+        self.below(n) == 0
+    }
+}
+
+/// Generate a request for an operation.
+pub fn generate(spec: &Spec, operation: &Operation, rng: &mut Rng) -> Request {
+    let document = spec.document();
+    let mut path = String::new();
+    for segment in operation.template.split('/').filter(|s| !s.is_empty()) {
+        let value = match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+            Some(name) => {
+                let schema = operation
+                    .parameters
+                    .iter()
+                    .find(|p| {
+                        p.get("in").and_then(Value::as_str) == Some("path")
+                            && p.get("name").and_then(Value::as_str) == Some(name)
+                    })
+                    .and_then(|p| p.get("schema"));
+                let value = match schema {
+                    Some(schema) => scalar(document, schema, rng),
+                    None => hostile_string(rng),
+                };
+                // An empty segment would address another route
+                if value.is_empty() {
+                    "_".to_string()
+                } else {
+                    value
+                }
+            }
+            None => segment.to_string(),
+        };
+        path.push('/');
+        path.push_str(&conformance::encode(&value));
+    }
+    if path.is_empty() {
+        path.push('/');
+    }
+
+    let mut query = Vec::new();
+    for parameter in &operation.parameters {
+        if parameter.get("in").and_then(Value::as_str) != Some("query") {
+            continue;
+        }
+        let Some(name) = parameter.get("name").and_then(Value::as_str) else {
+            continue;
+        };
+        let required = parameter.get("required").and_then(Value::as_bool) == Some(true);
+        if !required && rng.one_in(2) {
+            continue;
+        }
+        let value = match parameter.get("schema") {
+            Some(schema) => scalar(document, schema, rng),
+            None => hostile_string(rng),
+        };
+        query.push(format!(
+            "{}={}",
+            conformance::encode(name),
+            conformance::encode(&value)
+        ));
+    }
+    if !query.is_empty() {
+        path = format!("{}?{}", path, query.join("&"));
+    }
+
+    let body = operation
+        .body
+        .as_ref()
+        .map(|schema| value(document, schema, rng, 0));
+    Request {
+        method: operation.method.clone(),
+        path,
+        body,
+    }
+}
+
+/// A value for a schema: usually valid, sometimes at its limits, sometimes
+/// of the wrong type altogether.
+fn value(spec: &Value, schema: &Value, rng: &mut Rng, depth: usize) -> Value {
+    let schema = openapi::resolve(spec, schema);
+    if rng.one_in(10) {
+        return hostile(rng);
+    }
+    if let Some(Value::Array(values)) = schema.get("enum") {
+        if !values.is_empty() && !rng.one_in(5) {
+            return values[rng.below(values.len())].clone();
+        }
+    }
+    if depth >= MAX_DEPTH {
+        return Value::Null;
+    }
+    for key in ["oneOf", "anyOf"] {
+        if let Some(Value::Array(options)) = schema.get(key) {
+            if !options.is_empty() {
+                return value(spec, &options[rng.below(options.len())], rng, depth + 1);
+            }
+        }
+    }
+    if let Some(Value::Array(parts)) = schema.get("allOf") {
+        let mut merged = Map::new();
+        for part in parts {
+            if let Value::Object(fields) = value(spec, part, rng, depth + 1) {
+                merged.extend(fields);
+            }
+        }
+        return Value::Object(merged);
+    }
+
+    if openapi::is_type(schema, "object") {
+        let required: BTreeSet<&str> = schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .collect();
+        let mut record = Map::new();
+        for (name, property) in schema
+            .get("properties")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flatten()
+        {
+            // Required fields are left out now and then, optional ones half the time
+            let include = if required.contains(name.as_str()) {
+                !rng.one_in(8)
+            } else {
+                rng.one_in(2)
+            };
+            if include {
+                record.insert(name.clone(), value(spec, property, rng, depth + 1));
+            }
+        }
+        if rng.one_in(8) {
+            record.insert("__doubleagent_fuzz".to_string(), hostile(rng));
+        }
+        Value::Object(record)
+    } else if openapi::is_type(schema, "array") {
+        let items = schema.get("items").cloned().unwrap_or(json!({}));
+        let len = if rng.one_in(10) { 50 } else { rng.below(4) };
+        Value::Array(
+            (0..len)
+                .map(|_| value(spec, &items, rng, depth + 1))
+                .collect(),
+        )
+    } else if openapi::is_type(schema, "integer") || openapi::is_type(schema, "number") {
+        number(schema, rng)
+    } else if openapi::is_type(schema, "boolean") {
+        json!(rng.one_in(2))
+    } else if openapi::is_type(schema, "string") {
+        json!(string(spec, schema, rng))
+    } else {
+        hostile(rng)
+    }
+}
+
+/// A parameter value, as text.
+fn scalar(spec: &Value, schema: &Value, rng: &mut Rng) -> String {
+    match value(spec, schema, rng, MAX_DEPTH - 1) {
+        Value::String(s) => s,
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+fn number(schema: &Value, rng: &mut Rng) -> Value {
+    let min = schema.get("minimum").and_then(Value::as_i64);
+    let max = schema.get("maximum").and_then(Value::as_i64);
+    match rng.below(6) {
+        0 => json!(0),
+        1 => json!(-1),
+        2 => json!(i64::MAX),
+        3 => json!(min.map_or(i64::MIN, |min| min - 1)),
+        4 => json!(max.map_or(u32::MAX as i64 + 1, |max| max.saturating_add(1))),
+        _ => {
+            let low = min.unwrap_or(1);
+            let high = max.unwrap_or(low + 100).max(low);
+            json!(low + (rng.next_u64() % ((high - low) as u64 + 1)) as i64)
+        }
+    }
+}
+
+fn string(spec: &Value, schema: &Value, rng: &mut Rng) -> String {
+    match rng.below(4) {
+        // What a well-behaved client would send
+        0 => match openapi::example(spec, schema, 0) {
+            Value::String(s) => s,
+            _ => "string".to_string(),
+        },
+        1 => hostile_string(rng),
+        2 => {
+            let max = schema
+                .get("maxLength")
+                .and_then(Value::as_u64)
+                .unwrap_or(1000) as usize;
+            "a".repeat(max + 1)
+        }
+        _ => {
+            let len = 1 + rng.below(16);
+            (0..len)
+                .map(|_| (b'a' + rng.below(26) as u8) as char)
+                .collect()
+        }
+    }
+}
+
+fn hostile_string(rng: &mut Rng) -> String {
+    HOSTILE_STRINGS[rng.below(HOSTILE_STRINGS.len())].to_string()
+}
+
+/// A value of any type, likely not the one expected.
+fn hostile(rng: &mut Rng) -> Value {
+    match rng.below(8) {
+        0 => Value::Null,
+        1 => json!(true),
+        2 => json!(-1),
+        3 => json!(1e308),
+        4 => json!([]),
+        5 => json!({}),
+        6 => json!("x".repeat(10_000)),
+        _ => json!(hostile_string(rng)),
+    }
+}
+
+/// Smaller versions of a request, most aggressive first: without its body,
+/// query parameters or fields, and with shorter values.
+pub fn simplifications(request: &Request) -> Vec<Request> {
+    let mut candidates = Vec::new();
+    let with_body = |body: Option<Value>| Request {
+        method: request.method.clone(),
+        path: request.path.clone(),
+        body,
+    };
+    if let Some(body) = &request.body {
+        candidates.push(with_body(None));
+        candidates.extend(shrink(body).into_iter().map(|b| with_body(Some(b))));
+    }
+
+    if let Some((path, query)) = request.path.split_once('?') {
+        let params: Vec<&str> = query.split('&').collect();
+        let with_query = |params: &[String]| Request {
+            method: request.method.clone(),
+            path: if params.is_empty() {
+                path.to_string()
+            } else {
+                format!("{}?{}", path, params.join("&"))
+            },
+            body: request.body.clone(),
+        };
+        for i in 0..params.len() {
+            let mut fewer: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+            fewer.remove(i);
+            candidates.push(with_query(&fewer));
+        }
+        for (i, param) in params.iter().enumerate() {
+            let Some((name, value)) = param.split_once('=') else {
+                continue;
+            };
+            if value.len() > 1 {
+                let mut shorter: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+                shorter[i] = format!("{}={}", name, shorten(value));
+                candidates.push(with_query(&shorter));
+            }
+        }
+    }
+    candidates
+}
+
+/// Smaller versions of a value, one change each.
+fn shrink(value: &Value) -> Vec<Value> {
+    match value {
+        Value::Object(fields) => {
+            let mut smaller = Vec::new();
+            for key in fields.keys() {
+                let mut fewer = fields.clone();
+                fewer.remove(key);
+                smaller.push(Value::Object(fewer));
+            }
+            for (key, field) in fields {
+                for shrunk in shrink(field) {
+                    let mut changed = fields.clone();
+                    changed.insert(key.clone(), shrunk);
+                    smaller.push(Value::Object(changed));
+                }
+            }
+            smaller
+        }
+        Value::Array(items) if !items.is_empty() => {
+            let mut smaller = vec![json!([])];
+            if items.len() > 1 {
+                smaller.push(Value::Array(items[..items.len() / 2].to_vec()));
+            }
+            for i in 0..items.len() {
+                let mut fewer = items.clone();
+                fewer.remove(i);
+                smaller.push(Value::Array(fewer));
+            }
+            smaller
+        }
+        Value::String(s) if s.len() > 1 => vec![json!(""), json!(shorten(s))],
+        Value::Number(n) if n.as_i64() != Some(0) => vec![json!(0)],
+        Value::Bool(true) => vec![json!(false)],
+        _ => Vec::new(),
+    }
+}
+
+/// The first half of a string, on a character boundary.
+fn shorten(s: &str) -> String {
+    let half = s.chars().count() / 2;
+    s.chars().take(half.max(1)).collect()
+}
+
+/// Send requests generated from the description to the fake at `base_url`
+/// and collect what it handled badly.
+pub async fn run(base_url: &str, spec: &Spec, options: &FuzzOptions) -> Result<FuzzReport> {
+    let operations = operations(spec);
+    if operations.is_empty() {
+        return Err(Error::Other(
+            "The OpenAPI description has no operations to fuzz".to_string(),
+        ));
+    }
+    let client = reqwest::Client::builder()
+        .timeout(options.timeout)
+        .build()?;
+    let base_url = base_url.trim_end_matches('/');
+    let mut rng = Rng::new(options.seed);
+    let mut report = FuzzReport {
+        operations: operations.len(),
+        ..FuzzReport::default()
+    };
+    let mut found = BTreeSet::new();
+
+    for _ in 0..options.requests {
+        let operation = &operations[rng.below(operations.len())];
+        let request = generate(spec, operation, &mut rng);
+        report.sent += 1;
+        let Some((kind, status, detail)) = send(&client, base_url, spec, &request).await? else {
+            continue;
+        };
+        let key = (kind, operation.to_string());
+        if kind != FindingKind::Crash && found.contains(&key) {
+            continue;
+        }
+        let finding = Finding {
+            kind,
+            operation: operation.to_string(),
+            request,
+            status,
+            detail,
+        };
+        if kind == FindingKind::Crash {
+            // Nothing more can be learned from a fake that's gone
+            report.findings.push(finding);
+            break;
+        }
+        report
+            .findings
+            .push(minimize(&client, base_url, spec, finding).await?);
+        found.insert(key);
+    }
+    Ok(report)
+}
+
+/// Shrink a finding's request while the same kind of failure reproduces.
+async fn minimize(
+    client: &reqwest::Client,
+    base_url: &str,
+    spec: &Spec,
+    mut finding: Finding,
+) -> Result<Finding> {
+    let budget = match finding.kind {
+        FindingKind::Hang => MAX_HANG_SHRINK_STEPS,
+        _ => MAX_SHRINK_STEPS,
+    };
+    let mut steps = 0;
+    'smaller: loop {
+        for candidate in simplifications(&finding.request) {
+            if steps == budget {
+                break 'smaller;
+            }
+            steps += 1;
+            match send(client, base_url, spec, &candidate).await? {
+                Some((kind, status, detail)) if kind == finding.kind => {
+                    finding.request = candidate;
+                    finding.status = status;
+                    finding.detail = detail;
+                    continue 'smaller;
+                }
+                // A shrunk request that kills the fake is no help either
+                Some((FindingKind::Crash, _, _)) => break 'smaller,
+                _ => {}
+            }
+        }
+        break;
+    }
+    Ok(finding)
+}
+
+/// Send one request, returning what was wrong with the response, if anything.
+async fn send(
+    client: &reqwest::Client,
+    base_url: &str,
+    spec: &Spec,
+    request: &Request,
+) -> Result<Option<(FindingKind, Option<u16>, String)>> {
+    let method = reqwest::Method::from_bytes(request.method.as_bytes())
+        .map_err(|e| Error::Other(format!("Invalid method '{}': {}", request.method, e)))?;
+    let mut builder = client
+        .request(method, format!("{}{}", base_url, request.path))
+        .header(reqwest::header::ACCEPT, "application/json");
+    if let Some(body) = &request.body {
+        builder = builder.json(body);
+    }
+    let response = match builder.send().await {
+        Ok(response) => response,
+        Err(e) if e.is_timeout() => return Ok(Some((FindingKind::Hang, None, e.to_string()))),
+        Err(e) if e.is_connect() => return Ok(Some((FindingKind::Crash, None, e.to_string()))),
+        Err(e) => return Ok(Some((FindingKind::ServerError, None, e.to_string()))),
+    };
+    let status = response.status().as_u16();
+    let body = match response.bytes().await {
+        Ok(body) => body,
+        Err(e) if e.is_timeout() => {
+            return Ok(Some((FindingKind::Hang, Some(status), e.to_string())))
+        }
+        Err(e) => {
+            return Ok(Some((
+                FindingKind::ServerError,
+                Some(status),
+                e.to_string(),
+            )))
+        }
+    };
+    if status >= 500 {
+        let text = String::from_utf8_lossy(&body);
+        let detail = text.lines().next().unwrap_or_default().trim();
+        return Ok(Some((
+            FindingKind::ServerError,
+            Some(status),
+            detail.chars().take(200).collect(),
+        )));
+    }
+    if status < 400 {
+        let violations = spec.check(request, status, &body);
+        if !violations.is_empty() {
+            return Ok(Some((
+                FindingKind::InvalidResponse,
+                Some(status),
+                violations.join("; "),
+            )));
+        }
+    }
+    Ok(None)
+}
+
+/// Write a finding as a requests file in `dir`, returning its path.
+///
+/// The file replays with `doubleagent validate-spec <service> --requests <file>`.
+pub fn write_repro(
+    dir: &Path,
+    service: &str,
+    index: usize,
+    finding: &Finding,
+    seed: u64,
+) -> Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!(
+        "{}-{}-{}.requests",
+        service,
+        index + 1,
+        finding.kind.slug()
+    ));
+    let status = finding
+        .status
+        .map_or_else(|| "no response".to_string(), |s| format!("status {}", s));
+    let mut content = format!(
+        "# {} in {} ({}): {}\n\
+         # Found by: doubleagent fuzz {} --seed {}\n\
+         # Replay with: doubleagent validate-spec {} --requests {}\n",
+        finding.kind,
+        finding.operation,
+        status,
+        finding.detail.replace('\n', " "),
+        service,
+        seed,
+        service,
+        path.display()
+    );
+    content.push_str(&format!(
+        "{} {}",
+        finding.request.method, finding.request.path
+    ));
+    if let Some(body) = &finding.request.body {
+        content.push(' ');
+        content.push_str(&serde_json::to_string(body)?);
+    }
+    content.push('\n');
+    fs::write(&path, content)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn spec() -> Spec {
+        Spec::new(
+            serde_yaml::from_str(
+                r##"
+openapi: 3.0.3
+paths:
+  /repos/{owner}/{repo}/issues:
+    parameters:
+      - {name: owner, in: path, required: true, schema: {type: string}}
+      - {name: repo, in: path, required: true, schema: {type: string}}
+    get:
+      parameters:
+        - {name: state, in: query, schema: {type: string, enum: [open, closed]}}
+        - {name: per_page, in: query, schema: {type: integer, maximum: 100}}
+      responses:
+        "200": {description: ok}
+    post:
+      requestBody:
+        content:
+          application/json:
+            schema:
+              type: object
+              required: [title]
+              properties:
+                title: {type: string}
+                labels: {type: array, items: {type: string}}
+      responses:
+        "201": {description: created}
+    head:
+      responses:
+        "200": {description: ok}
+"##,
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_generated_requests_follow_the_description() {
+        let spec = spec();
+        let operations = operations(&spec);
+        let names: Vec<String> = operations.iter().map(|o| o.to_string()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "GET /repos/{owner}/{repo}/issues",
+                "POST /repos/{owner}/{repo}/issues"
+            ]
+        );
+
+        let mut rng = Rng::new(7);
+        for _ in 0..200 {
+            let get = generate(&spec, &operations[0], &mut rng);
+            assert!(get.path.starts_with("/repos/"), "{}", get.path);
+            assert!(get.path.contains("/issues"), "{}", get.path);
+            assert!(get.body.is_none());
+            let post = generate(&spec, &operations[1], &mut rng);
+            assert_eq!(post.method, "POST");
+            assert!(post.body.is_some());
+        }
+
+        // The same seed generates the same requests
+        let first: Vec<Request> = (0..20)
+            .scan(Rng::new(42), |rng, i| {
+                Some(generate(&spec, &operations[i % 2], rng))
+            })
+            .collect();
+        let again: Vec<Request> = (0..20)
+            .scan(Rng::new(42), |rng, i| {
+                Some(generate(&spec, &operations[i % 2], rng))
+            })
+            .collect();
+        assert_eq!(first, again);
+    }
+
+    #[test]
+    fn test_simplifications_shrink_body_and_query() {
+        let request = Request {
+            method: "POST".to_string(),
+            path: "/repos/acme/api/issues?state=open&per_page=100".to_string(),
+            body: Some(json!({"title": "abcd", "labels": ["x", "y"]})),
+        };
+        let candidates = simplifications(&request);
+        assert_eq!(candidates[0].body, None);
+        assert!(candidates
+            .iter()
+            .any(|c| c.body == Some(json!({"labels": ["x", "y"]}))));
+        assert!(candidates
+            .iter()
+            .any(|c| c.body == Some(json!({"title": "ab", "labels": ["x", "y"]}))));
+        assert!(candidates
+            .iter()
+            .any(|c| c.path == "/repos/acme/api/issues?per_page=100"));
+        assert!(candidates
+            .iter()
+            .any(|c| c.path == "/repos/acme/api/issues?state=open&per_page=1"));
+
+        let bare = Request {
+            method: "GET".to_string(),
+            path: "/repos/acme/api/issues".to_string(),
+            body: None,
+        };
+        assert!(simplifications(&bare).is_empty());
+    }
+
+    #[test]
+    fn test_repro_is_a_requests_file() {
+        let dir = tempdir().unwrap();
+        let finding = Finding {
+            kind: FindingKind::ServerError,
+            operation: "POST /repos/{owner}/{repo}/issues".to_string(),
+            request: Request {
+                method: "POST".to_string(),
+                path: "/repos/acme/api/issues".to_string(),
+                body: Some(json!({"title": null})),
+            },
+            status: Some(500),
+            detail: "Internal Server Error".to_string(),
+        };
+        let path = write_repro(dir.path(), "github", 0, &finding, 42).unwrap();
+        assert!(path.ends_with("github-1-server-error.requests"));
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains("--seed 42"), "{}", content);
+        let requests = conformance::parse_requests(&content).unwrap();
+        assert_eq!(requests, vec![finding.request]);
+    }
+}
@@ -0,0 +1,570 @@
+//! Synthetic seed payloads from a service's generator templates.
+//!
+//! A service can ship a `generators.yaml` next to its service.yaml describing
+//! how to make up records of each resource, so fakes can be filled without
+//! pulling anything real:
+//!
+//! ```yaml
+//! users:
+//!   count: 20                      # records made unless a count is asked for
+//!   fields:
+//!     id: {type: sequence}         # 1, 2, 3... (or "user-1"... with a prefix)
+//!     login: {type: username}
+//!     email: {type: email}
+//! issues:
+//!   fields:
+//!     title: {type: sentence, words: 5}
+//!     state: {type: choice, values: [open, closed], weights: [3, 1]}
+//!     comments: {type: integer, min: 0, max: 40, distribution: exponential}
+//!     author: {type: reference, resource: users, field: login, distribution: exponential}
+//!     created_at: {type: timestamp, from: 2023-01-01, to: 2024-12-31}
+//! ```
+//!
+//! Field types are `sequence`, `integer`, `boolean` (`probability`, default
+//! 0.5), `choice`, `name`, `username`, `email`, `word`, `sentence`,
+//! `timestamp`, `reference` and `constant` (`value`). A `reference` takes the
+//! `field` (default `id`) of a record of another resource, which is generated
+//! first. `distribution` picks integers and referenced records `uniform`ly
+//! (the default), around the middle (`normal`) or mostly from the start
+//! (`exponential`), e.g. so a few users author most issues.
+//!
+//! Generation is deterministic: the same template, counts and seed always
+//! give the same payload.
+
+use crate::dates::{self, SECONDS_PER_DAY};
+use crate::service::ServiceDefinition;
+use crate::{Error, Result};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+/// File in a service directory holding its generator templates.
+pub const GENERATORS_FILE: &str = "generators.yaml";
+
+/// Records made of a resource when neither the template nor the request says.
+const DEFAULT_COUNT: usize = 10;
+
+/// Generator templates of a service, by resource.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Generators {
+    resources: BTreeMap<String, ResourceTemplate>,
+}
+
+/// How to make up records of one resource.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceTemplate {
+    /// Records made unless a count is asked for
+    #[serde(default = "default_count")]
+    pub count: usize,
+    /// Generator of each field
+    pub fields: BTreeMap<String, Field>,
+}
+
+fn default_count() -> usize {
+    DEFAULT_COUNT
+}
+
+/// How to make up the value of a field.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Field {
+    /// Consecutive numbers, or strings if `prefix` is set
+    Sequence {
+        #[serde(default = "default_start")]
+        start: u64,
+        #[serde(default)]
+        prefix: Option<String>,
+    },
+    /// A whole number in `min..=max`
+    Integer {
+        min: i64,
+        max: i64,
+        #[serde(default)]
+        distribution: Distribution,
+    },
+    /// `true` with the given probability
+    Boolean {
+        #[serde(default = "default_probability")]
+        probability: f64,
+    },
+    /// One of `values`, optionally weighted
+    Choice {
+        values: Vec<Value>,
+        #[serde(default)]
+        weights: Vec<f64>,
+    },
+    /// A person's full name
+    Name,
+    /// A lowercase handle, unique per record
+    Username,
+    /// An `@example.com` address, unique per record
+    Email,
+    /// A single lowercase word
+    Word,
+    /// Words ending with a period
+    Sentence {
+        #[serde(default = "default_words")]
+        words: usize,
+    },
+    /// An RFC 3339 UTC time between two dates (`YYYY-MM-DD`)
+    Timestamp { from: String, to: String },
+    /// A field of a record of another resource
+    Reference {
+        resource: String,
+        #[serde(default = "default_reference_field")]
+        field: String,
+        #[serde(default)]
+        distribution: Distribution,
+    },
+    /// The same value in every record
+    Constant { value: Value },
+}
+
+fn default_start() -> u64 {
+    1
+}
+
+fn default_probability() -> f64 {
+    0.5
+}
+
+fn default_words() -> usize {
+    6
+}
+
+fn default_reference_field() -> String {
+    "id".to_string()
+}
+
+/// How values are spread over a range.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Distribution {
+    /// Every value equally likely
+    #[default]
+    Uniform,
+    /// Clustered around the middle of the range
+    Normal,
+    /// Mostly from the start of the range, with a long tail
+    Exponential,
+}
+
+impl Generators {
+    /// Load the templates a service ships.
+    pub fn load(service: &ServiceDefinition) -> Result<Self> {
+        let path = service.path.join(GENERATORS_FILE);
+        if !path.is_file() {
+            return Err(Error::Other(format!(
+                "Service '{}' has no {}, so it can't generate seed data",
+                service.name, GENERATORS_FILE
+            )));
+        }
+        Self::from_file(&path)
+    }
+
+    /// Load templates from a file.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let generators: Self = serde_yaml::from_str(&content)
+            .map_err(|e| Error::Other(format!("Invalid {}: {}", path.display(), e)))?;
+        generators
+            .order()
+            .map_err(|e| Error::Other(format!("Invalid {}: {}", path.display(), e)))?;
+        Ok(generators)
+    }
+
+    /// Names of the resources that can be generated.
+    pub fn resources(&self) -> impl Iterator<Item = &str> {
+        self.resources.keys().map(String::as_str)
+    }
+
+    /// Generate a seed payload with `counts` records of the resources named
+    /// there and the template's count of the others.
+    pub fn generate(&self, counts: &BTreeMap<String, usize>, seed: u64) -> Result<Value> {
+        if let Some(unknown) = counts.keys().find(|r| !self.resources.contains_key(*r)) {
+            let known: Vec<&str> = self.resources().collect();
+            return Err(Error::Other(format!(
+                "No generator for '{}' (resources: {})",
+                unknown,
+                known.join(", ")
+            )));
+        }
+
+        let mut rng = Rng::new(seed);
+        let mut payload = Map::new();
+        for name in self.order()? {
+            let template = &self.resources[name];
+            let count = counts.get(name).copied().unwrap_or(template.count);
+            let mut records = Vec::with_capacity(count);
+            for index in 0..count {
+                let mut record = Map::new();
+                for (field, generator) in &template.fields {
+                    let value = generator
+                        .generate(index, &payload, &mut rng)
+                        .map_err(|e| Error::Other(format!("{}.{}: {}", name, field, e)))?;
+                    record.insert(field.clone(), value);
+                }
+                records.push(Value::Object(record));
+            }
+            payload.insert(name.to_string(), Value::Array(records));
+        }
+        Ok(Value::Object(payload))
+    }
+
+    /// Resources in an order where every resource comes after those it
+    /// refers to.
+    fn order(&self) -> Result<Vec<&str>> {
+        fn visit<'a>(
+            generators: &'a Generators,
+            name: &'a str,
+            visiting: &mut BTreeSet<&'a str>,
+            order: &mut Vec<&'a str>,
+        ) -> Result<()> {
+            if order.contains(&name) {
+                return Ok(());
+            }
+            if !visiting.insert(name) {
+                return Err(Error::Other(format!(
+                    "references between resources form a cycle through '{}'",
+                    name
+                )));
+            }
+            for field in generators.resources[name].fields.values() {
+                if let Field::Reference { resource, .. } = field {
+                    if !generators.resources.contains_key(resource) {
+                        return Err(Error::Other(format!(
+                            "'{}' refers to '{}', which has no generator",
+                            name, resource
+                        )));
+                    }
+                    visit(generators, resource, visiting, order)?;
+                }
+            }
+            visiting.remove(name);
+            order.push(name);
+            Ok(())
+        }
+
+        let mut order = Vec::new();
+        for name in self.resources.keys() {
+            visit(self, name, &mut BTreeSet::new(), &mut order)?;
+        }
+        Ok(order)
+    }
+}
+
+impl Field {
+    /// The value of this field in record `index`, given the resources
+    /// generated so far.
+    fn generate(&self, index: usize, payload: &Map<String, Value>, rng: &mut Rng) -> Result<Value> {
+        let value = match self {
+            Field::Sequence { start, prefix } => {
+                let n = start + index as u64;
+                match prefix {
+                    Some(prefix) => Value::String(format!("{}{}", prefix, n)),
+                    None => Value::from(n),
+                }
+            }
+            Field::Integer {
+                min,
+                max,
+                distribution,
+            } => {
+                if min > max {
+                    return Err(Error::Other(format!("min {} is above max {}", min, max)));
+                }
+                let span = max.abs_diff(*min).saturating_add(1);
+                Value::from(min.wrapping_add(rng.pick(span, *distribution) as i64))
+            }
+            Field::Boolean { probability } => Value::Bool(rng.next_f64() < *probability),
+            Field::Choice { values, weights } => {
+                if values.is_empty() {
+                    return Err(Error::Other("choice has no values".to_string()));
+                }
+                if weights.is_empty() {
+                    values[rng.pick(values.len() as u64, Distribution::Uniform) as usize].clone()
+                } else if weights.len() != values.len() || weights.iter().any(|w| *w < 0.0) {
+                    return Err(Error::Other(
+                        "choice needs one non-negative weight per value".to_string(),
+                    ));
+                } else {
+                    let mut left = rng.next_f64() * weights.iter().sum::<f64>();
+                    let mut chosen = values.len() - 1;
+                    for (i, weight) in weights.iter().enumerate() {
+                        if left < *weight {
+                            chosen = i;
+                            break;
+                        }
+                        left -= weight;
+                    }
+                    values[chosen].clone()
+                }
+            }
+            Field::Name => {
+                let (first, last) = rng.name();
+                Value::String(format!("{} {}", first, last))
+            }
+            Field::Username => {
+                let (first, last) = rng.name();
+                Value::String(format!("{}{}{}", first, last, index + 1).to_lowercase())
+            }
+            Field::Email => {
+                let (first, last) = rng.name();
+                Value::String(format!("{}.{}{}@example.com", first, last, index + 1).to_lowercase())
+            }
+            Field::Word => Value::String(rng.word().to_string()),
+            Field::Sentence { words } => {
+                let mut sentence: Vec<&str> = (0..(*words).max(1)).map(|_| rng.word()).collect();
+                let first = capitalize(sentence[0]);
+                sentence[0] = &first;
+                Value::String(format!("{}.", sentence.join(" ")))
+            }
+            Field::Timestamp { from, to } => {
+                let from = dates::parse_date(from)?;
+                let to = dates::parse_date(to)?;
+                if from > to {
+                    return Err(Error::Other("from is after to".to_string()));
+                }
+                // Any second of the days from `from` through `to`
+                let span = ((to - from + 1) * SECONDS_PER_DAY) as u64;
+                Value::String(dates::format_time(
+                    from * SECONDS_PER_DAY + rng.pick(span, Distribution::Uniform) as i64,
+                ))
+            }
+            Field::Reference {
+                resource,
+                field,
+                distribution,
+            } => {
+                let records = payload
+                    .get(resource)
+                    .and_then(Value::as_array)
+                    .filter(|records| !records.is_empty())
+                    .ok_or_else(|| {
+                        Error::Other(format!("refers to '{}', which has no records", resource))
+                    })?;
+                let record = &records[rng.pick(records.len() as u64, *distribution) as usize];
+                record.get(field).cloned().ok_or_else(|| {
+                    Error::Other(format!("'{}' records have no '{}' field", resource, field))
+                })?
+            }
+            Field::Constant { value } => value.clone(),
+        };
+        Ok(value)
+    }
+}
+
+/// Parse `issues=2000,users=500` into record counts by resource.
+pub fn parse_counts(spec: &str) -> Result<BTreeMap<String, usize>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let invalid = || {
+                Error::Other(format!(
+                    "Invalid count '{}' (expected <resource>=<number>)",
+                    part
+                ))
+            };
+            let (resource, count) = part.split_once('=').ok_or_else(invalid)?;
+            let count = count.trim().parse().map_err(|_| invalid())?;
+            Ok((resource.trim().to_string(), count))
+        })
+        .collect()
+}
+
+/// A small deterministic random number generator (SplitMix64).
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A number in `0..n` (`n` > 0) spread by `distribution`.
+    fn pick(&mut self, n: u64, distribution: Distribution) -> u64 {
+        let x = match distribution {
+            Distribution::Uniform => return self.next_u64() % n,
+            Distribution::Normal => loop {
+                // Box-Muller, resampled until it falls in range
+                let (u, v) = (1.0 - self.next_f64(), self.next_f64());
+                let z = (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos();
+                let x = 0.5 + z / 6.0;
+                if (0.0..1.0).contains(&x) {
+                    break x;
+                }
+            },
+            Distribution::Exponential => {
+                // Truncated to [0, 1), with most values in the first fifth
+                const RATE: f64 = 5.0;
+                let u = self.next_f64() * (1.0 - (-RATE).exp());
+                -(1.0 - u).ln() / RATE
+            }
+        };
+        ((x * n as f64) as u64).min(n - 1)
+    }
+
+    fn name(&mut self) -> (&'static str, &'static str) {
+        let first = crate::redact::FIRST_NAMES;
+        let last = crate::redact::LAST_NAMES;
+        (
+            first[self.pick(first.len() as u64, Distribution::Uniform) as usize],
+            last[self.pick(last.len() as u64, Distribution::Uniform) as usize],
+        )
+    }
+
+    fn word(&mut self) -> &'static str {
+        WORDS[self.pick(WORDS.len() as u64, Distribution::Uniform) as usize]
+    }
+}
+
+const WORDS: &[&str] = &[
+    "add", "api", "branch", "bug", "build", "cache", "client", "config", "crash", "data", "deploy",
+    "docs", "error", "fix", "flaky", "handle", "login", "memory", "merge", "missing", "page",
+    "parser", "release", "request", "retry", "schema", "server", "slow", "support", "test",
+    "timeout", "update", "upload", "user", "webhook", "when", "with", "after", "on", "the",
+];
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATES: &str = "\
+users:
+  count: 5
+  fields:
+    id: {type: sequence}
+    login: {type: username}
+    email: {type: email}
+issues:
+  fields:
+    id: {type: sequence, start: 100, prefix: ISSUE-}
+    title: {type: sentence, words: 4}
+    state: {type: choice, values: [open, closed], weights: [1, 0]}
+    comments: {type: integer, min: 0, max: 9, distribution: exponential}
+    author: {type: reference, resource: users, field: login, distribution: normal}
+    created_at: {type: timestamp, from: 2024-02-28, to: 2024-03-01}
+";
+
+    fn generators(yaml: &str) -> Generators {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(GENERATORS_FILE);
+        fs::write(&path, yaml).unwrap();
+        Generators::from_file(&path).unwrap()
+    }
+
+    #[test]
+    fn test_generate_counts_and_references() {
+        let generators = generators(TEMPLATES);
+        let counts = parse_counts("issues=50").unwrap();
+        let payload = generators.generate(&counts, 7).unwrap();
+        assert_eq!(payload, generators.generate(&counts, 7).unwrap());
+        assert_ne!(payload, generators.generate(&counts, 8).unwrap());
+
+        let users = payload["users"].as_array().unwrap();
+        let issues = payload["issues"].as_array().unwrap();
+        assert_eq!((users.len(), issues.len()), (5, 50));
+        assert_eq!(users[4]["id"], 5);
+        assert!(users[0]["email"]
+            .as_str()
+            .unwrap()
+            .ends_with("1@example.com"));
+
+        let logins: Vec<&Value> = users.iter().map(|u| &u["login"]).collect();
+        for issue in issues {
+            assert!(logins.contains(&&issue["author"]), "{}", issue);
+            assert_eq!(issue["state"], "open");
+            assert!((0..=9).contains(&issue["comments"].as_i64().unwrap()));
+            let created = issue["created_at"].as_str().unwrap();
+            assert!(
+                ("2024-02-28".."2024-03-02").contains(&created),
+                "{}",
+                created
+            );
+            assert!(issue["title"].as_str().unwrap().ends_with('.'));
+        }
+        assert_eq!(issues[0]["id"], "ISSUE-100");
+
+        let msg = generators
+            .generate(&parse_counts("repos=1").unwrap(), 0)
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("resources: issues, users"), "{}", msg);
+        let msg = generators
+            .generate(&parse_counts("users=0").unwrap(), 0)
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("issues.author"), "{}", msg);
+    }
+
+    #[test]
+    fn test_invalid_templates_and_counts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(GENERATORS_FILE);
+        fs::write(
+            &path,
+            "a:\n  fields: {b: {type: reference, resource: b}}\n\
+             b:\n  fields: {a: {type: reference, resource: a}}\n",
+        )
+        .unwrap();
+        let msg = Generators::from_file(&path).unwrap_err().to_string();
+        assert!(msg.contains("cycle"), "{}", msg);
+
+        assert!(parse_counts("issues").is_err());
+        assert!(parse_counts("issues=many").is_err());
+        assert_eq!(
+            parse_counts("issues=2000, users=500").unwrap(),
+            BTreeMap::from([("issues".to_string(), 2000), ("users".to_string(), 500)])
+        );
+    }
+
+    #[test]
+    fn test_bundled_generators_match_seed_schemas() {
+        let services = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../services");
+        for entry in fs::read_dir(services).unwrap() {
+            let dir = entry.unwrap().path();
+            if !dir.join(GENERATORS_FILE).exists() {
+                continue;
+            }
+            let service = ServiceDefinition::from_dir(&dir).unwrap();
+            let payload = Generators::load(&service)
+                .unwrap()
+                .generate(&BTreeMap::new(), 0)
+                .unwrap();
+            let diagnostics = crate::snapshot::schema::check(&service, &dir, &payload)
+                .unwrap()
+                .unwrap_or_default();
+            assert!(
+                !crate::validate::has_errors(&diagnostics),
+                "{}: {:?}",
+                dir.display(),
+                diagnostics
+            );
+        }
+    }
+}
@@ -90,6 +90,25 @@ impl ServiceFetcher {
         self.fetch_service(name)
     }
 
+    /// Copy an installed service's files aside, so they can be put back if
+    /// what replaces them doesn't work.
+    pub fn backup_service(&self, name: &str) -> Result<ServiceBackup> {
+        let path = self.cache_dir.join(name);
+        if !path.exists() {
+            return Err(Error::ServiceNotFound(format!(
+                "Service '{}' is not installed. Use 'doubleagent add {}' first.",
+                name, name
+            )));
+        }
+        // Hidden, so it isn't listed as an installed service
+        let backup = self.cache_dir.join(".previous").join(name);
+        if backup.exists() {
+            fs::remove_dir_all(&backup)?;
+        }
+        copy_dir_recursive(&path, &backup)?;
+        Ok(ServiceBackup { path, backup })
+    }
+
     /// Update all cached services
     pub fn update_all_services(&self) -> Result<Vec<String>> {
         let mut updated = Vec::new();
@@ -274,17 +293,36 @@ impl ServiceFetcher {
 fn print_progress(progress: &Progress) {
     let received = progress.received_objects();
     let total = progress.total_objects();
-    if total > 0 {
-        debug!(
-            "Receiving objects: {}% ({}/{})",
-            (received * 100) / total,
-            received,
-            total
-        );
+    if let Some(percent) = (received * 100).checked_div(total) {
+        debug!("Receiving objects: {}% ({}/{})", percent, received, total);
     }
 }
 
 /// Recursively copy a directory
+/// The files of an installed service as they were before an update.
+#[derive(Debug)]
+pub struct ServiceBackup {
+    path: PathBuf,
+    backup: PathBuf,
+}
+
+impl ServiceBackup {
+    /// Put the backed-up files back in place of the current ones.
+    pub fn restore(self) -> Result<()> {
+        if self.path.exists() {
+            fs::remove_dir_all(&self.path)?;
+        }
+        fs::rename(&self.backup, &self.path)?;
+        Ok(())
+    }
+
+    /// Keep the current files and delete the backup.
+    pub fn discard(self) -> Result<()> {
+        fs::remove_dir_all(&self.backup)?;
+        Ok(())
+    }
+}
+
 fn copy_dir_recursive(src: &Path, dst: &Path) -> Result<()> {
     fs::create_dir_all(dst)?;
 
@@ -298,7 +336,11 @@ fn copy_dir_recursive(src: &Path, dst: &Path) -> Result<()> {
             continue;
         }
 
-        if src_path.is_dir() {
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            // Such as the interpreter links in a service's virtualenv
+            std::os::unix::fs::symlink(fs::read_link(&src_path)?, &dst_path)?;
+        } else if file_type.is_dir() {
             copy_dir_recursive(&src_path, &dst_path)?;
         } else {
             fs::copy(&src_path, &dst_path)?;
@@ -327,4 +369,31 @@ mod tests {
         assert_eq!(fetcher.repo_cache_dir, temp_dir.path().join(".repo"));
         assert_eq!(fetcher.branch, "main");
     }
+
+    #[test]
+    fn test_backup_restores_the_previous_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let fetcher = ServiceFetcher::new(
+            "https://github.com/example/services".to_string(),
+            temp_dir.path().to_path_buf(),
+            "main".to_string(),
+        );
+        let service = temp_dir.path().join("github");
+        fs::create_dir_all(service.join("server")).unwrap();
+        fs::write(service.join("service.yaml"), "version: 1").unwrap();
+
+        let backup = fetcher.backup_service("github").unwrap();
+        fs::write(service.join("service.yaml"), "version: 2").unwrap();
+        fs::write(service.join("new.txt"), "").unwrap();
+        backup.restore().unwrap();
+        assert_eq!(
+            fs::read_to_string(service.join("service.yaml")).unwrap(),
+            "version: 1"
+        );
+        assert!(!service.join("new.txt").exists());
+
+        fetcher.backup_service("github").unwrap().discard().unwrap();
+        assert!(!temp_dir.path().join(".previous").join("github").exists());
+        assert!(fetcher.backup_service("slack").is_err());
+    }
 }
@@ -0,0 +1,630 @@
+//! Golden files of the real API's responses, for `doubleagent contract
+//! --record` and `--compare`.
+//!
+//! The contract scenarios are a requests file in the contracts directory
+//! (`golden.requests` by default), in the format `validate-spec --requests`
+//! reads: `METHOD /path [JSON body]` per line. Recording sends each to the
+//! real API and stores its status and body as a golden file; comparing sends
+//! the same requests to the fake and diffs its responses against those files
+//! field by field.
+//!
+//! After an intentional change to the fake, `--update-golden` rewrites the
+//! golden files from the fake's responses instead, and both record modes
+//! report how each file changed as a line diff of the old and new responses.
+//!
+//! Fields that are meant to differ, such as ids and timestamps, are left out
+//! of the comparison with `ignore` globs over body paths, where `*` matches
+//! within one segment (an object key or an array index) and `**` any number
+//! of segments:
+//!
+//! ```yaml
+//! contracts:
+//!   golden:
+//!     requests: golden.requests
+//!     directory: golden
+//!     ignore: ["**.id", "**.node_id", "**.created_at", "**.updated_at", "**.*url"]
+//! ```
+
+use crate::conformance::{self, Request};
+use crate::contract::{Outcome, TestCase};
+use crate::redact::glob_match;
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How many differences are listed for one response before the rest are counted.
+const MAX_DIFFERENCES: usize = 10;
+
+/// Configuration of recorded golden files, under `contracts.golden`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GoldenConfig {
+    /// Requests file of the scenarios, relative to the contracts directory
+    #[serde(default = "default_requests")]
+    pub requests: String,
+    /// Directory of the golden files, relative to the contracts directory
+    #[serde(default = "default_directory")]
+    pub directory: String,
+    /// Globs of body fields left out of the comparison
+    #[serde(default)]
+    pub ignore: Vec<String>,
+}
+
+impl Default for GoldenConfig {
+    fn default() -> Self {
+        Self {
+            requests: default_requests(),
+            directory: default_directory(),
+            ignore: Vec::new(),
+        }
+    }
+}
+
+fn default_requests() -> String {
+    "golden.requests".to_string()
+}
+
+fn default_directory() -> String {
+    "golden".to_string()
+}
+
+/// A recorded response, as stored in a golden file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Golden {
+    pub method: String,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_body: Option<Value>,
+    pub status: u16,
+    /// The JSON body, a string if it wasn't JSON, or null if empty
+    pub body: Value,
+}
+
+/// How a fake's response to one scenario differs from the golden file.
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub request: Request,
+    /// The fake's status
+    pub status: u16,
+    /// Empty if the responses match
+    pub differences: Vec<String>,
+    /// The golden response against the fake's, if they differ
+    pub diff: Vec<DiffLine>,
+}
+
+/// A response written to a scenario's golden file.
+#[derive(Debug, Clone)]
+pub struct Recorded {
+    pub golden: Golden,
+    /// How it differs from the file it replaced, `None` if there was none
+    pub changes: Option<Vec<String>>,
+    /// The replaced response against the new one, if they differ
+    pub diff: Vec<DiffLine>,
+}
+
+/// A line of a diff between two responses, as pretty-printed JSON.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    Same(String),
+    /// Only in the golden (or replaced) response
+    Removed(String),
+    /// Only in the new response
+    Added(String),
+}
+
+impl Comparison {
+    /// The comparison as a test case of a contract report.
+    pub fn to_test_case(&self) -> TestCase {
+        let outcome = if self.differences.is_empty() {
+            Outcome::Passed
+        } else {
+            Outcome::Failed
+        };
+        TestCase {
+            name: self.request.to_string(),
+            classname: "golden".to_string(),
+            time: 0.0,
+            outcome,
+            message: (!self.differences.is_empty()).then(|| self.differences.join("; ")),
+            details: (!self.diff.is_empty()).then(|| unified(&self.diff)),
+            retries: 0,
+        }
+    }
+}
+
+/// The scenarios of a service's contracts and where their golden files live.
+#[derive(Debug, Clone)]
+pub struct Scenarios {
+    pub requests: Vec<Request>,
+    pub directory: PathBuf,
+    ignore: Vec<Vec<String>>,
+}
+
+impl Scenarios {
+    /// Read the requests file named in `config` from the contracts directory.
+    pub fn load(contracts_dir: &Path, config: &GoldenConfig) -> Result<Self> {
+        let path = contracts_dir.join(&config.requests);
+        let content = fs::read_to_string(&path).map_err(|e| {
+            Error::Other(format!(
+                "Can't read the contract scenarios at {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        let requests = conformance::parse_requests(&content)?;
+        if requests.is_empty() {
+            return Err(Error::Other(format!(
+                "{} lists no requests to record",
+                path.display()
+            )));
+        }
+        Ok(Self {
+            requests,
+            directory: contracts_dir.join(&config.directory),
+            ignore: config.ignore.iter().map(|glob| segments(glob)).collect(),
+        })
+    }
+
+    /// The golden file of a request.
+    pub fn file(&self, request: &Request) -> PathBuf {
+        self.directory.join(file_name(request))
+    }
+
+    /// The golden file of a request, if it was recorded.
+    pub fn read(&self, request: &Request) -> Result<Option<Golden>> {
+        let path = self.file(request);
+        let Ok(content) = fs::read_to_string(&path) else {
+            return Ok(None);
+        };
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|e| Error::Other(format!("Invalid golden file {}: {}", path.display(), e)))
+    }
+
+    /// Send every scenario to `base_url` (the real API, or the fake with
+    /// `--update-golden`) and store its responses as golden files, returning
+    /// how each compares with the file it replaced.
+    pub async fn record(&self, base_url: &str, token: Option<&str>) -> Result<Vec<Recorded>> {
+        fs::create_dir_all(&self.directory)?;
+        let client = reqwest::Client::new();
+        let mut recorded = Vec::new();
+        for request in &self.requests {
+            let (status, body) = send(&client, base_url, request, token).await?;
+            let previous = self.read(request)?;
+            let golden = Golden {
+                method: request.method.clone(),
+                path: request.path.clone(),
+                request_body: request.body.clone(),
+                status,
+                body,
+            };
+            fs::write(
+                self.file(request),
+                serde_json::to_string_pretty(&golden)? + "\n",
+            )?;
+            let changes = previous
+                .as_ref()
+                .map(|previous| self.differences(previous, status, &golden.body));
+            let diff = match (&previous, &changes) {
+                (Some(previous), Some(changes)) if !changes.is_empty() => {
+                    self.line_diff(previous, status, &golden.body)
+                }
+                _ => Vec::new(),
+            };
+            recorded.push(Recorded {
+                golden,
+                changes,
+                diff,
+            });
+        }
+        Ok(recorded)
+    }
+
+    /// Send every scenario to the fake at `base_url` and compare its
+    /// responses with the golden files.
+    pub async fn compare(&self, base_url: &str) -> Result<Vec<Comparison>> {
+        let client = reqwest::Client::new();
+        let mut comparisons = Vec::new();
+        for request in &self.requests {
+            let (status, body) = send(&client, base_url, request, None).await?;
+            let (differences, diff) = match self.read(request)? {
+                Some(golden) => {
+                    let differences = self.differences(&golden, status, &body);
+                    let diff = if differences.is_empty() {
+                        Vec::new()
+                    } else {
+                        self.line_diff(&golden, status, &body)
+                    };
+                    (differences, diff)
+                }
+                None => (
+                    vec![format!(
+                        "no golden file at {}; record one with --record",
+                        self.file(request).display()
+                    )],
+                    Vec::new(),
+                ),
+            };
+            comparisons.push(Comparison {
+                request: request.clone(),
+                status,
+                differences,
+                diff,
+            });
+        }
+        Ok(comparisons)
+    }
+
+    /// How a response differs from a golden one, ignored fields aside.
+    pub fn differences(&self, golden: &Golden, status: u16, body: &Value) -> Vec<String> {
+        let mut differences = Vec::new();
+        if status != golden.status {
+            differences.push(format!(
+                "status: expected {}, got {}",
+                golden.status, status
+            ));
+        }
+        diff(
+            &golden.body,
+            body,
+            &mut Vec::new(),
+            &self.ignore,
+            "the golden file",
+            &mut differences,
+        );
+        if differences.len() <= MAX_DIFFERENCES {
+            return differences;
+        }
+        let more = differences.len() - MAX_DIFFERENCES;
+        differences.truncate(MAX_DIFFERENCES);
+        differences.push(format!("... and {} more", more));
+        differences
+    }
+
+    /// The golden response against another, line by line, with the status
+    /// first and ignored fields left out.
+    pub fn line_diff(&self, golden: &Golden, status: u16, body: &Value) -> Vec<DiffLine> {
+        let render = |status: u16, body: &Value| -> Vec<String> {
+            let body = strip(body, &mut Vec::new(), &self.ignore);
+            let body = serde_json::to_string_pretty(&body).unwrap_or_default();
+            std::iter::once(format!("status: {}", status))
+                .chain(body.lines().map(str::to_string))
+                .collect()
+        };
+        line_diff(&render(golden.status, &golden.body), &render(status, body))
+    }
+}
+
+/// `value` without the fields matching the `ignore` globs.
+fn strip(value: &Value, path: &mut Vec<String>, ignore: &[Vec<String>]) -> Value {
+    match value {
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .filter_map(|(key, value)| {
+                    path.push(key.clone());
+                    let kept = (!ignored(path, ignore)).then(|| strip(value, path, ignore));
+                    path.pop();
+                    kept.map(|value| (key.clone(), value))
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    path.push(i.to_string());
+                    let item = strip(item, path, ignore);
+                    path.pop();
+                    item
+                })
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+/// A diff of two lists of lines, from their longest common subsequence.
+pub fn line_diff(old: &[String], new: &[String]) -> Vec<DiffLine> {
+    // common[i][j]: length of the longest common subsequence of old[i..] and new[j..]
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(DiffLine::Same(old[i].clone()));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(DiffLine::Removed(old[i].clone()));
+            i += 1;
+        } else {
+            lines.push(DiffLine::Added(new[j].clone()));
+            j += 1;
+        }
+    }
+    lines
+}
+
+/// Send a request, with the token as a bearer token if given, returning the
+/// status and body.
+async fn send(
+    client: &reqwest::Client,
+    base_url: &str,
+    request: &Request,
+    token: Option<&str>,
+) -> Result<(u16, Value)> {
+    let method = reqwest::Method::from_bytes(request.method.as_bytes())
+        .map_err(|e| Error::Other(format!("Invalid method '{}': {}", request.method, e)))?;
+    let mut builder = client
+        .request(
+            method,
+            format!("{}{}", base_url.trim_end_matches('/'), request.path),
+        )
+        .header(reqwest::header::ACCEPT, "application/json")
+        .header(
+            reqwest::header::USER_AGENT,
+            concat!("doubleagent/", env!("CARGO_PKG_VERSION")),
+        );
+    if let Some(token) = token {
+        builder = builder.bearer_auth(token);
+    }
+    if let Some(body) = &request.body {
+        builder = builder.json(body);
+    }
+    let response = builder.send().await?;
+    let status = response.status().as_u16();
+    let bytes = response.bytes().await?;
+    Ok((status, parse_body(&bytes)))
+}
+
+/// A response body as stored in a golden file: its JSON, a string if it
+/// isn't JSON, or null if empty.
+pub(crate) fn parse_body(bytes: &[u8]) -> Value {
+    if bytes.iter().all(u8::is_ascii_whitespace) {
+        Value::Null
+    } else {
+        serde_json::from_slice(bytes)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(bytes).into_owned()))
+    }
+}
+
+/// A diff as plain text, `-` and `+` marking changed lines, with unchanged
+/// lines more than a few lines away from any change elided as `...`.
+pub fn unified(diff: &[DiffLine]) -> String {
+    const CONTEXT: usize = 2;
+    let changed: Vec<usize> = diff
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, DiffLine::Same(_)))
+        .map(|(i, _)| i)
+        .collect();
+    let near = |i: usize| changed.iter().any(|c| c.abs_diff(i) <= CONTEXT);
+    let mut lines = Vec::new();
+    let mut skipped = false;
+    for (i, line) in diff.iter().enumerate() {
+        if !near(i) {
+            skipped = true;
+            continue;
+        }
+        if skipped {
+            lines.push("...".to_string());
+            skipped = false;
+        }
+        lines.push(match line {
+            DiffLine::Same(text) => format!("  {}", text),
+            DiffLine::Removed(text) => format!("- {}", text),
+            DiffLine::Added(text) => format!("+ {}", text),
+        });
+    }
+    lines.join("\n")
+}
+
+/// Name of a request's golden file, e.g. `get-repos-acme-api-issues-state-open.json`.
+pub fn file_name(request: &Request) -> String {
+    let mut name = request.method.to_lowercase();
+    let mut dash = false;
+    for c in request.path.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            if !dash {
+                name.push('-');
+                dash = true;
+            }
+            name.push(c.to_ascii_lowercase());
+        } else {
+            dash = false;
+        }
+    }
+    name + ".json"
+}
+
+/// Split a glob or body path into segments, e.g. `**.labels.*.name`.
+pub(crate) fn segments(glob: &str) -> Vec<String> {
+    glob.split('.').map(str::to_string).collect()
+}
+
+/// Body path for messages, e.g. `body.0.title`.
+fn display(path: &[String]) -> String {
+    std::iter::once("body")
+        .chain(path.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Collect the differences between `expected` and `actual` at `path`;
+/// `reference` names where `expected` came from, for fields only `actual` has.
+pub(crate) fn diff(
+    expected: &Value,
+    actual: &Value,
+    path: &mut Vec<String>,
+    ignore: &[Vec<String>],
+    reference: &str,
+    differences: &mut Vec<String>,
+) {
+    if ignored(path, ignore) {
+        return;
+    }
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            let keys: BTreeSet<&String> = expected.keys().chain(actual.keys()).collect();
+            for key in keys {
+                path.push(key.clone());
+                match (expected.get(key), actual.get(key)) {
+                    (Some(expected), Some(actual)) => {
+                        diff(expected, actual, path, ignore, reference, differences)
+                    }
+                    (Some(_), None) if !ignored(path, ignore) => {
+                        differences.push(format!("{}: missing", display(path)))
+                    }
+                    (None, Some(_)) if !ignored(path, ignore) => {
+                        differences.push(format!("{}: not in {}", display(path), reference))
+                    }
+                    _ => {}
+                }
+                path.pop();
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            for (i, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+                path.push(i.to_string());
+                diff(expected, actual, path, ignore, reference, differences);
+                path.pop();
+            }
+            if expected.len() != actual.len() {
+                differences.push(format!(
+                    "{}: expected {} items, got {}",
+                    display(path),
+                    expected.len(),
+                    actual.len()
+                ));
+            }
+        }
+        (expected, actual) if expected != actual => differences.push(format!(
+            "{}: expected {}, got {}",
+            display(path),
+            expected,
+            actual
+        )),
+        _ => {}
+    }
+}
+
+/// Whether a body path matches one of the `ignore` globs.
+fn ignored(path: &[String], ignore: &[Vec<String>]) -> bool {
+    ignore.iter().any(|glob| glob_match(glob, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn scenarios(ignore: &[&str]) -> Scenarios {
+        Scenarios {
+            requests: Vec::new(),
+            directory: PathBuf::from("golden"),
+            ignore: ignore.iter().map(|glob| segments(glob)).collect(),
+        }
+    }
+
+    fn golden(status: u16, body: Value) -> Golden {
+        Golden {
+            method: "GET".to_string(),
+            path: "/repos/acme/api/issues".to_string(),
+            request_body: None,
+            status,
+            body,
+        }
+    }
+
+    #[test]
+    fn test_differences_ignore_allowlisted_fields() {
+        let recorded = golden(
+            200,
+            json!([
+                {"id": 1, "title": "Flaky", "labels": ["bug"], "created_at": "2024-01-01"},
+                {"id": 2, "title": "Slow", "labels": []}
+            ]),
+        );
+        let allowlisted = scenarios(&["**.id", "**.created_at"]);
+        let same = json!([
+            {"id": 71, "title": "Flaky", "labels": ["bug"], "created_at": "2026-10-16"},
+            {"id": 72, "title": "Slow", "labels": []}
+        ]);
+        assert!(allowlisted.differences(&recorded, 200, &same).is_empty());
+
+        let different = json!([
+            {"id": 71, "title": "flaky", "labels": ["bug", "p1"], "state": "open"}
+        ]);
+        assert_eq!(
+            allowlisted.differences(&recorded, 201, &different),
+            [
+                "status: expected 200, got 201",
+                "body.0.labels: expected 1 items, got 2",
+                "body.0.state: not in the golden file",
+                "body.0.title: expected \"Flaky\", got \"flaky\"",
+                "body: expected 2 items, got 1",
+            ]
+        );
+
+        // Without an allowlist, every id and timestamp counts
+        let differences = scenarios(&[]).differences(&recorded, 200, &same);
+        assert_eq!(differences.len(), 3, "{:?}", differences);
+    }
+
+    #[test]
+    fn test_line_diff_leaves_out_ignored_fields() {
+        let recorded = golden(200, json!({"id": 1, "title": "Flaky", "state": "open"}));
+        let diff = scenarios(&["id"]).line_diff(
+            &recorded,
+            200,
+            &json!({"id": 2, "title": "flaky", "state": "open"}),
+        );
+        assert_eq!(
+            diff,
+            [
+                DiffLine::Same("status: 200".to_string()),
+                DiffLine::Same("{".to_string()),
+                DiffLine::Same("  \"state\": \"open\",".to_string()),
+                DiffLine::Removed("  \"title\": \"Flaky\"".to_string()),
+                DiffLine::Added("  \"title\": \"flaky\"".to_string()),
+                DiffLine::Same("}".to_string()),
+            ]
+        );
+        assert_eq!(
+            unified(&diff),
+            "...\n  {\n    \"state\": \"open\",\n-   \"title\": \"Flaky\"\n+   \"title\": \"flaky\"\n  }"
+        );
+    }
+
+    #[test]
+    fn test_file_name() {
+        let request = Request {
+            method: "GET".to_string(),
+            path: "/repos/acme/api/issues?state=open".to_string(),
+            body: None,
+        };
+        assert_eq!(
+            file_name(&request),
+            "get-repos-acme-api-issues-state-open.json"
+        );
+        let request = Request {
+            method: "POST".to_string(),
+            path: "/".to_string(),
+            body: None,
+        };
+        assert_eq!(file_name(&request), "post.json");
+    }
+}
@@ -0,0 +1,180 @@
+//! Health check and startup timing.
+//!
+//! Defaults come from the `health_poll_interval_ms`, `health_request_timeout_ms`
+//! and `startup_timeout_secs` settings. A service can override any of them
+//! under `services.<name>.health` in the global config or doubleagent.yaml,
+//! e.g. to give a slow fake more time on a CI runner.
+//!
+//! Health checks made by `status` and `dashboard` are kept in each service's
+//! health history. A service whose health changed [`FLAP_CHANGES`] times in
+//! the last [`FLAP_WINDOW_SECS`] is flapping: both commands flag it, and with
+//! the `flap_webhook` setting a [`FlapAlert`] is posted when it starts to.
+
+use crate::hooks;
+use crate::process::HealthChange;
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Changes of health within [`FLAP_WINDOW_SECS`] that make a service flapping.
+pub const FLAP_CHANGES: usize = 4;
+
+/// Span, in seconds, flapping is looked for in.
+pub const FLAP_WINDOW_SECS: i64 = 600;
+
+/// Timing used when waiting for and checking service health.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthSettings {
+    /// Delay between health polls while waiting for startup
+    pub poll_interval: Duration,
+    /// Timeout of a single health request
+    pub request_timeout: Duration,
+    /// How long to wait for a started service to become healthy
+    pub startup_timeout: Duration,
+}
+
+impl HealthSettings {
+    /// Apply per-service overrides on top of these settings.
+    pub fn with_overrides(&self, overrides: &HealthOverrides) -> Self {
+        Self {
+            poll_interval: overrides
+                .poll_interval_ms
+                .map_or(self.poll_interval, Duration::from_millis),
+            request_timeout: overrides
+                .request_timeout_ms
+                .map_or(self.request_timeout, Duration::from_millis),
+            startup_timeout: overrides
+                .startup_timeout_secs
+                .map_or(self.startup_timeout, Duration::from_secs),
+        }
+    }
+}
+
+/// Per-service overrides of [`HealthSettings`]; unset fields keep the default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HealthOverrides {
+    pub poll_interval_ms: Option<u64>,
+    pub request_timeout_ms: Option<u64>,
+    pub startup_timeout_secs: Option<u64>,
+}
+
+impl HealthOverrides {
+    /// Combine two sets of overrides, preferring fields set in `other`.
+    pub fn merge(&self, other: &HealthOverrides) -> Self {
+        Self {
+            poll_interval_ms: other.poll_interval_ms.or(self.poll_interval_ms),
+            request_timeout_ms: other.request_timeout_ms.or(self.request_timeout_ms),
+            startup_timeout_secs: other.startup_timeout_secs.or(self.startup_timeout_secs),
+        }
+    }
+}
+
+/// Changes of health in the flap window before `now`, from a history oldest
+/// first whose first entry is when the service was first seen.
+pub fn recent_changes(history: &[HealthChange], now: i64) -> usize {
+    history
+        .iter()
+        .skip(1)
+        .filter_map(|change| change.at.parse::<i64>().ok())
+        .filter(|at| now - at <= FLAP_WINDOW_SECS)
+        .count()
+}
+
+/// Whether a service with this health history is flapping at `now`.
+pub fn is_flapping(history: &[HealthChange], now: i64) -> bool {
+    recent_changes(history, now) >= FLAP_CHANGES
+}
+
+/// What the `flap_webhook` is sent when a service starts flapping.
+#[derive(Debug, Clone, Serialize)]
+pub struct FlapAlert {
+    /// Always `flapping`
+    pub event: &'static str,
+    pub service: String,
+    pub workspace: String,
+    /// Whether the service was healthy at the last check
+    pub healthy: bool,
+    /// Changes of health in the last `window_seconds`
+    pub changes: usize,
+    pub window_seconds: i64,
+}
+
+impl FlapAlert {
+    pub fn new(service: &str, workspace: &str, healthy: bool, changes: usize) -> Self {
+        Self {
+            event: "flapping",
+            service: service.to_string(),
+            workspace: workspace.to_string(),
+            healthy,
+            changes,
+            window_seconds: FLAP_WINDOW_SECS,
+        }
+    }
+
+    /// Post the alert to `url` as JSON.
+    pub async fn send(&self, url: &str) -> Result<()> {
+        hooks::post(url, self).await
+    }
+}
+
+/// Check a `flap_webhook` or `crash_webhook` setting: empty, or an http:// or
+/// https:// URL.
+pub fn validate_webhook(value: &str) -> std::result::Result<(), String> {
+    if value.is_empty() || value.starts_with("http://") || value.starts_with("https://") {
+        Ok(())
+    } else {
+        Err("expected an http:// or https:// URL".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(times: &[i64]) -> Vec<HealthChange> {
+        times
+            .iter()
+            .enumerate()
+            .map(|(i, at)| HealthChange {
+                healthy: i % 2 == 0,
+                at: at.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_flapping() {
+        // First seen long ago, then four changes in the last few minutes
+        let flapping = history(&[0, 1_000, 1_100, 1_200, 1_300]);
+        assert_eq!(recent_changes(&flapping, 1_400), 4);
+        assert!(is_flapping(&flapping, 1_400));
+        // Changes out of the window no longer count
+        assert!(!is_flapping(&flapping, 1_000 + FLAP_WINDOW_SECS + 1));
+        // Being first seen isn't a change
+        assert!(!is_flapping(&history(&[1_000, 1_100, 1_200, 1_300]), 1_400));
+    }
+
+    #[test]
+    fn test_overrides_replace_only_set_fields() {
+        let defaults = HealthSettings {
+            poll_interval: Duration::from_millis(500),
+            request_timeout: Duration::from_secs(2),
+            startup_timeout: Duration::from_secs(30),
+        };
+        let global = HealthOverrides {
+            startup_timeout_secs: Some(60),
+            poll_interval_ms: Some(250),
+            ..Default::default()
+        };
+        let project = HealthOverrides {
+            startup_timeout_secs: Some(120),
+            ..Default::default()
+        };
+
+        let health = defaults.with_overrides(&global.merge(&project));
+        assert_eq!(health.startup_timeout, Duration::from_secs(120));
+        assert_eq!(health.poll_interval, Duration::from_millis(250));
+        assert_eq!(health.request_timeout, defaults.request_timeout);
+    }
+}
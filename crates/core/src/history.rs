@@ -0,0 +1,324 @@
+//! History of contract test runs, for `doubleagent contract history` and
+//! `contract --compare-to-last`.
+//!
+//! Every `contract` run appends one JSON line per service and target to
+//! `<data_dir>/contract-history/<service>.jsonl`: when it ran, the service's
+//! version, whether it passed and how long each test took. A run is compared
+//! with the last one of the same target and profile to find tests that newly
+//! fail and tests that got markedly slower.
+
+use crate::contract::{Outcome, TargetResults};
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+/// A test counts as slower if it took this many times as long as last time...
+pub const SLOWDOWN_RATIO: f64 = 1.5;
+
+/// ...and at least this many seconds more, so fast tests' jitter is ignored.
+pub const SLOWDOWN_MIN_SECONDS: f64 = 0.5;
+
+/// One test of a recorded run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TestRecord {
+    /// `classname::name`, as the runner reported it
+    pub name: String,
+    pub outcome: Outcome,
+    /// Seconds the test took
+    pub time: f64,
+}
+
+/// One recorded run of a service's contracts against one target.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunRecord {
+    /// When it ran (seconds since the Unix epoch)
+    pub timestamp: i64,
+    pub service: String,
+    /// Version of the service from service.yaml, if it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// `fake` or `real`
+    pub target: String,
+    /// Snapshot profile the fake was seeded with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    pub success: bool,
+    /// Seconds the whole run took
+    pub time: f64,
+    /// Per-test results, if the runner wrote any
+    #[serde(default)]
+    pub tests: Vec<TestRecord>,
+}
+
+impl RunRecord {
+    /// Record of a run that ended at `timestamp`.
+    pub fn new(
+        service: &str,
+        version: Option<&str>,
+        results: &TargetResults,
+        timestamp: i64,
+    ) -> Self {
+        Self {
+            timestamp,
+            service: service.to_string(),
+            version: version.map(str::to_string),
+            target: results.target.clone(),
+            profile: results.profile.clone(),
+            success: results.success,
+            time: results.time,
+            tests: results
+                .tests
+                .iter()
+                .map(|test| TestRecord {
+                    name: format!("{}::{}", test.classname, test.name),
+                    outcome: test.outcome,
+                    time: test.time,
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether `other` ran against the same target with the same profile.
+    pub fn same_run(&self, other: &RunRecord) -> bool {
+        self.target == other.target && self.profile == other.profile
+    }
+
+    /// Number of tests that ended with `outcome`.
+    pub fn count(&self, outcome: Outcome) -> usize {
+        self.tests.iter().filter(|t| t.outcome == outcome).count()
+    }
+}
+
+/// A test that took markedly longer than in the previous run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slowdown {
+    pub test: String,
+    /// Seconds it took in the previous run
+    pub before: f64,
+    pub after: f64,
+}
+
+/// What got worse (or better) since the previous run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Regressions {
+    /// Tests that passed last time and fail or error now
+    pub newly_failing: Vec<String>,
+    /// Tests that failed last time and pass now
+    pub fixed: Vec<String>,
+    /// Tests that got slower, by [`SLOWDOWN_RATIO`] and [`SLOWDOWN_MIN_SECONDS`]
+    pub slower: Vec<Slowdown>,
+}
+
+impl Regressions {
+    /// Compare a run with the previous one of the same target.
+    pub fn between(previous: &RunRecord, current: &RunRecord) -> Self {
+        let before: HashMap<&str, &TestRecord> = previous
+            .tests
+            .iter()
+            .map(|test| (test.name.as_str(), test))
+            .collect();
+        let failing = |outcome: Outcome| matches!(outcome, Outcome::Failed | Outcome::Error);
+        let mut regressions = Regressions::default();
+        for test in &current.tests {
+            let Some(last) = before.get(test.name.as_str()) else {
+                continue;
+            };
+            if last.outcome == Outcome::Passed && failing(test.outcome) {
+                regressions.newly_failing.push(test.name.clone());
+            } else if failing(last.outcome) && test.outcome == Outcome::Passed {
+                regressions.fixed.push(test.name.clone());
+            }
+            if test.outcome == Outcome::Passed
+                && last.outcome == Outcome::Passed
+                && test.time >= last.time * SLOWDOWN_RATIO
+                && test.time - last.time >= SLOWDOWN_MIN_SECONDS
+            {
+                regressions.slower.push(Slowdown {
+                    test: test.name.clone(),
+                    before: last.time,
+                    after: test.time,
+                });
+            }
+        }
+        regressions
+    }
+
+    /// Whether any test newly fails or got slower.
+    pub fn any(&self) -> bool {
+        !self.newly_failing.is_empty() || !self.slower.is_empty()
+    }
+}
+
+/// The contract run history in a directory, one file per service.
+#[derive(Debug, Clone)]
+pub struct ContractHistory {
+    dir: PathBuf,
+}
+
+impl ContractHistory {
+    /// History kept in `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Path of a service's history file.
+    pub fn path(&self, service: &str) -> PathBuf {
+        self.dir.join(format!("{}.jsonl", service))
+    }
+
+    /// Append a run to its service's history.
+    pub fn append(&self, record: &RunRecord) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(&record.service))?;
+        // One write per run, so parallel services never interleave lines
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Every recorded run of a service, oldest first. A service never run is
+    /// empty.
+    pub fn runs(&self, service: &str) -> Result<Vec<RunRecord>> {
+        let path = self.path(service);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let mut runs = Vec::new();
+        for (i, line) in BufReader::new(fs::File::open(&path)?).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str(&line)
+                .map_err(|e| Error::Other(format!("{} line {}: {}", path.display(), i + 1, e)))?;
+            runs.push(record);
+        }
+        Ok(runs)
+    }
+
+    /// The last recorded run of a service like `record`: the same target and
+    /// profile.
+    pub fn last(&self, record: &RunRecord) -> Result<Option<RunRecord>> {
+        Ok(self
+            .runs(&record.service)?
+            .into_iter()
+            .rev()
+            .find(|run| run.same_run(record)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract::TestCase;
+    use tempfile::tempdir;
+
+    fn results(target: &str, tests: &[(&str, Outcome, f64)]) -> TargetResults {
+        TargetResults {
+            target: target.to_string(),
+            profile: None,
+            success: tests
+                .iter()
+                .all(|(_, outcome, _)| *outcome != Outcome::Failed),
+            time: tests.iter().map(|(_, _, time)| time).sum(),
+            tests: tests
+                .iter()
+                .map(|(name, outcome, time)| TestCase {
+                    name: name.to_string(),
+                    classname: "test_issues.TestIssues".to_string(),
+                    time: *time,
+                    outcome: *outcome,
+                    message: None,
+                    details: None,
+                    retries: 0,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_last_run_of_the_same_target() {
+        let dir = tempdir().unwrap();
+        let history = ContractHistory::new(dir.path());
+        let first = RunRecord::new(
+            "github",
+            Some("1.0.0"),
+            &results("fake", &[("test_get", Outcome::Passed, 0.1)]),
+            100,
+        );
+        let real = RunRecord::new(
+            "github",
+            None,
+            &results("real", &[("test_get", Outcome::Passed, 0.9)]),
+            200,
+        );
+        history.append(&first).unwrap();
+        history.append(&real).unwrap();
+
+        assert_eq!(history.runs("github").unwrap(), vec![first.clone(), real]);
+        assert!(history.runs("slack").unwrap().is_empty());
+        let next = RunRecord::new("github", Some("1.1.0"), &results("fake", &[]), 300);
+        assert_eq!(history.last(&next).unwrap(), Some(first));
+    }
+
+    #[test]
+    fn test_regressions_between_runs() {
+        let previous = RunRecord::new(
+            "github",
+            None,
+            &results(
+                "fake",
+                &[
+                    ("test_create", Outcome::Passed, 0.2),
+                    ("test_close", Outcome::Failed, 0.1),
+                    ("test_list", Outcome::Passed, 1.0),
+                    ("test_get", Outcome::Passed, 0.01),
+                ],
+            ),
+            100,
+        );
+        let current = RunRecord::new(
+            "github",
+            None,
+            &results(
+                "fake",
+                &[
+                    ("test_create", Outcome::Error, 0.2),
+                    ("test_close", Outcome::Passed, 0.1),
+                    ("test_list", Outcome::Passed, 2.0),
+                    // Three times as slow, but by too little to matter
+                    ("test_get", Outcome::Passed, 0.03),
+                    ("test_new", Outcome::Failed, 0.1),
+                ],
+            ),
+            200,
+        );
+
+        let regressions = Regressions::between(&previous, &current);
+        assert_eq!(
+            regressions.newly_failing,
+            vec!["test_issues.TestIssues::test_create"]
+        );
+        assert_eq!(
+            regressions.fixed,
+            vec!["test_issues.TestIssues::test_close"]
+        );
+        assert_eq!(
+            regressions.slower,
+            vec![Slowdown {
+                test: "test_issues.TestIssues::test_list".to_string(),
+                before: 1.0,
+                after: 2.0,
+            }]
+        );
+        assert!(regressions.any());
+        assert!(!Regressions::between(&current, &current).any());
+    }
+}
@@ -0,0 +1,234 @@
+//! Crash notification hooks, so long-running experiments alert someone
+//! instead of degrading silently.
+//!
+//! With the `crash_hook` setting (a shell command) or `crash_webhook` (a URL),
+//! an [`Alert`] goes out when a service is found to have crashed, i.e. its
+//! process died without `doubleagent stop`, or when it fails
+//! [`FAILURE_STREAK`] health checks in a row. Crashes are noticed by `status`,
+//! `status --watch`, `dashboard` and `up`, and failed checks by all but `up`.
+//! The command runs under `sh -c` with the alert in `DOUBLEAGENT_*` variables
+//! (see [`Alert::env`]); the webhook is posted the alert as JSON. Each is
+//! given [`HOOK_TIMEOUT`].
+
+use crate::config::Config;
+use crate::{Error, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+/// Failed health checks in a row that raise an alert.
+pub const FAILURE_STREAK: u32 = 3;
+
+/// Lines of a crashed service's output sent with its alert.
+pub const LOG_LINES: usize = 20;
+
+/// How long a hook command or webhook may take before it is given up on.
+pub const HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What happened to a service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    /// Its process died without being stopped
+    Crashed,
+    /// It failed [`FAILURE_STREAK`] health checks in a row
+    Unhealthy,
+}
+
+impl Event {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Event::Crashed => "crashed",
+            Event::Unhealthy => "unhealthy",
+        }
+    }
+}
+
+/// What the hooks are told.
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub event: Event,
+    pub service: String,
+    pub workspace: String,
+    pub pid: u32,
+    pub port: u16,
+    /// What went wrong, for people
+    pub message: String,
+    /// The last lines of the service's output
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub log_tail: Vec<String>,
+}
+
+impl Alert {
+    /// Environment the `crash_hook` command runs with.
+    pub fn env(&self) -> BTreeMap<String, String> {
+        [
+            ("DOUBLEAGENT_EVENT", self.event.as_str().to_string()),
+            ("DOUBLEAGENT_SERVICE", self.service.clone()),
+            ("DOUBLEAGENT_WORKSPACE", self.workspace.clone()),
+            ("DOUBLEAGENT_PID", self.pid.to_string()),
+            ("DOUBLEAGENT_PORT", self.port.to_string()),
+            ("DOUBLEAGENT_MESSAGE", self.message.clone()),
+            ("DOUBLEAGENT_LOG_TAIL", self.log_tail.join("\n")),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect()
+    }
+}
+
+/// Whether any crash hook is configured.
+pub fn configured(config: &Config) -> bool {
+    config.crash_hook.is_some() || config.crash_webhook.is_some()
+}
+
+/// Send an alert to the configured hooks, trying each even if another fails.
+pub async fn notify(config: &Config, alert: &Alert) -> Result<()> {
+    let mut errors = Vec::new();
+    if let Some(command) = &config.crash_hook {
+        if let Err(e) = run_command(command, alert).await {
+            errors.push(format!("crash_hook: {}", e));
+        }
+    }
+    if let Some(url) = &config.crash_webhook {
+        if let Err(e) = post(url, alert).await {
+            errors.push(format!("crash_webhook: {}", e));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Other(errors.join("; ")))
+    }
+}
+
+async fn run_command(command: &str, alert: &Alert) -> Result<()> {
+    let mut child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(alert.env())
+        .kill_on_drop(true)
+        .spawn()?;
+    let status = tokio::time::timeout(HOOK_TIMEOUT, child.wait())
+        .await
+        .map_err(|_| {
+            Error::Other(format!(
+                "'{}' took longer than {}s",
+                command,
+                HOOK_TIMEOUT.as_secs()
+            ))
+        })??;
+    if !status.success() {
+        return Err(Error::Other(format!(
+            "'{}' exited with {}",
+            command, status
+        )));
+    }
+    Ok(())
+}
+
+/// Post `body` to `url` as JSON.
+pub(crate) async fn post(url: &str, body: &impl Serialize) -> Result<()> {
+    let response = reqwest::Client::new()
+        .post(url)
+        .timeout(HOOK_TIMEOUT)
+        .json(body)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(Error::Other(format!(
+            "{} answered {}",
+            url,
+            response.status()
+        )));
+    }
+    Ok(())
+}
+
+/// The last `count` lines of a service's log, or none if it can't be read.
+pub fn log_tail(path: &Path, count: usize) -> Vec<String> {
+    // Enough for the lines of any sensible log
+    const TAIL_BYTES: u64 = 16 * 1024;
+    let mut lines = tail_lines(path, TAIL_BYTES);
+    lines.drain(..lines.len().saturating_sub(count));
+    lines
+}
+
+/// The whole lines in the last `bytes` of a log, or none if it can't be read.
+pub fn tail_lines(path: &Path, bytes: u64) -> Vec<String> {
+    let Ok(mut file) = File::open(path) else {
+        return Vec::new();
+    };
+    let length = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let start = length.saturating_sub(bytes);
+    let mut content = Vec::new();
+    if file.seek(SeekFrom::Start(start)).is_err() || file.read_to_end(&mut content).is_err() {
+        return Vec::new();
+    }
+    let text = String::from_utf8_lossy(&content);
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+    if start > 0 && !lines.is_empty() {
+        // The first line was cut partway through
+        lines.remove(0);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alert() -> Alert {
+        Alert {
+            event: Event::Crashed,
+            service: "github".to_string(),
+            workspace: "default".to_string(),
+            pid: 4242,
+            port: 8080,
+            message: "github exited".to_string(),
+            log_tail: vec!["Traceback".to_string(), "KeyError: 'id'".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_alert() {
+        let alert = alert();
+        let env = alert.env();
+        assert_eq!(env["DOUBLEAGENT_EVENT"], "crashed");
+        assert_eq!(env["DOUBLEAGENT_LOG_TAIL"], "Traceback\nKeyError: 'id'");
+        let json = serde_json::to_value(&alert).unwrap();
+        assert_eq!(json["event"], "crashed");
+        assert_eq!(json["port"], 8080);
+    }
+
+    #[tokio::test]
+    async fn test_notify_runs_the_command() {
+        let temp = tempfile::tempdir().unwrap();
+        let out = temp.path().join("out");
+        let mut config = Config::builder().root(temp.path()).build().unwrap();
+        config.crash_hook = Some(format!(
+            "echo \"$DOUBLEAGENT_EVENT $DOUBLEAGENT_SERVICE\" > {}",
+            out.display()
+        ));
+        notify(&config, &alert()).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "crashed github\n");
+
+        config.crash_hook = Some("exit 3".to_string());
+        assert!(notify(&config, &alert()).await.is_err());
+    }
+
+    #[test]
+    fn test_log_tail() {
+        let temp = tempfile::tempdir().unwrap();
+        let log = temp.path().join("github.log");
+        std::fs::write(&log, "one\ntwo\nthree\n").unwrap();
+        assert_eq!(log_tail(&log, 2), ["two", "three"]);
+        assert!(log_tail(&temp.path().join("missing.log"), 2).is_empty());
+        // A line cut by the start of the tail is left out
+        assert_eq!(tail_lines(&log, 8), ["three"]);
+        assert_eq!(tail_lines(&log, 100), ["one", "two", "three"]);
+    }
+}
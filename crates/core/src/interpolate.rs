@@ -0,0 +1,304 @@
+//! Environment variable interpolation for configuration files.
+//!
+//! String values in service.yaml and doubleagent.yaml may reference environment
+//! variables as `${VAR}` or `${VAR:-default}`. Use `$$` for a literal `$`.
+//! Interpolation runs on parsed YAML values, so substituted text can never
+//! change the structure of the document.
+//!
+//! A variable set to an empty string is set: `${VAR}` becomes empty, and only
+//! `${VAR:-default}` falls back to the default for it.
+
+use crate::format::Format;
+use crate::{Error, Result};
+use serde::de::DeserializeOwned;
+use serde_yaml::value::{Tag, TaggedValue};
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Tag of the values [`parse_config_deferred`] leaves in place of strings that
+/// reference unset variables.
+const UNSET_TAG: &str = "unset";
+
+/// Parse YAML content, interpolating environment variables in string values.
+///
+/// `source` is only used to make error messages point at the right file.
+pub fn from_yaml_str<T: DeserializeOwned>(content: &str, source: &Path) -> Result<T> {
+    from_value(serde_yaml::from_str(content)?, source)
+}
+
+/// Parse YAML, TOML or JSON content (chosen by the extension of `source`),
+/// interpolating environment variables in string values.
+pub fn from_config_str<T: DeserializeOwned>(content: &str, source: &Path) -> Result<T> {
+    from_value(Format::from_path(source).parse(content, source)?, source)
+}
+
+/// Parse YAML, TOML or JSON content like [`from_config_str`], but leave a
+/// marker in place of each string referencing unset variables instead of
+/// failing, so only what reads those values fails (see [`ensure_set`]).
+pub fn parse_config_deferred(content: &str, source: &Path) -> Result<Value> {
+    let mut value = Format::from_path(source).parse(content, source)?;
+    let lookup = |name: &str| std::env::var(name).ok();
+    interpolate_value(&mut value, &lookup, &mut Vec::new(), Some(source))
+        .map_err(|e| Error::Other(format!("{}: {}", source.display(), e)))?;
+    Ok(value)
+}
+
+/// Fail if `value` holds strings that [`parse_config_deferred`] couldn't
+/// interpolate, naming the files and the unset variables.
+pub fn ensure_set(value: &Value) -> Result<()> {
+    let mut unset = BTreeMap::new();
+    find_unset(value, &mut unset);
+    if unset.is_empty() {
+        return Ok(());
+    }
+    let lines: Vec<String> = unset
+        .into_iter()
+        .map(|(source, mut missing)| {
+            missing.sort();
+            missing.dedup();
+            format!(
+                "{} references environment variables that are not set: {}",
+                source,
+                missing.join(", ")
+            )
+        })
+        .collect();
+    Err(unset_error(&lines.join("\n")))
+}
+
+fn from_value<T: DeserializeOwned>(mut value: Value, source: &Path) -> Result<T> {
+    let mut missing = Vec::new();
+    interpolate_value(
+        &mut value,
+        &|name| std::env::var(name).ok(),
+        &mut missing,
+        None,
+    )
+    .map_err(|e| Error::Other(format!("{}: {}", source.display(), e)))?;
+
+    if !missing.is_empty() {
+        missing.sort();
+        missing.dedup();
+        return Err(unset_error(&format!(
+            "{} references environment variables that are not set: {}",
+            source.display(),
+            missing.join(", ")
+        )));
+    }
+
+    Ok(serde_yaml::from_value(value)?)
+}
+
+fn unset_error(problem: &str) -> Error {
+    Error::Other(format!(
+        "{}\nSet them or provide a default with ${{VAR:-default}}.",
+        problem
+    ))
+}
+
+/// Interpolate all string values in place, collecting unset variable names,
+/// or, with a `deferred` source, marking the strings that reference them.
+fn interpolate_value(
+    value: &mut Value,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    missing: &mut Vec<String>,
+    deferred: Option<&Path>,
+) -> std::result::Result<(), String> {
+    match value {
+        Value::String(s) => {
+            let mut unset = Vec::new();
+            let output = interpolate_str(s, lookup, &mut unset)?;
+            match deferred {
+                Some(source) if !unset.is_empty() => *value = unset_marker(source, unset),
+                _ => {
+                    *s = output;
+                    missing.extend(unset);
+                }
+            }
+        }
+        Value::Sequence(items) => {
+            for item in items {
+                interpolate_value(item, lookup, missing, deferred)?;
+            }
+        }
+        Value::Mapping(mapping) => {
+            for (_, item) in mapping.iter_mut() {
+                interpolate_value(item, lookup, missing, deferred)?;
+            }
+        }
+        Value::Tagged(tagged) => {
+            interpolate_value(&mut tagged.value, lookup, missing, deferred)?;
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+    Ok(())
+}
+
+fn unset_marker(source: &Path, variables: Vec<String>) -> Value {
+    let mut marker = Mapping::new();
+    marker.insert("file".into(), source.display().to_string().into());
+    marker.insert("variables".into(), variables.into());
+    Value::Tagged(Box::new(TaggedValue {
+        tag: Tag::new(UNSET_TAG),
+        value: Value::Mapping(marker),
+    }))
+}
+
+fn find_unset(value: &Value, unset: &mut BTreeMap<String, Vec<String>>) {
+    match value {
+        Value::Tagged(tagged) if tagged.tag == UNSET_TAG => {
+            let file = tagged.value.get("file").and_then(Value::as_str);
+            let variables = tagged.value.get("variables").and_then(Value::as_sequence);
+            if let (Some(file), Some(variables)) = (file, variables) {
+                unset.entry(file.to_string()).or_default().extend(
+                    variables
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(str::to_string),
+                );
+            }
+        }
+        Value::Tagged(tagged) => find_unset(&tagged.value, unset),
+        Value::Sequence(items) => items.iter().for_each(|item| find_unset(item, unset)),
+        Value::Mapping(mapping) => mapping.values().for_each(|item| find_unset(item, unset)),
+        Value::String(_) | Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+/// Interpolate `${VAR}` and `${VAR:-default}` references in a single string.
+///
+/// Unset variables without a default are pushed to `missing` and replaced with
+/// an empty string; a variable set to an empty string counts as set. Returns
+/// an error for malformed references.
+pub fn interpolate_str(
+    input: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    missing: &mut Vec<String>,
+) -> std::result::Result<String, String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(pos) = rest.find('$') {
+        output.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        if let Some(after) = rest.strip_prefix("$$") {
+            output.push('$');
+            rest = after;
+            continue;
+        }
+
+        let Some(after) = rest.strip_prefix("${") else {
+            // A lone `$` is kept as-is
+            output.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+
+        let end = after
+            .find('}')
+            .ok_or_else(|| format!("unterminated variable reference in '{}'", input))?;
+        let expr = &after[..end];
+        rest = &after[end + 1..];
+
+        let (name, default) = match expr.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expr, None),
+        };
+
+        if !is_valid_name(name) {
+            return Err(format!("invalid variable name '{}' in '{}'", name, input));
+        }
+
+        match (lookup(name), default) {
+            (Some(value), Some(default)) if value.is_empty() => output.push_str(default),
+            (Some(value), _) => output.push_str(&value),
+            (None, Some(default)) => output.push_str(default),
+            (None, None) => missing.push(name.to_string()),
+        }
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Whether `name` can name a variable: letters, digits and `_`, not
+/// starting with a digit.
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn render(input: &str) -> std::result::Result<(String, Vec<String>), String> {
+        let vars: HashMap<&str, &str> = [("HOME", "/home/agent"), ("EMPTY", "")].into();
+        let lookup = |name: &str| vars.get(name).map(|v| v.to_string());
+        let mut missing = Vec::new();
+        let output = interpolate_str(input, &lookup, &mut missing)?;
+        Ok((output, missing))
+    }
+
+    #[test]
+    fn test_interpolates_variables_and_defaults() {
+        let (output, missing) = render("${HOME}/data:${CI_JOB_ID:-local}").unwrap();
+        assert_eq!(output, "/home/agent/data:local");
+        assert!(missing.is_empty());
+    }
+
+    #[test]
+    fn test_empty_variable_uses_default() {
+        let (output, _) = render("${EMPTY:-fallback}").unwrap();
+        assert_eq!(output, "fallback");
+    }
+
+    #[test]
+    fn test_empty_variable_is_set() {
+        let (output, missing) = render("[${EMPTY}]").unwrap();
+        assert_eq!(output, "[]");
+        assert!(missing.is_empty());
+    }
+
+    #[test]
+    fn test_escapes_and_lone_dollar() {
+        let (output, _) = render("$$HOME costs $5").unwrap();
+        assert_eq!(output, "$HOME costs $5");
+    }
+
+    #[test]
+    fn test_reports_missing_variables() {
+        let (_, missing) = render("${CI_JOB_ID}-${OTHER}").unwrap();
+        assert_eq!(missing, vec!["CI_JOB_ID", "OTHER"]);
+    }
+
+    #[test]
+    fn test_rejects_malformed_references() {
+        assert!(render("${HOME").is_err());
+        assert!(render("${1BAD}").is_err());
+    }
+
+    #[test]
+    fn test_from_yaml_str_names_file_and_variables() {
+        let yaml = "env:\n  TOKEN: ${DOUBLEAGENT_TEST_SURELY_UNSET_VAR}\n";
+        let err = from_yaml_str::<Value>(yaml, Path::new("service.yaml")).unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("service.yaml"));
+        assert!(msg.contains("DOUBLEAGENT_TEST_SURELY_UNSET_VAR"));
+    }
+
+    #[test]
+    fn test_deferred_parse_fails_only_where_unset_values_are_read() {
+        let yaml = "base_port: 9000\nservices:\n  github:\n    env:\n      TOKEN: ${DOUBLEAGENT_TEST_SURELY_UNSET_VAR}\n";
+        let value = parse_config_deferred(yaml, Path::new("doubleagent.yaml")).unwrap();
+
+        assert!(ensure_set(&value["base_port"]).is_ok());
+        let msg = ensure_set(&value["services"]).unwrap_err().to_string();
+        assert!(msg.contains("doubleagent.yaml"));
+        assert!(msg.contains("DOUBLEAGENT_TEST_SURELY_UNSET_VAR"));
+    }
+}
@@ -3,15 +3,60 @@
 //! This crate provides the core functionality for managing fake services,
 //! including process management, service registry, and git operations.
 
+pub mod artifacts;
+pub mod audit;
+pub mod compliance;
 pub mod config;
+pub mod conformance;
+pub mod contract;
+pub mod control;
+pub mod coverage;
+pub mod dates;
+pub mod differential;
+pub mod doctor;
 pub mod error;
+pub mod extends;
+pub mod format;
+pub mod fuzz;
+pub mod generate;
 pub mod git;
+pub mod golden;
+pub mod health;
+pub mod history;
+pub mod hooks;
+pub mod interpolate;
+pub mod manifest;
+pub mod metrics;
+pub mod migrate;
 pub mod mise;
+pub mod namespace;
+pub mod openapi;
+pub mod operations;
+pub mod ports;
 pub mod process;
+pub mod redact;
+pub mod references;
+pub mod reload;
+pub mod report;
+pub mod request_log;
+pub mod rows;
+pub mod secrets;
+pub mod seed;
 pub mod service;
+pub mod settings;
+pub mod snapshot;
+pub mod tags;
+pub mod telemetry;
+pub mod template;
+pub mod validate;
+pub mod workspace;
 
 // Re-exports for convenience
-pub use config::Config;
+pub use config::{Config, ConfigBuilder};
+pub use control::{ControlClient, RetryPolicy, SeedMode, SeedReport};
 pub use error::{Error, Result};
-pub use process::{ProcessManager, ServiceInfo};
-pub use service::{ContractsConfig, ServerConfig, ServiceDefinition, ServiceRegistry};
+pub use process::{HealthChange, ProcessManager, SeedRecord, ServiceInfo, StartOptions};
+pub use service::{
+    ContractSuiteConfig, ContractsConfig, RealApiConfig, SdkConfig, ServerConfig,
+    ServiceDefinition, ServiceRegistry,
+};
@@ -0,0 +1,766 @@
+//! Desired-state environment manifests for `doubleagent apply -f`.
+//!
+//! A manifest lists the services an environment should run, with optional
+//! ports, seed data, environment overrides and tags. [`plan`] compares it with
+//! the running services and returns the actions needed to converge.
+//!
+//! The `services` key of doubleagent.yaml is read the same way by `up` and
+//! `down`: either a list of names or a mapping with the same per-service
+//! settings as a manifest.
+//!
+//! A top-level `seeds` key maps services to an ordered list of seeds, applied
+//! one after another once the service is healthy:
+//!
+//! ```yaml
+//! seeds:
+//!   github:
+//!     - {fixture: base}
+//!     - {snapshot: prod-small}
+//!     - ./seeds/github-extra.yaml
+//! ```
+
+use crate::extends;
+use crate::health::HealthOverrides;
+use crate::interpolate;
+use crate::ports::PortAllocator;
+use crate::process::ServiceInfo;
+use crate::redact::RedactionRule;
+use crate::reload::{self, DefinitionStatus};
+use crate::service::{ServiceDefinition, ServiceRegistry};
+use crate::tags::Tags;
+use crate::{Error, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// An environment manifest.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Desired services keyed by name
+    #[serde(default)]
+    pub services: BTreeMap<String, ManifestService>,
+    /// Seeds applied in order after start, keyed by service name
+    #[serde(default)]
+    pub seeds: BTreeMap<String, Vec<SeedSetting>>,
+    /// Directory containing the manifest, used to resolve relative paths
+    #[serde(skip)]
+    pub base_dir: PathBuf,
+}
+
+/// Desired settings for one service.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestService {
+    /// Expected service version (checked against service.yaml)
+    pub version: Option<String>,
+    /// Port to run on (default: next free port from base_port)
+    pub port: Option<u16>,
+    /// Fixture from the service's fixtures/ directory to seed after start
+    pub fixture: Option<String>,
+    /// Seed to load after start: a file relative to the manifest, or
+    /// `{snapshot: <profile>}`, `{fixture: <name>}` or `{file: <path>}`
+    pub seed: Option<SeedSetting>,
+    /// Environment variables set on top of the service's server.env
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Tags attached to the running service
+    #[serde(default)]
+    pub tags: Tags,
+    /// Health check and startup timing overrides
+    #[serde(default)]
+    pub health: HealthOverrides,
+    /// Redaction rules applied to snapshots, after those in service.yaml
+    #[serde(default)]
+    pub redaction: Vec<RedactionRule>,
+}
+
+impl Manifest {
+    /// Load a manifest from a YAML file, interpolating environment variables.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            Error::Other(format!("Failed to read manifest {}: {}", path.display(), e))
+        })?;
+        let mut manifest: Manifest = interpolate::from_yaml_str(&content, path)?;
+        manifest.base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        manifest.check(path)?;
+        Ok(manifest)
+    }
+
+    /// Load the services declared in a project config file (doubleagent.yaml,
+    /// or its TOML/JSON equivalent), including those of the configs it `extends`.
+    ///
+    /// Other top-level keys are ignored; they are settings handled elsewhere.
+    pub fn from_project_config(path: &Path, cache_dir: &Path) -> Result<Self> {
+        let project: Option<ProjectServices> =
+            serde_yaml::from_value(extends::load(path, cache_dir)?)?;
+
+        let (services, seeds) = match project {
+            Some(project) => (project.services, project.seeds),
+            None => (ServiceList::default(), BTreeMap::new()),
+        };
+        let services = match services {
+            ServiceList::Names(names) => names
+                .into_iter()
+                .map(|name| (name, ManifestService::default()))
+                .collect(),
+            ServiceList::Detailed(services) => services
+                .into_iter()
+                .map(|(name, service)| (name, service.unwrap_or_default()))
+                .collect(),
+        };
+
+        let manifest = Manifest {
+            services,
+            seeds,
+            base_dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        };
+        manifest.check(path)?;
+        Ok(manifest)
+    }
+
+    fn check(&self, path: &Path) -> Result<()> {
+        for (name, service) in &self.services {
+            if service.fixture.is_some() && service.seed.is_some() {
+                return Err(Error::Other(format!(
+                    "{}: service '{}' sets both 'fixture' and 'seed', pick one",
+                    path.display(),
+                    name
+                )));
+            }
+        }
+        for (name, seeds) in &self.seeds {
+            let Some(service) = self.services.get(name) else {
+                return Err(Error::Other(format!(
+                    "{}: 'seeds' lists '{}', which isn't one of its services",
+                    path.display(),
+                    name
+                )));
+            };
+            if !seeds.is_empty() && (service.fixture.is_some() || service.seed.is_some()) {
+                return Err(Error::Other(format!(
+                    "{}: service '{}' has a 'seed' and is listed under 'seeds', pick one",
+                    path.display(),
+                    name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The seed sources declared for a service, in the order to apply them.
+    ///
+    /// Empty if the service has no seed.
+    pub fn seed_sources(&self, name: &str) -> Vec<SeedSource> {
+        let Some(service) = self.services.get(name) else {
+            return Vec::new();
+        };
+        if let Some(fixture) = &service.fixture {
+            return vec![SeedSource::Fixture(fixture.clone())];
+        }
+        match &service.seed {
+            Some(seed) => vec![self.source(seed)],
+            None => self
+                .seeds
+                .get(name)
+                .into_iter()
+                .flatten()
+                .map(|seed| self.source(seed))
+                .collect(),
+        }
+    }
+
+    fn source(&self, seed: &SeedSetting) -> SeedSource {
+        match seed {
+            SeedSetting::Path(path) | SeedSetting::Source(SeedFrom::File(path)) => {
+                SeedSource::File(self.base_dir.join(path))
+            }
+            SeedSetting::Source(SeedFrom::Snapshot(profile)) => {
+                SeedSource::Snapshot(profile.clone())
+            }
+            SeedSetting::Source(SeedFrom::Fixture(name)) => SeedSource::Fixture(name.clone()),
+        }
+    }
+}
+
+/// How a service's `seed` is written.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum SeedSetting {
+    /// A seed file, relative to the manifest
+    Path(PathBuf),
+    /// A source named by its kind
+    Source(SeedFrom),
+}
+
+/// A seed source named by its kind, e.g. `{snapshot: prod-small}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum SeedFrom {
+    /// A stored snapshot profile
+    Snapshot(String),
+    /// A named fixture in the service's fixtures/ directory
+    Fixture(String),
+    /// A seed file, relative to the manifest
+    File(PathBuf),
+}
+
+/// The part of doubleagent.yaml that declares services.
+#[derive(Deserialize)]
+struct ProjectServices {
+    #[serde(default)]
+    services: ServiceList,
+    #[serde(default)]
+    seeds: BTreeMap<String, Vec<SeedSetting>>,
+}
+
+/// Services as a plain list of names, or keyed by name with settings.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ServiceList {
+    Names(Vec<String>),
+    Detailed(BTreeMap<String, Option<ManifestService>>),
+}
+
+impl Default for ServiceList {
+    fn default() -> Self {
+        ServiceList::Names(Vec::new())
+    }
+}
+
+/// Where seed data for a service comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SeedSource {
+    /// A named fixture in the service's fixtures/ directory
+    Fixture(String),
+    /// A seed file on disk
+    File(PathBuf),
+    /// A stored snapshot profile
+    Snapshot(String),
+}
+
+impl fmt::Display for SeedSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SeedSource::Fixture(name) => write!(f, "fixture:{}", name),
+            SeedSource::File(path) => write!(f, "file:{}", path.display()),
+            SeedSource::Snapshot(profile) => write!(f, "snapshot:{}", profile),
+        }
+    }
+}
+
+impl FromStr for SeedSource {
+    type Err = Error;
+
+    /// Parse the `kind:value` form written by `Display`, as recorded for
+    /// running services.
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            Some(("fixture", name)) => Ok(SeedSource::Fixture(name.to_string())),
+            Some(("file", path)) => Ok(SeedSource::File(PathBuf::from(path))),
+            Some(("snapshot", profile)) => Ok(SeedSource::Snapshot(profile.to_string())),
+            _ => Err(Error::Other(format!(
+                "Invalid seed source '{}', expected fixture:, file: or snapshot:",
+                s
+            ))),
+        }
+    }
+}
+
+/// Separates the sources of a seed applied in several steps, as recorded for
+/// running services.
+const SOURCE_SEPARATOR: &str = " + ";
+
+impl SeedSource {
+    /// Record a list of sources applied in order, e.g.
+    /// `fixture:base + snapshot:prod-small`.
+    pub fn join(sources: &[SeedSource]) -> String {
+        sources
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(SOURCE_SEPARATOR)
+    }
+
+    /// Parse sources recorded with [`SeedSource::join`].
+    pub fn parse_list(s: &str) -> Result<Vec<SeedSource>> {
+        s.split(SOURCE_SEPARATOR).map(str::parse).collect()
+    }
+}
+
+/// The definitions of a manifest's services, loaded from `path`, checked
+/// against the versions it pins.
+///
+/// With `install`, services that aren't installed are fetched. Without it
+/// nothing is fetched: they are returned separately, as services converging
+/// would install.
+pub fn resolve_definitions(
+    manifest: &Manifest,
+    path: &Path,
+    registry: &ServiceRegistry,
+    install: bool,
+) -> Result<(HashMap<String, ServiceDefinition>, Vec<String>)> {
+    let mut definitions = HashMap::new();
+    let mut uninstalled = Vec::new();
+    for (name, desired) in &manifest.services {
+        if !install && registry.service_dir(name).is_none() {
+            uninstalled.push(name.clone());
+            continue;
+        }
+        let service = registry.get_or_install(name, install)?;
+        if let Some(version) = &desired.version {
+            if service.version.as_deref() != Some(version.as_str()) {
+                return Err(Error::Other(format!(
+                    "{} requires {} version {}, but {} is installed",
+                    path.display(),
+                    name,
+                    version,
+                    service.version.as_deref().unwrap_or("(unversioned)")
+                )));
+            }
+        }
+        definitions.insert(name.clone(), service);
+    }
+    Ok((definitions, uninstalled))
+}
+
+/// A step needed to converge on the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Start a service that is not running
+    Start { service: String, port: u16 },
+    /// Restart a running service to apply changes
+    Restart {
+        service: String,
+        port: u16,
+        reason: String,
+    },
+    /// Reset and seed a service, from each source in order
+    Seed {
+        service: String,
+        sources: Vec<SeedSource>,
+    },
+    /// Stop a running service that is not in the manifest
+    Stop { service: String },
+    /// A service already matching the manifest
+    Unchanged { service: String },
+}
+
+/// Compute the actions needed to make the running services match the manifest.
+///
+/// Services without an explicit port get the next port from `ports`, which
+/// never hands out ports used by explicit entries or running services.
+/// Services the manifest doesn't list are stopped before anything starts.
+pub fn plan(
+    manifest: &Manifest,
+    running: &HashMap<String, ServiceInfo>,
+    ports: &mut PortAllocator,
+) -> Result<Vec<Action>> {
+    let mut actions = Vec::new();
+
+    // Ports that are spoken for and must not be handed out automatically
+    for port in manifest.services.values().filter_map(|s| s.port) {
+        ports.reserve(port);
+    }
+    for (name, info) in running {
+        if manifest.services.contains_key(name) {
+            ports.reserve(info.port);
+        }
+    }
+
+    // Stops come first, freeing the ports of services the manifest dropped
+    let mut extra: Vec<&String> = running
+        .keys()
+        .filter(|name| !manifest.services.contains_key(*name))
+        .collect();
+    extra.sort();
+    for name in extra {
+        actions.push(Action::Stop {
+            service: name.clone(),
+        });
+    }
+
+    for (name, desired) in &manifest.services {
+        let sources = manifest.seed_sources(name);
+
+        let (restarted, start_action) = match running.get(name) {
+            None => {
+                let port = match desired.port {
+                    Some(port) => port,
+                    None => ports.allocate()?,
+                };
+                (
+                    true,
+                    Some(Action::Start {
+                        service: name.clone(),
+                        port,
+                    }),
+                )
+            }
+            Some(info) => {
+                let port = desired.port.unwrap_or(info.port);
+                match restart_reason(desired, info, port) {
+                    Some(reason) => (
+                        true,
+                        Some(Action::Restart {
+                            service: name.clone(),
+                            port,
+                            reason,
+                        }),
+                    ),
+                    None => (false, None),
+                }
+            }
+        };
+
+        let needs_seed = !sources.is_empty()
+            && (restarted
+                || running
+                    .get(name)
+                    .is_some_and(|info| info.seed.as_deref() != Some(&SeedSource::join(&sources))));
+
+        let changed = start_action.is_some() || needs_seed;
+        actions.extend(start_action);
+        if needs_seed {
+            actions.push(Action::Seed {
+                service: name.clone(),
+                sources,
+            });
+        }
+        if !changed {
+            actions.push(Action::Unchanged {
+                service: name.clone(),
+            });
+        }
+    }
+
+    Ok(actions)
+}
+
+/// Why a running service must be restarted to match the manifest, if at all.
+fn restart_reason(desired: &ManifestService, info: &ServiceInfo, port: u16) -> Option<String> {
+    if port != info.port {
+        return Some(format!("port {} -> {}", info.port, port));
+    }
+    if desired.env != info.env_overrides {
+        return Some("env changed".to_string());
+    }
+    if reload::definition_status(info) == DefinitionStatus::Changed {
+        return Some("service.yaml changed".to_string());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ports::PortRange;
+
+    fn info(port: u16) -> ServiceInfo {
+        ServiceInfo {
+            pid: 1,
+            port,
+            started_at: "0".to_string(),
+            service_path: "/nonexistent".to_string(),
+            config_fingerprint: None,
+            tags: Tags::new(),
+            env_overrides: BTreeMap::new(),
+            seed: None,
+            seed_history: Vec::new(),
+            namespaces: Vec::new(),
+            restarts: 0,
+            health_history: Vec::new(),
+            failed_checks: 0,
+            project_config: None,
+            project_fingerprint: None,
+        }
+    }
+
+    fn allocator(used: &[u16]) -> PortAllocator {
+        PortAllocator::new(PortRange::default(), 8080, used.iter().copied().collect())
+    }
+
+    fn manifest(yaml: &str) -> Manifest {
+        let mut manifest: Manifest = serde_yaml::from_str(yaml).unwrap();
+        manifest.base_dir = PathBuf::from("/env");
+        manifest
+    }
+
+    #[test]
+    fn test_plan_starts_missing_and_stops_extra() {
+        let manifest = manifest("services:\n  github: {}\n  slack:\n    port: 8080\n");
+        let running = HashMap::from([("descope".to_string(), info(9000))]);
+
+        let actions = plan(&manifest, &running, &mut allocator(&[])).unwrap();
+
+        assert_eq!(
+            actions,
+            vec![
+                Action::Stop {
+                    service: "descope".to_string()
+                },
+                Action::Start {
+                    service: "github".to_string(),
+                    port: 8081
+                },
+                Action::Start {
+                    service: "slack".to_string(),
+                    port: 8080
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_stops_before_starting_on_a_freed_port() {
+        let manifest = manifest("services:\n  slack:\n    port: 9000\n");
+        let running = HashMap::from([
+            ("descope".to_string(), info(9000)),
+            ("airtable".to_string(), info(9001)),
+        ]);
+
+        let actions = plan(&manifest, &running, &mut allocator(&[])).unwrap();
+
+        assert_eq!(
+            actions,
+            vec![
+                Action::Stop {
+                    service: "airtable".to_string()
+                },
+                Action::Stop {
+                    service: "descope".to_string()
+                },
+                Action::Start {
+                    service: "slack".to_string(),
+                    port: 9000
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_restarts_on_env_change_and_reseeds() {
+        let manifest = manifest(
+            "services:\n  github:\n    env:\n      LOG_LEVEL: debug\n    seed: seeds/github.yaml\n",
+        );
+        let running = HashMap::from([("github".to_string(), info(8080))]);
+
+        let actions = plan(&manifest, &running, &mut allocator(&[])).unwrap();
+
+        assert_eq!(
+            actions,
+            vec![
+                Action::Restart {
+                    service: "github".to_string(),
+                    port: 8080,
+                    reason: "env changed".to_string()
+                },
+                Action::Seed {
+                    service: "github".to_string(),
+                    sources: vec![SeedSource::File(PathBuf::from("/env/seeds/github.yaml"))]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_leaves_matching_services_alone() {
+        let manifest = manifest("services:\n  github:\n    fixture: startup\n");
+        let mut github = info(8080);
+        github.seed = Some("fixture:startup".to_string());
+        let running = HashMap::from([("github".to_string(), github)]);
+
+        let actions = plan(&manifest, &running, &mut allocator(&[])).unwrap();
+
+        assert_eq!(
+            actions,
+            vec![Action::Unchanged {
+                service: "github".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_plan_skips_reserved_ports() {
+        let manifest = manifest("services:\n  github: {}\n");
+        let actions = plan(&manifest, &HashMap::new(), &mut allocator(&[8080, 8081])).unwrap();
+
+        assert_eq!(
+            actions,
+            vec![Action::Start {
+                service: "github".to_string(),
+                port: 8082
+            }]
+        );
+    }
+
+    #[test]
+    fn test_project_config_accepts_list_or_mapping() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("doubleagent.yaml");
+
+        fs::write(&path, "services:\n  - github\n  - slack\nbase_port: 9000\n").unwrap();
+        let manifest = Manifest::from_project_config(&path, dir.path()).unwrap();
+        assert_eq!(
+            manifest.services.keys().collect::<Vec<_>>(),
+            vec!["github", "slack"]
+        );
+
+        fs::write(
+            &path,
+            "services:\n  github:\n    port: 9001\n    fixture: startup\n  slack:\n",
+        )
+        .unwrap();
+        let manifest = Manifest::from_project_config(&path, dir.path()).unwrap();
+        assert_eq!(manifest.services["github"].port, Some(9001));
+        assert!(manifest.services.contains_key("slack"));
+        assert_eq!(
+            manifest.seed_sources("github"),
+            vec![SeedSource::Fixture("startup".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_seed_names_its_source() {
+        let manifest = manifest(
+            "services:\n  github:\n    seed: {snapshot: prod-small}\n  \
+             slack:\n    seed: {fixture: startup}\n  \
+             descope:\n    seed: {file: seeds/descope.json}\n  \
+             stripe:\n    seed: seeds/stripe.yaml\n",
+        );
+        assert_eq!(
+            manifest.seed_sources("github"),
+            vec![SeedSource::Snapshot("prod-small".to_string())]
+        );
+        assert_eq!(
+            manifest.seed_sources("slack"),
+            vec![SeedSource::Fixture("startup".to_string())]
+        );
+        assert_eq!(
+            manifest.seed_sources("descope"),
+            vec![SeedSource::File(PathBuf::from("/env/seeds/descope.json"))]
+        );
+        assert_eq!(
+            manifest.seed_sources("stripe"),
+            vec![SeedSource::File(PathBuf::from("/env/seeds/stripe.yaml"))]
+        );
+
+        assert!(serde_yaml::from_str::<Manifest>(
+            "services:\n  github:\n    seed: {snapshot: a, fixture: b}\n"
+        )
+        .is_err());
+
+        for source in [
+            SeedSource::Snapshot("prod-small".to_string()),
+            SeedSource::File(PathBuf::from("/env/seeds/a:b.yaml")),
+        ] {
+            assert_eq!(source.to_string().parse::<SeedSource>().unwrap(), source);
+        }
+        assert!("prod".parse::<SeedSource>().is_err());
+    }
+
+    #[test]
+    fn test_seeds_are_applied_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("doubleagent.yaml");
+        fs::write(
+            &path,
+            "services: [github, slack]\n\
+             seeds:\n  github:\n    - {fixture: base}\n    - {snapshot: prod-small}\n    \
+             - seeds/extra.yaml\n",
+        )
+        .unwrap();
+        let manifest = Manifest::from_project_config(&path, dir.path()).unwrap();
+        let sources = manifest.seed_sources("github");
+        assert_eq!(
+            sources,
+            vec![
+                SeedSource::Fixture("base".to_string()),
+                SeedSource::Snapshot("prod-small".to_string()),
+                SeedSource::File(dir.path().join("seeds/extra.yaml")),
+            ]
+        );
+        assert!(manifest.seed_sources("slack").is_empty());
+        assert_eq!(
+            SeedSource::parse_list(&SeedSource::join(&sources)).unwrap(),
+            sources
+        );
+
+        // Reseeded only when the list changes
+        let mut github = info(8080);
+        github.seed = Some(SeedSource::join(&sources));
+        let running = HashMap::from([("github".to_string(), github)]);
+        let actions = plan(&manifest, &running, &mut allocator(&[])).unwrap();
+        assert!(!actions.iter().any(|a| matches!(a, Action::Seed { .. })));
+        let mut shorter = manifest.clone();
+        shorter.seeds.get_mut("github").unwrap().pop();
+        let actions = plan(&shorter, &running, &mut allocator(&[])).unwrap();
+        assert!(actions.contains(&Action::Seed {
+            service: "github".to_string(),
+            sources: sources[..2].to_vec(),
+        }));
+
+        for (yaml, error) in [
+            (
+                "services: [github]\nseeds:\n  slack: [a.yaml]\n",
+                "isn't one of its services",
+            ),
+            (
+                "services:\n  github: {fixture: base}\nseeds:\n  github: [a.yaml]\n",
+                "pick one",
+            ),
+        ] {
+            fs::write(&path, yaml).unwrap();
+            let msg = Manifest::from_project_config(&path, dir.path())
+                .unwrap_err()
+                .to_string();
+            assert!(msg.contains(error), "{}", msg);
+        }
+    }
+
+    #[test]
+    fn test_unknown_fields_are_rejected() {
+        assert!(serde_yaml::from_str::<Manifest>("services:\n  github:\n    prot: 1\n").is_err());
+    }
+
+    #[test]
+    fn test_resolve_definitions_without_installing() {
+        let temp = tempfile::tempdir().unwrap();
+        let services_dir = temp.path().join("services");
+        let github = services_dir.join("github");
+        fs::create_dir_all(&github).unwrap();
+        fs::write(
+            github.join("service.yaml"),
+            "name: github\nversion: '1.0'\nserver:\n  command: [python3, main.py]\n",
+        )
+        .unwrap();
+        let registry =
+            ServiceRegistry::new(&services_dir, "file:///nonexistent/services", "main").unwrap();
+        let listing = || {
+            let mut entries: Vec<_> = fs::read_dir(&services_dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect();
+            entries.sort();
+            entries
+        };
+        let before = listing();
+
+        let path = Path::new("doubleagent.yaml");
+        let desired = manifest("services:\n  github: {version: '1.0'}\n  slack: {}\n");
+        let (definitions, uninstalled) =
+            resolve_definitions(&desired, path, &registry, false).unwrap();
+        assert_eq!(definitions.keys().collect::<Vec<_>>(), ["github"]);
+        assert_eq!(uninstalled, ["slack"]);
+        assert_eq!(listing(), before);
+
+        let pinned = manifest("services:\n  github: {version: '2.0'}\n");
+        let err = resolve_definitions(&pinned, path, &registry, false)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("requires github version 2.0"), "{}", err);
+    }
+}
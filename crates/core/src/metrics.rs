@@ -0,0 +1,343 @@
+//! Prometheus metrics of running services, for `doubleagent metrics`.
+//!
+//! [`serve`] answers `GET /metrics` in the Prometheus text format. Each scrape
+//! checks every running service's health and reads its request log from the
+//! optional `/_doubleagent/requests` control endpoint (see [`crate::coverage`]):
+//!
+//! - `doubleagent_service_up`: 1 when the health check passes, else 0; a
+//!   service seen once stays listed, at 0, after it stops
+//! - `doubleagent_service_start_time_seconds`: when the running fake started
+//! - `doubleagent_service_restarts_total`: restarts seen since the exporter
+//!   started, counted when a service comes back under a new process
+//! - `doubleagent_health_check_duration_seconds`: how long the last health
+//!   check took
+//! - `doubleagent_fake_requests_total`: requests the fake logged, by `method`
+//!   and `status`; a reset clears the log, which Prometheus sees as a counter
+//!   reset
+
+use crate::config::Config;
+use crate::control::ControlClient;
+use crate::process::ProcessManager;
+use crate::{tags, Result};
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response};
+use hyper_util::rt::TokioIo;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+
+/// Port Prometheus exporters conventionally listen on.
+pub const DEFAULT_PORT: u16 = 9464;
+
+/// What one scrape found for a service.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sample {
+    pub service: String,
+    pub up: bool,
+    /// Unix seconds the running fake started at
+    pub started_at: Option<i64>,
+    pub restarts: u64,
+    /// Duration of the health check, when the service is running
+    pub health_seconds: Option<f64>,
+    /// Logged requests by method and status, for fakes that log them
+    pub requests: BTreeMap<(String, String), usize>,
+}
+
+/// Samples the running services and remembers what it saw, to count restarts
+/// and keep reporting stopped services.
+#[derive(Debug, Default)]
+pub struct Exporter {
+    /// Only services with all of these tags are sampled
+    tags: Vec<(String, String)>,
+    /// Process each service last ran as, if it was running, and its restarts
+    seen: BTreeMap<String, (Option<u32>, u64)>,
+}
+
+impl Exporter {
+    pub fn new(tags: Vec<(String, String)>) -> Self {
+        Self {
+            tags,
+            seen: BTreeMap::new(),
+        }
+    }
+
+    /// Sample the services in the state file, in the Prometheus text format.
+    pub async fn scrape(&mut self, config: &Config) -> Result<String> {
+        let manager = ProcessManager::load(&config.state_file)?;
+        let mut samples = Vec::new();
+        for name in manager.running_services() {
+            let Some(info) = manager.get_info(&name) else {
+                continue;
+            };
+            if !tags::matches(&info.tags, &self.tags) {
+                continue;
+            }
+            let checked = Instant::now();
+            let up = manager.check_health(&name, &config.health_for(&name)).await;
+            let health_seconds = checked.elapsed().as_secs_f64();
+            let requests = if up {
+                requests(info.port).await
+            } else {
+                BTreeMap::new()
+            };
+            samples.push(Sample {
+                restarts: self.observe(&name, Some(info.pid)),
+                service: name,
+                up,
+                started_at: info.started_at.parse().ok(),
+                health_seconds: Some(health_seconds),
+                requests,
+            });
+        }
+
+        let stopped: Vec<String> = self
+            .seen
+            .keys()
+            .filter(|name| !samples.iter().any(|sample| &sample.service == *name))
+            .cloned()
+            .collect();
+        for name in stopped {
+            samples.push(Sample {
+                restarts: self.observe(&name, None),
+                service: name,
+                ..Sample::default()
+            });
+        }
+        samples.sort_by(|a, b| a.service.cmp(&b.service));
+        Ok(render(&samples))
+    }
+
+    /// Record the process a service runs as (`None` when stopped), returning
+    /// how many times it restarted.
+    fn observe(&mut self, name: &str, pid: Option<u32>) -> u64 {
+        let (last, restarts) = self.seen.entry(name.to_string()).or_insert((pid, 0));
+        if pid.is_some() && *last != pid {
+            *restarts += 1;
+        }
+        *last = pid;
+        *restarts
+    }
+}
+
+/// Logged requests of the fake on `port` by method and status; empty when it
+/// keeps no log.
+async fn requests(port: u16) -> BTreeMap<(String, String), usize> {
+    let mut counts = BTreeMap::new();
+    if let Ok(log) = ControlClient::new(port).requests().await {
+        for request in log.requests {
+            let status = request
+                .status
+                .map_or_else(|| "unknown".to_string(), |s| s.to_string());
+            *counts
+                .entry((request.method.to_uppercase(), status))
+                .or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+/// Samples in the Prometheus text exposition format.
+pub fn render(samples: &[Sample]) -> String {
+    let mut out = String::new();
+    let mut family = |name: &str, kind: &str, help: &str, lines: Vec<String>| {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for line in lines {
+            let _ = writeln!(out, "{}{}", name, line);
+        }
+    };
+    let service = |sample: &Sample| format!("service=\"{}\"", label(&sample.service));
+
+    family(
+        "doubleagent_service_up",
+        "gauge",
+        "Whether the service passes its health check.",
+        samples
+            .iter()
+            .map(|s| format!("{{{}}} {}", service(s), u8::from(s.up)))
+            .collect(),
+    );
+    family(
+        "doubleagent_service_start_time_seconds",
+        "gauge",
+        "Start time of the running service, in Unix seconds.",
+        samples
+            .iter()
+            .filter_map(|s| Some(format!("{{{}}} {}", service(s), s.started_at?)))
+            .collect(),
+    );
+    family(
+        "doubleagent_service_restarts_total",
+        "counter",
+        "Restarts of the service seen since the exporter started.",
+        samples
+            .iter()
+            .map(|s| format!("{{{}}} {}", service(s), s.restarts))
+            .collect(),
+    );
+    family(
+        "doubleagent_health_check_duration_seconds",
+        "gauge",
+        "Duration of the last health check.",
+        samples
+            .iter()
+            .filter_map(|s| Some(format!("{{{}}} {:.6}", service(s), s.health_seconds?)))
+            .collect(),
+    );
+    family(
+        "doubleagent_fake_requests_total",
+        "counter",
+        "Requests the fake logged, by method and status.",
+        samples
+            .iter()
+            .flat_map(|s| {
+                s.requests.iter().map(move |((method, status), count)| {
+                    format!(
+                        "{{{},method=\"{}\",status=\"{}\"}} {}",
+                        service(s),
+                        label(method),
+                        label(status),
+                        count
+                    )
+                })
+            })
+            .collect(),
+    );
+    out
+}
+
+/// A label value with `\`, `"` and newlines escaped.
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Answer scrapes on `listener` until `shutdown` completes.
+pub async fn serve(
+    listener: TcpListener,
+    config: Config,
+    exporter: Exporter,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let config = Arc::new(config);
+    // Scrapes take turns, so restarts are counted once
+    let exporter = Arc::new(Mutex::new(exporter));
+    tokio::pin!(shutdown);
+
+    loop {
+        let (stream, _) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = &mut shutdown => return Ok(()),
+        };
+
+        let config = config.clone();
+        let exporter = exporter.clone();
+        tokio::spawn(async move {
+            let service =
+                service_fn(move |request| answer(config.clone(), exporter.clone(), request));
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("Metrics connection error: {}", e);
+            }
+        });
+    }
+}
+
+async fn answer(
+    config: Arc<Config>,
+    exporter: Arc<Mutex<Exporter>>,
+    request: Request<Incoming>,
+) -> std::result::Result<Response<Full<Bytes>>, std::convert::Infallible> {
+    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        return Ok(Response::builder()
+            .status(404)
+            .body(Full::new(Bytes::from(
+                "doubleagent metrics: see /metrics\n",
+            )))
+            .unwrap());
+    }
+    let response = match exporter.lock().await.scrape(&config).await {
+        Ok(text) => Response::builder()
+            .header("content-type", "text/plain; version=0.0.4; charset=utf-8")
+            .body(Full::new(Bytes::from(text))),
+        Err(e) => Response::builder()
+            .status(500)
+            .body(Full::new(Bytes::from(format!(
+                "doubleagent metrics: {}\n",
+                e
+            )))),
+    };
+    Ok(response.unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let samples = [
+            Sample {
+                service: "github".to_string(),
+                up: true,
+                started_at: Some(1_700_000_000),
+                restarts: 1,
+                health_seconds: Some(0.0025),
+                requests: BTreeMap::from([
+                    (("GET".to_string(), "200".to_string()), 3),
+                    (("POST".to_string(), "422".to_string()), 1),
+                ]),
+            },
+            Sample {
+                service: "sla\"ck".to_string(),
+                ..Sample::default()
+            },
+        ];
+        let text = render(&samples);
+        assert!(text.contains("# TYPE doubleagent_service_up gauge\n"));
+        assert!(text.contains("doubleagent_service_up{service=\"github\"} 1\n"));
+        assert!(text.contains("doubleagent_service_up{service=\"sla\\\"ck\"} 0\n"));
+        assert!(text
+            .contains("doubleagent_service_start_time_seconds{service=\"github\"} 1700000000\n"));
+        assert!(text.contains("doubleagent_service_restarts_total{service=\"github\"} 1\n"));
+        assert!(text
+            .contains("doubleagent_health_check_duration_seconds{service=\"github\"} 0.002500\n"));
+        assert!(text.contains(
+            "doubleagent_fake_requests_total{service=\"github\",method=\"POST\",status=\"422\"} 1\n"
+        ));
+        // A stopped service has no start time or health check to report
+        assert!(!text.contains("start_time_seconds{service=\"sla"));
+    }
+
+    #[test]
+    fn test_observe_counts_restarts() {
+        let mut exporter = Exporter::default();
+        assert_eq!(exporter.observe("github", Some(10)), 0);
+        assert_eq!(exporter.observe("github", Some(10)), 0);
+        assert_eq!(exporter.observe("github", Some(11)), 1);
+        // Stopping isn't a restart; starting again is
+        assert_eq!(exporter.observe("github", None), 1);
+        assert_eq!(exporter.observe("github", Some(12)), 2);
+    }
+
+    #[tokio::test]
+    async fn test_scrape_keeps_stopped_services() {
+        let temp = tempfile::tempdir().unwrap();
+        let config = Config::builder().root(temp.path()).build().unwrap();
+        let mut exporter = Exporter::default();
+        exporter.observe("github", Some(10));
+        let text = exporter.scrape(&config).await.unwrap();
+        assert!(text.contains("doubleagent_service_up{service=\"github\"} 0\n"));
+    }
+}
@@ -0,0 +1,166 @@
+//! Versioning of the project config schema.
+//!
+//! doubleagent.yaml declares its schema with a top-level `version` key; files
+//! without one are version 1. Older versions are upgraded in memory when
+//! loaded, so they keep working, and `doubleagent migrate-config` rewrites a
+//! file in the latest version.
+//!
+//! | Version | Changes |
+//! |---------|---------|
+//! | 1 | `services` is a list of names or a mapping keyed by name |
+//! | 2 | `services` is always a mapping keyed by name |
+
+use crate::format::Format;
+use crate::{Error, Result};
+use serde_yaml::{Mapping, Value};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Key holding the schema version.
+pub const KEY: &str = "version";
+
+/// The latest schema version, written by `init` and `migrate-config`.
+pub const CURRENT_VERSION: u64 = 2;
+
+/// Upgrade steps; entry `n` turns version `n + 1` into version `n + 2`.
+const MIGRATIONS: &[fn(&mut Mapping)] = &[services_as_mapping];
+
+/// The schema version a document declares (1 when it has no `version` key).
+pub fn version(document: &Value, source: impl fmt::Display) -> Result<u64> {
+    let version = match document.get(KEY) {
+        None | Some(Value::Null) => return Ok(1),
+        Some(version) => version.as_u64().filter(|v| *v >= 1).ok_or_else(|| {
+            Error::Other(format!("{}: '{}' must be a positive integer", source, KEY))
+        })?,
+    };
+    if version > CURRENT_VERSION {
+        return Err(Error::Other(format!(
+            "{} uses config version {}, but this doubleagent only understands up to version {}. \
+             Upgrade doubleagent to use it.",
+            source, version, CURRENT_VERSION
+        )));
+    }
+    Ok(version)
+}
+
+/// Upgrade a document to [`CURRENT_VERSION`], setting its `version` key.
+///
+/// Returns the version the document had before.
+pub fn upgrade(document: &mut Value, source: impl fmt::Display) -> Result<u64> {
+    let from = version(document, &source)?;
+    let mapping = match document {
+        Value::Mapping(mapping) => mapping,
+        Value::Null => return Ok(from),
+        _ => {
+            return Err(Error::Other(format!(
+                "{} must contain a mapping of keys to values",
+                source
+            )))
+        }
+    };
+
+    for step in &MIGRATIONS[(from - 1) as usize..] {
+        step(mapping);
+    }
+
+    // Keep the version as the first key of rewritten files
+    mapping.remove(KEY);
+    let mut upgraded = Mapping::new();
+    upgraded.insert(Value::from(KEY), Value::from(CURRENT_VERSION));
+    upgraded.extend(std::mem::take(mapping));
+    *mapping = upgraded;
+    Ok(from)
+}
+
+/// Rewrite a project config file in the latest schema version.
+///
+/// Environment variable references are kept as written. Returns the version
+/// the file had before, and the new content if it changed.
+pub fn migrate_file(path: &Path) -> Result<(u64, Option<String>)> {
+    let format = Format::from_path(path);
+    let content = fs::read_to_string(path)
+        .map_err(|e| Error::Other(format!("Failed to read {}: {}", path.display(), e)))?;
+    let mut document = format.parse(&content, path)?;
+
+    let from = upgrade(&mut document, path.display())?;
+    if from == CURRENT_VERSION {
+        return Ok((from, None));
+    }
+    Ok((from, Some(format.to_string(&document)?)))
+}
+
+/// Version 2: services are always keyed by name.
+fn services_as_mapping(document: &mut Mapping) {
+    let Some(services) = document.get_mut("services") else {
+        return;
+    };
+    let mapping: Mapping = match std::mem::take(services) {
+        Value::Sequence(names) => names
+            .into_iter()
+            .map(|name| (name, Value::Mapping(Mapping::new())))
+            .collect(),
+        // Null entries can't be written as TOML; an empty mapping means the same
+        Value::Mapping(entries) => entries
+            .into_iter()
+            .map(|(name, service)| match service {
+                Value::Null => (name, Value::Mapping(Mapping::new())),
+                service => (name, service),
+            })
+            .collect(),
+        Value::Null => Mapping::new(),
+        other => {
+            *services = other;
+            return;
+        }
+    };
+    *services = Value::Mapping(mapping);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_upgrade_version_1_services_list() {
+        let mut document: Value =
+            serde_yaml::from_str("base_port: 9000\nservices:\n  - github\n  - slack\n").unwrap();
+
+        assert_eq!(upgrade(&mut document, "doubleagent.yaml").unwrap(), 1);
+        assert_eq!(
+            serde_yaml::to_string(&document).unwrap(),
+            "version: 2\nbase_port: 9000\nservices:\n  github: {}\n  slack: {}\n"
+        );
+    }
+
+    #[test]
+    fn test_rejects_newer_versions() {
+        let document: Value = serde_yaml::from_str("version: 99\n").unwrap();
+        let msg = version(&document, "doubleagent.yaml")
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("Upgrade doubleagent"), "{}", msg);
+    }
+
+    #[test]
+    fn test_migrate_file_keeps_format_and_references() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("doubleagent.toml");
+        fs::write(
+            &path,
+            "branch = \"${BRANCH:-main}\"\nservices = [\"github\"]\n",
+        )
+        .unwrap();
+
+        let (from, content) = migrate_file(&path).unwrap();
+        let content = content.unwrap();
+        assert_eq!(from, 1);
+        assert!(content.starts_with("version = 2\n"), "{}", content);
+        assert!(content.contains("${BRANCH:-main}"), "{}", content);
+        assert!(content.contains("[services.github]"), "{}", content);
+
+        fs::write(&path, content).unwrap();
+        assert_eq!(migrate_file(&path).unwrap(), (2, None));
+    }
+}
@@ -0,0 +1,127 @@
+//! Namespaces: isolated slices of one running fake's state.
+//!
+//! Parallel test workers (e.g. pytest-xdist) sharing a fake race on its
+//! state. A fake that lists `namespaces: true` in `/_doubleagent/info` keeps
+//! separate state per value of the `X-DoubleAgent-Namespace` header, so each
+//! test session mints a namespace with `doubleagent namespace create`, sends
+//! the header with its requests, and seeds and resets only its own namespace
+//! (`--namespace`, or `DOUBLEAGENT_NAMESPACE` in the environment). Requests
+//! without the header share the default namespace.
+
+use crate::control::ControlClient;
+use crate::{Error, Result};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+/// Header naming the namespace a request belongs to.
+pub const HEADER: &str = "X-DoubleAgent-Namespace";
+
+/// Environment variable the CLI reads the namespace from, and that test
+/// sessions export for their clients.
+pub const ENV_VAR: &str = "DOUBLEAGENT_NAMESPACE";
+
+/// Longest namespace accepted, so it fits in a header and a file name.
+const MAX_LEN: usize = 64;
+
+/// Mint a new namespace, optionally prefixed with a label (e.g. `gw0`).
+pub fn mint(label: Option<&str>) -> Result<String> {
+    static MINTED: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let seed = format!(
+        "{}:{}:{}",
+        std::process::id(),
+        nanos,
+        MINTED.fetch_add(1, Ordering::Relaxed)
+    );
+    let id = format!("{:x}", Sha256::digest(seed.as_bytes()));
+    let namespace = match label {
+        Some(label) => format!("{}-{}", label, &id[..12]),
+        None => format!("ns-{}", &id[..12]),
+    };
+    validate(&namespace)?;
+    Ok(namespace)
+}
+
+/// Check a namespace is safe to send as a header.
+pub fn validate(namespace: &str) -> Result<()> {
+    let valid = !namespace.is_empty()
+        && namespace.len() <= MAX_LEN
+        && namespace
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(Error::Other(format!(
+            "Invalid namespace '{}': use up to {} letters, digits, '-' and '_'",
+            namespace, MAX_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// The namespace given in the environment, if any.
+pub fn from_env() -> Option<String> {
+    std::env::var(ENV_VAR).ok().filter(|ns| !ns.is_empty())
+}
+
+/// A client for one namespace of the service at `port`, after checking the
+/// service supports namespaces.
+pub async fn client(port: u16, namespace: &str) -> Result<ControlClient> {
+    validate(namespace)?;
+    let client = ControlClient::new(port).with_namespace(namespace);
+    if !client.supports_namespaces().await {
+        return Err(Error::Other(format!(
+            "The service at {} doesn't support namespaces \
+             (namespaces in /_doubleagent/info isn't true)",
+            client.base_url()
+        )));
+    }
+    Ok(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::rest::tests::serve;
+    use serde_json::json;
+
+    #[test]
+    fn test_minted_namespaces_are_unique_and_valid() {
+        let a = mint(Some("gw0")).unwrap();
+        let b = mint(Some("gw0")).unwrap();
+        assert!(a.starts_with("gw0-"), "{}", a);
+        assert_ne!(a, b);
+        assert!(mint(None).unwrap().starts_with("ns-"));
+
+        for invalid in ["", "a b", "a/b", &"x".repeat(MAX_LEN + 1)] {
+            assert!(validate(invalid).is_err(), "{}", invalid);
+        }
+        assert!(mint(Some("bad label")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_namespaced_client_sends_header() {
+        let (url, server) = serve(vec![
+            json!({"name": "github", "namespaces": true}),
+            json!({"status": "ok"}),
+        ]);
+        let port = url.rsplit(':').next().unwrap().parse().unwrap();
+
+        client(port, "gw0-1").await.unwrap().reset().await.unwrap();
+        let requests = server.join().unwrap();
+        let reset = requests
+            .iter()
+            .position(|line| line.starts_with("POST /_doubleagent/reset"))
+            .unwrap();
+        assert!(requests[reset..].contains(&"x-doubleagent-namespace: gw0-1".to_string()));
+
+        let (url, server) = serve(vec![json!({"name": "github"})]);
+        let port = url.rsplit(':').next().unwrap().parse().unwrap();
+        let msg = client(port, "gw0-1").await.err().unwrap().to_string();
+        assert!(msg.contains("doesn't support namespaces"), "{}", msg);
+        server.join().unwrap();
+    }
+}
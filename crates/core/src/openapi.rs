@@ -0,0 +1,370 @@
+//! Seed payloads synthesized from a service's OpenAPI description.
+//!
+//! A fake that ships an `openapi.yaml` (or `.yml`/`.json`) next to its
+//! service.yaml can be seeded before anyone has written fixtures for it
+//! (`doubleagent seed <service> --openapi`). Every `GET` operation that
+//! returns a list names a resource after the last literal segment of its
+//! path (`/repos/{owner}/{repo}/issues` seeds `issues`), and its records are
+//! taken from, in order of preference:
+//!
+//! 1. the response's own `example` or first `examples` entry;
+//! 2. `example`/`examples` on the item schema and its properties;
+//! 3. values made up from each property's type, `format`, `enum` or
+//!    `default`, so the record is minimal but valid.
+//!
+//! If the service also ships a `seed.schema.json`, only the resources it
+//! lists are kept, and resources it lists that no operation returns are
+//! matched to a component schema by name (`users` to `User`).
+
+use crate::service::ServiceDefinition;
+use crate::snapshot::schema::SEED_SCHEMA_FILE;
+use crate::{Error, Result};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+
+/// Files an OpenAPI description is looked for in, in a service directory.
+pub const OPENAPI_FILES: [&str; 3] = ["openapi.yaml", "openapi.yml", "openapi.json"];
+
+/// How deep nested schemas are followed before giving up on a value.
+const MAX_DEPTH: usize = 8;
+
+/// Path of the OpenAPI description a service ships, if any.
+pub fn find(service: &ServiceDefinition) -> Option<PathBuf> {
+    OPENAPI_FILES
+        .iter()
+        .map(|name| service.path.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Read and parse the service's OpenAPI description, with the path it came from.
+///
+/// `purpose` completes the error for a service without one: "so it can't be ...".
+pub fn load(service: &ServiceDefinition, purpose: &str) -> Result<(PathBuf, Value)> {
+    let path = find(service).ok_or_else(|| {
+        Error::Other(format!(
+            "Service '{}' has no {}, so it can't be {}",
+            service.name,
+            OPENAPI_FILES.join(" or "),
+            purpose
+        ))
+    })?;
+    let content = fs::read_to_string(&path)?;
+    let spec = serde_yaml::from_str(&content)
+        .map_err(|e| Error::Other(format!("Invalid {}: {}", path.display(), e)))?;
+    Ok((path, spec))
+}
+
+/// Synthesize a seed payload from the service's OpenAPI description.
+pub fn seed(service: &ServiceDefinition) -> Result<Value> {
+    let (path, spec) = load(service, "seeded from OpenAPI examples")?;
+
+    let schema_path = service.path.join(SEED_SCHEMA_FILE);
+    let resources = if schema_path.is_file() {
+        let schema: Value = serde_json::from_str(&fs::read_to_string(&schema_path)?)
+            .map_err(|e| Error::Other(format!("Invalid {}: {}", schema_path.display(), e)))?;
+        schema
+            .get("properties")
+            .and_then(Value::as_object)
+            .map(|properties| properties.keys().cloned().collect())
+    } else {
+        None
+    };
+
+    let payload = synthesize(&spec, resources.as_ref());
+    if payload.as_object().is_none_or(Map::is_empty) {
+        return Err(Error::Other(format!(
+            "{} has no GET operations returning lists to take records from",
+            path.display()
+        )));
+    }
+    Ok(payload)
+}
+
+/// Build a payload from a parsed description, keeping only `resources` if given.
+pub fn synthesize(spec: &Value, resources: Option<&BTreeSet<String>>) -> Value {
+    let mut payload = Map::new();
+    let paths = spec.get("paths").and_then(Value::as_object);
+    for (path, item) in paths.into_iter().flatten() {
+        let Some(resource) = resource_name(path) else {
+            continue;
+        };
+        if payload.contains_key(&resource) || resources.is_some_and(|r| !r.contains(&resource)) {
+            continue;
+        }
+        let Some(media) = item
+            .pointer("/get/responses")
+            .and_then(Value::as_object)
+            .and_then(|responses| {
+                ["200", "2XX", "default"]
+                    .iter()
+                    .find_map(|code| responses.get(*code))
+            })
+            .map(|response| resolve(spec, response))
+            .and_then(|response| response.pointer("/content/application~1json"))
+        else {
+            continue;
+        };
+        if let Some(records) = list_records(spec, media) {
+            payload.insert(resource, Value::Array(records));
+        }
+    }
+
+    // Resources the seed schema wants that no operation lists
+    let components = spec
+        .pointer("/components/schemas")
+        .and_then(Value::as_object);
+    for resource in resources.into_iter().flatten() {
+        if payload.contains_key(resource) {
+            continue;
+        }
+        let singular = resource.strip_suffix('s').unwrap_or(resource);
+        let schema = components.into_iter().flatten().find_map(|(name, schema)| {
+            let name = name.to_lowercase();
+            (name == *resource || name == singular).then_some(schema)
+        });
+        if let Some(schema) = schema {
+            payload.insert(resource.clone(), json!([example(spec, schema, 0)]));
+        }
+    }
+    Value::Object(payload)
+}
+
+/// The last literal segment of a path that lists a resource.
+fn resource_name(path: &str) -> Option<String> {
+    let last = path.trim_end_matches('/').rsplit('/').next()?;
+    (!last.is_empty() && !last.starts_with('{')).then(|| last.to_string())
+}
+
+/// Records of a list response: its example, or one made from its schema.
+///
+/// Lists wrapped in an object (`{"data": [...]}`) are unwrapped.
+fn list_records(spec: &Value, media: &Value) -> Option<Vec<Value>> {
+    let schema = media.get("schema").map(|s| resolve(spec, s));
+    let given = media.get("example").or_else(|| {
+        media
+            .get("examples")
+            .and_then(Value::as_object)
+            .and_then(|examples| examples.values().next())
+            .map(|example| resolve(spec, example))
+            .and_then(|example| example.get("value"))
+    });
+
+    // Where the list is: the response itself, or one property of it
+    let wrapper = match schema {
+        Some(schema) if is_type(schema, "array") => None,
+        Some(schema) => Some(
+            schema
+                .get("properties")?
+                .as_object()?
+                .iter()
+                .find(|(_, property)| is_type(resolve(spec, property), "array"))?
+                .0
+                .clone(),
+        ),
+        None => None,
+    };
+    let list = match &wrapper {
+        Some(key) => given.and_then(|example| example.get(key)),
+        None => given,
+    };
+    if let Some(Value::Array(records)) = list {
+        return Some(records.clone());
+    }
+
+    let mut items = schema?;
+    if let Some(key) = &wrapper {
+        items = resolve(spec, items.pointer(&format!("/properties/{}", key))?);
+    }
+    let item = resolve(spec, items.get("items")?);
+    Some(vec![example(spec, item, 0)])
+}
+
+/// Follow a local `$ref`, if the value is one.
+pub(crate) fn resolve<'a>(spec: &'a Value, value: &'a Value) -> &'a Value {
+    let mut value = value;
+    for _ in 0..MAX_DEPTH {
+        match value
+            .get("$ref")
+            .and_then(Value::as_str)
+            .and_then(|r| r.strip_prefix('#'))
+            .and_then(|pointer| spec.pointer(pointer))
+        {
+            Some(target) => value = target,
+            None => break,
+        }
+    }
+    value
+}
+
+pub(crate) fn is_type(schema: &Value, name: &str) -> bool {
+    match schema.get("type") {
+        Some(Value::String(t)) => t == name,
+        Some(Value::Array(types)) => types.iter().any(|t| t == name),
+        None => name == "object" && schema.get("properties").is_some(),
+        _ => false,
+    }
+}
+
+/// A value for a schema: its own example, or one made from its parts.
+pub(crate) fn example(spec: &Value, schema: &Value, depth: usize) -> Value {
+    let schema = resolve(spec, schema);
+    if let Some(example) = schema.get("example") {
+        return example.clone();
+    }
+    if let Some(Value::Array(examples)) = schema.get("examples") {
+        if let Some(example) = examples.first() {
+            return example.clone();
+        }
+    }
+    if let Some(value) = schema.get("default").or_else(|| schema.get("const")) {
+        return value.clone();
+    }
+    if let Some(Value::Array(values)) = schema.get("enum") {
+        if let Some(value) = values.first() {
+            return value.clone();
+        }
+    }
+    if depth >= MAX_DEPTH {
+        return Value::Null;
+    }
+
+    if let Some(Value::Array(parts)) = schema.get("allOf") {
+        let mut merged = Map::new();
+        for part in parts {
+            if let Value::Object(fields) = example(spec, part, depth + 1) {
+                merged.extend(fields);
+            }
+        }
+        return Value::Object(merged);
+    }
+    for key in ["oneOf", "anyOf"] {
+        if let Some(first) = schema.get(key).and_then(|s| s.get(0)) {
+            return example(spec, first, depth + 1);
+        }
+    }
+
+    let format = schema.get("format").and_then(Value::as_str).unwrap_or("");
+    if is_type(schema, "object") {
+        let record = schema
+            .get("properties")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flatten()
+            .map(|(name, property)| (name.clone(), example(spec, property, depth + 1)))
+            .collect();
+        Value::Object(record)
+    } else if is_type(schema, "array") {
+        match schema.get("items") {
+            Some(items) => json!([example(spec, items, depth + 1)]),
+            None => json!([]),
+        }
+    } else if is_type(schema, "integer") {
+        json!(schema.get("minimum").and_then(Value::as_i64).unwrap_or(1))
+    } else if is_type(schema, "number") {
+        json!(schema.get("minimum").and_then(Value::as_f64).unwrap_or(1.0))
+    } else if is_type(schema, "boolean") {
+        json!(false)
+    } else if is_type(schema, "string") {
+        json!(match format {
+            "date-time" => "2024-01-01T00:00:00Z",
+            "date" => "2024-01-01",
+            "email" => "user@example.com",
+            "uuid" => "00000000-0000-4000-8000-000000000000",
+            "uri" | "url" => "https://example.com",
+            "ipv4" => "192.0.2.1",
+            _ => "string",
+        })
+    } else {
+        Value::Null
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: &str = r##"
+openapi: 3.0.3
+paths:
+  /repos/{owner}/{repo}/issues:
+    get:
+      responses:
+        "200":
+          content:
+            application/json:
+              schema:
+                type: array
+                items: {$ref: "#/components/schemas/Issue"}
+  /repos/{owner}/{repo}/issues/{number}:
+    get:
+      responses:
+        "200":
+          content:
+            application/json:
+              schema: {$ref: "#/components/schemas/Issue"}
+  /users:
+    get:
+      responses:
+        "200":
+          content:
+            application/json:
+              schema:
+                type: object
+                properties:
+                  data: {type: array, items: {$ref: "#/components/schemas/User"}}
+              examples:
+                two:
+                  value: {data: [{login: ada}, {login: bob}]}
+components:
+  schemas:
+    Issue:
+      type: object
+      properties:
+        id: {type: integer, example: 42}
+        title: {type: string}
+        state: {type: string, enum: [open, closed]}
+        created_at: {type: string, format: date-time}
+        labels: {type: array, items: {type: string, example: bug}}
+        user: {$ref: "#/components/schemas/User"}
+    User:
+      type: object
+      properties:
+        login: {type: string, example: octocat}
+        site_admin: {type: boolean}
+    Org:
+      allOf:
+        - {$ref: "#/components/schemas/User"}
+        - properties: {seats: {type: integer, minimum: 5}}
+"##;
+
+    #[test]
+    fn test_synthesizes_records_from_examples_and_schemas() {
+        let spec: Value = serde_yaml::from_str(SPEC).unwrap();
+        assert_eq!(
+            synthesize(&spec, None),
+            json!({
+                "issues": [{
+                    "id": 42,
+                    "title": "string",
+                    "state": "open",
+                    "created_at": "2024-01-01T00:00:00Z",
+                    "labels": ["bug"],
+                    "user": {"login": "octocat", "site_admin": false},
+                }],
+                "users": [{"login": "ada"}, {"login": "bob"}],
+            })
+        );
+
+        // The seed schema picks resources, and names components to fall back on
+        let wanted = BTreeSet::from(["orgs".to_string(), "users".to_string()]);
+        assert_eq!(
+            synthesize(&spec, Some(&wanted)),
+            json!({
+                "orgs": [{"login": "octocat", "site_admin": false, "seats": 5}],
+                "users": [{"login": "ada"}, {"login": "bob"}],
+            })
+        );
+    }
+}
@@ -0,0 +1,159 @@
+//! Log of the commands that changed what runs, for shared machines.
+//!
+//! Every state-changing command (`start`, `stop`, `seed`, `apply`, `snapshot
+//! pull`, `config set`, ...) and every action taken from the dashboard
+//! appends one JSON line to `<state_dir>/audit/operations.jsonl` when it ends:
+//! who ran it and when, its arguments, the workspace, and whether it worked.
+//! Unlike the snapshot audit log (see [`crate::audit`]) it is always kept.
+//! `doubleagent audit show` reads it back.
+
+use crate::audit;
+use crate::settings::SECRET_KEYS;
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+/// File in the audit directory operations are appended to.
+pub const OPERATIONS_FILE: &str = "operations.jsonl";
+
+/// Stands in for argument values that shouldn't be kept, such as secrets.
+pub const REDACTED: &str = "[redacted]";
+
+/// One command that ran.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperationEntry {
+    /// When it ended (seconds since the Unix epoch)
+    pub timestamp: u64,
+    /// Local user who ran it
+    pub user: String,
+    /// Command, e.g. "start" or "snapshot"
+    pub command: String,
+    /// Its command line after `doubleagent`, secrets redacted
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Workspace it ran in
+    pub workspace: String,
+    /// Whether it succeeded
+    pub ok: bool,
+    /// Why it failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl OperationEntry {
+    /// A command run by the current user that ends now.
+    pub fn new(command: &str, args: Vec<String>, workspace: &str, error: Option<String>) -> Self {
+        Self {
+            timestamp: crate::dates::now().max(0) as u64,
+            user: audit::current_user(),
+            command: command.to_string(),
+            args,
+            workspace: workspace.to_string(),
+            ok: error.is_none(),
+            error,
+        }
+    }
+
+    /// The command line after `doubleagent`, e.g. `start github --tag team=x`.
+    pub fn command_line(&self) -> String {
+        self.args.join(" ")
+    }
+}
+
+/// The append-only operation log in a directory.
+#[derive(Debug, Clone)]
+pub struct OperationLog {
+    dir: PathBuf,
+}
+
+impl OperationLog {
+    /// Log kept in `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Path of the log file.
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(OPERATIONS_FILE)
+    }
+
+    /// Append an entry, creating the log readable only by its owner.
+    pub fn append(&self, entry: &OperationEntry) -> Result<()> {
+        audit::append_line(&self.dir, &self.path(), &serde_json::to_string(entry)?)
+    }
+
+    /// Every entry, oldest first. A log that doesn't exist yet is empty.
+    pub fn entries(&self) -> Result<Vec<OperationEntry>> {
+        let path = self.path();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let mut entries = Vec::new();
+        for (i, line) in BufReader::new(fs::File::open(&path)?).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = serde_json::from_str(&line)
+                .map_err(|e| Error::Other(format!("{} line {}: {}", path.display(), i + 1, e)))?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+/// Arguments with the values of secret settings replaced by [`REDACTED`]:
+/// whatever follows `set <key>` for a key in [`SECRET_KEYS`].
+pub fn redact_args(args: &[String]) -> Vec<String> {
+    let mut redacted = args.to_vec();
+    for i in 0..redacted.len().saturating_sub(2) {
+        if redacted[i] == "set" && SECRET_KEYS.contains(&redacted[i + 1].as_str()) {
+            redacted[i + 2] = REDACTED.to_string();
+        }
+    }
+    redacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_entries_are_appended_in_order() {
+        let dir = tempdir().unwrap();
+        let log = OperationLog::new(dir.path().join("audit"));
+        assert!(log.entries().unwrap().is_empty());
+
+        let args = |line: &str| line.split(' ').map(ToString::to_string).collect();
+        let start = OperationEntry::new("start", args("start github"), "default", None);
+        let stop = OperationEntry::new(
+            "stop",
+            args("-w ci stop slack"),
+            "ci",
+            Some("slack is not running".to_string()),
+        );
+        log.append(&start).unwrap();
+        log.append(&stop).unwrap();
+
+        let entries = log.entries().unwrap();
+        assert_eq!(entries, vec![start, stop]);
+        assert!(entries[0].ok);
+        assert!(!entries[1].ok);
+        assert_eq!(entries[0].command_line(), "start github");
+    }
+
+    #[test]
+    fn test_redact_args() {
+        let args =
+            |line: &str| -> Vec<String> { line.split(' ').map(ToString::to_string).collect() };
+        assert_eq!(
+            redact_args(&args("config set pseudonym_seed hunter2")),
+            args("config set pseudonym_seed [redacted]")
+        );
+        let registry = args("config set registry s3://team");
+        assert_eq!(redact_args(&registry), registry);
+    }
+}
@@ -0,0 +1,236 @@
+//! Port ranges and allocation for started services.
+
+use crate::{Error, Result};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// An inclusive range of ports services may be started on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl PortRange {
+    /// Whether a port lies within the range.
+    pub fn contains(&self, port: u16) -> bool {
+        (self.start..=self.end).contains(&port)
+    }
+
+    /// Fail if a port lies outside the range.
+    pub fn check(&self, port: u16) -> Result<()> {
+        if self.contains(port) {
+            Ok(())
+        } else {
+            Err(Error::Other(format!(
+                "Port {} is outside the allowed port range {} (see 'doubleagent config get port_range')",
+                port, self
+            )))
+        }
+    }
+}
+
+impl Default for PortRange {
+    fn default() -> Self {
+        Self {
+            start: 1024,
+            end: u16::MAX,
+        }
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("expected a range like '8000-8999', got '{}'", s))?;
+        let parse = |p: &str| match p.trim().parse::<u16>() {
+            Ok(0) | Err(_) => Err(format!("'{}' is not a valid port", p.trim())),
+            Ok(port) => Ok(port),
+        };
+        let range = PortRange {
+            start: parse(start)?,
+            end: parse(end)?,
+        };
+        if range.start > range.end {
+            return Err(format!("range '{}' ends before it starts", s));
+        }
+        Ok(range)
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+/// Hands out ports within a range, skipping ports that are already used.
+#[derive(Debug, Clone)]
+pub struct PortAllocator {
+    range: PortRange,
+    preferred: u16,
+    used: BTreeSet<u16>,
+}
+
+impl PortAllocator {
+    /// Create an allocator that starts searching at `preferred`.
+    ///
+    /// If `preferred` lies outside the range, the search starts at the
+    /// beginning of the range instead.
+    pub fn new(range: PortRange, preferred: u16, used: BTreeSet<u16>) -> Self {
+        let preferred = if range.contains(preferred) {
+            preferred
+        } else {
+            range.start
+        };
+        Self {
+            range,
+            preferred,
+            used,
+        }
+    }
+
+    /// Mark a port as used so it is never handed out.
+    pub fn reserve(&mut self, port: u16) {
+        self.used.insert(port);
+    }
+
+    /// Take a port that was asked for explicitly, failing rather than
+    /// searching for another one if it is taken.
+    pub fn claim(&mut self, port: u16) -> Result<u16> {
+        self.range.check(port)?;
+        if self.used.contains(&port) {
+            return Err(Error::Other(format!(
+                "Port {} is already used by a doubleagent service; pass another --port or leave it out to pick a free one",
+                port
+            )));
+        }
+        if !is_free(port) {
+            return Err(Error::Other(format!(
+                "Port {} is already in use; pass another --port or leave it out to pick a free one",
+                port
+            )));
+        }
+        self.used.insert(port);
+        Ok(port)
+    }
+
+    /// Return the lowest unused port at or above the preferred port, wrapping
+    /// around to the start of the range, and mark it used.
+    pub fn allocate(&mut self) -> Result<u16> {
+        let port = (self.preferred..=self.range.end)
+            .chain(self.range.start..self.preferred)
+            .find(|port| !self.used.contains(port))
+            .ok_or_else(|| {
+                Error::Other(format!("No free port left in port range {}", self.range))
+            })?;
+        self.used.insert(port);
+        Ok(port)
+    }
+
+    /// Like [`allocate`](Self::allocate), but also skip ports that something
+    /// outside doubleagent is listening on, such as a parallel job with its
+    /// own state.
+    pub fn allocate_free(&mut self) -> Result<u16> {
+        loop {
+            let port = self.allocate()?;
+            if is_free(port) {
+                return Ok(port);
+            }
+        }
+    }
+}
+
+/// Whether a port can be bound on localhost.
+pub fn is_free(port: u16) -> bool {
+    std::net::TcpListener::bind(("127.0.0.1", port)).is_ok()
+}
+
+/// Wait for a port to be released, such as by a process that was stopped.
+pub async fn wait_until_free(port: u16, timeout: Duration) -> Result<()> {
+    let start = Instant::now();
+    while !is_free(port) {
+        if start.elapsed() > timeout {
+            return Err(Error::Other(format!(
+                "Port {} is still in use after {}s",
+                port,
+                timeout.as_secs()
+            )));
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_port_range() {
+        assert_eq!(
+            "9000-9099".parse::<PortRange>().unwrap(),
+            PortRange {
+                start: 9000,
+                end: 9099
+            }
+        );
+        assert!("9099-9000".parse::<PortRange>().is_err());
+        assert!("9000".parse::<PortRange>().is_err());
+        assert!("0-10".parse::<PortRange>().is_err());
+    }
+
+    #[test]
+    fn test_allocate_skips_used_ports() {
+        let range = PortRange::default();
+        let mut ports = PortAllocator::new(range, 8080, BTreeSet::from([8080, 8081, 8083]));
+        assert_eq!(ports.allocate().unwrap(), 8082);
+        assert_eq!(ports.allocate().unwrap(), 8084);
+    }
+
+    #[test]
+    fn test_allocate_stays_within_range() {
+        let range: PortRange = "9000-9001".parse().unwrap();
+        let mut ports = PortAllocator::new(range, 8080, BTreeSet::new());
+        assert_eq!(ports.allocate().unwrap(), 9000);
+        assert_eq!(ports.allocate().unwrap(), 9001);
+        assert!(ports.allocate().is_err());
+    }
+
+    #[test]
+    fn test_allocate_free_skips_bound_ports() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let bound = listener.local_addr().unwrap().port();
+        let mut ports = PortAllocator::new(PortRange::default(), bound, BTreeSet::new());
+        let port = ports.allocate_free().unwrap();
+        assert_ne!(port, bound);
+        assert!(is_free(port));
+    }
+
+    #[test]
+    fn test_claim_fails_on_a_taken_port() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let bound = listener.local_addr().unwrap().port();
+        let mut ports = PortAllocator::new(PortRange::default(), 8080, BTreeSet::from([8080]));
+        let err = ports.claim(bound).unwrap_err().to_string();
+        assert!(err.contains("already in use"), "{}", err);
+        let err = ports.claim(8080).unwrap_err().to_string();
+        assert!(err.contains("used by a doubleagent service"), "{}", err);
+
+        drop(listener);
+        assert_eq!(ports.claim(bound).unwrap(), bound);
+        assert!(ports.claim(bound).is_err());
+    }
+
+    #[test]
+    fn test_allocate_wraps_around() {
+        let range: PortRange = "9000-9002".parse().unwrap();
+        let mut ports = PortAllocator::new(range, 9002, BTreeSet::from([9002]));
+        assert_eq!(ports.allocate().unwrap(), 9000);
+    }
+}
@@ -1,17 +1,26 @@
 //! Process management for running DoubleAgent services.
+//!
+//! Each service's output goes to a log file, `logs/<service>.log` next to the
+//! state file, started afresh each time the service is.
 
+use crate::health::{self, HealthSettings};
 use crate::mise;
+use crate::ports;
+use crate::reload;
 use crate::service::ServiceDefinition;
+use crate::tags::Tags;
 use crate::{Error, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs;
-use std::path::Path;
-use std::process::{Child, Stdio};
-use std::time::{Duration, Instant};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::Child;
+use std::sync::Mutex;
+use std::time::Instant;
 
 /// Information about a running service.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ServiceInfo {
     /// Process ID
     pub pid: u32,
@@ -21,6 +30,121 @@ pub struct ServiceInfo {
     pub started_at: String,
     /// Path to the service directory
     pub service_path: String,
+    /// Fingerprint of service.yaml at start time, used to detect pending changes
+    #[serde(default)]
+    pub config_fingerprint: Option<String>,
+    /// User-supplied key/value tags
+    #[serde(default, skip_serializing_if = "Tags::is_empty")]
+    pub tags: Tags,
+    /// Environment variables set on top of the service's server.env
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env_overrides: BTreeMap<String, String>,
+    /// Description of the last seed applied (e.g. "fixture:startup")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<String>,
+    /// Seeds applied since the service started (or was last reset), oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub seed_history: Vec<SeedRecord>,
+    /// Namespaces minted with `namespace create`, in the order they were made
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub namespaces: Vec<String>,
+    /// Times the service was restarted in place, e.g. by `apply` or `upgrade`
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub restarts: u32,
+    /// Changes of health health checks saw, oldest first, starting from when
+    /// the service was first checked
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub health_history: Vec<HealthChange>,
+    /// Health checks failed in a row, up to the last
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub failed_checks: u32,
+    /// Project config the service was last converged on by `up`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project_config: Option<String>,
+    /// Fingerprint of the service's entry in the project config at that time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project_fingerprint: Option<String>,
+}
+
+impl ServiceInfo {
+    /// The last change of health a health check saw.
+    pub fn health(&self) -> Option<&HealthChange> {
+        self.health_history.last()
+    }
+
+    /// Whether the service's health has been flapping at `now`.
+    pub fn is_flapping(&self, now: i64) -> bool {
+        health::is_flapping(&self.health_history, now)
+    }
+}
+
+/// Changes of health kept in a service's history.
+const HEALTH_HISTORY_LIMIT: usize = 20;
+
+/// A service seen to become healthy or unhealthy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthChange {
+    pub healthy: bool,
+    /// Unix timestamp of the change
+    pub at: String,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+/// Seeds kept in a service's history.
+const SEED_HISTORY_LIMIT: usize = 20;
+
+/// Most recent seeds that keep the state they replaced, so they can be undone.
+pub const SEED_UNDO_LIMIT: usize = 5;
+
+/// A seed applied to a running service.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SeedRecord {
+    /// What was seeded (e.g. "fixture:startup" or "generate:issues=200")
+    pub source: String,
+    /// Unix timestamp of the seed
+    pub at: String,
+    /// Records seeded per resource
+    #[serde(default)]
+    pub counts: BTreeMap<String, u64>,
+    /// The service's exported state before the seed, restored to undo it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prior: Option<serde_json::Value>,
+    /// Why the state before the seed couldn't be exported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prior_error: Option<String>,
+}
+
+impl SeedRecord {
+    /// A seed applied now, over the state `prior` exported before it.
+    pub fn new(
+        source: impl Into<String>,
+        counts: BTreeMap<String, u64>,
+        prior: Result<serde_json::Value>,
+    ) -> Self {
+        let (prior, prior_error) = match prior {
+            Ok(prior) => (Some(prior), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        Self {
+            source: source.into(),
+            at: chrono_lite_now(),
+            counts,
+            prior,
+            prior_error,
+        }
+    }
+}
+
+/// Options for starting a service.
+#[derive(Debug, Clone, Default)]
+pub struct StartOptions {
+    /// Environment variables set on top of the service's server.env
+    pub env: BTreeMap<String, String>,
+    /// File the output goes to instead of the service's log file
+    pub log_file: Option<PathBuf>,
 }
 
 #[derive(Default, Serialize, Deserialize)]
@@ -28,43 +152,181 @@ struct State {
     services: HashMap<String, ServiceInfo>,
 }
 
+impl State {
+    /// Read a state file, which is empty if it doesn't exist yet.
+    fn read(state_file: &Path) -> Result<Self> {
+        if !state_file.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(state_file)?;
+        serde_json::from_str(&content).map_err(|e| {
+            Error::Other(format!(
+                "{} is corrupt ({}); move it aside (mv {0} {0}.bak) and stop any services it listed",
+                state_file.display(),
+                e
+            ))
+        })
+    }
+
+    /// Write a state file whole: to a temporary file first, renamed over it,
+    /// so a reader never sees it half written.
+    fn write(&self, state_file: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+        let temp = state_file.with_extension(format!("json.{}.tmp", std::process::id()));
+        fs::write(&temp, content)?;
+        fs::rename(&temp, state_file)?;
+        Ok(())
+    }
+}
+
+/// An exclusive lock on a state file, held while it is read, changed and
+/// written so commands running at once don't lose each other's changes.
+/// Released when dropped.
+struct StateLock(#[allow(dead_code)] File);
+
+impl StateLock {
+    fn acquire(state_file: &Path) -> Result<Self> {
+        if let Some(parent) = state_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(state_file.with_extension("json.lock"))?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Self(file))
+    }
+}
+
 /// Manages running service processes.
 pub struct ProcessManager {
     state: State,
+    /// The running services as they were loaded or last saved, to tell which
+    /// this manager changed
+    loaded: Mutex<HashMap<String, ServiceInfo>>,
+    /// Services whose process died without being stopped, found on load
+    crashed: Vec<(String, ServiceInfo)>,
+    /// Crashed services to drop from the state file on save
+    forgotten: Vec<(String, u32)>,
     #[allow(dead_code)]
     processes: HashMap<String, Child>,
+    /// Where the services' output is written
+    logs_dir: PathBuf,
 }
 
 impl ProcessManager {
     /// Load process state from a file.
     ///
-    /// Automatically cleans up entries for dead processes.
+    /// Entries for dead processes are set aside as [`crashed`](Self::crashed).
+    /// A state file that can't be parsed is an error rather than taken as
+    /// empty, which would forget the services it lists.
     pub fn load(state_file: &Path) -> Result<Self> {
-        let state = if state_file.exists() {
-            let content = fs::read_to_string(state_file)?;
-            serde_json::from_str(&content).unwrap_or_default()
-        } else {
-            State::default()
-        };
+        let state = State::read(state_file)?;
 
-        // Clean up dead processes
+        // Set dead processes aside
         let mut cleaned_state = State::default();
+        let mut crashed = Vec::new();
         for (name, info) in state.services {
             if Self::process_alive(info.pid) {
                 cleaned_state.services.insert(name, info);
+            } else {
+                crashed.push((name, info));
             }
         }
+        crashed.sort_by(|a, b| a.0.cmp(&b.0));
 
+        let logs_dir = state_file
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join("logs");
         Ok(Self {
+            loaded: Mutex::new(cleaned_state.services.clone()),
             state: cleaned_state,
+            crashed,
+            forgotten: Vec::new(),
             processes: HashMap::new(),
+            logs_dir,
         })
     }
 
+    /// Load the state, change it with `change` and save it, holding the state
+    /// file's lock throughout so no other command's change comes in between.
+    ///
+    /// For short changes; commands that take a while should change a loaded
+    /// manager and [`save`](Self::save) it.
+    pub fn update<T>(state_file: &Path, change: impl FnOnce(&mut Self) -> T) -> Result<T> {
+        let _lock = StateLock::acquire(state_file)?;
+        let mut manager = Self::load(state_file)?;
+        let result = change(&mut manager);
+        manager.save_locked(state_file)?;
+        Ok(result)
+    }
+
+    /// Services in the state file whose process had died when it was loaded:
+    /// they crashed, or exited on their own. They stay in the state file, for
+    /// the crash hooks to be told, until [`forget_crashed`](Self::forget_crashed).
+    pub fn crashed(&self) -> &[(String, ServiceInfo)] {
+        &self.crashed
+    }
+
+    /// Drop the crashed services from the state file on the next save.
+    pub fn forget_crashed(&mut self) -> Vec<(String, ServiceInfo)> {
+        let crashed = std::mem::take(&mut self.crashed);
+        self.forgotten
+            .extend(crashed.iter().map(|(name, info)| (name.clone(), info.pid)));
+        crashed
+    }
+
     /// Save process state to a file.
+    ///
+    /// Only the services this manager started, stopped or changed since it
+    /// was loaded are written; the state file is re-read under its lock so
+    /// the changes other commands saved meanwhile are kept.
     pub fn save(&self, state_file: &Path) -> Result<()> {
-        let content = serde_json::to_string_pretty(&self.state)?;
-        fs::write(state_file, content)?;
+        let _lock = StateLock::acquire(state_file)?;
+        self.save_locked(state_file)
+    }
+
+    /// [`save`](Self::save), with the state file's lock already held.
+    fn save_locked(&self, state_file: &Path) -> Result<()> {
+        let mut on_disk = State::read(state_file)?;
+        let unchanged = on_disk.services.clone();
+        let mut loaded = self.loaded.lock().unwrap_or_else(|e| e.into_inner());
+        let names: BTreeSet<&String> = loaded.keys().chain(self.state.services.keys()).collect();
+        for name in names {
+            let before = loaded.get(name);
+            match self.state.services.get(name) {
+                Some(info) if before != Some(info) => {
+                    on_disk.services.insert(name.clone(), info.clone());
+                }
+                // Stopped here, unless another command started it again since
+                None if on_disk
+                    .services
+                    .get(name)
+                    .is_some_and(|info| before.is_some_and(|before| before.pid == info.pid)) =>
+                {
+                    on_disk.services.remove(name);
+                }
+                _ => {}
+            }
+        }
+        for (name, pid) in &self.forgotten {
+            if on_disk
+                .services
+                .get(name)
+                .is_some_and(|info| info.pid == *pid)
+            {
+                on_disk.services.remove(name);
+            }
+        }
+        // Health checks save often, mostly with nothing new
+        if on_disk.services != unchanged {
+            on_disk.write(state_file)?;
+        }
+        *loaded = self.state.services.clone();
         Ok(())
     }
 
@@ -87,25 +349,63 @@ impl ProcessManager {
         self.state.services.get(name).cloned()
     }
 
+    /// The file a service's output goes to, from its latest start.
+    pub fn log_file(&self, name: &str) -> PathBuf {
+        self.logs_dir.join(format!("{}.log", name))
+    }
+
+    /// The file the output of a service's previous instance is kept in once
+    /// an upgrade replaced it.
+    pub fn previous_log_file(&self, name: &str) -> PathBuf {
+        self.logs_dir.join(format!("{}.previous.log", name))
+    }
+
+    /// The file the trial instance of an upgrade writes to, leaving the log
+    /// of the instance still running alone.
+    pub fn trial_log_file(&self, name: &str) -> PathBuf {
+        self.logs_dir.join(format!("{}.upgrade.log", name))
+    }
+
     /// Start a service on the given port.
     ///
     /// Returns the process ID of the started service.
     pub async fn start(&mut self, service: &ServiceDefinition, port: u16) -> Result<u32> {
+        self.start_with(service, port, StartOptions::default())
+            .await
+    }
+
+    /// Start a service on the given port with additional options.
+    ///
+    /// Returns the process ID of the started service.
+    pub async fn start_with(
+        &mut self,
+        service: &ServiceDefinition,
+        port: u16,
+        options: StartOptions,
+    ) -> Result<u32> {
         // Install mise tools if .mise.toml exists
         mise::install_tools(&service.path)?;
 
         // Build command, wrapping with mise if .mise.toml exists
         let mut cmd = mise::build_command(&service.path, &service.server.command)?;
 
+        fs::create_dir_all(&self.logs_dir)?;
+        let log_file = options
+            .log_file
+            .unwrap_or_else(|| self.log_file(&service.name));
+        let log = fs::File::create(log_file)?;
         cmd.current_dir(service.path.join("server"))
             .env("PORT", port.to_string())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null());
+            .stdout(log.try_clone()?)
+            .stderr(log);
 
-        // Add any configured environment variables
+        // Add any configured environment variables, then per-start overrides
         for (key, value) in &service.server.env {
             cmd.env(key, value);
         }
+        for (key, value) in &options.env {
+            cmd.env(key, value);
+        }
 
         let child = cmd.spawn()?;
         let pid = child.id();
@@ -115,6 +415,17 @@ impl ProcessManager {
             port,
             started_at: chrono_lite_now(),
             service_path: service.path.display().to_string(),
+            config_fingerprint: reload::fingerprint(&service.path),
+            tags: Tags::new(),
+            env_overrides: options.env,
+            seed: None,
+            seed_history: Vec::new(),
+            namespaces: Vec::new(),
+            restarts: 0,
+            health_history: Vec::new(),
+            failed_checks: 0,
+            project_config: None,
+            project_fingerprint: None,
         };
 
         self.state.services.insert(service.name.clone(), info);
@@ -123,6 +434,153 @@ impl ProcessManager {
         Ok(pid)
     }
 
+    /// Replace the tags of a running service.
+    pub fn set_tags(&mut self, name: &str, tags: Tags) {
+        if let Some(info) = self.state.services.get_mut(name) {
+            info.tags = tags;
+        }
+    }
+
+    /// Record the seed last applied to a running service.
+    pub fn set_seed(&mut self, name: &str, seed: Option<String>) {
+        if let Some(info) = self.state.services.get_mut(name) {
+            info.seed = seed;
+        }
+    }
+
+    /// Add a seed to a running service's history.
+    ///
+    /// Only the most recent [`SEED_UNDO_LIMIT`] seeds keep the state they
+    /// replaced, which keeps the state file small.
+    pub fn record_seed(&mut self, name: &str, record: SeedRecord) {
+        if let Some(info) = self.state.services.get_mut(name) {
+            info.seed_history.push(record);
+            let len = info.seed_history.len();
+            if len > SEED_HISTORY_LIMIT {
+                info.seed_history.drain(..len - SEED_HISTORY_LIMIT);
+            }
+            let len = info.seed_history.len();
+            for record in &mut info.seed_history[..len.saturating_sub(SEED_UNDO_LIMIT)] {
+                record.prior = None;
+                record.prior_error = None;
+            }
+        }
+    }
+
+    /// Remove the most recent seed from a running service's history.
+    pub fn pop_seed(&mut self, name: &str) -> Option<SeedRecord> {
+        self.state.services.get_mut(name)?.seed_history.pop()
+    }
+
+    /// Forget the seeds applied to a running service, as after a reset.
+    pub fn clear_seed_history(&mut self, name: &str) {
+        if let Some(info) = self.state.services.get_mut(name) {
+            info.seed_history.clear();
+        }
+    }
+
+    /// Remember a namespace minted for a running service.
+    pub fn add_namespace(&mut self, name: &str, namespace: &str) {
+        if let Some(info) = self.state.services.get_mut(name) {
+            info.namespaces.push(namespace.to_string());
+        }
+    }
+
+    /// Forget a namespace of a running service, returning whether it had it.
+    pub fn remove_namespace(&mut self, name: &str, namespace: &str) -> bool {
+        let Some(info) = self.state.services.get_mut(name) else {
+            return false;
+        };
+        let before = info.namespaces.len();
+        info.namespaces.retain(|ns| ns != namespace);
+        info.namespaces.len() < before
+    }
+
+    /// Count a restart of a service, on top of those of the instance it replaced,
+    /// keeping the project config that instance was converged on.
+    pub fn record_restart(&mut self, name: &str, previous: &ServiceInfo) {
+        if let Some(info) = self.state.services.get_mut(name) {
+            info.restarts = previous.restarts + 1;
+            info.project_config = previous.project_config.clone();
+            info.project_fingerprint = previous.project_fingerprint.clone();
+        }
+    }
+
+    /// Record the project config a running service was converged on, with
+    /// the fingerprint of its entry there (see `reload::project_fingerprint`).
+    pub fn set_project(&mut self, name: &str, path: &Path, fingerprint: Option<String>) {
+        if let Some(info) = self.state.services.get_mut(name) {
+            info.project_config = Some(path.display().to_string());
+            info.project_fingerprint = fingerprint;
+        }
+    }
+
+    /// Record the outcome of a health check of a running service, returning
+    /// whether it changed the service's health or its streak of failed checks.
+    ///
+    /// A service first seen healthy is taken to have been so since it started.
+    pub fn record_health(&mut self, name: &str, healthy: bool) -> bool {
+        let Some(info) = self.state.services.get_mut(name) else {
+            return false;
+        };
+        let failed_checks = if healthy { 0 } else { info.failed_checks + 1 };
+        let counted = failed_checks != info.failed_checks;
+        info.failed_checks = failed_checks;
+        if info.health().is_some_and(|h| h.healthy == healthy) {
+            return counted;
+        }
+        let at = if info.health_history.is_empty() && healthy {
+            info.started_at.clone()
+        } else {
+            chrono_lite_now()
+        };
+        info.health_history.push(HealthChange { healthy, at });
+        let len = info.health_history.len();
+        if len > HEALTH_HISTORY_LIMIT {
+            info.health_history.drain(..len - HEALTH_HISTORY_LIMIT);
+        }
+        true
+    }
+
+    /// Track an already running process under the given service name.
+    ///
+    /// Replaces any existing entry for the service.
+    pub fn track(&mut self, name: &str, info: ServiceInfo) {
+        self.state.services.insert(name.to_string(), info);
+        self.processes.remove(name);
+    }
+
+    /// Terminate a process that is no longer tracked, such as a replaced instance.
+    pub fn terminate(pid: u32) -> Result<()> {
+        Self::kill_process(pid)
+    }
+
+    /// Replace the `old` instance of a service, no longer tracked, with
+    /// `service` on the same port, so clients keep the URL they have.
+    ///
+    /// The old instance's log is kept as the
+    /// [previous log file](Self::previous_log_file). Returns the process ID
+    /// of the new instance once it is healthy.
+    pub async fn replace_in_place(
+        &mut self,
+        old: &ServiceInfo,
+        service: &ServiceDefinition,
+        options: StartOptions,
+        health: &HealthSettings,
+    ) -> Result<u32> {
+        Self::kill_process(old.pid)?;
+        ports::wait_until_free(old.port, health.startup_timeout).await?;
+
+        let log_file = self.log_file(&service.name);
+        if log_file.exists() {
+            fs::rename(&log_file, self.previous_log_file(&service.name))?;
+        }
+        let pid = self.start_with(service, old.port, options).await?;
+        self.wait_for_health(&service.name, old.port, health)
+            .await?;
+        Ok(pid)
+    }
+
     /// Stop a running service.
     pub async fn stop(&mut self, name: &str) -> Result<()> {
         if let Some(info) = self.state.services.remove(name) {
@@ -134,21 +592,25 @@ impl ProcessManager {
 
     /// Wait for a service to become healthy.
     ///
-    /// Polls the health endpoint until it returns success or the timeout is reached.
-    pub async fn wait_for_health(&self, name: &str, port: u16, timeout_secs: u64) -> Result<()> {
+    /// Polls the health endpoint until it returns success or the startup timeout is reached.
+    pub async fn wait_for_health(
+        &self,
+        name: &str,
+        port: u16,
+        health: &HealthSettings,
+    ) -> Result<()> {
         let url = format!("http://localhost:{}/_doubleagent/health", port);
         let client = reqwest::Client::new();
         let start = Instant::now();
-        let timeout = Duration::from_secs(timeout_secs);
 
         loop {
-            if start.elapsed() > timeout {
-                return Err(Error::HealthCheckTimeout(timeout_secs));
+            if start.elapsed() > health.startup_timeout {
+                return Err(Error::HealthCheckTimeout(health.startup_timeout.as_secs()));
             }
 
             match client
                 .get(&url)
-                .timeout(Duration::from_secs(2))
+                .timeout(health.request_timeout)
                 .send()
                 .await
             {
@@ -162,21 +624,21 @@ impl ProcessManager {
                             return Err(Error::ServiceProcessDied);
                         }
                     }
-                    tokio::time::sleep(Duration::from_millis(500)).await;
+                    tokio::time::sleep(health.poll_interval).await;
                 }
             }
         }
     }
 
     /// Check if a service is healthy (async).
-    pub async fn check_health(&self, name: &str) -> bool {
+    pub async fn check_health(&self, name: &str, health: &HealthSettings) -> bool {
         if let Some(info) = self.state.services.get(name) {
             let url = format!("http://localhost:{}/_doubleagent/health", info.port);
             let client = reqwest::Client::new();
 
             match client
                 .get(&url)
-                .timeout(Duration::from_secs(2))
+                .timeout(health.request_timeout)
                 .send()
                 .await
             {
@@ -205,6 +667,12 @@ impl ProcessManager {
     }
 }
 
+/// Find a free TCP port on localhost by binding to port 0.
+pub fn free_port() -> Result<u16> {
+    let listener = std::net::TcpListener::bind(("127.0.0.1", 0))?;
+    Ok(listener.local_addr()?.port())
+}
+
 /// Get current timestamp as a string (without chrono dependency).
 fn chrono_lite_now() -> String {
     use std::time::SystemTime;
@@ -213,3 +681,216 @@ fn chrono_lite_now() -> String {
         .unwrap();
     format!("{}", duration.as_secs())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn info(started_at: &str) -> ServiceInfo {
+        ServiceInfo {
+            pid: std::process::id(),
+            port: 8080,
+            started_at: started_at.to_string(),
+            service_path: String::new(),
+            config_fingerprint: None,
+            tags: Tags::new(),
+            env_overrides: BTreeMap::new(),
+            seed: None,
+            seed_history: Vec::new(),
+            namespaces: Vec::new(),
+            restarts: 0,
+            health_history: Vec::new(),
+            failed_checks: 0,
+            project_config: None,
+            project_fingerprint: None,
+        }
+    }
+
+    #[test]
+    fn test_record_health() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut manager = ProcessManager::load(&temp.path().join("state.json")).unwrap();
+        manager.track("github", info("1700000000"));
+
+        // Healthy when first seen counts from the start
+        assert!(manager.record_health("github", true));
+        let info = manager.get_info("github").unwrap();
+        assert_eq!(info.health().unwrap().at, "1700000000");
+        assert!(!manager.record_health("github", true));
+
+        assert!(manager.record_health("github", false));
+        let info = manager.get_info("github").unwrap();
+        assert!(!info.health().unwrap().healthy);
+        assert_ne!(info.health().unwrap().at, "1700000000");
+        assert_eq!(info.health_history.len(), 2);
+        assert!(!manager.record_health("slack", true));
+
+        // Each failed check in a row is counted
+        assert!(manager.record_health("github", false));
+        assert_eq!(manager.get_info("github").unwrap().failed_checks, 2);
+        manager.record_health("github", true);
+        assert_eq!(manager.get_info("github").unwrap().failed_checks, 0);
+    }
+
+    #[test]
+    fn test_load_finds_crashed_services() {
+        let temp = tempfile::tempdir().unwrap();
+        let state_file = temp.path().join("state.json");
+        let mut manager = ProcessManager::load(&state_file).unwrap();
+        manager.track("github", info("1700000000"));
+        let mut dead = info("1700000000");
+        dead.pid = u32::MAX / 2;
+        manager.track("slack", dead);
+        manager.save(&state_file).unwrap();
+
+        let manager = ProcessManager::load(&state_file).unwrap();
+        assert_eq!(manager.running_services(), ["github"]);
+        assert_eq!(manager.crashed().len(), 1);
+        assert_eq!(manager.crashed()[0].0, "slack");
+    }
+
+    #[test]
+    fn test_crashed_services_are_kept_until_forgotten() {
+        let temp = tempfile::tempdir().unwrap();
+        let state_file = temp.path().join("state.json");
+        let mut manager = ProcessManager::load(&state_file).unwrap();
+        let mut dead = info("1700000000");
+        dead.pid = u32::MAX / 2;
+        manager.track("slack", dead);
+        manager.save(&state_file).unwrap();
+
+        ProcessManager::load(&state_file)
+            .unwrap()
+            .save(&state_file)
+            .unwrap();
+        assert_eq!(
+            ProcessManager::load(&state_file).unwrap().crashed().len(),
+            1
+        );
+
+        let forgotten =
+            ProcessManager::update(&state_file, |manager| manager.forget_crashed()).unwrap();
+        assert_eq!(forgotten.len(), 1);
+        assert!(ProcessManager::load(&state_file)
+            .unwrap()
+            .crashed()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_save_keeps_changes_saved_meanwhile() {
+        let temp = tempfile::tempdir().unwrap();
+        let state_file = temp.path().join("state.json");
+        let mut manager = ProcessManager::load(&state_file).unwrap();
+        manager.track("github", info("1700000000"));
+        manager.track("slack", info("1700000000"));
+        manager.save(&state_file).unwrap();
+
+        // Two commands load the same state and each change one service
+        let mut first = ProcessManager::load(&state_file).unwrap();
+        let mut second = ProcessManager::load(&state_file).unwrap();
+        first.set_tags(
+            "github",
+            [("team".to_string(), "payments".to_string())].into(),
+        );
+        first.save(&state_file).unwrap();
+        second.state.services.remove("slack");
+        second.track("jira", info("1700000100"));
+        second.save(&state_file).unwrap();
+
+        let manager = ProcessManager::load(&state_file).unwrap();
+        let mut running = manager.running_services();
+        running.sort();
+        assert_eq!(running, ["github", "jira"]);
+        assert_eq!(manager.get_info("github").unwrap().tags.len(), 1);
+    }
+
+    #[test]
+    fn test_load_rejects_a_corrupt_state_file() {
+        let temp = tempfile::tempdir().unwrap();
+        let state_file = temp.path().join("state.json");
+        fs::write(&state_file, "{ not json").unwrap();
+        let error = ProcessManager::load(&state_file).err().unwrap();
+        assert!(error.to_string().contains("is corrupt"));
+        assert!(ProcessManager::update(&state_file, |_| ()).is_err());
+        assert_eq!(fs::read_to_string(&state_file).unwrap(), "{ not json");
+    }
+
+    #[test]
+    fn test_record_restart() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut manager = ProcessManager::load(&temp.path().join("state.json")).unwrap();
+        let mut previous = info("1700000000");
+        previous.restarts = 2;
+        manager.track("github", info("1700000100"));
+        manager.record_restart("github", &previous);
+        assert_eq!(manager.get_info("github").unwrap().restarts, 3);
+    }
+
+    /// A service serving its server directory, which answers `/version`.
+    fn http_service(dir: &Path, version: &str) -> ServiceDefinition {
+        let server = dir.join("server");
+        fs::create_dir_all(server.join("_doubleagent")).unwrap();
+        fs::write(server.join("_doubleagent/health"), "ok").unwrap();
+        fs::write(server.join("version"), version).unwrap();
+        fs::write(
+            server.join("serve.py"),
+            "import http.server, os\n\
+             print('serving version', open('version').read(), flush=True)\n\
+             http.server.ThreadingHTTPServer(('127.0.0.1', int(os.environ['PORT'])), \
+             http.server.SimpleHTTPRequestHandler).serve_forever()\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("service.yaml"),
+            "name: fake\nserver:\n  command: [python3, serve.py]\n",
+        )
+        .unwrap();
+        ServiceDefinition::from_dir(dir).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_replace_in_place_keeps_the_url_and_the_old_log() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut manager = ProcessManager::load(&temp.path().join("state.json")).unwrap();
+        let health = HealthSettings {
+            poll_interval: Duration::from_millis(50),
+            request_timeout: Duration::from_secs(1),
+            startup_timeout: Duration::from_secs(10),
+        };
+        let port = free_port().unwrap();
+        let version = || async move {
+            reqwest::get(format!("http://127.0.0.1:{}/version", port))
+                .await
+                .unwrap()
+                .text()
+                .await
+                .unwrap()
+        };
+
+        let v1 = http_service(&temp.path().join("v1"), "1");
+        manager.start(&v1, port).await.unwrap();
+        manager
+            .wait_for_health("fake", port, &health)
+            .await
+            .unwrap();
+        assert_eq!(version().await, "1");
+
+        let old = manager.get_info("fake").unwrap();
+        let v2 = http_service(&temp.path().join("v2"), "2");
+        let replaced = manager
+            .replace_in_place(&old, &v2, StartOptions::default(), &health)
+            .await;
+        let served = version().await;
+        manager.stop("fake").await.unwrap();
+        assert_ne!(replaced.unwrap(), old.pid);
+        assert_eq!(served, "2");
+
+        let log = fs::read_to_string(manager.log_file("fake")).unwrap();
+        let previous = fs::read_to_string(manager.previous_log_file("fake")).unwrap();
+        assert!(log.starts_with("serving version 2"), "{}", log);
+        assert!(previous.starts_with("serving version 1"), "{}", previous);
+        assert!(previous.contains("GET /version"), "{}", previous);
+    }
+}
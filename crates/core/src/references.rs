@@ -0,0 +1,339 @@
+//! Symbolic references between the resources of a seed payload.
+//!
+//! Records may refer to a value of another resource's record instead of
+//! hard-coding ids the fake assigns:
+//!
+//! ```yaml
+//! repos:
+//!   - owner: acme
+//!     name: api
+//! issues:
+//!   - title: Flaky test
+//!     repo_id: { $ref: "repos[0].id" }
+//! ```
+//!
+//! A reference is `<resource>[<index>]` followed by a `.` separated path into
+//! that record (numbers index arrays). Payloads with references are seeded in
+//! dependency order, one call per stage, and each reference is replaced by the
+//! value from the record the fake created, which it returns under `records`
+//! in its seed response. Fakes that don't return records can still resolve
+//! references to values given in the seed file itself.
+
+use crate::{Error, Result};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Key of a reference object.
+pub const REF_KEY: &str = "$ref";
+
+/// A parsed `repos[0].id` reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Reference {
+    resource: String,
+    index: usize,
+    path: Vec<String>,
+}
+
+impl Reference {
+    fn parse(text: &str) -> Result<Self> {
+        let invalid = || {
+            Error::Other(format!(
+                "Invalid $ref '{}', expected <resource>[<index>].<field>",
+                text
+            ))
+        };
+        let (resource, rest) = text.split_once('[').ok_or_else(invalid)?;
+        let (index, path) = rest.split_once(']').ok_or_else(invalid)?;
+        let index = index.trim().parse().map_err(|_| invalid())?;
+        let path = match path {
+            "" => Vec::new(),
+            path => {
+                let path = path.strip_prefix('.').ok_or_else(invalid)?;
+                let segments: Vec<String> = path.split('.').map(str::to_string).collect();
+                if segments.iter().any(String::is_empty) {
+                    return Err(invalid());
+                }
+                segments
+            }
+        };
+        let resource = resource.trim();
+        if resource.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            resource: resource.to_string(),
+            index,
+            path,
+        })
+    }
+}
+
+/// The text of a `{$ref: ...}` object, if `value` is one.
+fn reference(value: &Value) -> Option<&str> {
+    match value {
+        Value::Object(map) if map.len() == 1 => map.get(REF_KEY)?.as_str(),
+        _ => None,
+    }
+}
+
+fn collect(value: &Value, refs: &mut Vec<Reference>) -> Result<()> {
+    if let Some(text) = reference(value) {
+        refs.push(Reference::parse(text)?);
+        return Ok(());
+    }
+    match value {
+        Value::Array(items) => items.iter().try_for_each(|item| collect(item, refs)),
+        Value::Object(map) => map.values().try_for_each(|item| collect(item, refs)),
+        _ => Ok(()),
+    }
+}
+
+/// JSON pointers to every reference in a payload, e.g. `/issues/0/repo_id`.
+///
+/// Checks of a payload before it is seeded use them to tell references,
+/// which only hold their final value once seeded, from mistakes.
+pub fn pointers(payload: &Value) -> Vec<String> {
+    fn walk(value: &Value, pointer: String, found: &mut Vec<String>) {
+        if reference(value).is_some() {
+            found.push(pointer);
+            return;
+        }
+        match value {
+            Value::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    walk(item, format!("{}/{}", pointer, i), found);
+                }
+            }
+            Value::Object(map) => {
+                for (key, item) in map {
+                    let key = key.replace('~', "~0").replace('/', "~1");
+                    walk(item, format!("{}/{}", pointer, key), found);
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut found = Vec::new();
+    walk(payload, String::new(), &mut found);
+    found
+}
+
+/// The order to seed a payload's resources in, as stages of resources that
+/// only reference resources of earlier stages.
+///
+/// Returns `None` when the payload has no references, so it can be seeded in
+/// one call as it is.
+pub fn seed_order(payload: &Value) -> Result<Option<Vec<Vec<String>>>> {
+    let Value::Object(resources) = payload else {
+        return Ok(None);
+    };
+
+    let mut deps: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+    let mut any = false;
+    for (resource, records) in resources {
+        let mut refs = Vec::new();
+        collect(records, &mut refs)?;
+        any |= !refs.is_empty();
+        for reference in refs {
+            if reference.resource == *resource {
+                return Err(Error::Other(format!(
+                    "{} references itself ({}[{}]), which can't be resolved before it is seeded",
+                    resource, reference.resource, reference.index
+                )));
+            }
+            if !resources.contains_key(&reference.resource) {
+                return Err(Error::Other(format!(
+                    "{} references {}, which isn't in the payload",
+                    resource, reference.resource
+                )));
+            }
+            deps.entry(resource).or_default().insert(reference.resource);
+        }
+    }
+    if !any {
+        return Ok(None);
+    }
+
+    let mut seeded: BTreeSet<&str> = BTreeSet::new();
+    let mut stages = Vec::new();
+    while seeded.len() < resources.len() {
+        let stage: Vec<&str> = resources
+            .keys()
+            .map(String::as_str)
+            .filter(|resource| !seeded.contains(resource))
+            .filter(|resource| {
+                deps.get(resource)
+                    .is_none_or(|deps| deps.iter().all(|dep| seeded.contains(dep.as_str())))
+            })
+            .collect();
+        if stage.is_empty() {
+            let cycle: Vec<&str> = resources
+                .keys()
+                .map(String::as_str)
+                .filter(|resource| !seeded.contains(resource))
+                .collect();
+            return Err(Error::Other(format!(
+                "References form a cycle between {}",
+                cycle.join(", ")
+            )));
+        }
+        seeded.extend(&stage);
+        stages.push(stage.into_iter().map(str::to_string).collect());
+    }
+    Ok(Some(stages))
+}
+
+/// Replace every reference in `records` with the value it points at.
+///
+/// References resolve against the records the fake returned in `created`,
+/// or against the (already resolved) records of `payload` for resources the
+/// fake didn't return.
+pub fn resolve(
+    records: &mut Value,
+    payload: &Map<String, Value>,
+    created: &BTreeMap<String, Vec<Value>>,
+) -> Result<()> {
+    if let Some(text) = reference(records) {
+        *records = lookup(&Reference::parse(text)?, text, payload, created)?;
+        return Ok(());
+    }
+    match records {
+        Value::Array(items) => items
+            .iter_mut()
+            .try_for_each(|item| resolve(item, payload, created)),
+        Value::Object(map) => map
+            .values_mut()
+            .try_for_each(|item| resolve(item, payload, created)),
+        _ => Ok(()),
+    }
+}
+
+fn lookup(
+    reference: &Reference,
+    text: &str,
+    payload: &Map<String, Value>,
+    created: &BTreeMap<String, Vec<Value>>,
+) -> Result<Value> {
+    let returned = created.get(&reference.resource);
+    let records = match returned {
+        Some(records) => records.as_slice(),
+        None => payload
+            .get(&reference.resource)
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default(),
+    };
+    let record = records.get(reference.index).ok_or_else(|| {
+        Error::Other(format!(
+            "Can't resolve $ref '{}': there are only {} {}",
+            text,
+            records.len(),
+            reference.resource
+        ))
+    })?;
+
+    let value = reference
+        .path
+        .iter()
+        .try_fold(record, |value, segment| match value {
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+            _ => value.get(segment),
+        });
+    match value {
+        Some(value) => Ok(value.clone()),
+        None if returned.is_none() => Err(Error::Other(format!(
+            "Can't resolve $ref '{}': the seed file doesn't set it, and the fake didn't \
+             return the {} it created (under \"records\" in its seed response)",
+            text, reference.resource
+        ))),
+        None => Err(Error::Other(format!(
+            "Can't resolve $ref '{}': {}[{}] has no '{}'",
+            text,
+            reference.resource,
+            reference.index,
+            reference.path.join(".")
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_seed_order_follows_references() {
+        assert_eq!(
+            seed_order(&json!({"repos": [{"name": "api"}]})).unwrap(),
+            None
+        );
+
+        let payload = json!({
+            "comments": [{"issue": {"$ref": "issues[0].id"}}],
+            "issues": [{"repo_id": {"$ref": "repos[0].id"}}],
+            "repos": [{"name": "api"}],
+            "users": [{"login": "octo"}],
+        });
+        assert_eq!(
+            seed_order(&payload).unwrap().unwrap(),
+            vec![
+                vec!["repos".to_string(), "users".to_string()],
+                vec!["issues".to_string()],
+                vec!["comments".to_string()],
+            ]
+        );
+
+        assert_eq!(
+            pointers(&payload),
+            ["/comments/0/issue", "/issues/0/repo_id"]
+        );
+
+        for (payload, error) in [
+            (
+                json!({"a": [{"x": {"$ref": "b[0].id"}}], "b": [{"y": {"$ref": "a[0].id"}}]}),
+                "cycle between a, b",
+            ),
+            (
+                json!({"a": [{"x": {"$ref": "a[1].id"}}]}),
+                "a references itself",
+            ),
+            (
+                json!({"a": [{"x": {"$ref": "b[0].id"}}]}),
+                "isn't in the payload",
+            ),
+            (json!({"a": [{"x": {"$ref": "b.id"}}]}), "Invalid $ref"),
+        ] {
+            let msg = seed_order(&payload).unwrap_err().to_string();
+            assert!(msg.contains(error), "{}", msg);
+        }
+    }
+
+    #[test]
+    fn test_resolve_prefers_created_records() {
+        let payload = json!({"repos": [{"name": "api", "owner": {"login": "acme"}}]});
+        let payload = payload.as_object().unwrap();
+        let mut issues = json!([{
+            "repo_id": {"$ref": "repos[0].id"},
+            "owner": {"$ref": "repos[0].owner.login"},
+        }]);
+
+        let created = BTreeMap::new();
+        let msg = resolve(&mut issues.clone(), payload, &created)
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("didn't return the repos"), "{}", msg);
+
+        let created = BTreeMap::from([(
+            "repos".to_string(),
+            vec![json!({"id": 42, "owner": {"login": "acme"}})],
+        )]);
+        resolve(&mut issues, payload, &created).unwrap();
+        assert_eq!(issues, json!([{"repo_id": 42, "owner": "acme"}]));
+
+        let mut missing = json!({"repo": {"$ref": "repos[3].id"}});
+        let msg = resolve(&mut missing, payload, &created)
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("there are only 1 repos"), "{}", msg);
+    }
+}
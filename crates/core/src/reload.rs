@@ -0,0 +1,252 @@
+//! Detection of service definition changes for running services.
+//!
+//! When a service starts, a fingerprint of its service.yaml is recorded in the
+//! state file. Comparing it against the file on disk tells `status` whether the
+//! running process is using a stale definition and needs a restart to apply it.
+//!
+//! Services started by `up` also record a fingerprint of their entry in the
+//! project config (doubleagent.yaml), taken after merging the configs it
+//! `extends`, so a change to either file shows up the same way.
+
+use crate::extends;
+use crate::process::ServiceInfo;
+use crate::service::ServiceDefinition;
+use crate::Result;
+use serde_yaml::Value;
+use std::fs;
+use std::path::Path;
+
+/// How a running service's definition compares to what is on disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DefinitionStatus {
+    /// The running process uses the current definition.
+    Current,
+    /// The definition changed on disk and a restart is needed to apply it.
+    Changed,
+    /// The definition changed on disk but no longer parses.
+    Invalid(String),
+    /// No fingerprint was recorded when the service started.
+    Unknown,
+}
+
+/// Compute a stable fingerprint of a service's service.yaml.
+///
+/// Returns None if the file cannot be read.
+pub fn fingerprint(service_dir: &Path) -> Option<String> {
+    let content = fs::read(service_dir.join("service.yaml")).ok()?;
+    Some(fnv1a_hex(&content))
+}
+
+/// Compare the fingerprint recorded for a running service with its definition on disk.
+pub fn definition_status(info: &ServiceInfo) -> DefinitionStatus {
+    let Some(recorded) = &info.config_fingerprint else {
+        return DefinitionStatus::Unknown;
+    };
+
+    let service_dir = Path::new(&info.service_path);
+    match fingerprint(service_dir) {
+        Some(current) if &current == recorded => DefinitionStatus::Current,
+        Some(_) => match ServiceDefinition::from_dir(service_dir) {
+            Ok(_) => DefinitionStatus::Changed,
+            Err(e) => DefinitionStatus::Invalid(e.to_string()),
+        },
+        None => DefinitionStatus::Invalid(format!(
+            "service.yaml not found at {}",
+            service_dir.display()
+        )),
+    }
+}
+
+/// Compute a stable fingerprint of what a project config, merged with the
+/// configs it `extends`, declares for one service: its entry under `services`
+/// and its `seeds`. A service the config no longer lists gets a fingerprint too.
+pub fn project_fingerprint(path: &Path, cache_dir: &Path, service: &str) -> Result<String> {
+    let project = extends::load(path, cache_dir)?;
+    let entry = match project.get("services") {
+        Some(Value::Sequence(names)) => names
+            .iter()
+            .any(|name| name.as_str() == Some(service))
+            .then(|| Value::String(service.to_string())),
+        Some(Value::Mapping(services)) => services.get(service).cloned(),
+        _ => None,
+    };
+    let seeds = project.get("seeds").and_then(|seeds| seeds.get(service));
+    let content = serde_yaml::to_string(&(entry, seeds))?;
+    Ok(fnv1a_hex(content.as_bytes()))
+}
+
+/// Compare the project config fingerprint recorded for a running service with
+/// the project config (and the configs it extends) on disk.
+pub fn project_status(info: &ServiceInfo, cache_dir: &Path, service: &str) -> DefinitionStatus {
+    let (Some(path), Some(recorded)) = (&info.project_config, &info.project_fingerprint) else {
+        return DefinitionStatus::Unknown;
+    };
+
+    match project_fingerprint(Path::new(path), cache_dir, service) {
+        Ok(current) if &current == recorded => DefinitionStatus::Current,
+        Ok(_) => DefinitionStatus::Changed,
+        Err(e) => DefinitionStatus::Invalid(e.to_string()),
+    }
+}
+
+/// 64-bit FNV-1a hash, hex encoded (stable across Rust versions, unlike DefaultHasher).
+pub(crate) fn fnv1a_hex(bytes: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const SERVICE_YAML: &str = "name: test\nserver:\n  command: [\"python\", \"main.py\"]\n";
+
+    fn info_for(dir: &Path, fingerprint: Option<String>) -> ServiceInfo {
+        ServiceInfo {
+            pid: 1,
+            port: 8080,
+            started_at: "0".to_string(),
+            service_path: dir.display().to_string(),
+            config_fingerprint: fingerprint,
+            tags: Default::default(),
+            env_overrides: Default::default(),
+            seed: None,
+            seed_history: Vec::new(),
+            namespaces: Vec::new(),
+            restarts: 0,
+            health_history: Vec::new(),
+            failed_checks: 0,
+            project_config: None,
+            project_fingerprint: None,
+        }
+    }
+
+    #[test]
+    fn test_fingerprint_is_stable() {
+        assert_eq!(fnv1a_hex(b""), "cbf29ce484222325");
+        assert_eq!(fnv1a_hex(b"a"), "af63dc4c8601ec8c");
+    }
+
+    #[test]
+    fn test_definition_status_current() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("service.yaml"), SERVICE_YAML).unwrap();
+        let info = info_for(dir.path(), fingerprint(dir.path()));
+
+        assert_eq!(definition_status(&info), DefinitionStatus::Current);
+    }
+
+    #[test]
+    fn test_definition_status_changed() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("service.yaml"), SERVICE_YAML).unwrap();
+        let info = info_for(dir.path(), fingerprint(dir.path()));
+
+        fs::write(
+            dir.path().join("service.yaml"),
+            format!("{}version: \"2.0\"\n", SERVICE_YAML),
+        )
+        .unwrap();
+
+        assert_eq!(definition_status(&info), DefinitionStatus::Changed);
+    }
+
+    #[test]
+    fn test_definition_status_invalid() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("service.yaml"), SERVICE_YAML).unwrap();
+        let info = info_for(dir.path(), fingerprint(dir.path()));
+
+        fs::write(dir.path().join("service.yaml"), "name: [unclosed").unwrap();
+
+        assert!(matches!(
+            definition_status(&info),
+            DefinitionStatus::Invalid(_)
+        ));
+    }
+
+    #[test]
+    fn test_definition_status_unknown_without_fingerprint() {
+        let dir = tempdir().unwrap();
+        let info = info_for(dir.path(), None);
+
+        assert_eq!(definition_status(&info), DefinitionStatus::Unknown);
+    }
+
+    fn project_info(dir: &Path, service: &str) -> ServiceInfo {
+        let path = dir.join("doubleagent.yaml");
+        let mut info = info_for(dir, None);
+        info.project_fingerprint = Some(project_fingerprint(&path, dir, service).unwrap());
+        info.project_config = Some(path.display().to_string());
+        info
+    }
+
+    #[test]
+    fn test_project_status_sees_changes_to_the_service_entry() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("doubleagent.yaml");
+        fs::write(&path, "services:\n  github: {}\n  slack: {}\n").unwrap();
+        let info = project_info(dir.path(), "github");
+
+        fs::write(
+            &path,
+            "services:\n  github: {}\n  slack:\n    env: {A: b}\n",
+        )
+        .unwrap();
+        assert_eq!(
+            project_status(&info, dir.path(), "github"),
+            DefinitionStatus::Current
+        );
+
+        fs::write(&path, "services:\n  github:\n    env: {A: b}\n").unwrap();
+        assert_eq!(
+            project_status(&info, dir.path(), "github"),
+            DefinitionStatus::Changed
+        );
+    }
+
+    #[test]
+    fn test_project_status_sees_changes_to_a_base_config() {
+        let dir = tempdir().unwrap();
+        let base = dir.path().join("base.yaml");
+        fs::write(&base, "services:\n  github:\n    fixture: startup\n").unwrap();
+        fs::write(
+            dir.path().join("doubleagent.yaml"),
+            "extends: base.yaml\nservices:\n  github: {}\n",
+        )
+        .unwrap();
+        let info = project_info(dir.path(), "github");
+        assert_eq!(
+            project_status(&info, dir.path(), "github"),
+            DefinitionStatus::Current
+        );
+
+        fs::write(&base, "services:\n  github:\n    fixture: empty\n").unwrap();
+        assert_eq!(
+            project_status(&info, dir.path(), "github"),
+            DefinitionStatus::Changed
+        );
+
+        fs::write(&base, "services: [unclosed").unwrap();
+        assert!(matches!(
+            project_status(&info, dir.path(), "github"),
+            DefinitionStatus::Invalid(_)
+        ));
+    }
+
+    #[test]
+    fn test_project_status_unknown_without_fingerprint() {
+        let dir = tempdir().unwrap();
+        let info = info_for(dir.path(), None);
+
+        assert_eq!(
+            project_status(&info, dir.path(), "github"),
+            DefinitionStatus::Unknown
+        );
+    }
+}
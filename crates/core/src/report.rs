@@ -0,0 +1,595 @@
+//! Human-readable contract reports, for `doubleagent contract --report
+//! markdown`, `--report html` and `--report github`.
+//!
+//! Markdown and HTML hold the same content: a summary table of every service
+//! and target, then a section per service with its failures (message, and the
+//! traceback or diff behind it), its slowest tests and, when measured, its
+//! endpoint coverage. Markdown suits a pull request comment or a CI job
+//! summary; HTML is a single self-contained page for a CI artifact.
+//!
+//! For GitHub Actions, each failed test becomes an `::error` workflow command
+//! pointing at the test's file and line, so that it shows on the pull
+//! request's diff, and the summary table becomes the job summary.
+
+use crate::contract::{escape, ContractReport, Outcome, TargetResults, TestCase};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Tests listed as the slowest of each service.
+const SLOWEST: usize = 5;
+
+/// Endpoints never requested listed in a service's coverage before the rest are counted.
+const MAX_MISSED: usize = 20;
+
+/// Whether every target of a service passed.
+fn passed(report: &ContractReport) -> bool {
+    report.targets.iter().all(|t| t.success)
+}
+
+/// Tests that failed or errored, with the target they ran against.
+fn failures(report: &ContractReport) -> Vec<(&TargetResults, &TestCase)> {
+    report
+        .targets
+        .iter()
+        .flat_map(|target| target.tests.iter().map(move |test| (target, test)))
+        .filter(|(_, test)| test.failed())
+        .collect()
+}
+
+/// The slowest tests of a service, slowest first.
+fn slowest(report: &ContractReport) -> Vec<(&TargetResults, &TestCase)> {
+    let mut tests: Vec<_> = report
+        .targets
+        .iter()
+        .flat_map(|target| target.tests.iter().map(move |test| (target, test)))
+        .filter(|(_, test)| test.time > 0.0)
+        .collect();
+    tests.sort_by(|a, b| b.1.time.total_cmp(&a.1.time));
+    tests.truncate(SLOWEST);
+    tests
+}
+
+fn test_name(test: &TestCase) -> String {
+    if test.classname.is_empty() {
+        test.name.clone()
+    } else {
+        format!("{}::{}", test.classname, test.name)
+    }
+}
+
+fn seconds(time: f64) -> String {
+    format!("{:.2}s", time)
+}
+
+/// The reports as Markdown.
+pub fn to_markdown(reports: &[ContractReport]) -> String {
+    let mut md = String::from("# Contract test report\n\n");
+    summary_table(reports, &mut md);
+
+    for report in reports {
+        let _ = write!(
+            md,
+            "\n## {} {}\n",
+            if passed(report) { "✅" } else { "❌" },
+            report.service
+        );
+
+        let failures = failures(report);
+        if !failures.is_empty() {
+            md.push_str("\n### Failures\n");
+            for (target, test) in failures {
+                let _ = writeln!(md, "\n**{}** ({})", code(&test_name(test)), target.label());
+                if let Some(message) = &test.message {
+                    let _ = writeln!(md, "\n{}", fence(message));
+                }
+                if let Some(details) = &test.details {
+                    let _ = writeln!(
+                        md,
+                        "\n<details><summary>Details</summary>\n\n{}\n\n</details>",
+                        fence(details)
+                    );
+                }
+            }
+        } else if report.targets.iter().any(|t| !t.success) {
+            md.push_str(
+                "\nThe test command failed without reporting a failing test; see its output.\n",
+            );
+        }
+
+        let slowest = slowest(report);
+        if !slowest.is_empty() {
+            md.push_str("\n### Slowest tests\n\n| Test | Target | Time |\n|---|---|---:|\n");
+            for (target, test) in slowest {
+                let _ = writeln!(
+                    md,
+                    "| {} | {} | {} |",
+                    cell(&code(&test_name(test))),
+                    cell(&target.label()),
+                    seconds(test.time)
+                );
+            }
+        }
+
+        if let Some(coverage) = &report.coverage {
+            let _ = write!(
+                md,
+                "\n### Coverage\n\n{} of {} endpoints requested ({:.1}%).\n",
+                coverage.hit, coverage.declared, coverage.percent
+            );
+            let missed: Vec<_> = coverage.endpoints.iter().filter(|e| e.hits == 0).collect();
+            if !missed.is_empty() {
+                md.push_str("\nNever requested:\n\n");
+                for hits in missed.iter().take(MAX_MISSED) {
+                    let _ = writeln!(
+                        md,
+                        "- {}",
+                        code(&format!("{} {}", hits.endpoint.method, hits.endpoint.path))
+                    );
+                }
+                if missed.len() > MAX_MISSED {
+                    let _ = writeln!(md, "- ... and {} more", missed.len() - MAX_MISSED);
+                }
+            }
+        }
+    }
+    md
+}
+
+/// A Markdown table of every service and target.
+fn summary_table(reports: &[ContractReport], md: &mut String) {
+    md.push_str("| | Service | Target | Passed | Failed | Skipped | Flaky | Time |\n");
+    md.push_str("|---|---|---|---:|---:|---:|---:|---:|\n");
+    for report in reports {
+        for target in &report.targets {
+            let _ = writeln!(
+                md,
+                "| {} | {} | {} | {} | {} | {} | {} | {} |",
+                if target.success { "✅" } else { "❌" },
+                cell(&report.service),
+                cell(&target.label()),
+                target.count(Outcome::Passed),
+                target.count(Outcome::Failed) + target.count(Outcome::Error),
+                target.count(Outcome::Skipped),
+                target.flaky(),
+                seconds(target.time)
+            );
+        }
+    }
+}
+
+/// GitHub Actions workflow commands annotating each failed test, as an
+/// `::error`, and each flaky one, as a `::warning`.
+///
+/// `dirs` holds the directory of each service's tests relative to the
+/// repository root; the tests of services without one are annotated without
+/// a file.
+pub fn github_annotations(reports: &[ContractReport], dirs: &BTreeMap<String, String>) -> String {
+    let mut out = String::new();
+    for report in reports {
+        let dir = dirs.get(&report.service);
+        for target in &report.targets {
+            let title = format!("{} ({})", report.service, target.label());
+            for test in &target.tests {
+                let level = if test.failed() {
+                    "error"
+                } else if test.flaky() {
+                    "warning"
+                } else {
+                    continue;
+                };
+                let mut properties = Vec::new();
+                if let (Some(dir), (Some(file), line)) = (dir, location(test)) {
+                    let file = format!("{}/{}", dir.trim_end_matches('/'), file);
+                    properties.push(format!("file={}", property(&file)));
+                    if let Some(line) = line {
+                        properties.push(format!("line={}", line));
+                    }
+                }
+                properties.push(format!(
+                    "title={}",
+                    property(&format!("{}: {}", title, test_name(test)))
+                ));
+                let message = match &test.message {
+                    _ if test.flaky() => format!("passed after {} retries", test.retries),
+                    Some(message) => message.clone(),
+                    None => format!("{:?}", test.outcome).to_lowercase(),
+                };
+                let _ = writeln!(
+                    out,
+                    "::{} {}::{}",
+                    level,
+                    properties.join(","),
+                    data(&message)
+                );
+            }
+            if !target.success && !target.tests.iter().any(TestCase::failed) {
+                let _ = writeln!(
+                    out,
+                    "::error title={}::The test command failed without reporting a failing test; \
+                     see its output.",
+                    property(&title)
+                );
+            }
+        }
+    }
+    out
+}
+
+/// The GitHub Actions job summary: the table of every service and target.
+pub fn github_summary(reports: &[ContractReport]) -> String {
+    let mut md = String::from("## Contract tests\n\n");
+    summary_table(reports, &mut md);
+    let failed: usize = reports.iter().map(|report| failures(report).len()).sum();
+    if failed > 0 {
+        let _ = writeln!(
+            md,
+            "\n{} failed {}; see the annotations.",
+            failed,
+            if failed == 1 { "test" } else { "tests" }
+        );
+    }
+    md
+}
+
+/// The file of a test, relative to its tests' directory, and the line it
+/// failed at if the traceback tells.
+///
+/// The traceback's last `path.py:line:` frame in the test's module wins;
+/// without one, the file comes from the module in its class name (pytest's
+/// `dir.test_module.TestClass`).
+fn location(test: &TestCase) -> (Option<String>, Option<u32>) {
+    let module: Vec<&str> = test
+        .classname
+        .split('.')
+        .take_while(|part| !part.starts_with(|c: char| c.is_ascii_uppercase()))
+        .filter(|part| !part.is_empty())
+        .collect();
+    let Some(name) = module.last().map(|name| format!("{}.py", name)) else {
+        return (None, None);
+    };
+    let frame = test
+        .details
+        .iter()
+        .flat_map(|d| d.lines())
+        .rev()
+        .find_map(|line| {
+            let (path, rest) = line.split_once(".py:")?;
+            let (number, _) = rest.split_once(':')?;
+            let file = format!("{}.py", path.trim());
+            let in_module = file == name || file.ends_with(&format!("/{}", name));
+            Some((file, number.parse().ok()?)).filter(|_| in_module)
+        });
+    match frame {
+        Some((file, line)) => (Some(file), Some(line)),
+        None => (Some(format!("{}.py", module.join("/"))), None),
+    }
+}
+
+/// A workflow command's message, escaped.
+fn data(text: &str) -> String {
+    text.trim_end()
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// A workflow command's property value, escaped.
+fn property(text: &str) -> String {
+    data(text).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// Inline code, with a fence longer than any backtick run inside.
+fn code(text: &str) -> String {
+    let ticks = "`".repeat(longest_run(text, '`') + 1);
+    let pad = if text.starts_with('`') || text.ends_with('`') {
+        " "
+    } else {
+        ""
+    };
+    format!("{}{}{}{}{}", ticks, pad, text, pad, ticks)
+}
+
+/// A code block, with a fence longer than any backtick run inside.
+fn fence(text: &str) -> String {
+    let ticks = "`".repeat((longest_run(text, '`') + 1).max(3));
+    format!("{}\n{}\n{}", ticks, text.trim_end(), ticks)
+}
+
+fn longest_run(text: &str, c: char) -> usize {
+    let (mut longest, mut run) = (0, 0);
+    for ch in text.chars() {
+        run = if ch == c { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+    longest
+}
+
+/// A table cell, which can't hold pipes or line breaks.
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+const STYLE: &str = "body{font-family:system-ui,sans-serif;margin:2rem auto;max-width:72rem;\
+color:#1f2328;padding:0 1rem}table{border-collapse:collapse;margin:1rem 0}\
+th,td{border:1px solid #d0d7de;padding:.3rem .6rem;text-align:left}\
+td.n{text-align:right}.pass{color:#1a7f37}.fail{color:#cf222e}\
+pre{background:#f6f8fa;padding:.6rem;overflow-x:auto}code{font-size:.9em}\
+.failure{border-left:3px solid #cf222e;padding-left:.8rem;margin:1rem 0}";
+
+/// The reports as a self-contained HTML page.
+pub fn to_html(reports: &[ContractReport]) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Contract test report</title>\n<style>{}</style>\n</head>\n<body>\n\
+         <h1>Contract test report</h1>\n<table>\n<tr><th></th><th>Service</th><th>Target</th>\
+         <th>Passed</th><th>Failed</th><th>Skipped</th><th>Flaky</th><th>Time</th></tr>\n",
+        STYLE
+    );
+    for report in reports {
+        for target in &report.targets {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td><a href=\"#{}\">{}</a></td><td>{}</td><td class=\"n\">{}</td>\
+                 <td class=\"n\">{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td>\
+                 <td class=\"n\">{}</td></tr>",
+                status(target.success),
+                escape(&report.service),
+                escape(&report.service),
+                escape(&target.label()),
+                target.count(Outcome::Passed),
+                target.count(Outcome::Failed) + target.count(Outcome::Error),
+                target.count(Outcome::Skipped),
+                target.flaky(),
+                seconds(target.time)
+            );
+        }
+    }
+    html.push_str("</table>\n");
+
+    for report in reports {
+        let _ = writeln!(
+            html,
+            "<h2 id=\"{}\">{} {}</h2>",
+            escape(&report.service),
+            status(passed(report)),
+            escape(&report.service)
+        );
+
+        let failures = failures(report);
+        if !failures.is_empty() {
+            html.push_str("<h3>Failures</h3>\n");
+            for (target, test) in failures {
+                let _ = writeln!(
+                    html,
+                    "<div class=\"failure\"><p><code>{}</code> ({})</p>",
+                    escape(&test_name(test)),
+                    escape(&target.label())
+                );
+                if let Some(message) = &test.message {
+                    let _ = writeln!(html, "<pre>{}</pre>", escape_text(message));
+                }
+                if let Some(details) = &test.details {
+                    let _ = writeln!(
+                        html,
+                        "<details><summary>Details</summary><pre>{}</pre></details>",
+                        escape_text(details)
+                    );
+                }
+                html.push_str("</div>\n");
+            }
+        } else if report.targets.iter().any(|t| !t.success) {
+            html.push_str(
+                "<p>The test command failed without reporting a failing test; see its output.</p>\n",
+            );
+        }
+
+        let slowest = slowest(report);
+        if !slowest.is_empty() {
+            html.push_str(
+                "<h3>Slowest tests</h3>\n<table>\n<tr><th>Test</th><th>Target</th><th>Time</th></tr>\n",
+            );
+            for (target, test) in slowest {
+                let _ = writeln!(
+                    html,
+                    "<tr><td><code>{}</code></td><td>{}</td><td class=\"n\">{}</td></tr>",
+                    escape(&test_name(test)),
+                    escape(&target.label()),
+                    seconds(test.time)
+                );
+            }
+            html.push_str("</table>\n");
+        }
+
+        if let Some(coverage) = &report.coverage {
+            let _ = writeln!(
+                html,
+                "<h3>Coverage</h3>\n<p>{} of {} endpoints requested ({:.1}%).</p>",
+                coverage.hit, coverage.declared, coverage.percent
+            );
+            let missed: Vec<_> = coverage.endpoints.iter().filter(|e| e.hits == 0).collect();
+            if !missed.is_empty() {
+                html.push_str("<p>Never requested:</p>\n<ul>\n");
+                for hits in missed.iter().take(MAX_MISSED) {
+                    let _ = writeln!(
+                        html,
+                        "<li><code>{} {}</code></li>",
+                        escape(&hits.endpoint.method),
+                        escape(&hits.endpoint.path)
+                    );
+                }
+                if missed.len() > MAX_MISSED {
+                    let _ = writeln!(html, "<li>... and {} more</li>", missed.len() - MAX_MISSED);
+                }
+                html.push_str("</ul>\n");
+            }
+        }
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn status(success: bool) -> &'static str {
+    if success {
+        "<span class=\"pass\">✔</span>"
+    } else {
+        "<span class=\"fail\">✘</span>"
+    }
+}
+
+/// Escaped text for a `<pre>`, keeping its line breaks.
+fn escape_text(text: &str) -> String {
+    escape(text.trim_end()).replace("&#10;", "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coverage::{Coverage, Endpoint, EndpointHits};
+
+    fn reports() -> Vec<ContractReport> {
+        let test = |name: &str, time: f64, outcome: Outcome| TestCase {
+            name: name.to_string(),
+            classname: "test_issues.TestIssues".to_string(),
+            time,
+            outcome,
+            message: None,
+            details: None,
+            retries: 0,
+        };
+        let mut failed = test("test_close_issue", 0.4, Outcome::Failed);
+        failed.message = Some("assert 'open' == 'closed'".to_string());
+        failed.details = Some("- closed\n+ open <b>".to_string());
+        vec![ContractReport {
+            service: "github".to_string(),
+            targets: vec![TargetResults {
+                target: "fake".to_string(),
+                profile: None,
+                success: false,
+                time: 2.5,
+                tests: vec![
+                    test("test_create_issue", 1.2, Outcome::Passed),
+                    failed,
+                    test("test_lock", 0.0, Outcome::Skipped),
+                ],
+            }],
+            coverage: Some(Coverage {
+                hit: 1,
+                declared: 2,
+                percent: 50.0,
+                endpoints: vec![
+                    EndpointHits {
+                        endpoint: Endpoint {
+                            method: "POST".to_string(),
+                            path: "/repos/{owner}/{repo}/issues".to_string(),
+                        },
+                        hits: 3,
+                    },
+                    EndpointHits {
+                        endpoint: Endpoint {
+                            method: "GET".to_string(),
+                            path: "/user".to_string(),
+                        },
+                        hits: 0,
+                    },
+                ],
+                undeclared: Vec::new(),
+            }),
+        }]
+    }
+
+    #[test]
+    fn test_markdown_report() {
+        let md = to_markdown(&reports());
+        assert!(
+            md.contains("| ❌ | github | fake | 1 | 1 | 1 | 0 | 2.50s |"),
+            "{}",
+            md
+        );
+        assert!(md.contains("## ❌ github"), "{}", md);
+        assert!(
+            md.contains("**`test_issues.TestIssues::test_close_issue`** (fake)\n\n```\nassert 'open' == 'closed'\n```"),
+            "{}",
+            md
+        );
+        assert!(md.contains("```\n- closed\n+ open <b>\n```"), "{}", md);
+        // Slowest first, skipped tests that took no time left out
+        let slow = md.find("test_create_issue`").unwrap();
+        assert!(slow < md.rfind("test_close_issue").unwrap(), "{}", md);
+        assert!(!md.contains("test_lock"), "{}", md);
+        assert!(md.contains("1 of 2 endpoints requested (50.0%)"), "{}", md);
+        assert!(md.contains("- `GET /user`"), "{}", md);
+    }
+
+    #[test]
+    fn test_html_report_escapes_output() {
+        let html = to_html(&reports());
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(
+            html.contains("<pre>- closed\n+ open &lt;b&gt;</pre>"),
+            "{}",
+            html
+        );
+        assert!(html.contains("<li><code>GET /user</code></li>"), "{}", html);
+        assert!(html.ends_with("</html>\n"));
+    }
+
+    #[test]
+    fn test_github_annotations() {
+        let mut reports = reports();
+        let failed = &mut reports[0].targets[0].tests[1];
+        failed.classname = "tests.test_issues.TestIssues".to_string();
+        failed.details = Some(
+            "def test_close_issue(self):\n>       assert 'open' == 'closed'\n\n\
+             conftest.py:12: in client\ntests/test_issues.py:42: AssertionError"
+                .to_string(),
+        );
+        let dirs = BTreeMap::from([(
+            "github".to_string(),
+            "services/github/contracts".to_string(),
+        )]);
+
+        assert_eq!(
+            github_annotations(&reports, &dirs),
+            "::error file=services/github/contracts/tests/test_issues.py,line=42,\
+             title=github (fake)%3A tests.test_issues.TestIssues%3A%3Atest_close_issue\
+             ::assert 'open' == 'closed'\n"
+        );
+
+        // Without a traceback frame the file comes from the class name, and
+        // without a directory there is none
+        reports[0].targets[0].tests[1].details = None;
+        let annotations = github_annotations(&reports, &dirs);
+        assert!(
+            annotations
+                .starts_with("::error file=services/github/contracts/tests/test_issues.py,title="),
+            "{}",
+            annotations
+        );
+        let annotations = github_annotations(&reports, &BTreeMap::new());
+        assert!(
+            annotations.starts_with("::error title=github (fake)%3A "),
+            "{}",
+            annotations
+        );
+    }
+
+    #[test]
+    fn test_github_summary() {
+        let summary = github_summary(&reports());
+        assert!(
+            summary.starts_with("## Contract tests\n\n| |"),
+            "{}",
+            summary
+        );
+        assert!(summary.contains("| ❌ | github | fake | 1 | 1 | 1 | 0 | 2.50s |"));
+        assert!(summary.ends_with("\n1 failed test; see the annotations.\n"));
+        assert_eq!(data("50%\nok\n"), "50%25%0Aok");
+        assert_eq!(property("a: b, c"), "a%3A b%2C c");
+    }
+
+    #[test]
+    fn test_code_fences_outlast_backticks() {
+        assert_eq!(code("a`b"), "``a`b``");
+        assert_eq!(fence("```\nx\n```"), "````\n```\nx\n```\n````");
+    }
+}
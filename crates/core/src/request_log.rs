@@ -0,0 +1,387 @@
+//! A fake's log of the requests it served, for `doubleagent requests`.
+//!
+//! The log comes from the optional `GET /_doubleagent/requests` control
+//! endpoint (see [`crate::coverage`]). Requests are picked by method, by path,
+//! where `*` matches any run of characters (`/repos/*/issues`), and by when
+//! they were served, for fakes that log a `time`.
+//!
+//! `doubleagent requests assert` checks [`Expectation`]s against the log,
+//! each written as `[METHOD] PATH [STATUS] [OP COUNT]`:
+//!
+//! - `POST /repos/*/issues == 1`: exactly one such request
+//! - `GET /user 2xx >= 3`: at least three that succeeded
+//! - `DELETE /repos/* == 0`: none
+//! - `/rate_limit`: at least one, with any method
+
+use crate::coverage::LoggedRequest;
+use crate::dates;
+use crate::{Error, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+/// Which logged requests to show.
+#[derive(Debug, Clone, Default)]
+pub struct RequestFilter {
+    pub method: Option<String>,
+    /// Path pattern, with `*` wildcards
+    pub path: Option<String>,
+    /// Served at or after, in seconds since the Unix epoch
+    pub since: Option<i64>,
+}
+
+impl RequestFilter {
+    /// Whether the filter selects `request`; with `since`, requests logged
+    /// without a time never match.
+    pub fn matches(&self, request: &LoggedRequest) -> bool {
+        if let Some(method) = &self.method {
+            if !method.eq_ignore_ascii_case(&request.method) {
+                return false;
+            }
+        }
+        if let Some(path) = &self.path {
+            if !wildcard(path, &request.path) {
+                return false;
+            }
+        }
+        match (self.since, request.time) {
+            (Some(since), Some(time)) => time >= since as f64,
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+}
+
+/// How a count of matching requests compares with the expected one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Ge,
+    Le,
+    Gt,
+    Lt,
+}
+
+impl Comparison {
+    const ALL: [(&'static str, Comparison); 6] = [
+        ("==", Comparison::Eq),
+        ("!=", Comparison::Ne),
+        (">=", Comparison::Ge),
+        ("<=", Comparison::Le),
+        (">", Comparison::Gt),
+        ("<", Comparison::Lt),
+    ];
+
+    fn holds(self, actual: usize, expected: usize) -> bool {
+        match self {
+            Comparison::Eq => actual == expected,
+            Comparison::Ne => actual != expected,
+            Comparison::Ge => actual >= expected,
+            Comparison::Le => actual <= expected,
+            Comparison::Gt => actual > expected,
+            Comparison::Lt => actual < expected,
+        }
+    }
+}
+
+/// How many logged requests of a kind a test expects.
+#[derive(Debug, Clone)]
+pub struct Expectation {
+    /// As written, e.g. `POST /repos/*/issues == 1`
+    pub text: String,
+    pub filter: RequestFilter,
+    /// Status code, or class such as `4xx`
+    pub status: Option<String>,
+    pub comparison: Comparison,
+    pub count: usize,
+}
+
+impl Expectation {
+    /// Parse `[METHOD] PATH [STATUS] [OP COUNT]`; without a comparison at
+    /// least one request must match.
+    pub fn parse(text: &str) -> Result<Self> {
+        let invalid = |why: &str| {
+            Error::Other(format!(
+                "invalid expectation '{}': {} (expected e.g. 'POST /repos/*/issues == 1')",
+                text, why
+            ))
+        };
+        let mut words: Vec<&str> = text.split_whitespace().collect();
+
+        let (mut comparison, mut count) = (Comparison::Ge, 1);
+        if let Some(i) = words
+            .iter()
+            .position(|w| Comparison::ALL.iter().any(|(op, _)| w == op))
+        {
+            if i + 2 != words.len() {
+                return Err(invalid("the comparison must come last, before a count"));
+            }
+            comparison = Comparison::ALL
+                .iter()
+                .find(|(op, _)| *op == words[i])
+                .map(|(_, comparison)| *comparison)
+                .unwrap_or(Comparison::Ge);
+            count = words[i + 1]
+                .parse()
+                .map_err(|_| invalid("the count must be a whole number"))?;
+            words.truncate(i);
+        }
+
+        let method = match words.first().copied() {
+            Some(word) if !word.starts_with('/') && word != "*" => {
+                if !word.chars().all(|c| c.is_ascii_alphabetic()) {
+                    return Err(invalid("expected a method or a path starting with /"));
+                }
+                words.remove(0);
+                Some(word.to_ascii_uppercase())
+            }
+            _ => None,
+        };
+        let path = match words.first() {
+            Some(path) => path.to_string(),
+            None => return Err(invalid("no path")),
+        };
+        let status = match words.get(1) {
+            Some(status) if is_status(status) => Some(status.to_ascii_lowercase()),
+            Some(_) => {
+                return Err(invalid(
+                    "the status must be a code such as 201 or a class such as 4xx",
+                ))
+            }
+            None => None,
+        };
+        if words.len() > 2 {
+            return Err(invalid("unexpected words after the status"));
+        }
+
+        Ok(Self {
+            text: text.split_whitespace().collect::<Vec<_>>().join(" "),
+            filter: RequestFilter {
+                method,
+                path: Some(path),
+                since: None,
+            },
+            status,
+            comparison,
+            count,
+        })
+    }
+
+    /// Whether `request` is one of those counted.
+    pub fn matches(&self, request: &LoggedRequest) -> bool {
+        if !self.filter.matches(request) {
+            return false;
+        }
+        match (&self.status, request.status) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(pattern), Some(status)) => {
+                let status = status.to_string();
+                pattern
+                    .chars()
+                    .zip(status.chars())
+                    .all(|(p, s)| p == 'x' || p == s)
+                    && pattern.len() == status.len()
+            }
+        }
+    }
+
+    /// Count the matching requests of `requests` and compare.
+    pub fn check(&self, requests: &[LoggedRequest]) -> Outcome {
+        let actual = requests.iter().filter(|r| self.matches(r)).count();
+        Outcome {
+            expectation: self.text.clone(),
+            actual,
+            passed: self.comparison.holds(actual, self.count),
+        }
+    }
+}
+
+/// A status code (`201`) or class (`2xx`).
+fn is_status(word: &str) -> bool {
+    let mut chars = word.chars();
+    word.len() == 3
+        && chars.next().is_some_and(|c| ('1'..='5').contains(&c))
+        && chars.all(|c| c.is_ascii_digit() || c.eq_ignore_ascii_case(&'x'))
+}
+
+/// How an expectation fared against the log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Outcome {
+    pub expectation: String,
+    /// Requests that matched
+    pub actual: usize,
+    pub passed: bool,
+}
+
+/// Seconds since the Unix epoch of a `--since` value: a span back from `now`
+/// such as `30s`, `5m` or `1h30m`, or a time (`YYYY-MM-DDTHH:MM:SSZ`, or a
+/// `YYYY-MM-DD` date).
+pub fn parse_since(text: &str, now: i64) -> Result<i64> {
+    let span = text.trim();
+    let mut relative = dates::NOW.to_string();
+    // Each unit of a span counts back, so `1h30m` is `$now - 1h - 30m`
+    let mut start = 0;
+    for (i, c) in span.char_indices() {
+        if c.is_ascii_alphabetic() {
+            relative.push_str(&format!(" - {}", &span[start..=i]));
+            start = i + 1;
+        }
+    }
+    if start == span.len() && !span.is_empty() {
+        if let Some(Ok(time)) = dates::parse_relative(&relative, now) {
+            return Ok(time);
+        }
+    }
+    dates::parse_time(span).map_err(|_| {
+        Error::Other(format!(
+            "invalid --since '{}' (expected e.g. 30s, 5m, 1h30m or 2026-01-31T12:00:00Z)",
+            text
+        ))
+    })
+}
+
+/// The requests of a `/_doubleagent/requests` response, each as the fake
+/// logged it and as read.
+pub fn entries(log: &Value) -> Result<Vec<(Value, LoggedRequest)>> {
+    let Some(requests) = log.get("requests").and_then(Value::as_array) else {
+        return Ok(Vec::new());
+    };
+    requests
+        .iter()
+        .map(|entry| {
+            let request = serde_json::from_value(entry.clone())
+                .map_err(|e| Error::Other(format!("invalid request log entry {}: {}", entry, e)))?;
+            Ok((entry.clone(), request))
+        })
+        .collect()
+}
+
+/// Whether `text` matches `pattern`, in which `*` stands for any run of
+/// characters.
+fn wildcard(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*`: the whole text must be the pattern
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(method: &str, path: &str, time: Option<f64>) -> LoggedRequest {
+        LoggedRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            status: Some(200),
+            time,
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = RequestFilter {
+            method: Some("post".to_string()),
+            path: Some("/repos/*/issues".to_string()),
+            since: Some(100),
+        };
+        assert!(filter.matches(&request("POST", "/repos/acme/api/issues", Some(100.5))));
+        assert!(!filter.matches(&request("GET", "/repos/acme/api/issues", Some(100.5))));
+        assert!(!filter.matches(&request("POST", "/repos/acme/api/issues/1", Some(100.5))));
+        assert!(!filter.matches(&request("POST", "/repos/acme/api/issues", Some(99.0))));
+        assert!(!filter.matches(&request("POST", "/repos/acme/api/issues", None)));
+        assert!(RequestFilter::default().matches(&request("GET", "/user", None)));
+    }
+
+    #[test]
+    fn test_wildcard() {
+        assert!(wildcard("/user", "/user"));
+        assert!(!wildcard("/user", "/users"));
+        assert!(wildcard("/repos/*", "/repos/acme/api"));
+        assert!(wildcard("*/issues/*", "/repos/acme/api/issues/7"));
+        assert!(!wildcard("/repos/*/pulls", "/repos/acme/api/issues"));
+        assert!(wildcard("*", ""));
+    }
+
+    #[test]
+    fn test_parse_since() {
+        assert_eq!(parse_since("30s", 1000).unwrap(), 970);
+        assert_eq!(parse_since("1h30m", 10_000).unwrap(), 4600);
+        assert_eq!(parse_since("2026-01-31", 0).unwrap(), 1_769_817_600);
+        assert_eq!(
+            parse_since("2026-01-31T00:00:10Z", 0).unwrap(),
+            1_769_817_610
+        );
+        assert!(parse_since("soon", 0).is_err());
+        assert!(parse_since("5", 0).is_err());
+    }
+
+    #[test]
+    fn test_parse_expectation() {
+        let exact = Expectation::parse("post  /repos/*/issues 201 == 1").unwrap();
+        assert_eq!(exact.text, "post /repos/*/issues 201 == 1");
+        assert_eq!(exact.filter.method.as_deref(), Some("POST"));
+        assert_eq!(exact.filter.path.as_deref(), Some("/repos/*/issues"));
+        assert_eq!(exact.status.as_deref(), Some("201"));
+        assert_eq!((exact.comparison, exact.count), (Comparison::Eq, 1));
+
+        let any = Expectation::parse("/rate_limit").unwrap();
+        assert_eq!(any.filter.method, None);
+        assert_eq!((any.comparison, any.count), (Comparison::Ge, 1));
+
+        assert!(Expectation::parse("").is_err());
+        assert!(Expectation::parse("POST").is_err());
+        assert!(Expectation::parse("POST /user == one").is_err());
+        assert!(Expectation::parse("POST == 1 /user").is_err());
+        assert!(Expectation::parse("POST /user 20 == 1").is_err());
+        assert!(Expectation::parse("PO-ST /user").is_err());
+    }
+
+    #[test]
+    fn test_check_expectations() {
+        let requests = [
+            request("POST", "/repos/acme/api/issues", None),
+            request("GET", "/user", None),
+            LoggedRequest {
+                status: Some(404),
+                ..request("GET", "/user", None)
+            },
+        ];
+        let check = |text: &str| Expectation::parse(text).unwrap().check(&requests);
+        assert!(check("POST /repos/*/issues == 1").passed);
+        assert!(!check("POST /repos/*/issues == 2").passed);
+        assert_eq!(check("GET /user").actual, 2);
+        assert_eq!(check("GET /user 2xx").actual, 1);
+        assert_eq!(check("GET /user 404").actual, 1);
+        assert!(check("DELETE /repos/* == 0").passed);
+        assert!(check("/user < 3").passed);
+        assert!(!check("* > 3").passed);
+    }
+
+    #[test]
+    fn test_entries_keep_what_the_fake_logged() {
+        let log = json!({"requests": [
+            {"method": "GET", "path": "/user", "status": 200, "time": 5.0, "user_agent": "sdk"},
+        ]});
+        let logged = entries(&log).unwrap();
+        assert_eq!(logged[0].0["user_agent"], "sdk");
+        assert_eq!(logged[0].1, request("GET", "/user", Some(5.0)));
+        assert!(entries(&json!({"requests": [{"path": "/user"}]})).is_err());
+        assert!(entries(&json!({})).unwrap().is_empty());
+    }
+}
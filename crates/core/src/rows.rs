@@ -0,0 +1,248 @@
+//! Seed records from CSV and NDJSON files.
+//!
+//! Each row (or line) becomes one record of a single resource, named by the
+//! file (`users.csv` seeds `users`) or given with `seed --resource`. CSV
+//! cells that read back unchanged as a number or `true`/`false` become one,
+//! so `42` is a number but the zip code `02139` stays a string; empty cells
+//! are left out, so the fake's defaults apply. `--column field=Header`
+//! renames a column to the field the fake expects.
+
+use crate::{Error, Result};
+use serde_json::{Map, Number, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Whether a file holds rows rather than a seed payload, by its extension.
+pub fn is_rows_file(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("csv" | "ndjson" | "jsonl")
+    )
+}
+
+/// Parse a CSV or NDJSON file's content into a seed payload of one resource.
+///
+/// `columns` maps field names to the columns (or NDJSON keys) holding them.
+pub fn parse(
+    path: &Path,
+    content: &str,
+    resource: Option<&str>,
+    columns: &BTreeMap<String, String>,
+) -> Result<Value> {
+    let resource = match resource {
+        Some(resource) => resource.to_string(),
+        None => path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .map(str::to_string)
+            .ok_or_else(|| {
+                Error::Other(format!(
+                    "Can't name the resource of {}, pass --resource",
+                    path.display()
+                ))
+            })?,
+    };
+    let in_file = |e: String| Error::Other(format!("{}: {}", path.display(), e));
+    let mut records = if path.extension().and_then(|e| e.to_str()) == Some("csv") {
+        let (headers, records) = parse_csv(content).map_err(in_file)?;
+        if let Some(column) = columns.values().find(|column| !headers.contains(column)) {
+            return Err(Error::Other(format!(
+                "{} has no column '{}'",
+                path.display(),
+                column
+            )));
+        }
+        records
+    } else {
+        parse_ndjson(content).map_err(in_file)?
+    };
+
+    for record in &mut records {
+        if let Value::Object(record) = record {
+            for (field, column) in columns {
+                if let Some(value) = record.remove(column) {
+                    record.insert(field.clone(), value);
+                }
+            }
+        }
+    }
+
+    let mut payload = Map::new();
+    payload.insert(resource, Value::Array(records));
+    Ok(Value::Object(payload))
+}
+
+/// Parse a `field=Column` mapping.
+pub fn parse_column(s: &str) -> Result<(String, String)> {
+    match s.split_once('=') {
+        Some((field, column)) if !field.trim().is_empty() && !column.trim().is_empty() => {
+            Ok((field.trim().to_string(), column.trim().to_string()))
+        }
+        _ => Err(Error::Other(format!(
+            "Invalid column mapping '{}', expected field=Column",
+            s
+        ))),
+    }
+}
+
+/// One JSON object per non-empty line.
+fn parse_ndjson(content: &str) -> std::result::Result<Vec<Value>, String> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| match serde_json::from_str(line) {
+            Ok(Value::Object(record)) => Ok(Value::Object(record)),
+            Ok(_) => Err(format!("line {}: expected a JSON object", i + 1)),
+            Err(e) => Err(format!("line {}: {}", i + 1, e)),
+        })
+        .collect()
+}
+
+/// RFC 4180 CSV with a header row: `,` separated, `"` quoted, `""` escaped.
+/// Returns the column names and the records.
+fn parse_csv(content: &str) -> std::result::Result<(Vec<String>, Vec<Value>), String> {
+    let mut rows = split_csv(content.strip_prefix('\u{feff}').unwrap_or(content))?.into_iter();
+    let Some((_, headers)) = rows.next() else {
+        return Ok((Vec::new(), Vec::new()));
+    };
+    let headers: Vec<String> = headers.iter().map(|h| h.trim().to_string()).collect();
+    if headers.iter().any(String::is_empty) {
+        return Err("the header has an empty column name".to_string());
+    }
+
+    let records = rows
+        .filter(|(_, cells)| !(cells.len() == 1 && cells[0].is_empty()))
+        .map(|(line, cells)| {
+            if cells.len() != headers.len() {
+                return Err(format!(
+                    "line {}: {} cells, but the header has {} columns",
+                    line,
+                    cells.len(),
+                    headers.len()
+                ));
+            }
+            let record = headers
+                .iter()
+                .zip(cells)
+                .filter(|(_, cell)| !cell.is_empty())
+                .map(|(header, cell)| (header.clone(), cell_value(cell)))
+                .collect();
+            Ok(Value::Object(record))
+        })
+        .collect::<std::result::Result<_, String>>()?;
+    Ok((headers, records))
+}
+
+/// Split CSV text into rows of cells, with the line each row starts on.
+fn split_csv(content: &str) -> std::result::Result<Vec<(usize, Vec<String>)>, String> {
+    let mut rows = Vec::new();
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut row_line = 1;
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                cell.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if cell.is_empty() => quoted = true,
+            ',' if !quoted => cells.push(std::mem::take(&mut cell)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' if !quoted => {
+                cells.push(std::mem::take(&mut cell));
+                rows.push((row_line, std::mem::take(&mut cells)));
+                line += 1;
+                row_line = line;
+            }
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                cell.push(c);
+            }
+        }
+    }
+    if quoted {
+        return Err(format!("line {}: unterminated quoted cell", row_line));
+    }
+    if !cell.is_empty() || !cells.is_empty() {
+        cells.push(cell);
+        rows.push((row_line, cells));
+    }
+    Ok(rows)
+}
+
+/// A number or boolean if the cell reads back unchanged as one, else a string.
+fn cell_value(cell: String) -> Value {
+    match cell.as_str() {
+        "true" => return Value::Bool(true),
+        "false" => return Value::Bool(false),
+        _ => {}
+    }
+    if let Ok(n) = cell.parse::<i64>() {
+        if n.to_string() == cell {
+            return Value::Number(n.into());
+        }
+    }
+    if let Some(n) = cell.parse::<f64>().ok().and_then(Number::from_f64) {
+        if n.to_string() == cell {
+            return Value::Number(n);
+        }
+    }
+    Value::String(cell)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_csv_rows_become_records() {
+        let csv = "Email,name,seats,zip,admin\r\n\
+                   ada@acme.io,\"Lovelace, Ada\",5,02139,true\r\n\
+                   \"bob@acme.io\",\"Bob \"\"B\"\"\",,1.5,false\r\n";
+        let columns = BTreeMap::from([("email".to_string(), "Email".to_string())]);
+        let payload = parse(Path::new("team.csv"), csv, Some("users"), &columns).unwrap();
+        assert_eq!(
+            payload,
+            json!({"users": [
+                {"email": "ada@acme.io", "name": "Lovelace, Ada", "seats": 5, "zip": "02139", "admin": true},
+                {"email": "bob@acme.io", "name": "Bob \"B\"", "zip": 1.5, "admin": false},
+            ]})
+        );
+
+        let msg = parse(Path::new("users.csv"), "a,b\n1\n", None, &BTreeMap::new())
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("users.csv: line 2: 1 cells"), "{}", msg);
+        let missing = BTreeMap::from([("email".to_string(), "Mail".to_string())]);
+        assert!(parse(Path::new("users.csv"), "a\n1\n", None, &missing).is_err());
+        assert!(parse(Path::new("users.csv"), "a\n\"1\n", None, &BTreeMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_ndjson_lines_become_records() {
+        let ndjson = "{\"login\": \"ada\"}\n\n{\"login\": \"bob\", \"site_admin\": true}\n";
+        let payload = parse(Path::new("users.ndjson"), ndjson, None, &BTreeMap::new()).unwrap();
+        assert_eq!(
+            payload,
+            json!({"users": [{"login": "ada"}, {"login": "bob", "site_admin": true}]})
+        );
+
+        let msg = parse(
+            Path::new("users.ndjson"),
+            "{}\n[1]\n",
+            None,
+            &BTreeMap::new(),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(msg.contains("line 2: expected a JSON object"), "{}", msg);
+    }
+}
@@ -0,0 +1,269 @@
+//! Secrets resolution for credentials referenced from configuration.
+//!
+//! Config values of the form `secret://<name>` are resolved just before use
+//! rather than being stored in plaintext YAML. Each provider is tried in
+//! order until one has the secret:
+//!
+//! 1. Environment variable `DOUBLEAGENT_SECRET_<NAME>` (upper-cased)
+//! 2. File `<secrets_dir>/<name>` (by default `$XDG_CONFIG_HOME/doubleagent/secrets`)
+//! 3. OS keychain entry with service `doubleagent` and account `<name>`
+//!    (`security` on macOS, `secret-tool` on Linux)
+
+use crate::{Error, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// URL scheme marking a value as a secret reference.
+pub const SCHEME: &str = "secret://";
+
+/// Prefix of environment variables holding secrets.
+const ENV_PREFIX: &str = "DOUBLEAGENT_SECRET_";
+
+/// Keychain service name secrets are stored under.
+const KEYCHAIN_SERVICE: &str = "doubleagent";
+
+/// Return the secret name if a value is a `secret://` reference.
+pub fn parse_ref(value: &str) -> Option<&str> {
+    value.strip_prefix(SCHEME)
+}
+
+/// A source secrets can be read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Provider {
+    /// `DOUBLEAGENT_SECRET_<NAME>` environment variables
+    Env,
+    /// One file per secret in a directory
+    File(PathBuf),
+    /// The OS keychain
+    Keychain,
+}
+
+impl Provider {
+    /// Look up a secret, returning None if this provider doesn't have it.
+    pub fn get(&self, name: &str) -> Result<Option<String>> {
+        match self {
+            Provider::Env => Ok(std::env::var(env_var(name)).ok().filter(|v| !v.is_empty())),
+            Provider::File(dir) => {
+                let path = dir.join(name);
+                if !path.is_file() {
+                    return Ok(None);
+                }
+                warn_if_readable_by_others(&path);
+                let value = fs::read_to_string(&path)?;
+                Ok(Some(value.trim_end_matches(['\r', '\n']).to_string()))
+            }
+            Provider::Keychain => keychain_get(name),
+        }
+    }
+
+    /// Where this provider looks for a secret, for error messages.
+    fn describe(&self, name: &str) -> String {
+        match self {
+            Provider::Env => format!("environment variable {}", env_var(name)),
+            Provider::File(dir) => format!("file {}", dir.join(name).display()),
+            Provider::Keychain => format!(
+                "keychain entry service={} account={}",
+                KEYCHAIN_SERVICE, name
+            ),
+        }
+    }
+}
+
+/// Resolves `secret://` references through an ordered list of providers.
+#[derive(Debug, Clone)]
+pub struct SecretResolver {
+    providers: Vec<Provider>,
+}
+
+impl SecretResolver {
+    /// Create a resolver using the default provider chain (env, file, keychain).
+    pub fn new(secrets_dir: impl Into<PathBuf>) -> Self {
+        Self::with_providers(vec![
+            Provider::Env,
+            Provider::File(secrets_dir.into()),
+            Provider::Keychain,
+        ])
+    }
+
+    /// Create a resolver with an explicit provider chain.
+    pub fn with_providers(providers: Vec<Provider>) -> Self {
+        Self { providers }
+    }
+
+    /// Resolve a secret by name.
+    pub fn get(&self, name: &str) -> Result<String> {
+        if let Some(value) = self.find(name)? {
+            return Ok(value);
+        }
+
+        let searched: Vec<String> = self.providers.iter().map(|p| p.describe(name)).collect();
+        Err(Error::Other(format!(
+            "Secret '{}' not found. Looked in:\n  {}",
+            name,
+            searched.join("\n  ")
+        )))
+    }
+
+    /// Look up an optional secret, returning None if no provider has it.
+    pub fn find(&self, name: &str) -> Result<Option<String>> {
+        if !is_valid_name(name) {
+            return Err(Error::Other(format!(
+                "Invalid secret name '{}' (use letters, digits, '_', '-' and '.')",
+                name
+            )));
+        }
+
+        for provider in &self.providers {
+            if let Some(value) = provider.get(name)? {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Resolve a value, replacing it if it is a `secret://` reference.
+    pub fn resolve(&self, value: &str) -> Result<String> {
+        match parse_ref(value) {
+            Some(name) => self.get(name),
+            None => Ok(value.to_string()),
+        }
+    }
+
+    /// Resolve every `secret://` reference in a map of values.
+    pub fn resolve_map(
+        &self,
+        values: &BTreeMap<String, String>,
+    ) -> Result<BTreeMap<String, String>> {
+        values
+            .iter()
+            .map(|(key, value)| Ok((key.clone(), self.resolve(value)?)))
+            .collect()
+    }
+}
+
+fn env_var(name: &str) -> String {
+    let suffix: String = name
+        .chars()
+        .map(|c| match c {
+            '-' | '.' => '_',
+            c => c.to_ascii_uppercase(),
+        })
+        .collect();
+    format!("{}{}", ENV_PREFIX, suffix)
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Read a secret from the OS keychain, if a keychain tool is available.
+fn keychain_get(name: &str) -> Result<Option<String>> {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("security");
+        command.args([
+            "find-generic-password",
+            "-s",
+            KEYCHAIN_SERVICE,
+            "-a",
+            name,
+            "-w",
+        ]);
+        command
+    } else if which::which("secret-tool").is_ok() {
+        let mut command = Command::new("secret-tool");
+        command.args(["lookup", "service", KEYCHAIN_SERVICE, "account", name]);
+        command
+    } else {
+        return Ok(None);
+    };
+
+    let output = command
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+    if !output.status.success() || output.stdout.is_empty() {
+        return Ok(None);
+    }
+
+    let value = String::from_utf8(output.stdout)
+        .map_err(|_| Error::Other(format!("Keychain secret '{}' is not valid UTF-8", name)))?;
+    Ok(Some(value.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+#[cfg(unix)]
+fn warn_if_readable_by_others(path: &std::path::Path) {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Ok(metadata) = fs::metadata(path) {
+        if metadata.permissions().mode() & 0o077 != 0 {
+            tracing::warn!(
+                "Secret file {} is accessible by other users; run 'chmod 600' on it",
+                path.display()
+            );
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn warn_if_readable_by_others(_: &std::path::Path) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_plain_values_pass_through() {
+        let resolver = SecretResolver::with_providers(vec![]);
+        assert_eq!(resolver.resolve("not-a-secret").unwrap(), "not-a-secret");
+    }
+
+    #[test]
+    fn test_file_provider_trims_trailing_newline() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("github_token"), "ghp_abc\n").unwrap();
+
+        let resolver = SecretResolver::with_providers(vec![Provider::File(dir.path().into())]);
+        assert_eq!(
+            resolver.resolve("secret://github_token").unwrap(),
+            "ghp_abc"
+        );
+    }
+
+    #[test]
+    fn test_providers_are_tried_in_order() {
+        let first = tempdir().unwrap();
+        let second = tempdir().unwrap();
+        fs::write(second.path().join("token"), "from-second").unwrap();
+
+        let resolver = SecretResolver::with_providers(vec![
+            Provider::File(first.path().into()),
+            Provider::File(second.path().into()),
+        ]);
+        assert_eq!(resolver.get("token").unwrap(), "from-second");
+    }
+
+    #[test]
+    fn test_missing_secret_lists_locations() {
+        let dir = tempdir().unwrap();
+        let resolver =
+            SecretResolver::with_providers(vec![Provider::Env, Provider::File(dir.path().into())]);
+
+        let msg = resolver.get("stripe-key").unwrap_err().to_string();
+        assert!(msg.contains("DOUBLEAGENT_SECRET_STRIPE_KEY"));
+        assert!(msg.contains("stripe-key"));
+    }
+
+    #[test]
+    fn test_rejects_path_traversal() {
+        let resolver = SecretResolver::with_providers(vec![]);
+        assert!(resolver.get("../etc/passwd").is_err());
+        assert!(resolver.get("").is_err());
+    }
+}
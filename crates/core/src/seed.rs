@@ -0,0 +1,826 @@
+//! Loading seed payloads from files, service fixtures and snapshots.
+
+use crate::control::{ControlClient, RetryPolicy, SeedMode};
+use crate::manifest::SeedSource;
+use crate::process::{ProcessManager, SeedRecord, SEED_UNDO_LIMIT};
+use crate::rows;
+use crate::service::ServiceDefinition;
+use crate::snapshot::diff::DEFAULT_KEY;
+use crate::snapshot::merge::merge_resource;
+use crate::snapshot::{ConflictPolicy, SnapshotStore};
+use crate::template;
+use crate::{Error, Result};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// The file name that reads a seed payload from stdin instead.
+pub const STDIN: &str = "-";
+
+/// Load a seed payload from a YAML or JSON file, or a directory of them.
+///
+/// Files ending in `.yaml` or `.yml` are parsed as YAML, CSV and NDJSON files
+/// as the records of the resource they are named after (see [`crate::rows`]),
+/// everything else as JSON. See [`load_dir`] for directories, and
+/// [`parse_seed`] for [`STDIN`].
+pub fn load_file(path: &Path) -> Result<Value> {
+    if path == Path::new(STDIN) {
+        return parse_seed(&read_stdin()?);
+    }
+    if path.is_dir() {
+        return merge_files(load_dir(path)?);
+    }
+    if rows::is_rows_file(path) {
+        return load_rows(path, None, &BTreeMap::new());
+    }
+    let content = read_seed_file(path)?;
+
+    let is_yaml = matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("yaml") | Some("yml")
+    );
+
+    if is_yaml {
+        Ok(serde_yaml::from_str(&content)?)
+    } else {
+        Ok(serde_json::from_str(&content)?)
+    }
+}
+
+/// Load a CSV or NDJSON file as the records of `resource` (by default the
+/// file's name), with `columns` mapping fields to the columns holding them.
+pub fn load_rows(
+    path: &Path,
+    resource: Option<&str>,
+    columns: &BTreeMap<String, String>,
+) -> Result<Value> {
+    if path == Path::new(STDIN) {
+        let resource = resource.ok_or_else(|| {
+            Error::Other("Rows read from stdin need --resource to name them".to_string())
+        })?;
+        let content = read_stdin()?;
+        // NDJSON lines are objects; anything else is taken as CSV
+        let format = if content.trim_start().starts_with('{') {
+            "stdin.ndjson"
+        } else {
+            "stdin.csv"
+        };
+        return rows::parse(Path::new(format), &content, Some(resource), columns);
+    }
+    if !rows::is_rows_file(path) {
+        return Err(Error::Other(format!(
+            "{} isn't a .csv, .ndjson or .jsonl file of records",
+            path.display()
+        )));
+    }
+    rows::parse(path, &read_seed_file(path)?, resource, columns)
+}
+
+/// Parse a seed payload whose format isn't known, such as one piped to
+/// stdin: as JSON if it starts like a JSON object or array, else as YAML.
+///
+/// YAML flow mappings also start with `{`, so text that isn't valid JSON is
+/// tried as YAML before giving up.
+pub fn parse_seed(content: &str) -> Result<Value> {
+    let trimmed = content.trim_start();
+    if !(trimmed.starts_with('{') || trimmed.starts_with('[')) {
+        return serde_yaml::from_str(content)
+            .map_err(|e| Error::Other(format!("Invalid YAML seed: {}", e)));
+    }
+    match serde_json::from_str(content) {
+        Ok(seed) => Ok(seed),
+        Err(json) => serde_yaml::from_str(content)
+            .map_err(|_| Error::Other(format!("Invalid JSON seed: {}", json))),
+    }
+}
+
+fn read_stdin() -> Result<String> {
+    let mut content = String::new();
+    std::io::stdin()
+        .read_to_string(&mut content)
+        .map_err(|e| Error::Other(format!("Failed to read seed from stdin: {}", e)))?;
+    Ok(content)
+}
+
+fn read_seed_file(path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|e| {
+        Error::Other(format!(
+            "Failed to read seed file {}: {}",
+            path.display(),
+            e
+        ))
+    })
+}
+
+/// Load a seed file, or a directory of them, and render it as a template
+/// with `vars` (see [`crate::template`]).
+pub fn load_template(path: &Path, vars: &BTreeMap<String, String>) -> Result<Value> {
+    if path.is_dir() {
+        let mut files = load_dir(path)?;
+        for (file, seed) in &mut files {
+            template::render(seed, vars, file)?;
+        }
+        return merge_files(files);
+    }
+    let mut seed = load_file(path)?;
+    template::render(&mut seed, vars, path)?;
+    Ok(seed)
+}
+
+/// Load every `.yaml`, `.yml`, `.json`, `.csv`, `.ndjson` and `.jsonl` file
+/// of a directory, sorted by name.
+///
+/// Large baselines are easier to keep as one file per resource, e.g.
+/// `seeds/github/repos.yaml` and `seeds/github/issues.yaml`. Hidden files and
+/// subdirectories are skipped.
+pub fn load_dir(dir: &Path) -> Result<Vec<(PathBuf, Value)>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        let seed_file = rows::is_rows_file(&path)
+            || matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("yaml" | "yml" | "json")
+            );
+        if seed_file && !name.starts_with('.') && path.is_file() {
+            paths.push(path);
+        }
+    }
+    if paths.is_empty() {
+        return Err(Error::Other(format!(
+            "{} has no YAML, JSON, CSV or NDJSON seed files",
+            dir.display()
+        )));
+    }
+    paths.sort();
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let seed =
+                load_file(&path).map_err(|e| Error::Other(format!("{}: {}", path.display(), e)))?;
+            Ok((path, seed))
+        })
+        .collect()
+}
+
+/// Merge the payloads of several files into one: records of a resource found
+/// in more than one file are appended in file order.
+fn merge_files(files: Vec<(PathBuf, Value)>) -> Result<Value> {
+    let mut merged = Map::new();
+    let mut origin: BTreeMap<String, PathBuf> = BTreeMap::new();
+    for (path, seed) in files {
+        let Value::Object(resources) = seed else {
+            return Err(Error::Other(format!(
+                "{}: a seed file must be a mapping of resource names to records",
+                path.display()
+            )));
+        };
+        for (resource, records) in resources {
+            match (merged.get_mut(&resource), records) {
+                (None, records) => {
+                    origin.insert(resource.clone(), path.clone());
+                    merged.insert(resource, records);
+                }
+                (Some(Value::Array(into)), Value::Array(records)) => into.extend(records),
+                (Some(_), _) => {
+                    return Err(Error::Other(format!(
+                        "{} and {} both set '{}', and only lists of records can be combined",
+                        origin[&resource].display(),
+                        path.display(),
+                        resource
+                    )));
+                }
+            }
+        }
+    }
+    Ok(Value::Object(merged))
+}
+
+/// Resolve a named fixture shipped in a service's `fixtures/` directory.
+pub fn fixture_path(service: &ServiceDefinition, name: &str) -> Result<PathBuf> {
+    let fixtures_dir = service.path.join("fixtures");
+
+    for ext in ["yaml", "yml", "json"] {
+        let path = fixtures_dir.join(format!("{}.{}", name, ext));
+        if path.exists() {
+            return Ok(path);
+        }
+    }
+
+    Err(Error::Other(format!(
+        "Fixture '{}' not found for service '{}' (looked in {})",
+        name,
+        service.name,
+        fixtures_dir.display()
+    )))
+}
+
+/// Load fixtures named like `base+billing+edge-cases`, layering each pack
+/// over the ones before it.
+///
+/// Resources are unioned. Records are matched by `id` (or, without one, by
+/// their whole content): a record of a later pack replaces the earlier
+/// record with its id, and the others are added in order. Resources that
+/// aren't lists of records are replaced as a whole.
+pub fn load_fixtures(service: &ServiceDefinition, names: &str) -> Result<Value> {
+    let mut merged = Map::new();
+    for name in names.split('+').map(str::trim) {
+        if name.is_empty() {
+            return Err(Error::Other(format!(
+                "Invalid fixtures '{}', expected names joined by '+' (e.g. base+billing)",
+                names
+            )));
+        }
+        let path = fixture_path(service, name)?;
+        let Value::Object(resources) = load_file(&path)? else {
+            return Err(Error::Other(format!(
+                "{}: a fixture must be a mapping of resource names to records",
+                path.display()
+            )));
+        };
+        for (resource, records) in resources {
+            match merged.get_mut(&resource) {
+                Some(existing) => {
+                    // Never fails under ConflictPolicy::Last
+                    let _ = merge_resource(existing, &records, DEFAULT_KEY, ConflictPolicy::Last);
+                }
+                None => {
+                    merged.insert(resource, records);
+                }
+            }
+        }
+    }
+    Ok(Value::Object(merged))
+}
+
+/// Load the payload of a seed source for a service.
+///
+/// Snapshot profiles are read from `store` and checked like for `seed --snapshot`.
+pub fn load_source(
+    source: &SeedSource,
+    service: &ServiceDefinition,
+    store: &SnapshotStore,
+) -> Result<Value> {
+    match source {
+        SeedSource::Fixture(names) => load_fixtures(service, names),
+        SeedSource::File(path) => load_file(path),
+        SeedSource::Snapshot(profile) => store.load_seed(service, profile),
+    }
+}
+
+/// Split a combined seed file, `{github: {...}, slack: {...}}`, into the
+/// payload of each service.
+///
+/// `source` is only used to make error messages point at the right file.
+pub fn split_combined(seed: Value, source: &Path) -> Result<BTreeMap<String, Value>> {
+    let Value::Object(services) = seed else {
+        return Err(Error::Other(format!(
+            "{} must map service names to their seed payloads",
+            source.display()
+        )));
+    };
+    if services.is_empty() {
+        return Err(Error::Other(format!(
+            "{} has no services to seed",
+            source.display()
+        )));
+    }
+    services
+        .into_iter()
+        .map(|(service, payload)| match payload {
+            Value::Object(_) => Ok((service, payload)),
+            _ => Err(Error::Other(format!(
+                "{}: the payload of '{}' must be a mapping of resources",
+                source.display(),
+                service
+            ))),
+        })
+        .collect()
+}
+
+/// Seed each service with its payload from a combined seed file, returning
+/// the service's response or error for each.
+///
+/// Nothing is seeded unless every service is running.
+pub async fn seed_combined(
+    manager: &ProcessManager,
+    payloads: &BTreeMap<String, Value>,
+    mode: SeedMode,
+    retry: RetryPolicy,
+) -> Result<Vec<(String, Result<Value>)>> {
+    let stopped: Vec<&str> = payloads
+        .keys()
+        .filter(|service| manager.get_info(service).is_none())
+        .map(String::as_str)
+        .collect();
+    if !stopped.is_empty() {
+        return Err(Error::Other(format!(
+            "Not running: {}. Start them before seeding, so they are seeded together",
+            stopped.join(", ")
+        )));
+    }
+
+    let mut results = Vec::new();
+    for (service, payload) in payloads {
+        let port = manager.get_info(service).map_or(0, |info| info.port);
+        let result = ControlClient::new(port)
+            .with_retry(retry)
+            .seed_with(payload, mode)
+            .await;
+        results.push((service.clone(), result));
+    }
+    Ok(results)
+}
+
+/// Reset a running service to the seed it was last given with `start`,
+/// `apply` or `seed --replace`, or to empty if it has none.
+///
+/// The seed is loaded before the service is touched, so a seed that can no
+/// longer be loaded leaves its state as it is. Returns the sources reapplied.
+pub async fn reset_to_seed(
+    manager: &ProcessManager,
+    store: &SnapshotStore,
+    service: &ServiceDefinition,
+) -> Result<Option<Vec<SeedSource>>> {
+    let info = manager
+        .get_info(&service.name)
+        .ok_or_else(|| Error::Other(format!("{} is not running", service.name)))?;
+    let client = ControlClient::new(info.port);
+    let Some(source) = info.seed.as_deref() else {
+        client.reset().await?;
+        return Ok(None);
+    };
+
+    let sources = SeedSource::parse_list(source)?;
+    let payloads = sources
+        .iter()
+        .map(|source| load_source(source, service, store))
+        .collect::<Result<Vec<_>>>()?;
+    for (i, data) in payloads.iter().enumerate() {
+        let mode = if i == 0 {
+            SeedMode::Replace
+        } else {
+            SeedMode::Default
+        };
+        client.seed_with(data, mode).await?;
+    }
+    Ok(Some(sources))
+}
+
+/// Records per resource of a payload, for a service's seed history.
+pub fn record_counts(payload: &Value) -> BTreeMap<String, u64> {
+    payload
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(resource, records)| {
+            Some((resource.clone(), records.as_array()?.len() as u64))
+        })
+        .collect()
+}
+
+/// Undo the last seed of a running service, restoring the state it was
+/// seeded over, and remove it from the service's history.
+///
+/// The service is reset and, unless it was empty, seeded with the state it
+/// exported before the seed.
+pub async fn undo_seed(manager: &mut ProcessManager, service: &str) -> Result<SeedRecord> {
+    let info = manager
+        .get_info(service)
+        .ok_or_else(|| Error::Other(format!("{} is not running", service)))?;
+    let last = info
+        .seed_history
+        .last()
+        .ok_or_else(|| Error::Other(format!("{} has no seeds to undo", service)))?;
+    let prior = last.prior.as_ref().ok_or_else(|| match &last.prior_error {
+        Some(e) => Error::Other(format!(
+            "The last seed of {} ({}) can't be undone: its state before the seed \
+             couldn't be exported: {}",
+            service, last.source, e
+        )),
+        None => Error::Other(format!(
+            "The last seed of {} ({}) can't be undone: its state before the seed \
+             wasn't kept (only the last {} seeds keep it)",
+            service, last.source, SEED_UNDO_LIMIT
+        )),
+    })?;
+
+    let client = ControlClient::new(info.port);
+    client.reset().await?;
+    if prior.as_object().is_none_or(|resources| {
+        resources
+            .values()
+            .any(|records| records.as_array().is_none_or(|r| !r.is_empty()))
+    }) {
+        client.seed(prior).await?;
+    }
+    manager.pop_seed(service);
+    Ok(last.clone())
+}
+
+/// Seed a freshly started service from each of `sources` in order.
+///
+/// If loading or posting a payload fails the service is stopped, so it never
+/// runs without the data it was asked to start with; otherwise the sources are
+/// recorded as the service's seed for `status`, and each in its seed history.
+pub async fn seed_started(
+    manager: &mut ProcessManager,
+    store: &SnapshotStore,
+    service: &ServiceDefinition,
+    sources: &[SeedSource],
+) -> Result<()> {
+    let port = manager
+        .get_info(&service.name)
+        .map(|info| info.port)
+        .ok_or_else(|| Error::Other(format!("{} is not running", service.name)))?;
+    let client = ControlClient::new(port);
+    for source in sources {
+        let seeded = async {
+            let data = load_source(source, service, store)?;
+            let prior = client.export().await;
+            client.seed(&data).await?;
+            Ok::<_, Error>((record_counts(&data), prior))
+        }
+        .await;
+
+        match seeded {
+            Ok((counts, prior)) => manager.record_seed(
+                &service.name,
+                SeedRecord::new(source.to_string(), counts, prior),
+            ),
+            Err(e) => {
+                manager.stop(&service.name).await?;
+                return Err(Error::Other(format!(
+                    "Seeding {} from {} failed, so it was stopped: {}",
+                    service.name, source, e
+                )));
+            }
+        }
+    }
+    manager.set_seed(&service.name, Some(SeedSource::join(sources)));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::ServiceInfo;
+    use crate::snapshot::rest::tests::serve;
+    use crate::snapshot::SnapshotManifest;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    fn github() -> ServiceDefinition {
+        let mut service: ServiceDefinition =
+            serde_yaml::from_str("name: github\nserver:\n  command: [true]\n").unwrap();
+        service.path = std::env::temp_dir();
+        service
+    }
+
+    fn running(port: u16) -> (ProcessManager, std::process::Child) {
+        let child = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let mut manager = ProcessManager::load(Path::new("/nonexistent/state.json")).unwrap();
+        manager.track(
+            "github",
+            ServiceInfo {
+                pid: child.id(),
+                port,
+                started_at: "0".to_string(),
+                service_path: String::new(),
+                config_fingerprint: None,
+                tags: Default::default(),
+                env_overrides: Default::default(),
+                seed: None,
+                seed_history: Vec::new(),
+                namespaces: Vec::new(),
+                restarts: 0,
+                health_history: Vec::new(),
+                failed_checks: 0,
+                project_config: None,
+                project_fingerprint: None,
+            },
+        );
+        (manager, child)
+    }
+
+    #[test]
+    fn test_parse_seed_detects_format() {
+        let expected = json!({"repos": [{"name": "api"}]});
+        for content in [
+            r#"{"repos": [{"name": "api"}]}"#,
+            "repos:\n  - name: api\n",
+            "{repos: [{name: api}]}",
+        ] {
+            assert_eq!(parse_seed(content).unwrap(), expected, "{}", content);
+        }
+
+        let msg = parse_seed(r#"{"repos": [}"#).unwrap_err().to_string();
+        assert!(msg.starts_with("Invalid JSON seed"), "{}", msg);
+        let msg = parse_seed("repos: [\n").unwrap_err().to_string();
+        assert!(msg.starts_with("Invalid YAML seed"), "{}", msg);
+    }
+
+    #[test]
+    fn test_directory_files_merge_in_order() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("b-issues.yaml"), "issues: [{title: b}]\n").unwrap();
+        fs::write(
+            dir.path().join("a-repos.json"),
+            r#"{"repos": [{"name": "api"}], "issues": [{"title": "a"}]}"#,
+        )
+        .unwrap();
+        fs::write(dir.path().join("README.md"), "ignored").unwrap();
+        fs::write(
+            dir.path().join("users.csv"),
+            "login,site_admin\nocto,true\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            load_file(dir.path()).unwrap(),
+            json!({
+                "repos": [{"name": "api"}],
+                "issues": [{"title": "a"}, {"title": "b"}],
+                "users": [{"login": "octo", "site_admin": true}],
+            })
+        );
+
+        fs::write(dir.path().join("c-broken.yaml"), "issues: [\n").unwrap();
+        let msg = load_file(dir.path()).unwrap_err().to_string();
+        assert!(msg.contains("c-broken.yaml"), "{}", msg);
+
+        fs::write(dir.path().join("c-broken.yaml"), "repos: {name: web}\n").unwrap();
+        let msg = load_file(dir.path()).unwrap_err().to_string();
+        assert!(msg.contains("a-repos.json and"), "{}", msg);
+        assert!(load_file(tempdir().unwrap().path()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_seeded_snapshot_is_recorded() {
+        let dir = tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path());
+        let seed = json!({"repos": [{"name": "a"}]});
+        let mut manifest = SnapshotManifest::new("github", "prod", "rest", &seed);
+        store.save(&mut manifest, &seed).unwrap();
+
+        let (url, server) = serve(vec![json!({"repos": []}), json!({"seeded": {"repos": 1}})]);
+        let port = url.rsplit(':').next().unwrap().parse().unwrap();
+        let (mut manager, mut child) = running(port);
+
+        let source = SeedSource::Snapshot("prod".to_string());
+        seed_started(&mut manager, &store, &github(), &[source])
+            .await
+            .unwrap();
+        assert_eq!(
+            manager.get_info("github").unwrap().seed.as_deref(),
+            Some("snapshot:prod")
+        );
+        let history = manager.get_info("github").unwrap().seed_history;
+        assert_eq!(history.len(), 1);
+        assert_eq!(
+            history[0].counts,
+            BTreeMap::from([("repos".to_string(), 1)])
+        );
+        assert_eq!(history[0].prior, Some(json!({"repos": []})));
+        let requests: Vec<String> = server
+            .join()
+            .unwrap()
+            .into_iter()
+            .filter(|line| line.contains("/_doubleagent/"))
+            .collect();
+        assert_eq!(
+            requests,
+            [
+                "GET /_doubleagent/export HTTP/1.1",
+                "POST /_doubleagent/seed HTTP/1.1"
+            ]
+        );
+        child.kill().unwrap();
+    }
+
+    #[test]
+    fn test_fixture_packs_layer_in_order() {
+        let dir = tempdir().unwrap();
+        let fixtures = dir.path().join("fixtures");
+        fs::create_dir(&fixtures).unwrap();
+        fs::write(
+            fixtures.join("base.yaml"),
+            "users: [{id: 1, plan: free}, {id: 2, plan: free}]\nsettings: {theme: light}\n",
+        )
+        .unwrap();
+        fs::write(
+            fixtures.join("billing.json"),
+            r#"{"users": [{"id": 2, "plan": "pro"}, {"id": 3}], "settings": {"theme": "dark"}}"#,
+        )
+        .unwrap();
+        let mut service = github();
+        service.path = dir.path().to_path_buf();
+
+        assert_eq!(
+            load_fixtures(&service, "base+billing").unwrap(),
+            json!({
+                "users": [{"id": 1, "plan": "free"}, {"id": 2, "plan": "pro"}, {"id": 3}],
+                "settings": {"theme": "dark"},
+            })
+        );
+        assert_eq!(
+            load_source(
+                &SeedSource::Fixture("base".to_string()),
+                &service,
+                &SnapshotStore::new(dir.path())
+            )
+            .unwrap()["users"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+        let msg = load_fixtures(&service, "base+edge")
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("Fixture 'edge' not found"), "{}", msg);
+        assert!(load_fixtures(&service, "base++billing").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reset_keeps_recorded_seed() {
+        let dir = tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path());
+        let seed = json!({"repos": [{"name": "a"}]});
+        let mut manifest = SnapshotManifest::new("github", "prod", "rest", &seed);
+        store.save(&mut manifest, &seed).unwrap();
+
+        // The service lists no seed modes, so it is reset before seeding
+        let (url, server) = serve(vec![
+            json!({"name": "github"}),
+            json!({"status": "ok"}),
+            json!({"seeded": {"repos": 1}}),
+        ]);
+        let port = url.rsplit(':').next().unwrap().parse().unwrap();
+        let (mut manager, mut child) = running(port);
+        manager.set_seed("github", Some("snapshot:prod".to_string()));
+
+        let source = reset_to_seed(&manager, &store, &github()).await.unwrap();
+        assert_eq!(source, Some(vec![SeedSource::Snapshot("prod".to_string())]));
+        let requests: Vec<String> = server
+            .join()
+            .unwrap()
+            .into_iter()
+            .filter(|line| line.contains("/_doubleagent/"))
+            .collect();
+        assert_eq!(
+            requests,
+            [
+                "GET /_doubleagent/info HTTP/1.1",
+                "POST /_doubleagent/reset HTTP/1.1",
+                "POST /_doubleagent/seed HTTP/1.1"
+            ]
+        );
+        child.kill().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_undo_restores_state_before_last_seed() {
+        let (url, server) = serve(vec![
+            json!({"status": "ok"}),
+            json!({"seeded": {"repos": 1}}),
+        ]);
+        let port = url.rsplit(':').next().unwrap().parse().unwrap();
+        let (mut manager, mut child) = running(port);
+
+        let msg = undo_seed(&mut manager, "github")
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("no seeds to undo"), "{}", msg);
+
+        let seed = |n: u64, prior: Value| {
+            SeedRecord::new(
+                format!("fixture:{}", n),
+                BTreeMap::from([("repos".to_string(), n)]),
+                Ok(prior),
+            )
+        };
+        for n in 0..SEED_UNDO_LIMIT as u64 + 1 {
+            manager.record_seed("github", seed(n, json!({"repos": []})));
+        }
+        manager.record_seed("github", seed(9, json!({"repos": [{"name": "api"}]})));
+        let history = manager.get_info("github").unwrap().seed_history;
+        assert_eq!(history.len(), SEED_UNDO_LIMIT + 2);
+        assert_eq!(
+            history.iter().filter(|r| r.prior.is_some()).count(),
+            SEED_UNDO_LIMIT
+        );
+
+        let undone = undo_seed(&mut manager, "github").await.unwrap();
+        assert_eq!(undone.source, "fixture:9");
+        let requests: Vec<String> = server
+            .join()
+            .unwrap()
+            .into_iter()
+            .filter(|line| line.contains("/_doubleagent/"))
+            .collect();
+        assert_eq!(
+            requests,
+            [
+                "POST /_doubleagent/reset HTTP/1.1",
+                "POST /_doubleagent/seed HTTP/1.1"
+            ]
+        );
+        assert_eq!(
+            manager.get_info("github").unwrap().seed_history.len(),
+            SEED_UNDO_LIMIT + 1
+        );
+        child.kill().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_undo_names_a_failed_export() {
+        let (mut manager, mut child) = running(1);
+        manager.record_seed(
+            "github",
+            SeedRecord::new(
+                "fixture:startup",
+                BTreeMap::new(),
+                Err(Error::ExportUnsupported),
+            ),
+        );
+        let msg = undo_seed(&mut manager, "github")
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("couldn't be exported"), "{}", msg);
+        assert!(msg.contains("does not support export"), "{}", msg);
+        child.kill().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_combined_file_seeds_each_service() {
+        let combined = json!({
+            "github": {"repos": [{"name": "api"}]},
+            "slack": {"channels": [{"name": "general"}]},
+        });
+        let payloads = split_combined(combined, Path::new("world.yaml")).unwrap();
+        assert_eq!(payloads.keys().collect::<Vec<_>>(), ["github", "slack"]);
+        let msg = split_combined(json!({"github": [1]}), Path::new("world.yaml"))
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("payload of 'github'"), "{}", msg);
+
+        let (url, server) = serve(vec![json!({"seeded": {"repos": 1}})]);
+        let port = url.rsplit(':').next().unwrap().parse().unwrap();
+        let (manager, mut child) = running(port);
+
+        let msg = seed_combined(
+            &manager,
+            &payloads,
+            SeedMode::Default,
+            RetryPolicy::default(),
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+        assert!(msg.contains("Not running: slack"), "{}", msg);
+
+        let github = payloads
+            .into_iter()
+            .filter(|(s, _)| s == "github")
+            .collect();
+        let results = seed_combined(&manager, &github, SeedMode::Default, RetryPolicy::default())
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(
+            results[0].1.as_ref().unwrap(),
+            &json!({"seeded": {"repos": 1}})
+        );
+        assert!(server.join().unwrap()[0].starts_with("POST /_doubleagent/seed"));
+        child.kill().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_seed_stops_service() {
+        let dir = tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path());
+        let (mut manager, mut child) = running(1);
+
+        let source = SeedSource::Snapshot("prod".to_string());
+        let msg = seed_started(&mut manager, &store, &github(), &[source])
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("from snapshot:prod failed"), "{}", msg);
+        assert!(manager.get_info("github").is_none());
+        child.wait().unwrap();
+    }
+}
@@ -1,9 +1,13 @@
 //! Service definitions and registry management.
 
-use crate::git::ServiceFetcher;
+use crate::git::{ServiceBackup, ServiceFetcher};
+use crate::golden::GoldenConfig;
+use crate::interpolate;
+use crate::redact::RedactionRule;
+use crate::snapshot::ConnectorConfig;
 use crate::{Error, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -30,6 +34,18 @@ pub struct ServiceDefinition {
     pub server: ServerConfig,
     /// Contract test configuration
     pub contracts: Option<ContractsConfig>,
+    /// How to pull snapshots of the real service's data
+    pub connector: Option<ConnectorConfig>,
+    /// Redaction applied to snapshots of this service
+    #[serde(default)]
+    pub redaction: Vec<RedactionRule>,
+    /// Version of the seed payload format the fake accepts; snapshots taken
+    /// with another version are refused until migrated
+    #[serde(default = "default_seed_schema_version")]
+    pub seed_schema_version: u32,
+    /// Commands migrating seed payloads from one schema version to the next
+    #[serde(default)]
+    pub seed_migrations: Vec<SeedMigration>,
     /// Path to the service directory (not serialized)
     #[serde(skip)]
     pub path: PathBuf,
@@ -45,6 +61,15 @@ pub struct ServerConfig {
     pub env: HashMap<String, String>,
 }
 
+/// A migration of seed payloads to the next `seed_schema_version`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeedMigration {
+    /// Version the migration upgrades from (to `from + 1`)
+    pub from: u32,
+    /// Command reading the payload as JSON on stdin and printing the migrated one
+    pub command: Vec<String>,
+}
+
 /// Configuration for contract tests.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ContractsConfig {
@@ -53,12 +78,139 @@ pub struct ContractsConfig {
     /// Directory containing contract tests (default: "contracts")
     #[serde(default = "default_contracts_dir")]
     pub directory: String,
+    /// The real API the same tests can run against (`contract --target real`)
+    pub real: Option<RealApiConfig>,
+    /// Scenarios recorded from the real API and compared with the fake's
+    /// responses (`contract --record` and `--compare`)
+    pub golden: Option<GoldenConfig>,
+    /// Filter expression picking a fast subset of the tests, run before
+    /// `doubleagent run --verify-contracts` hands the fake over
+    pub smoke: Option<String>,
+    /// Smoke script exercising the vendor's official SDK against the fake
+    pub sdk: Option<SdkConfig>,
+    /// Times to re-run failed tests before failing them; tests that pass on
+    /// a retry are reported as flaky
+    #[serde(default)]
+    pub retries: u32,
+    /// Seconds a single test may take before it fails
+    pub timeout: Option<f64>,
+    /// Extra environment variables for the test command, rendered as `{{ }}`
+    /// templates (see [`crate::contract::render_env`])
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Named selections of the tests, run with `contract --suite <name>`
+    #[serde(default)]
+    pub suites: Vec<ContractSuiteConfig>,
+}
+
+/// A named suite of contract tests, e.g. a cheap `pr` suite and a `nightly`
+/// one, or REST and GraphQL contracts with runners of their own, picked with
+/// `contract --suite`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContractSuiteConfig {
+    pub name: String,
+    /// Command running the suite (default: `contracts.command`)
+    #[serde(default)]
+    pub command: Vec<String>,
+    /// Directory of the suite's tests (default: `contracts.directory`)
+    pub directory: Option<String>,
+    /// Environment variables added to, or replacing, those of `contracts.env`
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Run only the tests with one of these tags
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Leave out the tests with any of these tags
+    #[serde(default)]
+    pub skip_tags: Vec<String>,
+}
+
+impl ContractsConfig {
+    /// The configuration suite `name` runs with: its command and directory
+    /// in place of the defaults and its env over `contracts.env`, along with
+    /// the suite itself.
+    pub fn suite(&self, name: &str) -> Result<(ContractsConfig, &ContractSuiteConfig)> {
+        let suite = self
+            .suites
+            .iter()
+            .find(|suite| suite.name == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = self.suites.iter().map(|s| s.name.as_str()).collect();
+                Error::Other(if names.is_empty() {
+                    format!("No contract suite '{}'; contracts.suites is empty", name)
+                } else {
+                    format!(
+                        "No contract suite '{}' (available: {})",
+                        name,
+                        names.join(", ")
+                    )
+                })
+            })?;
+        let mut config = self.clone();
+        if !suite.command.is_empty() {
+            config.command = suite.command.clone();
+        }
+        if let Some(directory) = &suite.directory {
+            config.directory = directory.clone();
+        }
+        config.env.extend(suite.env.clone());
+        Ok((config, suite))
+    }
+}
+
+/// How the official SDK is installed and exercised against the fake.
+///
+/// Both commands run in the contracts directory, through mise if the service
+/// has a `.mise.toml`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SdkConfig {
+    /// Command installing the SDK (e.g. `["uv", "sync"]` or `["npm", "ci"]`)
+    #[serde(default)]
+    pub install: Vec<String>,
+    /// Command running the smoke script, which finds the fake's URL in
+    /// `DOUBLEAGENT_{SERVICE}_URL` and exits non-zero on failure
+    pub command: Vec<String>,
+}
+
+/// How contract tests reach the real API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RealApiConfig {
+    /// Base URL of the real API, passed to tests instead of the fake's
+    pub base_url: String,
+    /// Environment variable holding the token to authenticate with
+    pub auth_env: Option<String>,
+}
+
+pub(crate) fn default_seed_schema_version() -> u32 {
+    1
 }
 
 fn default_contracts_dir() -> String {
     "contracts".to_string()
 }
 
+impl ServiceDefinition {
+    /// Load a service definition from a service directory containing service.yaml.
+    ///
+    /// Environment variable references (`${VAR}`, `${VAR:-default}`) are interpolated.
+    pub fn from_dir(service_dir: &Path) -> Result<Self> {
+        let service_yaml = service_dir.join("service.yaml");
+
+        if !service_yaml.exists() {
+            return Err(Error::ServiceNotFound(format!(
+                "service.yaml not found at {}",
+                service_yaml.display()
+            )));
+        }
+
+        let content = fs::read_to_string(&service_yaml)?;
+        let mut service: ServiceDefinition = interpolate::from_yaml_str(&content, &service_yaml)?;
+        service.path = service_dir.to_path_buf();
+
+        Ok(service)
+    }
+}
+
 /// Registry for managing service installations.
 pub struct ServiceRegistry {
     services_dir: PathBuf,
@@ -97,6 +249,15 @@ impl ServiceRegistry {
         None
     }
 
+    /// Directory of an available service: the local working directory first,
+    /// then the services cache.
+    pub fn service_dir(&self, name: &str) -> Option<PathBuf> {
+        self.find_local_service(name).or_else(|| {
+            self.is_installed(name)
+                .then(|| self.services_dir.join(name))
+        })
+    }
+
     /// Check if a service is installed in the local cache.
     pub fn is_installed(&self, name: &str) -> bool {
         let service_dir = self.services_dir.join(name);
@@ -108,12 +269,12 @@ impl ServiceRegistry {
     ///
     /// Priority order:
     /// 1. Local working directory (./services/{name}) - for development/CI
-    /// 2. Services cache (~/.doubleagent/services/{name})
+    /// 2. Services cache ($XDG_CACHE_HOME/doubleagent/services/{name})
     /// 3. Fetch from remote repository (if auto_install is true)
     pub fn get_or_install(&self, name: &str, auto_install: bool) -> Result<ServiceDefinition> {
         // First, check local working directory (highest priority)
         if let Some(local_path) = self.find_local_service(name) {
-            return self.load_service_from_path(&local_path);
+            return ServiceDefinition::from_dir(&local_path);
         }
 
         // Then check cache
@@ -135,28 +296,10 @@ impl ServiceRegistry {
         self.get(name)
     }
 
-    /// Load a service definition from a specific path.
-    fn load_service_from_path(&self, service_dir: &Path) -> Result<ServiceDefinition> {
-        let service_yaml = service_dir.join("service.yaml");
-
-        if !service_yaml.exists() {
-            return Err(Error::ServiceNotFound(format!(
-                "service.yaml not found at {}",
-                service_yaml.display()
-            )));
-        }
-
-        let content = fs::read_to_string(&service_yaml)?;
-        let mut service: ServiceDefinition = serde_yaml::from_str(&content)?;
-        service.path = service_dir.to_path_buf();
-
-        Ok(service)
-    }
-
     /// Get a service definition from the local cache.
     pub fn get(&self, name: &str) -> Result<ServiceDefinition> {
         let service_dir = self.services_dir.join(name);
-        self.load_service_from_path(&service_dir).map_err(|_| {
+        ServiceDefinition::from_dir(&service_dir).map_err(|_| {
             Error::ServiceNotFound(format!(
                 "Service '{}' not installed. Run 'doubleagent add {}' to install it, \
                  or 'doubleagent list --remote' to see available services.",
@@ -187,16 +330,10 @@ impl ServiceRegistry {
                 continue;
             }
 
-            if path.is_dir() {
-                let service_yaml = path.join("service.yaml");
-                if service_yaml.exists() {
-                    if let Ok(content) = fs::read_to_string(&service_yaml) {
-                        if let Ok(mut service) = serde_yaml::from_str::<ServiceDefinition>(&content)
-                        {
-                            service.path = path;
-                            services.push(service);
-                        }
-                    }
+            if path.is_dir() && path.join("service.yaml").exists() {
+                match ServiceDefinition::from_dir(&path) {
+                    Ok(service) => services.push(service),
+                    Err(e) => tracing::warn!("Skipping {}: {}", path.display(), e),
                 }
             }
         }
@@ -220,6 +357,12 @@ impl ServiceRegistry {
         self.fetcher.update_service(name)
     }
 
+    /// Copy an installed service's files aside before updating it, see
+    /// [`ServiceBackup`].
+    pub fn backup(&self, name: &str) -> Result<ServiceBackup> {
+        self.fetcher.backup_service(name)
+    }
+
     /// Update all installed services to the latest version.
     pub fn update_all(&self) -> Result<Vec<String>> {
         self.fetcher.update_all_services()
@@ -0,0 +1,807 @@
+//! Layered settings from defaults, global config, project config, environment
+//! and command-line flags.
+//!
+//! Settings are simple scalar keys (e.g. `repo_url`, `base_port`) that may be set
+//! in the global config file, at the top level of doubleagent.yaml, through an
+//! environment variable, or by a flag. Every key resolves the same way:
+//! flag > env > project > global > default.
+
+use crate::compliance::ComplianceMode;
+use crate::extends;
+use crate::format::Format;
+use crate::git::DEFAULT_REPO_URL;
+use crate::health::{self, HealthOverrides};
+use crate::interpolate;
+use crate::operations::REDACTED;
+use crate::ports::PortRange;
+use crate::snapshot;
+use crate::telemetry;
+use crate::workspace::{self, DEFAULT_WORKSPACE};
+use crate::{Error, Result};
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Specification of a configurable key.
+pub struct KeySpec {
+    /// Key name as written in config files
+    pub name: &'static str,
+    /// Environment variable that overrides the key
+    pub env: &'static str,
+    /// Default value when no layer sets the key
+    pub default: &'static str,
+    /// Human-readable description
+    pub description: &'static str,
+    validate: fn(&str) -> std::result::Result<(), String>,
+}
+
+/// All configurable keys.
+pub const KEYS: &[KeySpec] = &[
+    KeySpec {
+        name: "repo_url",
+        env: "DOUBLEAGENT_SERVICES_REPO",
+        default: DEFAULT_REPO_URL,
+        description: "URL of the services repository",
+        validate: validate_any,
+    },
+    KeySpec {
+        name: "branch",
+        env: "DOUBLEAGENT_BRANCH",
+        default: "main",
+        description: "Branch to fetch services from",
+        validate: validate_any,
+    },
+    KeySpec {
+        name: "base_port",
+        env: "DOUBLEAGENT_BASE_PORT",
+        default: "8080",
+        description: "Port for the first started service",
+        validate: validate_port,
+    },
+    KeySpec {
+        name: "port_range",
+        env: "DOUBLEAGENT_PORT_RANGE",
+        default: "1024-65535",
+        description: "Ports services may be started on (e.g. 9000-9999)",
+        validate: validate_port_range,
+    },
+    KeySpec {
+        name: "contract_port",
+        env: "DOUBLEAGENT_CONTRACT_PORT",
+        default: "18080",
+        description: "Preferred port for services started by contract tests",
+        validate: validate_port,
+    },
+    KeySpec {
+        name: "health_poll_interval_ms",
+        env: "DOUBLEAGENT_HEALTH_POLL_INTERVAL_MS",
+        default: "500",
+        description: "Delay between health polls while a service starts",
+        validate: validate_positive,
+    },
+    KeySpec {
+        name: "health_request_timeout_ms",
+        env: "DOUBLEAGENT_HEALTH_REQUEST_TIMEOUT_MS",
+        default: "2000",
+        description: "Timeout of a single health request",
+        validate: validate_positive,
+    },
+    KeySpec {
+        name: "startup_timeout_secs",
+        env: "DOUBLEAGENT_STARTUP_TIMEOUT_SECS",
+        default: "30",
+        description: "How long to wait for a started service to become healthy",
+        validate: validate_positive,
+    },
+    KeySpec {
+        name: "compliance_mode",
+        env: "DOUBLEAGENT_COMPLIANCE_MODE",
+        default: "off",
+        description: "Compliance mode for production-derived data (off, warn, allow-redacted, strict)",
+        validate: validate_compliance_mode,
+    },
+    KeySpec {
+        name: "registry",
+        env: "DOUBLEAGENT_REGISTRY",
+        default: "",
+        description: "Shared registry snapshots are pushed to and fetched from (s3://, gs://, az://, oci:// or https:// URL)",
+        validate: validate_registry,
+    },
+    KeySpec {
+        name: "pseudonym_seed",
+        env: "DOUBLEAGENT_PSEUDONYM_SEED",
+        default: "",
+        description: "Secret pseudonymized snapshot values are derived from",
+        validate: validate_any,
+    },
+    KeySpec {
+        name: "otel_endpoint",
+        env: "DOUBLEAGENT_OTEL_ENDPOINT",
+        default: "",
+        description: "OTLP/HTTP collector started fakes send trace spans to (e.g. http://localhost:4318)",
+        validate: telemetry::validate_endpoint,
+    },
+    KeySpec {
+        name: "flap_webhook",
+        env: "DOUBLEAGENT_FLAP_WEBHOOK",
+        default: "",
+        description: "URL a JSON alert is posted to when a service starts flapping between healthy and unhealthy",
+        validate: health::validate_webhook,
+    },
+    KeySpec {
+        name: "crash_hook",
+        env: "DOUBLEAGENT_CRASH_HOOK",
+        default: "",
+        description: "Shell command run when a service crashes or keeps failing health checks",
+        validate: validate_any,
+    },
+    KeySpec {
+        name: "crash_webhook",
+        env: "DOUBLEAGENT_CRASH_WEBHOOK",
+        default: "",
+        description: "URL a JSON alert is posted to when a service crashes or keeps failing health checks",
+        validate: health::validate_webhook,
+    },
+    KeySpec {
+        name: "workspace",
+        env: "DOUBLEAGENT_WORKSPACE",
+        default: DEFAULT_WORKSPACE,
+        description: "Workspace whose services commands operate on",
+        validate: workspace::validate_name,
+    },
+];
+
+/// Keys whose values are secrets, kept out of logs.
+pub const SECRET_KEYS: &[&str] = &["pseudonym_seed"];
+
+/// Look up the specification of a key.
+pub fn key_spec(name: &str) -> Result<&'static KeySpec> {
+    KEYS.iter().find(|k| k.name == name).ok_or_else(|| {
+        let known: Vec<&str> = KEYS.iter().map(|k| k.name).collect();
+        Error::Other(format!(
+            "Unknown config key '{}'. Known keys: {}",
+            name,
+            known.join(", ")
+        ))
+    })
+}
+
+/// Where an effective value came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    /// Built-in default
+    Default,
+    /// Global config file
+    Global(PathBuf),
+    /// Project config file (doubleagent.yaml)
+    Project(PathBuf),
+    /// A config the project config `extends`, by path or URL
+    Base(String),
+    /// Environment variable
+    Env(&'static str),
+    /// Command-line flag
+    Flag,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::Global(path) => write!(f, "global config ({})", path.display()),
+            Origin::Project(path) => write!(f, "project config ({})", path.display()),
+            Origin::Base(source) => write!(f, "config the project extends ({})", source),
+            Origin::Env(var) => write!(f, "environment ({})", var),
+            Origin::Flag => write!(f, "command line"),
+        }
+    }
+}
+
+/// An effective value together with its origin.
+#[derive(Debug, Clone)]
+pub struct Resolved {
+    pub value: String,
+    pub origin: Origin,
+}
+
+/// Values given on the command line, the highest-precedence layer.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    values: BTreeMap<&'static str, String>,
+}
+
+impl Overrides {
+    /// Create an empty set of overrides.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Override a key, validating the value like `config set` does.
+    pub fn set(&mut self, name: &str, value: impl Into<String>) -> Result<()> {
+        let spec = key_spec(name)?;
+        let value = value.into();
+        (spec.validate)(&value)
+            .map_err(|e| Error::Other(format!("Invalid value for '{}': {}", name, e)))?;
+        self.values.insert(spec.name, value);
+        Ok(())
+    }
+
+    /// The overridden value of a key, if any.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+}
+
+/// The settings layers.
+pub struct Settings {
+    global_path: PathBuf,
+    global: Mapping,
+    project_path: Option<PathBuf>,
+    project: Mapping,
+    /// The file each project key was last set in, when it's not the project config
+    project_bases: BTreeMap<String, String>,
+    /// Variables read instead of the process environment, if set
+    env: Option<BTreeMap<String, String>>,
+    overrides: Overrides,
+}
+
+impl Settings {
+    /// Load settings from the global config file and an optional project config file.
+    ///
+    /// Missing files are treated as empty layers. Environment variables referenced
+    /// in the project config are interpolated, and the configs it `extends` are
+    /// merged in (remote ones are cached under `cache_dir`). A value referencing
+    /// an unset variable only fails when it is resolved.
+    pub fn load(global_path: &Path, project_path: Option<&Path>, cache_dir: &Path) -> Result<Self> {
+        let (project, mut project_bases) = match project_path {
+            Some(path) if path.exists() => extends::load_deferred(path, cache_dir)?,
+            _ => (Value::Null, BTreeMap::new()),
+        };
+        let project = match project {
+            Value::Mapping(mapping) => mapping,
+            Value::Null => Mapping::new(),
+            _ => {
+                return Err(Error::Other(format!(
+                    "{} must contain a mapping of keys to values",
+                    project_path.unwrap_or(global_path).display()
+                )))
+            }
+        };
+        if let Some(path) = project_path {
+            let own = path.display().to_string();
+            project_bases.retain(|_, source| *source != own);
+        }
+
+        Ok(Self {
+            global_path: global_path.to_path_buf(),
+            global: read_mapping(global_path)?,
+            project_path: project_path.map(Path::to_path_buf),
+            project,
+            project_bases,
+            env: None,
+            overrides: Overrides::default(),
+        })
+    }
+
+    /// Add command-line overrides on top of the loaded layers.
+    pub fn with_overrides(mut self, overrides: Overrides) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// Read the environment variable layer from `env` instead of the process
+    /// environment.
+    pub fn with_env(mut self, env: BTreeMap<String, String>) -> Self {
+        self.env = Some(env);
+        self
+    }
+
+    /// Resolve the effective value of a key (flag > env > project > global > default).
+    pub fn resolve(&self, name: &str) -> Result<Resolved> {
+        let spec = key_spec(name)?;
+
+        if let Some(value) = self.overrides.get(spec.name) {
+            return Ok(Resolved {
+                value: value.to_string(),
+                origin: Origin::Flag,
+            });
+        }
+
+        let from_env = match &self.env {
+            Some(env) => env.get(spec.env).cloned(),
+            None => std::env::var(spec.env).ok(),
+        };
+        if let Some(value) = from_env {
+            return Ok(Resolved {
+                value,
+                origin: Origin::Env(spec.env),
+            });
+        }
+
+        if let Some(value) = self.project.get(name) {
+            interpolate::ensure_set(value)?;
+        }
+        if let (Some(path), Some(value)) = (&self.project_path, scalar(&self.project, name)) {
+            let origin = match self.project_bases.get(name) {
+                Some(base) => Origin::Base(base.clone()),
+                None => Origin::Project(path.clone()),
+            };
+            return Ok(Resolved { value, origin });
+        }
+
+        if let Some(value) = scalar(&self.global, name) {
+            return Ok(Resolved {
+                value,
+                origin: Origin::Global(self.global_path.clone()),
+            });
+        }
+
+        Ok(Resolved {
+            value: spec.default.to_string(),
+            origin: Origin::Default,
+        })
+    }
+
+    /// Resolve the effective value of a key, ignoring its origin.
+    pub fn get(&self, name: &str) -> Result<String> {
+        self.resolve(name).map(|r| r.value)
+    }
+
+    /// Every key with its effective value, as `config show` lists them: the
+    /// values of [`SECRET_KEYS`] that are set are [`REDACTED`], so they can
+    /// only be read by asking for them with [`get`](Self::get).
+    pub fn show(&self) -> Result<Vec<(&'static str, Resolved)>> {
+        KEYS.iter()
+            .map(|spec| {
+                let mut resolved = self.resolve(spec.name)?;
+                if SECRET_KEYS.contains(&spec.name) && !resolved.value.is_empty() {
+                    resolved.value = REDACTED.to_string();
+                }
+                Ok((spec.name, resolved))
+            })
+            .collect()
+    }
+
+    /// Resolve a key and parse it, reporting where an invalid value came from.
+    pub fn get_parsed<T: std::str::FromStr>(&self, name: &str) -> Result<T> {
+        let resolved = self.resolve(name)?;
+        resolved.value.parse().map_err(|_| {
+            Error::Other(format!(
+                "Invalid value '{}' for '{}' from {}",
+                resolved.value, name, resolved.origin
+            ))
+        })
+    }
+
+    /// Per-service health overrides from `services.<name>.health`.
+    ///
+    /// Fields set in the project config take precedence over the global config.
+    pub fn service_health(&self) -> Result<BTreeMap<String, HealthOverrides>> {
+        let mut overrides = service_health(&self.global, &self.global_path)?;
+        if let Some(path) = &self.project_path {
+            for (name, project) in service_health(&self.project, path)? {
+                let merged = overrides
+                    .get(&name)
+                    .cloned()
+                    .unwrap_or_default()
+                    .merge(&project);
+                overrides.insert(name, merged);
+            }
+        }
+        Ok(overrides)
+    }
+}
+
+/// Read `services.<name>.health` entries from one layer.
+///
+/// A plain list of service names carries no overrides.
+fn service_health(mapping: &Mapping, path: &Path) -> Result<BTreeMap<String, HealthOverrides>> {
+    let mut overrides = BTreeMap::new();
+    let Some(Value::Mapping(services)) = mapping.get("services") else {
+        return Ok(overrides);
+    };
+    for (name, service) in services {
+        let (Some(name), Some(health)) = (name.as_str(), service.get("health")) else {
+            continue;
+        };
+        interpolate::ensure_set(health)?;
+        let health = serde_yaml::from_value(health.clone()).map_err(|e| {
+            Error::Other(format!(
+                "{}: invalid health settings for service '{}': {}",
+                path.display(),
+                name,
+                e
+            ))
+        })?;
+        overrides.insert(name.to_string(), health);
+    }
+    Ok(overrides)
+}
+
+/// Set a key in a config file, rewriting only the line that holds it so the
+/// file's comments and the order of its keys are kept.
+///
+/// A file that can't be edited that way (say, the key holds a nested value)
+/// is left alone, with an error giving the line to put in it by hand.
+pub fn set_value(path: &Path, name: &str, value: &str) -> Result<()> {
+    let spec = key_spec(name)?;
+    (spec.validate)(value)
+        .map_err(|e| Error::Other(format!("Invalid value for '{}': {}", name, e)))?;
+
+    let format = Format::from_path(path);
+    let value = parse_scalar(value);
+    let content = read_content(path)?;
+    let edited = format.set_key(&content, name, &value)?;
+
+    let mut expected = read_mapping(path)?;
+    expected.insert(Value::from(name), value.clone());
+    if parse_mapping(format, &edited, path).as_ref() != Some(&expected) {
+        return Err(Error::Other(format!(
+            "Can't set '{}' in {} without rewriting it; set it there by hand:\n  {}",
+            name,
+            path.display(),
+            format.key_line(name, &value)?
+        )));
+    }
+    write_content(path, &edited)
+}
+
+/// Remove a key from a config file, removing only the line that holds it.
+/// Returns whether the key was present.
+pub fn unset_value(path: &Path, name: &str) -> Result<bool> {
+    key_spec(name)?;
+
+    let mut expected = read_mapping(path)?;
+    if expected.remove(name).is_none() {
+        return Ok(false);
+    }
+    let format = Format::from_path(path);
+    let edited = format.remove_key(&read_content(path)?, name);
+    match edited {
+        Some(edited) if parse_mapping(format, &edited, path).as_ref() == Some(&expected) => {
+            write_content(path, &edited)?;
+            Ok(true)
+        }
+        _ => Err(Error::Other(format!(
+            "Can't remove '{}' from {} without rewriting it; remove it there by hand",
+            name,
+            path.display()
+        ))),
+    }
+}
+
+fn read_content(path: &Path) -> Result<String> {
+    if !path.exists() {
+        return Ok(String::new());
+    }
+    Ok(fs::read_to_string(path)?)
+}
+
+fn read_mapping(path: &Path) -> Result<Mapping> {
+    if !path.exists() {
+        return Ok(Mapping::new());
+    }
+
+    let content = fs::read_to_string(path)?;
+    match Format::from_path(path).parse(&content, path)? {
+        Value::Mapping(mapping) => Ok(mapping),
+        Value::Null => Ok(Mapping::new()),
+        _ => Err(Error::Other(format!(
+            "{} must contain a mapping of keys to values",
+            path.display()
+        ))),
+    }
+}
+
+/// The mapping edited content parses to, if it still is one.
+fn parse_mapping(format: Format, content: &str, path: &Path) -> Option<Mapping> {
+    match format.parse(content, path).ok()? {
+        Value::Mapping(mapping) => Some(mapping),
+        Value::Null => Some(Mapping::new()),
+        _ => None,
+    }
+}
+
+fn write_content(path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, content)?;
+    Ok(())
+}
+
+/// Read a scalar key from a mapping as a string.
+fn scalar(mapping: &Mapping, name: &str) -> Option<String> {
+    match mapping.get(name)? {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Store numbers as YAML numbers so hand-edited files stay natural.
+fn parse_scalar(value: &str) -> Value {
+    match value.parse::<u64>() {
+        Ok(n) => Value::from(n),
+        Err(_) => Value::from(value),
+    }
+}
+
+fn validate_any(_: &str) -> std::result::Result<(), String> {
+    Ok(())
+}
+
+fn validate_port(value: &str) -> std::result::Result<(), String> {
+    match value.parse::<u16>() {
+        Ok(0) | Err(_) => Err(format!("'{}' is not a valid port", value)),
+        Ok(_) => Ok(()),
+    }
+}
+
+fn validate_positive(value: &str) -> std::result::Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(0) | Err(_) => Err(format!("'{}' is not a positive integer", value)),
+        Ok(_) => Ok(()),
+    }
+}
+
+fn validate_port_range(value: &str) -> std::result::Result<(), String> {
+    value.parse::<PortRange>().map(|_| ())
+}
+
+fn validate_registry(value: &str) -> std::result::Result<(), String> {
+    if value.is_empty() {
+        return Ok(());
+    }
+    snapshot::registry::validate_url(value)
+}
+
+fn validate_compliance_mode(value: &str) -> std::result::Result<(), String> {
+    value.parse::<ComplianceMode>().map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_resolve_default() {
+        let dir = tempdir().unwrap();
+        let settings = Settings::load(&dir.path().join("config.yaml"), None, dir.path()).unwrap();
+
+        let resolved = settings.resolve("compliance_mode").unwrap();
+        assert_eq!(resolved.value, "off");
+        assert_eq!(resolved.origin, Origin::Default);
+    }
+
+    #[test]
+    fn test_project_overrides_global() {
+        let dir = tempdir().unwrap();
+        let global = dir.path().join("config.yaml");
+        let project = dir.path().join("doubleagent.yaml");
+        fs::write(&global, "base_port: 9000\nbranch: dev\n").unwrap();
+        fs::write(&project, "services:\n  - github\nbase_port: 9100\n").unwrap();
+
+        let settings = Settings::load(&global, Some(&project), dir.path()).unwrap();
+
+        let port = settings.resolve("base_port").unwrap();
+        assert_eq!(port.value, "9100");
+        assert_eq!(port.origin, Origin::Project(project.clone()));
+
+        let branch = settings.resolve("branch").unwrap();
+        assert_eq!(branch.value, "dev");
+        assert_eq!(branch.origin, Origin::Global(global.clone()));
+
+        assert_eq!(settings.get_parsed::<u16>("base_port").unwrap(), 9100);
+    }
+
+    #[test]
+    fn test_precedence_across_all_layers() {
+        let dir = tempdir().unwrap();
+        let global = dir.path().join("config.yaml");
+        let project = dir.path().join("doubleagent.yaml");
+        fs::write(&global, "contract_port: 19001\n").unwrap();
+        fs::write(&project, "contract_port: 19002\n").unwrap();
+
+        let load_with = |env: &[(&str, &str)], overrides: Overrides| {
+            let env = env
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            Settings::load(&global, Some(&project), dir.path())
+                .unwrap()
+                .with_env(env)
+                .with_overrides(overrides)
+                .resolve("contract_port")
+                .unwrap()
+        };
+        let load = || load_with(&[], Overrides::new());
+        let mut flags = Overrides::new();
+        flags.set("contract_port", "19004").unwrap();
+
+        // Project beats global; the env var and the flag are layered on below
+        assert_eq!(load().value, "19002");
+
+        let env = [("DOUBLEAGENT_CONTRACT_PORT", "19003")];
+        let from_env = load_with(&env, Overrides::new());
+        assert_eq!(from_env.value, "19003");
+        assert_eq!(from_env.origin, Origin::Env("DOUBLEAGENT_CONTRACT_PORT"));
+        let from_flag = load_with(&env, flags);
+        assert_eq!(from_flag.value, "19004");
+        assert_eq!(from_flag.origin, Origin::Flag);
+
+        fs::remove_file(&project).unwrap();
+        assert_eq!(load().value, "19001");
+        fs::remove_file(&global).unwrap();
+        assert_eq!(load().origin, Origin::Default);
+    }
+
+    #[test]
+    fn test_show_redacts_secrets() {
+        let dir = tempdir().unwrap();
+        let global = dir.path().join("config.yaml");
+        fs::write(&global, "pseudonym_seed: hunter2\nbranch: dev\n").unwrap();
+        let settings = Settings::load(&global, None, dir.path()).unwrap();
+
+        let shown: BTreeMap<_, _> = settings.show().unwrap().into_iter().collect();
+        assert_eq!(shown.len(), KEYS.len());
+        assert_eq!(shown["pseudonym_seed"].value, REDACTED);
+        assert_eq!(
+            shown["pseudonym_seed"].origin,
+            Origin::Global(global.clone())
+        );
+        assert_eq!(shown["branch"].value, "dev");
+        assert_eq!(settings.get("pseudonym_seed").unwrap(), "hunter2");
+
+        fs::write(&global, "branch: dev\n").unwrap();
+        let settings = Settings::load(&global, None, dir.path()).unwrap();
+        let shown: BTreeMap<_, _> = settings.show().unwrap().into_iter().collect();
+        assert_eq!(shown["pseudonym_seed"].value, "");
+    }
+
+    #[test]
+    fn test_overrides_reject_invalid_values() {
+        let mut flags = Overrides::new();
+        assert!(flags.set("base_port", "http").is_err());
+        assert!(flags.set("no_such_key", "1").is_err());
+        assert!(flags.set("workspace", "demo").is_ok());
+        assert_eq!(flags.get("workspace"), Some("demo"));
+    }
+
+    #[test]
+    fn test_set_and_unset_preserve_other_keys() {
+        let dir = tempdir().unwrap();
+        let project = dir.path().join("doubleagent.yaml");
+        fs::write(&project, "services:\n  - github\n").unwrap();
+
+        set_value(&project, "base_port", "9000").unwrap();
+        let content = fs::read_to_string(&project).unwrap();
+        assert!(content.contains("base_port: 9000"));
+        assert!(content.contains("- github"));
+
+        assert!(unset_value(&project, "base_port").unwrap());
+        assert!(!unset_value(&project, "base_port").unwrap());
+        assert!(!fs::read_to_string(&project).unwrap().contains("base_port"));
+    }
+
+    #[test]
+    fn test_set_and_unset_keep_comments_and_order() {
+        let dir = tempdir().unwrap();
+        let project = dir.path().join("doubleagent.yaml");
+        let original = "# Our fakes\nbranch: dev  # until the release\nservices:\n  - github\n";
+        fs::write(&project, original).unwrap();
+
+        set_value(&project, "branch", "main").unwrap();
+        set_value(&project, "base_port", "9000").unwrap();
+        assert_eq!(
+            fs::read_to_string(&project).unwrap(),
+            "# Our fakes\nbranch: main  # until the release\nservices:\n  - github\nbase_port: 9000\n"
+        );
+
+        unset_value(&project, "base_port").unwrap();
+        set_value(&project, "branch", "dev").unwrap();
+        assert_eq!(fs::read_to_string(&project).unwrap(), original);
+    }
+
+    #[test]
+    fn test_set_refuses_what_it_cant_edit_in_place() {
+        let dir = tempdir().unwrap();
+        let project = dir.path().join("doubleagent.yaml");
+        let original = "branch:\n  name: dev\n";
+        fs::write(&project, original).unwrap();
+
+        let err = set_value(&project, "branch", "main")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("branch: main"));
+        assert!(unset_value(&project, "branch").is_err());
+        assert_eq!(fs::read_to_string(&project).unwrap(), original);
+    }
+
+    #[test]
+    fn test_set_and_unset_in_toml_and_json() {
+        let dir = tempdir().unwrap();
+        let toml = dir.path().join("doubleagent.toml");
+        fs::write(
+            &toml,
+            "# fakes\nbranch = \"dev\"\n\n[services.github]\nport = 9100\n",
+        )
+        .unwrap();
+        set_value(&toml, "base_port", "9000").unwrap();
+        set_value(&toml, "branch", "main").unwrap();
+        assert_eq!(
+            fs::read_to_string(&toml).unwrap(),
+            "# fakes\nbranch = \"main\"\nbase_port = 9000\n\n[services.github]\nport = 9100\n"
+        );
+
+        let json = dir.path().join("doubleagent.json");
+        fs::write(&json, "{\n  \"services\": [\"github\"]\n}\n").unwrap();
+        set_value(&json, "base_port", "9000").unwrap();
+        assert_eq!(
+            fs::read_to_string(&json).unwrap(),
+            "{\n  \"services\": [\"github\"],\n  \"base_port\": 9000\n}\n"
+        );
+        unset_value(&json, "base_port").unwrap();
+        assert_eq!(
+            fs::read_to_string(&json).unwrap(),
+            "{\n  \"services\": [\"github\"]\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_values_from_an_extended_config_name_it() {
+        let dir = tempdir().unwrap();
+        let base = dir.path().join("base.yaml");
+        let project = dir.path().join("doubleagent.yaml");
+        fs::write(&base, "branch: dev\nbase_port: 9000\n").unwrap();
+        fs::write(&project, "extends: base.yaml\nbase_port: 9100\n").unwrap();
+
+        let settings =
+            Settings::load(&dir.path().join("config.yaml"), Some(&project), dir.path()).unwrap();
+
+        let branch = settings.resolve("branch").unwrap();
+        assert_eq!(branch.value, "dev");
+        assert_eq!(branch.origin, Origin::Base(base.display().to_string()));
+        let port = settings.resolve("base_port").unwrap();
+        assert_eq!(port.origin, Origin::Project(project.clone()));
+    }
+
+    #[test]
+    fn test_service_health_merges_layers() {
+        let dir = tempdir().unwrap();
+        let global = dir.path().join("config.yaml");
+        let project = dir.path().join("doubleagent.yaml");
+        fs::write(
+            &global,
+            "services:\n  stripe:\n    health:\n      startup_timeout_secs: 60\n      poll_interval_ms: 250\n",
+        )
+        .unwrap();
+        fs::write(
+            &project,
+            "services:\n  stripe:\n    health:\n      startup_timeout_secs: 120\n  github:\n",
+        )
+        .unwrap();
+
+        let settings = Settings::load(&global, Some(&project), dir.path()).unwrap();
+        let health = settings.service_health().unwrap();
+        assert_eq!(health["stripe"].startup_timeout_secs, Some(120));
+        assert_eq!(health["stripe"].poll_interval_ms, Some(250));
+        assert!(!health.contains_key("github"));
+    }
+
+    #[test]
+    fn test_set_rejects_invalid_values() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+
+        assert!(set_value(&path, "base_port", "http").is_err());
+        assert!(set_value(&path, "port_range", "9000").is_err());
+        assert!(set_value(&path, "compliance_mode", "maybe").is_err());
+        assert!(set_value(&path, "startup_timeout_secs", "0").is_err());
+        assert!(set_value(&path, "no_such_key", "1").is_err());
+        assert!(!path.exists());
+    }
+}
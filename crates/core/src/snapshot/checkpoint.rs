@@ -0,0 +1,250 @@
+//! Progress of an unfinished pull, kept so a failed pull can be resumed.
+//!
+//! While a connector runs, every page is written to
+//! `<data_dir>/snapshots/.partial/<service>/<profile>/`: the records of each
+//! stream are appended to `<stream>.jsonl` and `state.json` records where each
+//! stream stands. Pulling the same profile again picks up after the last page
+//! written, unless the connector, limit or starting cursors changed. The
+//! directory is removed once the profile is stored.
+//!
+//! Records are kept as pulled, before redaction, so the directory is only
+//! readable by its owner.
+
+use super::rest::Next;
+use super::{read_json, sha256_hex, write_file};
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+const STATE_FILE: &str = "state.json";
+
+/// Where one stream of an unfinished pull stands.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StreamState {
+    /// Whether every page was pulled
+    pub done: bool,
+    /// Pages pulled so far
+    pub pages: u32,
+    /// Records pulled so far
+    pub records: usize,
+    /// Page to pull next
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) next: Option<Next>,
+    /// Highest incremental cursor seen so far
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    /// Hash of what was being pulled; a different pull starts over
+    fingerprint: String,
+    streams: BTreeMap<String, StreamState>,
+}
+
+/// The saved progress of a pull.
+#[derive(Debug)]
+pub struct Checkpoint {
+    dir: PathBuf,
+    state: State,
+}
+
+impl Checkpoint {
+    /// Open the checkpoint in `dir` for a pull described by `fingerprint`,
+    /// discarding progress saved for a different pull.
+    pub fn open(dir: &Path, fingerprint: &str) -> Result<Self> {
+        let fingerprint = sha256_hex(fingerprint.as_bytes());
+        let state = match read_json::<State>(&dir.join(STATE_FILE)) {
+            Ok(state) if state.fingerprint == fingerprint => state,
+            _ => {
+                if dir.exists() {
+                    fs::remove_dir_all(dir)?;
+                }
+                State {
+                    fingerprint,
+                    streams: BTreeMap::new(),
+                }
+            }
+        };
+        fs::create_dir_all(dir)?;
+        restrict_to_owner(dir)?;
+        let checkpoint = Self {
+            dir: dir.to_path_buf(),
+            state,
+        };
+        // Drop records of pages whose state wasn't saved before appending more
+        for (name, stream) in &checkpoint.state.streams {
+            truncate_lines(&checkpoint.records_file(name), stream.records)?;
+        }
+        Ok(checkpoint)
+    }
+
+    /// Whether progress of an earlier attempt was found.
+    pub fn resumed(&self) -> bool {
+        !self.state.streams.is_empty()
+    }
+
+    /// Where a stream stands, if any of it was pulled.
+    pub fn stream(&self, name: &str) -> Option<&StreamState> {
+        self.state.streams.get(name)
+    }
+
+    /// Records of a stream saved so far.
+    pub fn records(&self, name: &str) -> Result<Vec<Value>> {
+        let Some(state) = self.stream(name) else {
+            return Ok(Vec::new());
+        };
+        let file = fs::File::open(self.records_file(name))?;
+        // Lines past the count were written by a page whose state wasn't saved
+        let mut records = Vec::with_capacity(state.records);
+        for line in BufReader::new(file).lines().take(state.records) {
+            records.push(serde_json::from_str(&line?)?);
+        }
+        Ok(records)
+    }
+
+    /// Save a pulled page: its records, then where the stream now stands.
+    pub fn save_page(&mut self, name: &str, records: &[Value], state: StreamState) -> Result<()> {
+        let path = self.records_file(name);
+        let mut file = if self.stream(name).is_some() {
+            OpenOptions::new().append(true).open(&path)?
+        } else {
+            // Replaces leftovers of a first page whose state wasn't saved
+            fs::File::create(&path)?
+        };
+        let mut lines = String::new();
+        for record in records {
+            lines.push_str(&serde_json::to_string(record)?);
+            lines.push('\n');
+        }
+        file.write_all(lines.as_bytes())?;
+        file.sync_data()?;
+
+        self.state.streams.insert(name.to_string(), state);
+        write_file(
+            &self.dir.join(STATE_FILE),
+            serde_json::to_string_pretty(&self.state)?.as_bytes(),
+        )
+    }
+
+    /// Drop the saved progress, once the pull is stored.
+    pub fn remove(self) -> Result<()> {
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(())
+    }
+
+    fn records_file(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.jsonl", name))
+    }
+}
+
+/// Cut a JSON lines file down to its first `lines` lines.
+fn truncate_lines(path: &Path, lines: usize) -> Result<()> {
+    let mut len = 0;
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let mut line = String::new();
+    for _ in 0..lines {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 {
+            break;
+        }
+        len += read as u64;
+    }
+    if fs::metadata(path)?.len() > len {
+        OpenOptions::new().write(true).open(path)?.set_len(len)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn restrict_to_owner(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restrict_to_owner(_: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    fn page(pages: u32, records: usize) -> StreamState {
+        StreamState {
+            pages,
+            records,
+            next: Some(Next::Page(u64::from(pages) + 1)),
+            ..StreamState::default()
+        }
+    }
+
+    #[test]
+    fn test_progress_survives_reopening() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("partial");
+
+        let mut checkpoint = Checkpoint::open(&path, "connector-a").unwrap();
+        assert!(!checkpoint.resumed());
+        checkpoint
+            .save_page("issues", &[json!({"id": 1}), json!({"id": 2})], page(1, 2))
+            .unwrap();
+        checkpoint
+            .save_page("issues", &[json!({"id": 3})], page(2, 3))
+            .unwrap();
+
+        let checkpoint = Checkpoint::open(&path, "connector-a").unwrap();
+        assert!(checkpoint.resumed());
+        assert_eq!(checkpoint.stream("issues"), Some(&page(2, 3)));
+        assert_eq!(
+            checkpoint.records("issues").unwrap(),
+            vec![json!({"id": 1}), json!({"id": 2}), json!({"id": 3})]
+        );
+        assert!(checkpoint.records("repos").unwrap().is_empty());
+
+        let changed = Checkpoint::open(&path, "connector-b").unwrap();
+        assert!(!changed.resumed());
+        changed.remove().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_records_of_unsaved_pages_are_dropped() {
+        let dir = tempdir().unwrap();
+        let mut checkpoint = Checkpoint::open(dir.path(), "connector").unwrap();
+        checkpoint
+            .save_page("issues", &[json!({"id": 1})], page(1, 1))
+            .unwrap();
+        // A page whose records were written but whose state wasn't
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(checkpoint.records_file("issues"))
+            .unwrap();
+        writeln!(file, "{}", json!({"id": 2})).unwrap();
+
+        let mut checkpoint = Checkpoint::open(dir.path(), "connector").unwrap();
+        assert_eq!(
+            checkpoint.records("issues").unwrap(),
+            vec![json!({"id": 1})]
+        );
+        checkpoint
+            .save_page("issues", &[json!({"id": 3})], page(2, 2))
+            .unwrap();
+        assert_eq!(
+            checkpoint.records("issues").unwrap(),
+            vec![json!({"id": 1}), json!({"id": 3})]
+        );
+    }
+}
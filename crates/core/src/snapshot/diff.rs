@@ -0,0 +1,243 @@
+//! Comparing two seed payloads resource by resource.
+//!
+//! Records are matched by a key field (`id` by default); records without it
+//! are matched by their whole content, so they can only be added or removed.
+
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Key field records are matched by when none is given.
+pub const DEFAULT_KEY: &str = "id";
+
+/// Differences between two seed payloads.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SnapshotDiff {
+    /// Resources with differences, by name
+    pub resources: Vec<ResourceDiff>,
+}
+
+/// Differences within one resource.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResourceDiff {
+    /// Resource name
+    pub name: String,
+    /// Records only in the newer payload
+    pub added: Vec<Value>,
+    /// Records only in the older payload
+    pub removed: Vec<Value>,
+    /// Records in both with different content
+    pub changed: Vec<ChangedRecord>,
+    /// Patch operations turning the old resource into the new one
+    patch: Vec<Value>,
+}
+
+/// A record present in both payloads with different content.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangedRecord {
+    /// Value of the key field
+    pub key: String,
+    /// Top-level fields that were added, removed or changed
+    pub fields: Vec<String>,
+}
+
+impl SnapshotDiff {
+    /// Whether the payloads are equal.
+    pub fn is_empty(&self) -> bool {
+        self.resources.is_empty()
+    }
+
+    /// The differences as a JSON Patch (RFC 6902) to apply to the older payload.
+    pub fn to_json_patch(&self) -> Value {
+        Value::Array(
+            self.resources
+                .iter()
+                .flat_map(|r| r.patch.iter().cloned())
+                .collect(),
+        )
+    }
+}
+
+/// Compare two seed payloads, matching records by the `key` field.
+pub fn diff(from: &Value, to: &Value, key: &str) -> SnapshotDiff {
+    let empty = Map::new();
+    let from = from.as_object().unwrap_or(&empty);
+    let to = to.as_object().unwrap_or(&empty);
+
+    let names: BTreeSet<&String> = from.keys().chain(to.keys()).collect();
+    let resources = names
+        .into_iter()
+        .filter_map(|name| {
+            let resource = diff_resource(name, from.get(name), to.get(name), key);
+            (!resource.patch.is_empty()).then_some(resource)
+        })
+        .collect();
+
+    SnapshotDiff { resources }
+}
+
+fn diff_resource(name: &str, from: Option<&Value>, to: Option<&Value>, key: &str) -> ResourceDiff {
+    let path = format!("/{}", escape(name));
+    let mut diff = ResourceDiff {
+        name: name.to_string(),
+        ..Default::default()
+    };
+
+    let (from, to) = match (from, to) {
+        (None, Some(to)) => {
+            diff.added = records(to).to_vec();
+            diff.patch
+                .push(json!({"op": "add", "path": path, "value": to}));
+            return diff;
+        }
+        (Some(from), None) => {
+            diff.removed = records(from).to_vec();
+            diff.patch.push(json!({"op": "remove", "path": path}));
+            return diff;
+        }
+        (Some(from), Some(to)) => (from, to),
+        (None, None) => return diff,
+    };
+    if from == to {
+        return diff;
+    }
+    if !from.is_array() || !to.is_array() {
+        diff.patch
+            .push(json!({"op": "replace", "path": path, "value": to}));
+        return diff;
+    }
+
+    let old = index(records(from), key);
+    let new = index(records(to), key);
+
+    // Replacements first, while old indices are still valid, then removals
+    // from the end, then additions
+    let mut removed = Vec::new();
+    for (id, (i, record)) in &old {
+        match new.get(id) {
+            Some((_, updated)) if updated != record => {
+                diff.changed.push(ChangedRecord {
+                    key: id.clone(),
+                    fields: changed_fields(record, updated),
+                });
+                diff.patch.push(
+                    json!({"op": "replace", "path": format!("{}/{}", path, i), "value": updated}),
+                );
+            }
+            Some(_) => {}
+            None => removed.push((*i, (*record).clone())),
+        }
+    }
+    removed.sort_by_key(|(i, _)| std::cmp::Reverse(*i));
+    for (i, _) in &removed {
+        diff.patch
+            .push(json!({"op": "remove", "path": format!("{}/{}", path, i)}));
+    }
+    removed.reverse();
+    diff.removed = removed.into_iter().map(|(_, record)| record).collect();
+
+    let mut added: Vec<(usize, &Value)> = new
+        .iter()
+        .filter(|(id, _)| !old.contains_key(*id))
+        .map(|(_, (i, record))| (*i, *record))
+        .collect();
+    added.sort_by_key(|(i, _)| *i);
+    for (_, record) in added {
+        diff.patch
+            .push(json!({"op": "add", "path": format!("{}/-", path), "value": record}));
+        diff.added.push(record.clone());
+    }
+
+    diff
+}
+
+fn records(resource: &Value) -> &[Value] {
+    resource.as_array().map_or(&[], Vec::as_slice)
+}
+
+/// Records by key, with their position.
+fn index<'a>(records: &'a [Value], key: &str) -> BTreeMap<String, (usize, &'a Value)> {
+    records
+        .iter()
+        .enumerate()
+        .map(|(i, record)| {
+            let id = match record.get(key) {
+                Some(Value::String(s)) => s.clone(),
+                Some(value) if !value.is_null() => value.to_string(),
+                _ => record.to_string(),
+            };
+            (id, (i, record))
+        })
+        .collect()
+}
+
+/// Top-level fields that differ between two records.
+fn changed_fields(before: &Value, after: &Value) -> Vec<String> {
+    let empty = Map::new();
+    let before = before.as_object().unwrap_or(&empty);
+    let after = after.as_object().unwrap_or(&empty);
+    let fields: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    fields
+        .into_iter()
+        .filter(|field| before.get(*field) != after.get(*field))
+        .cloned()
+        .collect()
+}
+
+/// Escape a JSON Pointer reference token.
+fn escape(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_matches_records_by_key() {
+        let from = json!({
+            "users": [{"id": 1, "name": "ada"}, {"id": 2, "name": "bob"}, {"id": 3, "name": "cy"}],
+            "teams": [{"id": "t1"}],
+        });
+        let to = json!({
+            "users": [{"id": 1, "name": "ada"}, {"id": 3, "name": "cyd", "admin": true}, {"id": 4, "name": "dee"}],
+            "issues": [{"id": 9}],
+        });
+
+        let diff = diff(&from, &to, DEFAULT_KEY);
+        let names: Vec<&str> = diff.resources.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["issues", "teams", "users"]);
+
+        let users = &diff.resources[2];
+        assert_eq!(users.added, vec![json!({"id": 4, "name": "dee"})]);
+        assert_eq!(users.removed, vec![json!({"id": 2, "name": "bob"})]);
+        assert_eq!(
+            users.changed,
+            vec![ChangedRecord {
+                key: "3".to_string(),
+                fields: vec!["admin".to_string(), "name".to_string()],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_patch_orders_operations_safely() {
+        let from = json!({"users": [{"id": 1}, {"id": 2}, {"id": 3, "x": 1}]});
+        let to = json!({"users": [{"id": 3, "x": 2}, {"id": 5}]});
+
+        assert_eq!(
+            diff(&from, &to, DEFAULT_KEY).to_json_patch(),
+            json!([
+                {"op": "replace", "path": "/users/2", "value": {"id": 3, "x": 2}},
+                {"op": "remove", "path": "/users/1"},
+                {"op": "remove", "path": "/users/0"},
+                {"op": "add", "path": "/users/-", "value": {"id": 5}},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_identical_payloads_have_no_diff() {
+        let payload = json!({"users": [{"id": 1}]});
+        assert!(diff(&payload, &payload, DEFAULT_KEY).is_empty());
+    }
+}
@@ -0,0 +1,302 @@
+//! Browsing the records of a stored profile.
+//!
+//! `snapshot inspect --data` pages through one resource at a time, optionally
+//! keeping only records that match a jq-style filter:
+//!
+//! ```text
+//! .state == "open" and .comments > 3
+//! .owner.login == octo or .private
+//! .labels contains "bug"
+//! ```
+//!
+//! A condition is a path into the record (`.` separated, numbers index
+//! arrays) compared with a value by `==`, `!=`, `<`, `<=`, `>`, `>=` or
+//! `contains`, or a path alone, which matches when the value is there and
+//! isn't `false`. Values are JSON (`"open"`, `3`, `true`, `null`); other bare
+//! words are strings. `and` binds tighter than `or`.
+
+use crate::{Error, Result};
+use serde_json::Value;
+use std::cmp::Ordering;
+
+/// A parsed filter: records match when any of its groups matches entirely.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    any: Vec<Vec<Condition>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Condition {
+    path: Vec<String>,
+    test: Option<(Op, Value)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Op(Op),
+}
+
+impl Filter {
+    /// Parse a filter expression.
+    pub fn parse(expr: &str) -> Result<Self> {
+        let invalid = |reason: &str| Error::Other(format!("Invalid filter '{}': {}", expr, reason));
+        let mut tokens = tokenize(expr)
+            .map_err(|e| invalid(&e))?
+            .into_iter()
+            .peekable();
+
+        let mut any = Vec::new();
+        let mut all = Vec::new();
+        loop {
+            let path = match tokens.next() {
+                Some(Token::Word(word)) => parse_path(&word).ok_or_else(|| {
+                    invalid(&format!("expected a path like .name, got '{}'", word))
+                })?,
+                Some(_) => return Err(invalid("expected a path like .name")),
+                None => return Err(invalid("expected a condition")),
+            };
+            let test = match tokens.peek() {
+                Some(Token::Op(op)) => {
+                    let op = *op;
+                    tokens.next();
+                    let value = match tokens.next() {
+                        Some(Token::Text(text)) => Value::String(text),
+                        Some(Token::Word(word)) => {
+                            serde_json::from_str(&word).unwrap_or(Value::String(word))
+                        }
+                        _ => return Err(invalid("expected a value to compare with")),
+                    };
+                    Some((op, value))
+                }
+                _ => None,
+            };
+            all.push(Condition { path, test });
+
+            match tokens.next() {
+                None => break,
+                Some(Token::Word(word)) if word == "and" => {}
+                Some(Token::Word(word)) if word == "or" => any.push(std::mem::take(&mut all)),
+                Some(_) => return Err(invalid("expected 'and' or 'or' between conditions")),
+            }
+        }
+        any.push(all);
+        Ok(Self { any })
+    }
+
+    /// Whether a record matches.
+    pub fn matches(&self, record: &Value) -> bool {
+        self.any
+            .iter()
+            .any(|all| all.iter().all(|condition| condition.matches(record)))
+    }
+}
+
+impl Condition {
+    fn matches(&self, record: &Value) -> bool {
+        let value = self
+            .path
+            .iter()
+            .try_fold(record, |value, segment| match value {
+                Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+                _ => value.get(segment),
+            })
+            .unwrap_or(&Value::Null);
+        let Some((op, expected)) = &self.test else {
+            return !matches!(value, Value::Null | Value::Bool(false));
+        };
+        match op {
+            Op::Eq => compare(value, expected) == Some(Ordering::Equal),
+            Op::Ne => compare(value, expected) != Some(Ordering::Equal),
+            Op::Lt => compare(value, expected) == Some(Ordering::Less),
+            Op::Le => matches!(
+                compare(value, expected),
+                Some(Ordering::Less | Ordering::Equal)
+            ),
+            Op::Gt => compare(value, expected) == Some(Ordering::Greater),
+            Op::Ge => matches!(
+                compare(value, expected),
+                Some(Ordering::Greater | Ordering::Equal)
+            ),
+            Op::Contains => match (value, expected) {
+                (Value::String(s), Value::String(part)) => s.contains(part.as_str()),
+                (Value::Array(items), _) => items
+                    .iter()
+                    .any(|item| compare(item, expected) == Some(Ordering::Equal)),
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Order two values of the same kind; numbers compare by value.
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ if a == b => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+/// Split `.owner.login` (or `owner.login`) into its segments.
+fn parse_path(word: &str) -> Option<Vec<String>> {
+    let path = word.strip_prefix('.').unwrap_or(word);
+    if path.is_empty() {
+        return None;
+    }
+    let segments: Vec<String> = path.split('.').map(str::to_string).collect();
+    (!segments.iter().any(String::is_empty)).then_some(segments)
+}
+
+fn tokenize(expr: &str) -> std::result::Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            // Scan to the closing quote, then let JSON handle the escapes
+            chars.next();
+            let mut escaped = false;
+            let end = loop {
+                match chars.next() {
+                    Some((i, '"')) if !escaped => break i,
+                    Some((_, c)) => escaped = c == '\\' && !escaped,
+                    None => return Err("unterminated string".to_string()),
+                }
+            };
+            let text = serde_json::from_str(&expr[start..=end]).map_err(|e| e.to_string())?;
+            tokens.push(Token::Text(text));
+        } else if "=!<>".contains(c) {
+            let mut op = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if !"=!<>".contains(c) {
+                    break;
+                }
+                op.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Op(match op.as_str() {
+                "==" => Op::Eq,
+                "!=" => Op::Ne,
+                "<" => Op::Lt,
+                "<=" => Op::Le,
+                ">" => Op::Gt,
+                ">=" => Op::Ge,
+                _ => return Err(format!("unknown operator '{}'", op)),
+            }));
+        } else {
+            let mut word = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if c.is_whitespace() || "=!<>\"".contains(c) {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(match word.as_str() {
+                "contains" => Token::Op(Op::Contains),
+                _ => Token::Word(word),
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+/// One page of a resource's records.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    /// Records on the page
+    pub records: Vec<Value>,
+    /// Position of the first of them among the matching records, from 0
+    pub offset: usize,
+    /// Number of records that matched the filter (all of them without one)
+    pub matched: usize,
+}
+
+/// Page `number` (from 1) of `size` records, of those matching `filter`.
+pub fn page(records: &[Value], filter: Option<&Filter>, number: usize, size: usize) -> Page {
+    let matching: Vec<&Value> = records
+        .iter()
+        .filter(|record| filter.is_none_or(|f| f.matches(record)))
+        .collect();
+    let offset = number.saturating_sub(1).saturating_mul(size);
+    Page {
+        records: matching
+            .iter()
+            .skip(offset)
+            .take(size)
+            .map(|&record| record.clone())
+            .collect(),
+        offset,
+        matched: matching.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_filters_match_records() {
+        let open =
+            json!({"state": "open", "comments": 5, "labels": ["bug"], "owner": {"login": "octo"}});
+        let closed =
+            json!({"state": "closed", "comments": 1, "private": true, "title": "Fix \"x\""});
+        let matches = |expr: &str| {
+            let filter = Filter::parse(expr).unwrap();
+            (filter.matches(&open), filter.matches(&closed))
+        };
+
+        assert_eq!(matches(r#".state == "open""#), (true, false));
+        assert_eq!(matches(".state != open"), (false, true));
+        assert_eq!(matches(".comments >= 5"), (true, false));
+        assert_eq!(matches(".comments < 5 and .private"), (false, true));
+        assert_eq!(matches(".owner.login == octo or .private"), (true, true));
+        assert_eq!(matches(r#".labels contains "bug""#), (true, false));
+        assert_eq!(matches(r#".title contains "\"x\"""#), (false, true));
+        assert_eq!(matches(".labels.0 == bug"), (true, false));
+
+        for invalid in [
+            "",
+            ".state ==",
+            ".state === 1",
+            "== 1",
+            ".a .b",
+            r#".a == "x"#,
+        ] {
+            assert!(Filter::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_page_through_matching_records() {
+        let records: Vec<Value> = (1..=7).map(|id| json!({"id": id})).collect();
+        let second = page(&records, None, 2, 3);
+        assert_eq!(
+            second.records,
+            vec![json!({"id": 4}), json!({"id": 5}), json!({"id": 6})]
+        );
+        assert_eq!((second.offset, second.matched), (3, 7));
+
+        let filter = Filter::parse(".id > 4").unwrap();
+        let filtered = page(&records, Some(&filter), 1, 2);
+        assert_eq!(filtered.records, vec![json!({"id": 5}), json!({"id": 6})]);
+        assert_eq!(filtered.matched, 3);
+        assert!(page(&records, Some(&filter), 3, 2).records.is_empty());
+    }
+}
@@ -0,0 +1,270 @@
+//! Combining several stored profiles into one derived profile.
+//!
+//! Resources are unioned. Within a resource, records are matched by a key
+//! field like in [`diff`](super::diff): records only in one profile are kept,
+//! identical ones are kept once, and records sharing a key with different
+//! content are conflicts settled by a [`ConflictPolicy`]. Profiles are merged
+//! in the order given, so "first" and "last" refer to that order.
+
+use super::{SnapshotManifest, SnapshotStore};
+use crate::redact::RedactionReport;
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// Connector name recorded for merged profiles.
+pub const MERGE_CONNECTOR: &str = "merge";
+
+/// How to settle records that differ between profiles.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep the record from the profile listed first
+    First,
+    /// Keep the record from the profile listed last
+    #[default]
+    Last,
+    /// Refuse to merge
+    Fail,
+}
+
+/// A profile a derived profile was built from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Source profile name
+    pub profile: String,
+    /// When the source was taken
+    pub created_at: u64,
+    /// Connector that produced the source
+    pub connector: String,
+    /// SHA-256 of the source's uncompressed seed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed_sha256: Option<String>,
+}
+
+/// Merge stored profiles of a service into a new profile `into`.
+///
+/// Returns the new profile's manifest and the number of conflicts settled.
+/// All sources must share a `seed_schema_version`; redaction counts are
+/// carried over from them.
+pub fn merge(
+    store: &SnapshotStore,
+    service: &str,
+    profiles: &[String],
+    into: &str,
+    key: &str,
+    policy: ConflictPolicy,
+) -> Result<(SnapshotManifest, usize)> {
+    if profiles.len() < 2 {
+        return Err(Error::Other(
+            "Merging needs at least two profiles".to_string(),
+        ));
+    }
+
+    let mut sources = Vec::new();
+    for profile in profiles {
+        let manifest = store.manifest(service, profile)?;
+        let seed = store.load_seed_payload(service, profile)?;
+        sources.push((manifest, seed));
+    }
+    let version = sources[0].0.seed_schema_version;
+    if let Some((other, _)) = sources
+        .iter()
+        .find(|(m, _)| m.seed_schema_version != version)
+    {
+        return Err(Error::Other(format!(
+            "Can't merge {}/{} (seed schema version {}) with {}/{} (version {}); migrate them first",
+            service,
+            profiles[0],
+            version,
+            service,
+            other.profile,
+            other.seed_schema_version
+        )));
+    }
+
+    let mut merged = Map::new();
+    let mut conflicts = 0;
+    for (manifest, seed) in &sources {
+        let Some(resources) = seed.as_object() else {
+            continue;
+        };
+        for (name, incoming) in resources {
+            let Some(existing) = merged.get_mut(name) else {
+                merged.insert(name.clone(), incoming.clone());
+                continue;
+            };
+            conflicts += merge_resource(existing, incoming, key, policy).map_err(|record| {
+                Error::Other(format!(
+                    "{}/{} conflicts with an earlier profile on {} {}={} \
+                     (use --on-conflict first or last to keep one side)",
+                    service, manifest.profile, name, key, record
+                ))
+            })?;
+        }
+    }
+
+    let seed = Value::Object(merged);
+    let mut manifest = SnapshotManifest::new(service, into, MERGE_CONNECTOR, &seed);
+    manifest.seed_schema_version = version;
+    let mut redactions = RedactionReport::new();
+    for (source, _) in &sources {
+        for (path, count) in &source.redactions {
+            *redactions.entry(path.clone()).or_default() += count;
+        }
+    }
+    manifest.redactions = redactions;
+    manifest.pseudonyms = sources
+        .iter()
+        .flat_map(|(source, _)| source.pseudonyms.iter().cloned())
+        .collect();
+    manifest.sources = sources
+        .into_iter()
+        .map(|(source, _)| Provenance {
+            profile: source.profile,
+            created_at: source.created_at,
+            connector: source.connector,
+            seed_sha256: source.seed_sha256,
+        })
+        .collect();
+    store.save(&mut manifest, &seed)?;
+    Ok((manifest, conflicts))
+}
+
+/// Merge `incoming` records into `existing`, returning the number of
+/// conflicts, or the key of the first conflict under [`ConflictPolicy::Fail`].
+pub(crate) fn merge_resource(
+    existing: &mut Value,
+    incoming: &Value,
+    key: &str,
+    policy: ConflictPolicy,
+) -> std::result::Result<usize, String> {
+    let (Value::Array(records), Value::Array(new)) = (&mut *existing, incoming) else {
+        // Resources that aren't record lists are settled as a whole
+        if existing == incoming {
+            return Ok(0);
+        }
+        match policy {
+            ConflictPolicy::First => {}
+            ConflictPolicy::Last => *existing = incoming.clone(),
+            ConflictPolicy::Fail => return Err("(whole resource)".to_string()),
+        }
+        return Ok(1);
+    };
+
+    let mut positions: HashMap<String, usize> = records
+        .iter()
+        .enumerate()
+        .map(|(i, record)| (identity(record, key), i))
+        .collect();
+    let mut conflicts = 0;
+    for record in new {
+        let id = identity(record, key);
+        match positions.get(&id) {
+            None => {
+                positions.insert(id, records.len());
+                records.push(record.clone());
+            }
+            Some(&i) if records[i] == *record => {}
+            Some(&i) => {
+                conflicts += 1;
+                match policy {
+                    ConflictPolicy::First => {}
+                    ConflictPolicy::Last => records[i] = record.clone(),
+                    ConflictPolicy::Fail => return Err(id),
+                }
+            }
+        }
+    }
+    Ok(conflicts)
+}
+
+/// The key field's value, or the whole record when it has none.
+pub(super) fn identity(record: &Value, key: &str) -> String {
+    match record.get(key) {
+        Some(Value::String(s)) => s.clone(),
+        Some(value) if !value.is_null() => value.to_string(),
+        _ => record.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::diff::DEFAULT_KEY;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    fn store_with(profiles: &[(&str, Value)]) -> (tempfile::TempDir, SnapshotStore) {
+        let dir = tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path());
+        for (profile, seed) in profiles {
+            let mut manifest = SnapshotManifest::new("github", profile, "rest", seed);
+            store.save(&mut manifest, seed).unwrap();
+        }
+        (dir, store)
+    }
+
+    fn names(profiles: &[&str]) -> Vec<String> {
+        profiles.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_merge_unions_resources_with_provenance() {
+        let (_dir, store) = store_with(&[
+            ("repos", json!({"repos": [{"id": 1, "name": "api"}]})),
+            (
+                "issues",
+                json!({"issues": [{"id": 7}], "repos": [{"id": 1, "name": "api"}, {"id": 2}]}),
+            ),
+        ]);
+
+        let (manifest, conflicts) = merge(
+            &store,
+            "github",
+            &names(&["repos", "issues"]),
+            "baseline",
+            DEFAULT_KEY,
+            ConflictPolicy::Fail,
+        )
+        .unwrap();
+        assert_eq!(conflicts, 0);
+        assert_eq!(manifest.connector, MERGE_CONNECTOR);
+        let sources: Vec<&str> = manifest
+            .sources
+            .iter()
+            .map(|s| s.profile.as_str())
+            .collect();
+        assert_eq!(sources, vec!["repos", "issues"]);
+        assert_eq!(
+            store.load_seed_payload("github", "baseline").unwrap(),
+            json!({"repos": [{"id": 1, "name": "api"}, {"id": 2}], "issues": [{"id": 7}]})
+        );
+        assert_eq!(store.manifest("github", "baseline").unwrap(), manifest);
+    }
+
+    #[test]
+    fn test_conflict_policies() {
+        let (_dir, store) = store_with(&[
+            ("prod", json!({"users": [{"id": 1, "role": "admin"}]})),
+            (
+                "edge",
+                json!({"users": [{"id": 1, "role": "banned"}, {"id": 2}]}),
+            ),
+        ]);
+        let profiles = names(&["prod", "edge"]);
+        let merged = |policy| {
+            merge(&store, "github", &profiles, "out", DEFAULT_KEY, policy).map(|(_, n)| {
+                (
+                    n,
+                    store.load_seed_payload("github", "out").unwrap()["users"][0]["role"].clone(),
+                )
+            })
+        };
+
+        assert_eq!(merged(ConflictPolicy::First).unwrap(), (1, json!("admin")));
+        assert_eq!(merged(ConflictPolicy::Last).unwrap(), (1, json!("banned")));
+        let msg = merged(ConflictPolicy::Fail).unwrap_err().to_string();
+        assert!(msg.contains("users id=1"), "{}", msg);
+    }
+}
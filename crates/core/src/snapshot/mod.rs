@@ -0,0 +1,1189 @@
+//! Snapshots: seed payloads pulled from real APIs and stored as named profiles.
+//!
+//! A service declares how to pull its data in the `connector` section of its
+//! service.yaml. Each pull is stored as a profile under
+//! `<data_dir>/snapshots/<service>/<profile>/`:
+//!
+//! - `seed.json.zst`: the payload, one array of records per resource, ready to
+//!   be POSTed to `/_doubleagent/seed`, as zstd-compressed JSON
+//! - `manifest.json`: a [`SnapshotManifest`] describing the pull
+//!
+//! Profiles stored before compression was added keep a plain `seed.json`,
+//! which is read the same way.
+
+pub mod checkpoint;
+pub mod diff;
+pub mod inspect;
+pub mod merge;
+#[cfg(feature = "native-storage")]
+mod native;
+pub mod oci;
+pub mod progress;
+pub mod record;
+pub mod registry;
+pub mod rest;
+pub mod sample;
+pub mod scenario;
+pub mod schema;
+
+use crate::redact::{RedactionReport, Redactor};
+use crate::secrets::SecretResolver;
+use crate::service::{default_seed_schema_version, ServiceDefinition};
+use crate::{workspace, Error, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub use checkpoint::Checkpoint;
+pub use diff::{diff, SnapshotDiff};
+pub use merge::{merge, ConflictPolicy, Provenance};
+pub use progress::ProgressEvent;
+pub use record::Recording;
+pub use registry::Registry;
+pub use rest::{RequestLimits, RestConnector};
+pub use sample::{sample, Reference, SampleOptions};
+pub use scenario::Scenario;
+
+/// Profile name used when none is given.
+pub const DEFAULT_PROFILE: &str = "default";
+
+const MANIFEST_FILE: &str = "manifest.json";
+const SEED_FILE: &str = "seed.json";
+const COMPRESSED_SEED_FILE: &str = "seed.json.zst";
+
+/// zstd level seed payloads are compressed with.
+const COMPRESSION_LEVEL: i32 = 9;
+
+/// How snapshots of a service are pulled, from the `connector` section of
+/// service.yaml.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ConnectorConfig {
+    /// A plain REST API, pulled natively without any subprocess
+    Rest(RestConnector),
+}
+
+impl ConnectorConfig {
+    /// Name of the connector type, as written in service.yaml.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ConnectorConfig::Rest(_) => "rest",
+        }
+    }
+}
+
+/// Description of a stored snapshot profile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    /// Service the snapshot belongs to
+    pub service: String,
+    /// Profile name
+    pub profile: String,
+    /// When the snapshot was taken (seconds since the Unix epoch)
+    pub created_at: u64,
+    /// Connector type that produced it
+    pub connector: String,
+    /// Number of records per resource
+    pub resources: BTreeMap<String, usize>,
+    /// Number of values redacted, by path
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub redactions: RedactionReport,
+    /// Digests of the pseudonyms redaction put in the payload, left alone
+    /// when it is redacted or scanned again
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub pseudonyms: BTreeSet<String>,
+    /// SHA-256 of the uncompressed seed, checked when a profile is fetched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed_sha256: Option<String>,
+    /// The service's `seed_schema_version` the payload was written for
+    #[serde(default = "default_seed_schema_version")]
+    pub seed_schema_version: u32,
+    /// Profiles a merged profile was built from
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<Provenance>,
+    /// Last cursor of each incremental stream, where a refresh resumes
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub cursors: BTreeMap<String, String>,
+    /// When the profile was last refreshed (seconds since the Unix epoch)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refreshed_at: Option<u64>,
+    /// Size of the seed payload as JSON, in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Size of the payload as stored, in bytes (absent for uncompressed profiles)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compressed_size: Option<u64>,
+    /// API requests the last pull or refresh made
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests: Option<u64>,
+    /// Request budget the last pull or refresh had, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_requests: Option<u64>,
+}
+
+impl SnapshotManifest {
+    /// Describe a seed payload taken now.
+    pub fn new(service: &str, profile: &str, connector: &str, seed: &Value) -> Self {
+        let resources = seed
+            .as_object()
+            .map(|resources| {
+                resources
+                    .iter()
+                    .map(|(name, records)| (name.clone(), records.as_array().map_or(0, Vec::len)))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let json = seed_json(seed);
+        Self {
+            service: service.to_string(),
+            profile: profile.to_string(),
+            created_at: now(),
+            connector: connector.to_string(),
+            resources,
+            redactions: RedactionReport::new(),
+            pseudonyms: BTreeSet::new(),
+            seed_sha256: Some(sha256_hex(json.as_bytes())),
+            seed_schema_version: default_seed_schema_version(),
+            sources: Vec::new(),
+            cursors: BTreeMap::new(),
+            refreshed_at: None,
+            size: Some(json.len() as u64),
+            compressed_size: None,
+            requests: None,
+            max_requests: None,
+        }
+    }
+
+    /// Describe `seed` in place of the payload this manifest described, adding
+    /// the values `report` counts as redacted from it. Where the profile came
+    /// from and how it was pulled are kept.
+    fn redescribe(&mut self, seed: &Value, report: RedactionReport) {
+        let described = Self::new(&self.service, &self.profile, &self.connector, seed);
+        self.resources = described.resources;
+        self.seed_sha256 = described.seed_sha256;
+        self.size = described.size;
+        for (path, count) in report {
+            *self.redactions.entry(path).or_default() += count;
+        }
+    }
+
+    /// Check that seed.json content matches this manifest.
+    pub fn verify(&self, seed_json: &[u8]) -> Result<()> {
+        if let Some(expected) = &self.seed_sha256 {
+            let actual = sha256_hex(seed_json);
+            if actual != *expected {
+                return Err(Error::Other(format!(
+                    "Snapshot {}/{} is corrupt: seed.json has checksum {}, manifest expects {}",
+                    self.service, self.profile, actual, expected
+                )));
+            }
+        }
+
+        let seed: Value = serde_json::from_slice(seed_json).map_err(|e| {
+            Error::Other(format!(
+                "Snapshot {}/{} has an invalid seed.json: {}",
+                self.service, self.profile, e
+            ))
+        })?;
+        let counted = SnapshotManifest::new(&self.service, &self.profile, &self.connector, &seed);
+        if counted.resources != self.resources {
+            return Err(Error::Other(format!(
+                "Snapshot {}/{} does not match its manifest: record counts differ",
+                self.service, self.profile
+            )));
+        }
+        Ok(())
+    }
+
+    /// Name of the seed file of the profile.
+    fn seed_file(&self) -> &'static str {
+        if self.compressed_size.is_some() {
+            COMPRESSED_SEED_FILE
+        } else {
+            SEED_FILE
+        }
+    }
+
+    /// Total number of records across resources.
+    pub fn total_records(&self) -> usize {
+        self.resources.values().sum()
+    }
+}
+
+/// Local storage of snapshot profiles.
+#[derive(Debug, Clone)]
+pub struct SnapshotStore {
+    root: PathBuf,
+}
+
+impl SnapshotStore {
+    /// Store snapshots under `<data_dir>/snapshots`.
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            root: data_dir.join("snapshots"),
+        }
+    }
+
+    /// Directory holding a profile.
+    pub fn profile_dir(&self, service: &str, profile: &str) -> PathBuf {
+        self.root.join(service).join(profile)
+    }
+
+    /// Whether a profile has been stored.
+    pub fn exists(&self, service: &str, profile: &str) -> bool {
+        self.profile_dir(service, profile)
+            .join(MANIFEST_FILE)
+            .is_file()
+    }
+
+    /// Store a seed payload as a profile, replacing any previous one.
+    ///
+    /// The payload is compressed, and the manifest's sizes updated to match.
+    pub fn save(&self, manifest: &mut SnapshotManifest, seed: &Value) -> Result<PathBuf> {
+        check_profile_name(&manifest.profile)?;
+        let dir = self.profile_dir(&manifest.service, &manifest.profile);
+        fs::create_dir_all(&dir)?;
+
+        let json = seed_json(seed);
+        let compressed = zstd::encode_all(json.as_bytes(), COMPRESSION_LEVEL)?;
+        manifest.size = Some(json.len() as u64);
+        manifest.compressed_size = Some(compressed.len() as u64);
+
+        // The manifest is written last, so a profile only exists once complete
+        write_file(&dir.join(COMPRESSED_SEED_FILE), &compressed)?;
+        let legacy = dir.join(SEED_FILE);
+        if legacy.exists() {
+            fs::remove_file(legacy)?;
+        }
+        write_file(
+            &dir.join(MANIFEST_FILE),
+            serde_json::to_string_pretty(manifest)?.as_bytes(),
+        )?;
+        Ok(dir)
+    }
+
+    /// Where the progress of an unfinished pull of a profile is kept.
+    fn partial_dir(&self, service: &str, profile: &str) -> PathBuf {
+        self.root.join(".partial").join(service).join(profile)
+    }
+
+    /// Open the saved progress of a pull of a profile described by `fingerprint`.
+    fn checkpoint(&self, service: &str, profile: &str, fingerprint: &str) -> Result<Checkpoint> {
+        Checkpoint::open(&self.partial_dir(service, profile), fingerprint)
+    }
+
+    /// Drop the progress of an unfinished pull, so the next pull starts over.
+    pub fn discard_partial(&self, service: &str, profile: &str) -> Result<()> {
+        let dir = self.partial_dir(service, profile);
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+        Ok(())
+    }
+
+    /// Delete a stored profile.
+    pub fn remove(&self, service: &str, profile: &str) -> Result<()> {
+        check_profile_name(profile)?;
+        let dir = self.profile_dir(service, profile);
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+        Ok(())
+    }
+
+    /// Move a complete profile directory into place, replacing any previous one.
+    fn install(&self, staged: &Path, service: &str, profile: &str) -> Result<PathBuf> {
+        check_profile_name(profile)?;
+        let dir = self.profile_dir(service, profile);
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(self.root.join(service))?;
+        fs::rename(staged, &dir)?;
+        Ok(dir)
+    }
+
+    /// A fresh scratch directory inside the store, so it can be renamed into place.
+    fn staging_dir(&self) -> Result<PathBuf> {
+        let dir = self.root.join(format!(".staging-{}", std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    /// Read a profile's manifest.
+    pub fn manifest(&self, service: &str, profile: &str) -> Result<SnapshotManifest> {
+        let path = self.profile_dir(service, profile).join(MANIFEST_FILE);
+        if !path.is_file() {
+            return Err(Error::Other(format!(
+                "No snapshot profile '{}' for {}. Run 'doubleagent snapshot pull {} --profile {}' first.",
+                profile, service, service, profile
+            )));
+        }
+        read_json(&path)
+    }
+
+    /// Read a profile's seed payload.
+    pub fn load_seed_payload(&self, service: &str, profile: &str) -> Result<Value> {
+        let manifest = self.manifest(service, profile)?;
+        self.read_seed(&manifest)
+    }
+
+    /// Read and decompress a profile's seed payload.
+    fn read_seed(&self, manifest: &SnapshotManifest) -> Result<Value> {
+        let path = self.seed_path(manifest);
+        let bytes = read_seed_file(&path)?;
+        serde_json::from_slice(&bytes)
+            .map_err(|e| Error::Other(format!("Invalid {}: {}", path.display(), e)))
+    }
+
+    /// Read a profile's seed payload to seed a service with, refusing it if it
+    /// was written for another `seed_schema_version` of the service or doesn't
+    /// match the service's seed schema.
+    pub fn load_seed(&self, service: &ServiceDefinition, profile: &str) -> Result<Value> {
+        let manifest = self.manifest(&service.name, profile)?;
+        let (have, want) = (manifest.seed_schema_version, service.seed_schema_version);
+        if have < want {
+            return Err(Error::Other(format!(
+                "Snapshot {}/{} has seed schema version {}, but the service expects {}. \
+                 Run 'doubleagent snapshot migrate {} --profile {}' to upgrade it.",
+                service.name, profile, have, want, service.name, profile
+            )));
+        }
+        if have > want {
+            return Err(Error::Other(format!(
+                "Snapshot {}/{} has seed schema version {}, newer than the {} the installed service expects. \
+                 Run 'doubleagent update {}' to get a newer version of the service.",
+                service.name, profile, have, want, service.name
+            )));
+        }
+        let seed = self.read_seed(&manifest)?;
+        schema::validate(service, profile, &self.seed_path(&manifest), &seed)?;
+        Ok(seed)
+    }
+
+    /// Path of a profile's seed file.
+    pub fn seed_path(&self, manifest: &SnapshotManifest) -> PathBuf {
+        self.profile_dir(&manifest.service, &manifest.profile)
+            .join(manifest.seed_file())
+    }
+
+    /// Manifests of every stored profile, optionally for one service only.
+    pub fn list(&self, service: Option<&str>) -> Result<Vec<SnapshotManifest>> {
+        let services = match service {
+            Some(service) => vec![service.to_string()],
+            None => dir_names(&self.root)?,
+        };
+
+        let mut manifests = Vec::new();
+        for service in services {
+            for profile in dir_names(&self.root.join(&service))? {
+                let path = self.profile_dir(&service, &profile).join(MANIFEST_FILE);
+                if !path.is_file() {
+                    continue;
+                }
+                match read_json(&path) {
+                    Ok(manifest) => manifests.push(manifest),
+                    Err(e) => tracing::warn!("Skipping {}: {}", path.display(), e),
+                }
+            }
+        }
+        Ok(manifests)
+    }
+}
+
+/// Pull a snapshot of a service with its connector and store it as a
+/// redacted profile, reporting progress to `progress` as it goes.
+///
+/// Pages are saved as they are pulled, so pulling a profile again after a
+/// failure resumes where the failed pull stopped. `limits` override the
+/// connector's rate limit and request budget.
+///
+/// `check` is given the redacted profile before it is stored; if it fails,
+/// nothing is stored, the pulled pages are dropped and a profile stored
+/// before is left as it was.
+#[allow(clippy::too_many_arguments)]
+pub async fn pull(
+    service: &ServiceDefinition,
+    profile: &str,
+    store: &SnapshotStore,
+    secrets: &SecretResolver,
+    redactor: &Redactor,
+    limit: Option<usize>,
+    limits: RequestLimits,
+    progress: &dyn Fn(&ProgressEvent),
+    check: &dyn Fn(&SnapshotManifest, &Value) -> Result<()>,
+) -> Result<SnapshotManifest> {
+    check_profile_name(profile)?;
+    let connector = service.connector.as_ref().ok_or_else(|| {
+        Error::Other(format!(
+            "Service '{}' has no 'connector' section in service.yaml, so it can't be snapshotted",
+            service.name
+        ))
+    })?;
+
+    let cursors = BTreeMap::new();
+    let mut checkpoint = store.checkpoint(
+        &service.name,
+        profile,
+        &pull_fingerprint(connector, limit, &cursors)?,
+    )?;
+    let ConnectorConfig::Rest(rest) = connector;
+    let limits = rest.limits(limits);
+    let pulled = rest::pull(
+        rest,
+        secrets,
+        limit,
+        limits,
+        &cursors,
+        Some(&mut checkpoint),
+        progress,
+    )
+    .await?;
+    let mut seed = Value::Object(pulled.resources);
+    let mut manifest = redacted_manifest(service, profile, connector.kind(), &mut seed, redactor)?;
+    manifest.cursors = pulled.cursors;
+    manifest.requests = Some(pulled.requests);
+    manifest.max_requests = limits.max_requests;
+    if let Err(e) = check(&manifest, &seed) {
+        checkpoint.remove()?;
+        return Err(e);
+    }
+    store.save(&mut manifest, &seed)?;
+    checkpoint.remove()?;
+    Ok(manifest)
+}
+
+/// Bring a pulled profile up to date, returning its new manifest and the
+/// number of records fetched.
+///
+/// Incremental streams with a stored cursor only fetch records past it and
+/// replace stored records by their key; other streams are pulled again in
+/// full. `check` is given the refreshed profile before it replaces the
+/// stored one, which is kept as it was if the check fails.
+#[allow(clippy::too_many_arguments)]
+pub async fn refresh(
+    service: &ServiceDefinition,
+    profile: &str,
+    store: &SnapshotStore,
+    secrets: &SecretResolver,
+    redactor: &Redactor,
+    limits: RequestLimits,
+    progress: &dyn Fn(&ProgressEvent),
+    check: &dyn Fn(&SnapshotManifest, &Value) -> Result<()>,
+) -> Result<(SnapshotManifest, usize)> {
+    let previous = store.manifest(&service.name, profile)?;
+    let connector = match &service.connector {
+        Some(connector) if connector.kind() == previous.connector => connector,
+        _ => {
+            return Err(Error::Other(format!(
+                "Snapshot {}/{} was made by '{}', not pulled with the service's connector, so it can't be refreshed",
+                service.name, profile, previous.connector
+            )))
+        }
+    };
+    if previous.seed_schema_version != service.seed_schema_version {
+        return Err(Error::Other(format!(
+            "Snapshot {}/{} has seed schema version {}, but the service expects {}. \
+             Run 'doubleagent snapshot migrate {} --profile {}' before refreshing it.",
+            service.name,
+            profile,
+            previous.seed_schema_version,
+            service.seed_schema_version,
+            service.name,
+            profile
+        )));
+    }
+
+    let mut checkpoint = store.checkpoint(
+        &service.name,
+        profile,
+        &pull_fingerprint(connector, None, &previous.cursors)?,
+    )?;
+    let ConnectorConfig::Rest(rest) = connector;
+    let limits = rest.limits(limits);
+    let pulled = rest::pull(
+        rest,
+        secrets,
+        None,
+        limits,
+        &previous.cursors,
+        Some(&mut checkpoint),
+        progress,
+    )
+    .await?;
+    let mut seed = store.load_seed_payload(&service.name, profile)?;
+    let resources = seed.as_object_mut().ok_or_else(|| {
+        Error::Other(format!(
+            "Snapshot {}/{} has an invalid seed.json",
+            service.name, profile
+        ))
+    })?;
+
+    let mut fetched = 0;
+    for (name, records) in pulled.resources {
+        fetched += records.as_array().map_or(0, Vec::len);
+        let incremental = rest.streams.get(&name).and_then(|s| s.incremental.as_ref());
+        match (incremental, resources.get_mut(&name)) {
+            (Some(incremental), Some(existing)) if previous.cursors.contains_key(&name) => {
+                // Replacing with the latest never fails
+                let _ = merge::merge_resource(
+                    existing,
+                    &records,
+                    &incremental.key,
+                    ConflictPolicy::Last,
+                );
+            }
+            _ => {
+                resources.insert(name, records);
+            }
+        }
+    }
+
+    let mut manifest = previous;
+    let report = redactor.redact(&mut seed, &mut manifest.pseudonyms);
+    manifest.redescribe(&seed, report);
+    manifest.refreshed_at = Some(now());
+    manifest.cursors.extend(pulled.cursors);
+    manifest.requests = Some(pulled.requests);
+    manifest.max_requests = limits.max_requests;
+    if let Err(e) = check(&manifest, &seed) {
+        checkpoint.remove()?;
+        return Err(e);
+    }
+    store.save(&mut manifest, &seed)?;
+    checkpoint.remove()?;
+    Ok((manifest, fetched))
+}
+
+/// Redact a seed payload from elsewhere (e.g. a file) and store it as a profile.
+pub fn import(
+    store: &SnapshotStore,
+    service: &ServiceDefinition,
+    profile: &str,
+    seed: Value,
+    redactor: &Redactor,
+) -> Result<SnapshotManifest> {
+    if !seed.is_object() {
+        return Err(Error::Other(
+            "A seed payload must be an object of resource names to records".to_string(),
+        ));
+    }
+    store_redacted(store, service, profile, "import", seed, redactor)
+}
+
+/// Redact the state exported from a running fake and store it as a profile.
+pub fn save_dump(
+    store: &SnapshotStore,
+    service: &ServiceDefinition,
+    profile: &str,
+    state: Value,
+    redactor: &Redactor,
+) -> Result<SnapshotManifest> {
+    if !state.is_object() {
+        return Err(Error::Other(format!(
+            "{} exported state that isn't an object of resource names to records",
+            service.name
+        )));
+    }
+    store_redacted(store, service, profile, "dump", state, redactor)
+}
+
+/// Redact the responses seen by a recording proxy and store them as a profile.
+pub fn save_recording(
+    store: &SnapshotStore,
+    service: &ServiceDefinition,
+    profile: &str,
+    recording: Recording,
+    redactor: &Redactor,
+) -> Result<SnapshotManifest> {
+    if recording.recorded == 0 {
+        return Err(Error::Other(format!(
+            "No JSON responses were recorded for {}, so no profile was stored",
+            service.name
+        )));
+    }
+    store_redacted(
+        store,
+        service,
+        profile,
+        "record",
+        recording.into_seed(),
+        redactor,
+    )
+}
+
+/// Apply redaction rules to a stored profile again, e.g. after adding rules.
+pub fn redact_profile(
+    store: &SnapshotStore,
+    service: &str,
+    profile: &str,
+    redactor: &Redactor,
+) -> Result<SnapshotManifest> {
+    let mut manifest = store.manifest(service, profile)?;
+    let mut seed = store.load_seed_payload(service, profile)?;
+    let report = redactor.redact(&mut seed, &mut manifest.pseudonyms);
+    manifest.redescribe(&seed, report);
+    store.save(&mut manifest, &seed)?;
+    Ok(manifest)
+}
+
+/// Migrate a stored profile to the service's current `seed_schema_version`
+/// with the service's `seed_migrations`, redacting the result again.
+///
+/// Each migration command runs in the service directory with the seed
+/// payload as JSON on stdin and prints the migrated payload on stdout.
+pub fn migrate(
+    store: &SnapshotStore,
+    service: &ServiceDefinition,
+    profile: &str,
+    redactor: &Redactor,
+) -> Result<SnapshotManifest> {
+    let previous = store.manifest(&service.name, profile)?;
+    let target = service.seed_schema_version;
+    if previous.seed_schema_version > target {
+        return Err(Error::Other(format!(
+            "Snapshot {}/{} has seed schema version {}, newer than the {} the installed service expects; it can't be migrated back",
+            service.name, profile, previous.seed_schema_version, target
+        )));
+    }
+
+    let mut seed = store.load_seed_payload(&service.name, profile)?;
+    for version in previous.seed_schema_version..target {
+        let migration = service
+            .seed_migrations
+            .iter()
+            .find(|m| m.from == version)
+            .ok_or_else(|| {
+            Error::Other(format!(
+                "Service '{}' has no seed migration from version {} (add one under 'seed_migrations' in service.yaml)",
+                service.name, version
+            ))
+        })?;
+        seed = run_migration(service, &migration.command, &seed).map_err(|e| {
+            Error::Other(format!(
+                "Seed migration {} -> {} of {}/{} failed: {}",
+                version,
+                version + 1,
+                service.name,
+                profile,
+                e
+            ))
+        })?;
+    }
+
+    let mut manifest = previous;
+    let report = redactor.redact(&mut seed, &mut manifest.pseudonyms);
+    manifest.redescribe(&seed, report);
+    manifest.seed_schema_version = target;
+    store.save(&mut manifest, &seed)?;
+    Ok(manifest)
+}
+
+fn run_migration(service: &ServiceDefinition, command: &[String], seed: &Value) -> Result<Value> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| Error::Other("empty command".to_string()))?;
+    let mut child = Command::new(program)
+        .args(args)
+        .current_dir(&service.path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Other(format!("could not run '{}': {}", program, e)))?;
+
+    // Write from another thread so a script printing as it reads can't block
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = seed_json(seed);
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    let _ = writer.join();
+
+    if !output.status.success() {
+        return Err(Error::Other(format!(
+            "'{}' exited with {}: {}",
+            command.join(" "),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let migrated: Value = serde_json::from_slice(&output.stdout).map_err(|e| {
+        Error::Other(format!(
+            "'{}' printed invalid JSON: {}",
+            command.join(" "),
+            e
+        ))
+    })?;
+    if !migrated.is_object() {
+        return Err(Error::Other(format!(
+            "'{}' must print an object of resource names to records",
+            command.join(" ")
+        )));
+    }
+    Ok(migrated)
+}
+
+fn store_redacted(
+    store: &SnapshotStore,
+    service: &ServiceDefinition,
+    profile: &str,
+    source: &str,
+    mut seed: Value,
+    redactor: &Redactor,
+) -> Result<SnapshotManifest> {
+    let mut manifest = redacted_manifest(service, profile, source, &mut seed, redactor)?;
+    store.save(&mut manifest, &seed)?;
+    Ok(manifest)
+}
+
+/// Redact a new seed payload and describe it.
+fn redacted_manifest(
+    service: &ServiceDefinition,
+    profile: &str,
+    source: &str,
+    seed: &mut Value,
+    redactor: &Redactor,
+) -> Result<SnapshotManifest> {
+    check_profile_name(profile)?;
+    let mut pseudonyms = BTreeSet::new();
+    let redactions = redactor.redact(seed, &mut pseudonyms);
+    let mut manifest = SnapshotManifest::new(&service.name, profile, source, seed);
+    manifest.redactions = redactions;
+    manifest.pseudonyms = pseudonyms;
+    manifest.seed_schema_version = service.seed_schema_version;
+    Ok(manifest)
+}
+
+/// What a pull depends on; saved progress is only reused for the same pull.
+fn pull_fingerprint(
+    connector: &ConnectorConfig,
+    limit: Option<usize>,
+    cursors: &BTreeMap<String, String>,
+) -> Result<String> {
+    // Changing the request limits keeps the pages already pulled
+    let ConnectorConfig::Rest(rest) = connector;
+    let mut rest = rest.clone();
+    rest.rate_limit = None;
+    rest.max_requests = None;
+    Ok(format!(
+        "{}\n{:?}\n{}",
+        serde_json::to_string(&ConnectorConfig::Rest(rest))?,
+        limit,
+        serde_json::to_string(cursors)?
+    ))
+}
+
+/// Seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Profile names follow the same rules as workspace names.
+fn check_profile_name(name: &str) -> Result<()> {
+    workspace::validate_name(name).map_err(|_| {
+        Error::Other(format!(
+            "Invalid profile name '{}' (use letters, digits, '-' and '_')",
+            name
+        ))
+    })
+}
+
+/// How a seed payload is written to seed.json.
+fn seed_json(seed: &Value) -> String {
+    serde_json::to_string_pretty(seed).unwrap_or_default()
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// The JSON bytes of a seed file, decompressing `.zst` files.
+fn read_seed_file(path: &Path) -> Result<Vec<u8>> {
+    let bytes = fs::read(path)
+        .map_err(|e| Error::Other(format!("Failed to read {}: {}", path.display(), e)))?;
+    if path.extension().is_some_and(|e| e == "zst") {
+        return zstd::decode_all(bytes.as_slice())
+            .map_err(|e| Error::Other(format!("Failed to decompress {}: {}", path.display(), e)));
+    }
+    Ok(bytes)
+}
+
+fn write_file(path: &Path, content: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| Error::Other(format!("Failed to read {}: {}", path.display(), e)))?;
+    serde_json::from_str(&content)
+        .map_err(|e| Error::Other(format!("Invalid {}: {}", path.display(), e)))
+}
+
+/// Sorted names of the subdirectories of a directory (none if it is missing).
+fn dir_names(dir: &Path) -> Result<Vec<String>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            match entry.file_name().to_str() {
+                Some(name) if !name.starts_with('.') => names.push(name.to_string()),
+                _ => {}
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    fn service(yaml: &str) -> ServiceDefinition {
+        let yaml = format!("{}server:\n  command: [true]\n", yaml);
+        let mut service: ServiceDefinition = serde_yaml::from_str(&yaml).unwrap();
+        service.path = std::env::temp_dir();
+        service
+    }
+
+    #[test]
+    fn test_store_round_trip() {
+        let dir = tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path());
+        let seed = json!({"repos": [{"name": "a"}, {"name": "b"}], "issues": []});
+
+        let mut manifest = SnapshotManifest::new("github", "prod", "rest", &seed);
+        assert_eq!(manifest.total_records(), 2);
+        store.save(&mut manifest, &seed).unwrap();
+
+        assert!(store.exists("github", "prod"));
+        assert_eq!(store.manifest("github", "prod").unwrap(), manifest);
+        assert_eq!(store.load_seed_payload("github", "prod").unwrap(), seed);
+        assert_eq!(store.list(None).unwrap(), vec![manifest]);
+        assert!(store.list(Some("slack")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_verify_detects_tampering() {
+        let seed = json!({"repos": [{"name": "a"}]});
+        let manifest = SnapshotManifest::new("github", "prod", "rest", &seed);
+        manifest.verify(seed_json(&seed).as_bytes()).unwrap();
+
+        let tampered = seed_json(&json!({"repos": [{"name": "b"}]}));
+        let msg = manifest
+            .verify(tampered.as_bytes())
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("corrupt"), "{}", msg);
+
+        let unchecked = SnapshotManifest {
+            seed_sha256: None,
+            ..manifest
+        };
+        let msg = unchecked
+            .verify(b"{\"repos\": []}")
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("record counts"), "{}", msg);
+    }
+
+    #[test]
+    fn test_import_and_redact_record_redactions() {
+        let dir = tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path());
+        let seed = json!({"users": [{"email": "ada@corp.io", "name": "Ada"}]});
+
+        let manifest = import(
+            &store,
+            &service("name: github\n"),
+            "prod",
+            seed,
+            &Redactor::new(&[]).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(manifest.connector, "import");
+        assert_eq!(manifest.redactions["users.email"], 1);
+
+        let github = service("name: github\n");
+        let redactor = Redactor::new(&[]).unwrap();
+        let dumped = save_dump(&store, &github, "session", json!({"users": []}), &redactor);
+        assert_eq!(dumped.unwrap().connector, "dump");
+        assert!(save_dump(&store, &github, "session", json!([]), &redactor).is_err());
+
+        let names: Vec<crate::redact::RedactionRule> =
+            serde_yaml::from_str("- field: users.name\n").unwrap();
+        let manifest =
+            redact_profile(&store, "github", "prod", &Redactor::new(&names).unwrap()).unwrap();
+        assert_eq!(manifest.redactions.len(), 2);
+        let seed = store.load_seed_payload("github", "prod").unwrap();
+        assert_eq!(seed["users"][0]["name"], json!(crate::redact::MASK));
+        store
+            .manifest("github", "prod")
+            .unwrap()
+            .verify(seed_json(&seed).as_bytes())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_redact_profile_leaves_the_pseudonyms_it_recorded() {
+        let dir = tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path());
+        let rules: Vec<crate::redact::RedactionRule> =
+            serde_yaml::from_str("- field: users.name\n  action: pseudonymize\n").unwrap();
+        let redactor = Redactor::new(&rules).unwrap();
+        let manifest = import(
+            &store,
+            &service("name: github\n"),
+            "prod",
+            json!({"users": [{"name": "Ada Lovelace"}]}),
+            &redactor,
+        )
+        .unwrap();
+        assert_eq!(manifest.pseudonyms.len(), 1);
+        let pseudonym =
+            store.load_seed_payload("github", "prod").unwrap()["users"][0]["name"].clone();
+
+        let again = redact_profile(&store, "github", "prod", &redactor).unwrap();
+        assert_eq!(again.redactions["users.name"], 1);
+        assert_eq!(again.pseudonyms, manifest.pseudonyms);
+        let seed = store.load_seed_payload("github", "prod").unwrap();
+        assert_eq!(seed["users"][0]["name"], pseudonym);
+    }
+
+    #[test]
+    fn test_redact_profile_keeps_the_rest_of_the_manifest() {
+        let dir = tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path());
+        let seed = json!({"users": [{"id": 1, "name": "Ada"}]});
+        let mut manifest = SnapshotManifest::new("github", "prod", "rest", &seed);
+        manifest.seed_schema_version = 2;
+        manifest
+            .cursors
+            .insert("users".to_string(), "2024-05-01".to_string());
+        manifest.refreshed_at = Some(1_700_000_000);
+        manifest.requests = Some(3);
+        manifest.max_requests = Some(100);
+        store.save(&mut manifest, &seed).unwrap();
+
+        let rules: Vec<crate::redact::RedactionRule> =
+            serde_yaml::from_str("- field: users.name\n").unwrap();
+        let redacted =
+            redact_profile(&store, "github", "prod", &Redactor::new(&rules).unwrap()).unwrap();
+        assert_eq!(redacted.redactions["users.name"], 1);
+        assert_ne!(redacted.seed_sha256, manifest.seed_sha256);
+        let stored = store.manifest("github", "prod").unwrap();
+        assert_eq!(stored.seed_schema_version, 2);
+        assert_eq!(stored.cursors, manifest.cursors);
+        assert_eq!(stored.refreshed_at, Some(1_700_000_000));
+        assert_eq!((stored.requests, stored.max_requests), (Some(3), Some(100)));
+    }
+
+    #[test]
+    fn test_seed_schema_mismatch_is_refused_until_migrated() {
+        let dir = tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path());
+        let redactor = Redactor::new(&[]).unwrap();
+        let seed = json!({"users": [{"id": 1, "name": "ada"}]});
+        let v1 = service("name: github\n");
+        import(&store, &v1, "prod", seed.clone(), &redactor).unwrap();
+        assert_eq!(store.load_seed(&v1, "prod").unwrap(), seed);
+
+        let v2 = service(
+            "name: github\nseed_schema_version: 2\nseed_migrations:\n  - from: 1\n    command: [sh, -c, \"sed 's/\\\"name\\\"/\\\"login\\\"/'\"]\n",
+        );
+        let msg = store.load_seed(&v2, "prod").unwrap_err().to_string();
+        assert!(
+            msg.contains("snapshot migrate github --profile prod"),
+            "{}",
+            msg
+        );
+
+        let manifest = migrate(&store, &v2, "prod", &redactor).unwrap();
+        assert_eq!(manifest.seed_schema_version, 2);
+        assert_eq!(
+            store.load_seed(&v2, "prod").unwrap(),
+            json!({"users": [{"id": 1, "login": "ada"}]})
+        );
+        assert!(store.load_seed(&v1, "prod").is_err());
+    }
+
+    #[test]
+    fn test_migrate_needs_every_step() {
+        let dir = tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path());
+        let redactor = Redactor::new(&[]).unwrap();
+        import(
+            &store,
+            &service("name: github\n"),
+            "prod",
+            json!({}),
+            &redactor,
+        )
+        .unwrap();
+
+        let v3 = service("name: github\nseed_schema_version: 3\nseed_migrations:\n  - { from: 1, command: [cat] }\n");
+        let msg = migrate(&store, &v3, "prod", &redactor)
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("no seed migration from version 2"), "{}", msg);
+        assert_eq!(
+            store
+                .manifest("github", "prod")
+                .unwrap()
+                .seed_schema_version,
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refresh_resumes_from_stored_cursors() {
+        let (url, server) = rest::tests::serve(vec![
+            json!([{"id": 1, "state": "open", "updated_at": "2024-05-01"}]),
+            json!([{"id": 9, "name": "api"}]),
+            json!([
+                {"id": 1, "state": "closed", "updated_at": "2024-05-03"},
+                {"id": 2, "state": "open", "updated_at": "2024-05-02"},
+            ]),
+            json!([{"id": 9, "name": "api-v2"}]),
+        ]);
+        let service = service(&format!(
+            "name: github\nconnector:\n  type: rest\n  base_url: {}\n  streams:\n    \
+             issues:\n      path: /issues\n      \
+             incremental: {{ cursor_field: /updated_at, param: since }}\n    \
+             repos:\n      path: /repos\n",
+            url
+        ));
+        let dir = tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path());
+        let secrets = SecretResolver::with_providers(vec![]);
+        let redactor = Redactor::new(&[]).unwrap();
+
+        let pulled = pull(
+            &service,
+            "prod",
+            &store,
+            &secrets,
+            &redactor,
+            None,
+            RequestLimits::default(),
+            &|_| {},
+            &|_, _| Ok(()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(pulled.cursors["issues"], "2024-05-01");
+        assert_eq!(pulled.requests, Some(2));
+
+        // Redacting again keeps the cursors the refresh resumes from
+        let rules: Vec<crate::redact::RedactionRule> =
+            serde_yaml::from_str("- field: repos.name\n").unwrap();
+        let redacted =
+            redact_profile(&store, "github", "prod", &Redactor::new(&rules).unwrap()).unwrap();
+        assert_eq!(redacted.cursors, pulled.cursors);
+
+        let (manifest, fetched) = refresh(
+            &service,
+            "prod",
+            &store,
+            &secrets,
+            &redactor,
+            RequestLimits::default(),
+            &|_| {},
+            &|_, _| Ok(()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(fetched, 3);
+        assert_eq!(manifest.cursors["issues"], "2024-05-03");
+        assert_eq!(manifest.created_at, pulled.created_at);
+        assert_eq!(manifest.redactions["repos.name"], 1);
+        assert!(manifest.refreshed_at.is_some());
+        assert_eq!(
+            store.load_seed_payload("github", "prod").unwrap(),
+            json!({
+                "issues": [
+                    {"id": 1, "state": "closed", "updated_at": "2024-05-03"},
+                    {"id": 2, "state": "open", "updated_at": "2024-05-02"},
+                ],
+                "repos": [{"id": 9, "name": "api-v2"}],
+            })
+        );
+
+        let requests = server.join().unwrap();
+        assert!(requests.contains(&"GET /issues?since=2024-05-01 HTTP/1.1".to_string()));
+    }
+
+    #[test]
+    fn test_missing_profile_suggests_pull() {
+        let dir = tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path());
+        let msg = store
+            .load_seed_payload("github", "prod")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            msg.contains("snapshot pull github --profile prod"),
+            "{}",
+            msg
+        );
+    }
+
+    #[test]
+    fn test_rejects_bad_profile_names() {
+        let dir = tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path());
+        let mut manifest = SnapshotManifest::new("github", "../escape", "rest", &json!({}));
+        assert!(store.save(&mut manifest, &json!({})).is_err());
+    }
+
+    #[test]
+    fn test_seeds_are_compressed_and_plain_ones_still_load() {
+        let dir = tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path());
+        let seed = json!({"repos": vec![json!({"name": "api", "private": false}); 200]});
+
+        // A profile written before compression: plain seed.json, no sizes
+        let legacy = SnapshotManifest {
+            size: None,
+            ..SnapshotManifest::new("github", "prod", "rest", &seed)
+        };
+        let dir = store.profile_dir("github", "prod");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(SEED_FILE), seed_json(&seed)).unwrap();
+        fs::write(
+            dir.join(MANIFEST_FILE),
+            serde_json::to_string(&legacy).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(store.load_seed_payload("github", "prod").unwrap(), seed);
+
+        let redactor = Redactor::new(&[]).unwrap();
+        let manifest = redact_profile(&store, "github", "prod", &redactor).unwrap();
+        assert!(!dir.join(SEED_FILE).exists());
+        assert!(dir.join(COMPRESSED_SEED_FILE).is_file());
+        let (size, compressed) = (manifest.size.unwrap(), manifest.compressed_size.unwrap());
+        assert_eq!(size, seed_json(&seed).len() as u64);
+        assert!(compressed * 10 < size, "{} vs {}", compressed, size);
+        assert_eq!(store.load_seed_payload("github", "prod").unwrap(), seed);
+    }
+}
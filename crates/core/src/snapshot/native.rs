@@ -0,0 +1,167 @@
+//! Native S3 and Google Cloud Storage transfers for snapshot registries.
+//!
+//! Built with the `native-storage` feature. Objects are read and written with
+//! `object_store`, so no `aws` or `gcloud` CLI is needed. Credentials come from
+//! the providers' usual chains: the `AWS_*` variables, web identity, ECS and
+//! instance metadata for S3; `GOOGLE_APPLICATION_CREDENTIALS`, the gcloud
+//! application default credentials and the metadata server for GCS. Requests
+//! are retried with backoff, and large seeds are uploaded in parts.
+
+use object_store::aws::AmazonS3Builder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::path::Path as ObjectPath;
+use object_store::{BackoffConfig, ObjectStore, PutPayload, RetryConfig, WriteMultipart};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
+
+/// Files larger than this are uploaded in parts.
+const MULTIPART_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// Size of each part of a multipart upload; S3 needs at least 5 MiB.
+const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Parts of a multipart upload in flight at once.
+const MAX_CONCURRENT_PARTS: usize = 4;
+
+/// Retries of a failed request, with exponential backoff between them.
+const MAX_RETRIES: usize = 5;
+
+/// How long a request is retried for before its error is returned.
+const RETRY_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Copy between a local file and an `s3://` or `gs://` object.
+pub(super) async fn copy(local: &Path, url: &str, upload: bool) -> object_store::Result<()> {
+    let (store, path) = open(url)?;
+    if upload {
+        put(store.as_ref(), &path, local).await
+    } else {
+        let content = store.get(&path).await?.bytes().await?;
+        fs::write(local, content).map_err(io_error)
+    }
+}
+
+/// Whether a failed transfer may succeed through the provider's CLI instead.
+///
+/// A missing object is missing either way; anything else, most often
+/// credentials only the CLI knows about (profiles, SSO), is worth a retry.
+pub(super) fn cli_may_succeed(error: &object_store::Error) -> bool {
+    !matches!(error, object_store::Error::NotFound { .. })
+}
+
+async fn put(store: &dyn ObjectStore, path: &ObjectPath, local: &Path) -> object_store::Result<()> {
+    let mut file = fs::File::open(local).map_err(io_error)?;
+    let size = file.metadata().map_err(io_error)?.len();
+    if size <= MULTIPART_THRESHOLD {
+        let mut content = Vec::with_capacity(size as usize);
+        file.read_to_end(&mut content).map_err(io_error)?;
+        store.put(path, PutPayload::from(content)).await?;
+        return Ok(());
+    }
+
+    let mut upload =
+        WriteMultipart::new_with_chunk_size(store.put_multipart(path).await?, PART_SIZE);
+    let mut buffer = vec![0; PART_SIZE];
+    loop {
+        let read = match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) => {
+                let _ = upload.abort().await;
+                return Err(io_error(e));
+            }
+        };
+        if let Err(e) = upload.wait_for_capacity(MAX_CONCURRENT_PARTS).await {
+            let _ = upload.abort().await;
+            return Err(e);
+        }
+        upload.write(&buffer[..read]);
+    }
+    upload.finish().await?;
+    Ok(())
+}
+
+/// Open the bucket of an object URL and return the object's path in it.
+fn open(url: &str) -> object_store::Result<(Box<dyn ObjectStore>, ObjectPath)> {
+    let (bucket, key) = split_url(url)?;
+    let retry = RetryConfig {
+        backoff: BackoffConfig::default(),
+        max_retries: MAX_RETRIES,
+        retry_timeout: RETRY_TIMEOUT,
+    };
+    let store: Box<dyn ObjectStore> = if url.starts_with("s3://") {
+        Box::new(
+            AmazonS3Builder::from_env()
+                .with_url(bucket)
+                .with_retry(retry)
+                .build()?,
+        )
+    } else {
+        Box::new(
+            GoogleCloudStorageBuilder::from_env()
+                .with_url(bucket)
+                .with_retry(retry)
+                .build()?,
+        )
+    };
+    Ok((store, ObjectPath::from(key)))
+}
+
+/// Split `s3://bucket/key` or `gs://bucket/key` into bucket URL and key.
+fn split_url(url: &str) -> object_store::Result<(&str, &str)> {
+    let rest = url
+        .strip_prefix("s3://")
+        .or_else(|| url.strip_prefix("gs://"))
+        .unwrap_or("");
+    match rest.split_once('/') {
+        Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => {
+            Ok((&url[..url.len() - key.len() - 1], key))
+        }
+        _ => Err(object_store::Error::Generic {
+            store: "snapshot registry",
+            source: format!("'{}' is not an s3:// or gs:// object URL", url).into(),
+        }),
+    }
+}
+
+fn io_error(e: std::io::Error) -> object_store::Error {
+    object_store::Error::Generic {
+        store: "local file",
+        source: Box::new(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_url() {
+        assert_eq!(
+            split_url("s3://team-snapshots/doubleagent/github/prod/manifest.json").unwrap(),
+            (
+                "s3://team-snapshots",
+                "doubleagent/github/prod/manifest.json"
+            )
+        );
+        assert_eq!(
+            split_url("gs://bucket/github/prod/seed.json.zst").unwrap(),
+            ("gs://bucket", "github/prod/seed.json.zst")
+        );
+        assert!(split_url("s3://bucket").is_err());
+        assert!(split_url("https://example.com/seed.json").is_err());
+    }
+
+    #[test]
+    fn test_cli_fallback() {
+        assert!(!cli_may_succeed(&object_store::Error::NotFound {
+            path: "github/prod/manifest.json".into(),
+            source: "missing".into(),
+        }));
+        assert!(cli_may_succeed(&object_store::Error::Generic {
+            store: "S3",
+            source: "no credentials".into(),
+        }));
+    }
+}
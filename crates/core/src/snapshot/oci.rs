@@ -0,0 +1,544 @@
+//! OCI registries (ghcr.io, Docker Hub, Harbor, ...) as snapshot registries.
+//!
+//! A registry `oci://<host>/<path>` stores each service in the repository
+//! `<path>/<service>` and each profile as a tag of it. A profile is pushed as
+//! an OCI artifact whose layers are the profile's files, named by their
+//! `org.opencontainers.image.title` annotation like ORAS does, with the
+//! snapshot manifest's main fields repeated as annotations of the artifact so
+//! registry UIs can show them.
+//!
+//! Registries on `localhost` or `127.0.0.1` are spoken to over plain HTTP.
+//! Credentials are read from the `registry_username` and `registry_password`
+//! secrets (for ghcr.io, any username and a token with the `packages` scopes);
+//! without them only anonymous access is tried.
+
+use crate::secrets::SecretResolver;
+use crate::{Error, Result};
+use reqwest::header::{HeaderMap, CONTENT_TYPE, LOCATION, WWW_AUTHENTICATE};
+use reqwest::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Timeout of a single request to a registry.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+const ARTIFACT_TYPE: &str = "application/vnd.doubleagent.snapshot.v1";
+const EMPTY_MEDIA_TYPE: &str = "application/vnd.oci.empty.v1+json";
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+
+/// Prefix of the annotations describing a snapshot.
+pub const ANNOTATION_PREFIX: &str = "dev.doubleagent.snapshot.";
+
+/// A file of a profile, as an artifact layer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layer {
+    /// File name within the profile
+    pub name: String,
+    /// Media type of the layer
+    pub media_type: String,
+    /// File content
+    pub content: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ImageManifest {
+    schema_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    media_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    artifact_type: Option<String>,
+    config: Descriptor,
+    layers: Vec<Descriptor>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    annotations: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    media_type: String,
+    digest: String,
+    size: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    annotations: BTreeMap<String, String>,
+}
+
+impl Descriptor {
+    fn of(media_type: &str, content: &[u8]) -> Self {
+        Self {
+            media_type: media_type.to_string(),
+            digest: digest(content),
+            size: content.len() as u64,
+            annotations: BTreeMap::new(),
+        }
+    }
+}
+
+/// How requests are authorized after the registry's challenge.
+#[derive(Debug, Clone)]
+enum Auth {
+    Anonymous,
+    Basic(String, String),
+    Bearer(String),
+}
+
+/// A client for one repository of an OCI registry.
+pub struct Repository {
+    client: reqwest::Client,
+    /// `http(s)://<host>/v2/<repository>`
+    base: String,
+    credentials: Option<(String, String)>,
+    auth: Auth,
+}
+
+impl Repository {
+    /// Connect to the repository of a service in an `oci://` registry.
+    pub fn new(registry: &str, service: &str, secrets: &SecretResolver) -> Result<Self> {
+        let (host, path) = split(registry)?;
+        let scheme = if is_local(host) { "http" } else { "https" };
+        let repository = if path.is_empty() {
+            service.to_string()
+        } else {
+            format!("{}/{}", path, service)
+        };
+        let credentials = match (
+            secrets.find("registry_username")?,
+            secrets.find("registry_password")?,
+        ) {
+            (Some(username), Some(password)) => Some((username, password)),
+            (None, Some(password)) => Some(("doubleagent".to_string(), password)),
+            _ => None,
+        };
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .user_agent(concat!("doubleagent/", env!("CARGO_PKG_VERSION")))
+                .build()?,
+            base: format!("{}://{}/v2/{}", scheme, host, repository),
+            credentials,
+            auth: Auth::Anonymous,
+        })
+    }
+
+    /// Push `layers` as an artifact tagged `tag`, annotated with `annotations`.
+    pub async fn push(
+        &mut self,
+        tag: &str,
+        layers: &[Layer],
+        annotations: BTreeMap<String, String>,
+    ) -> Result<()> {
+        let config = b"{}";
+        self.upload_blob(config).await?;
+        let mut descriptors = Vec::new();
+        for layer in layers {
+            self.upload_blob(&layer.content).await?;
+            let mut descriptor = Descriptor::of(&layer.media_type, &layer.content);
+            descriptor
+                .annotations
+                .insert(TITLE_ANNOTATION.to_string(), layer.name.clone());
+            descriptors.push(descriptor);
+        }
+
+        let manifest = ImageManifest {
+            schema_version: 2,
+            media_type: Some(MANIFEST_MEDIA_TYPE.to_string()),
+            artifact_type: Some(ARTIFACT_TYPE.to_string()),
+            config: Descriptor::of(EMPTY_MEDIA_TYPE, config),
+            layers: descriptors,
+            annotations,
+        };
+        let body = serde_json::to_vec(&manifest)?;
+        let url = format!("{}/manifests/{}", self.base, tag);
+        let response = self
+            .send(Method::PUT, &url, Some(MANIFEST_MEDIA_TYPE), Some(body))
+            .await?;
+        expect(response, &url).await?;
+        Ok(())
+    }
+
+    /// Download the files of the artifact tagged `tag`, keyed by name.
+    ///
+    /// Each layer is checked against its digest.
+    pub async fn pull(&mut self, tag: &str) -> Result<BTreeMap<String, Vec<u8>>> {
+        let url = format!("{}/manifests/{}", self.base, tag);
+        let response = self.send(Method::GET, &url, None, None).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(Error::Other(format!("{} has no tag '{}'", self.base, tag)));
+        }
+        let response = expect(response, &url).await?;
+        let manifest: ImageManifest = serde_json::from_slice(&response.bytes().await?)
+            .map_err(|e| Error::Other(format!("Invalid OCI manifest at {}: {}", url, e)))?;
+
+        let mut files = BTreeMap::new();
+        for layer in manifest.layers {
+            let Some(name) = layer.annotations.get(TITLE_ANNOTATION) else {
+                continue;
+            };
+            let url = format!("{}/blobs/{}", self.base, layer.digest);
+            let response = self.send(Method::GET, &url, None, None).await?;
+            let content = expect(response, &url).await?.bytes().await?.to_vec();
+            if digest(&content) != layer.digest {
+                return Err(Error::Other(format!(
+                    "Layer {} of {}:{} does not match its digest {}",
+                    name, self.base, tag, layer.digest
+                )));
+            }
+            files.insert(name.clone(), content);
+        }
+        Ok(files)
+    }
+
+    /// Upload a blob unless the registry already has it.
+    async fn upload_blob(&mut self, content: &[u8]) -> Result<()> {
+        let digest = digest(content);
+        let url = format!("{}/blobs/{}", self.base, digest);
+        if self.send(Method::HEAD, &url, None, None).await?.status() == StatusCode::OK {
+            return Ok(());
+        }
+
+        let url = format!("{}/blobs/uploads/", self.base);
+        let response = self.send(Method::POST, &url, None, None).await?;
+        let response = expect(response, &url).await?;
+        let location = location(response.headers(), &self.base)
+            .ok_or_else(|| Error::Other(format!("{} did not return an upload location", url)))?;
+        let separator = if location.contains('?') { '&' } else { '?' };
+        let url = format!("{}{}digest={}", location, separator, digest);
+        let response = self
+            .send(
+                Method::PUT,
+                &url,
+                Some("application/octet-stream"),
+                Some(content.to_vec()),
+            )
+            .await?;
+        expect(response, &url).await?;
+        Ok(())
+    }
+
+    /// Send a request, answering the registry's authentication challenge once.
+    async fn send(
+        &mut self,
+        method: Method,
+        url: &str,
+        content_type: Option<&str>,
+        body: Option<Vec<u8>>,
+    ) -> Result<reqwest::Response> {
+        let mut challenged = false;
+        loop {
+            let mut request = self.client.request(method.clone(), url);
+            if method == Method::GET && url.contains("/manifests/") {
+                request = request.header(reqwest::header::ACCEPT, MANIFEST_MEDIA_TYPE);
+            }
+            if let Some(content_type) = content_type {
+                request = request.header(CONTENT_TYPE, content_type);
+            }
+            if let Some(body) = &body {
+                request = request.body(body.clone());
+            }
+            request = match &self.auth {
+                Auth::Anonymous => request,
+                Auth::Basic(username, password) => request.basic_auth(username, Some(password)),
+                Auth::Bearer(token) => request.bearer_auth(token),
+            };
+
+            let response = request.send().await?;
+            if response.status() != StatusCode::UNAUTHORIZED || challenged {
+                return Ok(response);
+            }
+            let challenge = response
+                .headers()
+                .get(WWW_AUTHENTICATE)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            self.auth = self.authenticate(&challenge).await?;
+            challenged = true;
+        }
+    }
+
+    /// Answer a `WWW-Authenticate` challenge.
+    async fn authenticate(&self, challenge: &str) -> Result<Auth> {
+        let denied = || {
+            Error::Other(format!(
+                "{} refused the credentials in the registry_username and registry_password secrets",
+                self.base
+            ))
+        };
+        let unauthorized = || {
+            Error::Other(format!(
+                "{} requires authentication; store credentials in the \
+                 registry_username and registry_password secrets",
+                self.base
+            ))
+        };
+
+        let (scheme, params) = challenge.split_once(' ').unwrap_or((challenge, ""));
+        if scheme.eq_ignore_ascii_case("basic") {
+            let (username, password) = self.credentials.clone().ok_or_else(unauthorized)?;
+            return Ok(Auth::Basic(username, password));
+        }
+        if !scheme.eq_ignore_ascii_case("bearer") {
+            return Err(unauthorized());
+        }
+
+        let params = challenge_params(params);
+        let realm = params.get("realm").ok_or_else(unauthorized)?;
+        let query: Vec<(&str, &String)> = ["service", "scope"]
+            .into_iter()
+            .filter_map(|key| params.get(key).map(|value| (key, value)))
+            .collect();
+        let mut request = self.client.get(realm).query(&query);
+        if let Some((username, password)) = &self.credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(if self.credentials.is_some() {
+                denied()
+            } else {
+                unauthorized()
+            });
+        }
+
+        #[derive(Deserialize)]
+        struct Token {
+            token: Option<String>,
+            access_token: Option<String>,
+        }
+        let token: Token = response.json().await?;
+        token
+            .token
+            .or(token.access_token)
+            .map(Auth::Bearer)
+            .ok_or_else(|| Error::Other(format!("{} returned no token", realm)))
+    }
+}
+
+/// Split `oci://<host>/<path>` into host and repository path.
+pub fn split(registry: &str) -> Result<(&str, &str)> {
+    let rest = registry
+        .strip_prefix("oci://")
+        .filter(|rest| !rest.is_empty())
+        .ok_or_else(|| Error::Other(format!("'{}' is not an oci:// registry", registry)))?;
+    let rest = rest.trim_end_matches('/');
+    Ok(rest.split_once('/').unwrap_or((rest, "")))
+}
+
+fn is_local(host: &str) -> bool {
+    let name = host.rsplit_once(':').map_or(host, |(name, _)| name);
+    name == "localhost" || name == "127.0.0.1"
+}
+
+fn digest(content: &[u8]) -> String {
+    format!("sha256:{:x}", Sha256::digest(content))
+}
+
+/// The absolute URL in a `Location` header, which may be relative to the host.
+fn location(headers: &HeaderMap, base: &str) -> Option<String> {
+    let location = headers.get(LOCATION)?.to_str().ok()?;
+    if location.starts_with("http://") || location.starts_with("https://") {
+        return Some(location.to_string());
+    }
+    let origin_end = base.find("/v2/")?;
+    Some(format!("{}{}", &base[..origin_end], location))
+}
+
+/// Parse `key="value",key2="value2"` challenge parameters.
+fn challenge_params(params: &str) -> BTreeMap<&str, String> {
+    let mut result = BTreeMap::new();
+    let mut rest = params.trim();
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim();
+        let (value, remaining) = match after.strip_prefix('"') {
+            Some(quoted) => match quoted.split_once('"') {
+                Some((value, remaining)) => (value, remaining),
+                None => (quoted, ""),
+            },
+            None => after.split_once(',').unwrap_or((after, "")),
+        };
+        result.insert(key, value.to_string());
+        rest = remaining.trim_start_matches(',').trim();
+    }
+    result
+}
+
+/// Turn an unsuccessful response into an error.
+async fn expect(response: reqwest::Response, url: &str) -> Result<reqwest::Response> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    Err(Error::Other(format!(
+        "{} returned {}: {}",
+        url,
+        status,
+        body.trim()
+    )))
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::secrets::Provider;
+    use http_body_util::{BodyExt, Full};
+    use hyper::body::Bytes;
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::{Request, Response};
+    use hyper_util::rt::TokioIo;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct State {
+        blobs: HashMap<String, Bytes>,
+        manifests: HashMap<String, Bytes>,
+        uploads: usize,
+    }
+
+    /// Serve an in-memory OCI registry that wants a bearer token, returning
+    /// its `oci://` URL.
+    pub(crate) async fn serve_registry() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(State::default()));
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let state = state.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |request| handle(addr, state.clone(), request));
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        format!("oci://{}/acme/snapshots", addr)
+    }
+
+    async fn handle(
+        addr: std::net::SocketAddr,
+        state: Arc<Mutex<State>>,
+        request: Request<hyper::body::Incoming>,
+    ) -> std::result::Result<Response<Full<Bytes>>, std::convert::Infallible> {
+        let reply = |status: u16, body: Bytes| {
+            Ok(Response::builder()
+                .status(status)
+                .body(Full::new(body))
+                .unwrap())
+        };
+        let (parts, body) = request.into_parts();
+        let path = parts.uri.path().to_string();
+        if path == "/token" {
+            return reply(200, Bytes::from(r#"{"token": "t0k"}"#));
+        }
+        let authorized = parts
+            .headers
+            .get("authorization")
+            .is_some_and(|v| v == "Bearer t0k");
+        if !authorized {
+            return Ok(Response::builder()
+                .status(401)
+                .header(
+                    "www-authenticate",
+                    format!(
+                        r#"Bearer realm="http://{}/token",service="test",scope="repository:x:pull""#,
+                        addr
+                    ),
+                )
+                .body(Full::new(Bytes::new()))
+                .unwrap());
+        }
+
+        let body = body.collect().await.unwrap().to_bytes();
+        let mut state = state.lock().unwrap();
+        let repo = "/v2/acme/snapshots/github";
+        let Some(rest) = path.strip_prefix(repo) else {
+            return reply(404, Bytes::new());
+        };
+        match (parts.method.as_str(), rest) {
+            ("POST", "/blobs/uploads/") => {
+                state.uploads += 1;
+                Ok(Response::builder()
+                    .status(202)
+                    .header(
+                        "location",
+                        format!("{}/blobs/uploads/{}", repo, state.uploads),
+                    )
+                    .body(Full::new(Bytes::new()))
+                    .unwrap())
+            }
+            ("PUT", r) if r.starts_with("/blobs/uploads/") => {
+                let digest = parts.uri.query().unwrap().trim_start_matches("digest=");
+                state.blobs.insert(digest.to_string(), body);
+                reply(201, Bytes::new())
+            }
+            ("HEAD" | "GET", r) if r.starts_with("/blobs/") => {
+                match state.blobs.get(r.trim_start_matches("/blobs/")) {
+                    Some(blob) => reply(200, blob.clone()),
+                    None => reply(404, Bytes::new()),
+                }
+            }
+            ("PUT", r) if r.starts_with("/manifests/") => {
+                state
+                    .manifests
+                    .insert(r.trim_start_matches("/manifests/").to_string(), body);
+                reply(201, Bytes::new())
+            }
+            ("GET", r) if r.starts_with("/manifests/") => {
+                match state.manifests.get(r.trim_start_matches("/manifests/")) {
+                    Some(manifest) => reply(200, manifest.clone()),
+                    None => reply(404, Bytes::new()),
+                }
+            }
+            _ => reply(405, Bytes::new()),
+        }
+    }
+
+    #[test]
+    fn test_challenge_params() {
+        let params = challenge_params(
+            r#"realm="https://ghcr.io/token",service="ghcr.io",scope="repository:acme/x:pull,push""#,
+        );
+        assert_eq!(params["realm"], "https://ghcr.io/token");
+        assert_eq!(params["service"], "ghcr.io");
+        assert_eq!(params["scope"], "repository:acme/x:pull,push");
+        assert_eq!(
+            split("oci://ghcr.io/acme/snapshots/").unwrap(),
+            ("ghcr.io", "acme/snapshots")
+        );
+        assert!(split("oci://").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_push_and_pull_round_trip() {
+        let registry = serve_registry().await;
+        let secrets = SecretResolver::with_providers(vec![Provider::Env]);
+        let layers = vec![Layer {
+            name: "manifest.json".to_string(),
+            media_type: "application/json".to_string(),
+            content: br#"{"profile": "prod"}"#.to_vec(),
+        }];
+
+        let mut repository = Repository::new(&registry, "github", &secrets).unwrap();
+        repository
+            .push("prod", &layers, BTreeMap::new())
+            .await
+            .unwrap();
+        let files = repository.pull("prod").await.unwrap();
+        assert_eq!(files["manifest.json"], layers[0].content);
+
+        let missing = repository.pull("staging").await.unwrap_err();
+        assert!(
+            missing.to_string().contains("no tag 'staging'"),
+            "{}",
+            missing
+        );
+    }
+}
@@ -0,0 +1,82 @@
+//! Progress reporting while a snapshot is pulled.
+//!
+//! Connectors report progress as a stream of [`ProgressEvent`]s. Between
+//! processes the protocol is line-delimited JSON, one event object per line,
+//! tagged by `type`:
+//!
+//! ```text
+//! {"type":"stream_started","stream":"issues"}
+//! {"type":"resumed","stream":"issues","count":800}
+//! {"type":"records","stream":"issues","count":1000}
+//! {"type":"stream_done","stream":"issues","count":1200}
+//! ```
+//!
+//! `count` is the running total for the stream, not the size of the last
+//! page; `resumed` gives the records kept from an interrupted pull. The
+//! native `rest` connector emits the same events in-process.
+
+use serde::{Deserialize, Serialize};
+
+/// One progress update from a connector.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    /// A stream started pulling
+    StreamStarted { stream: String },
+    /// A stream picked up where an interrupted pull left it
+    Resumed { stream: String, count: usize },
+    /// More records of a stream were pulled
+    Records { stream: String, count: usize },
+    /// A stream finished
+    StreamDone { stream: String, count: usize },
+}
+
+impl ProgressEvent {
+    /// Parse one line of the protocol. Lines that aren't events (blank lines,
+    /// log output) give `None`.
+    pub fn parse_line(line: &str) -> Option<Self> {
+        let line = line.trim();
+        if !line.starts_with('{') {
+            return None;
+        }
+        serde_json::from_str(line).ok()
+    }
+
+    /// The event as one line of the protocol, without the newline.
+    pub fn to_line(&self) -> String {
+        serde_json::to_string(self).expect("progress events always serialize")
+    }
+
+    /// Name of the stream the event is about.
+    pub fn stream(&self) -> &str {
+        match self {
+            ProgressEvent::StreamStarted { stream }
+            | ProgressEvent::Resumed { stream, .. }
+            | ProgressEvent::Records { stream, .. }
+            | ProgressEvent::StreamDone { stream, .. } => stream,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol_lines_round_trip() {
+        let event = ProgressEvent::Records {
+            stream: "issues".to_string(),
+            count: 200,
+        };
+        let line = event.to_line();
+        assert_eq!(line, r#"{"type":"records","stream":"issues","count":200}"#);
+        assert_eq!(
+            ProgressEvent::parse_line(&format!("  {}\n", line)),
+            Some(event)
+        );
+
+        assert_eq!(ProgressEvent::parse_line(""), None);
+        assert_eq!(ProgressEvent::parse_line("INFO syncing issues"), None);
+        assert_eq!(ProgressEvent::parse_line(r#"{"type":"log"}"#), None);
+    }
+}
@@ -0,0 +1,330 @@
+//! Record mode: building a snapshot from live traffic.
+//!
+//! [`run`] starts a plain HTTP proxy that forwards every request to the real
+//! API and returns its response unchanged. Successful JSON responses to `GET`
+//! requests are collected into a seed payload with one resource per endpoint:
+//! the request path with IDs (numbers, UUIDs and long hex strings) replaced by
+//! `{id}`, e.g. `/repos/{id}/issues`. An array body contributes its items as
+//! records, an object wrapping the records in `data`, `items` or `results`
+//! contributes those, and any other object is a record of its own. Records
+//! seen more than once (by `id`, or by content) are kept once, as last
+//! seen.
+
+use crate::{Error, Result};
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response};
+use hyper_util::rt::TokioIo;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+
+/// Object fields that commonly wrap a page of records.
+const WRAPPERS: &[&str] = &["data", "items", "results"];
+
+/// Headers that apply to a single connection and are not forwarded.
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+    "te",
+    "trailer",
+];
+
+/// Responses observed by the proxy.
+#[derive(Debug, Default)]
+pub struct Recording {
+    /// Records by endpoint, each keyed by its identity
+    resources: BTreeMap<String, BTreeMap<String, Value>>,
+    /// Number of requests proxied
+    pub requests: usize,
+    /// Number of responses recorded
+    pub recorded: usize,
+}
+
+impl Recording {
+    /// Add the records of a JSON response to an endpoint.
+    pub fn add(&mut self, path: &str, body: Value) {
+        let records = match body {
+            Value::Array(items) => items,
+            Value::Object(mut object) => {
+                match WRAPPERS
+                    .iter()
+                    .find(|w| object.get(**w).is_some_and(Value::is_array))
+                {
+                    Some(wrapper) => match object.remove(*wrapper) {
+                        Some(Value::Array(items)) => items,
+                        _ => Vec::new(),
+                    },
+                    None => vec![Value::Object(object)],
+                }
+            }
+            _ => return,
+        };
+
+        let resource = self.resources.entry(endpoint(path)).or_default();
+        for record in records {
+            let key = match record.get("id") {
+                Some(Value::String(id)) => id.clone(),
+                Some(id) if !id.is_null() => id.to_string(),
+                _ => record.to_string(),
+            };
+            resource.insert(key, record);
+        }
+        self.recorded += 1;
+    }
+
+    /// The recorded data as a seed payload.
+    pub fn into_seed(self) -> Value {
+        Value::Object(
+            self.resources
+                .into_iter()
+                .map(|(endpoint, records)| {
+                    (endpoint, Value::Array(records.into_values().collect()))
+                })
+                .collect::<Map<_, _>>(),
+        )
+    }
+}
+
+/// Proxy connections on `listener` to `target` until `shutdown` completes.
+pub async fn run(
+    listener: TcpListener,
+    target: &str,
+    shutdown: impl Future<Output = ()>,
+) -> Result<Recording> {
+    let target = target.trim_end_matches('/').to_string();
+    if !(target.starts_with("http://") || target.starts_with("https://")) {
+        return Err(Error::Other(format!(
+            "Record target '{}' must be an http:// or https:// URL",
+            target
+        )));
+    }
+
+    let recording = Arc::new(Mutex::new(Recording::default()));
+    let client = reqwest::Client::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        let (stream, _) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = &mut shutdown => break,
+        };
+
+        let recording = recording.clone();
+        let client = client.clone();
+        let target = target.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |request| {
+                forward(client.clone(), target.clone(), recording.clone(), request)
+            });
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("Record proxy connection error: {}", e);
+            }
+        });
+    }
+
+    let recording = std::mem::take(&mut *recording.lock().unwrap());
+    Ok(recording)
+}
+
+/// Forward one request and record its response.
+async fn forward(
+    client: reqwest::Client,
+    target: String,
+    recording: Arc<Mutex<Recording>>,
+    request: Request<Incoming>,
+) -> std::result::Result<Response<Full<Bytes>>, std::convert::Infallible> {
+    let (parts, body) = request.into_parts();
+    let path = parts.uri.path().to_string();
+    let path_and_query = parts
+        .uri
+        .path_and_query()
+        .map_or("/", |p| p.as_str())
+        .to_string();
+
+    let result = async {
+        let body = body.collect().await?.to_bytes();
+        let mut upstream = client.request(
+            parts.method.clone(),
+            format!("{}{}", target, path_and_query),
+        );
+        for (name, value) in &parts.headers {
+            // Ask for an identity encoding so JSON bodies can be read
+            if !is_hop_by_hop(name.as_str()) && name != "host" && name != "accept-encoding" {
+                upstream = upstream.header(name, value);
+            }
+        }
+        let response = upstream.body(body).send().await?;
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(response)
+    }
+    .await;
+
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::warn!("Record proxy could not reach {}: {}", target, e);
+            return Ok(Response::builder()
+                .status(502)
+                .body(Full::new(Bytes::from(format!("doubleagent record: {}", e))))
+                .unwrap());
+        }
+    };
+
+    let status = response.status();
+    let headers = response.headers().clone();
+    let bytes = response.bytes().await.unwrap_or_default();
+
+    {
+        let mut recording = recording.lock().unwrap();
+        recording.requests += 1;
+        let is_json = headers
+            .get(hyper::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("json"));
+        if parts.method == hyper::Method::GET && status.is_success() && is_json {
+            if let Ok(body) = serde_json::from_slice(&bytes) {
+                recording.add(&path, body);
+            }
+        }
+    }
+
+    let mut builder = Response::builder().status(status);
+    for (name, value) in &headers {
+        if !is_hop_by_hop(name.as_str()) && name != "content-length" {
+            builder = builder.header(name, value);
+        }
+    }
+    Ok(builder.body(Full::new(bytes)).unwrap())
+}
+
+pub(crate) fn is_hop_by_hop(name: &str) -> bool {
+    HOP_BY_HOP.contains(&name)
+}
+
+/// The endpoint a request path belongs to, with IDs replaced by `{id}`.
+pub fn endpoint(path: &str) -> String {
+    let segments: Vec<&str> = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|segment| if is_id(segment) { "{id}" } else { segment })
+        .collect();
+    format!("/{}", segments.join("/"))
+}
+
+fn is_id(segment: &str) -> bool {
+    let hex = segment.chars().filter(|c| *c != '-').count();
+    segment.chars().all(|c| c.is_ascii_digit())
+        || (hex >= 16 && segment.chars().all(|c| c.is_ascii_hexdigit() || c == '-'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    #[test]
+    fn test_endpoint_replaces_ids() {
+        assert_eq!(
+            endpoint("/repos/octo/api/issues/42"),
+            "/repos/octo/api/issues/{id}"
+        );
+        assert_eq!(
+            endpoint("/users/6f1c2a9e-8d3b-4c1e-9a7f-2b5d8e0c4a11/"),
+            "/users/{id}"
+        );
+        assert_eq!(endpoint("/"), "/");
+    }
+
+    #[test]
+    fn test_recording_dedupes_and_unwraps() {
+        let mut recording = Recording::default();
+        recording.add(
+            "/issues",
+            json!([{"id": 1, "title": "a"}, {"id": 2, "title": "b"}]),
+        );
+        recording.add(
+            "/issues",
+            json!({"data": [{"id": 1, "title": "a2"}], "has_more": false}),
+        );
+        recording.add("/issues/3", json!({"id": 3, "title": "c"}));
+
+        assert_eq!(
+            recording.into_seed(),
+            json!({
+                "/issues": [{"id": 1, "title": "a2"}, {"id": 2, "title": "b"}],
+                "/issues/{id}": [{"id": 3, "title": "c"}],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proxy_forwards_and_records() {
+        // Upstream answering a single JSON request
+        let upstream = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = format!("http://{}", upstream.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = upstream.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+            }
+            let body = r#"[{"id": 7, "name": "api"}]"#;
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+            let _ = reader.read(&mut [0; 1]);
+            line
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = format!("http://{}", listener.local_addr().unwrap());
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let recorder = tokio::spawn(async move {
+            run(listener, &target, async {
+                let _ = stopped.await;
+            })
+            .await
+        });
+
+        let body: Value = reqwest::get(format!("{}/orgs/acme/repos?page=1", proxy))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body, json!([{"id": 7, "name": "api"}]));
+
+        stop.send(()).unwrap();
+        let recording = recorder.await.unwrap().unwrap();
+        assert_eq!(recording.requests, 1);
+        assert_eq!(
+            recording.into_seed(),
+            json!({"/orgs/acme/repos": [{"id": 7, "name": "api"}]})
+        );
+        assert_eq!(
+            server.join().unwrap().trim(),
+            "GET /orgs/acme/repos?page=1 HTTP/1.1"
+        );
+    }
+}
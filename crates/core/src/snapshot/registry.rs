@@ -0,0 +1,547 @@
+//! Shared registries snapshot profiles are pushed to and fetched from.
+//!
+//! A registry is a base URL set with the `registry` setting. Profiles live at
+//! `<registry>/<service>/<profile>/manifest.json` and `.../seed.json.zst` (or
+//! `.../seed.json` for uncompressed profiles), the same layout as the local
+//! store. `https://` registries are read-only and read directly;
+//! `s3://`, `gs://` and Azure Blob Storage (`az://<account>/<container>/...`
+//! or `https://<account>.blob.core.windows.net/<container>/...`) registries go
+//! through the `aws`, `gcloud` (or `gsutil`) and `az` CLIs, so their usual
+//! credentials apply. With the `native-storage` feature, S3 and GCS objects are
+//! transferred natively (see [`native`](super::native)) and the CLIs are only a
+//! fallback. `oci://` registries hold profiles as OCI artifacts, see
+//! [`oci`](super::oci).
+
+#[cfg(feature = "native-storage")]
+use super::native;
+use super::oci::{self, Layer, ANNOTATION_PREFIX};
+use super::{
+    read_seed_file, SnapshotManifest, SnapshotStore, COMPRESSED_SEED_FILE, MANIFEST_FILE, SEED_FILE,
+};
+use crate::secrets::SecretResolver;
+use crate::{Error, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Timeout of a single download from an HTTP registry.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// A shared snapshot registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Registry {
+    /// An S3 bucket and prefix (`s3://bucket/prefix`)
+    S3(String),
+    /// A Google Cloud Storage bucket and prefix (`gs://bucket/prefix`)
+    Gcs(String),
+    /// An Azure Blob Storage container and prefix (`az://account/container/prefix`
+    /// or `https://account.blob.core.windows.net/container/prefix`)
+    Azure(String),
+    /// A static HTTP(S) server
+    Http(String),
+    /// An OCI registry and repository prefix (`oci://ghcr.io/org/snapshots`)
+    Oci(String),
+}
+
+impl Registry {
+    /// Parse a registry URL.
+    pub fn parse(url: &str) -> Result<Self> {
+        validate_url(url).map_err(|e| Error::Other(format!("Invalid registry: {}", e)))?;
+        let base = url.trim_end_matches('/').to_string();
+        Ok(if url.starts_with("s3://") {
+            Registry::S3(base)
+        } else if url.starts_with("gs://") {
+            Registry::Gcs(base)
+        } else if url.starts_with("oci://") {
+            Registry::Oci(base)
+        } else if url.starts_with("az://") || is_azure_blob_url(url) {
+            azure_object(&format!("{}/{}", base, MANIFEST_FILE)).map_err(|_| {
+                Error::Other(format!(
+                    "Invalid registry: '{}' does not name an Azure storage account and container",
+                    url
+                ))
+            })?;
+            Registry::Azure(base)
+        } else {
+            Registry::Http(base)
+        })
+    }
+
+    /// Base URL of the registry.
+    pub fn url(&self) -> &str {
+        match self {
+            Registry::S3(url)
+            | Registry::Gcs(url)
+            | Registry::Azure(url)
+            | Registry::Http(url)
+            | Registry::Oci(url) => url,
+        }
+    }
+
+    /// URL of a file of a profile in the registry.
+    pub fn object_url(&self, service: &str, profile: &str, file: &str) -> String {
+        format!("{}/{}/{}/{}", self.url(), service, profile, file)
+    }
+
+    /// Where a profile is stored in the registry, for messages.
+    pub fn profile_url(&self, service: &str, profile: &str) -> String {
+        match self {
+            Registry::Oci(url) => format!("{}/{}:{}", url, service, profile),
+            _ => format!("{}/{}/{}", self.url(), service, profile),
+        }
+    }
+
+    /// Upload a stored profile, replacing any copy in the registry.
+    ///
+    /// The local profile is verified first. The manifest is uploaded last, so
+    /// readers never see a manifest without its seed.
+    pub async fn push(
+        &self,
+        store: &SnapshotStore,
+        service: &str,
+        profile: &str,
+        secrets: &SecretResolver,
+    ) -> Result<SnapshotManifest> {
+        let manifest = store.manifest(service, profile)?;
+        let dir = store.profile_dir(service, profile);
+        let seed_file = manifest.seed_file();
+        manifest.verify(&read_seed_file(&dir.join(seed_file))?)?;
+
+        match self {
+            Registry::Http(url) => {
+                return Err(Error::Other(format!(
+                    "Can't push to {}: https:// registries are read-only. \
+                     Use an s3://, gs:// or oci:// registry.",
+                    url
+                )))
+            }
+            Registry::Oci(url) => {
+                let seed_type = if seed_file == COMPRESSED_SEED_FILE {
+                    "application/zstd"
+                } else {
+                    "application/json"
+                };
+                let layers = [
+                    Layer {
+                        name: seed_file.to_string(),
+                        media_type: seed_type.to_string(),
+                        content: fs::read(dir.join(seed_file))?,
+                    },
+                    Layer {
+                        name: MANIFEST_FILE.to_string(),
+                        media_type: "application/json".to_string(),
+                        content: fs::read(dir.join(MANIFEST_FILE))?,
+                    },
+                ];
+                oci::Repository::new(url, service, secrets)?
+                    .push(profile, &layers, annotations(&manifest))
+                    .await?;
+            }
+            Registry::S3(_) | Registry::Gcs(_) | Registry::Azure(_) => {
+                for file in [seed_file, MANIFEST_FILE] {
+                    self.upload(&dir.join(file), &self.object_url(service, profile, file))
+                        .await?;
+                }
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Download a profile into the local store after verifying it.
+    ///
+    /// The local copy is only replaced once both files are downloaded and
+    /// match the manifest, and `check` has passed the downloaded profile.
+    pub async fn fetch(
+        &self,
+        store: &SnapshotStore,
+        service: &str,
+        profile: &str,
+        secrets: &SecretResolver,
+        check: &dyn Fn(&SnapshotManifest, &Value) -> Result<()>,
+    ) -> Result<SnapshotManifest> {
+        let staged = store.staging_dir()?;
+        let result = match self.fetch_into(&staged, service, profile, secrets).await {
+            Ok(manifest) => read_seed_file(&staged.join(manifest.seed_file()))
+                .and_then(|seed| Ok(serde_json::from_slice(&seed)?))
+                .and_then(|seed| check(&manifest, &seed))
+                .map(|()| manifest),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(manifest) => {
+                store.install(&staged, service, profile)?;
+                Ok(manifest)
+            }
+            Err(e) => {
+                let _ = fs::remove_dir_all(&staged);
+                Err(e)
+            }
+        }
+    }
+
+    async fn fetch_into(
+        &self,
+        dir: &Path,
+        service: &str,
+        profile: &str,
+        secrets: &SecretResolver,
+    ) -> Result<SnapshotManifest> {
+        let manifest_path = dir.join(MANIFEST_FILE);
+        if let Registry::Oci(url) = self {
+            // An artifact is pulled whole; only the files of a profile are kept
+            let files = oci::Repository::new(url, service, secrets)?
+                .pull(profile)
+                .await?;
+            for (name, content) in files {
+                if [MANIFEST_FILE, SEED_FILE, COMPRESSED_SEED_FILE].contains(&name.as_str()) {
+                    fs::write(dir.join(name), content)?;
+                }
+            }
+        } else {
+            self.download(
+                &self.object_url(service, profile, MANIFEST_FILE),
+                &manifest_path,
+            )
+            .await?;
+        }
+        if !manifest_path.is_file() {
+            return Err(Error::Other(format!(
+                "{} has no {}",
+                self.profile_url(service, profile),
+                MANIFEST_FILE
+            )));
+        }
+
+        let manifest: SnapshotManifest = serde_json::from_slice(&fs::read(&manifest_path)?)
+            .map_err(|e| {
+                Error::Other(format!(
+                    "Invalid manifest for {}/{} in {}: {}",
+                    service,
+                    profile,
+                    self.url(),
+                    e
+                ))
+            })?;
+        if manifest.service != service || manifest.profile != profile {
+            return Err(Error::Other(format!(
+                "Manifest at {} describes {}/{}, expected {}/{}",
+                self.object_url(service, profile, MANIFEST_FILE),
+                manifest.service,
+                manifest.profile,
+                service,
+                profile
+            )));
+        }
+
+        // Compressed and plain profiles name their seed file differently
+        let seed_file = manifest.seed_file();
+        let seed_path = dir.join(seed_file);
+        if !matches!(self, Registry::Oci(_)) {
+            self.download(&self.object_url(service, profile, seed_file), &seed_path)
+                .await?;
+        } else if !seed_path.is_file() {
+            return Err(Error::Other(format!(
+                "{} has no {}",
+                self.profile_url(service, profile),
+                seed_file
+            )));
+        }
+        manifest.verify(&read_seed_file(&seed_path)?)?;
+        Ok(manifest)
+    }
+
+    async fn download(&self, url: &str, dest: &Path) -> Result<()> {
+        match self {
+            Registry::Http(_) => {
+                let response = reqwest::Client::new()
+                    .get(url)
+                    .timeout(DOWNLOAD_TIMEOUT)
+                    .send()
+                    .await?;
+                let status = response.status();
+                if !status.is_success() {
+                    return Err(Error::Other(format!("{} returned {}", url, status)));
+                }
+                fs::write(dest, response.bytes().await?)?;
+                Ok(())
+            }
+            Registry::S3(_) | Registry::Gcs(_) | Registry::Azure(_) => {
+                self.copy(dest, url, false).await
+            }
+            Registry::Oci(_) => unreachable!("OCI artifacts are pulled whole"),
+        }
+    }
+
+    async fn upload(&self, source: &Path, url: &str) -> Result<()> {
+        self.copy(source, url, true).await
+    }
+
+    /// Copy between a local file and an object, natively where built with
+    /// `native-storage` and otherwise with the cloud provider's CLI.
+    async fn copy(&self, local: &Path, url: &str, upload: bool) -> Result<()> {
+        #[cfg(feature = "native-storage")]
+        if matches!(self, Registry::S3(_) | Registry::Gcs(_)) {
+            match native::copy(local, url, upload).await {
+                Ok(()) => return Ok(()),
+                Err(e) if native::cli_may_succeed(&e) && self.has_cli() => {
+                    tracing::warn!("Native transfer of {} failed, using the CLI: {}", url, e);
+                }
+                Err(e) => {
+                    return Err(Error::Other(format!(
+                        "Failed to {} {}: {}",
+                        if upload { "upload" } else { "download" },
+                        url,
+                        e
+                    )))
+                }
+            }
+        }
+
+        let local = local.to_string_lossy().to_string();
+        let (from, to) = if upload {
+            (local.clone(), url.to_string())
+        } else {
+            (url.to_string(), local.clone())
+        };
+        let (program, mut args) = match self {
+            Registry::S3(_) => ("aws", vec!["s3", "cp", "--only-show-errors"]),
+            Registry::Gcs(_) if which::which("gcloud").is_ok() => {
+                ("gcloud", vec!["storage", "cp", "--quiet"])
+            }
+            Registry::Gcs(_) => ("gsutil", vec!["-q", "cp"]),
+            Registry::Azure(_) => {
+                let (account, container, blob) = azure_object(url)?;
+                let mut args = vec![
+                    "storage",
+                    "blob",
+                    if upload { "upload" } else { "download" },
+                    "--only-show-errors",
+                    "--account-name",
+                    account,
+                    "--container-name",
+                    container,
+                    "--name",
+                    blob,
+                    "--file",
+                    &local,
+                ];
+                if upload {
+                    args.push("--overwrite");
+                }
+                return run_cli("az", &args, url, upload).await;
+            }
+            _ => unreachable!("only object stores are copied to"),
+        };
+        args.extend([from.as_str(), to.as_str()]);
+        run_cli(program, &args, url, upload).await
+    }
+
+    /// Whether the CLI used to transfer objects of this registry is on PATH.
+    #[cfg(feature = "native-storage")]
+    fn has_cli(&self) -> bool {
+        let programs: &[&str] = match self {
+            Registry::S3(_) => &["aws"],
+            Registry::Gcs(_) => &["gcloud", "gsutil"],
+            Registry::Azure(_) => &["az"],
+            _ => &[],
+        };
+        programs.iter().any(|program| which::which(program).is_ok())
+    }
+}
+
+fn is_azure_blob_url(url: &str) -> bool {
+    url.strip_prefix("https://")
+        .and_then(|rest| rest.split('/').next())
+        .is_some_and(|host| host.ends_with(".blob.core.windows.net"))
+}
+
+/// Split an Azure object URL into storage account, container and blob name.
+fn azure_object(url: &str) -> Result<(&str, &str, &str)> {
+    let (account, path) = if let Some(rest) = url.strip_prefix("az://") {
+        rest.split_once('/').unwrap_or((rest, ""))
+    } else {
+        let rest = url.strip_prefix("https://").unwrap_or(url);
+        let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+        (
+            host.strip_suffix(".blob.core.windows.net").unwrap_or(host),
+            path,
+        )
+    };
+    match path.split_once('/') {
+        Some((container, blob))
+            if !account.is_empty() && !container.is_empty() && !blob.is_empty() =>
+        {
+            Ok((account, container, blob))
+        }
+        _ => Err(Error::Other(format!(
+            "'{}' is not an Azure blob URL (az://<account>/<container>/<blob>)",
+            url
+        ))),
+    }
+}
+
+/// Annotations describing a profile on its OCI artifact.
+fn annotations(manifest: &SnapshotManifest) -> BTreeMap<String, String> {
+    let mut annotations = BTreeMap::from([
+        ("service", manifest.service.clone()),
+        ("profile", manifest.profile.clone()),
+        ("connector", manifest.connector.clone()),
+        ("created_at", manifest.created_at.to_string()),
+        ("records", manifest.total_records().to_string()),
+        (
+            "seed_schema_version",
+            manifest.seed_schema_version.to_string(),
+        ),
+    ]);
+    if let Some(sha256) = &manifest.seed_sha256 {
+        annotations.insert("seed_sha256", sha256.clone());
+    }
+    annotations
+        .into_iter()
+        .map(|(key, value)| (format!("{}{}", ANNOTATION_PREFIX, key), value))
+        .collect()
+}
+
+/// Check a registry URL; used to validate the `registry` setting.
+pub fn validate_url(url: &str) -> std::result::Result<(), String> {
+    let supported = ["s3://", "gs://", "az://", "oci://", "https://", "http://"];
+    match supported.iter().find(|scheme| url.starts_with(**scheme)) {
+        Some(scheme) if url.len() > scheme.len() => Ok(()),
+        _ => Err(format!(
+            "'{}' is not an s3://, gs://, az://, oci:// or https:// URL",
+            url
+        )),
+    }
+}
+
+/// Run a cloud provider's CLI to transfer `url`.
+async fn run_cli(program: &str, args: &[&str], url: &str, upload: bool) -> Result<()> {
+    let path = which::which(program).map_err(|_| {
+        Error::Other(format!(
+            "Transferring {} needs the '{}' CLI on PATH",
+            url, program
+        ))
+    })?;
+    let output = Command::new(path)
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(Error::Other(format!(
+            "{} failed to {} {}: {}",
+            program,
+            if upload { "upload" } else { "download" },
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secrets::Provider;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_registries() {
+        let registry = Registry::parse("s3://team-snapshots/doubleagent/").unwrap();
+        assert_eq!(
+            registry,
+            Registry::S3("s3://team-snapshots/doubleagent".into())
+        );
+        assert_eq!(
+            registry.object_url("github", "prod", "seed.json"),
+            "s3://team-snapshots/doubleagent/github/prod/seed.json"
+        );
+        assert!(matches!(
+            Registry::parse("gs://bucket").unwrap(),
+            Registry::Gcs(_)
+        ));
+        assert!(matches!(
+            Registry::parse("https://snapshots.example.com").unwrap(),
+            Registry::Http(_)
+        ));
+        assert!(matches!(
+            Registry::parse("oci://ghcr.io/acme/snapshots").unwrap(),
+            Registry::Oci(_)
+        ));
+        assert!(matches!(
+            Registry::parse("az://acmestore/snapshots").unwrap(),
+            Registry::Azure(_)
+        ));
+        assert!(matches!(
+            Registry::parse("https://acmestore.blob.core.windows.net/snapshots/team").unwrap(),
+            Registry::Azure(_)
+        ));
+        assert!(Registry::parse("az://acmestore").is_err());
+        assert!(Registry::parse("ftp://example.com").is_err());
+        assert!(Registry::parse("s3://").is_err());
+    }
+
+    #[test]
+    fn test_azure_objects() {
+        assert_eq!(
+            azure_object("az://acmestore/snapshots/github/prod/manifest.json").unwrap(),
+            ("acmestore", "snapshots", "github/prod/manifest.json")
+        );
+        assert_eq!(
+            azure_object(
+                "https://acmestore.blob.core.windows.net/snapshots/team/github/prod/seed.json.zst"
+            )
+            .unwrap(),
+            ("acmestore", "snapshots", "team/github/prod/seed.json.zst")
+        );
+        assert!(azure_object("az://acmestore/snapshots").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_push_and_fetch_through_oci() {
+        let registry = Registry::parse(&oci::tests::serve_registry().await).unwrap();
+        let secrets = SecretResolver::with_providers(vec![Provider::Env]);
+        let seed = json!({"repos": [{"id": 1, "name": "api"}]});
+
+        let local = tempdir().unwrap();
+        let local = SnapshotStore::new(local.path());
+        let mut pushed = SnapshotManifest::new("github", "prod", "rest", &seed);
+        local.save(&mut pushed, &seed).unwrap();
+        registry
+            .push(&local, "github", "prod", &secrets)
+            .await
+            .unwrap();
+
+        let remote = tempdir().unwrap();
+        let remote = SnapshotStore::new(remote.path());
+        let fetched = registry
+            .fetch(&remote, "github", "prod", &secrets, &|_, _| Ok(()))
+            .await
+            .unwrap();
+        assert_eq!(fetched, pushed);
+        assert_eq!(remote.load_seed_payload("github", "prod").unwrap(), seed);
+        assert!(registry
+            .fetch(&remote, "github", "staging", &secrets, &|_, _| Ok(()))
+            .await
+            .is_err());
+
+        // A profile the check refuses isn't installed over the one stored
+        let mut kept = SnapshotManifest::new("github", "prod", "import", &json!({}));
+        remote.save(&mut kept, &json!({})).unwrap();
+        let refused = registry
+            .fetch(&remote, "github", "prod", &secrets, &|_, seed| {
+                assert_eq!(*seed, json!({"repos": [{"id": 1, "name": "api"}]}));
+                Err(Error::Other("refused".to_string()))
+            })
+            .await;
+        assert!(refused.is_err());
+        assert_eq!(
+            remote.manifest("github", "prod").unwrap().connector,
+            "import"
+        );
+    }
+}
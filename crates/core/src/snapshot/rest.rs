@@ -0,0 +1,820 @@
+//! Native puller for plain REST APIs (`connector.type: rest`).
+//!
+//! ```yaml
+//! connector:
+//!   type: rest
+//!   base_url: https://api.github.com
+//!   auth:
+//!     scheme: Bearer
+//!     token: secret://github_token
+//!   streams:
+//!     repos:
+//!       path: /user/repos
+//!       params: { per_page: "100" }
+//!       pagination: { type: link }
+//!       fields:
+//!         owner: /owner/login
+//!         name: /name
+//!         private: /private
+//! ```
+//!
+//! Each stream becomes one resource of the seed payload. Records are taken
+//! from the response body (or the array at the `records` JSON pointer) and,
+//! when `fields` is set, reshaped so each output field holds the value at a
+//! JSON pointer into the record.
+//!
+//! A stream with an `incremental` section remembers the highest value of its
+//! `cursor_field` seen in a pull. Later pulls given that cursor send it as the
+//! `param` query parameter, so the API only returns newer records:
+//!
+//! ```yaml
+//!     issues:
+//!       path: /issues
+//!       params: { sort: updated, state: all }
+//!       incremental: { cursor_field: /updated_at, param: since }
+//! ```
+//!
+//! `rate_limit` spaces requests to at most that many per second, and
+//! `max_requests` stops a pull that would need more requests than that, so
+//! pulls stay under the API's rate limits and paid quota. Pages pulled before
+//! the budget ran out are kept, so pulling again with a larger budget resumes:
+//!
+//! ```yaml
+//! connector:
+//!   type: rest
+//!   base_url: https://api.github.com
+//!   rate_limit: 5       # requests per second
+//!   max_requests: 500   # requests per pull
+//! ```
+
+use super::checkpoint::{Checkpoint, StreamState};
+use super::ProgressEvent;
+use crate::secrets::SecretResolver;
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Timeout of a single page request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A REST API to snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RestConnector {
+    /// Base URL the stream paths are relative to
+    pub base_url: String,
+    /// Credentials sent with every request
+    #[serde(default)]
+    pub auth: Option<RestAuth>,
+    /// Extra headers sent with every request
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Resources to pull, keyed by the name they get in the seed payload
+    pub streams: BTreeMap<String, RestStream>,
+    /// Most requests per second
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<f64>,
+    /// Most requests one pull may make
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_requests: Option<u64>,
+}
+
+impl RestConnector {
+    /// The connector's request limits, with those set in `overrides` (e.g.
+    /// from the command line) replacing them.
+    pub fn limits(&self, overrides: RequestLimits) -> RequestLimits {
+        RequestLimits {
+            rate_limit: overrides.rate_limit.or(self.rate_limit),
+            max_requests: overrides.max_requests.or(self.max_requests),
+        }
+    }
+}
+
+/// Limits on the requests a pull makes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RequestLimits {
+    /// Most requests per second
+    pub rate_limit: Option<f64>,
+    /// Most requests in one pull
+    pub max_requests: Option<u64>,
+}
+
+/// Credentials for a REST API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RestAuth {
+    /// Header carrying the credential (default: Authorization)
+    #[serde(default = "default_auth_header")]
+    pub header: String,
+    /// Scheme put before the token, e.g. "Bearer"
+    #[serde(default)]
+    pub scheme: Option<String>,
+    /// The token, usually a `secret://` reference
+    pub token: String,
+}
+
+fn default_auth_header() -> String {
+    "Authorization".to_string()
+}
+
+/// One endpoint pulled into a seed resource.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RestStream {
+    /// Path relative to the base URL
+    pub path: String,
+    /// Query parameters sent with every page
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+    /// JSON pointer to the records array in the response (default: the body)
+    #[serde(default)]
+    pub records: Option<String>,
+    /// How to get the next page
+    #[serde(default)]
+    pub pagination: Pagination,
+    /// Output field to JSON pointer into the record (default: whole records)
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+    /// Stop after this many pages
+    #[serde(default)]
+    pub max_pages: Option<u32>,
+    /// Only fetch records changed since the last pull
+    #[serde(default)]
+    pub incremental: Option<Incremental>,
+}
+
+/// How a stream is pulled incrementally.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Incremental {
+    /// JSON pointer into each record to the value that grows as records
+    /// change, e.g. `/updated_at`
+    pub cursor_field: String,
+    /// Query parameter the last cursor is sent as, e.g. `since`
+    pub param: String,
+    /// Field fetched records replace stored ones by
+    #[serde(default = "default_key")]
+    pub key: String,
+}
+
+fn default_key() -> String {
+    super::diff::DEFAULT_KEY.to_string()
+}
+
+/// Records and cursors of a pull.
+#[derive(Debug, Clone, Default)]
+pub struct Pulled {
+    /// Records by stream
+    pub resources: Map<String, Value>,
+    /// Highest cursor seen, by incremental stream
+    pub cursors: BTreeMap<String, String>,
+    /// Requests made, counting those of the pull a checkpoint resumes
+    pub requests: u64,
+}
+
+/// Pagination styles understood by the REST puller.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Pagination {
+    /// A single request
+    #[default]
+    None,
+    /// Follow `rel="next"` in the `Link` response header
+    Link,
+    /// Increment a page number query parameter until a page is empty
+    Page {
+        #[serde(default = "default_page_param")]
+        param: String,
+        #[serde(default = "default_first_page")]
+        start: u64,
+    },
+    /// Pass the cursor found at a JSON pointer in each response to the next
+    Cursor { param: String, cursor: String },
+}
+
+fn default_page_param() -> String {
+    "page".to_string()
+}
+
+fn default_first_page() -> u64 {
+    1
+}
+
+/// Pull every stream of a connector into a seed payload.
+///
+/// `limit` caps the number of records per stream, and `limits` the requests
+/// made. Incremental streams with a cursor in `cursors` only fetch records
+/// past it. Each page pulled is reported to `progress` and saved to
+/// `checkpoint`, whose saved pages are not pulled again.
+pub async fn pull(
+    connector: &RestConnector,
+    secrets: &SecretResolver,
+    limit: Option<usize>,
+    limits: RequestLimits,
+    cursors: &BTreeMap<String, String>,
+    mut checkpoint: Option<&mut Checkpoint>,
+    progress: &dyn Fn(&ProgressEvent),
+) -> Result<Pulled> {
+    let mut headers = secrets.resolve_map(&connector.headers)?;
+    if let Some(auth) = &connector.auth {
+        let token = secrets.resolve(&auth.token)?;
+        let value = match &auth.scheme {
+            Some(scheme) => format!("{} {}", scheme, token),
+            None => token,
+        };
+        headers.insert(auth.header.clone(), value);
+    }
+    let mut default_headers = reqwest::header::HeaderMap::new();
+    for (header, value) in &headers {
+        let name = reqwest::header::HeaderName::try_from(header.as_str())
+            .map_err(|_| Error::Other(format!("Invalid connector header name '{}'", header)))?;
+        let value = reqwest::header::HeaderValue::try_from(value.as_str()).map_err(|_| {
+            Error::Other(format!("Invalid value for connector header '{}'", header))
+        })?;
+        default_headers.insert(name, value);
+    }
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .user_agent(concat!("doubleagent/", env!("CARGO_PKG_VERSION")))
+        .default_headers(default_headers)
+        .build()?;
+
+    let mut throttle = Throttle::new(limits)?;
+    let mut pulled = Pulled::default();
+    for (name, stream) in &connector.streams {
+        let mut cursor = cursors.get(name).cloned();
+        progress(&ProgressEvent::StreamStarted {
+            stream: name.clone(),
+        });
+        let records = pull_stream(
+            &client,
+            &connector.base_url,
+            name,
+            stream,
+            limit,
+            &mut cursor,
+            &mut throttle,
+            checkpoint.as_deref_mut(),
+            progress,
+        )
+        .await?;
+        tracing::debug!("Pulled {} {}", records.len(), name);
+        progress(&ProgressEvent::StreamDone {
+            stream: name.clone(),
+            count: records.len(),
+        });
+        pulled.resources.insert(name.clone(), Value::Array(records));
+        if let (Some(_), Some(cursor)) = (&stream.incremental, cursor) {
+            pulled.cursors.insert(name.clone(), cursor);
+        }
+    }
+    pulled.requests = throttle.requests;
+    Ok(pulled)
+}
+
+/// Spaces requests by the rate limit and counts them against the budget.
+struct Throttle {
+    limits: RequestLimits,
+    interval: Option<Duration>,
+    last: Option<Instant>,
+    requests: u64,
+}
+
+impl Throttle {
+    fn new(limits: RequestLimits) -> Result<Self> {
+        let interval = match limits.rate_limit {
+            Some(rate) if !(rate > 0.0 && rate.is_finite()) => {
+                return Err(Error::Other(format!(
+                    "Invalid rate limit {} (expected requests per second above 0)",
+                    rate
+                )))
+            }
+            Some(rate) => Some(Duration::from_secs_f64(1.0 / rate)),
+            None => None,
+        };
+        Ok(Self {
+            limits,
+            interval,
+            last: None,
+            requests: 0,
+        })
+    }
+
+    /// Wait until the next request is allowed, failing once the budget is spent.
+    async fn wait(&mut self, stream: &str) -> Result<()> {
+        if let Some(max) = self.limits.max_requests {
+            if self.requests >= max {
+                return Err(Error::Other(format!(
+                    "Stream '{}': the budget of {} requests is spent. Pages pulled so far are kept; \
+                     pull again with a larger --max-requests to continue",
+                    stream, max
+                )));
+            }
+        }
+        if let (Some(interval), Some(last)) = (self.interval, self.last) {
+            tokio::time::sleep_until((last + interval).into()).await;
+        }
+        self.last = Some(Instant::now());
+        self.requests += 1;
+        Ok(())
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn pull_stream(
+    client: &reqwest::Client,
+    base_url: &str,
+    name: &str,
+    stream: &RestStream,
+    limit: Option<usize>,
+    cursor: &mut Option<String>,
+    throttle: &mut Throttle,
+    mut checkpoint: Option<&mut Checkpoint>,
+    progress: &dyn Fn(&ProgressEvent),
+) -> Result<Vec<Value>> {
+    let first_url = format!(
+        "{}/{}",
+        base_url.trim_end_matches('/'),
+        stream.path.trim_start_matches('/')
+    );
+
+    let mut records = Vec::new();
+    let mut next = Some(match &stream.pagination {
+        Pagination::Page { start, .. } => Next::Page(*start),
+        _ => Next::Url(first_url.clone()),
+    });
+    let mut pages = 0;
+    // Every page of a pull asks for records past the cursor it started from
+    let since = match (&stream.incremental, &cursor) {
+        (Some(incremental), Some(cursor)) => Some((incremental.param.clone(), cursor.clone())),
+        _ => None,
+    };
+
+    if let Some(checkpoint) = checkpoint.as_deref() {
+        if let Some(saved) = checkpoint.stream(name) {
+            records = checkpoint.records(name)?;
+            next = if saved.done { None } else { saved.next.clone() };
+            pages = saved.pages;
+            throttle.requests += u64::from(saved.pages);
+            if saved.cursor.is_some() {
+                cursor.clone_from(&saved.cursor);
+            }
+            progress(&ProgressEvent::Resumed {
+                stream: name.to_string(),
+                count: records.len(),
+            });
+        }
+    }
+
+    while let Some(page) = next.take() {
+        let mut request = match &page {
+            // A next link already carries every query parameter
+            Next::Url(url) if *url != first_url => client.get(url),
+            _ => client
+                .get(&first_url)
+                .query(&stream.params)
+                .query(&since.as_slice()),
+        };
+        match (&stream.pagination, &page) {
+            (Pagination::Page { param, .. }, Next::Page(number)) => {
+                request = request.query(&[(param, number.to_string())]);
+            }
+            (Pagination::Cursor { param, .. }, Next::Cursor(cursor)) => {
+                request = request.query(&[(param, cursor)]);
+            }
+            _ => {}
+        }
+
+        throttle.wait(name).await?;
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(Error::Other(format!(
+                "Stream '{}': {} returned {}: {}",
+                name,
+                stream.path,
+                status,
+                body.trim()
+            )));
+        }
+        let link = response
+            .headers()
+            .get(reqwest::header::LINK)
+            .and_then(|v| v.to_str().ok())
+            .and_then(next_link);
+        let body: Value = response.json().await?;
+
+        let page_records = extract_records(&body, stream.records.as_deref()).ok_or_else(|| {
+            Error::Other(format!(
+                "Stream '{}': response has no records array at '{}'",
+                name,
+                stream.records.as_deref().unwrap_or("")
+            ))
+        })?;
+        let empty = page_records.is_empty();
+        if let Some(incremental) = &stream.incremental {
+            for record in page_records {
+                advance_cursor(cursor, record.pointer(&incremental.cursor_field));
+            }
+        }
+        let before = records.len();
+        records.extend(page_records.iter().map(|r| map_fields(r, &stream.fields)));
+        pages += 1;
+
+        if let Some(limit) = limit {
+            records.truncate(limit);
+        }
+        progress(&ProgressEvent::Records {
+            stream: name.to_string(),
+            count: records.len(),
+        });
+        let finished = limit.is_some_and(|limit| records.len() >= limit)
+            || empty
+            || stream.max_pages.is_some_and(|max| pages >= max);
+
+        next = match &stream.pagination {
+            _ if finished => None,
+            Pagination::None => None,
+            Pagination::Link => link.map(Next::Url),
+            Pagination::Page { .. } => match page {
+                Next::Page(number) => Some(Next::Page(number + 1)),
+                _ => None,
+            },
+            Pagination::Cursor { cursor, .. } => match body.pointer(cursor) {
+                Some(Value::String(s)) if !s.is_empty() => Some(Next::Cursor(s.clone())),
+                Some(Value::Number(n)) => Some(Next::Cursor(n.to_string())),
+                _ => None,
+            },
+        };
+        if let Some(checkpoint) = checkpoint.as_deref_mut() {
+            let state = StreamState {
+                done: next.is_none(),
+                pages,
+                records: records.len(),
+                next: next.clone(),
+                cursor: cursor.clone(),
+            };
+            checkpoint.save_page(name, &records[before..], state)?;
+        }
+    }
+
+    Ok(records)
+}
+
+/// Move a cursor forward to a record's cursor value if it is past it.
+/// Numbers compare numerically, anything else (e.g. ISO 8601 timestamps) as
+/// text.
+fn advance_cursor(cursor: &mut Option<String>, value: Option<&Value>) {
+    let value = match value {
+        Some(Value::String(s)) if !s.is_empty() => s.clone(),
+        Some(Value::Number(n)) => n.to_string(),
+        _ => return,
+    };
+    let newer = match cursor.as_deref() {
+        None => true,
+        Some(current) => match (current.parse::<f64>(), value.parse::<f64>()) {
+            (Ok(current), Ok(value)) => value > current,
+            _ => value.as_str() > current,
+        },
+    };
+    if newer {
+        *cursor = Some(value);
+    }
+}
+
+/// The next page to request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Next {
+    Url(String),
+    Page(u64),
+    Cursor(String),
+}
+
+/// The records array of a response.
+fn extract_records<'a>(body: &'a Value, pointer: Option<&str>) -> Option<&'a Vec<Value>> {
+    match pointer {
+        Some(pointer) if !pointer.is_empty() => body.pointer(pointer)?.as_array(),
+        _ => body.as_array(),
+    }
+}
+
+/// Reshape a record with a field mapping; fields missing from it are left out.
+fn map_fields(record: &Value, fields: &BTreeMap<String, String>) -> Value {
+    if fields.is_empty() {
+        return record.clone();
+    }
+    Value::Object(
+        fields
+            .iter()
+            .filter_map(|(field, pointer)| {
+                record
+                    .pointer(pointer)
+                    .map(|value| (field.clone(), value.clone()))
+            })
+            .collect(),
+    )
+}
+
+/// The `rel="next"` URL of a `Link` header.
+fn next_link(header: &str) -> Option<String> {
+    header.split(',').find_map(|link| {
+        let (url, params) = link.split_once(';')?;
+        let is_next = params
+            .split(';')
+            .any(|p| matches!(p.trim(), "rel=\"next\"" | "rel=next"));
+        is_next.then(|| {
+            url.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        })
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_next_link() {
+        let header = r#"<https://api.example.com/items?page=3>; rel="last", <https://api.example.com/items?page=2>; rel="next""#;
+        assert_eq!(
+            next_link(header).as_deref(),
+            Some("https://api.example.com/items?page=2")
+        );
+        assert_eq!(next_link(r#"<https://x/1>; rel="prev""#), None);
+    }
+
+    #[test]
+    fn test_map_fields() {
+        let record = json!({"name": "api", "owner": {"login": "octo"}, "id": 7});
+        let fields = BTreeMap::from([
+            ("owner".to_string(), "/owner/login".to_string()),
+            ("name".to_string(), "/name".to_string()),
+            ("missing".to_string(), "/nope".to_string()),
+        ]);
+        assert_eq!(
+            map_fields(&record, &fields),
+            json!({"owner": "octo", "name": "api"})
+        );
+        assert_eq!(map_fields(&record, &BTreeMap::new()), record);
+    }
+
+    #[test]
+    fn test_advance_cursor() {
+        let mut cursor = None;
+        advance_cursor(&mut cursor, Some(&json!("2024-05-01T00:00:00Z")));
+        advance_cursor(&mut cursor, Some(&json!("2024-04-01T00:00:00Z")));
+        advance_cursor(&mut cursor, None);
+        assert_eq!(cursor.as_deref(), Some("2024-05-01T00:00:00Z"));
+
+        let mut cursor = Some("9".to_string());
+        advance_cursor(&mut cursor, Some(&json!(10)));
+        assert_eq!(cursor.as_deref(), Some("10"));
+    }
+
+    /// Serve `pages` in order, one per connection, recording request lines.
+    pub(crate) fn serve(pages: Vec<Value>) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for page in pages {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                requests.push(line.trim().to_string());
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    requests.push(header.trim().to_lowercase());
+                }
+                let body = page.to_string();
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    #[tokio::test]
+    async fn test_pull_pages_until_empty() {
+        let (url, server) = serve(vec![
+            json!({"data": [{"id": 1, "title": "a"}, {"id": 2, "title": "b"}]}),
+            json!({"data": [{"id": 3, "title": "c"}]}),
+            json!({"data": []}),
+        ]);
+        let connector: RestConnector = serde_yaml::from_str(&format!(
+            "base_url: {}\n\
+             auth: {{ scheme: Bearer, token: abc }}\n\
+             streams:\n  \
+               issues:\n    \
+                 path: /issues\n    \
+                 params: {{ per_page: '2' }}\n    \
+                 records: /data\n    \
+                 pagination: {{ type: page }}\n    \
+                 fields: {{ title: /title }}\n",
+            url
+        ))
+        .unwrap();
+
+        let events = std::sync::Mutex::new(Vec::new());
+        let pulled = pull(
+            &connector,
+            &SecretResolver::with_providers(vec![]),
+            None,
+            RequestLimits::default(),
+            &BTreeMap::new(),
+            None,
+            &|event| events.lock().unwrap().push(event.to_line()),
+        )
+        .await
+        .unwrap();
+        assert!(pulled.cursors.is_empty());
+        assert_eq!(
+            events.into_inner().unwrap(),
+            vec![
+                r#"{"type":"stream_started","stream":"issues"}"#,
+                r#"{"type":"records","stream":"issues","count":2}"#,
+                r#"{"type":"records","stream":"issues","count":3}"#,
+                r#"{"type":"records","stream":"issues","count":3}"#,
+                r#"{"type":"stream_done","stream":"issues","count":3}"#,
+            ]
+        );
+        assert_eq!(
+            Value::Object(pulled.resources),
+            json!({"issues": [{"title": "a"}, {"title": "b"}, {"title": "c"}]})
+        );
+
+        let requests = server.join().unwrap();
+        assert!(requests.contains(&"GET /issues?per_page=2&page=1 HTTP/1.1".to_string()));
+        assert!(requests.contains(&"GET /issues?per_page=2&page=2 HTTP/1.1".to_string()));
+        assert!(requests.contains(&"authorization: bearer abc".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_failed_pull_resumes_after_last_page() {
+        let connector = |url: &str| -> RestConnector {
+            serde_yaml::from_str(&format!(
+                "base_url: {}\n\
+                 streams:\n  \
+                   issues:\n    \
+                     path: /issues\n    \
+                     pagination: {{ type: page }}\n",
+                url
+            ))
+            .unwrap()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let secrets = SecretResolver::with_providers(vec![]);
+
+        // The server goes away after the first page
+        let (url, server) = serve(vec![json!([{"id": 1}, {"id": 2}])]);
+        let mut checkpoint = Checkpoint::open(dir.path(), "issues").unwrap();
+        let failed = pull(
+            &connector(&url),
+            &secrets,
+            None,
+            RequestLimits::default(),
+            &BTreeMap::new(),
+            Some(&mut checkpoint),
+            &|_| {},
+        )
+        .await;
+        assert!(failed.is_err());
+        server.join().unwrap();
+
+        let (url, server) = serve(vec![json!([{"id": 3}]), json!([])]);
+        let mut checkpoint = Checkpoint::open(dir.path(), "issues").unwrap();
+        let events = std::sync::Mutex::new(Vec::new());
+        let pulled = pull(
+            &connector(&url),
+            &secrets,
+            None,
+            RequestLimits::default(),
+            &BTreeMap::new(),
+            Some(&mut checkpoint),
+            &|event| events.lock().unwrap().push(event.to_line()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            Value::Object(pulled.resources),
+            json!({"issues": [{"id": 1}, {"id": 2}, {"id": 3}]})
+        );
+        assert!(events
+            .into_inner()
+            .unwrap()
+            .contains(&r#"{"type":"resumed","stream":"issues","count":2}"#.to_string()));
+        assert_eq!(server.join().unwrap()[0], "GET /issues?page=2 HTTP/1.1");
+    }
+
+    #[tokio::test]
+    async fn test_incremental_pull_sends_and_advances_cursor() {
+        let (url, server) = serve(vec![json!([
+            {"id": 4, "updated_at": "2024-05-02"},
+            {"id": 5, "updated_at": "2024-05-03"},
+        ])]);
+        let connector: RestConnector = serde_yaml::from_str(&format!(
+            "base_url: {}\n\
+             streams:\n  \
+               issues:\n    \
+                 path: /issues\n    \
+                 incremental: {{ cursor_field: /updated_at, param: since }}\n",
+            url
+        ))
+        .unwrap();
+
+        let cursors = BTreeMap::from([("issues".to_string(), "2024-05-01".to_string())]);
+        let pulled = pull(
+            &connector,
+            &SecretResolver::with_providers(vec![]),
+            None,
+            RequestLimits::default(),
+            &cursors,
+            None,
+            &|_| {},
+        )
+        .await
+        .unwrap();
+        assert_eq!(pulled.cursors["issues"], "2024-05-03");
+        assert_eq!(
+            server.join().unwrap()[0],
+            "GET /issues?since=2024-05-01 HTTP/1.1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pull_keeps_to_rate_limit_and_budget() {
+        let rest = |url: &str, limits: &str| -> RestConnector {
+            serde_yaml::from_str(&format!(
+                "base_url: {}\n\
+                 {}\n\
+                 streams:\n  \
+                   issues:\n    \
+                     path: /issues\n    \
+                     pagination: {{ type: page }}\n",
+                url, limits
+            ))
+            .unwrap()
+        };
+        let secrets = SecretResolver::with_providers(vec![]);
+
+        let (url, server) = serve(vec![json!([{"id": 1}]), json!([{"id": 2}]), json!([])]);
+        let connector = rest(&url, "rate_limit: 20");
+        let started = Instant::now();
+        let pulled = pull(
+            &connector,
+            &secrets,
+            None,
+            connector.limits(RequestLimits::default()),
+            &BTreeMap::new(),
+            None,
+            &|_| {},
+        )
+        .await
+        .unwrap();
+        assert_eq!(pulled.requests, 3);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        server.join().unwrap();
+
+        // The command line budget replaces the connector's
+        let (url, server) = serve(vec![json!([{"id": 1}])]);
+        let connector = rest(&url, "max_requests: 5");
+        let limits = connector.limits(RequestLimits {
+            max_requests: Some(1),
+            ..RequestLimits::default()
+        });
+        let msg = pull(
+            &connector,
+            &secrets,
+            None,
+            limits,
+            &BTreeMap::new(),
+            None,
+            &|_| {},
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+        assert!(msg.contains("budget of 1 requests"), "{}", msg);
+        assert_eq!(server.join().unwrap()[0], "GET /issues?page=1 HTTP/1.1");
+    }
+}
@@ -0,0 +1,443 @@
+//! Subsampling a stored profile into a smaller derived profile.
+//!
+//! Each resource keeps a fraction of its records, picked by hashing their key
+//! so the same records are picked every time. With `keep_referenced`, records
+//! of resources that refer to others are kept when a record they refer to was
+//! kept (issues of the sampled repos) instead of being sampled on their own,
+//! and every record a kept record refers to is added, so nothing dangles.
+//!
+//! References are fields named after another resource, `repo_id` or `repo_ids`
+//! for `repos`, holding that resource's key; others can be given explicitly.
+
+use super::merge::{identity, Provenance};
+use super::{SnapshotManifest, SnapshotStore};
+use crate::{Error, Result};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+
+/// Connector name recorded for sampled profiles.
+pub const SAMPLE_CONNECTOR: &str = "sample";
+
+/// A field of one resource holding the keys of another's records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    /// Resource holding the field
+    pub resource: String,
+    /// Field holding a key or an array of keys
+    pub field: String,
+    /// Resource referred to
+    pub target: String,
+    /// Field of the target matched against, `None` for the sampling key
+    pub target_field: Option<String>,
+}
+
+impl Reference {
+    /// Parse `issues.repo=repos` or `issues.repo=repos.full_name`.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let invalid = || {
+            Error::Other(format!(
+                "Invalid reference '{}' (expected <resource>.<field>=<resource>[.<field>])",
+                spec
+            ))
+        };
+        let (from, to) = spec.split_once('=').ok_or_else(invalid)?;
+        let (resource, field) = from.split_once('.').ok_or_else(invalid)?;
+        let (target, target_field) = match to.split_once('.') {
+            Some((target, field)) => (target, Some(field.to_string())),
+            None => (to, None),
+        };
+        if [resource, field, target].iter().any(|s| s.is_empty()) {
+            return Err(invalid());
+        }
+        Ok(Self {
+            resource: resource.to_string(),
+            field: field.to_string(),
+            target: target.to_string(),
+            target_field,
+        })
+    }
+}
+
+/// How to sample a profile.
+#[derive(Debug, Clone)]
+pub struct SampleOptions {
+    /// Share of each resource's records to keep, in (0, 1]
+    pub fraction: f64,
+    /// Keep the records sampled records refer to, and those referring to them
+    pub keep_referenced: bool,
+    /// Field identifying records
+    pub key: String,
+    /// References besides the inferred ones
+    pub references: Vec<Reference>,
+}
+
+/// Sample a stored profile of a service into a new profile `into`.
+///
+/// Redaction counts and the seed schema version are carried over from the
+/// source, which is recorded under `sources`.
+pub fn sample(
+    store: &SnapshotStore,
+    service: &str,
+    profile: &str,
+    into: &str,
+    options: &SampleOptions,
+) -> Result<SnapshotManifest> {
+    if !(options.fraction > 0.0 && options.fraction <= 1.0) {
+        return Err(Error::Other(format!(
+            "Invalid fraction {} (expected a number above 0 and at most 1)",
+            options.fraction
+        )));
+    }
+    let source = store.manifest(service, profile)?;
+    let seed = store.load_seed_payload(service, profile)?;
+    let resources = seed.as_object().ok_or_else(|| {
+        Error::Other(format!(
+            "Snapshot {}/{} has an invalid seed.json",
+            service, profile
+        ))
+    })?;
+    for reference in &options.references {
+        for name in [&reference.resource, &reference.target] {
+            if !resources.contains_key(name) {
+                return Err(Error::Other(format!(
+                    "Snapshot {}/{} has no '{}' resource to reference",
+                    service, profile, name
+                )));
+            }
+        }
+    }
+
+    let seed = Value::Object(sample_resources(resources, options));
+    let mut manifest = SnapshotManifest::new(service, into, SAMPLE_CONNECTOR, &seed);
+    manifest.seed_schema_version = source.seed_schema_version;
+    manifest.redactions = source.redactions.clone();
+    manifest.pseudonyms = source.pseudonyms.clone();
+    manifest.sources = vec![Provenance {
+        profile: source.profile,
+        created_at: source.created_at,
+        connector: source.connector,
+        seed_sha256: source.seed_sha256,
+    }];
+    store.save(&mut manifest, &seed)?;
+    Ok(manifest)
+}
+
+/// The sampled resources; resources that aren't record lists are kept whole.
+fn sample_resources(resources: &Map<String, Value>, options: &SampleOptions) -> Map<String, Value> {
+    let records: BTreeMap<&str, &[Value]> = resources
+        .iter()
+        .filter_map(|(name, value)| Some((name.as_str(), value.as_array()?.as_slice())))
+        .collect();
+    let mut references = options.references.clone();
+    references.extend(infer_references(&records));
+
+    let mut kept: BTreeMap<&str, HashSet<usize>> = BTreeMap::new();
+    for name in dependency_order(&records, &references) {
+        let outgoing: Vec<&Reference> = if options.keep_referenced {
+            references
+                .iter()
+                .filter(|r| r.resource == name && r.target != name)
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let mut picked = HashSet::new();
+        let mut independent = Vec::new();
+        for (i, record) in records[name].iter().enumerate() {
+            let mut refers = false;
+            for reference in &outgoing {
+                let targets = referenced(record, reference, &records, &options.key);
+                refers |= !targets.is_empty();
+                if targets.iter().any(|t| {
+                    kept.get(reference.target.as_str())
+                        .is_some_and(|k| k.contains(t))
+                }) {
+                    picked.insert(i);
+                }
+            }
+            if !refers {
+                independent.push(i);
+            }
+        }
+        picked.extend(pick(name, records[name], &independent, options));
+        kept.insert(name, picked);
+    }
+
+    if options.keep_referenced {
+        // Add what kept records refer to until nothing is missing
+        let mut pending: Vec<(&str, usize)> = kept
+            .iter()
+            .flat_map(|(name, indexes)| indexes.iter().map(move |i| (*name, *i)))
+            .collect();
+        while let Some((name, i)) = pending.pop() {
+            for reference in references.iter().filter(|r| r.resource == name) {
+                for target in referenced(&records[name][i], reference, &records, &options.key) {
+                    let target_name = reference.target.as_str();
+                    if kept.entry(target_name).or_default().insert(target) {
+                        pending.push((target_name, target));
+                    }
+                }
+            }
+        }
+    }
+
+    resources
+        .iter()
+        .map(|(name, value)| {
+            let value = match (records.get(name.as_str()), kept.get(name.as_str())) {
+                (Some(list), Some(indexes)) => Value::Array(
+                    list.iter()
+                        .enumerate()
+                        .filter(|(i, _)| indexes.contains(i))
+                        .map(|(_, record)| record.clone())
+                        .collect(),
+                ),
+                _ => value.clone(),
+            };
+            (name.clone(), value)
+        })
+        .collect()
+}
+
+/// Pick the share of `candidates` with the lowest hashes of their key, at
+/// least one if there are any.
+fn pick(
+    name: &str,
+    records: &[Value],
+    candidates: &[usize],
+    options: &SampleOptions,
+) -> Vec<usize> {
+    let count = (candidates.len() as f64 * options.fraction).ceil() as usize;
+    let mut ranked: Vec<(String, usize)> = candidates
+        .iter()
+        .map(|&i| {
+            let hash = Sha256::digest(format!("{}\0{}", name, identity(&records[i], &options.key)));
+            (format!("{:x}", hash), i)
+        })
+        .collect();
+    ranked.sort();
+    ranked.into_iter().take(count).map(|(_, i)| i).collect()
+}
+
+/// Indexes of the target records a record refers to.
+fn referenced(
+    record: &Value,
+    reference: &Reference,
+    records: &BTreeMap<&str, &[Value]>,
+    key: &str,
+) -> Vec<usize> {
+    let values: Vec<String> = match record.get(&reference.field) {
+        Some(Value::Array(values)) => values.iter().filter_map(key_value).collect(),
+        Some(value) => key_value(value).into_iter().collect(),
+        None => Vec::new(),
+    };
+    if values.is_empty() {
+        return Vec::new();
+    }
+    let field = reference.target_field.as_deref().unwrap_or(key);
+    records
+        .get(reference.target.as_str())
+        .map(|targets| {
+            targets
+                .iter()
+                .enumerate()
+                .filter(|(_, t)| {
+                    t.get(field)
+                        .and_then(key_value)
+                        .is_some_and(|v| values.contains(&v))
+                })
+                .map(|(i, _)| i)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// A scalar as compared between a reference and a key.
+fn key_value(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// References from `<singular>_id` and `<singular>_ids` fields to the
+/// resource named `<singular>`, `<singular>s` or `<singular>es`.
+fn infer_references(records: &BTreeMap<&str, &[Value]>) -> Vec<Reference> {
+    let mut references = Vec::new();
+    for (&name, list) in records {
+        let mut fields: Vec<&str> = list
+            .iter()
+            .filter_map(Value::as_object)
+            .flat_map(|record| record.keys().map(String::as_str))
+            .collect();
+        fields.sort();
+        fields.dedup();
+        for field in fields {
+            let Some(singular) = field
+                .strip_suffix("_ids")
+                .or_else(|| field.strip_suffix("_id"))
+            else {
+                continue;
+            };
+            let target = [
+                singular.to_string(),
+                format!("{}s", singular),
+                format!("{}es", singular),
+            ]
+            .into_iter()
+            .find(|t| records.contains_key(t.as_str()));
+            if let Some(target) = target {
+                references.push(Reference {
+                    resource: name.to_string(),
+                    field: field.to_string(),
+                    target,
+                    target_field: None,
+                });
+            }
+        }
+    }
+    references
+}
+
+/// Resources ordered so those referred to come before those referring to
+/// them; cycles are broken arbitrarily.
+fn dependency_order<'a>(
+    records: &BTreeMap<&'a str, &[Value]>,
+    references: &[Reference],
+) -> Vec<&'a str> {
+    fn visit<'a>(
+        name: &'a str,
+        records: &BTreeMap<&'a str, &[Value]>,
+        references: &[Reference],
+        seen: &mut HashSet<&'a str>,
+        order: &mut Vec<&'a str>,
+    ) {
+        if !seen.insert(name) {
+            return;
+        }
+        for reference in references.iter().filter(|r| r.resource == name) {
+            if let Some((&target, _)) = records.get_key_value(reference.target.as_str()) {
+                visit(target, records, references, seen, order);
+            }
+        }
+        order.push(name);
+    }
+
+    let mut order = Vec::new();
+    let mut seen = HashSet::new();
+    for &name in records.keys() {
+        visit(name, records, references, &mut seen, &mut order);
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::diff::DEFAULT_KEY;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    fn options(fraction: f64, keep_referenced: bool) -> SampleOptions {
+        SampleOptions {
+            fraction,
+            keep_referenced,
+            key: DEFAULT_KEY.to_string(),
+            references: Vec::new(),
+        }
+    }
+
+    fn ids(seed: &Value, resource: &str) -> Vec<u64> {
+        seed[resource]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["id"].as_u64().unwrap())
+            .collect()
+    }
+
+    fn payload() -> Value {
+        let repos: Vec<Value> = (1..=20).map(|id| json!({"id": id})).collect();
+        let users: Vec<Value> = (1..=20).map(|id| json!({"id": id})).collect();
+        let issues: Vec<Value> = (1..=100)
+            .map(|id| json!({"id": id, "repo_id": id % 20 + 1, "user_id": (id * 7) % 20 + 1}))
+            .collect();
+        json!({"repos": repos, "users": users, "issues": issues, "settings": {"plan": "pro"}})
+    }
+
+    #[test]
+    fn test_sampling_is_stable_and_sized() {
+        let seed = payload();
+        let resources = seed.as_object().unwrap();
+        let sampled = Value::Object(sample_resources(resources, &options(0.1, false)));
+        assert_eq!(ids(&sampled, "repos").len(), 2);
+        assert_eq!(ids(&sampled, "issues").len(), 10);
+        assert_eq!(sampled["settings"], json!({"plan": "pro"}));
+        assert_eq!(
+            Value::Object(sample_resources(resources, &options(0.1, false))),
+            sampled
+        );
+    }
+
+    #[test]
+    fn test_keep_referenced_preserves_integrity() {
+        let seed = payload();
+        let sampled = Value::Object(sample_resources(
+            seed.as_object().unwrap(),
+            &options(0.1, true),
+        ));
+        let repos = ids(&sampled, "repos");
+        let users = ids(&sampled, "users");
+
+        // Issues of the sampled repos and users, and what those issues refer to
+        let issues = sampled["issues"].as_array().unwrap();
+        assert!(issues.len() >= 10, "{}", issues.len());
+        for issue in issues {
+            assert!(repos.contains(&issue["repo_id"].as_u64().unwrap()));
+            assert!(users.contains(&issue["user_id"].as_u64().unwrap()));
+        }
+        for repo in &repos {
+            let issues_of_repo = issues
+                .iter()
+                .filter(|i| i["repo_id"] == json!(repo))
+                .count();
+            assert!(issues_of_repo > 0);
+        }
+    }
+
+    #[test]
+    fn test_explicit_references() {
+        let reference = Reference::parse("issues.repo=repos.full_name").unwrap();
+        assert_eq!(reference.target_field.as_deref(), Some("full_name"));
+        assert!(Reference::parse("issues=repos").is_err());
+
+        let seed = json!({
+            "repos": [{"id": 1, "full_name": "acme/api"}, {"id": 2, "full_name": "acme/web"}],
+            "issues": [{"id": 1, "repo": "acme/api"}, {"id": 2, "repo": "acme/web"}],
+        });
+        let mut options = options(0.5, true);
+        options.references = vec![reference];
+        let sampled = Value::Object(sample_resources(seed.as_object().unwrap(), &options));
+        let repo = &sampled["repos"][0]["full_name"];
+        assert_eq!(ids(&sampled, "issues").len(), 1);
+        assert_eq!(&sampled["issues"][0]["repo"], repo);
+    }
+
+    #[test]
+    fn test_sample_stores_profile_with_provenance() {
+        let dir = tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path());
+        let seed = payload();
+        let mut manifest = SnapshotManifest::new("github", "prod", "rest", &seed);
+        store.save(&mut manifest, &seed).unwrap();
+
+        let sampled = sample(&store, "github", "prod", "ci", &options(0.05, true)).unwrap();
+        assert_eq!(sampled.connector, SAMPLE_CONNECTOR);
+        assert_eq!(sampled.sources[0].profile, "prod");
+        assert!(sampled.total_records() < manifest.total_records());
+        assert_eq!(store.manifest("github", "ci").unwrap(), sampled);
+        assert!(sample(&store, "github", "prod", "ci", &options(0.0, false)).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Scenarios: profiles of several services that belong together.
+//!
+//! Agent tests often need the same world in every fake, such as the "acme"
+//! org in the github, slack and jira fakes. A scenario names one profile per
+//! service and is stored as `<data_dir>/snapshots/.scenarios/<name>.json`;
+//! `snapshot pull --scenario` links the profiles it pulls, and `start` and
+//! `run` with `--scenario` seed every service with its profile.
+
+use super::{check_profile_name, now, read_json, write_file, SnapshotStore};
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+const SCENARIOS_DIR: &str = ".scenarios";
+
+/// A named set of profiles, one per service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Scenario {
+    /// Scenario name
+    pub name: String,
+    /// Profile of each service, keyed by service
+    pub profiles: BTreeMap<String, String>,
+    /// When a profile was last linked (seconds since the Unix epoch)
+    pub updated_at: u64,
+}
+
+impl Scenario {
+    /// An empty scenario.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            profiles: BTreeMap::new(),
+            updated_at: now(),
+        }
+    }
+
+    /// Link a service's profile into the scenario, replacing its previous one.
+    pub fn link(&mut self, service: &str, profile: &str) {
+        self.profiles
+            .insert(service.to_string(), profile.to_string());
+        self.updated_at = now();
+    }
+
+    /// Profile of a service, if the scenario has one.
+    pub fn profile(&self, service: &str) -> Option<&str> {
+        self.profiles.get(service).map(String::as_str)
+    }
+}
+
+impl SnapshotStore {
+    fn scenario_path(&self, name: &str) -> PathBuf {
+        self.root.join(SCENARIOS_DIR).join(format!("{}.json", name))
+    }
+
+    /// Load a stored scenario.
+    pub fn scenario(&self, name: &str) -> Result<Scenario> {
+        check_scenario_name(name)?;
+        let path = self.scenario_path(name);
+        if !path.is_file() {
+            return Err(Error::Other(format!(
+                "No scenario '{}'. Pull one with 'doubleagent snapshot pull <services...> --scenario {}'",
+                name, name
+            )));
+        }
+        read_json(&path)
+    }
+
+    /// Load a scenario, or start an empty one if it isn't stored yet.
+    pub fn scenario_or_new(&self, name: &str) -> Result<Scenario> {
+        check_scenario_name(name)?;
+        if self.scenario_path(name).is_file() {
+            self.scenario(name)
+        } else {
+            Ok(Scenario::new(name))
+        }
+    }
+
+    /// Store a scenario after checking every profile it links exists.
+    pub fn save_scenario(&self, scenario: &Scenario) -> Result<()> {
+        check_scenario_name(&scenario.name)?;
+        for (service, profile) in &scenario.profiles {
+            if !self.exists(service, profile) {
+                return Err(Error::Other(format!(
+                    "Scenario '{}' links {}/{}, which isn't stored",
+                    scenario.name, service, profile
+                )));
+            }
+        }
+        let path = self.scenario_path(&scenario.name);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_file(&path, serde_json::to_string_pretty(scenario)?.as_bytes())
+    }
+
+    /// Every stored scenario, by name.
+    pub fn scenarios(&self) -> Result<Vec<Scenario>> {
+        let dir = self.root.join(SCENARIOS_DIR);
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut scenarios = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            match read_json(&path) {
+                Ok(scenario) => scenarios.push(scenario),
+                Err(e) => tracing::warn!("Skipping {}: {}", path.display(), e),
+            }
+        }
+        scenarios.sort_by(|a: &Scenario, b| a.name.cmp(&b.name));
+        Ok(scenarios)
+    }
+}
+
+fn check_scenario_name(name: &str) -> Result<()> {
+    check_profile_name(name).map_err(|_| {
+        Error::Other(format!(
+            "Invalid scenario name '{}' (use letters, digits, '-' and '_')",
+            name
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::SnapshotManifest;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_scenarios_link_stored_profiles() {
+        let dir = tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path());
+        for service in ["github", "slack"] {
+            let seed = json!({"orgs": [{"name": "acme"}]});
+            let mut manifest = SnapshotManifest::new(service, "acme", "rest", &seed);
+            store.save(&mut manifest, &seed).unwrap();
+        }
+
+        let mut scenario = store.scenario_or_new("acme").unwrap();
+        scenario.link("github", "acme");
+        scenario.link("slack", "acme");
+        store.save_scenario(&scenario).unwrap();
+
+        let loaded = store.scenario("acme").unwrap();
+        assert_eq!(loaded, scenario);
+        assert_eq!(loaded.profile("slack"), Some("acme"));
+        assert_eq!(loaded.profile("jira"), None);
+        assert_eq!(store.scenarios().unwrap(), vec![scenario.clone()]);
+        // Scenarios aren't mistaken for services
+        assert_eq!(store.list(None).unwrap().len(), 2);
+
+        scenario.link("jira", "acme");
+        let msg = store.save_scenario(&scenario).unwrap_err().to_string();
+        assert!(msg.contains("jira/acme"), "{}", msg);
+        assert!(store.scenario("globex").is_err());
+    }
+}
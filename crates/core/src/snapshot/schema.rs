@@ -0,0 +1,141 @@
+//! Checking seed payloads against the `seed.schema.json` a service ships.
+//!
+//! A fake answers a payload it can't read with a 500 at seed time. Services
+//! that describe their seed format with a JSON Schema next to their
+//! service.yaml get it checked first, so drift between a snapshot and the
+//! service is reported field by field. Services without one aren't checked.
+
+use crate::service::ServiceDefinition;
+use crate::validate::{self, Diagnostic};
+use crate::{Error, Result};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// File in a service directory holding the JSON Schema of its seed payload.
+pub const SEED_SCHEMA_FILE: &str = "seed.schema.json";
+
+/// Problems listed in an error before the rest are counted.
+const MAX_LISTED: usize = 5;
+
+/// Check a seed payload against the service's seed schema.
+///
+/// Returns `None` if the service ships no schema. Diagnostics point into the
+/// payload and name `file` as where it came from.
+pub fn check(
+    service: &ServiceDefinition,
+    file: &Path,
+    seed: &Value,
+) -> Result<Option<Vec<Diagnostic>>> {
+    let path = service.path.join(SEED_SCHEMA_FILE);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let schema: Value = serde_json::from_str(&fs::read_to_string(&path)?)
+        .map_err(|e| Error::Other(format!("Invalid {}: {}", path.display(), e)))?;
+    validate::check_document(file, seed, &schema)
+        .map(Some)
+        .map_err(|e| Error::Other(format!("Invalid {}: {}", path.display(), e)))
+}
+
+/// Fail with the offending fields if a profile's seed payload doesn't match
+/// the service's seed schema.
+pub fn validate(
+    service: &ServiceDefinition,
+    profile: &str,
+    file: &Path,
+    seed: &Value,
+) -> Result<()> {
+    let diagnostics = check(service, file, seed)?.unwrap_or_default();
+    if !validate::has_errors(&diagnostics) {
+        return Ok(());
+    }
+
+    let errors: Vec<&Diagnostic> = diagnostics
+        .iter()
+        .filter(|d| d.severity == validate::Severity::Error)
+        .collect();
+    let mut message = format!(
+        "Snapshot {}/{} does not match the service's {}:",
+        service.name, profile, SEED_SCHEMA_FILE
+    );
+    for diagnostic in errors.iter().take(MAX_LISTED) {
+        message.push_str(&format!("\n  {}", diagnostic));
+    }
+    if errors.len() > MAX_LISTED {
+        message.push_str(&format!(
+            "\n  ...and {} more (see 'doubleagent snapshot validate {} --profile {}')",
+            errors.len() - MAX_LISTED,
+            service.name,
+            profile
+        ));
+    }
+    Err(Error::Other(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    fn service_with_schema(dir: &Path, schema: &Value) -> ServiceDefinition {
+        fs::write(dir.join(SEED_SCHEMA_FILE), schema.to_string()).unwrap();
+        let mut service: ServiceDefinition =
+            serde_yaml::from_str("name: github\nserver:\n  command: [true]\n").unwrap();
+        service.path = dir.to_path_buf();
+        service
+    }
+
+    #[test]
+    fn test_problems_point_at_fields() {
+        let dir = tempdir().unwrap();
+        let service = service_with_schema(
+            dir.path(),
+            &json!({
+                "type": "object",
+                "properties": {
+                    "repos": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "required": ["owner", "name"],
+                            "properties": {"private": {"type": "boolean"}}
+                        }
+                    }
+                }
+            }),
+        );
+        let file = Path::new("seed.json.zst");
+
+        let valid = json!({"repos": [{"owner": "acme", "name": "api"}]});
+        assert!(check(&service, file, &valid).unwrap().unwrap().is_empty());
+        validate(&service, "prod", file, &valid).unwrap();
+
+        let drifted =
+            json!({"repos": [{"owner": "acme", "name": "api"}, {"name": "web", "private": "yes"}]});
+        let diagnostics = check(&service, file, &drifted).unwrap().unwrap();
+        let pointers: Vec<&str> = diagnostics.iter().map(|d| d.pointer.as_str()).collect();
+        assert_eq!(pointers, ["/repos/1", "/repos/1/private"]);
+
+        let msg = validate(&service, "prod", file, &drifted)
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("github/prod"), "{}", msg);
+        assert!(msg.contains("/repos/1/private"), "{}", msg);
+    }
+
+    #[test]
+    fn test_services_without_schema_are_not_checked() {
+        let dir = tempdir().unwrap();
+        let mut service: ServiceDefinition =
+            serde_yaml::from_str("name: github\nserver:\n  command: [true]\n").unwrap();
+        service.path = dir.path().to_path_buf();
+
+        let seed = json!({"repos": "not a list"});
+        assert!(check(&service, Path::new("seed.json"), &seed)
+            .unwrap()
+            .is_none());
+        validate(&service, "prod", Path::new("seed.json"), &seed).unwrap();
+    }
+}
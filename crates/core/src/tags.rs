@@ -0,0 +1,83 @@
+//! Key/value tags attached to running services for filtering.
+
+use crate::{Error, Result};
+use std::collections::BTreeMap;
+
+/// Tags attached to a service, sorted by key.
+pub type Tags = BTreeMap<String, String>;
+
+/// Parse a `key=value` tag.
+pub fn parse_tag(s: &str) -> Result<(String, String)> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| Error::Other(format!("Invalid tag '{}', expected key=value", s)))?;
+
+    let valid_key = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if !valid_key {
+        return Err(Error::Other(format!(
+            "Invalid tag key '{}', use letters, digits, '_', '-' or '.'",
+            key
+        )));
+    }
+
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Check whether tags contain every `key=value` pair in the filter.
+pub fn matches(tags: &Tags, filter: &[(String, String)]) -> bool {
+    filter
+        .iter()
+        .all(|(key, value)| tags.get(key) == Some(value))
+}
+
+/// Format tags as `key=value` pairs separated by spaces.
+pub fn format(tags: &Tags) -> String {
+    tags.iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tag() {
+        assert_eq!(
+            parse_tag("ticket=ENG-123").unwrap(),
+            ("ticket".to_string(), "ENG-123".to_string())
+        );
+        assert_eq!(
+            parse_tag("note=a=b").unwrap(),
+            ("note".to_string(), "a=b".to_string())
+        );
+        assert!(parse_tag("team").is_err());
+        assert!(parse_tag("=x").is_err());
+        assert!(parse_tag("bad key=x").is_err());
+    }
+
+    #[test]
+    fn test_matches_requires_all_pairs() {
+        let tags: Tags = [("team", "payments"), ("ticket", "ENG-123")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        assert!(matches(&tags, &[]));
+        assert!(matches(
+            &tags,
+            &[("team".to_string(), "payments".to_string())]
+        ));
+        assert!(!matches(
+            &tags,
+            &[
+                ("team".to_string(), "payments".to_string()),
+                ("ticket".to_string(), "ENG-999".to_string())
+            ]
+        ));
+    }
+}
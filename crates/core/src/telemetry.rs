@@ -0,0 +1,102 @@
+//! OpenTelemetry tracing through fakes, so their calls show up in the traces
+//! of the agent under test.
+//!
+//! With the `otel_endpoint` setting, `doubleagent start` and `run` start each
+//! fake with the standard `OTEL_*` variables (see [`env`]), for fakes built on
+//! an OpenTelemetry SDK, and then tell fakes that list `telemetry: true` in
+//! `/_doubleagent/info` where to send spans through the optional
+//! `POST /_doubleagent/telemetry` endpoint (see [`TelemetryConfig`]). Such a
+//! fake continues the trace of each request's `traceparent` header, exports a
+//! span per request over OTLP/HTTP, and passes the trace on in the calls it
+//! makes, such as webhook deliveries.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Control endpoint fakes are told where to send spans through.
+pub const ENDPOINT: &str = "telemetry";
+
+/// What `POST /_doubleagent/telemetry` sends.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TelemetryConfig {
+    /// OTLP/HTTP collector base URL; spans go to `<endpoint>/v1/traces`
+    pub endpoint: String,
+    /// `service.name` of the spans the fake emits
+    pub service_name: String,
+    /// Whether to continue incoming `traceparent` headers and pass them on
+    pub propagate: bool,
+}
+
+impl TelemetryConfig {
+    pub fn new(endpoint: &str, service: &str) -> Self {
+        Self {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            service_name: service_name(service),
+            propagate: true,
+        }
+    }
+}
+
+/// `service.name` of a fake's spans, told apart from the real service's.
+pub fn service_name(service: &str) -> String {
+    format!("doubleagent-{}", service)
+}
+
+/// Environment a fake is started with to export traces to `endpoint`.
+pub fn env(endpoint: &str, service: &str) -> BTreeMap<String, String> {
+    [
+        (
+            "OTEL_EXPORTER_OTLP_ENDPOINT",
+            endpoint.trim_end_matches('/').to_string(),
+        ),
+        ("OTEL_SERVICE_NAME", service_name(service)),
+        ("OTEL_TRACES_EXPORTER", "otlp".to_string()),
+        ("OTEL_PROPAGATORS", "tracecontext,baggage".to_string()),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value))
+    .collect()
+}
+
+/// Check an `otel_endpoint` setting: empty, or an http:// or https:// URL.
+pub fn validate_endpoint(value: &str) -> std::result::Result<(), String> {
+    if value.is_empty() || value.starts_with("http://") || value.starts_with("https://") {
+        Ok(())
+    } else {
+        Err("expected an http:// or https:// URL of an OTLP collector".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env() {
+        let env = env("http://localhost:4318/", "github");
+        assert_eq!(env["OTEL_EXPORTER_OTLP_ENDPOINT"], "http://localhost:4318");
+        assert_eq!(env["OTEL_SERVICE_NAME"], "doubleagent-github");
+        assert_eq!(env["OTEL_PROPAGATORS"], "tracecontext,baggage");
+        assert_eq!(env.len(), 4);
+    }
+
+    #[test]
+    fn test_config() {
+        let config = TelemetryConfig::new("http://collector:4318/", "slack");
+        assert_eq!(
+            serde_json::to_value(&config).unwrap(),
+            serde_json::json!({
+                "endpoint": "http://collector:4318",
+                "service_name": "doubleagent-slack",
+                "propagate": true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_endpoint() {
+        assert!(validate_endpoint("").is_ok());
+        assert!(validate_endpoint("http://localhost:4318").is_ok());
+        assert!(validate_endpoint("localhost:4317").is_err());
+    }
+}
@@ -0,0 +1,286 @@
+//! `{{ }}` templating of seed files.
+//!
+//! Seed files rendered as templates (`doubleagent seed --var` or
+//! `--template`) may vary per run:
+//!
+//! ```yaml
+//! orgs:
+//!   - name: "{{ org }}"                        # from --var org=acme
+//!     owner: '{{ env.USER | default("ci") }}'    # an environment variable
+//! issues:
+//!   - title: "Release {{ version | upper }}"
+//!     created_at: "{{ now | add_days(-7) }}"   # RFC 3339, a week ago
+//!     due_on: "{{ today | add_days(14) }}"      # YYYY-MM-DD
+//! ```
+//!
+//! A tag holds a variable given with `--var`, `env.<NAME>`, `now`, `today` or
+//! a quoted string, followed by any number of `|` filters: `default("...")`
+//! for unset or empty values, `upper`, `lower` and `add_days(<n>)`. Like
+//! environment interpolation in config files, rendering runs on parsed string
+//! values, so substituted text can never change the structure of the payload.
+//! Using a variable that isn't set and has no default is an error.
+
+use crate::dates::{self, SECONDS_PER_DAY};
+use crate::interpolate::is_valid_name;
+use crate::{Error, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Render every string value of a payload in place.
+///
+/// `source` is only used to make error messages point at the right file.
+pub fn render(value: &mut Value, vars: &BTreeMap<String, String>, source: &Path) -> Result<()> {
+    let lookup = |name: &str| std::env::var(name).ok();
+    let mut missing = Vec::new();
+    render_value(value, vars, &lookup, &mut missing)
+        .map_err(|e| Error::Other(format!("{}: {}", source.display(), e)))?;
+
+    if !missing.is_empty() {
+        missing.sort();
+        missing.dedup();
+        return Err(Error::Other(format!(
+            "{} uses variables that are not set: {}\n\
+             Pass them with --var <name>=<value> or give a default with | default(\"...\").",
+            source.display(),
+            missing.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+fn render_value(
+    value: &mut Value,
+    vars: &BTreeMap<String, String>,
+    env: &dyn Fn(&str) -> Option<String>,
+    missing: &mut Vec<String>,
+) -> std::result::Result<(), String> {
+    match value {
+        Value::String(s) => *s = render_str(s, vars, env, missing)?,
+        Value::Array(items) => {
+            for item in items {
+                render_value(item, vars, env, missing)?;
+            }
+        }
+        Value::Object(map) => {
+            for item in map.values_mut() {
+                render_value(item, vars, env, missing)?;
+            }
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+    Ok(())
+}
+
+/// Render the `{{ }}` tags of a single string.
+///
+/// Unset variables without a default are pushed to `missing` and rendered
+/// empty. Returns an error for malformed tags.
+pub fn render_str(
+    input: &str,
+    vars: &BTreeMap<String, String>,
+    env: &dyn Fn(&str) -> Option<String>,
+    missing: &mut Vec<String>,
+) -> std::result::Result<String, String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| format!("unterminated tag in '{}'", input))?;
+        let tag = &after[..end];
+        rest = &after[end + 2..];
+
+        let mut parts = split_filters(tag).into_iter();
+        let expr = parts.next().unwrap_or_default();
+        let (mut value, name) =
+            resolve(expr, vars, env).map_err(|e| format!("{} in '{}'", e, input))?;
+        for filter in parts {
+            value = apply_filter(filter, value).map_err(|e| format!("{} in '{}'", e, input))?;
+        }
+        match value {
+            Some(value) => output.push_str(&value),
+            None => missing.push(name),
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Value of a tag's expression (`None` if unset), with the name reported
+/// when it is missing.
+fn resolve(
+    expr: &str,
+    vars: &BTreeMap<String, String>,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> std::result::Result<(Option<String>, String), String> {
+    if expr.starts_with('"') {
+        return Ok((Some(parse_string(expr)?), String::new()));
+    }
+    if let Some(name) = expr.strip_prefix("env.") {
+        if !is_valid_name(name) {
+            return Err(format!("invalid variable name '{}'", name));
+        }
+        return Ok((env(name).filter(|v| !v.is_empty()), expr.to_string()));
+    }
+    if !is_valid_name(expr) {
+        return Err(format!("invalid tag '{{{{ {} }}}}'", expr));
+    }
+    let value = match (vars.get(expr), expr) {
+        (Some(value), _) => Some(value.clone()),
+        (None, "now") => Some(dates::format_time(dates::now())),
+        (None, "today") => Some(dates::format_date(dates::now().div_euclid(SECONDS_PER_DAY))),
+        (None, _) => None,
+    };
+    Ok((value.filter(|v| !v.is_empty()), expr.to_string()))
+}
+
+fn apply_filter(
+    filter: &str,
+    value: Option<String>,
+) -> std::result::Result<Option<String>, String> {
+    let (name, arg) = match filter.split_once('(') {
+        Some((name, arg)) => {
+            let arg = arg
+                .strip_suffix(')')
+                .ok_or_else(|| format!("unclosed '(' in filter '{}'", filter))?;
+            (name.trim(), Some(arg.trim()))
+        }
+        None => (filter, None),
+    };
+    let value = match (name, arg) {
+        ("default", Some(arg)) => Some(value.unwrap_or(parse_string(arg)?)),
+        ("upper", None) => value.map(|v| v.to_uppercase()),
+        ("lower", None) => value.map(|v| v.to_lowercase()),
+        ("add_days", Some(arg)) => {
+            let days: i64 = arg
+                .parse()
+                .map_err(|_| format!("add_days needs a whole number, got '{}'", arg))?;
+            match value {
+                Some(v) => Some(add_days(&v, days)?),
+                None => None,
+            }
+        }
+        _ => return Err(format!("unknown filter '{}'", filter)),
+    };
+    Ok(value)
+}
+
+/// Move a date or time by whole days, keeping its format.
+fn add_days(value: &str, days: i64) -> std::result::Result<String, String> {
+    let time = dates::parse_time(value).map_err(|e| e.to_string())? + days * SECONDS_PER_DAY;
+    Ok(if value.contains('T') {
+        dates::format_time(time)
+    } else {
+        dates::format_date(time.div_euclid(SECONDS_PER_DAY))
+    })
+}
+
+/// Split a tag on `|`, outside of quoted strings.
+fn split_filters(tag: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in tag.char_indices() {
+        match c {
+            '\\' if quoted => {
+                escaped = !escaped;
+                continue;
+            }
+            '"' if !escaped => quoted = !quoted,
+            '|' if !quoted => {
+                parts.push(tag[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+        escaped = false;
+    }
+    parts.push(tag[start..].trim());
+    parts
+}
+
+fn parse_string(literal: &str) -> std::result::Result<String, String> {
+    serde_json::from_str(literal).map_err(|_| format!("invalid string {}", literal))
+}
+
+/// Parse a `name=value` variable.
+pub fn parse_var(s: &str) -> Result<(String, String)> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| Error::Other(format!("Invalid variable '{}', expected name=value", s)))?;
+    if !is_valid_name(name) {
+        return Err(Error::Other(format!(
+            "Invalid variable name '{}', use letters, digits and '_'",
+            name
+        )));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render(input: &str) -> std::result::Result<(String, Vec<String>), String> {
+        let vars = BTreeMap::from([
+            ("org".to_string(), "acme".to_string()),
+            ("start".to_string(), "2024-02-27".to_string()),
+        ]);
+        let env = |name: &str| (name == "CI_USER").then(|| "bot".to_string());
+        let mut missing = Vec::new();
+        let output = render_str(input, &vars, &env, &mut missing)?;
+        Ok((output, missing))
+    }
+
+    #[test]
+    fn test_renders_variables_and_filters() {
+        let ok = |input: &str| render(input).unwrap().0;
+        assert_eq!(ok("{{ org }}/{{org | upper}}"), "acme/ACME");
+        assert_eq!(ok("{{ env.CI_USER }}"), "bot");
+        assert_eq!(ok(r#"{{ team | default("core") }}"#), "core");
+        assert_eq!(ok(r#"{{ "a | b" }}"#), "a | b");
+        assert_eq!(ok("{{ start | add_days(3) }}"), "2024-03-01");
+        assert_eq!(
+            ok(r#"{{ "2024-01-01T10:00:00Z" | add_days(-1) }}"#),
+            "2023-12-31T10:00:00Z"
+        );
+        assert_eq!(ok("{{ today }}").len(), 10);
+        assert_eq!(ok("no tags {here}"), "no tags {here}");
+
+        let (output, missing) = render("{{ team }}-{{ env.NOPE }}").unwrap();
+        assert_eq!(output, "-");
+        assert_eq!(missing, ["team", "env.NOPE"]);
+
+        for invalid in [
+            "{{ org",
+            "{{ org | shout }}",
+            "{{ 1org }}",
+            "{{ org | add_days(x) }}",
+        ] {
+            assert!(render(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_render_payload() {
+        let mut payload = json!({"orgs": [{"name": "{{ org }}", "seats": 5}]});
+        let vars = BTreeMap::from([("org".to_string(), "globex".to_string())]);
+        super::render(&mut payload, &vars, Path::new("seed.yaml")).unwrap();
+        assert_eq!(payload, json!({"orgs": [{"name": "globex", "seats": 5}]}));
+
+        let mut payload = json!({"orgs": [{"name": "{{ org }}"}]});
+        let msg = super::render(&mut payload, &BTreeMap::new(), Path::new("seed.yaml"))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            msg.contains("seed.yaml uses variables that are not set: org"),
+            "{}",
+            msg
+        );
+    }
+}
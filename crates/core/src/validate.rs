@@ -0,0 +1,494 @@
+//! Validation of project and service configuration files.
+//!
+//! Files are checked against the JSON Schemas in `schemas/` and then for
+//! references that must exist on disk, producing diagnostics instead of the
+//! serde errors that would otherwise surface at runtime.
+
+use crate::extends;
+use crate::interpolate;
+use crate::migrate;
+use crate::service::{ServiceDefinition, ServiceRegistry};
+use jsonschema::error::ValidationErrorKind;
+use jsonschema::ValidationError;
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// JSON Schema for service.yaml.
+pub const SERVICE_SCHEMA: &str = include_str!("../schemas/service.schema.json");
+/// JSON Schema for doubleagent.yaml.
+pub const PROJECT_SCHEMA: &str = include_str!("../schemas/project.schema.json");
+
+/// How serious a diagnostic is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A single problem found in a configuration file.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// File the problem was found in
+    pub file: PathBuf,
+    /// JSON pointer to the offending value (empty for the whole document)
+    pub pointer: String,
+    pub message: String,
+    /// Suggested fix
+    pub hint: Option<String>,
+}
+
+impl Diagnostic {
+    fn error(file: &Path, pointer: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            file: file.to_path_buf(),
+            pointer: pointer.into(),
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn warning(file: &Path, pointer: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(file, pointer, message)
+        }
+    }
+
+    fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.pointer.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.pointer, self.message)
+        }
+    }
+}
+
+/// Whether any diagnostic is an error.
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|d| d.severity == Severity::Error)
+}
+
+/// Validate a service directory's service.yaml and the paths it relies on.
+pub fn validate_service_dir(service_dir: &Path) -> Vec<Diagnostic> {
+    let file = service_dir.join("service.yaml");
+    let document = match load_document(&file) {
+        Ok(document) => document,
+        Err(diagnostic) => return vec![diagnostic],
+    };
+
+    let mut diagnostics = check_schema(&file, &document, SERVICE_SCHEMA);
+    if has_errors(&diagnostics) {
+        return diagnostics;
+    }
+
+    let service = match ServiceDefinition::from_dir(service_dir) {
+        Ok(service) => service,
+        Err(e) => {
+            diagnostics.push(Diagnostic::error(&file, "", e.to_string()));
+            return diagnostics;
+        }
+    };
+
+    if !service_dir.join("server").is_dir() {
+        diagnostics.push(
+            Diagnostic::error(&file, "/server", "server/ directory not found")
+                .with_hint("The server command runs from the service's server/ directory"),
+        );
+    }
+
+    if let Some(contracts) = &service.contracts {
+        if !service_dir.join(&contracts.directory).is_dir() {
+            diagnostics.push(Diagnostic::warning(
+                &file,
+                "/contracts/directory",
+                format!("contracts directory '{}' not found", contracts.directory),
+            ));
+        }
+    }
+
+    let dir_name = service_dir.file_name().and_then(|n| n.to_str());
+    if dir_name.is_some_and(|n| n != service.name) {
+        diagnostics.push(
+            Diagnostic::warning(
+                &file,
+                "/name",
+                format!(
+                    "name '{}' does not match directory '{}'",
+                    service.name,
+                    dir_name.unwrap_or_default()
+                ),
+            )
+            .with_hint("Services are looked up by directory name"),
+        );
+    }
+
+    diagnostics
+}
+
+/// Validate a project config file and check that the services it lists are available.
+///
+/// The file is checked on its own first, then merged with the configs it
+/// `extends` (remote ones cached under `cache_dir`) and checked again.
+pub fn validate_project_config(
+    path: &Path,
+    registry: &ServiceRegistry,
+    cache_dir: &Path,
+) -> Vec<Diagnostic> {
+    let document = match load_document(path) {
+        Ok(document) => document,
+        Err(diagnostic) => return vec![diagnostic],
+    };
+
+    let mut diagnostics = check_schema(path, &document, PROJECT_SCHEMA);
+    if has_errors(&diagnostics) {
+        return diagnostics;
+    }
+
+    let version = document
+        .get(migrate::KEY)
+        .and_then(Value::as_u64)
+        .unwrap_or(1);
+    if version < migrate::CURRENT_VERSION {
+        diagnostics.push(
+            Diagnostic::warning(
+                path,
+                "",
+                format!(
+                    "uses config version {} (latest is {})",
+                    version,
+                    migrate::CURRENT_VERSION
+                ),
+            )
+            .with_hint("Run 'doubleagent migrate-config' to upgrade it"),
+        );
+    }
+
+    // Check the result of merging in the configs the file builds on
+    let document = if document.get(extends::KEY).is_some() {
+        let merged = match extends::load(path, cache_dir) {
+            Ok(merged) => serde_json::to_value(merged).unwrap_or(Value::Null),
+            Err(e) => {
+                diagnostics.push(Diagnostic::error(path, "/extends", e.to_string()));
+                return diagnostics;
+            }
+        };
+        diagnostics.extend(check_schema(path, &merged, PROJECT_SCHEMA));
+        if has_errors(&diagnostics) {
+            return diagnostics;
+        }
+        merged
+    } else {
+        document
+    };
+
+    // Services are a list of names or a mapping keyed by name
+    let services: Vec<(String, &str)> = match document.get("services") {
+        Some(Value::Array(names)) => names
+            .iter()
+            .enumerate()
+            .filter_map(|(i, name)| Some((format!("/services/{}", i), name.as_str()?)))
+            .collect(),
+        Some(Value::Object(services)) => services
+            .keys()
+            .map(|name| (format!("/services/{}", name), name.as_str()))
+            .collect(),
+        _ => Vec::new(),
+    };
+    for (pointer, name) in services {
+        if registry.service_dir(name).is_none() {
+            diagnostics.push(
+                Diagnostic::warning(
+                    path,
+                    pointer,
+                    format!("service '{}' is not installed", name),
+                )
+                .with_hint(format!("Run 'doubleagent add {}'", name)),
+            );
+        }
+    }
+
+    diagnostics
+}
+
+/// Load a YAML, TOML or JSON file as JSON, interpolating environment variables.
+fn load_document(file: &Path) -> Result<Value, Diagnostic> {
+    let content = fs::read_to_string(file)
+        .map_err(|e| Diagnostic::error(file, "", format!("cannot read file: {}", e)))?;
+
+    interpolate::from_config_str::<Option<Value>>(&content, file)
+        .map(|v| v.unwrap_or(Value::Null))
+        .map_err(|e| Diagnostic::error(file, "", e.to_string()))
+}
+
+/// Check a document against a bundled JSON Schema.
+fn check_schema(file: &Path, document: &Value, schema: &str) -> Vec<Diagnostic> {
+    let schema: Value = serde_json::from_str(schema).expect("bundled schema is valid JSON");
+    check_document(file, document, &schema).expect("bundled schema compiles")
+}
+
+/// Check a document against a JSON Schema, failing if the schema itself is invalid.
+pub(crate) fn check_document(
+    file: &Path,
+    document: &Value,
+    schema: &Value,
+) -> Result<Vec<Diagnostic>, String> {
+    let validator = jsonschema::validator_for(schema).map_err(|e| e.to_string())?;
+    let known = property_names(schema);
+
+    let mut diagnostics = Vec::new();
+    for error in validator.iter_errors(document) {
+        collect_diagnostics(file, &error, &known, &mut diagnostics);
+    }
+    Ok(diagnostics)
+}
+
+/// Turn a schema error into diagnostics.
+///
+/// For `oneOf`/`anyOf` failures the errors of the alternative whose type
+/// matched are reported instead, since "not valid under any schema" says
+/// nothing about what to fix.
+fn collect_diagnostics(
+    file: &Path,
+    error: &ValidationError<'_>,
+    known: &[String],
+    out: &mut Vec<Diagnostic>,
+) {
+    let context = match error.kind() {
+        ValidationErrorKind::OneOfNotValid { context } | ValidationErrorKind::AnyOf { context } => {
+            Some(context)
+        }
+        _ => None,
+    };
+    let path = error.instance_path().to_string();
+    let matching_branch = context.and_then(|branches| {
+        branches.iter().find(|errors| {
+            !errors.is_empty()
+                && !errors.iter().any(|e| {
+                    matches!(e.kind(), ValidationErrorKind::Type { .. })
+                        && e.instance_path().to_string() == path
+                })
+        })
+    });
+    if let Some(errors) = matching_branch {
+        for error in errors {
+            collect_diagnostics(file, error, known, out);
+        }
+        return;
+    }
+
+    let diagnostic = Diagnostic::error(file, path, error.to_string());
+    out.push(match error.kind() {
+        ValidationErrorKind::AdditionalProperties { unexpected } => {
+            match unexpected.iter().find_map(|u| suggest(u, known)) {
+                Some(suggestion) => diagnostic.with_hint(format!("Did you mean '{}'?", suggestion)),
+                None => diagnostic,
+            }
+        }
+        _ => diagnostic,
+    });
+}
+
+/// Collect every property name declared anywhere in a schema.
+fn property_names(schema: &Value) -> Vec<String> {
+    let mut names = Vec::new();
+    if let Some(object) = schema.as_object() {
+        if let Some(properties) = object.get("properties").and_then(Value::as_object) {
+            names.extend(properties.keys().cloned());
+        }
+        for value in object.values() {
+            names.extend(property_names(value));
+        }
+    }
+    names
+}
+
+/// Suggest the closest known name for a likely typo.
+fn suggest(name: &str, known: &[String]) -> Option<String> {
+    known
+        .iter()
+        .map(|k| (edit_distance(name, k), k))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, k)| k.clone())
+}
+
+/// Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn service_dir(yaml: &str) -> tempfile::TempDir {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("service.yaml"), yaml).unwrap();
+        fs::create_dir(dir.path().join("server")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_valid_service_has_no_errors() {
+        let dir = service_dir("name: test\nserver:\n  command: [\"python\", \"main.py\"]\n");
+        let diagnostics = validate_service_dir(dir.path());
+        assert!(!has_errors(&diagnostics), "{:?}", diagnostics);
+    }
+
+    #[test]
+    fn test_rest_connector_is_checked() {
+        let valid = service_dir(
+            "name: test\nserver:\n  command: [\"python\"]\nconnector:\n  type: rest\n  \
+             base_url: https://api.example.com\n  streams:\n    items:\n      path: /items\n      \
+             pagination: { type: cursor, param: after, cursor: /next }\n",
+        );
+        let diagnostics = validate_service_dir(valid.path());
+        assert!(!has_errors(&diagnostics), "{:?}", diagnostics);
+
+        let invalid = service_dir(
+            "name: test\nserver:\n  command: [\"python\"]\nconnector:\n  type: rest\n  \
+             base_url: https://api.example.com\n  streams:\n    items:\n      pth: /items\n",
+        );
+        assert!(has_errors(&validate_service_dir(invalid.path())));
+    }
+
+    #[test]
+    fn test_seed_migrations_are_checked() {
+        let valid = service_dir(
+            "name: test\nserver:\n  command: [\"python\"]\nseed_schema_version: 2\n\
+             seed_migrations:\n  - from: 1\n    command: [python, migrations/v1.py]\n",
+        );
+        let diagnostics = validate_service_dir(valid.path());
+        assert!(!has_errors(&diagnostics), "{:?}", diagnostics);
+
+        let invalid = service_dir(
+            "name: test\nserver:\n  command: [\"python\"]\nseed_migrations:\n  - from: 1\n    command: []\n",
+        );
+        assert!(has_errors(&validate_service_dir(invalid.path())));
+    }
+
+    #[test]
+    fn test_typo_gets_suggestion() {
+        let dir = service_dir("name: test\nserver:\n  comand: [\"python\"]\n");
+        let diagnostics = validate_service_dir(dir.path());
+
+        let typo = diagnostics
+            .iter()
+            .find(|d| d.hint.is_some())
+            .expect("typo diagnostic");
+        assert_eq!(typo.pointer, "/server");
+        assert_eq!(typo.hint.as_deref(), Some("Did you mean 'command'?"));
+    }
+
+    #[test]
+    fn test_missing_server_dir_is_an_error() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("service.yaml"),
+            "name: test\nserver:\n  command: [\"python\"]\n",
+        )
+        .unwrap();
+
+        let diagnostics = validate_service_dir(dir.path());
+        assert!(diagnostics.iter().any(|d| d.pointer == "/server"));
+    }
+
+    #[test]
+    fn test_bundled_service_definitions_are_valid() {
+        let services = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../services");
+        for entry in fs::read_dir(services).unwrap() {
+            let dir = entry.unwrap().path();
+            if dir.join("service.yaml").exists() {
+                let diagnostics = validate_service_dir(&dir);
+                assert!(
+                    !has_errors(&diagnostics),
+                    "{}: {:?}",
+                    dir.display(),
+                    diagnostics
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_project_service_settings_are_checked() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("doubleagent.yaml");
+        fs::write(&path, "services:\n  github:\n    prot: 9000\n").unwrap();
+        let registry = ServiceRegistry::new(dir.path(), "", "main").unwrap();
+
+        let diagnostics = validate_project_config(&path, &registry, dir.path());
+
+        assert!(has_errors(&diagnostics));
+        assert!(diagnostics
+            .iter()
+            .any(|d| d.hint.as_deref() == Some("Did you mean 'port'?")));
+    }
+
+    #[test]
+    fn test_project_seeds_are_checked() {
+        let schema: Value = serde_json::from_str(PROJECT_SCHEMA).unwrap();
+        let validator = jsonschema::validator_for(&schema).unwrap();
+        let seeds =
+            serde_json::json!({"seeds": {"github": [{"fixture": "base"}, "seeds/extra.yaml"]}});
+        assert!(validator.is_valid(&seeds));
+        let typo = serde_json::json!({"seeds": {"github": [{"snapshots": "prod"}]}});
+        assert!(!validator.is_valid(&typo));
+    }
+
+    #[test]
+    fn test_project_schema_accepts_current_version() {
+        let schema: Value = serde_json::from_str(PROJECT_SCHEMA).unwrap();
+        assert_eq!(
+            schema["properties"]["version"]["maximum"],
+            migrate::CURRENT_VERSION
+        );
+    }
+
+    #[test]
+    fn test_project_extends_are_checked() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("doubleagent.yaml");
+        fs::write(dir.path().join("base.yaml"), "base_port: http\n").unwrap();
+        fs::write(&path, "extends: base.yaml\nservices: []\n").unwrap();
+        let registry = ServiceRegistry::new(dir.path(), "", "main").unwrap();
+
+        let diagnostics = validate_project_config(&path, &registry, dir.path());
+        assert!(diagnostics.iter().any(|d| d.pointer == "/base_port"));
+
+        fs::write(&path, "extends: missing.yaml\n").unwrap();
+        let diagnostics = validate_project_config(&path, &registry, dir.path());
+        assert!(diagnostics.iter().any(|d| d.pointer == "/extends"));
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("comand", "command"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("same", "same"), 0);
+    }
+}
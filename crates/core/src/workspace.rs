@@ -0,0 +1,136 @@
+//! Named workspaces for running independent environments side by side.
+//!
+//! Each workspace has its own state file and env file, so the same services
+//! can run in a "demo" and a "test" workspace at once. Ports are allocated so
+//! that workspaces never hand out a port another workspace is using
+//! (see [`ports_in_use`]).
+
+use crate::process::ProcessManager;
+use crate::{Error, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the workspace used when none is selected.
+pub const DEFAULT_WORKSPACE: &str = "default";
+
+/// Check that a workspace name is usable in file names.
+pub fn validate_name(name: &str) -> std::result::Result<(), String> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid workspace name '{}' (use letters, digits, '-' and '_')",
+            name
+        ))
+    }
+}
+
+/// State file of a workspace.
+///
+/// The default workspace keeps using `state.json` so existing state carries over.
+pub fn state_file(state_dir: &Path, workspace: &str) -> PathBuf {
+    if workspace == DEFAULT_WORKSPACE {
+        state_dir.join("state.json")
+    } else {
+        state_dir
+            .join("workspaces")
+            .join(workspace)
+            .join("state.json")
+    }
+}
+
+/// Name of the env file written for a workspace in the current directory.
+pub fn env_file_name(workspace: &str) -> String {
+    if workspace == DEFAULT_WORKSPACE {
+        ".doubleagent.env".to_string()
+    } else {
+        format!(".doubleagent.{}.env", workspace)
+    }
+}
+
+/// Names of all workspaces that have a state file, sorted.
+pub fn list(state_dir: &Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    if state_file(state_dir, DEFAULT_WORKSPACE).exists() {
+        names.push(DEFAULT_WORKSPACE.to_string());
+    }
+
+    let dir = state_dir.join("workspaces");
+    if dir.is_dir() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.path().join("state.json").exists() && validate_name(&name).is_ok() {
+                names.push(name);
+            }
+        }
+    }
+
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+/// Ports used by running services in any workspace.
+pub fn ports_in_use(state_dir: &Path) -> Result<BTreeSet<u16>> {
+    let mut ports = BTreeSet::new();
+    for name in list(state_dir)? {
+        let manager = ProcessManager::load(&state_file(state_dir, &name))?;
+        for service in manager.running_services() {
+            if let Some(info) = manager.get_info(&service) {
+                ports.insert(info.port);
+            }
+        }
+    }
+    Ok(ports)
+}
+
+/// Ensure a workspace name is valid, as a core error.
+pub fn check_name(name: &str) -> Result<()> {
+    validate_name(name).map_err(Error::Other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_default_workspace_keeps_legacy_paths() {
+        let dir = Path::new("/state");
+        assert_eq!(
+            state_file(dir, DEFAULT_WORKSPACE),
+            PathBuf::from("/state/state.json")
+        );
+        assert_eq!(
+            state_file(dir, "demo"),
+            PathBuf::from("/state/workspaces/demo/state.json")
+        );
+        assert_eq!(env_file_name(DEFAULT_WORKSPACE), ".doubleagent.env");
+        assert_eq!(env_file_name("demo"), ".doubleagent.demo.env");
+    }
+
+    #[test]
+    fn test_list_finds_workspaces_with_state() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("state.json"), "{}").unwrap();
+        fs::create_dir_all(dir.path().join("workspaces/test")).unwrap();
+        fs::write(dir.path().join("workspaces/test/state.json"), "{}").unwrap();
+        fs::create_dir_all(dir.path().join("workspaces/empty")).unwrap();
+
+        assert_eq!(list(dir.path()).unwrap(), vec!["default", "test"]);
+    }
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("demo-1").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("../x").is_err());
+    }
+}
@@ -2,9 +2,11 @@
 pytest fixtures for GitHub contract tests.
 
 Uses the official PyGithub SDK to verify the fake works correctly.
-The service is started by the CLI before tests run.
+The service is started by the CLI before tests run; with
+`--target real` the same tests run against the real GitHub API.
 """
 
+import json
 import os
 
 import httpx
@@ -12,19 +14,37 @@ import pytest
 from github import Github
 
 SERVICE_URL = os.environ["DOUBLEAGENT_GITHUB_URL"]
+TARGET = os.environ.get("DOUBLEAGENT_CONTRACT_TARGET", "fake")
+TOKEN = os.environ.get("DOUBLEAGENT_GITHUB_TOKEN", "fake-token")
+# Set when the CLI seeded the fake with a snapshot profile (--snapshot)
+PROFILE = os.environ.get("DOUBLEAGENT_CONTRACT_PROFILE")
+
+
+def pytest_collection_finish(session):
+    """List the collected tests with their markers for `contract --list`."""
+    path = os.environ.get("DOUBLEAGENT_CONTRACT_LIST")
+    if path:
+        with open(path, "w") as f:
+            for item in session.items:
+                tags = sorted({marker.name for marker in item.iter_markers()})
+                f.write(json.dumps({"id": item.nodeid, "tags": tags}) + "\n")
 
 
 @pytest.fixture
 def github_client() -> Github:
-    """Provides official PyGithub client configured for the fake."""
+    """Provides official PyGithub client configured for the target."""
     return Github(
         base_url=SERVICE_URL,
-        login_or_token="fake-token",
+        login_or_token=TOKEN,
     )
 
 
 @pytest.fixture(autouse=True)
 def reset_fake():
-    """Reset fake state before each test."""
-    httpx.post(f"{SERVICE_URL}/_doubleagent/reset")
+    """Reset fake state before each test (the real API has no reset).
+
+    Runs seeded with a snapshot profile keep the profile's data instead.
+    """
+    if TARGET == "fake" and PROFILE is None:
+        httpx.post(f"{SERVICE_URL}/_doubleagent/reset")
     yield
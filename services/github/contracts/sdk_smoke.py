@@ -0,0 +1,36 @@
+"""
+Official SDK smoke script for the GitHub fake.
+
+Drives PyGithub through the workflow agents use most: create a repository,
+open, update and close an issue, then delete the repository. Run by
+`doubleagent contract github` against the fake; any exception fails it.
+"""
+
+import os
+import uuid
+
+from github import Github
+
+SERVICE_URL = os.environ["DOUBLEAGENT_GITHUB_URL"]
+TOKEN = os.environ.get("DOUBLEAGENT_GITHUB_TOKEN", "fake-token")
+
+
+def main() -> None:
+    client = Github(base_url=SERVICE_URL, login_or_token=TOKEN)
+    user = client.get_user()
+
+    repo = user.create_repo(name=f"sdk-smoke-{uuid.uuid4().hex[:8]}", auto_init=True)
+    assert client.get_repo(repo.full_name).name == repo.name
+
+    issue = repo.create_issue(title="SDK smoke", body="Opened by PyGithub")
+    issue.edit(labels=["smoke"])
+    issue.edit(state="closed")
+    assert repo.get_issue(issue.number).state == "closed"
+    assert any(i.number == issue.number for i in repo.get_issues(state="closed"))
+
+    repo.delete()
+    print("PyGithub smoke script passed")
+
+
+if __name__ == "__main__":
+    main()
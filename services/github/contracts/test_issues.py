@@ -22,6 +22,7 @@ class TestIssues:
         )
         yield
     
+    @pytest.mark.smoke
     def test_create_issue(self, github_client: Github):
         """Test creating an issue."""
         issue = self.repo.create_issue(
@@ -79,6 +80,7 @@ class TestIssues:
         reopened = self.repo.get_issue(issue.number)
         assert reopened.state == "open"
     
+    @pytest.mark.slow
     def test_list_issues_filters_by_state(self, github_client: Github):
         """Test listing issues with state filter."""
         # Create open and closed issues
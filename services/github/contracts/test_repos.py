@@ -12,6 +12,7 @@ from github import Github
 class TestRepositories:
     """Tests for repository CRUD operations."""
     
+    @pytest.mark.smoke
     def test_create_repo(self, github_client: Github):
         """Test creating a repository."""
         user = github_client.get_user()
@@ -27,6 +28,7 @@ class TestRepositories:
         assert repo.description == "Test repository"
         assert repo.private == True
     
+    @pytest.mark.smoke
     def test_get_repo(self, github_client: Github):
         """Test getting a repository by full name."""
         user = github_client.get_user()
@@ -55,6 +57,7 @@ class TestRepositories:
         updated = github_client.get_repo(repo.full_name)
         assert updated.description == "Updated description"
     
+    @pytest.mark.destructive
     def test_delete_repo(self, github_client: Github):
         """Test deleting a repository."""
         user = github_client.get_user()
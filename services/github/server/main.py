@@ -9,10 +9,12 @@ import os
 import asyncio
 import time
 from contextlib import asynccontextmanager
+from contextvars import ContextVar
 from typing import Any, Optional
 
 import httpx
 from fastapi import FastAPI, HTTPException, Request, Query
+from fastapi.routing import APIRoute
 from pydantic import BaseModel
 
 
@@ -24,21 +26,60 @@ def get_base_url(request: Request) -> str:
 # State
 # =============================================================================
 
-state: dict[str, Any] = {
-    "users": {},
-    "repos": {},
-    "issues": {},
-    "pulls": {},
-    "webhooks": {},  # repo_key -> [webhook]
-    "event_log": [],  # list of dispatched webhook events for debugging
-}
+# Each namespace (the X-DoubleAgent-Namespace header of a request) has its own
+# state and counters, so parallel test sessions don't see each other's data.
+# Requests without the header share the default namespace "".
+NAMESPACE_HEADER = b"x-doubleagent-namespace"
 
-counters: dict[str, int] = {
-    "repo_id": 0,
-    "issue_id": 0,
-    "pull_id": 0,
-    "webhook_id": 0,
-}
+current_namespace: ContextVar[str] = ContextVar("namespace", default="")
+
+
+def fresh_state() -> dict[str, Any]:
+    return {
+        "users": {},
+        "repos": {},
+        "issues": {},
+        "pulls": {},
+        "webhooks": {},  # repo_key -> [webhook]
+        "event_log": [],  # list of dispatched webhook events for debugging
+    }
+
+
+def fresh_counters() -> dict[str, int]:
+    return {
+        "repo_id": 0,
+        "issue_id": 0,
+        "pull_id": 0,
+        "webhook_id": 0,
+    }
+
+
+# namespace -> (state, counters)
+namespaces: dict[str, tuple[dict[str, Any], dict[str, int]]] = {}
+
+
+def current() -> tuple[dict[str, Any], dict[str, int]]:
+    namespace = current_namespace.get()
+    if namespace not in namespaces:
+        namespaces[namespace] = (fresh_state(), fresh_counters())
+    return namespaces[namespace]
+
+
+class NamespacedDict:
+    """Stands in for a dict of the current request's namespace."""
+
+    def __init__(self, index: int):
+        self.index = index
+
+    def __getitem__(self, key: str) -> Any:
+        return current()[self.index][key]
+
+    def __setitem__(self, key: str, value: Any) -> None:
+        current()[self.index][key] = value
+
+
+state: Any = NamespacedDict(0)
+counters: Any = NamespacedDict(1)
 
 DEFAULT_USER = {
     "login": "doubleagent",
@@ -54,21 +95,8 @@ def next_id(key: str) -> int:
 
 
 def reset_state() -> None:
-    global state, counters
-    state = {
-        "users": {},
-        "repos": {},
-        "issues": {},
-        "pulls": {},
-        "webhooks": {},
-        "event_log": [],
-    }
-    counters = {
-        "repo_id": 0,
-        "issue_id": 0,
-        "pull_id": 0,
-        "webhook_id": 0,
-    }
+    """Reset the current namespace; other namespaces are untouched."""
+    namespaces.pop(current_namespace.get(), None)
 
 
 # =============================================================================
@@ -152,6 +180,130 @@ app = FastAPI(
 )
 
 
+class NamespaceMiddleware:
+    """Handle each request in the namespace its header names."""
+
+    def __init__(self, app):
+        self.app = app
+
+    async def __call__(self, scope, receive, send):
+        if scope["type"] != "http":
+            await self.app(scope, receive, send)
+            return
+        namespace = dict(scope["headers"]).get(NAMESPACE_HEADER, b"").decode()
+        token = current_namespace.set(namespace)
+        try:
+            await self.app(scope, receive, send)
+        finally:
+            current_namespace.reset(token)
+
+
+app.add_middleware(NamespaceMiddleware)
+
+
+# Requests served, for `doubleagent contract --coverage`
+request_log: list[dict[str, Any]] = []
+
+# Where to send a span per request, set from the OTEL_* environment or
+# POST /_doubleagent/telemetry; None sends none
+telemetry: Optional[dict[str, Any]] = (
+    {
+        "endpoint": os.environ["OTEL_EXPORTER_OTLP_ENDPOINT"].rstrip("/"),
+        "service_name": os.environ.get("OTEL_SERVICE_NAME", "doubleagent-github"),
+        "propagate": True,
+    }
+    if os.environ.get("OTEL_EXPORTER_OTLP_ENDPOINT")
+    else None
+)
+
+# traceparent of the request being served, passed on to webhook deliveries
+current_traceparent: ContextVar[Optional[str]] = ContextVar("traceparent", default=None)
+
+# Span exports in flight, kept so they aren't garbage collected
+span_exports: set[asyncio.Task] = set()
+
+
+def start_span(request: Request) -> Optional[dict[str, Any]]:
+    """A span for a request, continuing its traceparent header's trace."""
+    if telemetry is None or request.url.path.startswith("/_doubleagent"):
+        return None
+    trace_id, parent_id = os.urandom(16).hex(), ""
+    if telemetry["propagate"]:
+        parts = request.headers.get("traceparent", "").split("-")
+        if len(parts) == 4 and len(parts[1]) == 32 and len(parts[2]) == 16:
+            trace_id, parent_id = parts[1], parts[2]
+    return {
+        "trace_id": trace_id,
+        "span_id": os.urandom(8).hex(),
+        "parent_id": parent_id,
+        "start": time.time_ns(),
+    }
+
+
+async def export_span(config: dict[str, Any], span: dict[str, Any], request: Request, status: int) -> None:
+    """Send a request's span to the collector as OTLP/HTTP JSON."""
+    route = request.scope.get("route")
+    path = route.path if isinstance(route, APIRoute) else request.url.path
+    attributes = [
+        {"key": "http.request.method", "value": {"stringValue": request.method}},
+        {"key": "url.path", "value": {"stringValue": request.url.path}},
+        {"key": "http.route", "value": {"stringValue": path}},
+        {"key": "http.response.status_code", "value": {"intValue": str(status)}},
+    ]
+    body = {"resourceSpans": [{
+        "resource": {"attributes": [
+            {"key": "service.name", "value": {"stringValue": config["service_name"]}},
+        ]},
+        "scopeSpans": [{
+            "scope": {"name": "doubleagent"},
+            "spans": [{
+                "traceId": span["trace_id"],
+                "spanId": span["span_id"],
+                "parentSpanId": span["parent_id"],
+                "name": f"{request.method} {path}",
+                "kind": 2,  # SERVER
+                "startTimeUnixNano": str(span["start"]),
+                "endTimeUnixNano": str(time.time_ns()),
+                "attributes": attributes,
+                "status": {"code": 2 if status >= 500 else 0},
+            }],
+        }],
+    }]}
+    try:
+        async with httpx.AsyncClient() as client:
+            await client.post(f"{config['endpoint']}/v1/traces", json=body, timeout=5.0)
+    except httpx.HTTPError:
+        pass
+
+
+@app.middleware("http")
+async def log_requests(request: Request, call_next):
+    span = start_span(request)
+    token = None
+    if span is not None:
+        token = current_traceparent.set(f"00-{span['trace_id']}-{span['span_id']}-01")
+    try:
+        response = await call_next(request)
+    finally:
+        if token is not None:
+            current_traceparent.reset(token)
+    if not request.url.path.startswith("/_doubleagent"):
+        entry = {
+            "method": request.method,
+            "path": request.url.path,
+            "status": response.status_code,
+            "time": time.time(),
+        }
+        if span is not None:
+            entry["trace_id"] = span["trace_id"]
+        request_log.append(entry)
+    if span is not None and telemetry is not None:
+        task = asyncio.create_task(export_span(telemetry, span, request, response.status_code))
+        span_exports.add(task)
+        task.add_done_callback(span_exports.discard)
+    return response
+
+
 # =============================================================================
 # /_doubleagent endpoints (REQUIRED)
 # =============================================================================
@@ -164,15 +316,19 @@ async def health():
 
 @app.post("/_doubleagent/reset")
 async def reset():
-    """Reset all state - REQUIRED."""
+    """Reset the namespace's state - REQUIRED."""
     reset_state()
     return {"status": "ok"}
 
 
 @app.post("/_doubleagent/seed")
-async def seed(data: SeedData):
+async def seed(data: SeedData, mode: str = Query(default="default")):
     """Seed state from JSON - REQUIRED."""
+    if mode == "replace":
+        reset_state()
     seeded: dict[str, int] = {}
+    # Created records, in payload order, so the CLI can resolve $ref ids
+    records: dict[str, list] = {}
     
     if data.repos:
         for r in data.repos:
@@ -189,6 +345,7 @@ async def seed(data: SeedData):
                 "created_at": "2024-01-01T00:00:00Z",
                 "updated_at": "2024-01-01T00:00:00Z",
             }
+            records.setdefault("repos", []).append(state["repos"][key])
         seeded["repos"] = len(data.repos)
     
     if data.issues:
@@ -197,15 +354,18 @@ async def seed(data: SeedData):
             repo_key = i.get("repo", "doubleagent/test")
             state["issues"][issue_id] = {
                 "id": issue_id,
-                "number": issue_id,
+                "number": i.get("number", issue_id),
                 "title": i["title"],
                 "body": i.get("body", ""),
                 "state": i.get("state", "open"),
                 "repo_key": repo_key,
                 "user": DEFAULT_USER,
+                "labels": i.get("labels", []),
+                "assignees": i.get("assignees", []),
                 "created_at": "2024-01-01T00:00:00Z",
                 "updated_at": "2024-01-01T00:00:00Z",
             }
+            records.setdefault("issues", []).append(state["issues"][issue_id])
         seeded["issues"] = len(data.issues)
     
     if data.pulls:
@@ -229,6 +389,7 @@ async def seed(data: SeedData):
                 "mergeable": True,
                 "html_url": f"https://github.com/{repo_key}/pull/{number}",
             }
+            records.setdefault("pulls", []).append(state["pulls"][pull_id])
         seeded["pulls"] = len(data.pulls)
     
     if data.webhooks:
@@ -245,9 +406,10 @@ async def seed(data: SeedData):
             if repo_key not in state["webhooks"]:
                 state["webhooks"][repo_key] = []
             state["webhooks"][repo_key].append(hook)
+            records.setdefault("webhooks", []).append(hook)
         seeded["webhooks"] = len(data.webhooks)
     
-    return {"status": "ok", "seeded": seeded}
+    return {"status": "ok", "seeded": seeded, "records": records}
 
 
 @app.get("/_doubleagent/info")
@@ -256,6 +418,9 @@ async def info():
     return {
         "name": "github",
         "version": "1.0",
+        "seed_modes": ["replace"],
+        "namespaces": True,
+        "telemetry": True,
         "endpoints": {
             "repos": len(state["repos"]),
             "issues": len(state["issues"]),
@@ -264,6 +429,94 @@ async def info():
     }
 
 
+@app.get("/_doubleagent/export")
+async def export():
+    """Current state as a seed payload - OPTIONAL.
+
+    Seeding the result into a fresh namespace recreates the state, which is
+    what `doubleagent dump`, `upgrade` and `seed --undo` rely on.
+    """
+    repos = [
+        {
+            "owner": r["owner"]["login"],
+            "name": r["name"],
+            "private": r.get("private", False),
+            "description": r.get("description", ""),
+            "default_branch": r.get("default_branch", "main"),
+        }
+        for r in state["repos"].values()
+    ]
+    issues = [
+        {
+            "repo": i["repo_key"],
+            "number": i["number"],
+            "title": i["title"],
+            "body": i.get("body", ""),
+            "state": i.get("state", "open"),
+            "labels": i.get("labels", []),
+            "assignees": i.get("assignees", []),
+        }
+        for i in state["issues"].values()
+    ]
+    pulls = []
+    for p in state["pulls"].values():
+        owner, repo = p["repo_key"].split("/", 1)
+        pulls.append({
+            "owner": owner,
+            "repo": repo,
+            "number": p["number"],
+            "title": p["title"],
+            "body": p.get("body", ""),
+            "state": p.get("state", "open"),
+            "head": p["head"]["ref"],
+            "base": p["base"]["ref"],
+            "user": p["user"]["login"],
+            "merged": p.get("merged", False),
+        })
+    webhooks = []
+    for repo_key, hooks in state["webhooks"].items():
+        owner, repo = repo_key.split("/", 1)
+        for h in hooks:
+            webhooks.append({
+                "owner": owner,
+                "repo": repo,
+                "url": h["config"]["url"],
+                "events": h.get("events", ["*"]),
+                "active": h.get("active", True),
+            })
+    return {"repos": repos, "issues": issues, "pulls": pulls, "webhooks": webhooks}
+
+
+class TelemetryConfig(BaseModel):
+    endpoint: str
+    service_name: str = "doubleagent-github"
+    propagate: bool = True
+
+
+@app.post("/_doubleagent/telemetry")
+async def configure_telemetry(config: TelemetryConfig):
+    """Send a span per request to an OTLP/HTTP collector - OPTIONAL."""
+    global telemetry
+    telemetry = {
+        "endpoint": config.endpoint.rstrip("/"),
+        "service_name": config.service_name,
+        "propagate": config.propagate,
+    }
+    return {"status": "ok"}
+
+
+@app.get("/_doubleagent/requests")
+async def get_requests():
+    """Routes and the requests served, for endpoint coverage - OPTIONAL."""
+    routes = [
+        {"method": method, "path": route.path}
+        for route in app.routes
+        if isinstance(route, APIRoute) and not route.path.startswith("/_doubleagent")
+        for method in sorted(route.methods)
+    ]
+    return {"routes": routes, "requests": request_log}
+
+
 @app.get("/_doubleagent/events")
 async def get_events(limit: int = Query(default=50, le=500)):
     """
@@ -688,6 +941,18 @@ async def dispatch_webhook(owner: str, repo: str, event_type: str, payload: dict
         asyncio.create_task(_send_webhook(hook["url"], event_type, payload, key, hook["id"]))
 
 
+def webhook_headers(event_type: str) -> dict[str, str]:
+    headers = {
+        "X-GitHub-Event": event_type,
+        "Content-Type": "application/json",
+    }
+    # The delivery is part of the trace of the request that caused it
+    traceparent = current_traceparent.get()
+    if traceparent is not None:
+        headers["traceparent"] = traceparent
+    return headers
+
+
 async def _send_webhook(url: str, event_type: str, payload: dict, repo_key: str, hook_id: int) -> None:
     """Send webhook (runs as background task) and log the result."""
     event_record = {
@@ -706,10 +971,7 @@ async def _send_webhook(url: str, event_type: str, payload: dict, repo_key: str,
             resp = await client.post(
                 url,
                 json=payload,
-                headers={
-                    "X-GitHub-Event": event_type,
-                    "Content-Type": "application/json",
-                },
+                headers=webhook_headers(event_type),
                 timeout=5.0,
             )
             event_record["status"] = "delivered"